@@ -0,0 +1,94 @@
+//! Benchmarks of the hot paths of the searches: building the query,
+//! parsing the params and deserializing the hits returned by ElasticSearch.
+//! Run them with `cargo bench --features test-support`.
+
+#[macro_use] extern crate criterion;
+#[macro_use] extern crate maplit;
+extern crate serde_json;
+extern crate searchspot;
+
+use criterion::Criterion;
+
+use serde_json::Value as JsonValue;
+
+use searchspot::resource::*;
+use searchspot::resources::Talent;
+use searchspot::schema;
+use searchspot::test_support::talent;
+
+/// The params of a search for Rust developers sent by the main app.
+fn params() -> JsonValue {
+  serde_json::from_str(r#"{
+    "keywords":                "Rust Elixir",
+    "desired_work_roles":      ["Fullstack", "DevOps"],
+    "professional_experience": ["2..6", "6..10"],
+    "work_locations":          ["Berlin", "Amsterdam"],
+    "languages":               ["English", "German"],
+    "company_id":              6,
+    "contacted_talents":       [1, 2, 3, 4, 5],
+    "presented_talents":       [6, 7, 8],
+    "epoch":                   "2017-03-01T10:30:12+00:00",
+    "per_page":                50
+  }"#).unwrap()
+}
+
+/// A response of ElasticSearch containing `hits` talents, built from `test_support::talent`.
+fn response(hits: usize) -> JsonValue {
+  let talents = (1..hits as u64 + 1).map(|id| Talent {
+    skills:   vec!["Rust".to_owned(), "Elixir".to_owned()],
+    headline: "Senior Rust developer".to_owned(),
+    summary:  "I have been working with Rust and Elixir for the last years.".to_owned(),
+    ..talent(id)
+  }).collect::<Vec<Talent>>();
+
+  let hits = talents.iter().map(|talent| serde_json::to_value(&btreemap! {
+    "_index"  => serde_json::to_value(&"talents"),
+    "_type"   => serde_json::to_value(&"talent"),
+    "_id"     => serde_json::to_value(&talent.id.to_string()),
+    "_score"  => serde_json::to_value(&1.0),
+    "_source" => serde_json::to_value(talent)
+  })).collect::<Vec<JsonValue>>();
+
+  let total = hits.len();
+  serde_json::to_value(&btreemap! {
+    "took"      => serde_json::to_value(&3),
+    "timed_out" => serde_json::to_value(&false),
+    "_shards"   => serde_json::to_value(&btreemap! { "total" => 1, "successful" => 1, "failed" => 0 }),
+    "hits"      => serde_json::to_value(&btreemap! {
+      "total"     => serde_json::to_value(&total),
+      "max_score" => serde_json::to_value(&1.0),
+      "hits"      => JsonValue::Array(hits)
+    })
+  })
+}
+
+fn search_filters(c: &mut Criterion) {
+  let params  = schema::from_json(&params()).unwrap();
+  let options = SearchOptions::default();
+
+  c.bench_function("search_filters", |b| b.iter(|| {
+    Talent::search_filters(&params, "2017-03-01T10:30:12+00:00", &options)
+  }));
+}
+
+fn parse_params(c: &mut Criterion) {
+  let object = params();
+
+  c.bench_function("parse_params", |b| b.iter(|| {
+    let params = schema::from_json(&object).unwrap();
+    schema::validate(&params, &Talent::search_params())
+  }));
+}
+
+fn deserialize_hits(c: &mut Criterion) {
+  let params   = schema::from_json(&params()).unwrap();
+  let options  = SearchOptions::default();
+  let response = response(100);
+
+  c.bench_function("deserialize_hits", |b| b.iter(|| {
+    Talent::results(&params, &options, response.to_owned()).unwrap()
+  }));
+}
+
+criterion_group!(benches, search_filters, parse_params, deserialize_hits);
+criterion_main!(benches);
@@ -0,0 +1,201 @@
+//! Maintenance operations on the indexes, exposed through the admin endpoints
+//! and the `searchspot-admin` executable.
+
+use serde_json::Value as JsonValue;
+
+use chrono::UTC;
+
+use rs_es::Client;
+
+use resource::Resource;
+use error::Error;
+use config::{Config, ResourceSettings};
+use cluster;
+use cluster::Cluster;
+use logger::start_logging;
+use es;
+use es::Distribution;
+use scroll::{Scroll, PAGE_SIZE};
+
+/// Copy every document of index `from` into index `to` on the cluster listening
+/// on `url`, one page at a time, returning how many documents have been copied.
+pub fn copy<R: Resource>(es: &mut Client, url: &str, from: &str, to: &str) -> Result<u64, Error> {
+  let mut copied = 0;
+  let mut page   = vec![];
+
+  for resource in try!(Scroll::<R>::new(url, from)) {
+    page.push(try!(resource));
+
+    if page.len() as u64 == PAGE_SIZE {
+      copied += page.len() as u64;
+      try!(R::index(es, to, page));
+      page = vec![];
+    }
+  }
+
+  if !page.is_empty() {
+    copied += page.len() as u64;
+    try!(R::index(es, to, page));
+  }
+
+  Ok(copied)
+}
+
+/// Reset `index` through `R::reset_index` (or `es::create_index` on OpenSearch), then set the replicas given
+/// by `settings` on the cluster listening on `url`, if any.
+pub fn reset<R: Resource>(es: &mut Client, url: &str, index: &str, settings: &ResourceSettings) -> Result<(), Error> {
+  if es::distribution() == Distribution::OpenSearch {
+    // `rs-es` sends the mapping nested under the type, which OpenSearch refuses
+    let _ = es.delete_index(index);
+    try!(es::create_index(url, index, &R::index_settings(settings), &R::mapping()));
+  }
+  else {
+    try!(R::reset_index(es, index, settings));
+  }
+
+  if let Some(replicas) = settings.replicas {
+    try!(es::set_replicas(url, index, replicas));
+  }
+
+  Ok(())
+}
+
+/// Return the name of the index rebuilding `alias` at the current time.
+pub fn fresh_index(alias: &str) -> String {
+  format!("{}_{}", alias, UTC::now().format("%Y%m%d%H%M%S"))
+}
+
+/// The outcome of `reindex`: how many documents have been copied into which fresh index.
+#[derive(Debug, PartialEq)]
+pub struct Reindexed {
+  pub documents: u64,
+  pub target:    String
+}
+
+/// Rebuild `index` with the current mapping of `R` and the given `settings`, keeping
+/// its documents: they are copied into a fresh index, which `index` becomes an alias
+/// of in a single `_aliases` call, then the indexes it pointed to are deleted. The
+/// writes to `index` are refused while it is copied, so that none of them is lost,
+/// and accepted again if the reindex fails.
+pub fn reindex<R: Resource>(es: &mut Client, url: &str, index: &str, settings: &ResourceSettings) -> Result<Reindexed, Error> {
+  // the alias would be moved away from the other indexes behind it
+  for (real, aliases) in try!(es::aliases(url, index)) {
+    if real == index && !aliases.is_empty() {
+      return Err(Error::Validation(format!("{} is behind the aliases {}, which have to be reindexed instead.", index, aliases.join(", "))));
+    }
+  }
+
+  let fresh = fresh_index(index);
+  try!(reset::<R>(es, url, &fresh, settings));
+  try!(es::block_writes(url, index, true));
+
+  let swapped = copy::<R>(es, url, index, &fresh).and_then(|copied| {
+    try!(es.refresh().with_indexes(&[&fresh]).send());
+    let previous = try!(es::swap_alias(url, index, &fresh));
+    Ok((copied, previous))
+  });
+
+  match swapped {
+    Ok((copied, previous)) => {
+      for previous in previous {
+        if let Err(err) = es.delete_index(&previous) {
+          warn!("The previous index {} has not been deleted: {}", previous, err);
+        }
+      }
+
+      Ok(Reindexed { documents: copied, target: fresh })
+    },
+    Err(err) => {
+      let _ = es::block_writes(url, index, false);
+      let _ = es.delete_index(&fresh);
+      Err(err)
+    }
+  }
+}
+
+/// The documents and the size of the primary shards of an index.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct IndexStats {
+  pub index:         String,
+  pub documents:     u64,
+  pub deleted:       u64,
+  pub size_in_bytes: u64
+}
+
+/// Return the statistics of `index` on the cluster listening on `url`.
+pub fn stats(url: &str, index: &str) -> Result<IndexStats, Error> {
+  let response = try!(es::get(url, &format!("{}/_stats/docs,store", index)));
+  Ok(index_stats(index, &response))
+}
+
+/// Return the statistics of the first index of `GET /:index/_stats`,
+/// named after the real index rather than `index`, which may be an alias.
+fn index_stats(index: &str, response: &JsonValue) -> IndexStats {
+  let first = response.find("indices")
+                      .and_then(|indexes| indexes.as_object())
+                      .and_then(|indexes| indexes.iter().next());
+
+  let count = |pointer: &str| first.and_then(|(_, stats)| stats.pointer(pointer)).and_then(|count| count.as_u64()).unwrap_or(0);
+
+  IndexStats {
+    index:         first.map(|(name, _)| name.to_owned()).unwrap_or(index.to_owned()),
+    documents:     count("/primaries/docs/count"),
+    deleted:       count("/primaries/docs/deleted"),
+    size_in_bytes: count("/primaries/store/size_in_bytes")
+  }
+}
+
+/// Return the ids and the scores of the hits of the `response` of ElasticSearch to a search, in order.
+pub fn scores(response: &JsonValue) -> Vec<(String, Option<f64>)> {
+  response.pointer("/hits/hits")
+          .and_then(|hits| hits.as_array())
+          .map(|hits| hits.iter().map(|hit| {
+            (hit.find("_id").and_then(|id| id.as_str()).unwrap_or("").to_owned(),
+             hit.find("_score").and_then(|score| score.as_f64()))
+          }).collect())
+          .unwrap_or(vec![])
+}
+
+/// Connect to the cluster of `config`, returning the URL of one of its nodes.
+pub fn connect(config: &Config) -> String {
+  start_logging(config).unwrap();
+
+  cluster::bootstrap(&config);
+
+  let cluster = Cluster::new(&config.es.urls(), 1);
+  es::configure_compatibility(&config.es.compatibility, &cluster.url());
+  cluster.url()
+}
+
+#[cfg(test)]
+mod tests {
+  use admin::{index_stats, scores, fresh_index, IndexStats};
+
+  use serde_json;
+
+  #[test]
+  fn test_index_stats() {
+    let response = serde_json::from_str("{\"_shards\": {\"total\": 10, \"successful\": 5, \"failed\": 0}, \"indices\": {\"talents_v2\": \
+                                         {\"primaries\": {\"docs\": {\"count\": 1204, \"deleted\": 12}, \"store\": {\"size_in_bytes\": 4815162}}}}}").unwrap();
+
+    assert_eq!(index_stats("talents", &response),
+               IndexStats { index: "talents_v2".to_owned(), documents: 1204, deleted: 12, size_in_bytes: 4815162 });
+    assert_eq!(index_stats("talents", &serde_json::from_str("{}").unwrap()).index, "talents");
+  }
+
+  #[test]
+  fn test_fresh_index() {
+    let index = fresh_index("talents");
+    assert!(index.starts_with("talents_"));
+    assert_eq!(index.len(), "talents_20170304122400".len());
+  }
+
+  #[test]
+  fn test_scores() {
+    let response = serde_json::from_str("{\"hits\": {\"total\": 2, \"hits\": [{\"_id\": \"4\", \"_score\": 1.5}, \
+                                         {\"_id\": \"2\", \"_score\": null}]}}").unwrap();
+
+    assert_eq!(scores(&response), vec![("4".to_owned(), Some(1.5)), ("2".to_owned(), None)]);
+    assert!(scores(&serde_json::from_str("{}").unwrap()).is_empty());
+  }
+}
@@ -0,0 +1,164 @@
+//! The alerts about the new matches of the saved searches: every
+//! `saved_searches.alert_interval_minutes` the searches saved with `"alert": true`
+//! are run again, and the talents among their top `saved_searches.alert_max_results`
+//! that they did not match the last time are notified to the webhooks as
+//! `saved_search.new_matches`, the backbone of the job alerts.
+//!
+//! The first run of a saved search only remembers its matches, so that the
+//! recruiters are not alerted about every talent matching it already.
+
+use serde_json::Value as JsonValue;
+
+use params::{Map, Value};
+
+use chrono::UTC;
+
+use config::Config;
+use resource::Resource;
+use saved_searches;
+use saved_searches::SavedSearch;
+use cluster::Cluster;
+use error::Error;
+use metrics::METRICS;
+use server::search_options;
+use webhooks::WEBHOOKS;
+use es;
+
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+/// Return the IDs of `current` missing from `previous`, in the order of `current`.
+pub fn new_matches(previous: &[String], current: &[String]) -> Vec<String> {
+  let previous = previous.iter().collect::<HashSet<&String>>();
+  current.iter().filter(|id| !previous.contains(id)).cloned().collect()
+}
+
+/// Return the IDs of the documents found by `response`, the best match first.
+fn ids(response: &JsonValue) -> Vec<String> {
+  response.pointer("/hits/hits")
+          .and_then(|hits| hits.as_array())
+          .map(|hits| hits.iter()
+                          .filter_map(|hit| hit.find("_id").and_then(|id| id.as_str()))
+                          .map(|id| id.to_owned())
+                          .collect())
+          .unwrap_or(vec![])
+}
+
+/// Return `search` fetching the IDs of the documents only, without their
+/// `_source` nor highlights, since nothing else is notified.
+fn ids_only(mut search: JsonValue) -> JsonValue {
+  if let Some(body) = search.as_object_mut().and_then(|search| search.get_mut("body")).and_then(|body| body.as_object_mut()) {
+    body.insert("_source".to_owned(), JsonValue::Bool(false));
+    body.remove("highlight");
+  }
+
+  search
+}
+
+/// Run `search` again, returning it with its current top matches and their new ones,
+/// which are none on its first run.
+pub fn alert<R: Resource>(config: &Config, url: &str, search: &SavedSearch) -> Result<(SavedSearch, Vec<String>), Error> {
+  let mut overrides = Map::new();
+  try!(overrides.assign("offset", Value::String("0".to_owned())).map_err(|e| Error::Validation(format!("{:?}", e))));
+  try!(overrides.assign("per_page", Value::String(config.saved_searches.alert_max_results.to_string()))
+                .map_err(|e| Error::Validation(format!("{:?}", e))));
+
+  let params   = try!(search.params(&overrides).map_err(Error::Validation));
+  let options  = search_options(&config.resource(R::name()));
+  let preview  = ids_only(try!(R::preview(&config.es.index, &params, &options)));
+  let response = try!(es::search(url, &preview, false));
+  let current  = ids(&response);
+
+  let matches = if search.alerted_at.is_some() { new_matches(&search.matched_ids, &current) } else { vec![] };
+
+  let alerted = SavedSearch {
+    matched_ids: current,
+    alerted_at:  Some(UTC::now().to_rfc3339()),
+    ..search.to_owned()
+  };
+
+  Ok((alerted, matches))
+}
+
+/// Run every saved search to alert about, firing the webhooks for their new matches.
+pub fn run<R: Resource>(config: &Config, cluster: &Cluster) -> Result<(), Error> {
+  let url   = cluster.url();
+  let index = &config.saved_searches.index;
+
+  for search in try!(saved_searches::alerting(&url, index)) {
+    // a saved search failing, i.e. because its params are not valid anymore, does not stop the others
+    let (alerted, matches) = match alert::<R>(config, &url, &search) {
+      Ok(alerted) => alerted,
+      Err(err)    => {
+        warn!("The saved search {} cannot be alerted about: {}", search.id, err);
+        METRICS.increment("searchspot_alerts_total", &[("outcome", "error")]);
+        continue;
+      }
+    };
+
+    try!(saved_searches::save(&url, index, &alerted));
+
+    if !matches.is_empty() {
+      info!("The saved search {} has {} new matches.", search.id, matches.len());
+      WEBHOOKS.fire_new_matches(&config.es.index, &search.id, &search.recruiter_id, matches);
+    }
+
+    METRICS.increment("searchspot_alerts_total", &[("outcome", "ok")]);
+  }
+
+  Ok(())
+}
+
+/// Run the saved searches to alert about every `saved_searches.alert_interval_minutes`
+/// from a background thread, if enabled.
+pub fn start<R: Resource>(config: &Config, cluster: Arc<Cluster>) {
+  if !config.saved_searches.alerting() {
+    return;
+  }
+
+  let config = config.to_owned();
+
+  thread::spawn(move || loop {
+    thread::sleep(Duration::from_secs(config.saved_searches.alert_interval_minutes * 60));
+
+    if let Err(err) = run::<R>(&config, &cluster) {
+      error!("The saved searches cannot be alerted about: {}", err);
+    }
+  });
+}
+
+#[cfg(test)]
+mod tests {
+  use alerts::{new_matches, ids, ids_only};
+
+  use serde_json;
+
+  fn strings(ids: &[&str]) -> Vec<String> {
+    ids.iter().map(|id| id.to_string()).collect()
+  }
+
+  #[test]
+  fn test_new_matches() {
+    assert_eq!(new_matches(&strings(&["1", "2"]), &strings(&["3", "2", "4"])), strings(&["3", "4"]));
+    assert_eq!(new_matches(&strings(&["1", "2"]), &strings(&["2", "1"])), strings(&[]));
+    assert_eq!(new_matches(&[], &strings(&["1"])), strings(&["1"]));
+  }
+
+  #[test]
+  fn test_ids() {
+    let response = serde_json::from_str("{\"hits\": {\"total\": 2, \"hits\": [{\"_id\": \"2\"}, {\"_id\": \"1\"}]}}").unwrap();
+    assert_eq!(ids(&response), strings(&["2", "1"]));
+    assert!(ids(&serde_json::from_str("{}").unwrap()).is_empty());
+  }
+
+  #[test]
+  fn test_ids_only() {
+    let search = serde_json::from_str("{\"index\": [\"talents\"], \"body\": {\"_source\": [\"id\", \"summary\"], \"highlight\": {}}}").unwrap();
+    let search = ids_only(search);
+
+    assert_eq!(search.pointer("/body/_source"), Some(&serde_json::Value::Bool(false)));
+    assert_eq!(search.pointer("/body/highlight"), None);
+  }
+}
@@ -0,0 +1,402 @@
+//! The analytics of the searches that have not found anything, recorded in
+//! background into a dedicated ElasticSearch index with their keywords and
+//! filters, so that the most wanted ones we cannot satisfy (i.e.: the skills
+//! or the locations missing from our talents) can be aggregated.
+//!
+//! The same index records the feedback on the results of the searches (which
+//! talents have been clicked or contacted, see `POST /feedback`), the data
+//! the ranking is trained and evaluated on.
+
+use serde_json;
+use serde_json::Value as JsonValue;
+
+use chrono::UTC;
+
+use config::Analytics as AnalyticsConfig;
+use cluster::Cluster;
+use es;
+use keywords;
+use resource::{FacetCount, Facets};
+
+use rs_es::error::EsError;
+
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{channel, Sender};
+use std::thread;
+
+/// The ElasticSearch type of the searches recorded into the analytics index.
+const ZERO_RESULT_TYPE: &'static str = "zero_result";
+
+/// The ElasticSearch type of the feedback recorded into the analytics index.
+const FEEDBACK_TYPE: &'static str = "feedback";
+
+/// The params that do not describe what has been looked for, and are not recorded.
+const IGNORED: [&'static str; 13] = ["keywords", "ids", "company_id", "contacted_talents", "presented_talents",
+                                     "pinned_ids", "epoch", "index", "ignore_unavailable", "routing", "spellcheck", "offset", "per_page"];
+
+lazy_static! {
+  /// The analytics shared across the handlers.
+  pub static ref ANALYTICS: Analytics = Analytics::new();
+}
+
+/// A search without results, as it is recorded, i.e.: `{"timestamp": "2017-03-04T12:24:00+00:00",
+/// "resource": "talents", "index": "talents", "keywords": "haskell",
+/// "filters": ["work_locations:Berlin", "desired_work_roles:Fullstack"]}`.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct ZeroResult {
+  pub timestamp: String,
+  pub resource:  String,
+  pub index:     String,
+  pub keywords:  Option<String>, // sanitized and lowercased
+  pub filters:   Vec<String>     // as `name:value`
+}
+
+impl ZeroResult {
+  /// Return the entry of the search with given `params`, as `schema::to_json` returns them.
+  pub fn new(resource: &str, index: &str, params: &JsonValue) -> ZeroResult {
+    let keywords = params.find("keywords")
+                         .and_then(|keywords| keywords.as_str())
+                         .map(|keywords| keywords::sanitize(keywords).to_lowercase())
+                         .and_then(|keywords| if keywords.is_empty() { None } else { Some(keywords) });
+
+    let mut filters = vec![];
+    if let Some(params) = params.as_object() {
+      for (name, value) in params.iter().filter(|&(name, _)| !IGNORED.contains(&&**name)) {
+        let values = match *value {
+          JsonValue::Array(ref values) => values.clone(),
+          ref value                    => vec![value.clone()]
+        };
+
+        for value in values {
+          match value {
+            JsonValue::String(value) => filters.push(format!("{}:{}", name, value)),
+            JsonValue::Null          => (),
+            value                    => filters.push(format!("{}:{}", name, value))
+          }
+        }
+      }
+    }
+
+    ZeroResult {
+      timestamp: UTC::now().to_rfc3339(),
+      resource:  resource.to_owned(),
+      index:     index.to_owned(),
+      keywords:  keywords,
+      filters:   filters
+    }
+  }
+}
+
+/// The feedback on the results of a search, given by the request ID of the search, i.e.:
+/// `{"search_id": "2f4b1c1e", "recruiter_id": "42", "clicked": ["1", "7"], "contacted": ["7"]}`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Feedback {
+  #[serde(default)]
+  pub timestamp:    String, // set when recorded
+  #[serde(default)]
+  pub resource:     String,
+  #[serde(default)]
+  pub index:        String,
+  pub search_id:    String, // the `X-Request-Id` of the search
+  #[serde(default)]
+  pub recruiter_id: Option<String>,
+  #[serde(default)]
+  pub variant:      Option<String>, // the `X-Searchspot-Variant` of the search, if any
+  #[serde(default)]
+  pub clicked:      Vec<String>,
+  #[serde(default)]
+  pub contacted:    Vec<String>
+}
+
+impl Feedback {
+  /// Return `feedback` on the results of `resource` from `index`, timestamped now,
+  /// unless it is not about any talent.
+  pub fn new(feedback: Feedback, resource: &str, index: &str) -> Result<Feedback, String> {
+    if feedback.search_id.trim().is_empty() {
+      return Err("`search_id` cannot be empty.".to_owned());
+    }
+
+    if feedback.clicked.is_empty() && feedback.contacted.is_empty() {
+      return Err("Either `clicked` or `contacted` must contain an ID.".to_owned());
+    }
+
+    if feedback.clicked.iter().chain(feedback.contacted.iter()).any(|id| id.trim().is_empty()) {
+      return Err("`clicked` and `contacted` cannot contain empty IDs.".to_owned());
+    }
+
+    Ok(Feedback {
+      timestamp: UTC::now().to_rfc3339(),
+      resource:  resource.to_owned(),
+      index:     index.to_owned(),
+      ..feedback
+    })
+  }
+}
+
+/// The entries recorded into the analytics index.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Entry {
+  ZeroResult(ZeroResult),
+  Feedback(Feedback)
+}
+
+impl Entry {
+  /// Return the ElasticSearch type and the JSON of the entry.
+  fn document(&self) -> (&'static str, String) {
+    match *self {
+      Entry::ZeroResult(ref entry) => (ZERO_RESULT_TYPE, serde_json::to_string(entry).unwrap()),
+      Entry::Feedback(ref entry)   => (FEEDBACK_TYPE, serde_json::to_string(entry).unwrap())
+    }
+  }
+}
+
+impl From<ZeroResult> for Entry {
+  fn from(entry: ZeroResult) -> Entry {
+    Entry::ZeroResult(entry)
+  }
+}
+
+impl From<Feedback> for Entry {
+  fn from(entry: Feedback) -> Entry {
+    Entry::Feedback(entry)
+  }
+}
+
+/// The most wanted keywords and filters of the searches without results
+/// of the last `days`, i.e.: `{"days": 7, "searches": 42, "keywords":
+/// [{"value": "haskell", "count": 12}], "filters": {"work_locations":
+/// [{"value": "Berlin", "count": 4}]}}`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ZeroResults {
+  pub days:     u64,
+  pub searches: u64,
+  pub keywords: Vec<FacetCount>,
+  pub filters:  Facets
+}
+
+pub struct Analytics {
+  sender: Mutex<Option<Sender<Entry>>>
+}
+
+impl Analytics {
+  pub fn new() -> Analytics {
+    Analytics {
+      sender: Mutex::new(None)
+    }
+  }
+
+  /// Create the analytics index unless it exists already and start recording
+  /// the searches without results and the feedback into it from a background thread.
+  /// The entries recorded before, or when disabled, are dropped.
+  pub fn start(&self, config: &AnalyticsConfig, cluster: Arc<Cluster>) {
+    if !config.enabled {
+      return;
+    }
+
+    if let Err(err) = create_index(&cluster.url(), &config.index) {
+      error!("The analytics index {} cannot be created: {}", config.index, err);
+    }
+
+    let (sender, receiver) = channel::<Entry>();
+    let index = config.index.to_owned();
+
+    thread::spawn(move || {
+      for entry in receiver {
+        let (doc_type, line) = entry.document();
+
+        if let Err(err) = es::post(&cluster.url(), &format!("{}/{}", index, doc_type), &line) {
+          error!("The {} {} has not been recorded: {}", doc_type, line, err);
+        }
+      }
+    });
+
+    *self.sender.lock().unwrap() = Some(sender);
+  }
+
+  /// Queue `entry` to be recorded.
+  pub fn record<E: Into<Entry>>(&self, entry: E) {
+    if let Some(ref sender) = *self.sender.lock().unwrap() {
+      let _ = sender.send(entry.into());
+    }
+  }
+}
+
+/// Create `index` with the mapping of the recorded searches, which are
+/// aggregated on their exact values. Nothing is done if it exists already,
+/// but adding the mapping of the feedback to the indexes created before it.
+fn create_index(url: &str, index: &str) -> Result<(), EsError> {
+  let not_analyzed = btreemap! { "type" => "string", "index" => "not_analyzed" };
+
+  let feedback = btreemap! {
+    "properties" => btreemap! {
+      "timestamp"    => btreemap! { "type" => "date" },
+      "resource"     => not_analyzed.clone(),
+      "index"        => not_analyzed.clone(),
+      "search_id"    => not_analyzed.clone(),
+      "recruiter_id" => not_analyzed.clone(),
+      "variant"      => not_analyzed.clone(),
+      "clicked"      => not_analyzed.clone(),
+      "contacted"    => not_analyzed.clone()
+    }
+  };
+
+  let mapping = btreemap! {
+    "mappings" => btreemap! {
+      ZERO_RESULT_TYPE => btreemap! {
+        "properties" => btreemap! {
+          "timestamp" => btreemap! { "type" => "date" },
+          "resource"  => not_analyzed.clone(),
+          "index"     => not_analyzed.clone(),
+          "keywords"  => not_analyzed.clone(),
+          "filters"   => not_analyzed
+        }
+      }
+    }
+  };
+
+  match es::put(url, index, &serde_json::to_string(&mapping).unwrap()) {
+    Ok(_)                                                                => (),
+    Err(EsError::EsServerError(ref err)) if err.contains("already_exists") => (),
+    Err(err)                                                             => return Err(err)
+  }
+
+  try!(es::put(url, &format!("{}/_mapping/{}", index, FEEDBACK_TYPE), &serde_json::to_string(&feedback).unwrap()));
+  Ok(())
+}
+
+/// Return the body of the aggregation of the searches of `resource` without
+/// results of the last `days`, on their keywords and on the given `filters`.
+pub fn query(resource: &str, filters: &[&str], days: u64, size: u64) -> JsonValue {
+  let mut aggregations = btreemap! {
+    "keywords".to_owned() => serde_json::to_value(&btreemap! {
+      "terms" => btreemap! { "field" => serde_json::to_value(&"keywords"), "size" => serde_json::to_value(&size) }
+    })
+  };
+
+  // the filters are stored together as `name:value`, and aggregated by name
+  for name in filters {
+    aggregations.insert(format!("filters_{}", name), serde_json::to_value(&btreemap! {
+      "terms" => btreemap! {
+        "field"   => serde_json::to_value(&"filters"),
+        "include" => serde_json::to_value(&format!("{}:.*", name)),
+        "size"    => serde_json::to_value(&size)
+      }
+    }));
+  }
+
+  serde_json::to_value(&btreemap! {
+    "size"  => serde_json::to_value(&0),
+    "query" => serde_json::to_value(&btreemap! {
+      "bool" => btreemap! {
+        "filter" => vec![
+          serde_json::to_value(&btreemap! { "term" => btreemap! { "resource" => resource } }),
+          serde_json::to_value(&btreemap! { "range" => btreemap! { "timestamp" => btreemap! { "gte" => format!("now-{}d/d", days) } } })
+        ]
+      }
+    }),
+    "aggs" => serde_json::to_value(&aggregations)
+  })
+}
+
+/// Return the most wanted keywords and `filters` of the searches of `resource`
+/// without results recorded into `index` over the last `days`.
+pub fn zero_results(url: &str, index: &str, resource: &str, filters: &[&str], days: u64, size: u64) -> Result<ZeroResults, EsError> {
+  let body     = query(resource, filters, days, size);
+  let response = try!(es::post(url, &format!("{}/_search", index), &serde_json::to_string(&body).unwrap()));
+  Ok(results(&response, filters, days))
+}
+
+/// Return the aggregations of `response` as `ZeroResults`.
+fn results(response: &JsonValue, filters: &[&str], days: u64) -> ZeroResults {
+  fn buckets(response: &JsonValue, name: &str, prefix: &str) -> Vec<FacetCount> {
+    response.pointer(&format!("/aggregations/{}/buckets", name))
+            .and_then(|buckets| buckets.as_array())
+            .map(|buckets| buckets.iter().filter_map(|bucket| {
+              let value = bucket.find("key").and_then(|key| key.as_str());
+              let count = bucket.find("doc_count").and_then(|count| count.as_u64());
+
+              match (value, count) {
+                (Some(value), Some(count)) => Some(FacetCount {
+                  value: value.trim_left_matches(prefix).to_owned(),
+                  count: count
+                }),
+                _ => None
+              }
+            }).collect())
+            .unwrap_or(vec![])
+  }
+
+  ZeroResults {
+    days:     days,
+    searches: response.pointer("/hits/total").and_then(|total| total.as_u64()).unwrap_or(0),
+    keywords: buckets(response, "keywords", ""),
+    filters:  filters.iter()
+                     .map(|name| (name.to_string(), buckets(response, &format!("filters_{}", name), &format!("{}:", name))))
+                     .filter(|&(_, ref counts)| !counts.is_empty())
+                     .collect()
+  }
+}
+
+/// Return the names of the params among `names` that are recorded as filters.
+pub fn filters<'a>(names: &[&'a str]) -> Vec<&'a str> {
+  names.iter().cloned().filter(|name| !IGNORED.contains(name)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+  use analytics::{ZeroResult, Feedback, Entry, results, filters};
+  use resource::FacetCount;
+
+  use serde_json;
+
+  #[test]
+  fn test_zero_result() {
+    let params = serde_json::from_str("{\"keywords\": \"  Haskell \\n\", \"work_locations\": [\"Berlin\", \"Munich\"], \
+                                        \"company_id\": 1, \"per_page\": 10}").unwrap();
+    let entry = ZeroResult::new("talents", "talents_v2", &params);
+
+    assert_eq!(entry.keywords, Some("haskell".to_owned()));
+    assert_eq!(entry.filters,  vec!["work_locations:Berlin".to_owned(), "work_locations:Munich".to_owned()]);
+
+    let params = serde_json::from_str("{\"keywords\": \" \"}").unwrap();
+    let entry  = ZeroResult::new("talents", "talents", &params);
+    assert_eq!(entry.keywords, None);
+    assert!(entry.filters.is_empty());
+  }
+
+  #[test]
+  fn test_feedback() {
+    let feedback = serde_json::from_str::<Feedback>("{\"search_id\": \"2f4b1c1e\", \"clicked\": [\"1\", \"7\"], \"contacted\": [\"7\"]}").unwrap();
+    let entry    = Feedback::new(feedback.to_owned(), "talents", "talents_v2").unwrap();
+
+    assert_eq!(entry.index, "talents_v2");
+    assert_eq!(entry.clicked, vec!["1".to_owned(), "7".to_owned()]);
+    assert_eq!(entry.recruiter_id, None);
+    assert!(!entry.timestamp.is_empty());
+    assert_eq!(Entry::from(entry).document().0, "feedback");
+
+    assert!(Feedback::new(Feedback { search_id: " ".to_owned(), ..feedback.to_owned() }, "talents", "talents").is_err());
+    assert!(Feedback::new(Feedback { clicked: vec![], contacted: vec![], ..feedback.to_owned() }, "talents", "talents").is_err());
+    assert!(Feedback::new(Feedback { contacted: vec!["".to_owned()], ..feedback }, "talents", "talents").is_err());
+  }
+
+  #[test]
+  fn test_results() {
+    let response = serde_json::from_str("{\"hits\": {\"total\": 42}, \"aggregations\": {\
+      \"keywords\": {\"buckets\": [{\"key\": \"haskell\", \"doc_count\": 12}]},\
+      \"filters_work_locations\": {\"buckets\": [{\"key\": \"work_locations:Berlin\", \"doc_count\": 4}]},\
+      \"filters_languages\": {\"buckets\": []}}}").unwrap();
+    let results = results(&response, &["work_locations", "languages"], 7);
+
+    assert_eq!(results.days,     7);
+    assert_eq!(results.searches, 42);
+    assert_eq!(results.keywords, vec![FacetCount { value: "haskell".to_owned(), count: 12 }]);
+    assert_eq!(results.filters.len(), 1);
+    assert_eq!(results.filters["work_locations"], vec![FacetCount { value: "Berlin".to_owned(), count: 4 }]);
+  }
+
+  #[test]
+  fn test_filters() {
+    assert_eq!(filters(&["keywords", "work_locations", "per_page", "languages"]), vec!["work_locations", "languages"]);
+  }
+}
@@ -0,0 +1,336 @@
+//! The audit log of the searches, recording in background who searched
+//! what (the caller, the params, how many results have been found and
+//! how long it took) to a file or to a dedicated ElasticSearch index.
+//!
+//! The writes (indexing, updating, deleting and resetting) can be recorded as
+//! well, with the version of the documents before and after them, into the same
+//! index, which is only appended to.
+
+use serde::ser::Serialize;
+use serde_json;
+use serde_json::Value as JsonValue;
+
+use chrono::UTC;
+
+use config::{Audit as AuditConfig, Sink};
+use cluster::Cluster;
+use resource::Resource;
+use routing;
+use es;
+use jwt;
+use request_id;
+
+use std::collections::BTreeMap;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{channel, Sender};
+use std::thread;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The ElasticSearch type of the searches recorded into the audit index.
+const SEARCH_TYPE: &'static str = "search";
+
+/// The ElasticSearch type of the writes recorded into the audit index.
+const OPERATION_TYPE: &'static str = "operation";
+
+lazy_static! {
+  /// The audit log shared across the handlers.
+  pub static ref AUDIT: Audit = Audit::new();
+}
+
+/// A search, as it is recorded, i.e.: `{"timestamp": "2017-03-04T12:24:00+00:00",
+/// "request_id": "2f4b1c1e", "caller": "jwt:recruiter@example.com", "resource": "talents",
+/// "index": "talents", "params": {"keywords": "rust"}, "results": 12, "latency_ms": 34, "cached": false}`.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct SearchEntry {
+  pub timestamp:  String,
+  pub request_id: Option<String>,
+  pub caller:     String,
+  pub resource:   String,
+  pub index:      String,
+  pub params:     JsonValue, // as `schema::to_json` returns them, with sorted keys
+  pub results:    Option<u64>,
+  pub latency_ms: u64,
+  pub cached:     bool
+}
+
+impl SearchEntry {
+  pub fn new(caller: &str, resource: &str, index: &str, params: JsonValue, results: Option<u64>, latency_ms: u64, cached: bool) -> SearchEntry {
+    SearchEntry {
+      timestamp:  UTC::now().to_rfc3339(),
+      request_id: request_id::current(),
+      caller:     caller.to_owned(),
+      resource:   resource.to_owned(),
+      index:      index.to_owned(),
+      params:     params,
+      results:    results,
+      latency_ms: latency_ms,
+      cached:     cached
+    }
+  }
+}
+
+/// A write, as it is recorded, i.e.: `{"timestamp": "2017-03-04T12:24:00+00:00",
+/// "request_id": "2f4b1c1e", "actor": "token", "operation": "update", "resource": "talents",
+/// "index": "talents", "id": "42", "version_before": 3, "version_after": 4}`.
+/// `id` is missing for the resets and the reindexes, the versions when the document did
+/// not exist before (or does not anymore) or when it has been queued rather than indexed.
+/// The reindexes tell also the `target` index the documents of `index` have been copied into.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct OperationEntry {
+  pub timestamp:      String,
+  pub request_id:     Option<String>,
+  pub actor:          String,
+  pub operation:      &'static str, // "index", "update", "delete", "reset", "reindex" or "erase"
+  pub resource:       String,
+  pub index:          String,
+  pub id:             Option<String>,
+  pub version_before: Option<u64>,
+  pub version_after:  Option<u64>,
+  #[serde(skip_serializing_if="Option::is_none")]
+  pub target:         Option<String>
+}
+
+impl OperationEntry {
+  pub fn new(actor: &str, operation: &'static str, resource: &str, index: &str, id: Option<String>,
+             version_before: Option<u64>, version_after: Option<u64>) -> OperationEntry {
+    OperationEntry {
+      timestamp:      UTC::now().to_rfc3339(),
+      request_id:     request_id::current(),
+      actor:          actor.to_owned(),
+      operation:      operation,
+      resource:       resource.to_owned(),
+      index:          index.to_owned(),
+      id:             id,
+      version_before: version_before,
+      version_after:  version_after,
+      target:         None
+    }
+  }
+
+  /// Return the entry of a reindex, which has copied the documents of `index` into `target`.
+  pub fn with_target(mut self, target: &str) -> OperationEntry {
+    self.target = Some(target.to_owned());
+    self
+  }
+}
+
+pub struct Audit {
+  searches:   Mutex<Option<Sender<SearchEntry>>>,
+  operations: Mutex<Option<Sender<OperationEntry>>>
+}
+
+impl Audit {
+  pub fn new() -> Audit {
+    Audit {
+      searches:   Mutex::new(None),
+      operations: Mutex::new(None)
+    }
+  }
+
+  /// Start recording the searches to the configured sink, and the writes to
+  /// the audit index if `operations` is set, from background threads.
+  /// The entries recorded before, or when disabled, are dropped.
+  pub fn start(&self, config: &AuditConfig, cluster: Arc<Cluster>) {
+    let sink = config.sink();
+    if sink != Sink::Off {
+      *self.searches.lock().unwrap() = Some(spawn_writer(sink, cluster.clone(), SEARCH_TYPE));
+    }
+
+    if config.operations {
+      *self.operations.lock().unwrap() = Some(spawn_writer(Sink::Index(config.index.to_owned()), cluster, OPERATION_TYPE));
+    }
+  }
+
+  /// Queue the search `entry` to be recorded.
+  pub fn record(&self, entry: SearchEntry) {
+    if let Some(ref sender) = *self.searches.lock().unwrap() {
+      let _ = sender.send(entry);
+    }
+  }
+
+  /// Queue the write `entry` to be recorded.
+  pub fn record_operation(&self, entry: OperationEntry) {
+    if let Some(ref sender) = *self.operations.lock().unwrap() {
+      let _ = sender.send(entry);
+    }
+  }
+
+  /// Return whether the writes are recorded.
+  pub fn records_operations(&self) -> bool {
+    self.operations.lock().unwrap().is_some()
+  }
+}
+
+/// Return the versions of the documents `ids` stored into `index` on the cluster
+/// listening on `url`, read before they are written so that the audit log records
+/// the versions they had rather than guessing them (the external versions are not
+/// incremented). Nothing is read unless the writes are recorded.
+pub fn versions_before<R: Resource>(url: &str, index: &str, resources: &[R]) -> BTreeMap<String, u64> {
+  if resources.is_empty() || !AUDIT.records_operations() {
+    return BTreeMap::new();
+  }
+
+  let field = routing::field(R::name());
+  let docs  = resources.iter()
+                       .map(|resource| (resource.id(), field.as_ref().and_then(|field| routing::key(field, &serde_json::to_value(resource)))))
+                       .collect::<Vec<(String, Option<String>)>>();
+
+  match es::versions(url, index, &docs) {
+    Ok(versions) => versions,
+    Err(err)     => {
+      warn!("The versions recorded by the audit log cannot be read: {}", err);
+      BTreeMap::new()
+    }
+  }
+}
+
+/// Same as `versions_before`, for the document `id` about to be deleted: its routing key
+/// is unknown, so every shard is searched for it when the documents of `R` are routed.
+pub fn version_before<R: Resource>(url: &str, index: &str, id: &str) -> Option<u64> {
+  if !AUDIT.records_operations() {
+    return None;
+  }
+
+  let version = match routing::field(R::name()) {
+    Some(_) => {
+      let search = serde_json::to_value(&btreemap! {
+        "index" => serde_json::to_value(&vec![index]),
+        "body"  => serde_json::to_value(&btreemap! {
+          "query"   => serde_json::to_value(&btreemap! { "ids" => btreemap! { "values" => vec![id] } }),
+          "version" => JsonValue::Bool(true),
+          "_source" => JsonValue::Bool(false)
+        })
+      });
+
+      es::search(url, &search, false).map(|response| response.pointer("/hits/hits/0/_version").and_then(|version| version.as_u64()))
+    },
+    None => es::versions(url, index, &[(id.to_owned(), None)]).map(|versions| versions.get(id).cloned())
+  };
+
+  match version {
+    Ok(version) => version,
+    Err(err)    => {
+      warn!("The version recorded by the audit log cannot be read: {}", err);
+      None
+    }
+  }
+}
+
+/// Return the sender of the entries written to `sink` (as `doc_type`
+/// documents when it is an index) by a new background thread.
+fn spawn_writer<T: Serialize + Send + 'static>(sink: Sink, cluster: Arc<Cluster>, doc_type: &'static str) -> Sender<T> {
+  let (sender, receiver) = channel::<T>();
+
+  thread::spawn(move || {
+    for entry in receiver {
+      let line = serde_json::to_string(&entry).unwrap();
+
+      if let Err(err) = write(&sink, &cluster, doc_type, &line) {
+        error!("The audit entry {} has not been recorded: {}", line, err);
+      }
+    }
+  });
+
+  sender
+}
+
+/// Append `line` to `sink`.
+fn write(sink: &Sink, cluster: &Cluster, doc_type: &str, line: &str) -> Result<(), String> {
+  match *sink {
+    Sink::Off => Ok(()),
+    Sink::File(ref path) => {
+      // opened at every line, so that the file can be rotated
+      let mut file = try!(OpenOptions::new().create(true).append(true).open(path).map_err(|e| e.to_string()));
+      writeln!(file, "{}", line).map_err(|e| e.to_string())
+    },
+    Sink::Index(ref index) => {
+      // without an id, the entries are always created rather than replaced
+      es::post(&cluster.url(), &format!("{}/{}", index, doc_type), line).map(|_| ()).map_err(|e| e.to_string())
+    }
+  }
+}
+
+/// Return who sent a request authorized by `authorization` (the value of the
+/// `Authorization` header, if any): the `sub` claim of the JWT tokens, or
+/// `jwt` when missing, `token` for the TOTP tokens and `anonymous` otherwise.
+pub fn caller(authorization: Option<&str>, jwt_secret: Option<&str>) -> String {
+  let authorization = match authorization {
+    Some(authorization) => authorization,
+    None                => return "anonymous".to_owned()
+  };
+
+  if authorization.starts_with("Bearer ") {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as i64;
+
+    return match jwt_secret.map(|secret| jwt::decode(&authorization["Bearer ".len()..], secret, now)) {
+      Some(Ok(claims)) => claims.sub.map(|sub| format!("jwt:{}", sub)).unwrap_or("jwt".to_owned()),
+      _                => "anonymous".to_owned()
+    };
+  }
+
+  if authorization.contains("token ") {
+    "token".to_owned()
+  }
+  else {
+    "anonymous".to_owned()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use audit::{SearchEntry, OperationEntry, caller};
+  use jwt;
+  use jwt::Claims;
+
+  use serde_json;
+
+  #[test]
+  fn test_caller() {
+    let token = jwt::encode(&Claims { sub: Some("recruiter@example.com".to_owned()), exp: None, scope: "search".to_owned(), company_id: None }, "secret");
+    let bearer = format!("Bearer {}", token);
+
+    assert_eq!(caller(Some(&bearer), Some("secret")), "jwt:recruiter@example.com");
+    assert_eq!(caller(Some(&bearer), Some("other")),  "anonymous");
+    assert_eq!(caller(Some(&bearer), None),           "anonymous");
+
+    let token = jwt::encode(&Claims { sub: None, exp: None, scope: "search".to_owned(), company_id: None }, "secret");
+    assert_eq!(caller(Some(&format!("Bearer {}", token)), Some("secret")), "jwt");
+
+    assert_eq!(caller(Some("token 492039"), None), "token");
+    assert_eq!(caller(None, Some("secret")),        "anonymous");
+  }
+
+  #[test]
+  fn test_entry() {
+    let params = serde_json::to_value(&btreemap! { "keywords" => "rust" });
+    let mut entry = SearchEntry::new("token", "talents", "talents_v2", params, Some(12), 34, false);
+    entry.timestamp  = "2017-03-04T12:24:00+00:00".to_owned();
+    entry.request_id = Some("abc".to_owned());
+
+    assert_eq!(serde_json::to_string(&entry).unwrap(),
+               "{\"timestamp\":\"2017-03-04T12:24:00+00:00\",\"request_id\":\"abc\",\"caller\":\"token\",\
+                \"resource\":\"talents\",\"index\":\"talents_v2\",\"params\":{\"keywords\":\"rust\"},\
+                \"results\":12,\"latency_ms\":34,\"cached\":false}");
+  }
+
+  #[test]
+  fn test_operation_entry() {
+    let mut entry = OperationEntry::new("token", "update", "talents", "talents", Some("42".to_owned()), Some(3), Some(4));
+    entry.timestamp = "2017-03-04T12:24:00+00:00".to_owned();
+
+    assert_eq!(serde_json::to_string(&entry).unwrap(),
+               "{\"timestamp\":\"2017-03-04T12:24:00+00:00\",\"request_id\":null,\"actor\":\"token\",\
+                \"operation\":\"update\",\"resource\":\"talents\",\"index\":\"talents\",\"id\":\"42\",\
+                \"version_before\":3,\"version_after\":4}");
+
+    let mut entry = OperationEntry::new("token", "reindex", "talents", "talents", None, None, None).with_target("talents_20170304122400");
+    entry.timestamp = "2017-03-04T12:24:00+00:00".to_owned();
+
+    assert_eq!(serde_json::to_string(&entry).unwrap(),
+               "{\"timestamp\":\"2017-03-04T12:24:00+00:00\",\"request_id\":null,\"actor\":\"token\",\
+                \"operation\":\"reindex\",\"resource\":\"talents\",\"index\":\"talents\",\"id\":null,\
+                \"version_before\":null,\"version_after\":null,\"target\":\"talents_20170304122400\"}");
+  }
+}
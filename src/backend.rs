@@ -0,0 +1,98 @@
+//! Where the searches built by the resources are sent, so that the building
+//! of the queries can be tested without a cluster: `HttpBackend` sends them
+//! to ElasticSearch, while `RecordingBackend` keeps them and returns a canned
+//! response, i.e.:
+//!
+//! ```rust,ignore
+//! let backend = RecordingBackend::new();
+//! Talent::search_with(&backend, "talents", &params, &SearchOptions::default()).unwrap();
+//! assert_eq!(backend.requests()[0].pointer("/body/size"), Some(&JsonValue::U64(10)));
+//! ```
+
+use serde_json;
+use serde_json::Value as JsonValue;
+
+use rs_es::error::EsError;
+
+use es;
+
+use std::sync::Mutex;
+
+/// The response of ElasticSearch to a search without results.
+pub const EMPTY_RESPONSE: &'static str = "{\"took\": 1, \"timed_out\": false, \
+                                      \"_shards\": {\"total\": 1, \"successful\": 1, \"failed\": 0}, \
+                                      \"hits\": {\"total\": 0, \"max_score\": null, \"hits\": []}}";
+
+pub trait EsBackend {
+  /// Send `search`, given as `{"index": [...], "body": {...}}` (see `Resource::preview`),
+  /// and return the response.
+  fn search(&self, search: &JsonValue) -> Result<JsonValue, EsError>;
+}
+
+/// Send the searches to the cluster listening on `url`.
+pub struct HttpBackend {
+  url: String
+}
+
+impl HttpBackend {
+  pub fn new(url: &str) -> HttpBackend {
+    HttpBackend { url: url.to_owned() }
+  }
+}
+
+impl EsBackend for HttpBackend {
+  fn search(&self, search: &JsonValue) -> Result<JsonValue, EsError> {
+    es::search(&self.url, search, false)
+  }
+}
+
+/// Keep the searches, answering every one of them with the same response.
+pub struct RecordingBackend {
+  requests: Mutex<Vec<JsonValue>>,
+  response: JsonValue
+}
+
+impl RecordingBackend {
+  /// Answer the searches as if nothing was found.
+  pub fn new() -> RecordingBackend {
+    RecordingBackend::with_response(serde_json::from_str(EMPTY_RESPONSE).unwrap())
+  }
+
+  /// Answer the searches with `response`.
+  pub fn with_response(response: JsonValue) -> RecordingBackend {
+    RecordingBackend {
+      requests: Mutex::new(vec![]),
+      response: response
+    }
+  }
+
+  /// Return the searches received so far, in order.
+  pub fn requests(&self) -> Vec<JsonValue> {
+    self.requests.lock().unwrap().to_owned()
+  }
+}
+
+impl EsBackend for RecordingBackend {
+  fn search(&self, search: &JsonValue) -> Result<JsonValue, EsError> {
+    self.requests.lock().unwrap().push(search.to_owned());
+    Ok(self.response.to_owned())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use backend::{EsBackend, RecordingBackend};
+
+  use serde_json;
+  use serde_json::Value as JsonValue;
+
+  #[test]
+  fn test_recording_backend() {
+    let backend = RecordingBackend::new();
+    let search  = serde_json::from_str::<JsonValue>("{\"index\": [\"talents\"], \"body\": {\"size\": 10}}").unwrap();
+
+    let response = backend.search(&search).unwrap();
+    assert_eq!(response.pointer("/hits/total"), Some(&JsonValue::U64(0)));
+    assert_eq!(backend.requests(), vec![search]);
+  }
+}
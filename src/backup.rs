@@ -0,0 +1,410 @@
+//! The periodic backup of the raw documents of the index to S3, as gzipped
+//! NDJSON snapshots (one document per line, as accepted by `searchspot --import`),
+//! so that the search corpus can be restored anywhere, independently of the
+//! snapshots of ElasticSearch.
+//!
+//! The snapshots are uploaded in parts while the index is scrolled, so that they
+//! are never held whole in memory. Every instance may have the backups enabled:
+//! the one holding the `backup` lease (see `lease`) takes them, once the latest
+//! one in the bucket is older than the interval.
+
+use serde_json;
+
+use flate2::Compression;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+
+use chrono::{DateTime, UTC, TimeZone};
+
+use hyper::Client;
+
+use rusoto_core::{DefaultCredentialsProvider, Region, default_tls_client};
+use rusoto_s3::{S3, S3Client, PutObjectRequest, GetObjectRequest, ListObjectsRequest};
+use rusoto_s3::{CreateMultipartUploadRequest, UploadPartRequest, CompleteMultipartUploadRequest,
+                AbortMultipartUploadRequest, CompletedMultipartUpload, CompletedPart};
+
+use config::Backup as BackupConfig;
+use resource::Resource;
+use cluster::Cluster;
+use dump;
+use lease;
+use metrics::METRICS;
+
+use std::io;
+use std::io::{Read, Write};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/// How many bytes of the snapshot are uploaded by each part, the least accepted by S3.
+const PART_SIZE: usize = 5 * 1024 * 1024;
+
+/// How often an instance not holding the lease checks whether the backup is due.
+const CHECK_INTERVAL: u64 = 600; // seconds
+
+/// The S3 client used by the backups.
+type BackupClient = S3Client<DefaultCredentialsProvider, Client>;
+
+lazy_static! {
+  /// Held while the snapshots are uploaded or rewritten, so that a purge
+  /// never rewrites a snapshot that a backup is uploading, or the other way round.
+  static ref LOCK: Mutex<()> = Mutex::new(());
+}
+
+/// The multipart upload of a snapshot to `key`, sending a part every `PART_SIZE`
+/// bytes written. It is aborted if dropped before it has been completed.
+struct Upload<'a> {
+  client:    &'a BackupClient,
+  bucket:    String,
+  key:       String,
+  upload_id: String,
+  buffer:    Vec<u8>,
+  parts:     Vec<CompletedPart>,
+  completed: bool
+}
+
+impl<'a> Upload<'a> {
+  fn start(client: &'a BackupClient, bucket: &str, key: &str) -> Result<Upload<'a>, String> {
+    let request = CreateMultipartUploadRequest {
+      bucket:           bucket.to_owned(),
+      key:              key.to_owned(),
+      content_type:     Some("application/x-ndjson".to_owned()),
+      content_encoding: Some("gzip".to_owned()),
+      ..Default::default()
+    };
+
+    let created = try!(client.create_multipart_upload(&request).map_err(|err| err.to_string()));
+
+    Ok(Upload {
+      client:    client,
+      bucket:    bucket.to_owned(),
+      key:       key.to_owned(),
+      upload_id: try!(created.upload_id.ok_or("S3 has not returned the id of the upload.".to_owned())),
+      buffer:    Vec::with_capacity(PART_SIZE),
+      parts:     vec![],
+      completed: false
+    })
+  }
+
+  /// Upload the bytes written since the last part as the next one.
+  fn send_part(&mut self) -> Result<(), String> {
+    let number  = self.parts.len() as i64 + 1;
+    let request = UploadPartRequest {
+      bucket:      self.bucket.to_owned(),
+      key:         self.key.to_owned(),
+      upload_id:   self.upload_id.to_owned(),
+      part_number: number,
+      body:        Some(self.buffer.split_off(0)),
+      ..Default::default()
+    };
+
+    let uploaded = try!(self.client.upload_part(&request).map_err(|err| err.to_string()));
+    self.parts.push(CompletedPart { e_tag: uploaded.e_tag, part_number: Some(number) });
+    Ok(())
+  }
+
+  /// Upload the last part, then assemble the snapshot from the parts.
+  fn complete(&mut self) -> Result<(), String> {
+    if !self.buffer.is_empty() || self.parts.is_empty() {
+      try!(self.send_part());
+    }
+
+    let request = CompleteMultipartUploadRequest {
+      bucket:           self.bucket.to_owned(),
+      key:              self.key.to_owned(),
+      upload_id:        self.upload_id.to_owned(),
+      multipart_upload: Some(CompletedMultipartUpload { parts: Some(self.parts.to_owned()) }),
+      ..Default::default()
+    };
+
+    try!(self.client.complete_multipart_upload(&request).map_err(|err| err.to_string()));
+    self.completed = true;
+    Ok(())
+  }
+}
+
+impl<'a> Write for Upload<'a> {
+  fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+    self.buffer.extend_from_slice(bytes);
+
+    if self.buffer.len() >= PART_SIZE {
+      try!(self.send_part().map_err(|err| io::Error::new(io::ErrorKind::Other, err)));
+    }
+
+    Ok(bytes.len())
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    Ok(())
+  }
+}
+
+impl<'a> Drop for Upload<'a> {
+  fn drop(&mut self) {
+    if self.completed {
+      return;
+    }
+
+    let request = AbortMultipartUploadRequest {
+      bucket:    self.bucket.to_owned(),
+      key:       self.key.to_owned(),
+      upload_id: self.upload_id.to_owned(),
+      ..Default::default()
+    };
+
+    if let Err(err) = self.client.abort_multipart_upload(&request) {
+      error!("The upload of s3://{}/{} cannot be aborted: {}", self.bucket, self.key, err);
+    }
+  }
+}
+
+/// Return the client of the bucket of `config`.
+fn client(config: &BackupConfig) -> Result<BackupClient, String> {
+  let region      = try!(config.region.parse::<Region>().map_err(|err| err.to_string()));
+  let credentials = try!(DefaultCredentialsProvider::new().map_err(|err| err.to_string()));
+  let tls         = try!(default_tls_client().map_err(|err| err.to_string()));
+  Ok(S3Client::new(tls, credentials, region))
+}
+
+/// Return the key of the snapshot of `index` taken at `time`,
+/// i.e.: `searchspot/talents/20170304T122400Z.ndjson.gz`.
+pub fn key(prefix: &str, index: &str, time: DateTime<UTC>) -> String {
+  format!("{}{}/{}.ndjson.gz", prefix, index, time.format("%Y%m%dT%H%M%SZ"))
+}
+
+/// Return the time at which the snapshot `key` has been taken, if it is named as by `key`.
+pub fn taken_at(key: &str) -> Option<DateTime<UTC>> {
+  let name = key.rsplit('/').next().unwrap_or(key);
+  UTC.datetime_from_str(name.trim_right_matches(".ndjson.gz"), "%Y%m%dT%H%M%SZ").ok()
+}
+
+/// Return the keys of the snapshots of `index` in the bucket of `config`.
+fn list(client: &BackupClient, config: &BackupConfig, index: &str) -> Result<Vec<String>, String> {
+  let mut keys   = vec![];
+  let mut marker = None;
+
+  loop {
+    let request = ListObjectsRequest {
+      bucket: config.bucket.to_owned(),
+      prefix: Some(format!("{}{}/", config.prefix, index)),
+      marker: marker.to_owned(),
+      ..Default::default()
+    };
+
+    let listed = try!(client.list_objects(&request).map_err(|err| err.to_string()));
+    let page   = listed.contents.unwrap_or(vec![]).into_iter().filter_map(|object| object.key).collect::<Vec<String>>();
+
+    marker = listed.next_marker.or(page.last().cloned());
+    keys.extend(page);
+
+    if listed.is_truncated != Some(true) {
+      return Ok(keys);
+    }
+  }
+}
+
+/// Return the time of the latest snapshot of `index` in the bucket of `config`, if any.
+pub fn latest(config: &BackupConfig, index: &str) -> Result<Option<DateTime<UTC>>, String> {
+  let client = try!(client(config));
+  Ok(try!(list(&client, config, index)).iter().filter_map(|key| taken_at(key)).max())
+}
+
+/// Upload a snapshot of `index` to the bucket of `config`, returning its key.
+pub fn backup<R: Resource>(config: &BackupConfig, cluster: &Cluster, index: &str) -> Result<String, String> {
+  let client = try!(client(config));
+
+  let _lock  = LOCK.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+  let key    = key(&config.prefix, index, UTC::now());
+  let upload = try!(Upload::start(&client, &config.bucket, &key));
+
+  let (encoder, count) = try!(dump::write::<R, _>(&cluster.url(), index, GzEncoder::new(upload, Compression::Default)));
+  let mut upload       = try!(encoder.finish().map_err(|err| err.to_string()));
+  try!(upload.complete());
+
+  info!("{} documents of {} have been backed up to s3://{}/{}.", count, index, config.bucket, key);
+  Ok(key)
+}
+
+/// Return `ndjson` without the documents whose id is `id`, and how many they were.
+/// The lines that cannot be deserialized are kept as they are.
+pub fn without<R: Resource>(ndjson: &str, id: &str) -> (String, u64) {
+  let mut kept    = String::new();
+  let mut removed = 0;
+
+  for line in ndjson.lines().filter(|line| !line.trim().is_empty()) {
+    match serde_json::from_str::<R>(line) {
+      Ok(ref document) if document.id() == id => removed += 1,
+      _                                       => {
+        kept.push_str(line);
+        kept.push('\n');
+      }
+    }
+  }
+
+  (kept, removed)
+}
+
+/// Run `purge` from a background thread, since it downloads every snapshot.
+/// The failures are logged: the erasure must be sent again.
+pub fn purge_in_background<R: Resource>(config: &BackupConfig, index: &str, id: &str) {
+  let config = config.to_owned();
+  let index  = index.to_owned();
+  let id     = id.to_owned();
+
+  thread::spawn(move || match purge::<R>(&config, &index, &id) {
+    Ok(_) => METRICS.increment("searchspot_backup_purges_total", &[("outcome", "ok")]),
+    Err(err) => {
+      error!("The backups of {} in s3://{} have not been purged, the erasure must be sent again: {}", index, config.bucket, err);
+      METRICS.increment("searchspot_backup_purges_total", &[("outcome", "error")]);
+    }
+  });
+}
+
+/// Remove the document `id` from every snapshot of `index` in the bucket of `config`,
+/// uploading again the ones that contained it. Return their keys.
+pub fn purge<R: Resource>(config: &BackupConfig, index: &str, id: &str) -> Result<Vec<String>, String> {
+  let _lock  = LOCK.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+  let client = try!(client(config));
+  let keys   = try!(list(&client, config, index));
+
+  let mut rewritten = vec![];
+
+  for key in keys {
+    let request = GetObjectRequest { bucket: config.bucket.to_owned(), key: key.to_owned(), ..Default::default() };
+    let gzipped = try!(client.get_object(&request).map_err(|err| format!("{}: {}", key, err))).body.unwrap_or(vec![]);
+
+    let mut ndjson = String::new();
+    try!(GzDecoder::new(&gzipped[..]).and_then(|mut decoder| decoder.read_to_string(&mut ndjson))
+                                     .map_err(|err| format!("{}: {}", key, err)));
+
+    let (kept, removed) = without::<R>(&ndjson, id);
+    if removed == 0 {
+      continue;
+    }
+
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::Default);
+    try!(encoder.write_all(kept.as_bytes()).map_err(|err| format!("{}: {}", key, err)));
+    let body = try!(encoder.finish().map_err(|err| format!("{}: {}", key, err)));
+
+    let request = PutObjectRequest {
+      bucket:           config.bucket.to_owned(),
+      key:              key.to_owned(),
+      body:             Some(body),
+      content_type:     Some("application/x-ndjson".to_owned()),
+      content_encoding: Some("gzip".to_owned()),
+      ..Default::default()
+    };
+
+    try!(client.put_object(&request).map_err(|err| format!("{}: {}", key, err)));
+    info!("{} has been removed from s3://{}/{}.", id, config.bucket, key);
+    rewritten.push(key);
+  }
+
+  Ok(rewritten)
+}
+
+/// Return how long to wait before the next backup is due, `interval` after the `latest` one.
+pub fn due_in(latest: Option<DateTime<UTC>>, now: DateTime<UTC>, interval: Duration) -> Duration {
+  let elapsed = match latest {
+    Some(latest) => (now - latest).num_seconds(),
+    None         => return Duration::from_secs(0)
+  };
+
+  Duration::from_secs((interval.as_secs() as i64 - elapsed).max(0) as u64)
+}
+
+/// Back up `index` every `backup.interval_hours` from a background thread, if enabled:
+/// as soon as it starts if the latest backup is older than that, then once it is. Only
+/// the instance holding the `backup` lease takes it, the other ones check again later.
+pub fn start<R: Resource>(config: &BackupConfig, cluster: Arc<Cluster>, index: &str) {
+  if !config.enabled {
+    return;
+  }
+
+  let config   = config.to_owned();
+  let index    = index.to_owned();
+  let interval = Duration::from_secs(config.interval_hours * 3600);
+  let holder   = lease::holder();
+
+  thread::spawn(move || loop {
+    let wait = match latest(&config, &index) {
+      Ok(latest) => due_in(latest, UTC::now(), interval),
+      Err(err)   => {
+        error!("The backups of {} in s3://{} cannot be listed: {}", index, config.bucket, err);
+        Duration::from_secs(CHECK_INTERVAL)
+      }
+    };
+
+    if wait.as_secs() > 0 {
+      thread::sleep(wait);
+      continue;
+    }
+
+    // held for the whole interval, so that another instance does not take it again meanwhile
+    match lease::acquire(&cluster.url(), &format!("backup-{}", index), &holder, interval) {
+      Ok(true)  => (),
+      Ok(false) => {
+        thread::sleep(Duration::from_secs(CHECK_INTERVAL));
+        continue;
+      },
+      Err(err) => {
+        error!("The lease on the backups of {} cannot be taken: {}", index, err);
+        thread::sleep(Duration::from_secs(CHECK_INTERVAL));
+        continue;
+      }
+    }
+
+    match backup::<R>(&config, &cluster, &index) {
+      Ok(_) => METRICS.increment("searchspot_backups_total", &[("outcome", "ok")]),
+      Err(err) => {
+        error!("{} has not been backed up to s3://{}: {}", index, config.bucket, err);
+        METRICS.increment("searchspot_backups_total", &[("outcome", "error")]);
+        thread::sleep(Duration::from_secs(CHECK_INTERVAL));
+      }
+    }
+  });
+}
+
+#[cfg(test)]
+mod tests {
+  use backup::{key, taken_at, due_in, without};
+  use resources::Talent;
+  use test_support::talent;
+
+  use serde_json;
+
+  use chrono::{TimeZone, UTC};
+
+  use std::time::Duration;
+
+  #[test]
+  fn test_key() {
+    let time = UTC.ymd(2017, 3, 4).and_hms(12, 24, 0);
+
+    assert_eq!(key("searchspot/", "talents", time), "searchspot/talents/20170304T122400Z.ndjson.gz");
+    assert_eq!(key("", "talents", time),            "talents/20170304T122400Z.ndjson.gz");
+
+    assert_eq!(taken_at(&key("searchspot/", "talents", time)), Some(time));
+    assert_eq!(taken_at("searchspot/talents/latest.ndjson.gz"), None);
+  }
+
+  #[test]
+  fn test_due_in() {
+    let now = UTC.ymd(2017, 3, 4).and_hms(12, 0, 0);
+    let day = Duration::from_secs(86400);
+
+    assert_eq!(due_in(None, now, day), Duration::from_secs(0));
+    assert_eq!(due_in(Some(UTC.ymd(2017, 3, 3).and_hms(6, 0, 0)), now, day), Duration::from_secs(0));
+    assert_eq!(due_in(Some(UTC.ymd(2017, 3, 4).and_hms(6, 0, 0)), now, day), Duration::from_secs(64800));
+  }
+
+  #[test]
+  fn test_without() {
+    let (first, second) = (serde_json::to_string(&talent(42)).unwrap(), serde_json::to_string(&talent(43)).unwrap());
+    let (kept, removed) = without::<Talent>(&format!("{}\n{}\nnot a talent\n", first, second), "42");
+
+    assert_eq!(removed, 1);
+    assert_eq!(kept, format!("{}\nnot a talent\n", second));
+    assert_eq!(without::<Talent>(&kept, "42").1, 0);
+  }
+}
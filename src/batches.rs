@@ -0,0 +1,458 @@
+//! The batches of talents, stored into a dedicated ElasticSearch index (`[batches]`).
+//! A batch gives the dates between which its talents are visible: assigning talents
+//! to it (see `/batches/:id/talents`) sets their `batch_starts_at`, `batch_ends_at`
+//! and `added_to_batch_at`, and closing it ends the batch of all of them at once,
+//! so that the main application does not have to denormalize the dates of the
+//! batch into every talent it indexes.
+//!
+//! A talent belongs to a single batch: assigning it moves it out of its previous one.
+
+use serde_json;
+use serde_json::Value as JsonValue;
+
+use chrono::{UTC, DateTime};
+
+use rs_es::error::EsError;
+
+use error::Error;
+use request_id;
+use es;
+use routing;
+
+/// The ElasticSearch type of the batches.
+const BATCH_TYPE: &'static str = "batch";
+
+/// How many batches can hold the talents being assigned to another one.
+const MAX_PREVIOUS: u64 = 100;
+
+/// A batch, i.e.: `{"id": "2017-03", "name": "March 2017", "starts_at": "2017-03-06T00:00:00+00:00",
+/// "ends_at": "2017-03-20T00:00:00+00:00", "talent_ids": [42], "created_at": "2017-03-01T12:24:00+00:00"}`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Batch {
+  pub id:         String,
+  pub name:       String,
+  pub starts_at:  String,
+  pub ends_at:    String,
+  #[serde(default)]
+  pub talent_ids: Vec<u64>,
+  pub created_at: String,
+  #[serde(default)]
+  pub closed_at:  Option<String>
+}
+
+/// The body expected by `POST /batches`, whose `id` is generated if missing.
+#[derive(Deserialize, Debug)]
+pub struct NewBatch {
+  #[serde(default)]
+  pub id:        Option<String>,
+  pub name:      String,
+  pub starts_at: String,
+  pub ends_at:   String
+}
+
+/// The body expected by `POST /batches/:id/talents`.
+#[derive(Deserialize, Debug)]
+pub struct Assignment {
+  pub talent_ids: Vec<u64>
+}
+
+/// The response of `POST /batches/:id/talents` and `POST /batches/:id/close`:
+/// the talents whose dates have been set, and the ones that are not indexed.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct BatchReport {
+  pub batch:   Batch,
+  pub updated: Vec<u64>,
+  pub missing: Vec<u64>
+}
+
+impl Batch {
+  /// Return the batch to create, unless `new` is not valid.
+  pub fn new(new: NewBatch) -> Result<Batch, String> {
+    let id = match new.id {
+      Some(ref id) if id.trim().is_empty() => return Err("`id` cannot be empty.".to_owned()),
+      Some(id)                             => id,
+      None                                 => request_id::generate()
+    };
+
+    if new.name.trim().is_empty() {
+      return Err("`name` cannot be empty.".to_owned());
+    }
+
+    let starts_at = try!(DateTime::parse_from_rfc3339(&new.starts_at)
+                                  .map_err(|_| format!("`starts_at` expects a RFC 3339 date, got {:?}.", new.starts_at)));
+    let ends_at   = try!(DateTime::parse_from_rfc3339(&new.ends_at)
+                                  .map_err(|_| format!("`ends_at` expects a RFC 3339 date, got {:?}.", new.ends_at)));
+
+    if starts_at > ends_at {
+      return Err("`starts_at` cannot come after `ends_at`.".to_owned());
+    }
+
+    Ok(Batch {
+      id:         id,
+      name:       new.name.trim().to_owned(),
+      starts_at:  new.starts_at,
+      ends_at:    new.ends_at,
+      talent_ids: vec![],
+      created_at: UTC::now().to_rfc3339(),
+      closed_at:  None
+    })
+  }
+
+  pub fn is_closed(&self) -> bool {
+    self.closed_at.is_some()
+  }
+
+  /// Return the batch with `talent_ids` assigned to it, each of them once.
+  pub fn assign(&self, talent_ids: &[u64]) -> Batch {
+    let mut batch = self.to_owned();
+
+    for id in talent_ids {
+      if !batch.talent_ids.contains(id) {
+        batch.talent_ids.push(*id);
+      }
+    }
+
+    batch
+  }
+
+  /// Return the batch without `talent_ids`, assigned to another one.
+  pub fn unassign(&self, talent_ids: &[u64]) -> Batch {
+    Batch {
+      talent_ids: self.talent_ids.iter().cloned().filter(|id| !talent_ids.contains(id)).collect(),
+      ..self.to_owned()
+    }
+  }
+
+  /// Return the batch closed at `now`, which ends it unless it has ended already.
+  pub fn close(&self, now: &str) -> Batch {
+    let ended = match (DateTime::parse_from_rfc3339(&self.ends_at), DateTime::parse_from_rfc3339(now)) {
+      (Ok(ends_at), Ok(now)) => ends_at <= now,
+      _                      => false
+    };
+
+    Batch {
+      ends_at:   if ended { self.ends_at.to_owned() } else { now.to_owned() },
+      closed_at: Some(now.to_owned()),
+      ..self.to_owned()
+    }
+  }
+
+  /// Return the fields of its talents derived from the batch, and the time they have
+  /// been added to it if `added_at` is given.
+  pub fn talent_fields(&self, added_at: Option<&str>) -> JsonValue {
+    let mut fields = btreemap! {
+      "batch_starts_at" => self.starts_at.to_owned(),
+      "batch_ends_at"   => self.ends_at.to_owned()
+    };
+
+    if let Some(added_at) = added_at {
+      fields.insert("added_to_batch_at", added_at.to_owned());
+    }
+
+    serde_json::to_value(&fields)
+  }
+}
+
+/// Create `index` with the mapping of the batches. Nothing is done if it exists already.
+pub fn create_index(url: &str, index: &str) -> Result<(), EsError> {
+  let mapping = btreemap! {
+    "mappings" => btreemap! {
+      BATCH_TYPE => btreemap! {
+        "properties" => btreemap! {
+          "id"         => serde_json::to_value(&btreemap! { "type" => "string", "index" => "not_analyzed" }),
+          "name"       => serde_json::to_value(&btreemap! { "type" => "string" }),
+          "starts_at"  => serde_json::to_value(&btreemap! { "type" => "date" }),
+          "ends_at"    => serde_json::to_value(&btreemap! { "type" => "date" }),
+          "talent_ids" => serde_json::to_value(&btreemap! { "type" => "long" }),
+          "created_at" => serde_json::to_value(&btreemap! { "type" => "date" }),
+          "closed_at"  => serde_json::to_value(&btreemap! { "type" => "date" })
+        }
+      }
+    }
+  };
+
+  match es::put(url, index, &serde_json::to_string(&mapping).unwrap()) {
+    Ok(_)                                                                => Ok(()),
+    Err(EsError::EsServerError(ref err)) if err.contains("already_exists") => Ok(()),
+    Err(err)                                                             => Err(err)
+  }
+}
+
+fn document_path(index: &str, id: &str) -> String {
+  format!("{}/{}/{}", index, BATCH_TYPE, id)
+}
+
+/// Return whether `err` is the `404` returned for a missing document.
+fn is_missing(err: &EsError) -> bool {
+  match *err {
+    EsError::EsServerError(ref message) => message.starts_with("404"),
+    _                                   => false
+  }
+}
+
+/// Store `batch` into `index`, searchable right away.
+pub fn save(url: &str, index: &str, batch: &Batch) -> Result<(), Error> {
+  let path = format!("{}?refresh=true", document_path(index, &batch.id));
+  try!(es::put(url, &path, &serde_json::to_string(batch).unwrap()));
+  Ok(())
+}
+
+/// Return the batch with given `id`, if it exists.
+pub fn get(url: &str, index: &str, id: &str) -> Result<Option<Batch>, Error> {
+  match es::get(url, &document_path(index, id)) {
+    Ok(response)                    => Ok(response.find("_source").and_then(|source| serde_json::from_value(source.to_owned()).ok())),
+    Err(ref err) if is_missing(err) => Ok(None),
+    Err(err)                        => Err(Error::from(err))
+  }
+}
+
+/// Return the body of the search of the batches other than `id` holding any of `talent_ids`.
+pub fn previous_query(id: &str, talent_ids: &[u64]) -> JsonValue {
+  serde_json::to_value(&btreemap! {
+    "size"  => serde_json::to_value(&MAX_PREVIOUS),
+    "query" => serde_json::to_value(&btreemap! {
+      "bool" => btreemap! {
+        "filter"   => serde_json::to_value(&vec![btreemap! { "terms" => btreemap! { "talent_ids" => talent_ids } }]),
+        "must_not" => serde_json::to_value(&vec![btreemap! { "term" => btreemap! { "id" => id } }])
+      }
+    })
+  })
+}
+
+/// Return the batches other than `id` holding any of `talent_ids`.
+pub fn previous(url: &str, index: &str, id: &str, talent_ids: &[u64]) -> Result<Vec<Batch>, Error> {
+  let body     = previous_query(id, talent_ids);
+  let response = try!(es::post(url, &format!("{}/_search", index), &serde_json::to_string(&body).unwrap()));
+  Ok(hits(&response))
+}
+
+/// Move the talent `talent_id` out of every batch holding it, returning how many they were.
+pub fn withdraw(url: &str, index: &str, talent_id: u64) -> Result<u64, Error> {
+  let batches = try!(previous(url, index, "", &[talent_id]));
+
+  for batch in &batches {
+    try!(save(url, index, &batch.unassign(&[talent_id])));
+  }
+
+  Ok(batches.len() as u64)
+}
+
+/// Return the batches found by `response`.
+fn hits(response: &JsonValue) -> Vec<Batch> {
+  response.pointer("/hits/hits")
+          .and_then(|hits| hits.as_array())
+          .map(|hits| hits.iter()
+                          .filter_map(|hit| hit.find("_source"))
+                          .filter_map(|source| serde_json::from_value(source.to_owned()).ok())
+                          .collect())
+          .unwrap_or(vec![])
+}
+
+/// Return the `_bulk` body setting `fields` on the documents of `doc_type` with given `ids`.
+pub fn bulk_update(index: &str, doc_type: &str, ids: &[u64], fields: &JsonValue) -> String {
+  let mut body = String::new();
+
+  for id in ids {
+    let action = btreemap! {
+      "update" => btreemap! { "_index" => index.to_owned(), "_type" => doc_type.to_owned(), "_id" => id.to_string() }
+    };
+
+    body.push_str(&serde_json::to_string(&action).unwrap());
+    body.push('\n');
+    body.push_str(&serde_json::to_string(&btreemap! { "doc" => fields }).unwrap());
+    body.push('\n');
+  }
+
+  body
+}
+
+/// Return the `_bulk` body setting `fields` on the stored `documents` of `doc_type` (their
+/// `_source`), routed by their `field` under the keys given as `routing`. The ones whose key is
+/// unchanged are updated on its shard, while the other ones are deleted from the shard of their
+/// previous key and indexed again under the new one, since an update would leave them there.
+pub fn bulk_move(index: &str, doc_type: &str, field: &str, routing: &str, documents: &[JsonValue], fields: &JsonValue) -> String {
+  let mut body = String::new();
+
+  for document in documents {
+    let id = match document.find("id") {
+      Some(&JsonValue::U64(id))        => id.to_string(),
+      Some(&JsonValue::I64(id))        => id.to_string(),
+      Some(&JsonValue::String(ref id)) => id.to_owned(),
+      _                                => continue
+    };
+
+    let mut moved = document.to_owned();
+    if let (&mut JsonValue::Object(ref mut moved), Some(fields)) = (&mut moved, fields.as_object()) {
+      for (name, value) in fields {
+        moved.insert(name.to_owned(), value.to_owned());
+      }
+    }
+
+    let previous = routing::key(field, document);
+    let next     = routing::key(field, &moved);
+
+    let meta = |kind: &str, key: &Option<String>| {
+      let mut meta = btreemap! {
+        "_index".to_owned() => index.to_owned(),
+        "_type".to_owned()  => doc_type.to_owned(),
+        "_id".to_owned()    => id.to_owned()
+      };
+
+      if let Some(ref key) = *key {
+        meta.insert(routing.to_owned(), key.to_owned());
+      }
+
+      serde_json::to_string(&btreemap! { kind.to_owned() => meta }).unwrap()
+    };
+
+    if previous == next {
+      body.push_str(&meta("update", &next));
+      body.push('\n');
+      body.push_str(&serde_json::to_string(&btreemap! { "doc" => fields }).unwrap());
+      body.push('\n');
+    }
+    else {
+      body.push_str(&meta("delete", &previous));
+      body.push('\n');
+      body.push_str(&meta("index", &next));
+      body.push('\n');
+      body.push_str(&serde_json::to_string(&moved).unwrap());
+      body.push('\n');
+    }
+  }
+
+  body
+}
+
+/// Return the IDs of the documents the `_bulk` response could not update since they do not exist.
+pub fn missing(response: &JsonValue) -> Vec<u64> {
+  response.find("items")
+          .and_then(|items| items.as_array())
+          .map(|items| items.iter()
+                            .filter_map(|item| item.find("update"))
+                            .filter(|update| update.find("status").and_then(|status| status.as_u64()) == Some(404))
+                            .filter_map(|update| update.find("_id").and_then(|id| id.as_str()))
+                            .filter_map(|id| id.parse().ok())
+                            .collect())
+          .unwrap_or(vec![])
+}
+
+/// Set the fields derived from `batch` on its talents with given `ids`, stored into `index`
+/// as `doc_type`, returning the ones that are not indexed.
+pub fn update_talents(url: &str, index: &str, doc_type: &str, batch: &Batch, ids: &[u64], added_at: Option<&str>) -> Result<Vec<u64>, Error> {
+  if ids.is_empty() {
+    return Ok(vec![]);
+  }
+
+  let fields = batch.talent_fields(added_at);
+
+  let (body, mut not_found) = match routing::field(doc_type) {
+    None        => (bulk_update(index, doc_type, ids, &fields), vec![]),
+    // the shard of a routed talent is unknown until it has been found
+    Some(field) => {
+      try!(es::refresh(url, index));
+
+      let keys   = ids.iter().map(|id| id.to_string()).collect::<Vec<String>>();
+      let stored = try!(es::mget_routed(url, index, &keys));
+
+      let not_found = ids.iter()
+                         .zip(stored.iter())
+                         .filter(|&(_, document)| document.is_none())
+                         .map(|(id, _)| *id)
+                         .collect::<Vec<u64>>();
+      let documents = stored.into_iter().filter_map(|document| document).collect::<Vec<JsonValue>>();
+
+      (bulk_move(index, doc_type, &field, es::routing_field(es::distribution()), &documents, &fields), not_found)
+    }
+  };
+
+  if body.is_empty() {
+    return Ok(not_found);
+  }
+
+  let response = try!(es::post(url, "_bulk?refresh=true", &body));
+  not_found.extend(missing(&response));
+  Ok(not_found)
+}
+
+#[cfg(test)]
+mod tests {
+  use batches::{Batch, NewBatch, previous_query, bulk_update, bulk_move, missing};
+
+  use serde_json;
+
+  fn new_batch(id: Option<&str>, starts_at: &str, ends_at: &str) -> NewBatch {
+    NewBatch {
+      id:        id.map(|id| id.to_owned()),
+      name:      "March 2017".to_owned(),
+      starts_at: starts_at.to_owned(),
+      ends_at:   ends_at.to_owned()
+    }
+  }
+
+  #[test]
+  fn test_new() {
+    let batch = Batch::new(new_batch(Some("2017-03"), "2017-03-06T00:00:00+00:00", "2017-03-20T00:00:00+00:00")).unwrap();
+    assert_eq!(batch.id, "2017-03");
+    assert!(!batch.is_closed());
+
+    assert!(!Batch::new(new_batch(None, "2017-03-06T00:00:00+00:00", "2017-03-20T00:00:00+00:00")).unwrap().id.is_empty());
+    assert!(Batch::new(new_batch(Some(" "), "2017-03-06T00:00:00+00:00", "2017-03-20T00:00:00+00:00")).is_err());
+    assert!(Batch::new(new_batch(None, "2017-03-06", "2017-03-20T00:00:00+00:00")).is_err());
+    assert_eq!(Batch::new(new_batch(None, "2017-03-20T00:00:00+00:00", "2017-03-06T00:00:00+00:00")).unwrap_err(),
+               "`starts_at` cannot come after `ends_at`.");
+  }
+
+  #[test]
+  fn test_assign_and_close() {
+    let batch = Batch::new(new_batch(None, "2017-03-06T00:00:00+00:00", "2017-03-20T00:00:00+00:00")).unwrap();
+
+    let batch = batch.assign(&[1, 2]).assign(&[2, 3]);
+    assert_eq!(batch.talent_ids, vec![1, 2, 3]);
+    assert_eq!(batch.unassign(&[2]).talent_ids, vec![1, 3]);
+
+    let closed = batch.close("2017-03-10T00:00:00+00:00");
+    assert_eq!(closed.ends_at, "2017-03-10T00:00:00+00:00");
+    assert!(closed.is_closed());
+    assert_eq!(batch.close("2017-04-01T00:00:00+00:00").ends_at, "2017-03-20T00:00:00+00:00");
+
+    let fields = closed.talent_fields(None);
+    assert_eq!(fields.find("batch_ends_at"), Some(&serde_json::to_value(&"2017-03-10T00:00:00+00:00")));
+    assert_eq!(fields.find("added_to_batch_at"), None);
+  }
+
+  #[test]
+  fn test_previous_query() {
+    let body = previous_query("2017-03", &[1, 2]);
+    assert_eq!(body.pointer("/query/bool/filter/0/terms/talent_ids/1"), Some(&serde_json::to_value(&2)));
+    assert_eq!(body.pointer("/query/bool/must_not/0/term/id"), Some(&serde_json::to_value(&"2017-03")));
+  }
+
+  #[test]
+  fn test_bulk_update() {
+    let fields = serde_json::from_str("{\"batch_ends_at\": \"2017-03-10T00:00:00+00:00\"}").unwrap();
+    let body   = bulk_update("talents", "talent", &[42], &fields);
+
+    assert_eq!(body, "{\"update\":{\"_id\":\"42\",\"_index\":\"talents\",\"_type\":\"talent\"}}\n\
+                      {\"doc\":{\"batch_ends_at\":\"2017-03-10T00:00:00+00:00\"}}\n");
+
+    let response = serde_json::from_str("{\"items\": [{\"update\": {\"_id\": \"42\", \"status\": 200}}, \
+                                         {\"update\": {\"_id\": \"43\", \"status\": 404}}]}").unwrap();
+    assert_eq!(missing(&response), vec![43]);
+  }
+
+  #[test]
+  fn test_bulk_move() {
+    let fields    = serde_json::from_str("{\"batch_starts_at\": \"2017-03-20\", \"batch_ends_at\": \"2017-04-03\"}").unwrap();
+    let documents = serde_json::from_str::<Vec<serde_json::Value>>("[{\"id\": 42, \"batch_starts_at\": \"2017-03-20\"}, \
+                                                                     {\"id\": 43, \"batch_starts_at\": \"2017-03-06\"}]").unwrap();
+    let body      = bulk_move("talents", "talent", "batch_starts_at", "_routing", &documents, &fields);
+
+    // the talent staying on its shard is updated, the other one is moved to the shard of its new batch
+    assert_eq!(body, "{\"update\":{\"_id\":\"42\",\"_index\":\"talents\",\"_routing\":\"2017-03-20\",\"_type\":\"talent\"}}\n\
+                      {\"doc\":{\"batch_ends_at\":\"2017-04-03\",\"batch_starts_at\":\"2017-03-20\"}}\n\
+                      {\"delete\":{\"_id\":\"43\",\"_index\":\"talents\",\"_routing\":\"2017-03-06\",\"_type\":\"talent\"}}\n\
+                      {\"index\":{\"_id\":\"43\",\"_index\":\"talents\",\"_routing\":\"2017-03-20\",\"_type\":\"talent\"}}\n\
+                      {\"batch_ends_at\":\"2017-04-03\",\"batch_starts_at\":\"2017-03-20\",\"id\":43}\n");
+  }
+}
@@ -0,0 +1,309 @@
+//! `searchspot-admin`, the maintenance operations on the index of the talents
+//! (see `admin`), reading the same configuration as `searchspot`.
+
+extern crate searchspot;
+extern crate serde_json;
+extern crate rs_es;
+extern crate clap;
+
+use std::{env, process};
+use std::io::{self, BufRead, Write};
+
+use searchspot::resource::Resource;
+use searchspot::resources::Talent;
+use searchspot::config::{Config, PROFILES, PROFILE_VAR};
+use searchspot::health::check_mapping;
+use searchspot::error::Error;
+use searchspot::server::{plan_search, param_errors, oversized_page};
+use searchspot::admin;
+use searchspot::cache::CACHE;
+use searchspot::settings;
+use searchspot::curl;
+use searchspot::es;
+use searchspot::dump;
+use searchspot::migrations;
+use searchspot::slowlog;
+use rs_es::Client;
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+
+/// Return the command line flags.
+fn flags<'a>() -> ArgMatches<'a> {
+    let index = Arg::with_name("index")
+                    .long("index")
+                    .value_name("INDEX")
+                    .help("The index to operate on, es.index if not given");
+
+    App::new("searchspot-admin")
+        .version(env!("CARGO_PKG_VERSION"))
+        .about("The maintenance operations on the index of the talents")
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .arg(Arg::with_name("config")
+                 .long("config")
+                 .value_name("FILE")
+                 .global(true)
+                 .help("The configuration file, looked for in ./searchspot.toml and /etc/searchspot/config.toml if not given"))
+        .arg(Arg::with_name("env")
+                 .long("env")
+                 .value_name("PROFILE")
+                 .possible_values(&PROFILES)
+                 .global(true)
+                 .help("Selects the profile of the configuration file, replaces ENV"))
+        .subcommand(SubCommand::with_name("reset-index")
+                        .about("Deletes the index and creates it again with the current mapping, without any document")
+                        .arg(Arg::with_name("yes")
+                                 .long("yes")
+                                 .help("Does not ask to type the name of the index before deleting it"))
+                        .arg(index.clone()))
+        .subcommand(SubCommand::with_name("reindex")
+                        .about("Rebuilds the index with the current mapping, keeping its documents")
+                        .arg(index.clone()))
+        .subcommand(SubCommand::with_name("migrate")
+                        .about("Applies the migrations of the mapping and of the settings not applied to the index yet, in order")
+                        .arg(Arg::with_name("dry-run")
+                                 .long("dry-run")
+                                 .help("Prints the migrations that would be applied, without applying them"))
+                        .arg(index.clone()))
+        .subcommand(SubCommand::with_name("stats")
+                        .about("Prints the documents and the size of the index as JSON")
+                        .arg(index.clone()))
+        .subcommand(SubCommand::with_name("mapping")
+                        .about("Inspects the mapping of the index")
+                        .setting(AppSettings::SubcommandRequiredElseHelp)
+                        .subcommand(SubCommand::with_name("diff")
+                                        .about("Prints the fields mapped differently from the current mapping, exiting with 1 if any")
+                                        .arg(index.clone())))
+        .subcommand(SubCommand::with_name("search")
+                        .about("Prints the query built for the params, given as a query string, then the ids and the scores it finds")
+                        .arg(Arg::with_name("PARAMS").required(true))
+                        .arg(Arg::with_name("bucket")
+                                 .long("bucket")
+                                 .value_name("ID")
+                                 .help("Searches as the variant of the experiment that ID falls in, as sent by X-Searchspot-Bucket"))
+                        .arg(index.clone()))
+        .subcommand(SubCommand::with_name("slowlog")
+                        .about("Prints the slow searches logged to FILE as it grows (or to the standard input), with their params")
+                        .arg(Arg::with_name("threshold")
+                                 .long("threshold")
+                                 .value_name("DURATION")
+                                 .default_value("0ms")
+                                 .help("Only prints the searches taking at least DURATION, i.e. 500ms or 2s"))
+                        .arg(Arg::with_name("FILE")))
+        .subcommand(SubCommand::with_name("dump")
+                        .about("Writes every document of the index to FILE as gzipped NDJSON")
+                        .arg(Arg::with_name("FILE").required(true))
+                        .arg(index.clone()))
+        .subcommand(SubCommand::with_name("restore")
+                        .about("Indexes the documents of the NDJSON FILE (gzipped if named *.gz), creating the index if needed")
+                        .arg(Arg::with_name("FILE").required(true))
+                        .arg(Arg::with_name("errors")
+                                 .long("errors")
+                                 .value_name("FILE")
+                                 .help("Where the rejected documents are written, <FILE>.rejected if not given"))
+                        .arg(index.clone()))
+        .subcommand(SubCommand::with_name("doc")
+                        .about("Inspects the documents of the index")
+                        .setting(AppSettings::SubcommandRequiredElseHelp)
+                        .subcommand(SubCommand::with_name("get")
+                                        .about("Prints the talent with the given id as JSON, exiting with 1 if it does not exist")
+                                        .arg(Arg::with_name("ID").required(true))
+                                        .arg(index)))
+        .get_matches()
+}
+
+/// Run the subcommand of `flags`, returning whether it succeeded.
+fn run(config: &Config, flags: &ArgMatches) -> Result<bool, Error> {
+    let (name, subcommand) = match flags.subcommand() {
+        ("mapping", Some(mapping)) => mapping.subcommand(),
+        ("doc",     Some(doc))     => doc.subcommand(),
+        subcommand                 => subcommand
+    };
+    let subcommand = subcommand.unwrap();
+
+    // the logs are read without reaching ElasticSearch
+    if name == "slowlog" {
+        return slowlog(subcommand);
+    }
+
+    let url        = admin::connect(config);
+    let mut client = Client::new(&url).unwrap();
+    let settings   = config.resource(Talent::name());
+    let index      = subcommand.value_of("index")
+                               .map(|index| index.to_owned())
+                               .unwrap_or(config.index_of(Talent::name()));
+
+    match name {
+        "reset-index" => {
+            if !subcommand.is_present("yes") && !confirm(&index) {
+                let _ = writeln!(io::stderr(), "{} has not been reset.", index);
+                return Ok(false);
+            }
+
+            try!(admin::reset::<Talent>(&mut client, &url, &index, &settings));
+            CACHE.publish_invalidation(&config.cache, &index);
+            println!("{} has been reset.", index);
+            Ok(true)
+        },
+        "reindex" => {
+            let reindexed = try!(admin::reindex::<Talent>(&mut client, &url, &index, &settings));
+            CACHE.publish_invalidation(&config.cache, &index);
+            println!("{} has been reindexed, {} talents copied into {}.", index, reindexed.documents, reindexed.target);
+            Ok(true)
+        },
+        "migrate" => {
+            let dry_run = subcommand.is_present("dry-run");
+            let applied = try!(migrations::migrate(&url, &index, dry_run));
+
+            for &(version, description) in applied.iter() {
+                println!("{} {}: {}", if dry_run { "Would apply" } else { "Applied" }, version, description);
+            }
+            if applied.is_empty() {
+                println!("{} is up to date.", index);
+            }
+            Ok(true)
+        },
+        "stats" => {
+            let stats = try!(admin::stats(&url, &index));
+            println!("{}", serde_json::to_string_pretty(&stats).unwrap());
+            Ok(true)
+        },
+        "diff" => {
+            let problems = try!(check_mapping(&url, &index, &Talent::mapping()));
+            for problem in problems.iter() {
+                println!("{}", problem);
+            }
+            Ok(problems.is_empty())
+        },
+        "search" => {
+            let params = try!(curl::params(subcommand.value_of("PARAMS").unwrap()).map_err(Error::Validation));
+
+            let errors = param_errors(&params, &Talent::search_params());
+            if !errors.is_empty() {
+                return Err(Error::Validation(format!("The params are unknown or malformed: {}", serde_json::to_string(&errors).unwrap())));
+            }
+            if let Some((per_page, max_page_size)) = oversized_page(&params) {
+                return Err(Error::Validation(format!("`per_page` cannot exceed {}, got {}.", max_page_size, per_page)));
+            }
+
+            let plan = plan_search::<Talent>(config, &url, &index, &params, subcommand.value_of("bucket"));
+            if let Some((name, _)) = plan.variant {
+                println!("Searching as the variant {} of {}.", name, config.experiment.name);
+            }
+            if let Some(ref keywords) = plan.corrected {
+                println!("Searching for the corrected keywords: {}", keywords);
+            }
+
+            let search = try!(Talent::preview(&index, plan.params(&params), &plan.options));
+            println!("{}", serde_json::to_string_pretty(&search).unwrap());
+
+            let response = try!(es::search(&url, &search, false));
+            let total    = response.pointer("/hits/total").and_then(|total| total.as_u64()).unwrap_or(0);
+            let scores   = admin::scores(&response);
+
+            println!("{} talents found, {} returned:", total, scores.len());
+            for (id, score) in scores {
+                println!("{}\t{}", id, score.map(|score| score.to_string()).unwrap_or("-".to_owned()));
+            }
+            Ok(true)
+        },
+        "dump" => {
+            let path = subcommand.value_of("FILE").unwrap();
+            match dump::dump::<Talent>(&url, &index, path) {
+                Ok(count) => {
+                    println!("{} talents of {} have been written to {}.", count, index, path);
+                    Ok(true)
+                },
+                Err(err) => {
+                    let _ = writeln!(io::stderr(), "{} has not been dumped: {}", index, err);
+                    Ok(false)
+                }
+            }
+        },
+        "restore" => {
+            let path   = subcommand.value_of("FILE").unwrap();
+            let errors = subcommand.value_of("errors").map(|errors| errors.to_owned()).unwrap_or(format!("{}.rejected", path));
+            match dump::restore::<Talent>(config, path, &index, &errors) {
+                Ok(report) => {
+                    println!("{} has been restored into {}: {} indexed, {} rejected (see {}).", path, index, report.indexed, report.rejected, errors);
+                    Ok(report.rejected == 0)
+                },
+                Err(err) => {
+                    let _ = writeln!(io::stderr(), "{} has not been restored: {}", path, err);
+                    Ok(false)
+                }
+            }
+        },
+        "get" => {
+            let id = subcommand.value_of("ID").unwrap();
+            match try!(Talent::get(&mut client, id, &index)) {
+                Some(talent) => {
+                    println!("{}", serde_json::to_string_pretty(&talent).unwrap());
+                    Ok(true)
+                },
+                None => {
+                    let _ = writeln!(io::stderr(), "Talent {} not found in {}.", id, index);
+                    Ok(false)
+                }
+            }
+        },
+        _ => unreachable!()
+    }
+}
+
+/// Ask to type the name of `index` on the standard input, returning whether it has been.
+fn confirm(index: &str) -> bool {
+    print!("Every document of {} will be deleted. Type its name to confirm: ", index);
+    let _ = io::stdout().flush();
+
+    let mut answer = String::new();
+    match io::stdin().lock().read_line(&mut answer) {
+        Ok(_)  => answer.trim() == index,
+        Err(_) => false
+    }
+}
+
+/// Print the slow searches of the file of `flags`, or of the standard input, until interrupted.
+fn slowlog(flags: &ArgMatches) -> Result<bool, Error> {
+    let threshold = match slowlog::parse_threshold(flags.value_of("threshold").unwrap()) {
+        Ok(threshold) => threshold,
+        Err(err)      => {
+            let _ = writeln!(io::stderr(), "--threshold: {}", err);
+            return Ok(false);
+        }
+    };
+
+    let stdout = io::stdout();
+    let result = match flags.value_of("FILE") {
+        Some(path) => slowlog::follow(path, threshold, &mut stdout.lock()),
+        None       => {
+            let stdin = io::stdin();
+            slowlog::tail(stdin.lock(), threshold, &mut stdout.lock())
+        }
+    };
+
+    match result {
+        Ok(_)    => Ok(true),
+        Err(err) => {
+            let _ = writeln!(io::stderr(), "The slow searches cannot be read: {}", err);
+            Ok(false)
+        }
+    }
+}
+
+fn main() {
+    let flags = flags();
+
+    if let Some(profile) = flags.value_of("env") {
+        env::set_var(PROFILE_VAR, profile);
+    }
+
+    let config = Config::resolve(flags.value_of("config").map(|path| path.to_owned()));
+    settings::apply(&config);
+
+    match run(&config, &flags) {
+        Ok(succeeded) => process::exit(if succeeded { 0 } else { 1 }),
+        Err(err)      => {
+            let _ = writeln!(io::stderr(), "{}", err);
+            process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,148 @@
+//! The large batches of documents (i.e. a full reindex sent to the bulk
+//! endpoint), split in chunks which are indexed concurrently.
+
+use rs_es::operations::bulk::BulkResult;
+use rs_es::error::EsError;
+
+use cluster::Cluster;
+use error::Error;
+use resource::Resource;
+use retry::retry;
+
+use std::cmp;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::channel;
+use std::thread;
+
+/// How many documents are sent to ElasticSearch in a single bulk request.
+pub const CHUNK_SIZE: usize = 500;
+
+/// Split `resources` in chunks of at most `size` documents, in order.
+pub fn chunks<R: Clone>(resources: Vec<R>, size: usize) -> Vec<Vec<R>> {
+  resources.chunks(cmp::max(size, 1)).map(|chunk| chunk.to_vec()).collect()
+}
+
+/// Whether a document refused with `status` can be indexed again later: ElasticSearch
+/// is overloaded (`429`), failing (`5xx`) or refusing the writes for the time being
+/// (`403`, the `cluster_block_exception` of an index being reindexed).
+pub fn retryable(status: u64) -> bool {
+  status == 403 || status == 429 || status >= 500
+}
+
+/// Index `resources` into `index` in chunks of `CHUNK_SIZE` documents, sending
+/// at most `max_concurrent` of them at the same time, and return their results
+/// in the same order the documents have been given, failing if any chunk fails.
+pub fn index_many<R: Resource>(cluster: Arc<Cluster>, index: &str, resources: Vec<R>, max_concurrent: usize) -> Result<Vec<BulkResult>, Error> {
+  index_chunks(cluster, index, resources, max_concurrent).into_iter().collect()
+}
+
+/// As `index_many`, but return the result of every chunk (as split by `chunks`),
+/// so that a failed chunk does not hide the ones that have been indexed.
+/// The requests still wait for a free client when the pool of `cluster` is smaller.
+pub fn index_chunks<R: Resource>(cluster: Arc<Cluster>, index: &str, resources: Vec<R>, max_concurrent: usize) -> Vec<Result<BulkResult, Error>> {
+  let index = index.to_owned();
+  run_chunks(chunks(resources, CHUNK_SIZE), max_concurrent, move |chunk: Vec<R>| {
+    retry(|| R::index(&mut cluster.get(), &index, chunk.to_owned()))
+  })
+}
+
+/// Run `index` on every chunk, at most `max_concurrent` at the same time, and return
+/// exactly one result per chunk, in their order: a chunk whose indexing panics fails alone.
+fn run_chunks<T, F>(chunks: Vec<Vec<T>>, max_concurrent: usize, index: F) -> Vec<Result<BulkResult, Error>>
+  where T: Send + 'static, F: Fn(Vec<T>) -> Result<BulkResult, Error> + Send + Sync + 'static {
+  let count = chunks.len();
+  let index = Arc::new(index);
+
+  if count <= 1 || max_concurrent <= 1 {
+    return chunks.into_iter().map(|chunk| run(&*index, chunk)).collect();
+  }
+
+  let pending = Arc::new(Mutex::new(chunks.into_iter().enumerate()));
+  let (sender, receiver) = channel();
+
+  for _ in 0..cmp::min(max_concurrent, count) {
+    let pending = pending.clone();
+    let sender  = sender.clone();
+    let index   = index.clone();
+
+    thread::spawn(move || loop {
+      let next = pending.lock().unwrap().next();
+
+      match next {
+        Some((position, chunk)) => {
+          if sender.send((position, run(&*index, chunk))).is_err() {
+            break;
+          }
+        },
+        None => break
+      }
+    });
+  }
+
+  // the receiver is closed once every worker is done
+  drop(sender);
+
+  let mut results = (0..count).map(|_| None).collect::<Vec<Option<Result<BulkResult, Error>>>>();
+  for (position, result) in receiver {
+    results[position] = Some(result);
+  }
+
+  // the chunks of a worker that died anyway are reported as failed rather than dropped
+  results.into_iter().map(|result| result.unwrap_or_else(|| Err(panicked()))).collect()
+}
+
+/// Index `chunk` through `index`, turning a panic into the failure of the chunk.
+fn run<T, F>(index: &F, chunk: Vec<T>) -> Result<BulkResult, Error>
+  where F: Fn(Vec<T>) -> Result<BulkResult, Error> {
+  panic::catch_unwind(AssertUnwindSafe(|| index(chunk))).unwrap_or_else(|_| Err(panicked()))
+}
+
+/// The failure of a chunk whose indexing has panicked.
+fn panicked() -> Error {
+  Error::EsQuery(EsError::EsError("The chunk has not been indexed: its worker has panicked.".to_owned()))
+}
+
+#[cfg(test)]
+mod tests {
+  use bulk::{chunks, retryable, run_chunks};
+  use error::Error;
+
+  use rs_es::operations::bulk::BulkResult;
+
+  use serde_json;
+
+  fn indexed() -> BulkResult {
+    serde_json::from_str(r#"{"took": 1, "errors": false, "items": []}"#).unwrap()
+  }
+
+  #[test]
+  fn test_chunks() {
+    assert_eq!(chunks(vec![1, 2, 3, 4, 5], 2), vec![vec![1, 2], vec![3, 4], vec![5]]);
+    assert_eq!(chunks(vec![1, 2, 3], 5), vec![vec![1, 2, 3]]);
+    assert_eq!(chunks(Vec::<u32>::new(), 5), Vec::<Vec<u32>>::new());
+  }
+
+  #[test]
+  fn test_retryable() {
+    assert!(retryable(429));
+    assert!(retryable(503));
+    assert!(retryable(403));
+    assert!(!retryable(400));
+    assert!(!retryable(409));
+  }}
+
+  #[test]
+  fn test_run_chunks() {
+    for &max_concurrent in &[1, 3] {
+      let results = run_chunks(chunks((1..8).collect::<Vec<u32>>(), 2), max_concurrent, |chunk| match chunk[0] {
+        3 => panic!("the chunk of 3 and 4 cannot be indexed"),
+        5 => Err(Error::Validation("the chunk of 5 and 6 is refused".to_owned())),
+        _ => Ok(indexed())
+      });
+
+      // one result per chunk, in order, the failed ones included
+      assert_eq!(results.iter().map(|result| result.is_ok()).collect::<Vec<bool>>(), vec![true, false, false, true]);
+    }
+  }
+}
@@ -0,0 +1,446 @@
+//! The results of the latest searches, served again without querying
+//! ElasticSearch when the same search is repeated within the TTL
+//! (i.e. when a page firing the same search is reloaded).
+//!
+//! The entries are kept in memory, or in Redis when `cache.redis_url` is given
+//! so that they are shared by the instances behind the same load balancer.
+//! The writes are then published on `INVALIDATIONS_CHANNEL`, so that every
+//! instance stops serving the searches of the index written to. Since the
+//! writes published while an instance was not subscribed are lost, the
+//! generations are read again from Redis every time it subscribes.
+
+use serde::de::Deserialize;
+use serde::ser::Serialize;
+use serde_json;
+use serde_json::Value as JsonValue;
+
+use params::{Map, Value};
+
+use redis;
+use redis::RedisResult;
+
+use config::Cache as CacheConfig;
+use redis_store::RedisStore;
+
+use std::collections::{BTreeMap, HashMap};
+use std::mem;
+use std::sync::{Mutex, RwLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// The prefix of the keys of the entries stored in Redis.
+const ENTRY_PREFIX: &'static str = "searchspot:cache:";
+
+/// The prefix of the keys holding the generation of every index in Redis.
+const GENERATION_PREFIX: &'static str = "searchspot:generation:";
+
+/// The channel the writes are published on, as `<index>|<generation>`.
+pub const INVALIDATIONS_CHANNEL: &'static str = "searchspot:invalidations";
+
+/// How long to wait before subscribing again to the invalidations.
+const RESUBSCRIBE_DELAY: u64 = 5; // seconds
+
+lazy_static! {
+  /// The cache shared across the handlers.
+  pub static ref CACHE: Cache = Cache::new(&CacheConfig::default());
+}
+
+struct Entry {
+  value:     JsonValue,
+  stored_at: Instant,
+  used_at:   u64
+}
+
+/// The entries and the order they have been used in, the least recent first.
+struct Entries {
+  entries: HashMap<String, Entry>,
+  usage:   BTreeMap<u64, String>,
+  clock:   u64
+}
+
+impl Entries {
+  fn new() -> Entries {
+    Entries {
+      entries: HashMap::new(),
+      usage:   BTreeMap::new(),
+      clock:   0
+    }
+  }
+
+  fn tick(&mut self) -> u64 {
+    self.clock += 1;
+    self.clock
+  }
+
+  fn remove(&mut self, key: &str) {
+    if let Some(entry) = self.entries.remove(key) {
+      self.usage.remove(&entry.used_at);
+    }
+  }
+}
+
+/// A LRU cache of the search results whose entries expire after a TTL.
+pub struct Cache {
+  entries:     Mutex<Entries>,
+  settings:    Mutex<(usize, Duration)>,
+  generations: Mutex<HashMap<String, u64>>,
+  redis:       RwLock<Option<RedisStore>>
+}
+
+impl Cache {
+  pub fn new(config: &CacheConfig) -> Cache {
+    Cache {
+      entries:     Mutex::new(Entries::new()),
+      settings:    Mutex::new((config.size, Duration::from_secs(config.ttl))),
+      generations: Mutex::new(HashMap::new()),
+      redis:       RwLock::new(None)
+    }
+  }
+
+  /// Use the size and the TTL of `config`, dropping the current entries.
+  pub fn configure(&self, config: &CacheConfig) {
+    *self.settings.lock().unwrap() = (config.size, Duration::from_secs(config.ttl));
+    *self.entries.lock().unwrap()  = Entries::new();
+  }
+
+  /// Store the entries into `store` from now on, and listen to the
+  /// writes published by the other instances from a background thread.
+  pub fn connect(&'static self, store: RedisStore) {
+    *self.redis.write().unwrap() = Some(store.to_owned());
+
+    thread::spawn(move || loop {
+      if let Err(err) = self.subscribe(&store) {
+        error!("The cache invalidations cannot be received: {}", err);
+      }
+
+      thread::sleep(Duration::from_secs(RESUBSCRIBE_DELAY));
+    });
+  }
+
+  /// Tell the instances sharing the Redis server of `config`, if any, to stop serving the
+  /// searches on `index`, i.e. from the executables rebuilding it without serving them.
+  pub fn publish_invalidation(&self, config: &CacheConfig, index: &str) {
+    if let Some(ref redis_url) = config.redis_url {
+      match RedisStore::open(redis_url, config.redis_timeout()) {
+        Ok(store) => *self.redis.write().unwrap() = Some(store),
+        Err(err)  => error!("The rebuild of {} cannot be published to the instances: {}", index, err)
+      }
+    }
+
+    self.invalidate(index);
+  }
+
+  /// Return the Redis server the entries are stored into, if any.
+  fn store(&self) -> Option<RedisStore> {
+    self.redis.read().unwrap().clone()
+  }
+
+  /// Apply the writes published on `INVALIDATIONS_CHANNEL` as long as the connection is open.
+  fn subscribe(&self, store: &RedisStore) -> RedisResult<()> {
+    let mut pubsub = try!(store.client().get_pubsub());
+    try!(pubsub.subscribe(INVALIDATIONS_CHANNEL));
+
+    // the writes published while unsubscribed have been missed
+    self.resync(store);
+
+    loop {
+      let payload = try!(try!(pubsub.get_message()).get_payload::<String>());
+
+      match parse_invalidation(&payload) {
+        Some((index, generation)) => self.advance(index, generation),
+        None                      => warn!("Unexpected cache invalidation: {:?}", payload)
+      }
+    }
+  }
+
+  /// Return the value stored for `key`, unless it has expired.
+  pub fn get<T: Deserialize>(&self, key: &str) -> Option<T> {
+    let (size, ttl) = *self.settings.lock().unwrap();
+    if size == 0 {
+      return None;
+    }
+
+    if let Some(redis) = self.store() {
+      let key = format!("{}{}", ENTRY_PREFIX, key);
+      return match redis.query(|connection| redis::cmd("GET").arg(&*key).query::<Option<String>>(connection)) {
+        Ok(value) => value.and_then(|value| serde_json::from_str(&value).ok()),
+        Err(err)  => {
+          warn!("The cache cannot be read from Redis: {}", err);
+          None
+        }
+      };
+    }
+
+    let mut entries = self.entries.lock().unwrap();
+
+    let expired = match entries.entries.get(key) {
+      Some(entry) => entry.stored_at.elapsed() > ttl,
+      None        => return None
+    };
+
+    if expired {
+      entries.remove(key);
+      return None;
+    }
+
+    let used_at  = entries.tick();
+    let previous = {
+      let entry = entries.entries.get_mut(key).unwrap();
+      mem::replace(&mut entry.used_at, used_at)
+    };
+
+    entries.usage.remove(&previous);
+    entries.usage.insert(used_at, key.to_owned());
+
+    serde_json::from_value(entries.entries[key].value.to_owned()).ok()
+  }
+
+  /// Store `value` for `key`, evicting the least recently used entry if full.
+  pub fn put<T: Serialize>(&self, key: &str, value: &T) {
+    let (size, ttl) = *self.settings.lock().unwrap();
+    if size == 0 {
+      return;
+    }
+
+    // the entries expire on their own, Redis evicts them according to its `maxmemory-policy`
+    if let Some(redis) = self.store() {
+      let key   = format!("{}{}", ENTRY_PREFIX, key);
+      let value = serde_json::to_string(value).unwrap();
+      let ttl   = ttl.as_secs().max(1) as usize;
+
+      if let Err(err) = redis.query(|connection| redis::cmd("SETEX").arg(&*key).arg(ttl).arg(&*value).query::<()>(connection)) {
+        warn!("The cache cannot be written to Redis: {}", err);
+      }
+      return;
+    }
+
+    let mut entries = self.entries.lock().unwrap();
+    entries.remove(key);
+
+    while entries.entries.len() >= size {
+      let oldest = entries.usage.iter().next().map(|(_, key)| key.to_owned());
+      match oldest {
+        Some(oldest) => entries.remove(&oldest),
+        None         => break
+      }
+    }
+
+    let used_at = entries.tick();
+    entries.usage.insert(used_at, key.to_owned());
+    entries.entries.insert(key.to_owned(), Entry {
+      value:     serde_json::to_value(value),
+      stored_at: Instant::now(),
+      used_at:   used_at
+    });
+  }
+
+  /// Return how many times `index` has been written to, part of the keys of its searches.
+  /// With Redis, it is read from there the first time, and kept up to date by the invalidations.
+  pub fn generation(&self, index: &str) -> u64 {
+    if let Some(generation) = self.generations.lock().unwrap().get(index).cloned() {
+      return generation;
+    }
+
+    if let Some(redis) = self.store() {
+      self.read_generation(&redis, index);
+    }
+
+    self.generations.lock().unwrap().get(index).cloned().unwrap_or(0)
+  }
+
+  /// Move `index` to the generation stored into `redis`, if it is later than the known one.
+  fn read_generation(&self, redis: &RedisStore, index: &str) {
+    let key = format!("{}{}", GENERATION_PREFIX, index);
+    match redis.query(|connection| redis::cmd("GET").arg(&*key).query::<Option<u64>>(connection)) {
+      Ok(generation) => self.advance(index, generation.unwrap_or(0)),
+      Err(err)       => warn!("The generation of {} cannot be read from Redis: {}", index, err)
+    }
+  }
+
+  /// Read again the generations of the known indexes from `redis`.
+  fn resync(&self, redis: &RedisStore) {
+    let indexes = self.generations.lock().unwrap().keys().cloned().collect::<Vec<String>>();
+    for index in indexes {
+      self.read_generation(redis, &index);
+    }
+  }
+
+  /// Drop the entries of the searches on `index` after it has been written to.
+  /// The searches still running are stored with the previous generation,
+  /// so they are never served either. With Redis, the other instances are
+  /// told to do the same.
+  pub fn invalidate(&self, index: &str) {
+    let generation = self.generation(index) + 1;
+
+    if let Some(redis) = self.store() {
+      let key = format!("{}{}", GENERATION_PREFIX, index);
+      let published = redis.query(|connection| {
+        let generation = try!(redis::cmd("INCR").arg(&*key).query::<u64>(connection));
+        try!(redis::cmd("PUBLISH").arg(INVALIDATIONS_CHANNEL).arg(format!("{}|{}", index, generation)).query::<()>(connection));
+        Ok(generation)
+      });
+
+      match published {
+        Ok(generation) => return self.advance(index, generation),
+        Err(err)       => error!("The write to {} cannot be published to the other instances: {}", index, err)
+      }
+    }
+
+    self.advance(index, generation);
+  }
+
+  /// Move `index` to `generation`, unless it is there already,
+  /// dropping the entries of the previous ones.
+  fn advance(&self, index: &str, generation: u64) {
+    {
+      let mut generations = self.generations.lock().unwrap();
+      let current = generations.entry(index.to_owned()).or_insert(0);
+      if *current >= generation {
+        return;
+      }
+
+      *current = generation;
+    }
+
+    let mut entries = self.entries.lock().unwrap();
+    let stale = entries.entries.keys()
+                               .filter(|key| key.split('|').nth(1) == Some(index))
+                               .cloned()
+                               .collect::<Vec<String>>();
+
+    for key in stale {
+      entries.remove(&key);
+    }
+  }
+
+  /// Return how many entries are stored, including the expired ones.
+  pub fn len(&self) -> usize {
+    self.entries.lock().unwrap().entries.len()
+  }
+}
+
+/// Return the index and the generation of an invalidation, given as `<index>|<generation>`.
+fn parse_invalidation(payload: &str) -> Option<(&str, u64)> {
+  let mut parts = payload.rsplitn(2, '|');
+
+  match (parts.next().and_then(|generation| generation.parse().ok()), parts.next()) {
+    (Some(generation), Some(index)) if !index.is_empty() => Some((index, generation)),
+    _                                                     => None
+  }
+}
+
+/// Return the key of the search of `resource` on the given `generation` of `index`
+/// with given `params`, whose `epoch` is truncated to the minute, so that the searches
+/// sent with the current time share the same entry. `options` tells apart the searches
+/// run before and after the settings have been reloaded.
+pub fn key(resource: &str, index: &str, generation: u64, params: &Map, options: &str) -> String {
+  let mut params = params.to_owned();
+
+  let epoch = match params.remove("epoch") {
+    Some(Value::String(epoch)) => epoch.chars().take(16).collect::<String>(),
+    _                          => "".to_owned()
+  };
+
+  // the names of the indexes cannot contain `|`
+  format!("{}|{}|{}|{:?}|{}|{}", resource, index, generation, params, epoch, options)
+}
+
+#[cfg(test)]
+mod tests {
+  use cache::{Cache, key, parse_invalidation};
+  use config::Cache as CacheConfig;
+
+  use params::{Map, Value};
+
+  use std::thread;
+  use std::time::Duration;
+
+  #[test]
+  fn test_lru() {
+    let cache = Cache::new(&CacheConfig { size: 2, ttl: 60, ..Default::default() });
+
+    cache.put("a", &1);
+    cache.put("b", &2);
+    assert_eq!(cache.get::<u32>("a"), Some(1));
+
+    // `b` is the least recently used
+    cache.put("c", &3);
+    assert_eq!(cache.get::<u32>("b"), None);
+    assert_eq!(cache.get::<u32>("a"), Some(1));
+    assert_eq!(cache.get::<u32>("c"), Some(3));
+    assert_eq!(cache.len(), 2);
+  }
+
+  #[test]
+  fn test_ttl() {
+    let cache = Cache::new(&CacheConfig { size: 2, ttl: 0, ..Default::default() });
+
+    cache.put("a", &1);
+    thread::sleep(Duration::from_millis(5));
+    assert_eq!(cache.get::<u32>("a"), None);
+
+    // disabled
+    let cache = Cache::new(&CacheConfig { size: 0, ttl: 60, ..Default::default() });
+    cache.put("a", &1);
+    assert_eq!(cache.get::<u32>("a"), None);
+  }
+
+  #[test]
+  fn test_key() {
+    let mut first = Map::new();
+    first.assign("epoch", Value::String("2017-03-01T10:30:12+00:00".into())).unwrap();
+    first.assign("keywords", Value::String("rust".into())).unwrap();
+
+    let mut second = Map::new();
+    second.assign("keywords", Value::String("rust".into())).unwrap();
+    second.assign("epoch", Value::String("2017-03-01T10:30:48+00:00".into())).unwrap();
+
+    assert_eq!(key("talent", "talents", 0, &first, ""), key("talent", "talents", 0, &second, ""));
+    assert!(key("talent", "talents", 0, &first, "") != key("talent", "other", 0, &first, ""));
+    assert!(key("talent", "talents", 0, &first, "") != key("talent", "talents", 1, &first, ""));
+  }
+
+  #[test]
+  fn test_invalidate() {
+    let cache = Cache::new(&CacheConfig { size: 10, ttl: 60, ..Default::default() });
+    let params = Map::new();
+
+    let talents = key("talent", "talents", cache.generation("talents"), &params, "");
+    let others  = key("talent", "others", cache.generation("others"), &params, "");
+    cache.put(&talents, &1);
+    cache.put(&others, &2);
+
+    cache.invalidate("talents");
+    assert_eq!(cache.get::<u32>(&talents), None);
+    assert_eq!(cache.get::<u32>(&others), Some(2));
+
+    // the searches started before the write are not served
+    assert_eq!(cache.generation("talents"), 1);
+    assert!(key("talent", "talents", cache.generation("talents"), &params, "") != talents);
+  }
+
+  #[test]
+  fn test_advance() {
+    let cache = Cache::new(&CacheConfig { size: 10, ttl: 60, ..Default::default() });
+
+    // published by another instance
+    cache.advance("talents", 3);
+    assert_eq!(cache.generation("talents"), 3);
+
+    // received late, after a later write
+    cache.advance("talents", 2);
+    assert_eq!(cache.generation("talents"), 3);
+
+    cache.invalidate("talents");
+    assert_eq!(cache.generation("talents"), 4);
+  }
+
+  #[test]
+  fn test_parse_invalidation() {
+    assert_eq!(parse_invalidation("talents|3"),     Some(("talents", 3)));
+    assert_eq!(parse_invalidation("a|b|12"),        Some(("a|b", 12)));
+    assert_eq!(parse_invalidation("talents"),       None);
+    assert_eq!(parse_invalidation("talents|three"), None);
+    assert_eq!(parse_invalidation("|3"),            None);
+  }
+}
@@ -0,0 +1,134 @@
+//! The validation of a configuration file before it is deployed, run by
+//! `searchspot check-config <file>`: the file must be valid, every node of
+//! the cluster reachable and the index of the resource mapped as expected.
+
+use config::Config;
+use resource::Resource;
+use health::check_mapping;
+use error::Error;
+use es;
+use retry;
+
+use std::fmt;
+
+/// The outcome of one of the checks, described by a single line.
+pub struct Check {
+  pub name:    String,
+  pub outcome: Result<String, String>
+}
+
+/// The checks run in order, stopped at the first one the following
+/// ones depend on (i.e.: the cluster is not checked if the file is invalid).
+pub struct Report {
+  pub checks: Vec<Check>
+}
+
+impl Report {
+  fn push<S: Into<String>>(&mut self, name: S, outcome: Result<String, String>) -> bool {
+    let passed = outcome.is_ok();
+    self.checks.push(Check { name: name.into(), outcome: outcome });
+    passed
+  }
+
+  /// Return whether all the checks passed.
+  pub fn passed(&self) -> bool {
+    self.checks.iter().all(|check| check.outcome.is_ok())
+  }
+}
+
+impl fmt::Display for Report {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    for check in self.checks.iter() {
+      match check.outcome {
+        Ok(ref message)  => try!(writeln!(f, "ok      {}: {}", check.name, message)),
+        Err(ref message) => try!(writeln!(f, "FAILED  {}: {}", check.name, message.replace("\n", "\n          ")))
+      }
+    }
+
+    write!(f, "{}", if self.passed() { "The configuration is valid." } else { "The configuration is not valid." })
+  }
+}
+
+/// Check the configuration file at `path` and the cluster and the index of `R` it points to.
+pub fn check<R: Resource>(path: &str) -> Report {
+  let mut report = Report { checks: vec![] };
+
+  let config = match Config::load(path) {
+    Ok(config) => config,
+    Err(err)   => {
+      report.push(path, Err(err.to_string()));
+      return report;
+    }
+  };
+  report.push(path, Ok("valid".to_owned()));
+
+  es::configure_tls(config.es.ca_cert.to_owned(), config.es.insecure);
+  es::configure_timeouts(config.es.connect_timeout(), config.es.request_timeout());
+  retry::configure(config.es.retry.to_owned());
+
+  let mut reachable = vec![];
+  for url in config.es.urls() {
+    let version = es::get(&url, "").map(|info| {
+      info.pointer("/version/number").and_then(|number| number.as_str()).unwrap_or("unknown").to_owned()
+    });
+
+    if report.push(es::redact(&url), version.map(|version| format!("reachable (version {})", version)).map_err(|err| err.to_string())) {
+      reachable.push(url);
+    }
+  }
+
+  let url = match reachable.first() {
+    Some(url) => url.to_owned(),
+    None      => return report
+  };
+  es::configure_compatibility(&config.es.compatibility, &url);
+
+  let status = es::get(&url, "_cluster/health").map_err(|err| err.to_string()).and_then(|health| {
+    match health.find("status").and_then(|status| status.as_str()) {
+      Some("red")  => Err("the cluster is red".to_owned()),
+      Some(status) => Ok(format!("the cluster is {}", status)),
+      None         => Err("the status of the cluster is unknown".to_owned())
+    }
+  });
+  report.push("cluster", status);
+
+  let index = config.index_of(R::name());
+  let problems = check_mapping(&url, &index, &R::mapping()).map_err(|err| match Error::from(err) {
+    Error::IndexNotFound(_) => "it does not exist".to_owned(),
+    err                     => err.to_string()
+  });
+
+  let mapping = problems.and_then(|problems| {
+    if problems.is_empty() { Ok(format!("mapped as expected by `{}`", R::name())) } else { Err(problems.join("\n")) }
+  });
+  report.push(format!("index {}", index), mapping);
+
+  report
+}
+
+#[cfg(test)]
+mod tests {
+  use check::{check, Check, Report};
+  use resources::Talent;
+
+  #[test]
+  fn test_check() {
+    let report = check::<Talent>("examples/missing.toml");
+    assert!(!report.passed());
+    assert_eq!(report.checks.len(), 1);
+  }
+
+  #[test]
+  fn test_report() {
+    let report = Report { checks: vec![
+      Check { name: "searchspot.toml".to_owned(), outcome: Ok("valid".to_owned()) },
+      Check { name: "index talents".to_owned(),   outcome: Err("`talent.skills` is not mapped.\n`talent.weight` is not mapped.".to_owned()) }
+    ]};
+
+    assert!(!report.passed());
+    assert_eq!(report.to_string(), "ok      searchspot.toml: valid\n\
+                                    FAILED  index talents: `talent.skills` is not mapped.\n          \
+                                    `talent.weight` is not mapped.\n\
+                                    The configuration is not valid.");
+  }
+}
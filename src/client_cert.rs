@@ -0,0 +1,122 @@
+//! The verification of the certificates of the clients (mutual TLS), required
+//! by the write and admin endpoints when `tls.client_ca` is given.
+//!
+//! The certificate is verified during the handshake, so its common name is
+//! known before the first request of the connection is read. Since hyper
+//! handles each connection on the thread which has accepted it, the common
+//! name is kept in a thread-local until the next connection is accepted.
+
+use hyper;
+use hyper::net::{NetworkStream, SslServer};
+use hyper_openssl::{OpensslServer, SslStream};
+
+use openssl::nid;
+use openssl::pkey::PKey;
+use openssl::ssl::{SslAcceptorBuilder, SslMethod, SSL_VERIFY_PEER};
+use openssl::x509::X509;
+
+use config::TLS as TLSConfig;
+
+use std::cell::RefCell;
+use std::fmt::Debug;
+use std::fs::File;
+use std::io::Read;
+
+thread_local! {
+  /// The common name of the certificate of the client whose connection is handled by the current thread.
+  static PEER: RefCell<Option<String>> = RefCell::new(None);
+}
+
+/// Serve HTTPS, recording the common name of the certificate of the client, if any.
+#[derive(Clone)]
+pub struct ClientCertServer(OpensslServer);
+
+impl<T> SslServer<T> for ClientCertServer where T: NetworkStream + Clone + Sync + Send + Debug {
+  type Stream = SslStream<T>;
+
+  fn wrap_server(&self, stream: T) -> hyper::Result<SslStream<T>> {
+    let stream = try!(self.0.wrap_server(stream));
+
+    let peer = stream.lock().ssl().peer_certificate().and_then(|cert| common_name(&cert));
+    PEER.with(|current| *current.borrow_mut() = peer);
+
+    Ok(stream)
+  }
+}
+
+/// Return the server of the certificate (chain) and private key of `config`,
+/// asking the clients for a certificate signed by `tls.client_ca`, if given.
+/// The clients without a certificate are still accepted.
+pub fn server(config: &TLSConfig) -> Result<ClientCertServer, String> {
+  let key   = try!(PKey::private_key_from_pem(&try!(read(&config.key))).map_err(|err| err.to_string()));
+  let chain = try!(X509::stack_from_pem(&try!(read(&config.cert))).map_err(|err| err.to_string()));
+
+  let (cert, intermediates) = match chain.split_first() {
+    Some(chain) => chain,
+    None        => return Err(format!("{} does not contain any certificate.", config.cert))
+  };
+
+  let mut builder = try!(SslAcceptorBuilder::mozilla_intermediate(SslMethod::tls(), &key, cert, intermediates)
+                                            .map_err(|err| err.to_string()));
+
+  if let Some(ref client_ca) = config.client_ca {
+    try!(builder.builder_mut().set_ca_file(client_ca).map_err(|err| format!("{}: {}", client_ca, err)));
+    builder.builder_mut().set_verify(SSL_VERIFY_PEER);
+  }
+
+  Ok(ClientCertServer(OpensslServer::from(builder.build())))
+}
+
+fn read(path: &str) -> Result<Vec<u8>, String> {
+  let mut content = vec![];
+  try!(File::open(path).and_then(|mut file| file.read_to_end(&mut content)).map_err(|err| format!("{}: {}", path, err)));
+  Ok(content)
+}
+
+fn common_name(cert: &X509) -> Option<String> {
+  cert.subject_name()
+      .entries_by_nid(nid::COMMONNAME)
+      .next()
+      .and_then(|entry| entry.data().as_utf8().ok())
+      .map(|name| name.to_string())
+}
+
+/// Return the common name of the certificate of the current client, if it has sent one.
+pub fn peer() -> Option<String> {
+  PEER.with(|current| current.borrow().to_owned())
+}
+
+/// Return whether the client called `peer` (the common name of its certificate)
+/// can hit the write and admin endpoints: any client can when `tls.client_ca`
+/// is not given, otherwise only the ones listed in `tls.allowed_clients`
+/// (or any with a valid certificate, if empty).
+pub fn is_allowed(config: &TLSConfig, peer: Option<&str>) -> bool {
+  if config.client_ca.is_none() {
+    return true;
+  }
+
+  match peer {
+    Some(peer) => config.allowed_clients.is_empty() || config.allowed_clients.iter().any(|client| client == peer),
+    None       => false
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use client_cert::is_allowed;
+  use config::TLS;
+
+  #[test]
+  fn test_is_allowed() {
+    let mut tls = TLS { enabled: true, ..Default::default() };
+    assert!(is_allowed(&tls, None));
+
+    tls.client_ca = Some("/etc/searchspot/clients.pem".to_owned());
+    assert!(!is_allowed(&tls, None));
+    assert!(is_allowed(&tls, Some("honeypot")));
+
+    tls.allowed_clients = vec!["honeypot".to_owned(), "importer".to_owned()];
+    assert!(is_allowed(&tls, Some("importer")));
+    assert!(!is_allowed(&tls, Some("crawler")));
+  }
+}
@@ -0,0 +1,31 @@
+//! The current time as seen by the searches (i.e.: to show only the talents
+//! whose batch is alive), which the tests can fix to a given moment.
+
+use chrono::{DateTime, UTC};
+
+use std::fmt::Debug;
+
+pub trait Clock: Send + Sync + Debug {
+  /// Return the current time.
+  fn now(&self) -> DateTime<UTC>;
+}
+
+/// The clock of the system, used by the server.
+#[derive(Debug, Clone, Copy)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+  fn now(&self) -> DateTime<UTC> {
+    UTC::now()
+  }
+}
+
+/// A clock stopped at the given time.
+#[derive(Debug, Clone, Copy)]
+pub struct FixedClock(pub DateTime<UTC>);
+
+impl Clock for FixedClock {
+  fn now(&self) -> DateTime<UTC> {
+    self.0
+  }
+}
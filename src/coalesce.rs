@@ -0,0 +1,75 @@
+//! The writes of the same documents sent within a short time (i.e. several
+//! updates of a talent within a second), coalesced so that only their latest
+//! version is indexed once they are flushed.
+
+use std::collections::BTreeMap;
+use std::mem;
+use std::sync::Mutex;
+
+pub struct Coalescer<R> {
+  pending: Mutex<BTreeMap<String, R>>
+}
+
+impl<R> Coalescer<R> {
+  pub fn new() -> Coalescer<R> {
+    Coalescer {
+      pending: Mutex::new(BTreeMap::new())
+    }
+  }
+
+  /// Queue `resource`, replacing the pending version with the same `id`.
+  pub fn push(&self, id: String, resource: R) {
+    self.pending.lock().unwrap().insert(id, resource);
+  }
+
+  /// Queue again the resources that could not be flushed,
+  /// unless a newer version has been queued meanwhile.
+  pub fn restore(&self, resources: Vec<(String, R)>) {
+    let mut pending = self.pending.lock().unwrap();
+
+    for (id, resource) in resources {
+      pending.entry(id).or_insert(resource);
+    }
+  }
+
+  /// Take the pending resources, leaving none.
+  pub fn take(&self) -> Vec<(String, R)> {
+    let mut pending = self.pending.lock().unwrap();
+    mem::replace(&mut *pending, BTreeMap::new()).into_iter().collect()
+  }
+
+  /// Drop the pending version with given `id`, returning whether there was one.
+  pub fn remove(&self, id: &str) -> bool {
+    self.pending.lock().unwrap().remove(id).is_some()
+  }
+
+  pub fn len(&self) -> usize {
+    self.pending.lock().unwrap().len()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use coalesce::Coalescer;
+
+  #[test]
+  fn test_coalesce() {
+    let coalescer = Coalescer::new();
+
+    coalescer.push("1".to_owned(), "first");
+    coalescer.push("2".to_owned(), "second");
+    coalescer.push("1".to_owned(), "third");
+    assert_eq!(coalescer.len(), 2);
+
+    let pending = coalescer.take();
+    assert_eq!(pending, vec![("1".to_owned(), "third"), ("2".to_owned(), "second")]);
+    assert_eq!(coalescer.len(), 0);
+
+    // the newer versions are kept
+    coalescer.push("1".to_owned(), "fourth");
+    coalescer.restore(pending);
+    assert!(coalescer.remove("2"));
+    assert!(!coalescer.remove("3"));
+    assert_eq!(coalescer.take(), vec![("1".to_owned(), "fourth")]);
+  }
+}
@@ -0,0 +1,102 @@
+//! Negotiation and compression of the response bodies.
+
+use flate2::Compression;
+use flate2::write::{GzEncoder, DeflateEncoder};
+
+use std::io;
+use std::io::Write;
+
+/// The content encodings that can be applied to the responses.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Encoding {
+  Gzip,
+  Deflate
+}
+
+impl Encoding {
+  pub fn as_str(&self) -> &'static str {
+    match *self {
+      Encoding::Gzip    => "gzip",
+      Encoding::Deflate => "deflate"
+    }
+  }
+
+  /// Return the preferred encoding among the ones accepted
+  /// in given `Accept-Encoding` header, if any.
+  /// With the same quality, `gzip` is preferred over `deflate`.
+  pub fn negotiate(accept_encoding: &str) -> Option<Encoding> {
+    let mut best: Option<(Encoding, f32)> = None;
+
+    for item in accept_encoding.split(',') {
+      let mut parts = item.split(';').map(|part| part.trim());
+
+      let encoding = match parts.next() {
+        Some("gzip") | Some("x-gzip") | Some("*") => Encoding::Gzip,
+        Some("deflate")                           => Encoding::Deflate,
+        _                                         => continue
+      };
+
+      let quality = parts.filter(|part| part.starts_with("q="))
+                         .filter_map(|part| part[2..].parse::<f32>().ok())
+                         .next()
+                         .unwrap_or(1.0);
+
+      if quality <= 0.0 {
+        continue;
+      }
+
+      match best {
+        Some((_, best_quality)) if best_quality >= quality => {},
+        _ => best = Some((encoding, quality))
+      }
+    }
+
+    best.map(|(encoding, _)| encoding)
+  }
+
+  /// Compress `body` using the encoding.
+  pub fn compress(&self, body: &[u8]) -> io::Result<Vec<u8>> {
+    match *self {
+      Encoding::Gzip => {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::Default);
+        try!(encoder.write_all(body));
+        encoder.finish()
+      },
+      Encoding::Deflate => {
+        let mut encoder = DeflateEncoder::new(Vec::new(), Compression::Default);
+        try!(encoder.write_all(body));
+        encoder.finish()
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use compression::Encoding;
+
+  use flate2::read::GzDecoder;
+
+  use std::io::Read;
+
+  #[test]
+  fn test_negotiate() {
+    assert_eq!(Encoding::negotiate("gzip, deflate"), Some(Encoding::Gzip));
+    assert_eq!(Encoding::negotiate("deflate"), Some(Encoding::Deflate));
+    assert_eq!(Encoding::negotiate("gzip;q=0.5, deflate"), Some(Encoding::Deflate));
+    assert_eq!(Encoding::negotiate("gzip;q=0, br"), None);
+    assert_eq!(Encoding::negotiate("identity"), None);
+    assert_eq!(Encoding::negotiate(""), None);
+  }
+
+  #[test]
+  fn test_compress() {
+    let body = "{\"total\":0,\"talents\":[]}".repeat(10);
+    let compressed = Encoding::Gzip.compress(body.as_bytes()).unwrap();
+    assert!(compressed.len() < body.len());
+
+    let mut decompressed = String::new();
+    GzDecoder::new(&compressed[..]).unwrap().read_to_string(&mut decompressed).unwrap();
+    assert_eq!(decompressed, body);
+  }
+}
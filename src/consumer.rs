@@ -0,0 +1,446 @@
+//! The consumer of the indexing events published by the main application on
+//! an AMQP (i.e.: RabbitMQ) queue or on a Kafka topic (see `kafka`), applied
+//! through the bulk pipeline rather than pushed synchronously to the HTTP
+//! endpoints (see `searchspot --consume`).
+//!
+//! The events are acknowledged once applied, rejected when they cannot ever be
+//! (i.e.: malformed, or refused by the schema) and requeued when ElasticSearch fails,
+//! is overloaded or refuses the writes for the time being (see `bulk::retryable`).
+//! The ones carrying `updated_at` are indexed (or deleted) with it as external version,
+//! so that the events delivered again or out of order cannot replace a later update.
+//!
+//! The events pushed by the main application to `POST /webhooks/<resource>`
+//! are applied in background in the same way, by a `Worker`.
+
+use amqp;
+use amqp::{AMQPError, Basic, Channel, Session, Table};
+use amqp::protocol::basic::{Deliver, BasicProperties};
+
+use serde_json;
+use serde_json::Value as JsonValue;
+
+use chrono::{DateTime, Timelike};
+
+use config::Config;
+use resource::Resource;
+use cluster;
+use cluster::Cluster;
+use bulk;
+use es;
+use retry::retry;
+use settings;
+use logger::start_logging;
+use metrics::METRICS;
+use cache::CACHE;
+use redis_store::RedisStore;
+use audit;
+use audit::{AUDIT, OperationEntry};
+use webhooks::{WEBHOOKS, Event};
+use server::audit_indexed;
+use kafka;
+use queue::Reservation;
+
+use std::marker::PhantomData;
+use std::str;
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{channel, Sender};
+use std::thread;
+use std::time::Duration;
+
+/// How long to wait before connecting again to the broker.
+const RECONNECT_DELAY: u64 = 5; // seconds
+
+/// How long to wait before requeueing an event that has failed,
+/// so that it is not delivered again straight away.
+const REQUEUE_DELAY: u64 = 1; // seconds
+
+/// An event, i.e.: `{"event": "updated", "updated_at": "2017-03-04T12:24:00Z", "document": {...}}`
+/// or `{"event": "deleted", "id": "42"}`.
+#[derive(Deserialize, Debug)]
+pub struct Message<R> {
+  pub event:      String, // "created", "updated" or "deleted"
+  pub id:         Option<String>,
+  pub updated_at: Option<String>, // RFC 3339
+  pub document:   Option<R>
+}
+
+/// What an event asks for.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command<R> {
+  Index(R, Option<u64>), // with the external version, if any
+  Delete(String, Option<u64>)
+}
+
+/// What has been done with an event.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Outcome {
+  Applied,
+  Rejected(String), // never to be delivered again
+  Failed(String)    // to be delivered again
+}
+
+/// Return the command of the event `body`, or why it cannot be applied.
+pub fn parse<R: Resource>(body: &[u8]) -> Result<Command<R>, String> {
+  let body    = try!(str::from_utf8(body).map_err(|e| e.to_string()));
+  let message = try!(serde_json::from_str::<Message<R>>(body).map_err(|e| e.to_string()));
+
+  let version = match message.updated_at {
+    Some(ref updated_at) => Some(try!(version(updated_at))),
+    None                 => None
+  };
+
+  match &*message.event {
+    "created" | "updated" => match message.document {
+      Some(document) => {
+        let errors = document.validate();
+        if errors.is_empty() {
+          Ok(Command::Index(document, version))
+        }
+        else {
+          Err(errors.into_iter().map(|error| error.message).collect::<Vec<String>>().join(" "))
+        }
+      },
+      None => Err(format!("The {} events require a document.", message.event))
+    },
+    "deleted" => match message.id.or(message.document.map(|document| document.id())) {
+      Some(id) => Ok(Command::Delete(id, version)),
+      None     => Err("The deleted events require an id.".to_owned())
+    },
+    event => Err(format!("Expected one of created, updated or deleted, got {:?}.", event))
+  }
+}
+
+/// An event pushed by the main application, i.e.: `{"event": "talent.updated",
+/// "updated_at": "2017-03-04T12:24:00Z", "data": {"id": 42, ...}}` or
+/// `{"event": "talent.deleted", "data": {"id": 42}}`.
+#[derive(Deserialize, Debug)]
+pub struct Webhook {
+  pub event:      String, // "<resource>.created", "<resource>.updated" or "<resource>.deleted"
+  pub updated_at: Option<String>, // RFC 3339
+  pub data:       JsonValue
+}
+
+/// Return the command of the event `body` pushed by the main application, or why it cannot be applied.
+pub fn parse_webhook<R: Resource>(body: &[u8]) -> Result<Command<R>, String> {
+  let body    = try!(str::from_utf8(body).map_err(|e| e.to_string()));
+  let webhook = try!(serde_json::from_str::<Webhook>(body).map_err(|e| e.to_string()));
+
+  let prefix  = format!("{}.", R::name());
+  let event   = if webhook.event.starts_with(&*prefix) { &webhook.event[prefix.len()..] } else { "" };
+  let version = match webhook.updated_at {
+    Some(ref updated_at) => Some(try!(version(updated_at))),
+    None                 => None
+  };
+
+  match event {
+    "created" | "updated" => {
+      let document = try!(serde_json::from_value::<R>(webhook.data).map_err(|e| e.to_string()));
+
+      let errors = document.validate();
+      if errors.is_empty() {
+        Ok(Command::Index(document, version))
+      }
+      else {
+        Err(errors.into_iter().map(|error| error.message).collect::<Vec<String>>().join(" "))
+      }
+    },
+    "deleted" => match webhook.data.find("id") {
+      Some(&JsonValue::String(ref id)) => Ok(Command::Delete(id.to_owned(), version)),
+      Some(id) if id.is_u64()          => Ok(Command::Delete(id.to_string(), version)),
+      _                                => Err("The deleted events require data.id.".to_owned())
+    },
+    _ => Err(format!("Expected one of {0}created, {0}updated or {0}deleted, got {1:?}.", prefix, webhook.event))
+  }
+}
+
+/// Return the external version of a document updated at `updated_at`,
+/// as milliseconds since the UNIX epoch.
+pub fn version(updated_at: &str) -> Result<u64, String> {
+  match DateTime::parse_from_rfc3339(updated_at) {
+    Ok(time) if time.timestamp() >= 0 => Ok(time.timestamp() as u64 * 1000 + (time.nanosecond() / 1_000_000) as u64),
+    _                                 => Err(format!("Expected updated_at as RFC 3339 time, got {:?}.", updated_at))
+  }
+}
+
+/// Apply `command` to `index`, recording the writes as done by `source`.
+pub fn apply<R: Resource>(source: &str, cluster: Arc<Cluster>, index: &str, command: Command<R>) -> Outcome {
+  match command {
+    Command::Index(document, version) => {
+      let before = audit::versions_before(&cluster.url(), index, &[document.to_owned()]);
+      METRICS.increment("searchspot_index_operations_total", &[("operation", "consume")]);
+      let results = METRICS.instrument(R::name(), index, "consume", || match version {
+        Some(version) => retry(|| R::index_versioned(&mut cluster.get(), index, vec![(document.to_owned(), version)])).map(|result| vec![result]),
+        None          => bulk::index_many(cluster.clone(), index, vec![document], 1)
+      });
+
+      let results = match results {
+        Ok(results) => results,
+        Err(err)    => return Outcome::Failed(err.to_string())
+      };
+
+      let items = results.into_iter().flat_map(|result| result.items.into_iter()).collect::<Vec<_>>();
+      audit_indexed::<R>(source, index, &items, &before);
+
+      // a conflict means that a later version has been indexed already
+      let refused = items.iter().find(|item| item.inner.status >= 300 && item.inner.status != 409);
+      match refused {
+        Some(item) if bulk::retryable(item.inner.status) => {
+          Outcome::Failed(format!("ElasticSearch cannot index `{}` for now ({}).", item.inner.id, item.inner.status))
+        },
+        Some(item) => Outcome::Rejected(format!("ElasticSearch has refused `{}` ({}).", item.inner.id, item.inner.status)),
+        None       => {
+          CACHE.invalidate(index);
+          WEBHOOKS.fire(Event::Indexed, index, items.into_iter().map(|item| item.inner.id).collect());
+          Outcome::Applied
+        }
+      }
+    },
+    Command::Delete(id, Some(version)) => {
+      let before = audit::version_before::<R>(&cluster.url(), index, &id);
+      METRICS.increment("searchspot_index_operations_total", &[("operation", "delete")]);
+      let result = match METRICS.instrument(R::name(), index, "delete", || retry(|| R::delete_versioned(&mut cluster.get(), &id, index, version))) {
+        Ok(result) => result,
+        Err(err)   => return Outcome::Failed(err.to_string())
+      };
+
+      // missing already, or updated after the deletion (a conflict): applied anyway
+      let statuses = result.items.iter().map(|item| item.inner.status).collect::<Vec<u64>>();
+      if let Some(&status) = statuses.iter().find(|&&status| status >= 300 && status != 404 && status != 409) {
+        return if bulk::retryable(status) {
+          Outcome::Failed(format!("ElasticSearch cannot delete `{}` for now ({}).", id, status))
+        }
+        else {
+          Outcome::Rejected(format!("ElasticSearch has refused to delete `{}` ({}).", id, status))
+        };
+      }
+
+      if statuses.iter().any(|&status| status < 300) {
+        CACHE.invalidate(index);
+        AUDIT.record_operation(OperationEntry::new(source, "delete", R::name(), index, Some(id.to_owned()), before, None));
+        WEBHOOKS.fire(Event::Deleted, index, vec![id]);
+      }
+
+      Outcome::Applied
+    },
+    Command::Delete(id, None) => {
+      let before = audit::version_before::<R>(&cluster.url(), index, &id);
+      METRICS.increment("searchspot_index_operations_total", &[("operation", "delete")]);
+      let result = match METRICS.instrument(R::name(), index, "delete", || retry(|| R::delete(&mut cluster.get(), &id, index))) {
+        Ok(result) => result,
+        Err(err)   => return Outcome::Failed(err.to_string())
+      };
+
+      // deleting twice is harmless, the event has been applied anyway
+      if result.found {
+        CACHE.invalidate(index);
+        AUDIT.record_operation(OperationEntry::new(source, "delete", R::name(), index, Some(id.to_owned()), before, None));
+        WEBHOOKS.fire(Event::Deleted, index, vec![id]);
+      }
+
+      Outcome::Applied
+    }
+  }
+}
+
+/// Apply the event `body` received from `source` (`amqp` or `kafka`)
+/// to `index`, logging and counting its outcome.
+pub fn handle<R: Resource>(source: &str, cluster: Arc<Cluster>, index: &str, body: &[u8]) -> Outcome {
+  let outcome = match parse::<R>(body) {
+    Ok(command) => apply::<R>(source, cluster, index, command),
+    Err(err)    => Outcome::Rejected(err)
+  };
+
+  report(source, &String::from_utf8_lossy(body), &outcome);
+  outcome
+}
+
+/// Log and count the `outcome` of `event`, waiting before it is delivered again if it has failed.
+fn report(source: &str, event: &str, outcome: &Outcome) {
+  match *outcome {
+    Outcome::Applied => {
+      METRICS.increment("searchspot_consumed_events_total", &[("source", source), ("outcome", "applied")]);
+    },
+    Outcome::Rejected(ref err) => {
+      warn!("The event {} has been rejected: {}", event, err);
+      METRICS.increment("searchspot_consumed_events_total", &[("source", source), ("outcome", "rejected")]);
+    },
+    Outcome::Failed(ref err) => {
+      error!("The event {} will be delivered again: {}", event, err);
+      METRICS.increment("searchspot_consumed_events_total", &[("source", source), ("outcome", "failed")]);
+      thread::sleep(Duration::from_secs(REQUEUE_DELAY));
+    }
+  }
+}
+
+/// Apply the commands pushed to it from a background thread, in order,
+/// applying again the ones that fail until ElasticSearch is back.
+/// The room they have been given in the `QUEUE`, if any, is held until they have been applied.
+pub struct Worker<R> {
+  sender: Mutex<Sender<(Arc<Cluster>, Command<R>, Option<Reservation<'static>>)>>
+}
+
+impl<R: Resource> Worker<R> {
+  /// Start applying the commands received from `source` to `index`.
+  pub fn start(source: &str, index: &str) -> Worker<R> {
+    let (sender, receiver) = channel::<(Arc<Cluster>, Command<R>, Option<Reservation<'static>>)>();
+    let source = source.to_owned();
+    let index  = index.to_owned();
+
+    thread::spawn(move || {
+      for (cluster, command, _reservation) in receiver {
+        loop {
+          let outcome = apply::<R>(&source, cluster.clone(), &index, command.clone());
+          report(&source, &format!("{:?}", command), &outcome);
+
+          if let Outcome::Failed(_) = outcome {
+            continue;
+          }
+          break;
+        }
+      }
+    });
+
+    Worker {
+      sender: Mutex::new(sender)
+    }
+  }
+
+  /// Queue `command`, to be applied through `cluster`, holding `reservation` until then.
+  pub fn push(&self, cluster: Arc<Cluster>, command: Command<R>, reservation: Option<Reservation<'static>>) {
+    let _ = self.sender.lock().unwrap().send((cluster, command, reservation));
+  }
+}
+
+/// Receive the events of the queue, applying them to the index of `config`.
+struct EventHandler<R> {
+  cluster:  Arc<Cluster>,
+  index:    String,
+  resource: PhantomData<R>
+}
+
+impl<R: Resource> amqp::Consumer for EventHandler<R> {
+  fn handle_delivery(&mut self, channel: &mut Channel, deliver: Deliver, _: BasicProperties, body: Vec<u8>) {
+    let result = match handle::<R>("amqp", self.cluster.clone(), &self.index, &body) {
+      Outcome::Applied     => channel.basic_ack(deliver.delivery_tag, false),
+      Outcome::Rejected(_) => channel.basic_reject(deliver.delivery_tag, false),
+      Outcome::Failed(_)   => channel.basic_nack(deliver.delivery_tag, false, true)
+    };
+
+    if let Err(err) = result {
+      error!("The event has not been acknowledged: {:?}", err);
+    }
+  }
+}
+
+/// Consume the events of `consumer.queue` (or of `kafka.topic` when `consumer.source`
+/// is `kafka`) until the process is stopped, connecting again to the broker
+/// whenever the connection is lost.
+pub fn consume<R: Resource>(config: Config) {
+  start_logging(&config).unwrap();
+  settings::apply(&config);
+  WEBHOOKS.start(&config.webhooks);
+
+  info!("Searchspot v{}", env!("CARGO_PKG_VERSION"));
+  for line in config.to_string().lines() {
+    info!("{}", line);
+  }
+
+  cluster::bootstrap(&config);
+
+  // the searches served by the instances sharing the cache are invalidated by the writes
+  CACHE.configure(&config.cache);
+  if let Some(ref redis_url) = config.cache.redis_url {
+    match RedisStore::open(redis_url, config.cache.redis_timeout()) {
+      Ok(store) => CACHE.connect(store),
+      Err(err)  => error!("The cache invalidations cannot be published: {}", err)
+    }
+  }
+
+  let cluster = Arc::new(Cluster::new(&config.es.urls(), config.es.connections));
+  es::configure_compatibility(&config.es.compatibility, &cluster.url());
+  AUDIT.start(&config.audit, cluster.clone());
+
+  loop {
+    if config.consumer.source == "kafka" {
+      if let Err(err) = kafka::run::<R>(&config, cluster.clone()) {
+        error!("The events of {} cannot be consumed: {}", config.kafka.topic, err);
+      }
+    }
+    else if let Err(err) = run::<R>(&config, cluster.clone()) {
+      error!("The events of {} cannot be consumed: {:?}", config.consumer.queue, err);
+    }
+
+    thread::sleep(Duration::from_secs(RECONNECT_DELAY));
+  }
+}
+
+/// Consume the events of `consumer.queue` as long as the connection is open.
+fn run<R: Resource>(config: &Config, cluster: Arc<Cluster>) -> Result<(), AMQPError> {
+  let ref consumer = config.consumer;
+
+  let mut session = try!(Session::open_url(&*consumer.url));
+  let mut channel = try!(session.open_channel(1));
+
+  // durable, so that the events survive the restarts of the broker
+  try!(channel.queue_declare(consumer.queue.to_owned(), false, true, false, false, false, Table::new()));
+  try!(channel.basic_prefetch(consumer.prefetch));
+
+  let handler = EventHandler::<R> {
+    cluster:  cluster,
+    index:    config.es.index.to_owned(),
+    resource: PhantomData
+  };
+  try!(channel.basic_consume(handler, consumer.queue.to_owned(), "searchspot".to_owned(), false, false, false, false, Table::new()));
+
+  info!("Consuming the events of {}.", consumer.queue);
+  channel.start_consuming();
+
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use consumer::{parse, parse_webhook, version, Command};
+  use resources::Talent;
+
+  #[test]
+  fn test_version() {
+    assert_eq!(version("2017-03-04T12:24:00Z"),          Ok(1488630240000));
+    assert_eq!(version("2017-03-04T13:24:00.123+01:00"), Ok(1488630240123));
+    assert!(version("yesterday").is_err());
+  }
+
+  #[test]
+  fn test_parse() {
+    match parse::<Talent>(b"{\"event\": \"deleted\", \"id\": \"42\"}") {
+      Ok(Command::Delete(id, None)) => assert_eq!(id, "42"),
+      other                         => panic!("Expected a deletion, got {:?}", other)
+    }
+
+    match parse::<Talent>(b"{\"event\": \"deleted\", \"id\": \"42\", \"updated_at\": \"2017-03-04T12:24:00Z\"}") {
+      Ok(Command::Delete(id, Some(version))) => assert_eq!((&*id, version), ("42", 1488630240000)),
+      other                                  => panic!("Expected a versioned deletion, got {:?}", other)
+    }
+
+    assert_eq!(parse::<Talent>(b"{\"event\": \"deleted\"}").unwrap_err(), "The deleted events require an id.");
+    assert_eq!(parse::<Talent>(b"{\"event\": \"updated\"}").unwrap_err(), "The updated events require a document.");
+    assert_eq!(parse::<Talent>(b"{\"event\": \"archived\", \"id\": \"42\"}").unwrap_err(),
+               "Expected one of created, updated or deleted, got \"archived\".");
+    assert!(parse::<Talent>(b"not json").is_err());
+    assert!(parse::<Talent>(b"{\"event\": \"updated\", \"updated_at\": \"yesterday\", \"document\": {\"id\": 1}}").is_err());
+  }
+
+  #[test]
+  fn test_parse_webhook() {
+    match parse_webhook::<Talent>(b"{\"event\": \"talent.deleted\", \"data\": {\"id\": 42}}") {
+      Ok(Command::Delete(id, None)) => assert_eq!(id, "42"),
+      other                         => panic!("Expected a deletion, got {:?}", other)
+    }
+
+    assert_eq!(parse_webhook::<Talent>(b"{\"event\": \"talent.deleted\", \"data\": {}}").unwrap_err(),
+               "The deleted events require data.id.");
+    assert_eq!(parse_webhook::<Talent>(b"{\"event\": \"company.deleted\", \"data\": {\"id\": 42}}").unwrap_err(),
+               "Expected one of talent.created, talent.updated or talent.deleted, got \"company.deleted\".");
+    assert!(parse_webhook::<Talent>(b"{\"event\": \"talent.updated\", \"data\": {\"id\": 42}}").is_err());
+  }
+}
@@ -0,0 +1,115 @@
+//! The dump of an index to a gzipped NDJSON file (one document per line) and
+//! its restore into another index, run by `searchspot-admin dump <file>` and
+//! `searchspot-admin restore <file>`, so that the documents can be moved
+//! between environments without the snapshots of ElasticSearch.
+
+use serde_json;
+
+use flate2::Compression;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+
+use config::Config;
+use resource::Resource;
+use cluster::Cluster;
+use scroll::Scroll;
+use error::Error;
+use import;
+use import::Report;
+use admin;
+use es;
+
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
+use std::sync::Arc;
+
+/// Write every document of `index` on the cluster listening on `url` to `out`
+/// as NDJSON, returning `out` and how many documents have been written.
+pub fn write<R: Resource, W: Write>(url: &str, index: &str, mut out: W) -> Result<(W, u64), String> {
+  let mut count = 0;
+
+  for document in try!(Scroll::<R>::new(url, index).map_err(|err| err.to_string())) {
+    let document = try!(document.map_err(|err| err.to_string()));
+    try!(writeln!(out, "{}", serde_json::to_string(&document).unwrap()).map_err(|err| err.to_string()));
+    count += 1;
+  }
+
+  Ok((out, count))
+}
+
+/// Write every document of `index` to the file at `path`, gzipped,
+/// returning how many documents have been written.
+pub fn dump<R: Resource>(url: &str, index: &str, path: &str) -> Result<u64, String> {
+  let file = try!(File::create(path).map_err(|err| format!("{}: {}", path, err)));
+
+  let (encoder, count) = try!(write::<R, _>(url, index, GzEncoder::new(file, Compression::Default)));
+  try!(encoder.finish().map_err(|err| format!("{}: {}", path, err)));
+
+  Ok(count)
+}
+
+/// Open the NDJSON file at `path`, gunzipping it when named `*.gz`.
+pub fn open(path: &str) -> Result<Box<BufRead>, String> {
+  let file = try!(File::open(path).map_err(|err| format!("{}: {}", path, err)));
+
+  if path.ends_with(".gz") {
+    let decoder = try!(GzDecoder::new(file).map_err(|err| format!("{}: {}", path, err)));
+    Ok(Box::new(BufReader::new(decoder)))
+  }
+  else {
+    Ok(Box::new(BufReader::new(file)))
+  }
+}
+
+/// Index the documents of the dump at `path` into `index`, writing the rejected ones
+/// to `errors_path` (see `import`). The index is created with the mapping of `R`
+/// if it does not exist yet.
+pub fn restore<R: Resource>(config: &Config, path: &str, index: &str, errors_path: &str) -> Result<Report, String> {
+  let cluster = Arc::new(Cluster::new(&config.es.urls(), config.es.connections));
+  let url     = cluster.url();
+
+  match es::get(&url, index).map_err(Error::from) {
+    Err(Error::IndexNotFound(_)) => {
+      try!(admin::reset::<R>(&mut cluster.get(), &url, index, &config.resource(R::name())).map_err(|err| err.to_string()));
+      info!("{} has been created with the mapping of `{}`.", index, R::name());
+    },
+    Err(err) => return Err(err.to_string()),
+    Ok(_)    => ()
+  }
+
+  let input      = try!(open(path));
+  let mut errors = try!(File::create(errors_path).map_err(|err| format!("{}: {}", errors_path, err)));
+
+  import::index_lines::<R, _, _>(cluster, index, input, &mut errors, config.es.max_concurrent_bulks)
+}
+
+#[cfg(test)]
+mod tests {
+  use dump::open;
+
+  use flate2::Compression;
+  use flate2::write::GzEncoder;
+
+  use std::env;
+  use std::fs::File;
+  use std::io::{BufRead, Write};
+
+  #[test]
+  fn test_open() {
+    let plain   = env::temp_dir().join("searchspot_test_open.ndjson");
+    let gzipped = env::temp_dir().join("searchspot_test_open.ndjson.gz");
+
+    File::create(&plain).unwrap().write_all(b"{\"id\": 1}\n{\"id\": 2}\n").unwrap();
+
+    let mut encoder = GzEncoder::new(File::create(&gzipped).unwrap(), Compression::Default);
+    encoder.write_all(b"{\"id\": 1}\n{\"id\": 2}\n").unwrap();
+    encoder.finish().unwrap();
+
+    for path in vec![plain, gzipped] {
+      let lines = open(path.to_str().unwrap()).unwrap().lines().map(|line| line.unwrap()).collect::<Vec<String>>();
+      assert_eq!(lines, vec!["{\"id\": 1}", "{\"id\": 2}"]);
+    }
+
+    assert!(open("missing.ndjson.gz").unwrap_err().starts_with("missing.ndjson.gz: "));
+  }
+}
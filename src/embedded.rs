@@ -0,0 +1,109 @@
+//! A throwaway ElasticSearch run through Docker, for the local development
+//! and the tests (`cargo test --features embedded-es`), so that no cluster
+//! has to be started by hand.
+//!
+//! The container is pinned to the version Searchspot is tested against
+//! (`SEARCHSPOT_EMBEDDED_ES_IMAGE` can replace it) and removed once the
+//! process which has started it exits, even when it panics or is killed.
+
+use hyper::Url;
+
+use es;
+
+use std::env;
+use std::process::{self, Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// The image run when `SEARCHSPOT_EMBEDDED_ES_IMAGE` is not given.
+pub const IMAGE: &'static str = "elasticsearch:2.4.6";
+
+/// How long the node is given to become available.
+const STARTUP_TIMEOUT: u64 = 120;
+
+/// An ElasticSearch node running inside a Docker container.
+pub struct EmbeddedEs {
+  pub container: String,
+  pub url:       String
+}
+
+impl EmbeddedEs {
+  /// Run a node listening on the port of `url` and wait for it to be available,
+  /// reusing the one already listening there (i.e.: started by a previous call).
+  pub fn start(url: &str) -> Result<EmbeddedEs, String> {
+    let port = try!(Url::parse(url).map_err(|err| err.to_string()))
+                 .port_or_known_default()
+                 .unwrap_or(9200);
+    let container = format!("searchspot-es-{}", port);
+
+    if es::get(url, "").is_ok() {
+      return Ok(EmbeddedEs { container: container, url: url.to_owned() });
+    }
+
+    let image = env::var("SEARCHSPOT_EMBEDDED_ES_IMAGE").unwrap_or(IMAGE.to_owned());
+    info!("Running {} as {} on port {}.", image, container, port);
+
+    let run = Command::new("docker")
+                      .args(&["run", "--detach", "--rm", "--name", &container,
+                              "--publish", &format!("{}:9200", port),
+                              "--env", "discovery.type=single-node", &image])
+                      .stdout(Stdio::null())
+                      .status();
+
+    match run {
+      Ok(status) if status.success() => (),
+      Ok(status) => return Err(format!("docker run has exited with {}.", status)),
+      Err(err)   => return Err(format!("docker cannot be run: {}", err))
+    }
+
+    try!(remove_on_exit(&container));
+
+    let node = EmbeddedEs { container: container, url: url.to_owned() };
+    try!(node.wait(Duration::from_secs(STARTUP_TIMEOUT)));
+
+    Ok(node)
+  }
+
+  /// Wait up to `timeout` for the node to be at least yellow.
+  pub fn wait(&self, timeout: Duration) -> Result<(), String> {
+    let started = Instant::now();
+
+    while started.elapsed() < timeout {
+      let status = es::get(&self.url, "_cluster/health").ok().and_then(|health| {
+        health.find("status").and_then(|s| s.as_str()).map(|s| s.to_owned())
+      });
+
+      match status.as_ref().map(String::as_str) {
+        Some("green") | Some("yellow") => return Ok(()),
+        _                              => thread::sleep(Duration::from_secs(1))
+      }
+    }
+
+    Err(format!("{} has not become available within {} seconds.", self.container, timeout.as_secs()))
+  }
+
+  /// Remove the container straight away.
+  pub fn stop(&self) -> Result<(), String> {
+    Command::new("docker")
+            .args(&["rm", "--force", &self.container])
+            .stdout(Stdio::null())
+            .status()
+            .map(|_| ())
+            .map_err(|err| err.to_string())
+  }
+}
+
+/// Remove `container` as soon as the current process has exited: the tests keep
+/// the node in a static, which is never dropped, hence a watchdog is left behind.
+fn remove_on_exit(container: &str) -> Result<(), String> {
+  let watchdog = format!("while kill -0 {} 2> /dev/null; do sleep 1; done; docker rm --force {} > /dev/null",
+                         process::id(), container);
+
+  Command::new("sh")
+          .args(&["-c", &watchdog])
+          .stdin(Stdio::null())
+          .stdout(Stdio::null())
+          .spawn()
+          .map(|_| ())
+          .map_err(|err| format!("The removal of {} cannot be scheduled: {}", container, err))
+}
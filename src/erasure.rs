@@ -0,0 +1,247 @@
+//! The erasure of a talent (the right to erasure of the GDPR), run by
+//! `DELETE /gdpr/talents/:id`: the talent is removed from every store that
+//! Searchspot keeps about it (the live index, the pending writes, the cached
+//! searches, the audit and analytics indexes, the batches and the backups),
+//! and each of them is reported as a step, with how many records have been
+//! deleted from it or why it could not be purged. The erasure is idempotent,
+//! so that it can be sent again until every step has succeeded.
+
+use serde_json;
+use serde_json::Value as JsonValue;
+
+use es;
+
+use std::collections::BTreeMap;
+
+/// How many documents are deleted by each bulk request.
+const PAGE_SIZE: u64 = 500;
+
+/// How long the scroll over the documents to delete is kept open between its pages.
+const KEEP_ALIVE: &'static str = "1m";
+
+/// The purge of one of the stores, i.e.:
+/// `{"store": "audit", "target": "searchspot_audit", "deleted": 3}`.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Step {
+  pub store:   &'static str, // "index", "queue", "cache", "audit", "analytics", "batches" or "backups"
+  pub target:  String,
+  pub deleted: u64,
+  #[serde(skip_serializing_if="Option::is_none")]
+  pub error:   Option<String>,
+  #[serde(skip_serializing_if="is_false")]
+  pub queued:  bool // purged in background, `deleted` being unknown
+}
+
+fn is_false(value: &bool) -> bool {
+  !*value
+}
+
+impl Step {
+  pub fn new(store: &'static str, target: &str, result: Result<u64, String>) -> Step {
+    let (deleted, error) = match result {
+      Ok(deleted) => (deleted, None),
+      Err(err)    => (0, Some(err))
+    };
+
+    Step {
+      store:   store,
+      target:  target.to_owned(),
+      deleted: deleted,
+      error:   error,
+      queued:  false
+    }
+  }
+
+  /// Return the step of a store that is purged in background.
+  pub fn queued(store: &'static str, target: &str) -> Step {
+    Step { queued: true, ..Step::new(store, target, Ok(0)) }
+  }
+}
+
+/// The report of the erasure of the talent `id`, `complete` if every step has succeeded.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct ErasureReport {
+  pub id:       String,
+  pub complete: bool,
+  pub steps:    Vec<Step>
+}
+
+impl ErasureReport {
+  pub fn new(id: &str, steps: Vec<Step>) -> ErasureReport {
+    ErasureReport {
+      id:       id.to_owned(),
+      complete: steps.iter().all(|step| step.error.is_none()),
+      steps:    steps
+    }
+  }
+}
+
+/// Return the query of the document `id`.
+pub fn document_query(id: &str) -> JsonValue {
+  serde_json::to_value(&btreemap! {
+    "query" => btreemap! { "ids" => btreemap! { "values" => vec![id] } }
+  })
+}
+
+/// Return the query of the audit entries about the talent `id`: the writes of
+/// the document and the searches giving it among their params.
+pub fn audit_query(resource: &str, id: &str) -> JsonValue {
+  let write = btreemap! {
+    "bool" => btreemap! {
+      "filter" => vec![btreemap! { "term" => btreemap! { "id" => id } },
+                       btreemap! { "term" => btreemap! { "resource" => resource } }]
+    }
+  };
+
+  let mut should = vec![serde_json::to_value(&write)];
+  for param in &["ids", "pinned_ids", "presented_talents", "contacted_talents"] {
+    should.push(serde_json::to_value(&btreemap! { "term" => btreemap! { format!("params.{}", param) => id } }));
+  }
+
+  serde_json::to_value(&btreemap! {
+    "query" => btreemap! { "bool" => serde_json::to_value(&btreemap! {
+      "should"               => serde_json::to_value(&should),
+      "minimum_should_match" => serde_json::to_value(&1)
+    }) }
+  })
+}
+
+/// Return the query of the feedback in the analytics about the talent `id`.
+pub fn analytics_query(id: &str) -> JsonValue {
+  let should = vec![btreemap! { "term" => btreemap! { "clicked" => id } },
+                    btreemap! { "term" => btreemap! { "contacted" => id } }];
+
+  serde_json::to_value(&btreemap! {
+    "query" => btreemap! { "bool" => serde_json::to_value(&btreemap! {
+      "should"               => serde_json::to_value(&should),
+      "minimum_should_match" => serde_json::to_value(&1)
+    }) }
+  })
+}
+
+/// Delete the documents of `indexes` (comma separated, skipped when missing)
+/// matching `query`, returning how many they were. They are scrolled and deleted
+/// through `_bulk` a page at a time, since `_delete_by_query` is missing before
+/// ElasticSearch 5.
+pub fn delete_by_query(url: &str, indexes: &str, query: &JsonValue) -> Result<u64, String> {
+  let mut search = query.to_owned();
+  if let JsonValue::Object(ref mut search) = search {
+    search.insert("size".to_owned(),    serde_json::to_value(&PAGE_SIZE));
+    search.insert("_source".to_owned(), JsonValue::Bool(false));
+    search.insert("sort".to_owned(),    serde_json::to_value(&vec!["_doc"]));
+  }
+
+  let mut page    = try!(es::open_scroll(url, indexes, &search, KEEP_ALIVE).map_err(|err| err.to_string()));
+  let mut deleted = 0;
+
+  loop {
+    let hits = es::hits(&page);
+    if hits.is_empty() {
+      break;
+    }
+
+    let next = delete_hits(url, &hits).and_then(|count| {
+      deleted += count;
+      es::scroll(url, &page, KEEP_ALIVE).map_err(|err| err.to_string())
+    });
+
+    page = match next {
+      Ok(next) => next,
+      Err(err) => {
+        let _ = es::clear_scroll(url, &page);
+        return Err(err);
+      }
+    };
+  }
+
+  let _ = es::clear_scroll(url, &page);
+  Ok(deleted)
+}
+
+/// Delete the documents of `hits` through `_bulk`, returning how many were found.
+fn delete_hits(url: &str, hits: &[JsonValue]) -> Result<u64, String> {
+  let body     = bulk_delete(hits, es::routing_field(es::distribution()));
+  let response = try!(es::post(url, "_bulk?refresh=true", &body).map_err(|err| err.to_string()));
+  let items    = response.find("items").and_then(|items| items.as_array()).cloned().unwrap_or(vec![]);
+
+  let mut deleted = 0;
+  for item in items {
+    // the documents deleted meanwhile are missing already
+    match item.pointer("/delete/status").and_then(|status| status.as_u64()) {
+      Some(status) if status < 300 => deleted += 1,
+      Some(404)                    => (),
+      _                            => return Err(format!("A document cannot be deleted: {}", item))
+    }
+  }
+
+  Ok(deleted)
+}
+
+/// Return the body of the `_bulk` request deleting the documents of `hits`,
+/// on the shards they have been routed to, whose keys are given as `routing`.
+fn bulk_delete(hits: &[JsonValue], routing: &str) -> String {
+  let mut body = String::new();
+
+  for hit in hits {
+    let mut action = BTreeMap::new();
+    for field in &["_index", "_type", "_id"] {
+      if let Some(value) = hit.find(field) {
+        action.insert(field.to_string(), value.to_owned());
+      }
+    }
+
+    // returned among the `fields` before ElasticSearch 5
+    if let Some(key) = hit.find("_routing").or(hit.pointer("/fields/_routing")) {
+      action.insert(routing.to_owned(), key.to_owned());
+    }
+
+    body.push_str(&serde_json::to_string(&btreemap! { "delete" => action }).unwrap());
+    body.push('\n');
+  }
+
+  body
+}
+
+#[cfg(test)]
+mod tests {
+  use erasure::{Step, ErasureReport, document_query, audit_query, analytics_query, bulk_delete};
+
+  use serde_json;
+
+  #[test]
+  fn test_report() {
+    let report = ErasureReport::new("42", vec![Step::new("index", "talents", Ok(1)),
+                                               Step::new("backups", "searchspot/talents/", Err("Access Denied".to_owned()))]);
+    assert!(!report.complete);
+    assert_eq!(serde_json::to_string(&report.steps[0]).unwrap(), "{\"store\":\"index\",\"target\":\"talents\",\"deleted\":1}");
+
+    assert!(ErasureReport::new("42", vec![Step::new("index", "talents", Ok(0))]).complete);
+
+    let queued = Step::queued("backups", "s3://searchspot/talents/");
+    assert_eq!(serde_json::to_string(&queued).unwrap(), "{\"store\":\"backups\",\"target\":\"s3://searchspot/talents/\",\"deleted\":0,\"queued\":true}");
+    assert!(ErasureReport::new("42", vec![queued]).complete);
+  }
+
+  #[test]
+  fn test_bulk_delete() {
+    let hits: Vec<serde_json::Value> = serde_json::from_str("[{\"_index\": \"talents\", \"_type\": \"talent\", \"_id\": \"42\", \"_score\": 1.0}, \
+                                                              {\"_index\": \"talents\", \"_type\": \"talent\", \"_id\": \"43\", \"fields\": {\"_routing\": \"2017-03-06\"}}]").unwrap();
+
+    assert_eq!(bulk_delete(&hits, "_routing"),
+               "{\"delete\":{\"_id\":\"42\",\"_index\":\"talents\",\"_type\":\"talent\"}}\n\
+                {\"delete\":{\"_id\":\"43\",\"_index\":\"talents\",\"_routing\":\"2017-03-06\",\"_type\":\"talent\"}}\n");
+  }
+
+  #[test]
+  fn test_queries() {
+    assert_eq!(document_query("42").pointer("/query/ids/values/0"), Some(&serde_json::to_value(&"42")));
+
+    let audit = audit_query("talents", "42");
+    assert_eq!(audit.pointer("/query/bool/should/0/bool/filter/1/term/resource"), Some(&serde_json::to_value(&"talents")));
+    assert_eq!(audit.pointer("/query/bool/should/2/term/params.pinned_ids"), Some(&serde_json::to_value(&"42")));
+
+    let analytics = analytics_query("42");
+    assert_eq!(analytics.pointer("/query/bool/should/1/term/contacted"), Some(&serde_json::to_value(&"42")));
+    assert_eq!(analytics.pointer("/query/bool/minimum_should_match"), Some(&serde_json::to_value(&1)));
+  }
+}
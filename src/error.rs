@@ -0,0 +1,281 @@
+use serde::ser::{Serialize, Serializer};
+use serde_json;
+use serde_json::Value as JsonValue;
+
+use iron::prelude::*;
+use iron::status;
+use iron::mime::Mime;
+
+use rs_es::error::EsError;
+
+use std::error::Error as StdError;
+use std::fmt;
+
+/// The errors returned by the resources, the configuration and the handlers.
+#[derive(Debug)]
+pub enum Error {
+  /// ElasticSearch could not be reached, or the connection dropped
+  EsTransport(EsError),
+  /// ElasticSearch has been reached but has not fulfilled the request
+  EsQuery(EsError),
+  /// The request is not valid (i.e.: a malformed param)
+  Validation(String),
+  /// The configuration file cannot be read or is not valid
+  Config(String),
+  /// The requested document does not exist
+  NotFound(String),
+  /// One of the searched indexes does not exist
+  IndexNotFound(String),
+  /// The operation has not been completed in time
+  Timeout(String),
+  /// The source of truth of the resources cannot be read (see `source`)
+  Source(String)
+}
+
+impl Error {
+  /// Return the code returned over HTTP for the error.
+  pub fn code(&self) -> ErrorCode {
+    match *self {
+      Error::EsTransport(_) | Error::EsQuery(_) => ErrorCode::EsUnavailable,
+      Error::Validation(_)                      => ErrorCode::ValidationError,
+      Error::Config(_)                          => ErrorCode::Unavailable,
+      Error::NotFound(_)                        => ErrorCode::NotFound,
+      Error::IndexNotFound(_)                   => ErrorCode::NotFound,
+      Error::Timeout(_)                         => ErrorCode::Timeout,
+      Error::Source(_)                          => ErrorCode::Unavailable
+    }
+  }
+
+  /// Return whether the error has been returned by ElasticSearch.
+  pub fn is_es(&self) -> bool {
+    match *self {
+      Error::EsTransport(_) | Error::EsQuery(_) => true,
+      _                                         => false
+    }
+  }
+
+  /// Return whether the error is not caused by the request, hence it is
+  /// reported to the monitor (i.e. ElasticSearch failing, or returning
+  /// something that cannot be deserialized).
+  pub fn is_unexpected(&self) -> bool {
+    match *self {
+      Error::EsTransport(_) | Error::EsQuery(_) | Error::Config(_) | Error::Source(_) => true,
+      _                                                                               => false
+    }
+  }
+
+  /// Return the error response matching the code of the error.
+  pub fn into_response(self) -> Response {
+    ErrorResponse::new(self.code(), self.to_string(), None).into_response()
+  }
+}
+
+impl fmt::Display for Error {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match *self {
+      Error::EsTransport(ref err)     => write!(f, "Cannot reach ElasticSearch: {}", err),
+      Error::EsQuery(ref err)         => write!(f, "ElasticSearch has returned an error: {}", err),
+      Error::IndexNotFound(ref index) => write!(f, "The index `{}` does not exist.", index),
+      Error::Validation(ref message) |
+      Error::Config(ref message)     |
+      Error::NotFound(ref message)   |
+      Error::Timeout(ref message)    => write!(f, "{}", message),
+      Error::Source(ref message)     => write!(f, "Cannot read the source of truth: {}", message)
+    }
+  }
+}
+
+impl StdError for Error {
+  fn description(&self) -> &str {
+    match *self {
+      Error::EsTransport(_)   => "ElasticSearch cannot be reached",
+      Error::EsQuery(_)       => "ElasticSearch has returned an error",
+      Error::Validation(_)    => "invalid request",
+      Error::Config(_)        => "invalid configuration",
+      Error::NotFound(_)      => "not found",
+      Error::IndexNotFound(_) => "index not found",
+      Error::Timeout(_)       => "timed out",
+      Error::Source(_)        => "source of truth unavailable"
+    }
+  }
+
+  fn cause(&self) -> Option<&StdError> {
+    match *self {
+      Error::EsTransport(ref err) | Error::EsQuery(ref err) => Some(err),
+      _                                                     => None
+    }
+  }
+}
+
+impl From<EsError> for Error {
+  fn from(err: EsError) -> Error {
+    if let Some(index) = missing_index(&err) {
+      return Error::IndexNotFound(index);
+    }
+
+    match err {
+      EsError::HttpError(_) | EsError::IoError(_) => Error::EsTransport(err),
+      _                                           => Error::EsQuery(err)
+    }
+  }
+}
+
+/// Return the index reported as missing by `err`, either sent as response to a search
+/// (i.e.: `404 Not Found - {"error": {"type": "index_not_found_exception", "index": "talents"}}`)
+/// or as error of one of the searches of `_msearch` (the `error` object alone).
+fn missing_index(err: &EsError) -> Option<String> {
+  let message = match *err {
+    EsError::EsServerError(ref message) => message,
+    _                                   => return None
+  };
+
+  let body = if message.starts_with('{') { &message[..] } else { message.splitn(2, " - ").nth(1).unwrap_or("") };
+  let body = match serde_json::from_str::<JsonValue>(body) {
+    Ok(body) => body,
+    Err(_)   => return None
+  };
+
+  let error = body.find("error").unwrap_or(&body);
+  if error.find("type").and_then(|kind| kind.as_str()) != Some("index_not_found_exception") {
+    return None;
+  }
+
+  Some(error.find("index").and_then(|index| index.as_str()).unwrap_or("").to_owned())
+}
+
+/// The stable codes that identify the errors returned over HTTP.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ErrorCode {
+  ValidationError,
+  InvalidParams,
+  EsUnavailable,
+  NotFound,
+  Unauthorized,
+  Forbidden,
+  Timeout,
+  Unavailable,
+  TooManyRequests,
+  Conflict
+}
+
+impl ErrorCode {
+  pub fn as_str(&self) -> &'static str {
+    match *self {
+      ErrorCode::ValidationError => "validation_error",
+      ErrorCode::InvalidParams   => "invalid_params",
+      ErrorCode::EsUnavailable   => "es_unavailable",
+      ErrorCode::NotFound        => "not_found",
+      ErrorCode::Unauthorized    => "unauthorized",
+      ErrorCode::Forbidden       => "forbidden",
+      ErrorCode::Timeout         => "timeout",
+      ErrorCode::Unavailable     => "unavailable",
+      ErrorCode::TooManyRequests => "too_many_requests",
+      ErrorCode::Conflict        => "conflict"
+    }
+  }
+
+  /// Return the HTTP status associated to the code.
+  pub fn status(&self) -> status::Status {
+    match *self {
+      ErrorCode::ValidationError => status::UnprocessableEntity,
+      ErrorCode::InvalidParams   => status::BadRequest,
+      ErrorCode::EsUnavailable   => status::BadGateway,
+      ErrorCode::NotFound        => status::NotFound,
+      ErrorCode::Unauthorized    => status::Unauthorized,
+      ErrorCode::Forbidden       => status::Forbidden,
+      ErrorCode::Timeout         => status::GatewayTimeout,
+      ErrorCode::Unavailable     => status::ServiceUnavailable,
+      ErrorCode::TooManyRequests => status::TooManyRequests,
+      ErrorCode::Conflict        => status::Conflict
+    }
+  }
+}
+
+impl Serialize for ErrorCode {
+  fn serialize<S: Serializer>(&self, serializer: &mut S) -> Result<(), S::Error> {
+    serializer.serialize_str(self.as_str())
+  }
+}
+
+/// The envelope used by every handler to return an error,
+/// i.e.: `{"code": "not_found", "message": "...", "details": null}`.
+#[derive(Serialize, Debug)]
+pub struct ErrorResponse {
+  pub code:    ErrorCode,
+  pub message: String,
+  pub details: Option<JsonValue>
+}
+
+impl ErrorResponse {
+  pub fn new<S: Into<String>>(code: ErrorCode, message: S, details: Option<JsonValue>) -> ErrorResponse {
+    ErrorResponse {
+      code:    code,
+      message: message.into(),
+      details: details
+    }
+  }
+
+  /// Return a JSON response whose status depends on the code.
+  pub fn into_response(self) -> Response {
+    let content_type = "application/json".parse::<Mime>().unwrap();
+    Response::with(
+      (content_type, self.code.status(), serde_json::to_string(&self).unwrap())
+    )
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use error::*;
+  use serde_json;
+
+  use rs_es::error::EsError;
+
+  use std::error::Error as StdError;
+  use std::io;
+
+  #[test]
+  fn test_serialize() {
+    let error = ErrorResponse::new(ErrorCode::NotFound, "Talent 4 not found.", None);
+    assert_eq!(serde_json::to_string(&error).unwrap(),
+               "{\"code\":\"not_found\",\"message\":\"Talent 4 not found.\",\"details\":null}");
+
+    let error = ErrorResponse::new(ErrorCode::ValidationError, "Invalid field.",
+                                   Some(serde_json::to_value(&vec!["weight"])));
+    assert_eq!(serde_json::to_string(&error).unwrap(),
+               "{\"code\":\"validation_error\",\"message\":\"Invalid field.\",\"details\":[\"weight\"]}");
+  }
+
+  #[test]
+  fn test_error() {
+    let error = Error::from(EsError::IoError(io::Error::new(io::ErrorKind::ConnectionRefused, "refused")));
+    match error {
+      Error::EsTransport(_) => (),
+      _                     => panic!("expected a transport error, got {:?}", error)
+    }
+    assert_eq!(error.code(), ErrorCode::EsUnavailable);
+    assert!(error.cause().is_some());
+
+    let error = Error::from(EsError::EsServerError("400 Bad Request - {}".to_owned()));
+    match error {
+      Error::EsQuery(_) => (),
+      _                 => panic!("expected a query error, got {:?}", error)
+    }
+
+    let error = Error::from(EsError::EsServerError("404 Not Found - {\"error\": {\"type\": \"index_not_found_exception\", \"index\": \"archived\"}, \"status\": 404}".to_owned()));
+    assert_eq!(error.code(), ErrorCode::NotFound);
+    assert_eq!(error.to_string(), "The index `archived` does not exist.");
+
+    let error = Error::from(EsError::EsServerError("{\"type\": \"index_not_found_exception\", \"index\": \"archived\"}".to_owned()));
+    assert_eq!(error.code(), ErrorCode::NotFound);
+
+    let error = Error::Validation("Invalid epoch.".to_owned());
+    assert_eq!(error.code(), ErrorCode::ValidationError);
+    assert_eq!(error.to_string(), "Invalid epoch.");
+    assert!(error.cause().is_none());
+    assert!(!error.is_es());
+    assert!(!error.is_unexpected());
+
+    assert!(Error::from(EsError::EsError("invalid type: string".to_owned())).is_unexpected());
+  }
+}
@@ -0,0 +1,808 @@
+//! A thin client for the ElasticSearch APIs that are not covered by `rs-es`.
+
+use serde_json;
+use serde_json::Value as JsonValue;
+
+use hyper;
+use hyper::Client as HttpClient;
+use hyper::client::pool::{Pool, Config as PoolConfig};
+use hyper::Url;
+use hyper::method::Method;
+use hyper::net::{HttpsConnector, HttpConnector, HttpStream, NetworkConnector};
+use hyper::header::{Headers, Authorization, Basic, ContentType};
+use hyper_openssl::OpensslClient;
+
+use openssl::ssl::{SslConnectorBuilder, SslMethod, SSL_VERIFY_NONE};
+
+use rs_es::Client;
+use rs_es::error::EsError;
+use rs_es::operations::mapping::Mapping;
+
+use request_id;
+use retry::retry;
+use tracing::{Span, TRACEPARENT_HEADER};
+
+use std::collections::BTreeMap;
+use std::env;
+use std::fmt;
+use std::io;
+use std::io::Read;
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+/// How the certificates of the cluster are verified.
+#[derive(Debug, Clone, Default)]
+struct Tls {
+  ca_cert:  Option<String>,
+  insecure: bool
+}
+
+/// How long the requests to the cluster can take.
+#[derive(Debug, Clone, Copy, Default)]
+struct Timeouts {
+  connect: Option<Duration>,
+  request: Option<Duration>
+}
+
+/// How the idle connections to the cluster are kept open.
+#[derive(Debug, Clone, Copy)]
+struct KeepAlive {
+  max_idle:     usize, // per node
+  idle_timeout: Option<Duration>
+}
+
+impl Default for KeepAlive {
+  fn default() -> KeepAlive {
+    KeepAlive {
+      max_idle:     8,
+      idle_timeout: Some(Duration::from_secs(30))
+    }
+  }
+}
+
+/// The search engine run by the cluster, whose APIs diverge in a few places.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Distribution {
+  ElasticSearch,
+  OpenSearch
+}
+
+impl Distribution {
+  /// Return the distribution called `name`, as given by `es.compatibility`.
+  pub fn from_name(name: &str) -> Option<Distribution> {
+    match name {
+      "elasticsearch" => Some(Distribution::ElasticSearch),
+      "opensearch"    => Some(Distribution::OpenSearch),
+      _               => None
+    }
+  }
+
+  /// Return the distribution described by the response of `GET /`, i.e.:
+  /// `{"version": {"distribution": "opensearch", "number": "1.3.0"}}`.
+  pub fn from_info(info: &JsonValue) -> Distribution {
+    match info.pointer("/version/distribution").and_then(|distribution| distribution.as_str()) {
+      Some("opensearch") => Distribution::OpenSearch,
+      _                  => Distribution::ElasticSearch
+    }
+  }
+}
+
+impl fmt::Display for Distribution {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match *self {
+      Distribution::ElasticSearch => write!(f, "ElasticSearch"),
+      Distribution::OpenSearch    => write!(f, "OpenSearch")
+    }
+  }
+}
+
+lazy_static! {
+  static ref TLS:      RwLock<Tls>      = RwLock::new(Tls::default());
+  static ref DISTRIBUTION: RwLock<Distribution> = RwLock::new(Distribution::ElasticSearch);
+  static ref TIMEOUTS: RwLock<Timeouts> = RwLock::new(Timeouts::default());
+  static ref KEEP_ALIVE: RwLock<KeepAlive> = RwLock::new(KeepAlive::default());
+
+  /// The client shared by every request, keeping the connections alive
+  /// between them. It is built again when the configuration changes.
+  static ref CLIENT: RwLock<Option<Arc<HttpClient>>> = RwLock::new(None);
+}
+
+
+/// Give up on the connections not established within `connect` and on
+/// the requests whose body is not sent or received within `request`.
+/// The requests sent through `rs-es` are not affected, since its client
+/// cannot be configured.
+pub fn configure_timeouts(connect: Option<Duration>, request: Option<Duration>) {
+  *TIMEOUTS.write().unwrap() = Timeouts { connect: connect, request: request };
+  *CLIENT.write().unwrap()   = None;
+}
+
+/// Keep up to `max_idle` idle connections open to each node, closing
+/// the ones that have been idle for longer than `idle_timeout`, if given.
+pub fn configure_keep_alive(max_idle: usize, idle_timeout: Option<Duration>) {
+  *KEEP_ALIVE.write().unwrap() = KeepAlive { max_idle: max_idle, idle_timeout: idle_timeout };
+  *CLIENT.write().unwrap()     = None;
+}
+
+/// Open the connections to the cluster, giving up after `timeout`, if any.
+struct TimeoutConnector {
+  timeout: Option<Duration>
+}
+
+impl NetworkConnector for TimeoutConnector {
+  type Stream = HttpStream;
+
+  fn connect(&self, host: &str, port: u16, scheme: &str) -> hyper::Result<HttpStream> {
+    let timeout = match self.timeout {
+      Some(timeout) => timeout,
+      None          => return HttpConnector.connect(host, port, scheme)
+    };
+
+    let mut last_error = io::Error::new(io::ErrorKind::NotFound, format!("{} cannot be resolved", host));
+
+    for addr in try!((host, port).to_socket_addrs()) {
+      match TcpStream::connect_timeout(&addr, timeout) {
+        Ok(stream) => return Ok(HttpStream(stream)),
+        Err(err)   => last_error = err
+      }
+    }
+
+    Err(hyper::Error::Io(last_error))
+  }
+}
+
+/// Trust the CA certificate found at `ca_cert`, if given, or skip the
+/// verification of the certificates when `insecure` is `true`.
+/// Must be called before any client is created.
+pub fn configure_tls(ca_cert: Option<String>, insecure: bool) {
+  if let Some(ref ca_cert) = ca_cert {
+    // `rs-es` does not accept a connector, but OpenSSL reads its
+    // default trusted certificates from here
+    env::set_var("SSL_CERT_FILE", ca_cert);
+  }
+
+  if insecure {
+    warn!("The certificates of ElasticSearch are not verified.");
+  }
+
+  *TLS.write().unwrap()    = Tls { ca_cert: ca_cert, insecure: insecure };
+  *CLIENT.write().unwrap() = None;
+}
+
+/// Return the OpenSSL client configured through `configure_tls`.
+fn ssl_client() -> Result<OpensslClient, EsError> {
+  let tls = TLS.read().unwrap();
+
+  let mut builder = try!(SslConnectorBuilder::new(SslMethod::tls()).map_err(|e| EsError::EsError(e.to_string())));
+
+  if let Some(ref ca_cert) = tls.ca_cert {
+    try!(builder.builder_mut().set_ca_file(ca_cert).map_err(|e| EsError::EsError(e.to_string())));
+  }
+
+  if tls.insecure {
+    builder.builder_mut().set_verify(SSL_VERIFY_NONE);
+  }
+
+  let mut ssl = OpensslClient::from(builder.build());
+  ssl.danger_disable_hostname_verification(tls.insecure);
+  Ok(ssl)
+}
+
+/// Return the shared client, building it on first use.
+fn client() -> Result<Arc<HttpClient>, EsError> {
+  if let Some(ref client) = *CLIENT.read().unwrap() {
+    return Ok(client.clone());
+  }
+
+  let mut shared = CLIENT.write().unwrap();
+
+  // another thread may have built it in the meantime
+  if let Some(ref client) = *shared {
+    return Ok(client.clone());
+  }
+
+  let timeouts   = *TIMEOUTS.read().unwrap();
+  let keep_alive = *KEEP_ALIVE.read().unwrap();
+  let ssl        = try!(ssl_client());
+  let connector  = TimeoutConnector { timeout: timeouts.connect };
+
+  // a connection closed by the cluster (or a proxy) while idle fails the
+  // following request, hence they are closed by Searchspot first
+  let mut pool = Pool::with_connector(PoolConfig { max_idle: keep_alive.max_idle },
+                                      HttpsConnector::with_connector(ssl, connector));
+  pool.set_idle_timeout(keep_alive.idle_timeout);
+
+  let mut client = HttpClient::with_connector(pool);
+  client.set_read_timeout(timeouts.request);
+  client.set_write_timeout(timeouts.request);
+
+  let client = Arc::new(client);
+  *shared = Some(client.clone());
+  Ok(client)
+}
+
+/// Shim the requests for the distribution given as `es.compatibility`, sniffing
+/// it from the cluster listening on `url` when `auto`. ElasticSearch is assumed
+/// when the cluster cannot be reached.
+pub fn configure_compatibility(compatibility: &str, url: &str) {
+  let distribution = match Distribution::from_name(compatibility) {
+    Some(distribution) => distribution,
+    None               => match get(url, "") {
+      Ok(info) => Distribution::from_info(&info),
+      Err(err) => {
+        warn!("Cannot sniff the distribution of the cluster, assuming ElasticSearch: {}", err);
+        Distribution::ElasticSearch
+      }
+    }
+  };
+
+  info!("The requests are sent as expected by {}.", distribution);
+  *DISTRIBUTION.write().unwrap() = distribution;
+}
+
+/// Return the distribution the requests are shimmed for.
+pub fn distribution() -> Distribution {
+  *DISTRIBUTION.read().unwrap()
+}
+
+/// Append `param` to `path` if the requests are shimmed for OpenSearch,
+/// so that it behaves as ElasticSearch does.
+fn shim(path: String, distribution: Distribution, param: &str) -> String {
+  if distribution != Distribution::OpenSearch {
+    return path;
+  }
+
+  let separator = if path.contains('?') { "&" } else { "?" };
+  format!("{}{}{}", path, separator, param)
+}
+
+/// Return `url` without its credentials (i.e.: to be printed), and without the trailing slash.
+pub fn redact(url: &str) -> String {
+  match Url::parse(url) {
+    Ok(mut url) => {
+      let _ = url.set_username("");
+      let _ = url.set_password(None);
+      url.as_str().trim_right_matches('/').to_owned()
+    },
+    Err(_) => url.to_owned()
+  }
+}
+
+/// Return the URL of the cluster that `es` sends its requests to, so that
+/// the requests `rs-es` cannot send are sent to the same node.
+pub fn url_of(es: &Client) -> String {
+  es.full_url("")
+}
+
+/// Return the path of the mapping of `index`, nested under the types
+/// of the documents as returned by ElasticSearch.
+pub fn mapping_path(index: &str) -> String {
+  shim(format!("{}/_mapping", index), distribution(), "include_type_name=true")
+}
+
+/// Return the path updating the mapping of `doc_type` inside `index`.
+pub fn type_mapping_path(index: &str, doc_type: &str) -> String {
+  shim(format!("{}/_mapping/{}", index, doc_type), distribution(), "include_type_name=true")
+}
+
+/// Return the path of the document `id` of `doc_type` inside `index`, whose
+/// types are replaced by `_doc` on OpenSearch, which refuses any other one.
+pub fn document_path(index: &str, doc_type: &str, id: &str) -> String {
+  let doc_type = if distribution() == Distribution::OpenSearch { "_doc" } else { doc_type };
+  format!("{}/{}/{}", index, doc_type, id)
+}
+
+/// Return the path creating `index`, with the mappings nested under the types of the documents.
+pub fn index_path(index: &str) -> String {
+  shim(index.to_owned(), distribution(), "include_type_name=true")
+}
+
+/// Create `index` with `settings` (i.e.: its analyzers) and the `mapping` of its types,
+/// which OpenSearch accepts only when asked to (unlike `rs-es`, which cannot).
+pub fn create_index(url: &str, index: &str, settings: &JsonValue, mapping: &Mapping) -> Result<JsonValue, EsError> {
+  put(url, &index_path(index), &index_body(settings, mapping))
+}
+
+fn index_body(settings: &JsonValue, mapping: &Mapping) -> String {
+  let mappings = mapping.iter()
+                        .map(|(doc_type, fields)| (doc_type.to_string(), btreemap! { "properties" => fields }))
+                        .collect::<BTreeMap<_, _>>();
+
+  serde_json::to_string(&btreemap! {
+    "settings" => settings.to_owned(),
+    "mappings" => serde_json::to_value(&mappings)
+  }).unwrap()
+}
+
+/// Send a `GET` request to given `path` of the cluster listening on `url`
+/// and return the decoded JSON response.
+pub fn get(url: &str, path: &str) -> Result<JsonValue, EsError> {
+  request(Method::Get, url, path, None)
+}
+
+/// Send a `POST` request with given `body` to given `path` of the cluster
+/// listening on `url` and return the decoded JSON response.
+pub fn post(url: &str, path: &str, body: &str) -> Result<JsonValue, EsError> {
+  request(Method::Post, url, path, Some(body))
+}
+
+/// Send a `PUT` request with given `body` to given `path` of the cluster
+/// listening on `url` and return the decoded JSON response.
+pub fn put(url: &str, path: &str, body: &str) -> Result<JsonValue, EsError> {
+  request(Method::Put, url, path, Some(body))
+}
+
+/// Perform the request, authenticating it with the credentials
+/// that may be contained in `url`. Any non-2xx response is
+/// returned as `EsError::EsServerError`. The transient failures
+/// of the idempotent requests are retried (see `retry`).
+pub fn request(method: Method, url: &str, path: &str, body: Option<&str>) -> Result<JsonValue, EsError> {
+  if idempotent(&method, path) {
+    retry(|| send(method.to_owned(), url, path, body))
+  }
+  else {
+    send(method, url, path, body)
+  }
+}
+
+/// The endpoints that only read, hence can be sent again through `POST`.
+const READ_ENDPOINTS: [&'static str; 6] = ["_search", "_msearch", "_count", "_mget", "_analyze", "_refresh"];
+
+/// Return whether sending the request to `path` again cannot apply it twice, or apply it
+/// after a later request: the `GET`, `HEAD`, `PUT` and `DELETE` requests (but the creations)
+/// and the searches sent through `POST`. The other `POST` requests are not (i.e.: the pages
+/// of a scroll, which moves on at every one, the `_bulk` requests or the `_aliases` swaps).
+pub fn idempotent(method: &Method, path: &str) -> bool {
+  let (path, query) = match path.find('?') {
+    Some(at) => (&path[..at], &path[at + 1..]),
+    None     => (path, "")
+  };
+
+  let endpoint = path.trim_right_matches('/').rsplit('/').next().unwrap_or("");
+  let creation = endpoint == "_create" || query.split('&').any(|param| param == "op_type=create");
+
+  match *method {
+    Method::Get | Method::Head | Method::Delete => true,
+    Method::Put                                 => !creation,
+    Method::Post                                => READ_ENDPOINTS.contains(&endpoint),
+    _                                           => false
+  }
+}
+
+fn send(method: Method, url: &str, path: &str, body: Option<&str>) -> Result<JsonValue, EsError> {
+  let base = try!(Url::parse(url).map_err(|e| EsError::EsError(e.to_string())));
+  let url  = try!(base.join(path).map_err(|e| EsError::EsError(e.to_string())));
+
+  let mut headers = Headers::new();
+  headers.set(ContentType::json());
+
+  // ElasticSearch reports it in its tasks and slow logs
+  if let Some(id) = request_id::current() {
+    headers.set_raw("X-Opaque-Id", vec![id.into_bytes()]);
+  }
+
+  let mut span = Span::client(&format!("{} {}", method, url.path()));
+  span.attribute("db.system", "elasticsearch");
+
+  if let Some(context) = span.context() {
+    headers.set_raw(TRACEPARENT_HEADER, vec![context.header().into_bytes()]);
+  }
+
+  if !base.username().is_empty() {
+    headers.set(Authorization(Basic {
+      username: base.username().to_owned(),
+      password: base.password().map(|p| p.to_owned())
+    }));
+  }
+
+  let client = try!(client());
+
+  let request = client.request(method, url).headers(headers);
+  let request = match body {
+    Some(body) => request.body(body),
+    None       => request
+  };
+
+  // the connection errors are kept as such, since they are retried
+  let mut response = try!(request.send().map_err(|err| {
+    span.fail(&err.to_string());
+    EsError::HttpError(err)
+  }));
+
+  let mut payload = String::new();
+  try!(response.read_to_string(&mut payload));
+
+  span.attribute("http.status_code", &response.status.to_u16().to_string());
+  if !response.status.is_success() {
+    span.fail(&response.status.to_string());
+    return Err(EsError::EsServerError(format!("{} - {}", response.status, payload)));
+  }
+
+  serde_json::from_str(&payload).map_err(|e| EsError::EsError(e.to_string()))
+}
+
+/// Fetch the `_source` of the documents with given `ids` from `index`
+/// in a single round trip, returning `None` for the missing ones.
+pub fn mget(url: &str, index: &str, ids: &[String]) -> Result<Vec<Option<JsonValue>>, EsError> {
+  let body = serde_json::to_string(&btreemap! { "ids" => ids }).unwrap();
+  let response = try!(post(url, &format!("{}/_mget", index), &body));
+
+  let docs = match response.find("docs").and_then(|docs| docs.as_array()) {
+    Some(docs) => docs.to_owned(),
+    None       => return Err(EsError::EsError(format!("Unexpected response: {}", response)))
+  };
+
+  Ok(docs.into_iter()
+         .map(|doc| match doc.find("found").and_then(|found| found.as_bool()) {
+           Some(true) => doc.find("_source").map(|source| source.to_owned()),
+           _          => None
+         })
+         .collect())
+}
+
+/// Same as `mget`, fetching each of the documents given as `(id, routing key)` from the
+/// shard of its key (or of its id, without one), in real time as `mget` does.
+pub fn mget_keyed(url: &str, index: &str, docs: &[(String, Option<String>)]) -> Result<Vec<Option<JsonValue>>, EsError> {
+  let response = try!(post(url, &format!("{}/_mget", index), &keyed_docs(docs)));
+
+  let docs = match response.find("docs").and_then(|docs| docs.as_array()) {
+    Some(docs) => docs.to_owned(),
+    None       => return Err(EsError::EsError(format!("Unexpected response: {}", response)))
+  };
+
+  Ok(docs.into_iter()
+         .map(|doc| match doc.find("found").and_then(|found| found.as_bool()) {
+           Some(true) => doc.find("_source").map(|source| source.to_owned()),
+           _          => None
+         })
+         .collect())
+}
+
+/// Return the versions of the documents given as `(id, routing key)` stored into `index`,
+/// by id, fetched in real time without their `_source`. The missing ones are left out.
+pub fn versions(url: &str, index: &str, docs: &[(String, Option<String>)]) -> Result<BTreeMap<String, u64>, EsError> {
+  let response = try!(post(url, &format!("{}/_mget?_source=false", index), &keyed_docs(docs)));
+
+  let docs = match response.find("docs").and_then(|docs| docs.as_array()) {
+    Some(docs) => docs.to_owned(),
+    None       => return Err(EsError::EsError(format!("Unexpected response: {}", response)))
+  };
+
+  Ok(docs.into_iter()
+         .filter_map(|doc| match (doc.find("_id").and_then(|id| id.as_str()), doc.find("_version").and_then(|version| version.as_u64())) {
+           (Some(id), Some(version)) => Some((id.to_owned(), version)),
+           _                         => None
+         })
+         .collect())
+}
+
+/// Return the body of the `_mget` request fetching the documents given as `(id, routing key)`.
+fn keyed_docs(docs: &[(String, Option<String>)]) -> String {
+  let routing = routing_field(distribution());
+
+  let docs = docs.iter()
+                 .map(|&(ref id, ref key)| {
+                   let mut doc = btreemap! { "_id".to_owned() => id.to_owned() };
+                   if let Some(ref key) = *key {
+                     doc.insert(routing.to_owned(), key.to_owned());
+                   }
+                   doc
+                 })
+                 .collect::<Vec<_>>();
+
+  serde_json::to_string(&btreemap! { "docs" => docs }).unwrap()
+}
+
+/// Return the name of the routing key of the documents given to `_mget` and `_bulk`
+/// by `distribution`, since `_routing` has been renamed in ElasticSearch 7.
+pub fn routing_field(distribution: Distribution) -> &'static str {
+  if distribution == Distribution::OpenSearch { "routing" } else { "_routing" }
+}
+
+/// Make the latest writes to `index` visible to its searches.
+pub fn refresh(url: &str, index: &str) -> Result<JsonValue, EsError> {
+  post(url, &format!("{}/_refresh", index), "")
+}
+
+/// Same as `mget`, searching every shard for the documents with given `ids`,
+/// since the ones routed by another key than their id are not found by `_mget`.
+pub fn mget_routed(url: &str, index: &str, ids: &[String]) -> Result<Vec<Option<JsonValue>>, EsError> {
+  let body = serde_json::to_string(&btreemap! {
+    "query" => serde_json::to_value(&btreemap! { "ids" => btreemap! { "values" => ids } }),
+    "size"  => serde_json::to_value(&ids.len())
+  }).unwrap();
+  let response = try!(post(url, &format!("{}/_search", index), &body));
+
+  let hits = match response.pointer("/hits/hits").and_then(|hits| hits.as_array()) {
+    Some(hits) => hits.to_owned(),
+    None       => return Err(EsError::EsError(format!("Unexpected response: {}", response)))
+  };
+
+  Ok(ids.iter()
+        .map(|id| hits.iter()
+                      .find(|hit| hit.find("_id").and_then(|found| found.as_str()) == Some(&**id))
+                      .and_then(|hit| hit.find("_source"))
+                      .map(|source| source.to_owned()))
+        .collect())
+}
+
+/// Set how many replicas of each shard of `index` are kept by the cluster.
+pub fn set_replicas(url: &str, index: &str, replicas: u32) -> Result<JsonValue, EsError> {
+  let body = serde_json::to_string(&btreemap! {
+    "index" => btreemap! { "number_of_replicas" => replicas }
+  }).unwrap();
+
+  put(url, &format!("{}/_settings", index), &body)
+}
+
+/// Return the indexes named `name` or behind the alias `name`, along with their aliases.
+pub fn aliases(url: &str, name: &str) -> Result<BTreeMap<String, Vec<String>>, EsError> {
+  let response = match get(url, &format!("{}/_alias", name)) {
+    Ok(response)                                                            => response,
+    Err(EsError::EsServerError(ref err)) if err.contains("index_not_found") => return Ok(BTreeMap::new()),
+    Err(err)                                                                => return Err(err)
+  };
+
+  Ok(response.as_object()
+             .map(|indexes| indexes.iter()
+                                   .map(|(index, aliases)| {
+                                     let aliases = aliases.find("aliases")
+                                                          .and_then(|aliases| aliases.as_object())
+                                                          .map(|aliases| aliases.keys().cloned().collect())
+                                                          .unwrap_or(vec![]);
+                                     (index.to_owned(), aliases)
+                                   })
+                                   .collect())
+             .unwrap_or(BTreeMap::new()))
+}
+
+/// Point `alias` to `index` alone in a single `_aliases` call, returning the indexes
+/// it pointed to before, which are left to be deleted. An index named `alias` is
+/// deleted by the same call (`remove_index`), since they cannot coexist, so that
+/// the searches never fail in between.
+pub fn swap_alias(url: &str, alias: &str, index: &str) -> Result<Vec<String>, EsError> {
+  let previous = try!(aliases(url, alias)).into_iter().map(|(previous, _)| previous).collect::<Vec<String>>();
+  let body     = serde_json::to_string(&btreemap! { "actions" => swap_actions(alias, index, &previous) }).unwrap();
+
+  try!(post(url, "_aliases", &body));
+  Ok(previous.into_iter().filter(|previous| previous != alias).collect())
+}
+
+/// Return the `_aliases` actions pointing `alias` to `index` instead of the `previous` indexes.
+fn swap_actions(alias: &str, index: &str, previous: &[String]) -> Vec<JsonValue> {
+  let mut actions = previous.iter()
+                            .map(|previous| if previous == alias {
+                              serde_json::to_value(&btreemap! { "remove_index" => btreemap! { "index" => previous.to_owned() } })
+                            }
+                            else {
+                              serde_json::to_value(&btreemap! { "remove" => btreemap! { "index" => previous.to_owned(), "alias" => alias.to_owned() } })
+                            })
+                            .collect::<Vec<JsonValue>>();
+  actions.push(serde_json::to_value(&btreemap! { "add" => btreemap! { "index" => index.to_owned(), "alias" => alias.to_owned() } }));
+  actions
+}
+
+/// Refuse (or accept again) the writes to `index`, i.e. while it is being copied.
+pub fn block_writes(url: &str, index: &str, blocked: bool) -> Result<JsonValue, EsError> {
+  let body = serde_json::to_string(&btreemap! {
+    "index" => btreemap! { "blocks.write" => blocked }
+  }).unwrap();
+
+  put(url, &format!("{}/_settings", index), &body)
+}
+
+/// Send the search given as `{"index": [...], "body": {...}}`, asking ElasticSearch
+/// to report how long each of its phases took on every shard if `profile` is given.
+/// The missing indexes are skipped if `"ignore_unavailable": true` is given as well,
+/// and only the shards of the keys of `"routing": "a,b"` are searched if it is given.
+pub fn search(url: &str, search: &JsonValue, profile: bool) -> Result<JsonValue, EsError> {
+  let (path, body) = search_request(search, profile);
+  post(url, &path, &serde_json::to_string(&body).unwrap())
+}
+
+/// Return the path and the body of the request sending `search`, given as
+/// `{"index": [...], "body": {...}}`, asking for its profile if `profile`.
+pub fn search_request(search: &JsonValue, profile: bool) -> (String, JsonValue) {
+  let indexes = search.find("index")
+                      .and_then(|indexes| indexes.as_array())
+                      .map(|indexes| indexes.iter().filter_map(|index| index.as_str()).collect::<Vec<&str>>())
+                      .unwrap_or(vec![]);
+
+  let mut body = search.find("body").cloned().unwrap_or(JsonValue::Null);
+  if profile {
+    if let JsonValue::Object(ref mut body) = body {
+      body.insert("profile".to_owned(), JsonValue::Bool(true));
+    }
+  }
+
+  let mut options = vec![];
+
+  // the indexes that do not exist are skipped instead of failing the search
+  if search.find("ignore_unavailable") == Some(&JsonValue::Bool(true)) {
+    options.push("ignore_unavailable=true".to_owned());
+  }
+
+  // only the shards of the routing keys are searched
+  if let Some(routing) = search.find("routing").and_then(|routing| routing.as_str()) {
+    options.push(format!("routing={}", routing));
+  }
+
+  let path = if options.is_empty() {
+    format!("{}/_search", indexes.join(","))
+  }
+  else {
+    format!("{}/_search?{}", indexes.join(","), options.join("&"))
+  };
+
+  // `hits.total` is returned as `{"value": 42, "relation": "eq"}` otherwise
+  let path = shim(path, distribution(), "rest_total_hits_as_int=true");
+  (path, body)
+}
+
+/// Send the searches given as `{"index": [...], "body": {...}}` in a single
+/// round trip through `_msearch`, returning their responses in order.
+/// The searches that failed are returned as `Err`.
+pub fn msearch(url: &str, searches: &[JsonValue]) -> Result<Vec<Result<JsonValue, EsError>>, EsError> {
+  let mut body = String::new();
+
+  for search in searches {
+    let mut header = btreemap! { "index" => search.find("index").cloned().unwrap_or(JsonValue::Null) };
+    if let Some(ignore_unavailable) = search.find("ignore_unavailable") {
+      header.insert("ignore_unavailable", ignore_unavailable.to_owned());
+    }
+    if let Some(routing) = search.find("routing") {
+      header.insert("routing", routing.to_owned());
+    }
+
+    body.push_str(&serde_json::to_string(&header).unwrap());
+    body.push('\n');
+    body.push_str(&serde_json::to_string(&search.find("body").cloned().unwrap_or(JsonValue::Null)).unwrap());
+    body.push('\n');
+  }
+
+  let path     = shim("_msearch".to_owned(), distribution(), "rest_total_hits_as_int=true");
+  let response = try!(post(url, &path, &body));
+
+  let responses = match response.find("responses").and_then(|responses| responses.as_array()) {
+    Some(responses) => responses.to_owned(),
+    None            => return Err(EsError::EsError(format!("Unexpected response: {}", response)))
+  };
+
+  Ok(responses.into_iter()
+              .map(|response| match response.find("error") {
+                Some(error) => Err(EsError::EsServerError(error.to_string())),
+                None        => Ok(response)
+              })
+              .collect())
+}
+
+/// Open a scroll over the hits of `indexes` (comma separated, skipped when missing)
+/// matching the search `body`, kept open for `keep_alive` (i.e.: `1m`) between its
+/// pages. Return the first page, whose `_scroll_id` fetches the next one.
+/// Unlike the `scan` of `rs-es`, it is supported by every version of ElasticSearch
+/// and by OpenSearch.
+pub fn open_scroll(url: &str, indexes: &str, body: &JsonValue, keep_alive: &str) -> Result<JsonValue, EsError> {
+  let path = format!("{}/_search?scroll={}&ignore_unavailable=true", indexes, keep_alive);
+  let path = shim(path, distribution(), "rest_total_hits_as_int=true");
+  post(url, &path, &serde_json::to_string(body).unwrap())
+}
+
+/// Return the page of the scroll following `page`, keeping it open for `keep_alive`.
+pub fn scroll(url: &str, page: &JsonValue, keep_alive: &str) -> Result<JsonValue, EsError> {
+  let scroll_id = try!(scroll_id(page));
+  let body      = serde_json::to_string(&btreemap! { "scroll" => keep_alive, "scroll_id" => scroll_id }).unwrap();
+  let path      = shim("_search/scroll".to_owned(), distribution(), "rest_total_hits_as_int=true");
+  post(url, &path, &body)
+}
+
+/// Close the scroll of `page` before it expires.
+pub fn clear_scroll(url: &str, page: &JsonValue) -> Result<JsonValue, EsError> {
+  let scroll_id = try!(scroll_id(page));
+  let body      = serde_json::to_string(&btreemap! { "scroll_id" => vec![scroll_id] }).unwrap();
+  request(Method::Delete, url, "_search/scroll", Some(&body))
+}
+
+fn scroll_id(page: &JsonValue) -> Result<&str, EsError> {
+  page.find("_scroll_id")
+      .and_then(|scroll_id| scroll_id.as_str())
+      .ok_or(EsError::EsError(format!("Unexpected response: {}", page)))
+}
+
+/// Return the hits of a page of search results, none once a scroll is over.
+pub fn hits(page: &JsonValue) -> Vec<JsonValue> {
+  page.pointer("/hits/hits").and_then(|hits| hits.as_array()).cloned().unwrap_or(vec![])
+}
+
+#[cfg(test)]
+mod tests {
+  use es::{Distribution, shim, index_body, search_request, hits, swap_actions, keyed_docs, idempotent};
+
+  use hyper::method::Method;
+
+  use serde_json;
+
+  #[test]
+  fn test_hits() {
+    let page = serde_json::from_str("{\"_scroll_id\": \"c2Nhbg\", \"hits\": {\"total\": 1, \"hits\": [{\"_id\": \"42\"}]}}").unwrap();
+    assert_eq!(hits(&page).len(), 1);
+
+    let over = serde_json::from_str("{\"_scroll_id\": \"c2Nhbg\", \"hits\": {\"total\": 1, \"hits\": []}}").unwrap();
+    assert!(hits(&over).is_empty());
+  }
+
+  #[test]
+  fn test_keyed_docs() {
+    assert_eq!(keyed_docs(&[("42".to_owned(), None), ("43".to_owned(), Some("2017-03-06".to_owned()))]),
+               "{\"docs\":[{\"_id\":\"42\"},{\"_id\":\"43\",\"_routing\":\"2017-03-06\"}]}");
+  }
+
+  #[test]
+  fn test_swap_actions() {
+    let actions = swap_actions("talents", "talents_20170304122400", &["talents".to_owned(), "talents_20170201000000".to_owned()]);
+
+    assert_eq!(serde_json::to_string(&actions).unwrap(),
+               "[{\"remove_index\":{\"index\":\"talents\"}},\
+                {\"remove\":{\"alias\":\"talents\",\"index\":\"talents_20170201000000\"}},\
+                {\"add\":{\"alias\":\"talents\",\"index\":\"talents_20170304122400\"}}]");
+  }
+
+  #[test]
+  fn test_distribution() {
+    let opensearch = serde_json::from_str("{\"version\": {\"distribution\": \"opensearch\", \"number\": \"1.3.0\"}}").unwrap();
+    assert_eq!(Distribution::from_info(&opensearch), Distribution::OpenSearch);
+
+    let elasticsearch = serde_json::from_str("{\"version\": {\"number\": \"2.4.6\"}}").unwrap();
+    assert_eq!(Distribution::from_info(&elasticsearch), Distribution::ElasticSearch);
+
+    assert_eq!(Distribution::from_name("opensearch"), Some(Distribution::OpenSearch));
+    assert_eq!(Distribution::from_name("auto"), None);
+  }
+
+  #[test]
+  fn test_idempotent() {
+    assert!(idempotent(&Method::Get, "talents/talent/42"));
+    assert!(idempotent(&Method::Delete, "talents"));
+    assert!(idempotent(&Method::Put, "searchspot_leases/lease/backup-talents?version=3"));
+    assert!(idempotent(&Method::Post, "talents/_search?ignore_unavailable=true"));
+    assert!(idempotent(&Method::Post, "talents/_search?scroll=1m"));
+    assert!(idempotent(&Method::Post, "_msearch?rest_total_hits_as_int=true"));
+
+    assert!(!idempotent(&Method::Put, "searchspot_leases/lease/backup-talents?op_type=create"));
+    assert!(!idempotent(&Method::Put, "talents/_create/42"));
+    assert!(!idempotent(&Method::Post, "_search/scroll"));
+    assert!(!idempotent(&Method::Post, "_bulk"));
+    assert!(!idempotent(&Method::Post, "_aliases"));
+    assert!(!idempotent(&Method::Post, "talents/_close"));
+  }
+
+  #[test]
+  fn test_shim() {
+    assert_eq!(shim("talents/_search".to_owned(), Distribution::ElasticSearch, "rest_total_hits_as_int=true"),
+               "talents/_search");
+    assert_eq!(shim("talents/_search".to_owned(), Distribution::OpenSearch, "rest_total_hits_as_int=true"),
+               "talents/_search?rest_total_hits_as_int=true");
+    assert_eq!(shim("talents/_search?ignore_unavailable=true".to_owned(), Distribution::OpenSearch, "rest_total_hits_as_int=true"),
+               "talents/_search?ignore_unavailable=true&rest_total_hits_as_int=true");
+  }
+
+  #[test]
+  fn test_search_request() {
+    let search = serde_json::from_str("{\"index\": [\"talents\"], \"body\": {}, \"ignore_unavailable\": true, \"routing\": \"a,b\"}").unwrap();
+    assert_eq!(search_request(&search, false).0, "talents/_search?ignore_unavailable=true&routing=a,b");
+
+    let search = serde_json::from_str("{\"index\": [\"talents\", \"archive\"], \"body\": {}}").unwrap();
+    assert_eq!(search_request(&search, false).0, "talents,archive/_search");
+  }
+
+  #[test]
+  fn test_index_body() {
+    let mapping = hashmap! { "talent" => hashmap! { "id" => hashmap! { "type" => "long" } } };
+
+    let settings = serde_json::from_str("{\"number_of_shards\": 1}").unwrap();
+    let body     = serde_json::from_str::<serde_json::Value>(&index_body(&settings, &mapping)).unwrap();
+
+    assert_eq!(body.pointer("/settings/number_of_shards").unwrap().as_u64(), Some(1));
+    assert_eq!(body.pointer("/mappings/talent/properties/id/type").unwrap().as_str(), Some("long"));
+  }
+}
@@ -0,0 +1,126 @@
+//! The ranking experiments, which measure a change of the relevance before
+//! rolling it out: the searches sent along with a bucket ID (i.e. the ID of the
+//! recruiter, through the `X-Searchspot-Bucket` header) are ranked by one of the
+//! variants of `[experiment]`, always the same for the same ID, and both their
+//! responses (`X-Searchspot-Variant`) and their metrics are tagged with it.
+//! The searches sent without a bucket ID are not part of the experiment.
+
+use crypto::digest::Digest;
+use crypto::sha2::Sha256;
+
+use config::{Experiment, Variant};
+use resource::SearchOptions;
+
+/// The header giving the ID the searches are bucketed by.
+pub const BUCKET_HEADER: &'static str = "X-Searchspot-Bucket";
+
+/// The header telling the variant that has ranked the results.
+pub const VARIANT_HEADER: &'static str = "X-Searchspot-Variant";
+
+/// Return the bucket of `id` in the experiment called `name`, from 0 to `buckets` (excluded).
+/// Salting by the name reshuffles the buckets of every new experiment.
+pub fn bucket(name: &str, id: &str, buckets: u64) -> u64 {
+  let mut hasher = Sha256::new();
+  hasher.input_str(name);
+  hasher.input_str(":");
+  hasher.input_str(id);
+
+  let mut digest = [0u8; 32];
+  hasher.result(&mut digest);
+
+  digest[..8].iter().fold(0u64, |hash, &byte| hash << 8 | byte as u64) % buckets
+}
+
+/// Return the name and the variant of `experiment` ranking the searches
+/// bucketed by `id`, chosen according to the weights of the variants.
+pub fn variant<'a>(experiment: &'a Experiment, id: &str) -> Option<(&'a str, &'a Variant)> {
+  if !experiment.running() || id.is_empty() {
+    return None;
+  }
+
+  let total      = experiment.variants.values().map(|variant| variant.weight).sum::<u64>();
+  let mut bucket = bucket(&experiment.name, id, total);
+
+  for (name, variant) in &experiment.variants {
+    if bucket < variant.weight {
+      return Some((name, variant));
+    }
+
+    bucket -= variant.weight;
+  }
+
+  None
+}
+
+/// Replace the settings of `options` with the ones given by `variant`.
+pub fn apply(variant: &Variant, options: &mut SearchOptions) {
+  options.boosts.extend(variant.boosts.to_owned());
+
+  if let Some(ref decay) = variant.decay {
+    options.decay = Some(decay.to_owned());
+  }
+
+  if let Some(decay_ranking) = variant.decay_ranking {
+    options.features.decay_ranking = decay_ranking;
+  }
+
+  if let Some(computed_score) = variant.computed_score {
+    options.features.computed_score = computed_score;
+  }
+
+  if let Some(ltr_rescoring) = variant.ltr_rescoring {
+    options.features.ltr_rescoring = ltr_rescoring;
+  }
+
+  if let Some(tie_breaker) = variant.tie_breaker {
+    options.tie_breaker = tie_breaker;
+  }
+
+  if let Some(ref minimum_should_match) = variant.minimum_should_match {
+    options.minimum_should_match = Some(minimum_should_match.to_owned());
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use experiments::{bucket, variant, apply};
+  use config::{Experiment, Variant};
+  use resource::SearchOptions;
+
+  fn experiment() -> Experiment {
+    let mut experiment = Experiment { enabled: true, name: "decay".to_owned(), ..Default::default() };
+    experiment.variants.insert("control".to_owned(), Variant::default());
+    experiment.variants.insert("no_decay".to_owned(), Variant { weight: 3, decay_ranking: Some(false), ..Default::default() });
+    experiment
+  }
+
+  #[test]
+  fn test_bucket() {
+    assert_eq!(bucket("decay", "42", 100), bucket("decay", "42", 100));
+    assert!((0..100).map(|id| bucket("decay", &id.to_string(), 4)).all(|bucket| bucket < 4));
+    assert!((0..100).any(|id| bucket("decay", &id.to_string(), 100) != bucket("boosts", &id.to_string(), 100)));
+  }
+
+  #[test]
+  fn test_variant() {
+    let experiment = experiment();
+
+    let chosen = (0..1000).map(|id| variant(&experiment, &id.to_string()).unwrap().0).collect::<Vec<&str>>();
+    let control = chosen.iter().filter(|&&name| name == "control").count();
+    assert!(control > 150 && control < 350, "{} searches out of 1000 in control", control);
+
+    assert_eq!(variant(&experiment, "42"), variant(&experiment, "42"));
+    assert_eq!(variant(&experiment, ""), None);
+    assert_eq!(variant(&Experiment { enabled: false, ..experiment.to_owned() }, "42"), None);
+  }
+
+  #[test]
+  fn test_apply() {
+    let mut options = SearchOptions::default();
+    apply(&Variant { tie_breaker: Some(0.3), decay_ranking: Some(false), ..Default::default() }, &mut options);
+
+    assert_eq!(options.tie_breaker, 0.3);
+    assert!(!options.features.decay_ranking);
+    assert_eq!(options.minimum_should_match, None);
+  }
+}
@@ -0,0 +1,272 @@
+//! The export of the resources matching a search as CSV, fetched through a
+//! scroll one page at a time and written while being fetched, so that the
+//! exports are never buffered all together in memory.
+
+use serde_json;
+use serde_json::Value as JsonValue;
+
+use iron::response::{WriteBody, ResponseBody};
+
+use rs_es::error::EsError;
+
+use hyper::method::Method;
+
+use es;
+use scroll::PAGE_SIZE;
+use redaction::Redaction;
+
+use std::io;
+use std::io::Write;
+
+/// How long ElasticSearch keeps the scroll open between two pages.
+const SCROLL: &'static str = "1m";
+
+/// The body of a `text/csv` response, writing the header and then a row
+/// for each resource found by the search, up to `max_rows` (`0` for no limit).
+pub struct CsvExport {
+  url:       String,
+  columns:   Vec<String>,
+  max_rows:  u64,
+  redaction: Option<Redaction>,
+  scroll_id: Option<String>,
+  page:      Vec<JsonValue>,
+  total:     Option<u64>
+}
+
+impl CsvExport {
+  /// Open a scroll over the resources matching the search `preview`, as returned
+  /// by `Resource::preview`, fetching the first page straight away so that
+  /// ElasticSearch failing is reported before the response is sent.
+  /// The search is sent as previewed (i.e.: with its `min_score` and its rescore)
+  /// but for its pagination and its sorting.
+  /// The personal fields of the resources are redacted by `redaction`, if given.
+  pub fn open(url: &str, preview: &JsonValue, columns: Vec<String>, max_rows: u64,
+              redaction: Option<Redaction>) -> Result<CsvExport, EsError> {
+    let indexes = preview.find("index")
+                         .and_then(|indexes| indexes.as_array())
+                         .map(|indexes| indexes.iter().filter_map(|index| index.as_str()).collect::<Vec<&str>>())
+                         .unwrap_or(vec![]);
+
+    let mut path = format!("{}/_search?scroll={}", indexes.join(","), SCROLL);
+    if preview.find("ignore_unavailable").and_then(|ignore| ignore.as_bool()) == Some(true) {
+      path.push_str("&ignore_unavailable=true");
+    }
+    if let Some(routing) = preview.find("routing").and_then(|routing| routing.as_str()) {
+      path.push_str(&format!("&routing={}", routing));
+    }
+
+    let body     = body(preview, &columns);
+    let response = try!(es::post(url, &path, &serde_json::to_string(&body).unwrap()));
+    let (scroll_id, page) = hits(&response);
+
+    Ok(CsvExport {
+      url:       url.to_owned(),
+      columns:   columns,
+      max_rows:  max_rows,
+      redaction: redaction,
+      scroll_id: scroll_id,
+      page:      page,
+      total:     total(&response)
+    })
+  }
+
+  /// Return how many resources match the search, before `max_rows` is applied.
+  pub fn total(&self) -> Option<u64> {
+    self.total
+  }
+
+  /// Fetch the next page of the scroll.
+  fn next_page(&mut self) -> Result<Vec<JsonValue>, EsError> {
+    let scroll_id = match self.scroll_id {
+      Some(ref scroll_id) => scroll_id.to_owned(),
+      None                => return Ok(vec![])
+    };
+
+    let body = btreemap! { "scroll" => SCROLL.to_owned(), "scroll_id" => scroll_id };
+    let response = try!(es::post(&self.url, "_search/scroll", &serde_json::to_string(&body).unwrap()));
+    let (scroll_id, page) = hits(&response);
+
+    self.scroll_id = scroll_id;
+    Ok(page)
+  }
+}
+
+impl WriteBody for CsvExport {
+  fn write_body(&mut self, res: &mut ResponseBody) -> io::Result<()> {
+    try!(write!(res, "{}\r\n", header(&self.columns)));
+
+    let mut written = 0;
+
+    while !self.page.is_empty() {
+      for mut source in self.page.drain(..) {
+        if self.max_rows > 0 && written == self.max_rows {
+          return Ok(());
+        }
+
+        if let Some(ref redaction) = self.redaction {
+          redaction.redact(&mut source);
+        }
+
+        try!(write!(res, "{}\r\n", row(&source, &self.columns)));
+        written += 1;
+      }
+
+      try!(res.flush());
+
+      // the status has been sent already, the export is truncated
+      self.page = match self.next_page() {
+        Ok(page) => page,
+        Err(err) => {
+          error!("The export has been truncated after {} rows: {}", written, err);
+          return Ok(());
+        }
+      };
+    }
+
+    Ok(())
+  }
+}
+
+impl Drop for CsvExport {
+  fn drop(&mut self) {
+    if let Some(scroll_id) = self.scroll_id.take() {
+      let body = serde_json::to_string(&btreemap! { "scroll_id" => vec![scroll_id] }).unwrap();
+
+      if let Err(err) = es::request(Method::Delete, &self.url, "_search/scroll", Some(&body)) {
+        error!("Cannot close the scroll: {}", err);
+      }
+    }
+  }
+}
+
+/// Return the body of the search `preview` fetching the `columns` of every
+/// resource it matches, one page of the scroll at a time.
+fn body(preview: &JsonValue, columns: &[String]) -> JsonValue {
+  let mut body = preview.find("body").and_then(|body| body.as_object()).cloned().unwrap_or_default();
+
+  body.remove("from");
+  body.remove("sort");
+  body.insert("_source".to_owned(), serde_json::to_value(&columns));
+  body.insert("size".to_owned(),    serde_json::to_value(&PAGE_SIZE));
+
+  // the order is not relevant, `_doc` is the cheapest one, but the rescored searches cannot be sorted
+  if !body.contains_key("rescore") {
+    body.insert("sort".to_owned(), serde_json::to_value(&["_doc"]));
+  }
+
+  JsonValue::Object(body)
+}
+
+/// Return the `hits.total` of the first page of the scroll, as a number or
+/// as `{"value": 42, "relation": "eq"}` (from ElasticSearch 7).
+fn total(response: &JsonValue) -> Option<u64> {
+  response.pointer("/hits/total")
+          .and_then(|total| total.as_u64().or(total.find("value").and_then(|value| value.as_u64())))
+}
+
+/// Return the scroll id and the `_source` of the hits of a page of the scroll.
+fn hits(response: &JsonValue) -> (Option<String>, Vec<JsonValue>) {
+  let scroll_id = response.find("_scroll_id").and_then(|id| id.as_str()).map(|id| id.to_owned());
+  let sources   = response.pointer("/hits/hits")
+                          .and_then(|hits| hits.as_array())
+                          .map(|hits| hits.iter().filter_map(|hit| hit.find("_source").cloned()).collect())
+                          .unwrap_or(vec![]);
+
+  (scroll_id, sources)
+}
+
+/// Return the header of the CSV, naming the `columns`.
+pub fn header(columns: &[String]) -> String {
+  columns.iter().map(|column| escape(column)).collect::<Vec<String>>().join(",")
+}
+
+/// Return the row of the resource whose `_source` is `source`, given its `columns`.
+pub fn row(source: &JsonValue, columns: &[String]) -> String {
+  columns.iter()
+         .map(|column| escape(&cell(source.find(column).unwrap_or(&JsonValue::Null))))
+         .collect::<Vec<String>>()
+         .join(",")
+}
+
+/// Return the text of a cell, joining the lists with `; `.
+fn cell(value: &JsonValue) -> String {
+  match *value {
+    JsonValue::Null               => "".to_owned(),
+    JsonValue::String(ref value)  => value.to_owned(),
+    JsonValue::Array(ref values)  => values.iter().map(cell).collect::<Vec<String>>().join("; "),
+    ref value                     => value.to_string()
+  }
+}
+
+/// Quote `value` when needed (RFC 4180), prefixing it with `'` when it would
+/// be taken for a formula by the spreadsheets (i.e.: `=HYPERLINK(...)`).
+fn escape(value: &str) -> String {
+  let value = match value.chars().next() {
+    Some('=') | Some('+') | Some('-') | Some('@') => format!("'{}", value),
+    _                                             => value.to_owned()
+  };
+
+  if value.contains(|c: char| c == ',' || c == '"' || c == '\n' || c == '\r') {
+    format!("\"{}\"", value.replace("\"", "\"\""))
+  }
+  else {
+    value
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use export::{header, row, body, hits, total, escape};
+
+  use serde_json;
+
+  #[test]
+  fn test_row() {
+    let columns = vec!["id".to_owned(), "headline".to_owned(), "skills".to_owned(), "avatar_url".to_owned()];
+    let source  = serde_json::from_str("{\"id\": 42, \"headline\": \"Backend, \\\"senior\\\"\", \"skills\": [\"Rust\", \"Go\"]}").unwrap();
+
+    assert_eq!(header(&columns), "id,headline,skills,avatar_url");
+    assert_eq!(row(&source, &columns), "42,\"Backend, \"\"senior\"\"\",Rust; Go,");
+  }
+
+  #[test]
+  fn test_escape() {
+    assert_eq!(escape("Berlin"),           "Berlin");
+    assert_eq!(escape("line\nbreak"),      "\"line\nbreak\"");
+    assert_eq!(escape("=HYPERLINK(\"x\")"), "\"'=HYPERLINK(\"\"x\"\")\"");
+    assert_eq!(escape("@SUM(A1)"),         "'@SUM(A1)");
+  }
+
+  #[test]
+  fn test_hits() {
+    let response = serde_json::from_str("{\"_scroll_id\": \"c2Nhbg\", \"hits\": {\"hits\": [{\"_id\": \"42\", \"_source\": {\"id\": 42}}]}}").unwrap();
+    let (scroll_id, sources) = hits(&response);
+
+    assert_eq!(scroll_id, Some("c2Nhbg".to_owned()));
+    assert_eq!(sources, vec![serde_json::from_str::<serde_json::Value>("{\"id\": 42}").unwrap()]);
+
+    assert_eq!(total(&serde_json::from_str("{\"hits\": {\"total\": 12}}").unwrap()), Some(12));
+    assert_eq!(total(&serde_json::from_str("{\"hits\": {\"total\": {\"value\": 12, \"relation\": \"eq\"}}}").unwrap()), Some(12));
+  }
+
+  #[test]
+  fn test_body() {
+    let columns = vec!["id".to_owned()];
+    let preview = serde_json::from_str::<serde_json::Value>("{\"index\": [\"talents\"], \"body\": {\"query\": {\"match_all\": {}}, \
+      \"from\": 20, \"size\": 10, \"sort\": [\"_score\"], \"min_score\": 0.5, \"track_scores\": true}}").unwrap();
+    let body    = body(&preview, &columns);
+
+    assert_eq!(body.find("from"), None);
+    assert_eq!(body.find("size"), Some(&serde_json::to_value(&500)));
+    assert_eq!(body.find("sort"), Some(&serde_json::to_value(&["_doc"])));
+    assert_eq!(body.find("_source"), Some(&serde_json::to_value(&columns)));
+    assert_eq!(body.find("min_score"), Some(&serde_json::to_value(&0.5)));
+    assert_eq!(body.find("query"), preview.pointer("/body/query"));
+
+    let preview = serde_json::from_str::<serde_json::Value>("{\"body\": {\"query\": {}, \"rescore\": {\"window_size\": 100}}}").unwrap();
+    let body    = body(&preview, &columns);
+
+    assert_eq!(body.find("sort"), None);
+    assert!(body.find("rescore").is_some());
+  }
+}
@@ -0,0 +1,412 @@
+//! The injection of the failures of ElasticSearch, so that the retries and the
+//! mapping of the errors can be tested without breaking a real cluster:
+//! `FaultProxy` is an HTTP server standing for a node, which forwards the
+//! requests to the cluster (if any) unless a fault has been queued, while
+//! `FaultyBackend` injects the same faults into the searches sent through
+//! another `EsBackend`, i.e.:
+//!
+//! ```rust,ignore
+//! let proxy = FaultProxy::start(None);
+//! proxy.inject(Fault::Status(429));
+//! proxy.inject(Fault::Status(503));
+//!
+//! assert!(es::get(&proxy.url, "talents").is_ok());
+//! assert_eq!(proxy.requests().len(), 3);
+//! ```
+//!
+//! Every request takes the first fault of the queue, or none once it is empty.
+
+use serde_json;
+use serde_json::Value as JsonValue;
+
+use hyper::Client as HttpClient;
+use hyper::method::Method;
+use hyper::status::StatusCode;
+
+use rs_es::error::EsError;
+
+use backend::{EsBackend, EMPTY_RESPONSE};
+
+use std::collections::{BTreeMap, VecDeque};
+use std::io;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Fault {
+  /// Hold the request for the duration without answering, then drop the
+  /// connection, as a node that is overloaded or partitioned away does
+  Timeout(Duration),
+  /// Answer with the status and the error ElasticSearch would return
+  /// (i.e.: `429` when its queues are full)
+  Status(u16),
+  /// Answer with the status and the body
+  Response(u16, String),
+  /// Answer `200 OK` with a truncated body
+  MalformedJson,
+  /// Answer a `_bulk` request as if every `n`th action had been rejected with `429`
+  PartialBulk(usize)
+}
+
+impl Fault {
+  /// Return the status and the body of the response to the request of `path`
+  /// with `body`, or `None` if the connection should be dropped.
+  fn respond(&self, path: &str, body: &str) -> Option<(u16, String)> {
+    match *self {
+      Fault::Timeout(duration)          => {
+        thread::sleep(duration);
+        None
+      },
+      Fault::Status(status)             => Some((status, error_body(status))),
+      Fault::Response(status, ref body) => Some((status, body.to_owned())),
+      Fault::MalformedJson              => Some((200, EMPTY_RESPONSE[..EMPTY_RESPONSE.len() / 2].to_owned())),
+      Fault::PartialBulk(n)             => Some((200, bulk_response(path, body, n)))
+    }
+  }
+
+  /// Return the error the fault causes to the requests sent through `es`.
+  fn error(&self) -> Option<EsError> {
+    match *self {
+      Fault::Timeout(duration)          => {
+        thread::sleep(duration);
+        Some(EsError::IoError(io::Error::new(io::ErrorKind::TimedOut, "timed out")))
+      },
+      Fault::Status(status)             => Some(server_error(status, &error_body(status))),
+      Fault::Response(status, ref body) => Some(server_error(status, body)),
+      Fault::MalformedJson              => Some(EsError::EsError(serde_json::from_str::<JsonValue>(&EMPTY_RESPONSE[..EMPTY_RESPONSE.len() / 2]).unwrap_err().to_string())),
+      Fault::PartialBulk(_)             => None
+    }
+  }
+}
+
+/// Return the error as returned by `es` for a response with `status` and `body`.
+fn server_error(status: u16, body: &str) -> EsError {
+  EsError::EsServerError(format!("{} - {}", StatusCode::from_u16(status), body))
+}
+
+/// Return the error ElasticSearch would return with `status`.
+fn error_body(status: u16) -> String {
+  let kind = match status {
+    429 => "es_rejected_execution_exception",
+    503 => "cluster_block_exception",
+    _   => "injected_fault"
+  };
+
+  format!("{{\"error\": {{\"type\": \"{}\", \"reason\": \"{}\"}}, \"status\": {}}}", kind, StatusCode::from_u16(status), status)
+}
+
+/// Return the response to the `_bulk` request of `path` with `body`, in which every `n`th
+/// action (if `n` is not 0) has been rejected and the other ones have been applied.
+pub fn bulk_response(path: &str, body: &str, n: usize) -> String {
+  // `/talents/talent/_bulk`, the actions may override them
+  let mut defaults = path.trim_left_matches('/').split('/').take_while(|segment| !segment.starts_with("_bulk"));
+  let index        = defaults.next().unwrap_or("").to_owned();
+  let doc_type     = defaults.next().unwrap_or("").to_owned();
+
+  let mut items = vec![];
+  let mut lines = body.lines().filter(|line| !line.trim().is_empty());
+
+  while let Some(line) = lines.next() {
+    let action = match serde_json::from_str::<JsonValue>(line).ok().and_then(|action| action.as_object().cloned()) {
+      Some(action) => action,
+      None         => continue
+    };
+
+    let (kind, meta) = match action.into_iter().next() {
+      Some(pair) => pair,
+      None       => continue
+    };
+
+    // the source follows every action but the deletions
+    if kind != "delete" {
+      lines.next();
+    }
+
+    let field    = |name: &str, default: &str| meta.find(name).and_then(|value| value.as_str()).unwrap_or(default).to_owned();
+    let rejected = n > 0 && (items.len() + 1) % n == 0;
+    let status   = if rejected { 429 } else if kind == "index" || kind == "create" { 201 } else { 200 };
+
+    let mut item = btreemap! {
+      "_index"   => JsonValue::String(field("_index", &index)),
+      "_type"    => JsonValue::String(field("_type", &doc_type)),
+      "_id"      => JsonValue::String(field("_id", "")),
+      "_version" => JsonValue::U64(1),
+      "_shards"  => serde_json::to_value(&btreemap! { "total" => 1, "successful" => if rejected { 0 } else { 1 }, "failed" => 0 }),
+      "status"   => JsonValue::U64(status)
+    };
+
+    if rejected {
+      item.insert("error", serde_json::from_str(&error_body(429)).ok().and_then(|body: JsonValue| body.find("error").cloned()).unwrap());
+    }
+
+    let mut object = BTreeMap::new();
+    object.insert(kind, serde_json::to_value(&item));
+    items.push(JsonValue::Object(object));
+  }
+
+  let errors = items.iter().any(|item| item.as_object().and_then(|item| item.values().next()).and_then(|item| item.find("error")).is_some());
+  serde_json::to_string(&btreemap! {
+    "took"   => JsonValue::U64(1),
+    "errors" => JsonValue::Bool(errors),
+    "items"  => JsonValue::Array(items)
+  }).unwrap()
+}
+
+struct State {
+  upstream: Option<String>,
+  faults:   Mutex<VecDeque<Fault>>,
+  requests: Mutex<Vec<String>>,
+  stopped:  AtomicBool
+}
+
+/// An HTTP server listening on a random port of the loopback interface,
+/// which is stopped once dropped.
+pub struct FaultProxy {
+  pub url: String,
+  state:   Arc<State>
+}
+
+impl FaultProxy {
+  /// Start the proxy of the cluster listening on `upstream`. Without one, the
+  /// `_bulk` requests are answered as if every action had been applied and
+  /// the other ones as if nothing had been found.
+  pub fn start(upstream: Option<&str>) -> FaultProxy {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url      = format!("http://{}", listener.local_addr().unwrap());
+
+    let state = Arc::new(State {
+      upstream: upstream.map(|upstream| upstream.trim_right_matches('/').to_owned()),
+      faults:   Mutex::new(VecDeque::new()),
+      requests: Mutex::new(vec![]),
+      stopped:  AtomicBool::new(false)
+    });
+
+    let shared = state.clone();
+    thread::spawn(move || {
+      for stream in listener.incoming() {
+        if shared.stopped.load(Ordering::SeqCst) {
+          break;
+        }
+
+        if let Ok(stream) = stream {
+          let state = shared.clone();
+          thread::spawn(move || { let _ = handle(&state, stream); });
+        }
+      }
+    });
+
+    FaultProxy { url: url, state: state }
+  }
+
+  /// Queue `fault`, injected into the first request not having one.
+  pub fn inject(&self, fault: Fault) {
+    self.state.faults.lock().unwrap().push_back(fault);
+  }
+
+  /// Return the requests received so far, in order, as `<method> <path>`.
+  pub fn requests(&self) -> Vec<String> {
+    self.state.requests.lock().unwrap().to_owned()
+  }
+}
+
+impl Drop for FaultProxy {
+  fn drop(&mut self) {
+    self.state.stopped.store(true, Ordering::SeqCst);
+    // wake up the listener, blocked until the next connection
+    let _ = TcpStream::connect(self.url.trim_left_matches("http://"));
+  }
+}
+
+/// Read the request of `stream`, then answer it as given by the first fault, if any.
+fn handle(state: &State, mut stream: TcpStream) -> io::Result<()> {
+  let (method, path, body) = {
+    let mut reader = BufReader::new(&stream);
+
+    let mut line = String::new();
+    try!(reader.read_line(&mut line));
+    let mut request = line.split_whitespace();
+    let method = request.next().unwrap_or("GET").to_owned();
+    let path   = request.next().unwrap_or("/").to_owned();
+
+    let mut length = 0;
+    loop {
+      line.clear();
+      if try!(reader.read_line(&mut line)) == 0 || line.trim().is_empty() {
+        break;
+      }
+
+      let mut header = line.splitn(2, ':');
+      if header.next().map(|name| name.trim().to_lowercase()) == Some("content-length".to_owned()) {
+        length = header.next().and_then(|value| value.trim().parse().ok()).unwrap_or(0);
+      }
+    }
+
+    let mut body = vec![0; length];
+    try!(reader.read_exact(&mut body));
+    (method, path, String::from_utf8_lossy(&body).into_owned())
+  };
+
+  state.requests.lock().unwrap().push(format!("{} {}", method, path));
+
+  let fault    = state.faults.lock().unwrap().pop_front();
+  let response = match fault {
+    Some(fault) => fault.respond(&path, &body),
+    None        => Some(forward(state, &method, &path, &body))
+  };
+
+  match response {
+    Some((status, body)) => write!(stream, "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                                   StatusCode::from_u16(status), body.len(), body),
+    None                 => Ok(())
+  }
+}
+
+/// Return the status and the body of the response of the upstream to the request.
+fn forward(state: &State, method: &str, path: &str, body: &str) -> (u16, String) {
+  let upstream = match state.upstream {
+    Some(ref upstream) => upstream,
+    None if path.contains("/_bulk") => return (200, bulk_response(path, body, 0)),
+    None                            => return (200, EMPTY_RESPONSE.to_owned())
+  };
+
+  let method   = method.parse::<Method>().unwrap_or(Method::Get);
+  let client   = HttpClient::new();
+  let url      = format!("{}{}", upstream, path);
+  let request  = client.request(method, &*url);
+  let request  = if body.is_empty() { request } else { request.body(body) };
+
+  match request.send() {
+    Ok(mut response) => {
+      let mut payload = String::new();
+      let _ = response.read_to_string(&mut payload);
+      (response.status.to_u16(), payload)
+    },
+    Err(err) => (502, format!("{{\"error\": {{\"type\": \"proxy_exception\", \"reason\": {:?}}}, \"status\": 502}}", err.to_string()))
+  }
+}
+
+/// Inject the faults into the searches sent through another backend.
+/// `Fault::PartialBulk` does not apply to the searches, which are then sent as they are.
+pub struct FaultyBackend<B: EsBackend> {
+  inner:  B,
+  faults: Mutex<VecDeque<Fault>>
+}
+
+impl<B: EsBackend> FaultyBackend<B> {
+  pub fn new(inner: B) -> FaultyBackend<B> {
+    FaultyBackend {
+      inner:  inner,
+      faults: Mutex::new(VecDeque::new())
+    }
+  }
+
+  /// Queue `fault`, injected into the first search not having one.
+  pub fn inject(&self, fault: Fault) {
+    self.faults.lock().unwrap().push_back(fault);
+  }
+}
+
+impl<B: EsBackend> EsBackend for FaultyBackend<B> {
+  fn search(&self, search: &JsonValue) -> Result<JsonValue, EsError> {
+    let fault = self.faults.lock().unwrap().pop_front();
+    match fault.and_then(|fault| fault.error()) {
+      Some(err) => Err(err),
+      None      => self.inner.search(search)
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use faults::{Fault, FaultProxy, FaultyBackend};
+  use backend::RecordingBackend;
+  use resource::{Resource, SearchOptions};
+  use resources::Talent;
+  use test_support::talent;
+  use retry::Retryable;
+  use config::Retry as RetryPolicy;
+  use error::Error;
+  use es;
+
+  use rs_es::Client;
+
+  use params::Map;
+
+  use std::time::Duration;
+
+  #[test]
+  fn test_retry() {
+    let proxy = FaultProxy::start(None);
+    proxy.inject(Fault::Status(429));
+    proxy.inject(Fault::Status(503));
+
+    assert!(es::get(&proxy.url, "talents").is_ok());
+    assert_eq!(proxy.requests(), vec!["GET /talents", "GET /talents", "GET /talents"]);
+
+    // the client errors are not retried
+    proxy.inject(Fault::Status(400));
+    let error = Error::from(es::get(&proxy.url, "talents").unwrap_err());
+    assert!(error.to_string().contains("400 Bad Request"));
+    assert_eq!(proxy.requests().len(), 4);
+  }
+
+  #[test]
+  fn test_timeout() {
+    let proxy = FaultProxy::start(None);
+    for _ in 0..3 {
+      proxy.inject(Fault::Timeout(Duration::from_millis(10)));
+    }
+
+    match Error::from(es::get(&proxy.url, "talents").unwrap_err()) {
+      error @ Error::EsTransport(_) => assert!(error.is_retryable(&RetryPolicy::default())),
+      error                         => panic!("expected a transport error, got {:?}", error)
+    }
+    assert_eq!(proxy.requests().len(), 3);
+  }
+
+  #[test]
+  fn test_malformed_json() {
+    let proxy = FaultProxy::start(None);
+    proxy.inject(Fault::MalformedJson);
+
+    let error = Error::from(es::get(&proxy.url, "talents").unwrap_err());
+    assert!(error.is_unexpected());
+    assert!(!error.is_retryable(&RetryPolicy::default()));
+    assert_eq!(proxy.requests().len(), 1);
+  }
+
+  #[test]
+  fn test_partial_bulk() {
+    let proxy   = FaultProxy::start(None);
+    let talents = (1..5).map(talent).collect::<Vec<Talent>>();
+    proxy.inject(Fault::PartialBulk(2));
+
+    let result = Talent::index(&mut Client::new(&proxy.url).unwrap(), "talents", talents).unwrap();
+    assert_eq!(result.items.iter().map(|item| item.inner.status).collect::<Vec<u64>>(), vec![201, 429, 201, 429]);
+    assert_eq!(result.items.iter().map(|item| item.inner.id.to_owned()).collect::<Vec<String>>(), vec!["1", "2", "3", "4"]);
+  }
+
+  #[test]
+  fn test_faulty_backend() {
+    let backend = FaultyBackend::new(RecordingBackend::new());
+    backend.inject(Fault::Response(404, "{\"error\": {\"type\": \"index_not_found_exception\", \"index\": \"talents\"}, \"status\": 404}".to_owned()));
+    backend.inject(Fault::MalformedJson);
+
+    match Talent::search_with(&backend, "talents", &Map::new(), &SearchOptions::default()) {
+      Err(Error::IndexNotFound(index)) => assert_eq!(index, "talents"),
+      result                           => panic!("expected a missing index, got {:?}", result.map(|_| ()))
+    }
+
+    match Talent::search_with(&backend, "talents", &Map::new(), &SearchOptions::default()) {
+      Err(error @ Error::EsQuery(_)) => assert!(error.is_unexpected()),
+      result                         => panic!("expected a query error, got {:?}", result.map(|_| ()))
+    }
+
+    assert!(Talent::search_with(&backend, "talents", &Map::new(), &SearchOptions::default()).is_ok());
+    assert_eq!(backend.inner.requests().len(), 1);
+  }
+}
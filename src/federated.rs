@@ -0,0 +1,197 @@
+//! The federated search of `GET /search`, run by the global search bar:
+//! the same params are sent to the search of each registered resource in a
+//! single `_msearch`, each resource keeping only the params it accepts, and
+//! their results are returned in one response as a section per resource.
+
+use serde_json;
+use serde_json::Value as JsonValue;
+
+use params::{Map, Value};
+
+use resource::{Resource, SearchOptions, Paginated, TimedOut};
+use schema::Param;
+use error::Error;
+
+use std::collections::BTreeMap;
+use std::marker::PhantomData;
+use std::sync::Arc;
+
+/// The resources searched by `GET /search`, by the name given as `resources[]` (i.e.: `talents`).
+pub type Registry = BTreeMap<String, Arc<Searchable>>;
+
+/// The search of a resource, whatever its type.
+pub trait Searchable: Send + Sync {
+  /// Return the name of the resource, as `Resource::name`
+  fn name(&self) -> &'static str;
+
+  /// Return the params accepted by the search, as `Resource::search_params`
+  fn search_params(&self) -> Vec<Param>;
+
+  /// Return the request sent to ElasticSearch, as `Resource::preview`
+  fn preview(&self, index: &str, params: &Map, options: &SearchOptions) -> Result<JsonValue, Error>;
+
+  /// Return the section of the results found by `response`
+  fn section(&self, section: &str, params: &Map, options: &SearchOptions, response: JsonValue) -> Result<Section, Error>;
+}
+
+/// The search of the resource `R`.
+pub struct ResourceSearch<R> {
+  resource: PhantomData<R>
+}
+
+impl<R: Resource> ResourceSearch<R> {
+  pub fn new() -> ResourceSearch<R> {
+    ResourceSearch { resource: PhantomData }
+  }
+}
+
+impl<R: Resource> Searchable for ResourceSearch<R> {
+  fn name(&self) -> &'static str {
+    R::name()
+  }
+
+  fn search_params(&self) -> Vec<Param> {
+    R::search_params()
+  }
+
+  fn preview(&self, index: &str, params: &Map, options: &SearchOptions) -> Result<JsonValue, Error> {
+    R::preview(index, params, options)
+  }
+
+  fn section(&self, section: &str, params: &Map, options: &SearchOptions, response: JsonValue) -> Result<Section, Error> {
+    let results = try!(R::results(params, options, response));
+
+    // partial results are never returned
+    if results.timed_out() {
+      return Err(Error::Timeout("The search has not been completed in time.".to_owned()));
+    }
+
+    Ok(Section {
+      resource: section.to_owned(),
+      total:    results.pagination().map(|pagination| pagination.total),
+      results:  Some(serde_json::to_value(&results)),
+      error:    None
+    })
+  }
+}
+
+/// The results of a resource, i.e.: `{"resource": "talents", "total": 42, "results": {...}}`,
+/// as `GET /talents` returns them, or `{"resource": "jobs", "error": "..."}` if its search failed.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Section {
+  pub resource: String,
+  #[serde(skip_serializing_if="Option::is_none")]
+  pub total:    Option<u64>,
+  #[serde(skip_serializing_if="Option::is_none")]
+  pub results:  Option<JsonValue>,
+  #[serde(skip_serializing_if="Option::is_none")]
+  pub error:    Option<String>
+}
+
+impl Section {
+  pub fn failed(resource: &str, err: &Error) -> Section {
+    Section {
+      resource: resource.to_owned(),
+      total:    None,
+      results:  None,
+      error:    Some(err.to_string())
+    }
+  }
+}
+
+/// The response of `GET /search`, its sections in the order of `resources[]`.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct FederatedResults {
+  pub sections: Vec<Section>
+}
+
+/// Return the names of the resources to search given as `resources[]`, every one of `registry` if missing.
+pub fn resources(params: &Map, registry: &Registry) -> Result<Vec<String>, String> {
+  let names = match params.get("resources") {
+    Some(&Value::Array(ref names)) => names.iter().filter_map(|name| match *name {
+                                        Value::String(ref name) => Some(name.to_owned()),
+                                        _                       => None
+                                      }).collect::<Vec<String>>(),
+    Some(&Value::String(ref name)) => vec![name.to_owned()],
+    _                              => return Ok(registry.keys().cloned().collect())
+  };
+
+  if let Some(unknown) = names.iter().find(|name| !registry.contains_key(*name)) {
+    let known = registry.keys().map(|name| name.as_str()).collect::<Vec<&str>>();
+    return Err(format!("`{}` cannot be searched, expected one of {}.", unknown, known.join(", ")));
+  }
+
+  let mut unique = vec![];
+  for name in names {
+    if !unique.contains(&name) {
+      unique.push(name);
+    }
+  }
+
+  Ok(unique)
+}
+
+/// Return the params among `params` accepted by `schema`.
+pub fn accepted(params: &Map, schema: &[Param]) -> Map {
+  let mut accepted = Map::new();
+
+  for (name, value) in params.iter() {
+    if schema.iter().any(|param| param.name == name.as_str()) {
+      accepted.insert(name.to_owned(), value.to_owned());
+    }
+  }
+
+  accepted
+}
+
+#[cfg(test)]
+mod tests {
+  use federated::{Registry, ResourceSearch, Section, resources, accepted};
+  use resources::Talent;
+  use schema::{Param, Kind};
+  use error::Error;
+
+  use params::{Map, Value};
+
+  use serde_json;
+
+  use std::sync::Arc;
+
+  fn registry() -> Registry {
+    let mut registry = Registry::new();
+    registry.insert("talents".to_owned(), Arc::new(ResourceSearch::<Talent>::new()));
+    registry
+  }
+
+  #[test]
+  fn test_resources() {
+    let registry = registry();
+    assert_eq!(resources(&Map::new(), &registry), Ok(vec!["talents".to_owned()]));
+
+    let mut params = Map::new();
+    params.assign("resources[]", Value::String("talents".into())).unwrap();
+    params.assign("resources[]", Value::String("talents".into())).unwrap();
+    assert_eq!(resources(&params, &registry), Ok(vec!["talents".to_owned()]));
+
+    params.assign("resources[]", Value::String("jobs".into())).unwrap();
+    assert_eq!(resources(&params, &registry), Err("`jobs` cannot be searched, expected one of talents.".to_owned()));
+  }
+
+  #[test]
+  fn test_accepted() {
+    let mut params = Map::new();
+    params.assign("keywords", Value::String("rust".into())).unwrap();
+    params.assign("resources[]", Value::String("talents".into())).unwrap();
+
+    let accepted = accepted(&params, &[Param::single("keywords", Kind::String)]);
+    assert_eq!(accepted.get("keywords"), Some(&Value::String("rust".into())));
+    assert!(accepted.get("resources").is_none());
+  }
+
+  #[test]
+  fn test_failed_section() {
+    let section = Section::failed("jobs", &Error::Timeout("The search has not been completed in time.".to_owned()));
+    assert_eq!(serde_json::to_string(&section).unwrap(),
+               "{\"resource\":\"jobs\",\"error\":\"The search has not been completed in time.\"}");
+  }
+}
@@ -0,0 +1,174 @@
+//! The resources kept as JSON or YAML files (i.e.: under `demo/`), which
+//! populate the indexes of the demo environments through `searchspot --seed <path>`.
+//!
+//! A file contains a list of resources, either as a JSON array or as a YAML
+//! sequence of mappings. A directory stands for all the files it contains.
+
+use serde_json;
+use serde_json::Value as JsonValue;
+
+use yaml_rust::{Yaml, YamlLoader};
+
+use config::Config;
+use resource::Resource;
+use cluster;
+use cluster::Cluster;
+use bulk;
+use es;
+use settings;
+use logger::start_logging;
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use std::sync::Arc;
+
+/// Return the resources of the file at `path`, read as YAML when named
+/// `*.yaml` or `*.yml` and as JSON otherwise, or of all the files inside
+/// the directory at `path`, in alphabetical order.
+pub fn load<R: Resource>(path: &str) -> Result<Vec<R>, String> {
+  if Path::new(path).is_dir() {
+    let mut paths = try!(fs::read_dir(path).map_err(|err| format!("{}: {}", path, err)))
+                      .filter_map(|entry| entry.ok())
+                      .map(|entry| entry.path())
+                      .filter(|path| path.is_file())
+                      .filter_map(|path| path.to_str().map(|path| path.to_owned()))
+                      .collect::<Vec<String>>();
+    paths.sort();
+
+    let mut resources = vec![];
+    for path in paths {
+      resources.extend(try!(load::<R>(&path)));
+    }
+
+    return Ok(resources);
+  }
+
+  let mut source = String::new();
+  try!(File::open(path).and_then(|mut file| file.read_to_string(&mut source)).map_err(|err| format!("{}: {}", path, err)));
+
+  let yaml = path.ends_with(".yaml") || path.ends_with(".yml");
+  parse::<R>(&source, yaml).map_err(|err| format!("{}: {}", path, err))
+}
+
+/// Return the resources listed in `source`, given as YAML if `yaml`, as JSON otherwise.
+pub fn parse<R: Resource>(source: &str, yaml: bool) -> Result<Vec<R>, String> {
+  let json = if yaml {
+    let documents = try!(YamlLoader::load_from_str(source).map_err(|err| err.to_string()));
+    match documents.first() {
+      Some(document) => try!(yaml_to_json(document)),
+      None           => JsonValue::Array(vec![])
+    }
+  }
+  else {
+    try!(serde_json::from_str::<JsonValue>(source).map_err(|err| err.to_string()))
+  };
+
+  serde_json::from_value::<Vec<R>>(json).map_err(|err| err.to_string())
+}
+
+fn yaml_to_json(yaml: &Yaml) -> Result<JsonValue, String> {
+  Ok(match *yaml {
+    Yaml::Null                => JsonValue::Null,
+    Yaml::Boolean(b)          => JsonValue::Bool(b),
+    Yaml::Integer(i) if i < 0 => JsonValue::I64(i),
+    Yaml::Integer(i)          => JsonValue::U64(i as u64),
+    Yaml::Real(ref r)         => JsonValue::F64(try!(r.parse().map_err(|_| format!("invalid float {}", r)))),
+    Yaml::String(ref s)       => JsonValue::String(s.to_owned()),
+    Yaml::Array(ref values)   => JsonValue::Array(try!(values.iter().map(yaml_to_json).collect::<Result<Vec<JsonValue>, String>>())),
+    Yaml::Hash(ref hash)      => {
+      let mut object = BTreeMap::new();
+      for (key, value) in hash {
+        let key = try!(key.as_str().ok_or(format!("expected a string as key, got {:?}", key)));
+        object.insert(key.to_owned(), try!(yaml_to_json(value)));
+      }
+      JsonValue::Object(object)
+    },
+    _ => return Err(format!("unsupported value {:?}", yaml))
+  })
+}
+
+/// Index the resources of the fixtures at `path` into the index of `R`,
+/// returning whether all of them have been indexed.
+pub fn seed<R: Resource>(config: Config, path: &str) -> bool {
+  start_logging(&config).unwrap();
+  settings::apply(&config);
+
+  cluster::bootstrap(&config);
+
+  let cluster = Arc::new(Cluster::new(&config.es.urls(), config.es.connections));
+  es::configure_compatibility(&config.es.compatibility, &cluster.url());
+  let index   = config.index_of(R::name());
+
+  let resources = match load::<R>(path) {
+    Ok(resources) => resources,
+    Err(err)      => {
+      error!("The fixtures cannot be read: {}", err);
+      return false;
+    }
+  };
+
+  let count = resources.len();
+  match bulk::index_many(cluster, &index, resources, config.es.max_concurrent_bulks) {
+    Ok(results) => {
+      let refused = results.iter().flat_map(|result| result.items.iter()).filter(|item| item.inner.status >= 300).count();
+      info!("{} fixtures of {} have been indexed into {}, {} refused.", count - refused, path, index, refused);
+      refused == 0
+    },
+    Err(err) => {
+      error!("The fixtures of {} have not been indexed: {}", path, err);
+      false
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use fixtures::{load, parse};
+  use resources::Talent;
+
+  #[test]
+  fn test_load() {
+    let talents = load::<Talent>("fixtures").unwrap();
+    assert_eq!(talents.iter().map(|talent| talent.id).collect::<Vec<u64>>(), vec![1, 2, 3, 4, 5]);
+
+    assert!(load::<Talent>("fixtures/missing.json").unwrap_err().starts_with("fixtures/missing.json: "));
+  }
+
+  #[test]
+  fn test_parse_yaml() {
+    let yaml = "
+- id: 42
+  accepted: true
+  desired_work_roles: [Fullstack]
+  desired_work_roles_vanilla: ~
+  desired_work_roles_experience: []
+  professional_experience: 2..6
+  work_locations: [Berlin]
+  current_location: Berlin
+  work_authorization: yes
+  skills: [Rust]
+  summary: ''
+  headline: Backend developer
+  work_experiences: []
+  contacted_company_ids: []
+  batch_starts_at: 2016-03-04T12:24:00+01:00
+  batch_ends_at: 2016-04-11T12:24:00+02:00
+  added_to_batch_at: 2016-03-11T12:24:37+01:00
+  weight: -5
+  blocked_companies: []
+  avatar_url: ''
+  salary_expectations: [{minimum: 40000, maximum: ~, currency: EUR, city: Berlin}]
+  latest_position: ''
+  languages: [English]
+";
+
+    let talents = parse::<Talent>(yaml, true).unwrap();
+    assert_eq!(talents.len(), 1);
+    assert_eq!(talents[0].weight, -5);
+    assert_eq!(talents[0].salary_expectations[0].minimum, Some(40000));
+    assert!(parse::<Talent>("[{\"id\": 42}]", false).is_err());
+  }
+}
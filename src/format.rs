@@ -0,0 +1,218 @@
+//! The formats in which the bodies of requests and responses can be encoded.
+//!
+//! MessagePack is packed and unpacked through the JSON values of `serde_json`
+//! with the plain codec of `rmp`, since every release of `rmp-serde` supporting
+//! the version of `serde` in use has been yanked.
+
+use serde::de::Deserialize;
+use serde::ser::Serialize;
+use serde_json;
+use serde_json::Value as JsonValue;
+
+use rmp::{encode, Marker};
+
+use std::collections::BTreeMap;
+use std::io::Read;
+
+use iron::mime::Mime;
+
+/// `JSON` is used unless `MessagePack` is explicitly asked for.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+  Json,
+  MessagePack
+}
+
+const MSGPACK_TYPES: [&'static str; 2] = ["application/msgpack", "application/x-msgpack"];
+
+impl Format {
+  /// Return the format asked for by the `Accept` header.
+  pub fn from_accept(accept: Option<&str>) -> Format {
+    let accepted = accept.unwrap_or("")
+                         .split(',')
+                         .map(|item| item.split(';').next().unwrap_or("").trim())
+                         .any(|item| MSGPACK_TYPES.contains(&item));
+
+    if accepted { Format::MessagePack } else { Format::Json }
+  }
+
+  /// Return the format of a request body according to its `Content-Type` header.
+  pub fn from_content_type(content_type: Option<&str>) -> Format {
+    let content_type = content_type.unwrap_or("").split(';').next().unwrap_or("").trim();
+
+    if MSGPACK_TYPES.contains(&content_type) { Format::MessagePack } else { Format::Json }
+  }
+
+  pub fn mime(&self) -> Mime {
+    match *self {
+      Format::Json        => "application/json".parse::<Mime>().unwrap(),
+      Format::MessagePack => "application/msgpack".parse::<Mime>().unwrap()
+    }
+  }
+
+  pub fn serialize<T: Serialize>(&self, value: &T) -> Result<Vec<u8>, String> {
+    match *self {
+      Format::Json => serde_json::to_vec(value).map_err(|e| e.to_string()),
+      Format::MessagePack => {
+        let mut buffer = vec![];
+        try!(pack(&serde_json::to_value(value), &mut buffer));
+        Ok(buffer)
+      }
+    }
+  }
+
+  pub fn deserialize<T: Deserialize>(&self, body: &[u8]) -> Result<T, String> {
+    match *self {
+      Format::Json        => serde_json::from_slice(body).map_err(|e| e.to_string()),
+      Format::MessagePack => {
+        let mut body = body;
+        let value    = try!(unpack(&mut body));
+        serde_json::from_value(value).map_err(|e| e.to_string())
+      }
+    }
+  }
+}
+
+/// Write `value` into `buffer` as MessagePack.
+fn pack(value: &JsonValue, buffer: &mut Vec<u8>) -> Result<(), String> {
+  match *value {
+    JsonValue::Null              => encode::write_nil(buffer).map_err(|e| e.to_string()),
+    JsonValue::Bool(value)       => encode::write_bool(buffer, value).map_err(|e| e.to_string()),
+    JsonValue::I64(value)        => encode::write_sint(buffer, value).map(|_| ()).map_err(|e| e.to_string()),
+    JsonValue::U64(value)        => encode::write_uint(buffer, value).map(|_| ()).map_err(|e| e.to_string()),
+    JsonValue::F64(value)        => encode::write_f64(buffer, value).map_err(|e| e.to_string()),
+    JsonValue::String(ref value) => encode::write_str(buffer, value).map_err(|e| e.to_string()),
+    JsonValue::Array(ref values) => {
+      try!(encode::write_array_len(buffer, values.len() as u32).map_err(|e| e.to_string()));
+      for value in values {
+        try!(pack(value, buffer));
+      }
+      Ok(())
+    },
+    JsonValue::Object(ref entries) => {
+      try!(encode::write_map_len(buffer, entries.len() as u32).map_err(|e| e.to_string()));
+      for (key, value) in entries {
+        try!(encode::write_str(buffer, key).map_err(|e| e.to_string()));
+        try!(pack(value, buffer));
+      }
+      Ok(())
+    }
+  }
+}
+
+/// Read the next value packed as MessagePack from `body`.
+/// The binaries and the extensions are refused, having no JSON counterpart.
+fn unpack(body: &mut &[u8]) -> Result<JsonValue, String> {
+  let marker = Marker::from_u8(try!(read_uint(body, 1)) as u8);
+
+  match marker {
+    Marker::Null          => Ok(JsonValue::Null),
+    Marker::True          => Ok(JsonValue::Bool(true)),
+    Marker::False         => Ok(JsonValue::Bool(false)),
+    Marker::FixPos(n)     => Ok(JsonValue::U64(n as u64)),
+    Marker::FixNeg(n)     => Ok(JsonValue::I64(n as i64)),
+    Marker::U8            => read_uint(body, 1).map(JsonValue::U64),
+    Marker::U16           => read_uint(body, 2).map(JsonValue::U64),
+    Marker::U32           => read_uint(body, 4).map(JsonValue::U64),
+    Marker::U64           => read_uint(body, 8).map(JsonValue::U64),
+    Marker::I8            => read_uint(body, 1).map(|n| JsonValue::I64(n as u8 as i8 as i64)),
+    Marker::I16           => read_uint(body, 2).map(|n| JsonValue::I64(n as u16 as i16 as i64)),
+    Marker::I32           => read_uint(body, 4).map(|n| JsonValue::I64(n as u32 as i32 as i64)),
+    Marker::I64           => read_uint(body, 8).map(|n| JsonValue::I64(n as i64)),
+    Marker::F32           => read_uint(body, 4).map(|n| JsonValue::F64(f32::from_bits(n as u32) as f64)),
+    Marker::F64           => read_uint(body, 8).map(|n| JsonValue::F64(f64::from_bits(n))),
+    Marker::FixStr(len)   => read_str(body, len as u64),
+    Marker::Str8          => { let len = try!(read_uint(body, 1)); read_str(body, len) },
+    Marker::Str16         => { let len = try!(read_uint(body, 2)); read_str(body, len) },
+    Marker::Str32         => { let len = try!(read_uint(body, 4)); read_str(body, len) },
+    Marker::FixArray(len) => read_array(body, len as u64),
+    Marker::Array16       => { let len = try!(read_uint(body, 2)); read_array(body, len) },
+    Marker::Array32       => { let len = try!(read_uint(body, 4)); read_array(body, len) },
+    Marker::FixMap(len)   => read_map(body, len as u64),
+    Marker::Map16         => { let len = try!(read_uint(body, 2)); read_map(body, len) },
+    Marker::Map32         => { let len = try!(read_uint(body, 4)); read_map(body, len) },
+    marker                => Err(format!("Unsupported MessagePack value ({:?}).", marker))
+  }
+}
+
+/// Read an unsigned integer of `size` bytes, big endian, from `body`.
+fn read_uint(body: &mut &[u8], size: usize) -> Result<u64, String> {
+  let mut bytes = [0; 8];
+  try!(body.read_exact(&mut bytes[..size]).map_err(|_| "Unexpected end of the MessagePack body.".to_owned()));
+  Ok(bytes[..size].iter().fold(0, |n, &byte| n << 8 | byte as u64))
+}
+
+fn read_str(body: &mut &[u8], len: u64) -> Result<JsonValue, String> {
+  if len > body.len() as u64 {
+    return Err("Unexpected end of the MessagePack body.".to_owned());
+  }
+
+  let (string, rest) = body.split_at(len as usize);
+  *body = rest;
+  String::from_utf8(string.to_vec()).map(JsonValue::String).map_err(|e| e.to_string())
+}
+
+fn read_array(body: &mut &[u8], len: u64) -> Result<JsonValue, String> {
+  // every value takes at least a byte, the length cannot be trusted otherwise
+  let mut values = Vec::with_capacity((len as usize).min(body.len()));
+  for _ in 0..len {
+    values.push(try!(unpack(body)));
+  }
+  Ok(JsonValue::Array(values))
+}
+
+fn read_map(body: &mut &[u8], len: u64) -> Result<JsonValue, String> {
+  let mut entries = BTreeMap::new();
+  for _ in 0..len {
+    let key = match try!(unpack(body)) {
+      JsonValue::String(key) => key,
+      key                    => return Err(format!("The keys of the MessagePack maps must be strings, got {}.", key))
+    };
+    entries.insert(key, try!(unpack(body)));
+  }
+  Ok(JsonValue::Object(entries))
+}
+
+#[cfg(test)]
+mod tests {
+  use format::Format;
+
+  use serde_json;
+  use serde_json::Value as JsonValue;
+
+  #[test]
+  fn test_negotiation() {
+    assert_eq!(Format::from_accept(Some("application/msgpack")), Format::MessagePack);
+    assert_eq!(Format::from_accept(Some("application/json, application/x-msgpack;q=0.9")), Format::MessagePack);
+    assert_eq!(Format::from_accept(Some("*/*")), Format::Json);
+    assert_eq!(Format::from_accept(None), Format::Json);
+
+    assert_eq!(Format::from_content_type(Some("application/msgpack")), Format::MessagePack);
+    assert_eq!(Format::from_content_type(Some("application/json; charset=utf-8")), Format::Json);
+  }
+
+  #[test]
+  fn test_roundtrip() {
+    let ids = vec![1, 2, 3];
+
+    for format in vec![Format::Json, Format::MessagePack] {
+      let body = format.serialize(&ids).unwrap();
+      assert_eq!(format.deserialize::<Vec<u32>>(&body).unwrap(), ids);
+    }
+
+    // `[1, 2, 3]` packed as a fixarray of positive fixints
+    assert_eq!(Format::MessagePack.serialize(&ids).unwrap(), vec![0x93, 0x01, 0x02, 0x03]);
+
+    let value = serde_json::from_str::<JsonValue>("{\"id\": 70000, \"weight\": -5, \"score\": 0.5, \"accepted\": true, \
+                                                   \"skills\": [\"Rust\", \"Go\"], \"avatar_url\": null}").unwrap();
+    let body  = Format::MessagePack.serialize(&value).unwrap();
+    assert_eq!(Format::MessagePack.deserialize::<JsonValue>(&body).unwrap(), value);
+  }
+
+  #[test]
+  fn test_malformed_msgpack() {
+    assert!(Format::MessagePack.deserialize::<JsonValue>(&[0x93, 0x01]).is_err());
+    assert!(Format::MessagePack.deserialize::<JsonValue>(&[0xc4, 0x01, 0x00]).is_err());
+    assert!(Format::MessagePack.deserialize::<JsonValue>(&[0x81, 0x01, 0x02]).is_err());
+  }
+}
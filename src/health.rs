@@ -0,0 +1,256 @@
+use es;
+
+use serde_json::Value as JsonValue;
+use rs_es::error::EsError;
+use rs_es::operations::mapping::Mapping;
+
+/// The health of the ElasticSearch cluster and of the index
+/// used by Searchspot.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Health {
+  pub status:            String,
+  pub es_cluster_status: Option<String>,
+  pub es_nodes:          Option<u64>, // the nodes which have joined the cluster
+  pub unreachable_nodes: usize, // the nodes Searchspot cannot reach
+  pub index_doc_count:   Option<u64>
+}
+
+impl Health {
+  /// Ping the cluster listening on `url` and count the documents inside `index`.
+  pub fn check(url: &str, index: &str) -> Health {
+    let cluster_health = es::get(url, "_cluster/health").ok();
+    let cluster_status = cluster_health.as_ref().and_then(|health| {
+      health.find("status").and_then(|s| s.as_str()).map(|s| s.to_owned())
+    });
+
+    let index_doc_count = es::get(url, &format!("{}/_count", index)).ok().and_then(|count| {
+      count.find("count").and_then(|c| c.as_u64())
+    });
+
+    let mut health = Health::new(cluster_status, index_doc_count);
+    health.es_nodes = cluster_health.as_ref().and_then(|health| health.find("number_of_nodes")).and_then(|n| n.as_u64());
+    health
+  }
+
+  /// Build a `Health` whose `status` is `ok` when the cluster is green,
+  /// `degraded` when it is yellow and `unavailable` when it is red,
+  /// unreachable or the index does not exist.
+  pub fn new(cluster_status: Option<String>, index_doc_count: Option<u64>) -> Health {
+    let status = match (cluster_status.as_ref().map(String::as_str), index_doc_count) {
+      (Some("green"),  Some(_)) => "ok",
+      (Some("yellow"), Some(_)) => "degraded",
+      _                         => "unavailable"
+    };
+
+    Health {
+      status:            status.to_owned(),
+      es_cluster_status: cluster_status,
+      es_nodes:          None,
+      unreachable_nodes: 0,
+      index_doc_count:   index_doc_count
+    }
+  }
+
+  /// Report the nodes that cannot be reached, a healthy cluster
+  /// being `degraded` while any of them is unreachable.
+  pub fn with_unreachable_nodes(mut self, unreachable: usize) -> Health {
+    if unreachable > 0 && self.status == "ok" {
+      self.status = "degraded".to_owned();
+    }
+
+    self.unreachable_nodes = unreachable;
+    self
+  }
+
+  /// Return `true` if searches can be performed.
+  pub fn is_available(&self) -> bool {
+    self.status != "unavailable"
+  }
+}
+
+/// Tell whether Searchspot is ready to serve the searches, that is
+/// ElasticSearch is available and the index has the expected mapping.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Readiness {
+  pub ready:    bool,
+  pub problems: Vec<String>
+}
+
+impl Readiness {
+  /// Check the cluster listening on `url` and compare the live
+  /// mapping of `index` with the `expected` one.
+  pub fn check(url: &str, index: &str, expected: &Mapping) -> Readiness {
+    let health = Health::check(url, index);
+    if !health.is_available() {
+      return Readiness {
+        ready:    false,
+        problems: vec![format!("ElasticSearch is {}.", health.status)]
+      };
+    }
+
+    let problems = match check_mapping(url, index, expected) {
+      Ok(problems) => problems,
+      Err(e)       => vec![format!("Cannot fetch the mapping of `{}`: {}", index, e)]
+    };
+
+    Readiness {
+      ready:    problems.is_empty(),
+      problems: problems
+    }
+  }
+}
+
+/// Fetch the live mapping of `index` from the cluster listening on `url`
+/// and compare it with the `expected` one (see `mapping_problems`).
+pub fn check_mapping(url: &str, index: &str, expected: &Mapping) -> Result<Vec<String>, EsError> {
+  es::get(url, &es::mapping_path(index)).map(|live| mapping_problems(&live, expected))
+}
+
+/// Compare the `live` mapping returned by `GET /:index/_mapping` with
+/// the `expected` one, returning a description for every field that is
+/// either missing or has a different type.
+pub fn mapping_problems(live: &JsonValue, expected: &Mapping) -> Vec<String> {
+  let mut problems = vec![];
+
+  // the response is keyed by the real index name, which differs if we're using an alias
+  let mappings = live.as_object()
+                     .and_then(|indexes| indexes.values().next())
+                     .and_then(|index| index.find("mappings"));
+
+  for (doc_type, fields) in expected.iter() {
+    let properties = mappings.and_then(|m| m.find(doc_type))
+                             .and_then(|t| t.find("properties"));
+
+    let properties = match properties {
+      Some(properties) => properties,
+      None => {
+        problems.push(format!("`{}` is not mapped.", doc_type));
+        continue;
+      }
+    };
+
+    for (field, options) in fields.iter() {
+      let expected_type = match options.get("type") {
+        Some(expected_type) => expected_type,
+        None                => continue
+      };
+
+      match properties.find(field).and_then(|f| f.find("type")).and_then(|t| t.as_str()) {
+        Some(live_type) if live_type == *expected_type => (),
+        Some(live_type) => problems.push(
+          format!("`{}.{}` is mapped as `{}` instead of `{}`.", doc_type, field, live_type, expected_type)),
+        None => problems.push(format!("`{}.{}` is not mapped.", doc_type, field))
+      }
+    }
+  }
+
+  problems.sort();
+  problems
+}
+
+#[cfg(test)]
+mod tests {
+  use health::{Health, mapping_problems};
+  use serde_json;
+
+  #[test]
+  fn test_new() {
+    // a green cluster with an existing index is ok
+    {
+      let health = Health::new(Some("green".to_owned()), Some(4));
+      assert_eq!(health.status, "ok");
+      assert!(health.is_available());
+    }
+
+    // a yellow cluster with an existing index is degraded
+    {
+      let health = Health::new(Some("yellow".to_owned()), Some(0));
+      assert_eq!(health.status, "degraded");
+      assert!(health.is_available());
+    }
+
+    // a red cluster is unavailable
+    {
+      let health = Health::new(Some("red".to_owned()), Some(4));
+      assert_eq!(health.status, "unavailable");
+      assert!(!health.is_available());
+    }
+
+    // a missing index is unavailable
+    {
+      let health = Health::new(Some("green".to_owned()), None);
+      assert_eq!(health.status, "unavailable");
+    }
+
+    // an unreachable cluster is unavailable
+    {
+      let health = Health::new(None, None);
+      assert_eq!(health.status, "unavailable");
+      assert_eq!(health.es_cluster_status, None);
+    }
+
+    // a green cluster with unreachable nodes is degraded
+    {
+      let health = Health::new(Some("green".to_owned()), Some(4)).with_unreachable_nodes(1);
+      assert_eq!(health.status, "degraded");
+      assert_eq!(health.unreachable_nodes, 1);
+      assert_eq!(Health::new(Some("red".to_owned()), Some(4)).with_unreachable_nodes(1).status, "unavailable");
+    }
+  }
+
+  #[test]
+  fn test_mapping_problems() {
+    let expected = hashmap! {
+      "talent" => hashmap! {
+        "id" => hashmap! {
+          "type"  => "integer",
+          "index" => "not_analyzed"
+        },
+
+        "skills" => hashmap! {
+          "type"     => "string",
+          "analyzer" => "trigrams"
+        },
+
+        "weight" => hashmap! {
+          "type" => "integer"
+        }
+      }
+    };
+
+    // a compatible mapping has no problems
+    {
+      let live = serde_json::from_str(r#"{
+        "talents_v2": { "mappings": { "talent": { "properties": {
+          "id":     { "type": "integer" },
+          "skills": { "type": "string", "analyzer": "trigrams" },
+          "weight": { "type": "integer" },
+          "extra":  { "type": "string" }
+        }}}}
+      }"#).unwrap();
+
+      assert!(mapping_problems(&live, &expected).is_empty());
+    }
+
+    // missing and differently typed fields are reported
+    {
+      let live = serde_json::from_str(r#"{
+        "talents": { "mappings": { "talent": { "properties": {
+          "id":     { "type": "long" },
+          "skills": { "type": "string" }
+        }}}}
+      }"#).unwrap();
+
+      assert_eq!(mapping_problems(&live, &expected), vec![
+        "`talent.id` is mapped as `long` instead of `integer`.".to_owned(),
+        "`talent.weight` is not mapped.".to_owned()
+      ]);
+    }
+
+    // a missing type is reported
+    {
+      let live = serde_json::from_str(r#"{ "talents": { "mappings": {} } }"#).unwrap();
+      assert_eq!(mapping_problems(&live, &expected), vec!["`talent` is not mapped.".to_owned()]);
+    }
+  }
+}
@@ -0,0 +1,194 @@
+//! The import of a NDJSON file (one document per line) into the index of the
+//! resource, run by `searchspot --import <file>` for the initial backfills and
+//! to restore an index from a backup (see `dump` to restore it into another index).
+//!
+//! The documents are validated as the ones sent to `POST /talents/bulk`, then
+//! indexed through the same bulk pipeline. The rejected lines are written to
+//! the error file as `{"line": 12, "error": "...", "document": "<the line>"}`,
+//! so that they can be fixed and imported again.
+
+use serde_json;
+
+use config::Config;
+use resource::Resource;
+use cluster;
+use cluster::Cluster;
+use error::Error;
+use bulk;
+use es;
+use settings;
+use logger::start_logging;
+use source;
+use dump;
+
+use std::fs::File;
+use std::io::{BufRead, Write};
+use std::sync::Arc;
+
+/// How many lines have been read, indexed and rejected.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Report {
+  pub read:     u64,
+  pub indexed:  u64,
+  pub rejected: u64
+}
+
+/// A line that has not been imported.
+#[derive(Serialize, Debug)]
+struct Rejected<'a> {
+  line:     u64,
+  error:    String,
+  document: &'a str
+}
+
+/// Read the documents of `input`, `size` at a time, giving each batch to `index`
+/// which returns why each document has not been indexed, if so. The documents that
+/// are not valid and the ones refused by `index` are written to `errors`.
+/// The import is interrupted, returning the error, if a batch cannot be indexed at all.
+pub fn load<R, B, W, F>(input: B, errors: &mut W, size: usize, mut index: F) -> Result<Report, String>
+  where R: Resource, B: BufRead, W: Write, F: FnMut(Vec<R>) -> Result<Vec<Option<String>>, Error> {
+  let mut report = Report::default();
+  let mut batch  = vec![];
+  let mut lines  = input.lines().enumerate().peekable();
+
+  while lines.peek().is_some() {
+    for (number, line) in lines.by_ref().take(size) {
+      let line = try!(line.map_err(|err| err.to_string()));
+      if line.trim().is_empty() {
+        continue;
+      }
+
+      report.read += 1;
+      match source::parse::<R>(&line) {
+        Ok(document) => batch.push((number as u64 + 1, line, document)),
+        Err(err)     => {
+          report.rejected += 1;
+          try!(reject(errors, number as u64 + 1, err, &line));
+        }
+      }
+    }
+
+    if batch.is_empty() {
+      continue;
+    }
+
+    let documents = batch.iter().map(|&(_, _, ref document)| document.to_owned()).collect::<Vec<R>>();
+    let outcomes  = try!(index(documents).map_err(|err| format!("The import has been interrupted after {} lines: {}", report.read, err)));
+
+    for ((number, line, _), outcome) in batch.drain(..).zip(outcomes.into_iter()) {
+      match outcome {
+        None      => report.indexed += 1,
+        Some(err) => {
+          report.rejected += 1;
+          try!(reject(errors, number, err, &line));
+        }
+      }
+    }
+
+    info!("{} lines have been read: {} indexed, {} rejected.", report.read, report.indexed, report.rejected);
+  }
+
+  Ok(report)
+}
+
+/// Write the line `number` to `errors`, along with the reason it has been rejected.
+fn reject<W: Write>(errors: &mut W, number: u64, error: String, document: &str) -> Result<(), String> {
+  let rejected = Rejected { line: number, error: error, document: document };
+  writeln!(errors, "{}", serde_json::to_string(&rejected).unwrap()).map_err(|err| err.to_string())
+}
+
+/// Index the documents of `input` into `index` through the bulk pipeline, writing the rejected ones to `errors`.
+pub fn index_lines<R, B, W>(cluster: Arc<Cluster>, index: &str, input: B, errors: &mut W, max_concurrent: usize) -> Result<Report, String>
+  where R: Resource, B: BufRead, W: Write {
+  // enough documents to keep every concurrent bulk request busy
+  let size = bulk::CHUNK_SIZE * max_concurrent.max(1);
+
+  load::<R, _, _, _>(input, errors, size, |documents| {
+    let results = try!(bulk::index_many(cluster.clone(), index, documents, max_concurrent));
+
+    // ElasticSearch returns the items in the same order they have been sent
+    Ok(results.into_iter()
+              .flat_map(|result| result.items.into_iter())
+              .map(|item| if item.inner.status < 300 { None } else { Some(format!("ElasticSearch has refused it ({}).", item.inner.status)) })
+              .collect())
+  })
+}
+
+/// Import the documents of the NDJSON file at `path` (gzipped if named `*.gz`) into the index of `R`,
+/// writing the rejected ones to `errors_path`. Return whether every one of them has been indexed.
+pub fn import<R: Resource>(config: Config, path: &str, errors_path: &str) -> bool {
+  start_logging(&config).unwrap();
+  settings::apply(&config);
+
+  cluster::bootstrap(&config);
+
+  let cluster = Arc::new(Cluster::new(&config.es.urls(), config.es.connections));
+  es::configure_compatibility(&config.es.compatibility, &cluster.url());
+  let index   = config.index_of(R::name());
+
+  let files = dump::open(path).and_then(|input| File::create(errors_path).map(|errors| (input, errors)).map_err(|err| format!("{}: {}", errors_path, err)));
+  let (input, mut errors) = match files {
+    Ok(files) => files,
+    Err(err)  => {
+      error!("{} cannot be imported: {}", path, err);
+      return false;
+    }
+  };
+
+  info!("Importing {} into {}, the rejected lines are written to {}.", path, index, errors_path);
+
+  match index_lines::<R, _, _>(cluster, &index, input, &mut errors, config.es.max_concurrent_bulks) {
+    Ok(report) => {
+      info!("{} has been imported: {} indexed, {} rejected.", path, report.indexed, report.rejected);
+      report.rejected == 0
+    },
+    Err(err) => {
+      error!("{}", err);
+      false
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use import::{load, Report};
+  use resources::Talent;
+  use error::Error;
+  use test_support::talent;
+
+  use serde_json;
+
+  use std::io::Cursor;
+  use std::str;
+
+  /// Return the line of the talent `id` (see `test_support::talent`).
+  fn line(id: u64) -> String {
+    serde_json::to_string(&talent(id)).unwrap()
+  }
+
+  #[test]
+  fn test_load() {
+    let input = format!("{}\nnot json\n\n{}\n{}\n", line(1), line(2), line(3));
+
+    let mut errors  = Vec::<u8>::new();
+    let mut batches = vec![];
+    let report = load::<Talent, _, _, _>(Cursor::new(input), &mut errors, 2, |documents| {
+      batches.push(documents.len());
+      Ok(documents.into_iter().map(|talent| if talent.id == 3 { Some("refused".to_owned()) } else { None }).collect())
+    }).unwrap();
+
+    assert_eq!(report, Report { read: 4, indexed: 2, rejected: 2 });
+    assert_eq!(batches, vec![1, 1, 1]);
+
+    let errors = str::from_utf8(&errors).unwrap().lines().collect::<Vec<&str>>();
+    assert_eq!(errors.len(), 2);
+    assert!(errors[0].starts_with("{\"line\":2,"));
+    assert!(errors[1].starts_with("{\"line\":5,\"error\":\"refused\","));
+  }
+
+  #[test]
+  fn test_load_interrupted() {
+    let result = load::<Talent, _, _, _>(Cursor::new(line(1)), &mut Vec::<u8>::new(), 2, |_| Err(Error::Timeout("timed out".to_owned())));
+    assert_eq!(result, Err("The import has been interrupted after 1 lines: timed out".to_owned()));
+  }
+}
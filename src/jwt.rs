@@ -0,0 +1,158 @@
+//! Validation of the JSON Web Tokens signed with HS256.
+
+use serde_json;
+
+use crypto::hmac::Hmac;
+use crypto::sha2::Sha256;
+use crypto::mac::Mac;
+use crypto::util::fixed_time_eq;
+
+use rustc_serialize::base64::{FromBase64, ToBase64, URL_SAFE};
+
+/// The permissions that can be granted to a token.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Scope {
+  Search,
+  Write,
+  Admin,
+  Pii // reads the personal data unredacted (see `redaction`)
+}
+
+impl Scope {
+  pub fn as_str(&self) -> &'static str {
+    match *self {
+      Scope::Search => "search",
+      Scope::Write  => "write",
+      Scope::Admin  => "admin",
+      Scope::Pii    => "pii"
+    }
+  }
+}
+
+/// The claims that Searchspot cares about.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Claims {
+  pub sub:        Option<String>,
+  pub exp:        Option<i64>,
+  #[serde(default)]
+  pub scope:      String,
+  /// The company the token has been issued to, whose searches count towards its quotas.
+  #[serde(default)]
+  pub company_id: Option<String>
+}
+
+impl Claims {
+  /// Return `true` if `scope` is contained in the space-separated `scope` claim.
+  pub fn has_scope(&self, scope: Scope) -> bool {
+    self.scope.split_whitespace().any(|s| s == scope.as_str())
+  }
+}
+
+#[derive(Deserialize)]
+struct Header {
+  alg: String
+}
+
+fn signature(secret: &str, payload: &str) -> Vec<u8> {
+  let mut hmac = Hmac::new(Sha256::new(), secret.as_bytes());
+  hmac.input(payload.as_bytes());
+  hmac.result().code().to_vec()
+}
+
+/// Verify the signature and the expiration of `token`, returning its claims.
+pub fn decode(token: &str, secret: &str, now: i64) -> Result<Claims, String> {
+  let parts = token.split('.').collect::<Vec<&str>>();
+  if parts.len() != 3 {
+    return Err("The token is malformed.".to_owned());
+  }
+
+  let header = try!(parts[0].from_base64().map_err(|e| e.to_string()));
+  let header: Header = try!(serde_json::from_slice(&header).map_err(|e| e.to_string()));
+  if header.alg != "HS256" {
+    return Err(format!("The algorithm `{}` is not supported.", header.alg));
+  }
+
+  let given    = try!(parts[2].from_base64().map_err(|e| e.to_string()));
+  let expected = signature(secret, &format!("{}.{}", parts[0], parts[1]));
+  if !fixed_time_eq(&given, &expected) {
+    return Err("The token signature is not valid.".to_owned());
+  }
+
+  let claims = try!(parts[1].from_base64().map_err(|e| e.to_string()));
+  let claims: Claims = try!(serde_json::from_slice(&claims).map_err(|e| e.to_string()));
+
+  match claims.exp {
+    Some(exp) if exp <= now => Err("The token has expired.".to_owned()),
+    _                       => Ok(claims)
+  }
+}
+
+/// Return a token containing `claims` signed with `secret`.
+pub fn encode(claims: &Claims, secret: &str) -> String {
+  let header  = r#"{"alg":"HS256","typ":"JWT"}"#.as_bytes().to_base64(URL_SAFE);
+  let payload = serde_json::to_string(claims).unwrap().as_bytes().to_base64(URL_SAFE);
+  let signing = format!("{}.{}", header, payload);
+
+  format!("{}.{}", signing, signature(secret, &signing).to_base64(URL_SAFE))
+}
+
+#[cfg(test)]
+mod tests {
+  use jwt::*;
+
+  fn claims(scope: &str, exp: Option<i64>) -> Claims {
+    Claims {
+      sub:        Some("honeypot".to_owned()),
+      exp:        exp,
+      scope:      scope.to_owned(),
+      company_id: None
+    }
+  }
+
+  #[test]
+  fn test_decode() {
+    let now = 1483228800;
+
+    // a valid token returns its claims
+    {
+      let token  = encode(&claims("search write", Some(now + 60)), "secret");
+      let claims = decode(&token, "secret", now).unwrap();
+      assert!(claims.has_scope(Scope::Search));
+      assert!(claims.has_scope(Scope::Write));
+      assert!(!claims.has_scope(Scope::Admin));
+    }
+
+    // a token without expiration never expires
+    {
+      let token = encode(&claims("admin", None), "secret");
+      assert!(decode(&token, "secret", now).unwrap().has_scope(Scope::Admin));
+    }
+
+    // an expired token is rejected
+    {
+      let token = encode(&claims("search", Some(now - 1)), "secret");
+      assert!(decode(&token, "secret", now).is_err());
+    }
+
+    // a token signed with another secret is rejected
+    {
+      let token = encode(&claims("search", None), "terces");
+      assert!(decode(&token, "secret", now).is_err());
+    }
+
+    // a tampered token is rejected
+    {
+      let token  = encode(&claims("search", None), "secret");
+      let forged = encode(&claims("admin", None), "secret");
+      let parts  = token.split('.').collect::<Vec<&str>>();
+      let forged = forged.split('.').collect::<Vec<&str>>();
+      assert!(decode(&format!("{}.{}.{}", parts[0], forged[1], parts[2]), "secret", now).is_err());
+    }
+
+    // garbage is rejected
+    {
+      assert!(decode("lololol", "secret", now).is_err());
+      assert!(decode("a.b.c", "secret", now).is_err());
+    }
+  }
+}
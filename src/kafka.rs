@@ -0,0 +1,74 @@
+//! The Kafka source of the indexing events, an alternative to the AMQP queue
+//! read by `consumer`: the events of `kafka.topic` are consumed as part of the
+//! consumer group `kafka.group`, whose offsets are stored by Kafka.
+//!
+//! The offsets are committed only once the events fetched have been either
+//! applied or rejected, so that the events are applied at least once: the ones
+//! that fail are applied again, before moving on, until ElasticSearch is back.
+//! The topic is still polled meanwhile, so that the connection to the brokers is
+//! kept alive, until `MAX_PENDING` events are waiting to be applied.
+
+use kafka_client::consumer::{Consumer, FetchOffset, GroupOffsetStorage};
+use kafka_client::error::Error as KafkaError;
+
+use config::Config;
+use resource::Resource;
+use cluster::Cluster;
+use consumer::{handle, Outcome};
+
+use std::collections::VecDeque;
+use std::sync::Arc;
+
+/// How many fetched events can wait to be applied before the topic stops being polled.
+const MAX_PENDING: usize = 1000;
+
+/// An event fetched from `topic`, waiting to be applied.
+struct Pending {
+  topic:     String,
+  partition: i32,
+  offset:    i64,
+  value:     Vec<u8>
+}
+
+/// Consume the events of `kafka.topic` as long as the brokers are reachable.
+pub fn run<R: Resource>(config: &Config, cluster: Arc<Cluster>) -> Result<(), KafkaError> {
+  let ref kafka = config.kafka;
+
+  let mut consumer = try!(Consumer::from_hosts(kafka.hosts.to_owned())
+                                   .with_topic(kafka.topic.to_owned())
+                                   .with_group(kafka.group.to_owned())
+                                   .with_fallback_offset(FetchOffset::Earliest)
+                                   .with_offset_storage(GroupOffsetStorage::Kafka)
+                                   .create());
+
+  info!("Consuming the events of {} as {}.", kafka.topic, kafka.group);
+
+  let mut pending = VecDeque::new();
+  loop {
+    if pending.len() < MAX_PENDING {
+      for set in try!(consumer.poll()).iter() {
+        for message in set.messages() {
+          pending.push_back(Pending {
+            topic:     set.topic().to_owned(),
+            partition: set.partition(),
+            offset:    message.offset,
+            value:     message.value.to_owned()
+          });
+        }
+      }
+    }
+
+    // the partitions are ordered, an event cannot be skipped: the one that has
+    // failed is applied again (once `handle` has backed off) after the next poll
+    while let Some(event) = pending.pop_front() {
+      if let Outcome::Failed(_) = handle::<R>("kafka", cluster.clone(), &config.es.index, &event.value) {
+        pending.push_front(event);
+        break;
+      }
+
+      try!(consumer.consume_message(&event.topic, event.partition, event.offset));
+    }
+
+    try!(consumer.commit_consumed());
+  }
+}
@@ -0,0 +1,28 @@
+//! The keywords given by the users, cleaned before being matched against the
+//! text fields, so that they cannot alter the structure of the query.
+
+/// Return `keywords` without the control characters and with the whitespaces
+/// collapsed into single spaces, trimmed. The result is empty if there are no
+/// keywords left to look for.
+pub fn sanitize(keywords: &str) -> String {
+  keywords.split(|c: char| c.is_whitespace() || c.is_control())
+          .filter(|word| !word.is_empty())
+          .collect::<Vec<&str>>()
+          .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+  use keywords::sanitize;
+
+  #[test]
+  fn test_sanitize() {
+    assert_eq!(sanitize("rust"), "rust");
+    assert_eq!(sanitize("  senior \t rust\n developer "), "senior rust developer");
+    assert_eq!(sanitize("rust\u{0}\u{1b}[31m"), "rust [31m");
+    assert_eq!(sanitize(" \n\t "), "");
+
+    // the syntax is kept, since `multi_match` reads it as text
+    assert_eq!(sanitize("\"c++\" OR title:/.*/"), "\"c++\" OR title:/.*/");
+  }
+}
@@ -0,0 +1,106 @@
+//! The leases on the jobs that a single instance must run at a time (i.e.
+//! the backups), stored as documents of `searchspot_leases`. A lease is taken
+//! by creating its document, and taken over once it has expired by replacing
+//! it only if nobody has done it meanwhile (through the optimistic concurrency
+//! of ElasticSearch), so that the instances agree on a single holder.
+
+use serde_json;
+use serde_json::Value as JsonValue;
+
+use chrono::{DateTime, UTC};
+use chrono::Duration as ChronoDuration;
+
+use rs_es::error::EsError;
+
+use es;
+
+use std::env;
+use std::process;
+use std::time::Duration;
+
+/// The index of the leases.
+pub const INDEX: &'static str = "searchspot_leases";
+
+/// The type of the documents of the leases.
+const DOC_TYPE: &'static str = "lease";
+
+/// Return the name of the current instance, as recorded by the leases it holds.
+pub fn holder() -> String {
+  format!("{}:{}", env::var("HOSTNAME").unwrap_or("localhost".to_owned()), process::id())
+}
+
+/// Take the lease `name` for `ttl` as `holder` on the cluster listening on `url`,
+/// returning whether it has been taken (or renewed, if it was held by `holder` already).
+/// It is refused while another instance holds it.
+pub fn acquire(url: &str, name: &str, holder: &str, ttl: Duration) -> Result<bool, String> {
+  let now  = UTC::now();
+  let path = es::document_path(INDEX, DOC_TYPE, name);
+  let body = serde_json::to_string(&btreemap! {
+    "holder"     => holder.to_owned(),
+    "expires_at" => (now + ChronoDuration::seconds(ttl.as_secs() as i64)).to_rfc3339()
+  }).unwrap();
+
+  let stored = match es::get(url, &path) {
+    Ok(stored)                                                    => stored,
+    Err(EsError::EsServerError(ref err)) if err.starts_with("404") => return created(es::put(url, &format!("{}?op_type=create", path), &body)),
+    Err(err)                                                      => return Err(err.to_string())
+  };
+
+  if stored.pointer("/_source/holder").and_then(|held| held.as_str()) != Some(holder) && !expired(&stored, now) {
+    return Ok(false);
+  }
+
+  created(es::put(url, &format!("{}?{}", path, condition(&stored)), &body))
+}
+
+/// Return whether the lease `stored` has expired at `now`, as it has if it cannot be read.
+fn expired(stored: &JsonValue, now: DateTime<UTC>) -> bool {
+  stored.pointer("/_source/expires_at")
+        .and_then(|expires_at| expires_at.as_str())
+        .and_then(|expires_at| DateTime::parse_from_rfc3339(expires_at).ok())
+        .map_or(true, |expires_at| expires_at.with_timezone(&UTC) <= now)
+}
+
+/// Return the query string replacing the lease `stored` only if it has not been replaced
+/// meanwhile: through its sequence number since ElasticSearch 6.7 (and on OpenSearch,
+/// which refuses the internal versions), through its version before.
+fn condition(stored: &JsonValue) -> String {
+  match (stored.find("_seq_no").and_then(|seq_no| seq_no.as_u64()), stored.find("_primary_term").and_then(|term| term.as_u64())) {
+    (Some(seq_no), Some(term)) => format!("if_seq_no={}&if_primary_term={}", seq_no, term),
+    _                          => format!("version={}", stored.find("_version").and_then(|version| version.as_u64()).unwrap_or(0))
+  }
+}
+
+/// Return whether the lease has been written, it has not if another instance has taken it first.
+fn created(result: Result<JsonValue, EsError>) -> Result<bool, String> {
+  match result {
+    Ok(_)                                                          => Ok(true),
+    Err(EsError::EsServerError(ref err)) if err.starts_with("409") => Ok(false),
+    Err(err)                                                       => Err(err.to_string())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use lease::{expired, condition};
+
+  use serde_json;
+
+  use chrono::{TimeZone, UTC};
+
+  #[test]
+  fn test_expired() {
+    let stored = serde_json::from_str("{\"_source\": {\"holder\": \"search1:42\", \"expires_at\": \"2017-03-04T12:24:00+00:00\"}}").unwrap();
+
+    assert!(!expired(&stored, UTC.ymd(2017, 3, 4).and_hms(12, 0, 0)));
+    assert!(expired(&stored, UTC.ymd(2017, 3, 4).and_hms(12, 24, 0)));
+    assert!(expired(&serde_json::from_str("{\"_source\": {}}").unwrap(), UTC.ymd(2017, 3, 4).and_hms(12, 0, 0)));
+  }
+
+  #[test]
+  fn test_condition() {
+    assert_eq!(condition(&serde_json::from_str("{\"_version\": 3, \"_seq_no\": 7, \"_primary_term\": 1}").unwrap()),
+               "if_seq_no=7&if_primary_term=1");
+    assert_eq!(condition(&serde_json::from_str("{\"_version\": 3}").unwrap()), "version=3");
+  }
+}
@@ -8,16 +8,39 @@ extern crate iron;
 extern crate logger as http_logger;
 extern crate router;
 extern crate params;
+extern crate chrono;
 extern crate persistent;
 extern crate unicase;
+extern crate hyper;
+extern crate hyper_openssl;
+extern crate openssl;
 
 extern crate rs_es;
 extern crate toml;
+extern crate yaml_rust;
 extern crate oath;
+extern crate crypto;
+extern crate rustc_serialize;
+extern crate flate2;
+extern crate uuid;
+extern crate rmp;
+extern crate chan_signal;
+#[macro_use] extern crate chan;
+extern crate num_cpus;
 extern crate rollbar;
 extern crate backtrace;
+extern crate amqp;
+extern crate kafka as kafka_client;
+extern crate redis;
+extern crate postgres;
+extern crate rusoto_core;
+extern crate rusoto_s3;
 #[macro_use] extern crate log;
 #[macro_use] extern crate maplit;
+#[cfg(test)]
+#[macro_use] extern crate proptest;
+#[cfg(test)]
+extern crate iron_test;
 
 // this macro is needed by resources/talent.rs
 // but moving it to resources/mod raises E0468
@@ -28,11 +51,81 @@ extern crate lazy_static;
 #[macro_use] pub mod macros;
 
 pub mod terms;
+pub mod keywords;
 pub mod matches;
 pub mod config;
 pub mod server;
 pub mod resource;
 pub mod logger;
 pub mod monitor;
+pub mod es;
+pub mod health;
+pub mod metrics;
+pub mod statsd;
+pub mod signature;
+pub mod jwt;
+pub mod error;
+pub mod version;
+pub mod stream;
+pub mod export;
+pub mod compression;
+pub mod request_id;
+pub mod admin;
+pub mod schema;
+pub mod format;
+pub mod shutdown;
+pub mod unix_socket;
+pub mod pool;
+pub mod cluster;
+pub mod settings;
+pub mod scoring;
+pub mod secrets;
+pub mod retry;
+pub mod cache;
+pub mod scroll;
+pub mod coalesce;
+pub mod queue;
+pub mod bulk;
+pub mod clock;
+pub mod webhooks;
+pub mod tracing;
+pub mod audit;
+pub mod analytics;
+pub mod saved_searches;
+pub mod batches;
+pub mod alerts;
+pub mod experiments;
+pub mod ltr;
+pub mod federated;
+pub mod spelling;
+pub mod redaction;
+pub mod erasure;
+pub mod quotas;
+pub mod redis_store;
+pub mod routing;
+pub mod usage;
+pub mod consumer;
+pub mod kafka;
+pub mod source;
+pub mod import;
+pub mod backup;
+pub mod lease;
+pub mod client_cert;
+pub mod curl;
+pub mod mappings;
+pub mod backend;
+pub mod fixtures;
+pub mod check;
+pub mod dump;
+pub mod systemd;
+pub mod loopback;
+pub mod migrations;
+pub mod slowlog;
+#[cfg(any(test, feature = "test-support"))]
+pub mod test_support;
+#[cfg(any(test, feature = "test-support"))]
+pub mod faults;
+#[cfg(feature = "embedded-es")]
+pub mod embedded;
 
 pub mod resources;
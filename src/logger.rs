@@ -1,42 +1,157 @@
 use log::*;
 use config::Config;
 use monitor::*;
+use request_id;
+
+use chrono::UTC;
+use serde_json;
+
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+lazy_static! {
+  /// The handle used to change the level once the logger has been set.
+  static ref MAX_LOG_LEVEL: Mutex<Option<MaxLogLevelFilter>> = Mutex::new(None);
+}
+
+thread_local! {
+  /// The fields attached to the lines logged by the current thread (i.e. the index
+  /// and the operation of the request being handled).
+  static FIELDS: RefCell<BTreeMap<&'static str, String>> = RefCell::new(BTreeMap::new());
+}
 
 pub fn start_logging(config: &Config) -> Result<(), SetLoggerError> {
   set_logger(|max_log_level| {
-    max_log_level.set(LogLevelFilter::Info);
+    max_log_level.set(config.log.level());
+    *MAX_LOG_LEVEL.lock().unwrap() = Some(max_log_level);
+
+    let json = config.log.is_json();
 
     if let Some(monitor) = config.monitor.to_owned() {
       if monitor.enabled == true {
         match MonitorProvider::find_with_config(&monitor.provider, &monitor) {
-          Some(monitor) => { return Box::new(Logger { monitor: monitor }); },
+          Some(monitor) => { return Box::new(Logger { monitor: monitor, json: json }); },
           None          => { panic!("Monitor {} has not been found.", monitor.provider); }
         };
       }
     }
 
-    Box::new(Logger { monitor: MonitorProvider::null_monitor() })
+    Box::new(Logger { monitor: MonitorProvider::null_monitor(), json: json })
   })
 }
 
 struct Logger<T: Monitor> {
-  monitor: T
+  monitor: T,
+  json:    bool // every line is a JSON object, rather than text
+}
+
+/// Change the level of the logs, if the logger has been started.
+pub fn set_level(level: LogLevelFilter) {
+  if let Some(ref max_log_level) = *MAX_LOG_LEVEL.lock().unwrap() {
+    max_log_level.set(level);
+  }
+}
+
+/// Attach `value` as `key` to the lines logged by the current thread from now on.
+pub fn set_field(key: &'static str, value: &str) {
+  FIELDS.with(|fields| fields.borrow_mut().insert(key, value.to_owned()));
+}
+
+/// Detach every field from the lines logged by the current thread.
+pub fn clear_fields() {
+  FIELDS.with(|fields| fields.borrow_mut().clear());
+}
+
+/// Return the fields attached to the lines logged by the current thread.
+pub fn fields() -> BTreeMap<&'static str, String> {
+  FIELDS.with(|fields| fields.borrow().to_owned())
+}
+
+/// Return `message` as a text line, i.e.:
+/// `INFO - [2f4b1c1e] Slow search index=talents operation=search`.
+fn text_line(level: LogLevel, request_id: Option<String>, message: &str, fields: &BTreeMap<&'static str, String>) -> String {
+  let mut line = match request_id {
+    Some(id) => format!("{} - [{}] {}", level, id, message),
+    None     => format!("{} - {}", level, message)
+  };
+
+  for (key, value) in fields {
+    line.push_str(&format!(" {}={}", key, value));
+  }
+
+  line
+}
+
+/// Return `message` as a JSON object, i.e.: `{"index":"talents","level":"INFO",
+/// "message":"Slow search","operation":"search","request_id":"2f4b1c1e","timestamp":"..."}`.
+fn json_line(level: LogLevel, request_id: Option<String>, message: &str, fields: &BTreeMap<&'static str, String>, timestamp: &str) -> String {
+  let mut line = fields.to_owned();
+  line.insert("level",     level.to_string());
+  line.insert("message",   message.to_owned());
+  line.insert("timestamp", timestamp.to_owned());
+
+  if let Some(id) = request_id {
+    line.insert("request_id", id);
+  }
+
+  serde_json::to_string(&line).unwrap()
 }
 
 impl<T: Monitor> Log for Logger<T> {
   fn enabled(&self, metadata: &LogMetadata) -> bool {
-    metadata.level() <= LogLevel::Info
+    metadata.level() <= max_log_level()
   }
 
   fn log(&self, record: &LogRecord) {
     if self.enabled(record.metadata()) {
-      let error_message = format!("{} - {}", record.level(), record.args());
+      let message = record.args().to_string();
+      let fields  = fields();
+      let line    = text_line(record.level(), request_id::current(), &message, &fields);
 
+      // the monitors always receive the text line
       if self.monitor.is_real() && record.level() == LogLevel::Error {
-        self.monitor.send(&error_message, record.location());
+        self.monitor.send(&line, record.location());
       }
 
-      println!("{}", error_message);
+      if self.json {
+        println!("{}", json_line(record.level(), request_id::current(), &message, &fields, &UTC::now().to_rfc3339()));
+      }
+      else {
+        println!("{}", line);
+      }
     }
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use logger::{set_field, clear_fields, fields, text_line, json_line};
+
+  use log::LogLevel;
+
+  #[test]
+  fn test_fields() {
+    set_field("index", "talents");
+    set_field("operation", "search");
+    assert_eq!(fields().get("index"), Some(&"talents".to_owned()));
+    assert_eq!(fields().len(), 2);
+
+    clear_fields();
+    assert!(fields().is_empty());
+  }
+
+  #[test]
+  fn test_lines() {
+    let mut fields = btreemap! { "index" => "talents".to_owned() };
+
+    assert_eq!(text_line(LogLevel::Warn, None, "Slow search", &btreemap! {}), "WARN - Slow search");
+    assert_eq!(text_line(LogLevel::Warn, Some("abc".to_owned()), "Slow search", &fields),
+               "WARN - [abc] Slow search index=talents");
+
+    fields.insert("operation", "search".to_owned());
+    assert_eq!(json_line(LogLevel::Info, Some("abc".to_owned()), "Slow \"search\"", &fields, "2016-03-04T12:24:00+00:00"),
+               "{\"index\":\"talents\",\"level\":\"INFO\",\"message\":\"Slow \\\"search\\\"\",\"operation\":\"search\",\
+                \"request_id\":\"abc\",\"timestamp\":\"2016-03-04T12:24:00+00:00\"}");
+  }
+}
@@ -0,0 +1,110 @@
+//! The self-check of the liveness of the server: `GET /live` is sent to the
+//! address it listens on (its Unix socket, or its port over TLS or plain HTTP),
+//! so that the watchdog of systemd is petted only while the requests are still
+//! accepted and handled, rather than as long as the main loop is running.
+
+use openssl::ssl::{SslConnectorBuilder, SslMethod, SSL_VERIFY_NONE};
+
+use config::Config;
+
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::os::unix::net::UnixStream;
+use std::time::Duration;
+
+/// The request sent to the server, which does not keep the connection open.
+const REQUEST: &'static [u8] = b"GET /live HTTP/1.0\r\nHost: localhost\r\n\r\n";
+
+/// Send `GET /live` to the server listening as given by `config`, returning why
+/// it has not answered `200` within `timeout` if it has not.
+pub fn check(config: &Config, timeout: Duration) -> Result<(), String> {
+  if let Some(ref socket) = config.http.socket {
+    let stream = try!(UnixStream::connect(socket).map_err(|err| format!("{}: {}", socket, err)));
+    try!(stream.set_read_timeout(Some(timeout)).map_err(|err| err.to_string()));
+    try!(stream.set_write_timeout(Some(timeout)).map_err(|err| err.to_string()));
+    return live(stream);
+  }
+
+  let address = try!(address(&config.http.host, config.http.port as u16));
+  let stream  = try!(TcpStream::connect_timeout(&address, timeout).map_err(|err| format!("{}: {}", address, err)));
+  try!(stream.set_read_timeout(Some(timeout)).map_err(|err| err.to_string()));
+  try!(stream.set_write_timeout(Some(timeout)).map_err(|err| err.to_string()));
+
+  if !config.tls.enabled {
+    return live(stream);
+  }
+
+  // only the handshake matters, the certificate is the own one of the server
+  let mut builder = try!(SslConnectorBuilder::new(SslMethod::tls()).map_err(|err| err.to_string()));
+  builder.builder_mut().set_verify(SSL_VERIFY_NONE);
+
+  let stream = try!(builder.build()
+                           .danger_connect_without_providing_domain_for_certificate_verification_and_server_name_indication(stream)
+                           .map_err(|err| err.to_string()));
+  live(stream)
+}
+
+/// Return the loopback address of the port the server listens on, when bound to every interface.
+fn address(host: &str, port: u16) -> Result<SocketAddr, String> {
+  let host = match host {
+    "0.0.0.0" | "" => "127.0.0.1",
+    "::"           => "::1",
+    host           => host
+  };
+
+  try!((host, port).to_socket_addrs().map_err(|err| format!("{}:{}: {}", host, port, err)))
+    .next()
+    .ok_or(format!("{}:{} cannot be resolved", host, port))
+}
+
+/// Send `REQUEST` through `stream`, checking that its response is `200`.
+fn live<S: Read + Write>(mut stream: S) -> Result<(), String> {
+  try!(stream.write_all(REQUEST).map_err(|err| err.to_string()));
+
+  let mut response = String::new();
+  try!(stream.read_to_string(&mut response).map_err(|err| err.to_string()));
+
+  let status = response.lines().next().unwrap_or("");
+  if status.starts_with("HTTP/1.") && status.split_whitespace().nth(1) == Some("200") {
+    Ok(())
+  }
+  else {
+    Err(format!("GET /live has been answered with {:?}", status))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use loopback::{address, live};
+
+  use std::io::{Read, Write};
+  use std::net::{TcpListener, TcpStream};
+  use std::thread;
+
+  fn serve(response: &'static str) -> TcpStream {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address  = listener.local_addr().unwrap();
+
+    thread::spawn(move || {
+      let (mut stream, _) = listener.accept().unwrap();
+      let mut request = [0; 64];
+      let _ = stream.read(&mut request);
+      let _ = stream.write_all(response.as_bytes());
+    });
+
+    TcpStream::connect(address).unwrap()
+  }
+
+  #[test]
+  fn test_live() {
+    assert!(live(serve("HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n")).is_ok());
+    assert!(live(serve("HTTP/1.1 503 Service Unavailable\r\n\r\n")).is_err());
+    assert!(live(serve("")).is_err());
+  }
+
+  #[test]
+  fn test_address() {
+    assert_eq!(address("0.0.0.0", 3000).unwrap().to_string(), "127.0.0.1:3000");
+    assert_eq!(address("127.0.0.1", 3001).unwrap().to_string(), "127.0.0.1:3001");
+  }
+}
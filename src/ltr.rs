@@ -0,0 +1,167 @@
+//! The learning to rank plugin of ElasticSearch (`elasticsearch-learning-to-rank`):
+//! once the `ltr_rescoring` feature is enabled, the top `ltr.window_size` full text
+//! matches are rescored by the `ltr_model` of their resource, whose features are
+//! given the keywords as their `keywords` param. With `ltr.log_features` the feature
+//! vectors of the results served are logged as well, i.e.:
+//!
+//! `LTR features: {"model":"talents_v1","results":[{"id":"42","rank":1,"features":{"headline":3.2}}]}`
+//!
+//! so that the models can be trained on what the recruiters do with the results.
+//! Only the searches sent through `Resource::preview` are rescored.
+
+use serde_json;
+use serde_json::Value as JsonValue;
+
+use config::Ltr;
+
+use std::collections::BTreeMap;
+
+/// The name of the feature vectors logged into the hits.
+const LOG_NAME: &'static str = "features";
+
+/// The rescoring of the top full text matches by a model.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rescoring {
+  pub model:        String,
+  pub store:        Option<String>,
+  pub window_size:  u64,
+  pub log_features: bool
+}
+
+impl Rescoring {
+  pub fn new(model: &str, ltr: &Ltr) -> Rescoring {
+    Rescoring {
+      model:        model.to_owned(),
+      store:        ltr.store.to_owned(),
+      window_size:  ltr.window_size,
+      log_features: ltr.log_features
+    }
+  }
+}
+
+/// The features of a result served, i.e.: `{"id": "42", "rank": 1, "features": {"headline": 3.2}}`.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct FeatureVector {
+  pub id:       String,
+  pub rank:     u64, // from 1
+  pub features: BTreeMap<String, f64>
+}
+
+/// Return the `sltr` query scoring the matches of `keywords` by the model of `rescoring`.
+pub fn query(rescoring: &Rescoring, keywords: &str) -> JsonValue {
+  let mut sltr = btreemap! {
+    "params" => serde_json::to_value(&btreemap! { "keywords" => keywords }),
+    "model"  => serde_json::to_value(&rescoring.model)
+  };
+
+  if let Some(ref store) = rescoring.store {
+    sltr.insert("store", serde_json::to_value(store));
+  }
+
+  serde_json::to_value(&btreemap! { "sltr" => sltr })
+}
+
+/// Return the `rescore` of the body of a full text search of `keywords`.
+pub fn rescore(rescoring: &Rescoring, keywords: &str) -> JsonValue {
+  serde_json::to_value(&btreemap! {
+    "window_size" => serde_json::to_value(&rescoring.window_size),
+    "query"       => serde_json::to_value(&btreemap! { "rescore_query" => query(rescoring, keywords) })
+  })
+}
+
+/// Return the `ext` of the body of a search asking for the features computed by its rescoring.
+pub fn log_specs() -> JsonValue {
+  serde_json::to_value(&btreemap! {
+    "ltr_log" => btreemap! {
+      "log_specs" => serde_json::to_value(&btreemap! {
+        "name"          => serde_json::to_value(&LOG_NAME),
+        "rescore_index" => serde_json::to_value(&0)
+      })
+    }
+  })
+}
+
+/// Return the feature vectors logged into the hits of `response`, the best match first.
+/// The features missing from a hit (i.e. not matched) are left out.
+pub fn feature_vectors(response: &JsonValue) -> Vec<FeatureVector> {
+  let hits = match response.pointer("/hits/hits").and_then(|hits| hits.as_array()) {
+    Some(hits) => hits,
+    None       => return vec![]
+  };
+
+  hits.iter().enumerate().filter_map(|(position, hit)| {
+    let logged = match hit.pointer("/fields/_ltrlog/0").and_then(|log| log.find(LOG_NAME)).and_then(|log| log.as_array()) {
+      Some(logged) => logged,
+      None         => return None
+    };
+
+    let features = logged.iter()
+                         .filter_map(|feature| match (feature.find("name").and_then(|name| name.as_str()),
+                                                      feature.find("value").and_then(|value| value.as_f64())) {
+                           (Some(name), Some(value)) => Some((name.to_owned(), value)),
+                           _                         => None
+                         })
+                         .collect();
+
+    Some(FeatureVector {
+      id:       hit.find("_id").and_then(|id| id.as_str()).unwrap_or("").to_owned(),
+      rank:     position as u64 + 1,
+      features: features
+    })
+  }).collect()
+}
+
+/// Log the feature vectors of the results of `response`, rescored by `model`, if any.
+pub fn log(model: &str, response: &JsonValue) {
+  let vectors = feature_vectors(response);
+  if vectors.is_empty() {
+    return;
+  }
+
+  let entry = btreemap! {
+    "model"   => serde_json::to_value(&model),
+    "results" => serde_json::to_value(&vectors)
+  };
+
+  info!("LTR features: {}", serde_json::to_string(&entry).unwrap());
+}
+
+#[cfg(test)]
+mod tests {
+  use ltr::{Rescoring, rescore, log_specs, feature_vectors};
+  use config::Ltr;
+
+  use serde_json;
+
+  #[test]
+  fn test_rescore() {
+    let rescoring = Rescoring::new("talents_v1", &Ltr { window_size: 50, ..Default::default() });
+    let body      = rescore(&rescoring, "rust");
+
+    assert_eq!(body.pointer("/window_size"), Some(&serde_json::to_value(&50)));
+    assert_eq!(body.pointer("/query/rescore_query/sltr/model"), Some(&serde_json::to_value(&"talents_v1")));
+    assert_eq!(body.pointer("/query/rescore_query/sltr/params/keywords"), Some(&serde_json::to_value(&"rust")));
+    assert_eq!(body.pointer("/query/rescore_query/sltr/store"), None);
+
+    let rescoring = Rescoring::new("talents_v1", &Ltr { store: Some("talents".to_owned()), ..Default::default() });
+    assert_eq!(rescore(&rescoring, "rust").pointer("/query/rescore_query/sltr/store"), Some(&serde_json::to_value(&"talents")));
+
+    assert_eq!(log_specs().pointer("/ltr_log/log_specs/rescore_index"), Some(&serde_json::to_value(&0)));
+  }
+
+  #[test]
+  fn test_feature_vectors() {
+    let response = serde_json::from_str("{\"hits\": {\"hits\": [{\"_id\": \"2\", \"fields\": {\"_ltrlog\": [{\"features\": \
+                                         [{\"name\": \"headline\", \"value\": 3.5}, {\"name\": \"skills\"}]}]}}, \
+                                         {\"_id\": \"1\"}, {\"_id\": \"3\", \"fields\": {\"_ltrlog\": [{\"features\": []}]}}]}}").unwrap();
+
+    let vectors = feature_vectors(&response);
+    assert_eq!(vectors.len(), 2);
+    assert_eq!(vectors[0].id, "2");
+    assert_eq!(vectors[0].rank, 1);
+    assert_eq!(vectors[0].features, btreemap! { "headline".to_owned() => 3.5 });
+    assert_eq!(vectors[1].rank, 3);
+
+    assert!(feature_vectors(&serde_json::from_str("{}").unwrap()).is_empty());
+  }
+}
@@ -14,6 +14,10 @@
 /// Otherwise, the output will be a `Vec<String>` fill with all the
 /// returned `String`s found inside the query string.
 ///
+/// The handlers refuse beforehand the values that would be dropped
+/// this way (see `schema::malformed`), so that they do not silently
+/// change the searches.
+///
 /// ```
 /// # #[macro_use] extern crate searchspot;
 /// # extern crate params;
@@ -69,10 +73,10 @@ macro_rules! string_vec_from_params {
   ($params:expr, $param:expr) => { type_vec_from_params!(String, $params, $param) }
 }
 
-/// Sugar for `type_vec_from_params` where `$t` is `i32`.
+/// Sugar for `type_vec_from_params` where `$t` is `u64`, the type of the ids.
 #[macro_export]
-macro_rules! i32_vec_from_params {
-  ($params:expr, $param:expr) => { type_vec_from_params!(i32, $params, $param) }
+macro_rules! u64_vec_from_params {
+  ($params:expr, $param:expr) => { type_vec_from_params!(u64, $params, $param) }
 }
 
 #[cfg(test)]
@@ -109,22 +113,40 @@ mod tests {
   }
 
   #[test]
-  fn test_i32_vec_from_params() {
-    // given a number casted to String, it returns a vector containing that string casted to i32
+  fn test_u64_vec_from_params() {
+    // given a number casted to String, it returns a vector containing that string casted to u64
     {
       let mut params = Map::new();
       params.assign("company_id", Value::String("4".into())).unwrap();
 
-      let company_ids: Vec<i32> = i32_vec_from_params!(params, "company_id");
+      let company_ids: Vec<u64> = u64_vec_from_params!(params, "company_id");
       assert_eq!(company_ids, vec![4]);
     }
 
+    // given an id greater than 2^31, it is kept as is
+    {
+      let mut params = Map::new();
+      params.assign("company_id", Value::String("4294967296".into())).unwrap();
+
+      let company_ids: Vec<u64> = u64_vec_from_params!(params, "company_id");
+      assert_eq!(company_ids, vec![4294967296]);
+    }
+
+    // given a negative number, it returns an empty vector
+    {
+      let mut params = Map::new();
+      params.assign("company_id", Value::String("-4".into())).unwrap();
+
+      let company_ids: Vec<u64> = u64_vec_from_params!(params, "company_id");
+      assert!(company_ids.is_empty());
+    }
+
     // given an empty string, it returns an empty vector
     {
       let mut params = Map::new();
       params.assign("company_id", Value::String("".into())).unwrap();
 
-      let company_ids: Vec<i32> = i32_vec_from_params!(params, "company_id");
+      let company_ids: Vec<u64> = u64_vec_from_params!(params, "company_id");
       assert!(company_ids.is_empty());
     }
 
@@ -133,7 +155,7 @@ mod tests {
       let mut params = Map::new();
       params.assign("company_id", Value::String("madukapls".into())).unwrap();
 
-      let company_ids: Vec<i32> = i32_vec_from_params!(params, "company_id");
+      let company_ids: Vec<u64> = u64_vec_from_params!(params, "company_id");
       assert!(company_ids.is_empty());
     }
 
@@ -141,13 +163,13 @@ mod tests {
       let mut params = Map::new();
       params.assign("company_id[]", Value::String("madukapls".into())).unwrap();
 
-      let company_ids: Vec<i32> = i32_vec_from_params!(params, "company_id");
+      let company_ids: Vec<u64> = u64_vec_from_params!(params, "company_id");
       assert!(company_ids.is_empty());
     }
 
     // given nothing, it returns an empty vector
     {
-      let company_ids: Vec<i32> = i32_vec_from_params!(Map::new(), "company_id");
+      let company_ids: Vec<u64> = u64_vec_from_params!(Map::new(), "company_id");
       assert!(company_ids.is_empty());
     }
   }
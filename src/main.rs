@@ -1,19 +1,139 @@
 extern crate searchspot;
 extern crate backtrace;
+extern crate clap;
 
-use std::{env, panic};
+use std::{env, panic, process};
 
 use searchspot::resources::Talent;
 use searchspot::server::Server;
-use searchspot::config::Config;
+use searchspot::consumer;
+use searchspot::source;
+use searchspot::import;
+use searchspot::curl;
+use searchspot::mappings;
+use searchspot::fixtures;
+use searchspot::check;
+use searchspot::config::{Config, PROFILES, PROFILE_VAR};
 use searchspot::monitor::*;
 use backtrace::Backtrace;
+use clap::{App, Arg, ArgMatches, SubCommand};
+
+/// Return the command line flags.
+fn flags<'a>() -> ArgMatches<'a> {
+    App::new("searchspot")
+        .version(env!("CARGO_PKG_VERSION"))
+        .about("The service responsible for Honeypot's ElasticSearch data")
+        .arg(Arg::with_name("config")
+                 .long("config")
+                 .value_name("FILE")
+                 .help("The configuration file, looked for in ./searchspot.toml and /etc/searchspot/config.toml if not given"))
+        .arg(Arg::with_name("CONFIG")
+                 .help("Same as --config")
+                 .conflicts_with("config"))
+        .arg(Arg::with_name("env")
+                 .long("env")
+                 .value_name("PROFILE")
+                 .possible_values(&PROFILES)
+                 .help("Selects the profile of the configuration file, replaces ENV"))
+        .arg(Arg::with_name("port")
+                 .long("port")
+                 .value_name("PORT")
+                 .help("Replaces http.port")
+                 .validator(|port| port.parse::<u32>().map(|_| ()).map_err(|e| e.to_string())))
+        .arg(Arg::with_name("es-host")
+                 .long("es-host")
+                 .value_name("URL")
+                 .multiple(true)
+                 .number_of_values(1)
+                 .help("Replaces es.hosts, can be given several times"))
+        .arg(Arg::with_name("log-level")
+                 .long("log-level")
+                 .value_name("LEVEL")
+                 .possible_values(&["off", "error", "warn", "info", "debug", "trace"])
+                 .help("Replaces log.level"))
+        .arg(Arg::with_name("consume")
+                 .long("consume")
+                 .help("Applies the indexing events of consumer.queue (or kafka.topic) instead of serving HTTP requests"))
+        .arg(Arg::with_name("reindex-from-source")
+                 .long("reindex-from-source")
+                 .conflicts_with("consume")
+                 .help("Rebuilds the index from the view of source.url, then exits"))
+        .arg(Arg::with_name("import")
+                 .long("import")
+                 .value_name("FILE")
+                 .conflicts_with_all(&["consume", "reindex-from-source"])
+                 .help("Indexes the talents of the NDJSON file (one per line), then exits"))
+        .arg(Arg::with_name("errors")
+                 .long("errors")
+                 .value_name("FILE")
+                 .requires("import")
+                 .help("Where the lines rejected by --import are written, <FILE>.rejected if not given"))
+        .arg(Arg::with_name("curl")
+                 .long("curl")
+                 .value_name("PARAMS")
+                 .conflicts_with_all(&["consume", "reindex-from-source", "import"])
+                 .help("Prints the curl command sending the search with the given params (i.e.: 'keywords=rust&work_locations[]=Berlin') to ElasticSearch, then exits"))
+        .arg(Arg::with_name("dump-mapping")
+                 .long("dump-mapping")
+                 .value_name("FILE")
+                 .conflicts_with_all(&["consume", "reindex-from-source", "import", "curl"])
+                 .help("Writes the live settings and mappings of the index to the JSON file, then exits"))
+        .arg(Arg::with_name("apply-mapping")
+                 .long("apply-mapping")
+                 .value_name("FILE")
+                 .conflicts_with_all(&["consume", "reindex-from-source", "import", "curl", "dump-mapping"])
+                 .help("Applies the settings and mappings of the JSON file written by --dump-mapping to the index, then exits"))
+        .arg(Arg::with_name("close-index")
+                 .long("close-index")
+                 .requires("apply-mapping")
+                 .help("Lets --apply-mapping close the index while its analysis is changed, refusing the searches meanwhile"))
+        .arg(Arg::with_name("seed")
+                 .long("seed")
+                 .value_name("PATH")
+                 .conflicts_with_all(&["consume", "reindex-from-source", "import", "curl", "dump-mapping", "apply-mapping"])
+                 .help("Indexes the talents of the JSON or YAML fixtures (a file or a directory, i.e.: demo/), then exits"))
+        .subcommand(SubCommand::with_name("check-config")
+                        .about("Validates the configuration file, the cluster it points to and the mapping of the index, then exits")
+                        .arg(Arg::with_name("FILE").required(true)))
+        .get_matches()
+}
+
+/// Give precedence to the flags over the configuration file and the environment
+/// setting the variables read by both of them, so that they survive the reloads.
+fn override_with_flags(flags: &ArgMatches) {
+    if let Some(profile) = flags.value_of("env") {
+        env::set_var(PROFILE_VAR, profile);
+    }
+
+    if let Some(port) = flags.value_of("port") {
+        env::set_var("SEARCHSPOT_HTTP__PORT", port);
+        env::set_var("PORT", port);
+    }
+
+    if let Some(hosts) = flags.values_of("es-host") {
+        let hosts = hosts.collect::<Vec<&str>>();
+        let list  = hosts.iter().map(|host| format!("{:?}", host)).collect::<Vec<String>>();
+        env::set_var("SEARCHSPOT_ES__HOSTS", format!("[{}]", list.join(", ")));
+        env::set_var("ES_HOSTS", hosts.join(","));
+    }
+
+    if let Some(level) = flags.value_of("log-level") {
+        env::set_var("SEARCHSPOT_LOG__LEVEL", level);
+        env::set_var("LOG_LEVEL", level);
+    }
+}
 
 fn main() {
-    let config = match env::args().nth(1) {
-      Some(file) => Config::from_file(file),
-      None       => Config::from_env()
-    };
+    let flags = flags();
+    override_with_flags(&flags);
+
+    if let Some(subcommand) = flags.subcommand_matches("check-config") {
+      let report = check::check::<Talent>(subcommand.value_of("FILE").unwrap());
+      println!("{}", report);
+      process::exit(if report.passed() { 0 } else { 1 });
+    }
+
+    let config = Config::resolve(flags.value_of("config").or(flags.value_of("CONFIG")).map(|path| path.to_owned()));
 
     if let Some(monitor) = config.monitor.to_owned() {
       if monitor.enabled == true {
@@ -29,8 +149,51 @@ fn main() {
       }
     }
 
+    let consume = flags.is_present("consume");
+
+    if let Some(path) = flags.value_of("import") {
+      let errors   = flags.value_of("errors").map(|errors| errors.to_owned()).unwrap_or(format!("{}.rejected", path));
+      let imported = panic::catch_unwind(|| import::import::<Talent>(config, path, &errors)).unwrap_or(false);
+      process::exit(if imported { 0 } else { 1 });
+    }
+
+    if let Some(query) = flags.value_of("curl") {
+      let printed = panic::catch_unwind(|| curl::print::<Talent>(config, query)).unwrap_or(false);
+      process::exit(if printed { 0 } else { 1 });
+    }
+
+    if let Some(path) = flags.value_of("dump-mapping") {
+      let dumped = panic::catch_unwind(|| mappings::dump_to_file::<Talent>(config, path)).unwrap_or(false);
+      process::exit(if dumped { 0 } else { 1 });
+    }
+
+    if let Some(path) = flags.value_of("apply-mapping") {
+      let close   = flags.is_present("close-index");
+      let applied = panic::catch_unwind(|| mappings::apply_file::<Talent>(config, path, close)).unwrap_or(false);
+      process::exit(if applied { 0 } else { 1 });
+    }
+
+    if let Some(path) = flags.value_of("seed") {
+      let seeded = panic::catch_unwind(|| fixtures::seed::<Talent>(config, path)).unwrap_or(false);
+      process::exit(if seeded { 0 } else { 1 });
+    }
+
+    if flags.is_present("reindex-from-source") {
+      let reindexed = panic::catch_unwind(|| source::reindex_from_source::<Talent>(config)).unwrap_or(false);
+      process::exit(if reindexed { 0 } else { 1 });
+    }
+
     let _ = panic::catch_unwind(|| {
-      let server = Server::<Talent>::new(config, "/talents");
+      if consume {
+        return consumer::consume::<Talent>(config);
+      }
+
+      let mut server = Server::<Talent>::new(config, "/talents");
+
+      if let Some(ref path) = config_path {
+        server = server.with_config_path(path);
+      }
+
       server.start();
     });
 }
@@ -0,0 +1,194 @@
+//! The dump of the live settings and mappings of an index to a JSON file,
+//! run by `searchspot --dump-mapping <file>`, and their application to the
+//! index of another cluster, run by `searchspot --apply-mapping <file>`, so
+//! that the tuning done on staging (i.e.: the analyzers) can be promoted to
+//! production as it is.
+//!
+//! The file looks like `{"index": "talents_v2", "settings": {"index": {...}},
+//! "mappings": {"talent": {"properties": {...}}}}`.
+
+use serde_json;
+use serde_json::Value as JsonValue;
+
+use config::Config;
+use resource::Resource;
+use error::Error;
+use admin::connect;
+use es;
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{Read, Write};
+
+/// The settings that describe an index rather than its tuning,
+/// which ElasticSearch refuses when the index is created.
+const GENERATED_SETTINGS: [&'static str; 4] = ["uuid", "creation_date", "version", "provided_name"];
+
+/// The settings applied to an existing index, the other ones (i.e.: its shards, replicas
+/// and refresh interval) being left to the configuration of the cluster it is applied to.
+const UPDATABLE_SETTINGS: [&'static str; 1] = ["analysis"];
+
+/// Return the settings of the first index of `GET /:index/_settings`,
+/// without the ones generated by ElasticSearch.
+pub fn portable_settings(live: &JsonValue) -> JsonValue {
+  let mut settings = live.as_object()
+                         .and_then(|indexes| indexes.values().next())
+                         .and_then(|index| index.find("settings"))
+                         .cloned()
+                         .unwrap_or(JsonValue::Object(BTreeMap::new()));
+
+  remove_index_settings(&mut settings, &GENERATED_SETTINGS);
+  settings
+}
+
+/// Return the `settings` applied to an existing index, i.e. `{"index": {"analysis": {...}}}`.
+pub fn updatable_settings(settings: &JsonValue) -> JsonValue {
+  let index = UPDATABLE_SETTINGS.iter()
+                                .filter_map(|key| settings.pointer(&format!("/index/{}", key)).map(|value| (key.to_string(), value.to_owned())))
+                                .collect::<BTreeMap<String, JsonValue>>();
+
+  if index.is_empty() {
+    JsonValue::Object(BTreeMap::new())
+  }
+  else {
+    JsonValue::Object(btreemap! { "index".to_owned() => JsonValue::Object(index) })
+  }
+}
+
+fn remove_index_settings(settings: &mut JsonValue, keys: &[&str]) {
+  if let Some(index) = settings.as_object_mut().and_then(|settings| settings.get_mut("index")).and_then(|index| index.as_object_mut()) {
+    for key in keys {
+      index.remove(*key);
+    }
+  }
+}
+
+/// Return the settings and the mappings of `index` on the cluster listening on `url`.
+pub fn dump(url: &str, index: &str) -> Result<JsonValue, Error> {
+  let settings = try!(es::get(url, &format!("{}/_settings", index)));
+  let mappings = try!(es::get(url, &es::mapping_path(index)));
+
+  // the responses are keyed by the real index name, which differs if we're using an alias
+  let name = settings.as_object().and_then(|indexes| indexes.keys().next()).cloned().unwrap_or(index.to_owned());
+
+  Ok(serde_json::to_value(&btreemap! {
+    "index"    => JsonValue::String(name),
+    "settings" => portable_settings(&settings),
+    "mappings" => mappings.as_object()
+                          .and_then(|indexes| indexes.values().next())
+                          .and_then(|index| index.find("mappings"))
+                          .cloned()
+                          .unwrap_or(JsonValue::Object(BTreeMap::new()))
+  }))
+}
+
+/// Apply the settings and the mappings of `dump` to `index` on the cluster listening on `url`,
+/// creating it if it does not exist. Only the analysis of an existing index is updated, which
+/// requires to close it meanwhile: it is refused unless `close` is given, if it has changed.
+/// The fields of its mappings can only be added (ElasticSearch refuses to change their types).
+pub fn apply(url: &str, index: &str, dump: &JsonValue, close: bool) -> Result<(), Error> {
+  let settings = dump.find("settings").cloned().unwrap_or(JsonValue::Object(BTreeMap::new()));
+  let mappings = dump.find("mappings").and_then(|mappings| mappings.as_object()).cloned().unwrap_or(BTreeMap::new());
+
+  match es::get(url, index).map_err(Error::from) {
+    Err(Error::IndexNotFound(_)) => {
+      let body = btreemap! { "settings" => settings, "mappings" => JsonValue::Object(mappings) };
+      try!(es::put(url, &es::index_path(index), &serde_json::to_string(&body).unwrap()));
+      return Ok(());
+    },
+    Err(err) => return Err(err),
+    Ok(_)    => ()
+  }
+
+  let updated = updatable_settings(&settings);
+  let live    = updatable_settings(&portable_settings(&try!(es::get(url, &format!("{}/_settings", index)))));
+
+  if updated != live && updated.as_object().map_or(false, |updated| !updated.is_empty()) {
+    // the analyzers can be changed only while the index is closed, refusing the searches meanwhile
+    if !close {
+      return Err(Error::Validation(format!("The analysis of `{}` has changed, which requires to close the index while it is \
+                                            updated: apply it again with --close-index.", index)));
+    }
+
+    try!(es::post(url, &format!("{}/_close", index), ""));
+    let result = es::put(url, &format!("{}/_settings", index), &serde_json::to_string(&updated).unwrap());
+    try!(es::post(url, &format!("{}/_open", index), ""));
+    try!(result);
+  }
+
+  for (doc_type, mapping) in mappings.iter() {
+    try!(es::put(url, &es::type_mapping_path(index, doc_type), &serde_json::to_string(mapping).unwrap()));
+  }
+
+  Ok(())
+}
+
+/// Write the settings and the mappings of the index of `R` to the file at `path`,
+/// returning whether they have been written.
+pub fn dump_to_file<R: Resource>(config: Config, path: &str) -> bool {
+  let url   = connect(&config);
+  let index = config.index_of(R::name());
+
+  let written = dump(&url, &index).map_err(|err| err.to_string()).and_then(|dump| {
+    File::create(path).and_then(|mut file| writeln!(file, "{}", serde_json::to_string_pretty(&dump).unwrap()))
+                      .map_err(|err| format!("{}: {}", path, err))
+  });
+
+  match written {
+    Ok(_) => {
+      info!("The settings and the mappings of {} have been written to {}.", index, path);
+      true
+    },
+    Err(err) => {
+      error!("The settings and the mappings of {} have not been written: {}", index, err);
+      false
+    }
+  }
+}
+
+/// Apply the settings and the mappings of the file at `path` to the index of `R`, closing
+/// it meanwhile if its analysis has changed and `close` is given, returning whether they
+/// have been applied.
+pub fn apply_file<R: Resource>(config: Config, path: &str, close: bool) -> bool {
+  let url   = connect(&config);
+  let index = config.index_of(R::name());
+
+  let mut content = String::new();
+  let applied = File::open(path).and_then(|mut file| file.read_to_string(&mut content))
+                                .map_err(|err| format!("{}: {}", path, err))
+                                .and_then(|_| serde_json::from_str::<JsonValue>(&content).map_err(|err| format!("{}: {}", path, err)))
+                                .and_then(|dump| apply(&url, &index, &dump, close).map_err(|err| err.to_string()));
+
+  match applied {
+    Ok(_) => {
+      info!("The settings and the mappings of {} have been applied to {}.", path, index);
+      true
+    },
+    Err(err) => {
+      error!("The settings and the mappings of {} have not been applied: {}", path, err);
+      false
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use mappings::{portable_settings, updatable_settings};
+
+  use serde_json;
+  use serde_json::Value as JsonValue;
+
+  #[test]
+  fn test_portable_settings() {
+    let live = serde_json::from_str("{\"talents_v2\": {\"settings\": {\"index\": {\"number_of_shards\": \"5\", \
+                                     \"uuid\": \"x1Q\", \"creation_date\": \"1488626640\", \"version\": {\"created\": \"2040699\"}, \
+                                     \"provided_name\": \"talents_v2\", \"analysis\": {\"analyzer\": {}}}}}}").unwrap();
+
+    let settings = portable_settings(&live);
+    assert_eq!(settings, serde_json::from_str::<JsonValue>("{\"index\": {\"number_of_shards\": \"5\", \"analysis\": {\"analyzer\": {}}}}").unwrap());
+    assert_eq!(updatable_settings(&settings), serde_json::from_str::<JsonValue>("{\"index\": {\"analysis\": {\"analyzer\": {}}}}").unwrap());
+
+    let settings = serde_json::from_str("{\"index\": {\"number_of_replicas\": \"2\", \"refresh_interval\": \"30s\"}}").unwrap();
+    assert_eq!(updatable_settings(&settings), serde_json::from_str::<JsonValue>("{}").unwrap());
+  }
+}
@@ -0,0 +1,366 @@
+//! A minimal registry of counters, gauges and histograms, rendered using
+//! the Prometheus text exposition format (or OpenMetrics, which carries the
+//! request ID of the last observation of every bucket as exemplar), and
+//! optionally sent to StatsD as well (see `statsd`).
+
+use statsd::{StatsD, Kind};
+use request_id;
+
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// The upper bounds (in seconds) of the buckets used by every histogram.
+const BUCKETS: [f64; 11] = [0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+/// The label of the exemplars, whose names and values cannot exceed
+/// `MAX_EXEMPLAR_LENGTH` characters together according to OpenMetrics.
+const EXEMPLAR_LABEL:      &'static str = "request_id";
+const MAX_EXEMPLAR_LENGTH: usize        = 128;
+
+lazy_static! {
+  /// The registry shared across the handlers.
+  pub static ref METRICS: Registry = Registry::new();
+}
+
+/// An observation of a histogram tied to the request that made it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Exemplar {
+  request_id: String,
+  value:      f64
+}
+
+/// A cumulative histogram whose buckets are defined by `BUCKETS`.
+#[derive(Debug, Clone, Default)]
+pub struct Histogram {
+  buckets:   Vec<u64>,
+  exemplars: Vec<Option<Exemplar>>, // by bucket, followed by `+Inf`
+  sum:       f64,
+  count:     u64
+}
+
+impl Histogram {
+  fn observe(&mut self, value: f64, request_id: Option<String>) {
+    if self.buckets.is_empty() {
+      self.buckets   = vec![0; BUCKETS.len()];
+      self.exemplars = vec![None; BUCKETS.len() + 1];
+    }
+
+    for (i, bound) in BUCKETS.iter().enumerate() {
+      if value <= *bound {
+        self.buckets[i] += 1;
+      }
+    }
+
+    // the exemplar belongs to the narrowest bucket containing the value
+    if let Some(request_id) = request_id {
+      let bucket = BUCKETS.iter().position(|bound| value <= *bound).unwrap_or(BUCKETS.len());
+      // the request IDs given by the callers can be longer (see `request_id`)
+      let request_id = request_id.chars().take(MAX_EXEMPLAR_LENGTH - EXEMPLAR_LABEL.len()).collect();
+      self.exemplars[bucket] = Some(Exemplar { request_id: request_id, value: value });
+    }
+
+    self.sum   += value;
+    self.count += 1;
+  }
+}
+
+/// Counters, gauges and histograms identified by their name and labels.
+#[derive(Debug, Default)]
+pub struct Registry {
+  counters:   Mutex<BTreeMap<(String, String), u64>>,
+  gauges:     Mutex<BTreeMap<(String, String), i64>>,
+  histograms: Mutex<BTreeMap<(String, String), Histogram>>,
+  statsd:     Mutex<Option<StatsD>>
+}
+
+impl Registry {
+  pub fn new() -> Registry {
+    Registry::default()
+  }
+
+  /// Send the metrics recorded from now on to `statsd` as well.
+  pub fn emit_to(&self, statsd: StatsD) {
+    *self.statsd.lock().unwrap() = Some(statsd);
+  }
+
+  /// Increment by one the counter `name` with given `labels`.
+  pub fn increment(&self, name: &str, labels: &[(&str, &str)]) {
+    {
+      let mut counters = self.counters.lock().unwrap();
+      *counters.entry((name.to_owned(), format_labels(labels))).or_insert(0) += 1;
+    }
+
+    self.emit(name, labels, "1", Kind::Counter);
+  }
+
+  /// Set the gauge `name` with given `labels` to `value`.
+  pub fn set(&self, name: &str, labels: &[(&str, &str)], value: i64) {
+    {
+      let mut gauges = self.gauges.lock().unwrap();
+      gauges.insert((name.to_owned(), format_labels(labels)), value);
+    }
+
+    // StatsD would read the negative values as decrements
+    self.emit(name, labels, &value.max(0).to_string(), Kind::Gauge);
+  }
+
+  /// Record `value` into the histogram `name` with given `labels`.
+  pub fn observe(&self, name: &str, labels: &[(&str, &str)], value: f64) {
+    self.record(name, labels, value);
+    self.emit(name, labels, &value.to_string(), Kind::Histogram);
+  }
+
+  /// Like `observe`, but takes a `Duration` that is recorded in seconds,
+  /// and sent to StatsD as a timing in milliseconds.
+  pub fn observe_duration(&self, name: &str, labels: &[(&str, &str)], duration: Duration) {
+    let seconds = duration.as_secs() as f64 + duration.subsec_nanos() as f64 / 1_000_000_000.0;
+    self.record(name, labels, seconds);
+    self.emit(name, labels, &(seconds * 1000.0).to_string(), Kind::Timing);
+  }
+
+  fn record(&self, name: &str, labels: &[(&str, &str)], value: f64) {
+    let mut histograms = self.histograms.lock().unwrap();
+    histograms.entry((name.to_owned(), format_labels(labels)))
+              .or_insert_with(Histogram::default)
+              .observe(value, request_id::current());
+  }
+
+  fn emit(&self, name: &str, labels: &[(&str, &str)], value: &str, kind: Kind) {
+    if let Some(ref statsd) = *self.statsd.lock().unwrap() {
+      statsd.send(name, labels, value, kind);
+    }
+  }
+
+  /// Run the `operation` (i.e.: `search` or `bulk`) of `resource` on `index`, counting it in
+  /// `searchspot_operations_total` and recording how long it took in
+  /// `searchspot_operation_duration_seconds`, both labelled with its `outcome` (`ok` or `error`).
+  pub fn instrument<T, E, F>(&self, resource: &str, index: &str, operation: &str, run: F) -> Result<T, E>
+    where F: FnOnce() -> Result<T, E> {
+    let started_at = Instant::now();
+    let result     = run();
+
+    let outcome = if result.is_ok() { "ok" } else { "error" };
+    let labels  = [("resource", resource), ("index", index), ("operation", operation), ("outcome", outcome)];
+    self.increment("searchspot_operations_total", &labels);
+    self.observe_duration("searchspot_operation_duration_seconds", &labels, started_at.elapsed());
+
+    result
+  }
+
+  /// Return the value of the counter `name` with given `labels`.
+  pub fn counter(&self, name: &str, labels: &[(&str, &str)]) -> u64 {
+    let counters = self.counters.lock().unwrap();
+    counters.get(&(name.to_owned(), format_labels(labels))).cloned().unwrap_or(0)
+  }
+
+  /// Return the value of the gauge `name` with given `labels`.
+  pub fn gauge(&self, name: &str, labels: &[(&str, &str)]) -> i64 {
+    let gauges = self.gauges.lock().unwrap();
+    gauges.get(&(name.to_owned(), format_labels(labels))).cloned().unwrap_or(0)
+  }
+
+  /// Render all the metrics using the Prometheus text format.
+  pub fn render(&self) -> String {
+    self.format(false)
+  }
+
+  /// Render all the metrics using the OpenMetrics text format, with the exemplars.
+  pub fn render_openmetrics(&self) -> String {
+    self.format(true)
+  }
+
+  fn format(&self, openmetrics: bool) -> String {
+    let mut output = String::new();
+
+    {
+      let counters = self.counters.lock().unwrap();
+      let mut last_name = "";
+
+      for (&(ref name, ref labels), value) in counters.iter() {
+        if name != last_name {
+          // OpenMetrics names the counters without their `_total` suffix
+          let family = if openmetrics { name.trim_right_matches("_total") } else { name.as_str() };
+          output.push_str(&format!("# TYPE {} counter\n", family));
+          last_name = name.as_str();
+        }
+
+        output.push_str(&format!("{}{} {}\n", name, wrap_labels(labels), value));
+      }
+    }
+
+    {
+      let gauges = self.gauges.lock().unwrap();
+      let mut last_name = "";
+
+      for (&(ref name, ref labels), value) in gauges.iter() {
+        if name != last_name {
+          output.push_str(&format!("# TYPE {} gauge\n", name));
+          last_name = name.as_str();
+        }
+
+        output.push_str(&format!("{}{} {}\n", name, wrap_labels(labels), value));
+      }
+    }
+
+    {
+      let histograms = self.histograms.lock().unwrap();
+      let mut last_name = "";
+
+      for (&(ref name, ref labels), histogram) in histograms.iter() {
+        if name != last_name {
+          output.push_str(&format!("# TYPE {} histogram\n", name));
+          last_name = name.as_str();
+        }
+
+        for (i, (bound, count)) in BUCKETS.iter().zip(histogram.buckets.iter()).enumerate() {
+          output.push_str(&format!("{}_bucket{} {}{}\n", name, wrap_labels(&join_labels(labels, &format!("le=\"{}\"", bound))), count,
+                                   exemplar(openmetrics, &histogram.exemplars[i])));
+        }
+
+        output.push_str(&format!("{}_bucket{} {}{}\n", name, wrap_labels(&join_labels(labels, "le=\"+Inf\"")), histogram.count,
+                                 exemplar(openmetrics, &histogram.exemplars[BUCKETS.len()])));
+        output.push_str(&format!("{}_sum{} {}\n",    name, wrap_labels(labels), histogram.sum));
+        output.push_str(&format!("{}_count{} {}\n",  name, wrap_labels(labels), histogram.count));
+      }
+    }
+
+    if openmetrics {
+      output.push_str("# EOF\n");
+    }
+
+    output
+  }
+}
+
+fn format_labels(labels: &[(&str, &str)]) -> String {
+  labels.iter()
+        .map(|&(key, value)| format!("{}=\"{}\"", key, value.replace("\\", "\\\\").replace("\"", "\\\"")))
+        .collect::<Vec<String>>()
+        .join(",")
+}
+
+/// Return the `exemplar` of a bucket as OpenMetrics appends it, i.e.: ` # {request_id="2f4b1c1e"} 0.3`.
+fn exemplar(openmetrics: bool, exemplar: &Option<Exemplar>) -> String {
+  match *exemplar {
+    Some(ref exemplar) if openmetrics => format!(" # {{{}}} {}", format_labels(&[(EXEMPLAR_LABEL, &exemplar.request_id)]), exemplar.value),
+    _                                 => "".to_owned()
+  }
+}
+
+fn join_labels(labels: &str, label: &str) -> String {
+  if labels.is_empty() {
+    label.to_owned()
+  }
+  else {
+    format!("{},{}", labels, label)
+  }
+}
+
+fn wrap_labels(labels: &str) -> String {
+  if labels.is_empty() {
+    "".to_owned()
+  }
+  else {
+    format!("{{{}}}", labels)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use metrics::Registry;
+  use request_id;
+
+  #[test]
+  fn test_counters() {
+    let registry = Registry::new();
+    registry.increment("searchspot_http_requests_total", &[("method", "GET"), ("status", "200")]);
+    registry.increment("searchspot_http_requests_total", &[("method", "GET"), ("status", "200")]);
+    registry.increment("searchspot_http_requests_total", &[("method", "POST"), ("status", "201")]);
+
+    assert_eq!(registry.counter("searchspot_http_requests_total", &[("method", "GET"), ("status", "200")]), 2);
+    assert_eq!(registry.counter("searchspot_http_requests_total", &[("method", "DELETE"), ("status", "204")]), 0);
+
+    assert_eq!(registry.render(), "# TYPE searchspot_http_requests_total counter\n\
+      searchspot_http_requests_total{method=\"GET\",status=\"200\"} 2\n\
+      searchspot_http_requests_total{method=\"POST\",status=\"201\"} 1\n");
+  }
+
+  #[test]
+  fn test_gauges() {
+    let registry = Registry::new();
+    registry.set("searchspot_write_queue_depth", &[], 12);
+    registry.set("searchspot_write_queue_depth", &[], 3);
+
+    assert_eq!(registry.gauge("searchspot_write_queue_depth", &[]), 3);
+    assert_eq!(registry.render(), "# TYPE searchspot_write_queue_depth gauge\n\
+      searchspot_write_queue_depth 3\n");
+  }
+
+  #[test]
+  fn test_histograms() {
+    let registry = Registry::new();
+    registry.observe("searchspot_search_duration_seconds", &[], 0.3);
+    registry.observe("searchspot_search_duration_seconds", &[], 20.0);
+
+    let output = registry.render();
+    assert!(output.starts_with("# TYPE searchspot_search_duration_seconds histogram\n"));
+    assert!(output.contains("searchspot_search_duration_seconds_bucket{le=\"0.25\"} 0\n"));
+    assert!(output.contains("searchspot_search_duration_seconds_bucket{le=\"0.5\"} 1\n"));
+    assert!(output.contains("searchspot_search_duration_seconds_bucket{le=\"+Inf\"} 2\n"));
+    assert!(output.contains("searchspot_search_duration_seconds_sum 20.3\n"));
+    assert!(output.contains("searchspot_search_duration_seconds_count 2\n"));
+  }
+
+  #[test]
+  fn test_openmetrics() {
+    let registry = Registry::new();
+    registry.increment("searchspot_http_requests_total", &[("method", "GET")]);
+
+    request_id::set(Some("2f4b1c1e".to_owned()));
+    registry.observe("searchspot_search_duration_seconds", &[], 0.3);
+    request_id::set(Some("7c4b4d2e".to_owned()));
+    registry.observe("searchspot_search_duration_seconds", &[], 20.0);
+    request_id::set(None);
+    registry.observe("searchspot_search_duration_seconds", &[], 0.4);
+
+    let output = registry.render_openmetrics();
+    assert!(output.starts_with("# TYPE searchspot_http_requests counter\nsearchspot_http_requests_total{method=\"GET\"} 1\n"));
+    assert!(output.contains("searchspot_search_duration_seconds_bucket{le=\"0.25\"} 0\n"));
+    assert!(output.contains("searchspot_search_duration_seconds_bucket{le=\"0.5\"} 2 # {request_id=\"2f4b1c1e\"} 0.3\n"));
+    assert!(output.contains("searchspot_search_duration_seconds_bucket{le=\"1\"} 2\n"));
+    assert!(output.contains("searchspot_search_duration_seconds_bucket{le=\"+Inf\"} 3 # {request_id=\"7c4b4d2e\"} 20\n"));
+    assert!(output.ends_with("# EOF\n"));
+
+    assert!(!registry.render().contains("request_id"));
+  }
+
+  #[test]
+  fn test_long_exemplars() {
+    let registry = Registry::new();
+
+    request_id::set(Some("a".repeat(200)));
+    registry.observe("searchspot_search_duration_seconds", &[], 0.3);
+    request_id::set(None);
+
+    let output = registry.render_openmetrics();
+    assert!(output.contains(&format!(" # {{request_id=\"{}\"}} 0.3\n", "a".repeat(118))));
+    assert!(!output.contains(&"a".repeat(119)));
+  }
+
+  #[test]
+  fn test_instrument() {
+    let registry = Registry::new();
+    assert_eq!(registry.instrument("talent", "talents", "search", || Ok::<u32, ()>(42)), Ok(42));
+    assert_eq!(registry.instrument("talent", "talents", "search", || Err::<u32, ()>(())), Err(()));
+    assert!(registry.instrument("talent", "talents", "bulk", || Ok::<u32, ()>(1)).is_ok());
+
+    let labels = |operation, outcome| [("resource", "talent"), ("index", "talents"), ("operation", operation), ("outcome", outcome)];
+    assert_eq!(registry.counter("searchspot_operations_total", &labels("search", "ok")),    1);
+    assert_eq!(registry.counter("searchspot_operations_total", &labels("search", "error")), 1);
+    assert_eq!(registry.counter("searchspot_operations_total", &labels("bulk", "ok")),      1);
+
+    assert!(registry.render().contains("searchspot_operation_duration_seconds_count{resource=\"talent\",index=\"talents\",\
+                                        operation=\"search\",outcome=\"ok\"} 1\n"));
+  }
+}
@@ -0,0 +1,133 @@
+//! The migrations of the mapping and of the settings of the index of the talents,
+//! run in order by `searchspot-admin migrate`. The versions already applied are
+//! recorded in the `_meta` of the mapping (i.e.: `{"migrations": [1, 2]}`), so
+//! that every migration is applied once to each index.
+//!
+//! A migration is a function of its own module, registered in `all` with a
+//! version greater than the last one.
+
+use serde_json;
+use serde_json::Value as JsonValue;
+
+use resource::Resource;
+use resources::Talent;
+use error::Error;
+use es;
+
+use std::collections::BTreeMap;
+
+mod v001_mapping;
+mod v002_computed_score;
+
+pub struct Migration {
+  pub version:     u32,
+  pub description: &'static str,
+  /// Apply the migration to the index given as second argument, on the cluster listening on the first one
+  pub run:         fn(&str, &str) -> Result<(), Error>
+}
+
+/// Return the migrations, in order.
+pub fn all() -> Vec<Migration> {
+  vec![
+    Migration { version: 1, description: "add the missing fields of the mapping", run: v001_mapping::run },
+    Migration { version: 2, description: "map the computed score",                run: v002_computed_score::run }
+  ]
+}
+
+/// Return the versions recorded in the `_meta` of `doc_type` by `GET /:index/_mapping`.
+pub fn applied_versions(live: &JsonValue, doc_type: &str) -> Vec<u32> {
+  meta(live, doc_type).find("migrations")
+                      .and_then(|versions| versions.as_array())
+                      .map(|versions| versions.iter().filter_map(|version| version.as_u64()).map(|version| version as u32).collect())
+                      .unwrap_or(vec![])
+}
+
+/// Return the `_meta` of `doc_type` by `GET /:index/_mapping`, keyed by the real index name.
+fn meta(live: &JsonValue, doc_type: &str) -> JsonValue {
+  live.as_object()
+      .and_then(|indexes| indexes.values().next())
+      .and_then(|index| index.pointer(&format!("/mappings/{}/_meta", doc_type)))
+      .cloned()
+      .unwrap_or(JsonValue::Object(BTreeMap::new()))
+}
+
+/// Return the `migrations` which have not been `applied`, in order.
+pub fn pending<'a>(migrations: &'a [Migration], applied: &[u32]) -> Vec<&'a Migration> {
+  let mut pending = migrations.iter().filter(|migration| !applied.contains(&migration.version)).collect::<Vec<&Migration>>();
+  pending.sort_by_key(|migration| migration.version);
+  pending
+}
+
+/// Apply the pending migrations to `index` on the cluster listening on `url`, recording
+/// each of them once applied, and return them. Nothing is applied if `dry_run`.
+pub fn migrate(url: &str, index: &str, dry_run: bool) -> Result<Vec<(u32, &'static str)>, Error> {
+  let doc_type   = Talent::name();
+  let migrations = all();
+
+  let live        = try!(es::get(url, &es::mapping_path(index)));
+  let mut applied = applied_versions(&live, doc_type);
+  let mut meta    = meta(&live, doc_type);
+
+  let pending = pending(&migrations, &applied).into_iter().map(|migration| (migration.version, migration.description, migration.run)).collect::<Vec<_>>();
+
+  for &(version, description, run) in pending.iter() {
+    if dry_run {
+      continue;
+    }
+
+    try!(run(url, index));
+    applied.push(version);
+    info!("The migration {} ({}) has been applied to {}.", version, description, index);
+
+    // recorded right away, so that a failing migration does not run the previous ones again
+    if let JsonValue::Object(ref mut meta) = meta {
+      meta.insert("migrations".to_owned(), serde_json::to_value(&applied));
+    }
+    let body = btreemap! { "_meta" => meta.to_owned() };
+    try!(es::put(url, &es::type_mapping_path(index, doc_type), &serde_json::to_string(&body).unwrap()));
+  }
+
+  Ok(pending.into_iter().map(|(version, description, _)| (version, description)).collect())
+}
+
+#[cfg(test)]
+mod tests {
+  use migrations::{all, applied_versions, pending, Migration};
+  use error::Error;
+
+  use serde_json;
+
+  fn noop(_: &str, _: &str) -> Result<(), Error> {
+    Ok(())
+  }
+
+  #[test]
+  fn test_applied_versions() {
+    let live = serde_json::from_str("{\"talents_v2\": {\"mappings\": {\"talent\": {\"_meta\": {\"migrations\": [1, 3]}, \"properties\": {}}}}}").unwrap();
+
+    assert_eq!(applied_versions(&live, "talent"), vec![1, 3]);
+    assert!(applied_versions(&serde_json::from_str("{\"talents_v2\": {\"mappings\": {}}}").unwrap(), "talent").is_empty());
+  }
+
+  #[test]
+  fn test_pending() {
+    let migrations = vec![
+      Migration { version: 3, description: "third",  run: noop },
+      Migration { version: 1, description: "first",  run: noop },
+      Migration { version: 2, description: "second", run: noop }
+    ];
+
+    assert_eq!(pending(&migrations, &[2]).iter().map(|migration| migration.version).collect::<Vec<u32>>(), vec![1, 3]);
+    assert!(pending(&migrations, &[1, 2, 3]).is_empty());
+  }
+
+  #[test]
+  fn test_all() {
+    let versions = all().iter().map(|migration| migration.version).collect::<Vec<u32>>();
+
+    let mut sorted = versions.to_owned();
+    sorted.sort();
+    sorted.dedup();
+    assert_eq!(versions, sorted);
+  }
+}
@@ -0,0 +1,21 @@
+//! Add the fields of the mapping of the talents missing from the index
+//! (i.e.: the ones added since it has been created), which is the baseline
+//! of the indexes created before the migrations.
+
+use serde_json;
+
+use resource::Resource;
+use resources::Talent;
+use error::Error;
+use es;
+
+use std::collections::BTreeMap;
+
+pub fn run(url: &str, index: &str) -> Result<(), Error> {
+  for (doc_type, fields) in Talent::mapping().iter() {
+    let body = btreemap! { "properties" => fields.iter().collect::<BTreeMap<_, _>>() };
+    try!(es::put(url, &es::type_mapping_path(index, doc_type), &serde_json::to_string(&body).unwrap()));
+  }
+
+  Ok(())
+}
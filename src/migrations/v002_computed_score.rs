@@ -0,0 +1,23 @@
+//! Map the `computed_score` of the talents (see `scoring`), which the talents
+//! indexed before it lack until the index is reindexed.
+
+use serde_json;
+
+use resource::Resource;
+use resources::Talent;
+use error::Error;
+use es;
+
+pub fn run(url: &str, index: &str) -> Result<(), Error> {
+  let body = btreemap! {
+    "properties" => btreemap! {
+      "computed_score" => btreemap! {
+        "type"  => "float",
+        "index" => "not_analyzed"
+      }
+    }
+  };
+
+  try!(es::put(url, &es::type_mapping_path(index, Talent::name()), &serde_json::to_string(&body).unwrap()));
+  Ok(())
+}
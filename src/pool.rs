@@ -0,0 +1,119 @@
+//! A fixed-size pool of ElasticSearch clients, so that the handlers
+//! can reach the cluster concurrently instead of sharing one client.
+//!
+//! The items taken out of the pool keep it alive, so that a pool can be
+//! dropped (i.e.: a node leaving the cluster) while some of them are used.
+
+use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, Mutex, Condvar};
+
+struct Shared<T> {
+  items:     Mutex<Vec<T>>,
+  available: Condvar
+}
+
+pub struct Pool<T> {
+  shared: Arc<Shared<T>>
+}
+
+impl<T> Pool<T> {
+  pub fn new(items: Vec<T>) -> Pool<T> {
+    Pool {
+      shared: Arc::new(Shared {
+        items:     Mutex::new(items),
+        available: Condvar::new()
+      })
+    }
+  }
+
+  /// Take an item out of the pool, waiting for one to be given
+  /// back if all of them are being used.
+  pub fn get(&self) -> Pooled<T> {
+    let mut items = self.shared.items.lock().unwrap();
+
+    loop {
+      if let Some(item) = items.pop() {
+        return Pooled { shared: self.shared.clone(), item: Some(item) };
+      }
+
+      items = self.shared.available.wait(items).unwrap();
+    }
+  }
+
+  /// Return how many items are not being used.
+  pub fn idle(&self) -> usize {
+    self.shared.items.lock().unwrap().len()
+  }
+}
+
+/// An item taken from the pool, given back once dropped.
+pub struct Pooled<T> {
+  shared: Arc<Shared<T>>,
+  item:   Option<T>
+}
+
+impl<T> Deref for Pooled<T> {
+  type Target = T;
+
+  fn deref(&self) -> &T {
+    self.item.as_ref().unwrap()
+  }
+}
+
+impl<T> DerefMut for Pooled<T> {
+  fn deref_mut(&mut self) -> &mut T {
+    self.item.as_mut().unwrap()
+  }
+}
+
+impl<T> Drop for Pooled<T> {
+  fn drop(&mut self) {
+    if let Some(item) = self.item.take() {
+      self.shared.items.lock().unwrap().push(item);
+      self.shared.available.notify_one();
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use pool::Pool;
+
+  use std::sync::Arc;
+  use std::thread;
+
+  #[test]
+  fn test_get() {
+    let pool = Pool::new(vec![1, 2]);
+
+    {
+      let first  = pool.get();
+      let second = pool.get();
+      assert_eq!(*first + *second, 3);
+      assert_eq!(pool.idle(), 0);
+    }
+
+    assert_eq!(pool.idle(), 2);
+  }
+
+  #[test]
+  fn test_wait() {
+    let pool  = Arc::new(Pool::new(vec![0]));
+    let taken = pool.get();
+
+    let waiting = pool.clone();
+    let handle  = thread::spawn(move || *waiting.get() + 1);
+
+    drop(taken);
+    assert_eq!(handle.join().unwrap(), 1);
+  }
+
+  #[test]
+  fn test_outlive() {
+    let pool  = Pool::new(vec![42]);
+    let taken = pool.get();
+
+    drop(pool);
+    assert_eq!(*taken, 42);
+  }
+}
@@ -0,0 +1,103 @@
+//! The documents being indexed, counted so that the writes are refused
+//! (with `429`) instead of piling up once too many of them are queued.
+
+use metrics::METRICS;
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+lazy_static! {
+  /// The queue shared across the writing handlers.
+  pub static ref QUEUE: Queue = Queue::new(0);
+}
+
+pub struct Queue {
+  depth:    AtomicUsize,
+  capacity: AtomicUsize
+}
+
+/// The room taken in the queue by a request, given back once dropped.
+pub struct Reservation<'a> {
+  queue:     &'a Queue,
+  documents: usize
+}
+
+impl Queue {
+  /// Accept up to `capacity` documents, or any number of them if `0`.
+  pub fn new(capacity: usize) -> Queue {
+    Queue {
+      depth:    AtomicUsize::new(0),
+      capacity: AtomicUsize::new(capacity)
+    }
+  }
+
+  pub fn configure(&self, capacity: usize) {
+    self.capacity.store(capacity, Ordering::SeqCst);
+  }
+
+  /// Reserve room for `documents`, considering as queued also the `pending`
+  /// ones kept elsewhere (i.e.: by the `Coalescer`). Return the current depth
+  /// if they do not fit, unless the queue is empty, so that any batch can pass.
+  pub fn reserve(&self, documents: usize, pending: usize) -> Result<Reservation, usize> {
+    let capacity = self.capacity();
+
+    loop {
+      let depth = self.depth.load(Ordering::SeqCst);
+      let total = depth + pending;
+
+      if capacity > 0 && total > 0 && total + documents > capacity {
+        return Err(total);
+      }
+
+      if self.depth.compare_and_swap(depth, depth + documents, Ordering::SeqCst) == depth {
+        METRICS.set("searchspot_write_queue_depth", &[], (depth + documents) as i64);
+
+        return Ok(Reservation {
+          queue:     self,
+          documents: documents
+        });
+      }
+    }
+  }
+
+  /// Return how many documents are being indexed.
+  pub fn depth(&self) -> usize {
+    self.depth.load(Ordering::SeqCst)
+  }
+
+  pub fn capacity(&self) -> usize {
+    self.capacity.load(Ordering::SeqCst)
+  }
+}
+
+impl<'a> Drop for Reservation<'a> {
+  fn drop(&mut self) {
+    let depth = self.queue.depth.fetch_sub(self.documents, Ordering::SeqCst) - self.documents;
+    METRICS.set("searchspot_write_queue_depth", &[], depth as i64);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use queue::Queue;
+
+  #[test]
+  fn test_reserve() {
+    let queue = Queue::new(10);
+
+    // any batch passes through an empty queue
+    let first = queue.reserve(12, 0).ok().unwrap();
+    assert_eq!(queue.depth(), 12);
+    assert_eq!(queue.reserve(1, 0).err(), Some(12));
+    drop(first);
+
+    let second = queue.reserve(6, 0).ok().unwrap();
+    assert_eq!(queue.reserve(4, 0).map(|_| ()).ok(), Some(()));
+    assert_eq!(queue.reserve(4, 3).err(), Some(9));
+    drop(second);
+    assert_eq!(queue.depth(), 0);
+
+    // unbounded
+    let queue = Queue::new(0);
+    assert!(queue.reserve(1000, 1000).is_ok());
+  }
+}
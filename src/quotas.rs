@@ -0,0 +1,118 @@
+//! The search quotas of the companies: once `[quotas]` is enabled, the searches
+//! are refused with `429` as soon as the company sending them has sent as many
+//! searches as its daily quota today, or as its monthly quota this calendar
+//! month (UTC), as counted by `usage`: across the instances when they share a
+//! Redis server, by each of them otherwise.
+//!
+//! The company is the `company_id` claim of the JWT authorizing the search,
+//! which its caller cannot change, or else the `company_id` param of the
+//! searches sent without a JWT (i.e. by the main app, through TOTP). The
+//! searches that are not sent for a company are neither limited nor counted.
+
+use chrono::{DateTime, Datelike, Duration, TimeZone, UTC};
+
+use config::Quotas;
+use usage::Usage;
+
+/// The quota a company has run out of, i.e.: `{"company_id": "42", "period": "daily",
+/// "limit": 1000, "used": 1000, "resets_at": "2017-03-05T00:00:00+00:00", "retry_after": 42960}`.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Exceeded {
+  pub company_id:  String,
+  pub period:      &'static str, // "daily" or "monthly"
+  pub limit:       u64,
+  pub used:        u64,
+  pub resets_at:   String,
+  pub retry_after: u64 // in seconds
+}
+
+impl Exceeded {
+  pub fn message(&self) -> String {
+    format!("The company {} has run out of its {} quota of {} searches, until {}.",
+            self.company_id, self.period, self.limit, self.resets_at)
+  }
+}
+
+/// Return the quota of `quotas` that `company_id` has run out of at `now`, if any.
+pub fn check(quotas: &Quotas, usage: &Usage, company_id: &str, now: DateTime<UTC>) -> Result<(), Exceeded> {
+  if !quotas.enabled {
+    return Ok(());
+  }
+
+  let (daily, monthly) = quotas.limits(company_id);
+  let today      = now.date().and_hms(0, 0, 0);
+  let this_month = UTC.ymd(now.year(), now.month(), 1).and_hms(0, 0, 0);
+
+  let periods = vec![("daily",   daily,   today,      today + Duration::days(1)),
+                     ("monthly", monthly, this_month, next_month(now))];
+
+  for (period, limit, since, resets_at) in periods {
+    if limit == 0 {
+      continue;
+    }
+
+    let used = usage.since(company_id, since, now);
+    if used >= limit {
+      return Err(Exceeded {
+        company_id:  company_id.to_owned(),
+        period:      period,
+        limit:       limit,
+        used:        used,
+        resets_at:   resets_at.to_rfc3339(),
+        retry_after: (resets_at - now).num_seconds().max(1) as u64
+      });
+    }
+  }
+
+  Ok(())
+}
+
+/// Return the beginning of the month following the one of `now`.
+fn next_month(now: DateTime<UTC>) -> DateTime<UTC> {
+  let (year, month) = if now.month() == 12 { (now.year() + 1, 1) } else { (now.year(), now.month() + 1) };
+  UTC.ymd(year, month, 1).and_hms(0, 0, 0)
+}
+
+#[cfg(test)]
+mod tests {
+  use quotas::{check, next_month};
+  use config::{Quotas, Quota};
+  use usage::Usage;
+
+  use chrono::{Duration, TimeZone, UTC};
+
+  #[test]
+  fn test_check() {
+    let mut quotas = Quotas { enabled: true, daily: 2, monthly: 3, ..Default::default() };
+    quotas.companies.insert("7".to_owned(), Quota { daily: Some(0), monthly: None });
+
+    let usage = Usage::new();
+    let now   = UTC.ymd(2017, 3, 4).and_hms(12, 0, 0);
+
+    usage.record("42", now - Duration::days(4)); // in February
+    usage.record("42", now - Duration::days(1));
+    usage.record("42", now);
+    assert_eq!(check(&quotas, &usage, "42", now), Ok(()));
+
+    usage.record("42", now);
+    let exceeded = check(&quotas, &usage, "42", now).unwrap_err();
+    assert_eq!((exceeded.period, exceeded.limit, exceeded.used), ("daily", 2, 2));
+    assert_eq!(exceeded.resets_at, "2017-03-05T00:00:00+00:00");
+    assert_eq!(exceeded.retry_after, 12 * 3600);
+
+    let tomorrow = now + Duration::days(1);
+    assert_eq!(check(&quotas, &usage, "42", tomorrow).unwrap_err().period, "monthly");
+
+    for _ in 0..3 {
+      usage.record("7", now);
+    }
+    assert_eq!(check(&quotas, &usage, "7", now).unwrap_err().period, "monthly");
+    assert_eq!(check(&Quotas { enabled: false, ..quotas.to_owned() }, &usage, "42", now), Ok(()));
+  }
+
+  #[test]
+  fn test_next_month() {
+    assert_eq!(next_month(UTC.ymd(2017, 3, 4).and_hms(12, 0, 0)),   UTC.ymd(2017, 4, 1).and_hms(0, 0, 0));
+    assert_eq!(next_month(UTC.ymd(2017, 12, 31).and_hms(23, 0, 0)), UTC.ymd(2018, 1, 1).and_hms(0, 0, 0));
+  }
+}
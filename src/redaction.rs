@@ -0,0 +1,183 @@
+//! The redaction of the personal data: once `[gdpr]` is enabled, the fields
+//! listed by `gdpr.fields` (i.e. the free text of `summary`) are stripped
+//! from, or replaced by their salted hash in, the documents returned by the
+//! searches, the fetches and the exports, unless the caller is granted the
+//! `pii` scope. Their highlights are always stripped, since hashing the
+//! fragments would not hide what they reveal.
+//! The IDs of the recruiters recorded by the analytics are always redacted.
+//! Hashing keeps the values comparable (i.e. to deduplicate them) without
+//! revealing them, provided that the salt is kept secret.
+
+use crypto::digest::Digest;
+use crypto::sha2::Sha256;
+
+use serde_json::Value as JsonValue;
+
+use config::Gdpr;
+
+/// How the personal fields are redacted.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Mode {
+  Strip,
+  Hash
+}
+
+impl Mode {
+  pub fn parse(mode: &str) -> Option<Mode> {
+    match mode {
+      "strip" => Some(Mode::Strip),
+      "hash"  => Some(Mode::Hash),
+      _       => None
+    }
+  }
+}
+
+/// The redaction applied to the responses of the callers without the `pii` scope.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Redaction {
+  pub mode:   Mode,
+  pub fields: Vec<String>,
+  pub salt:   String
+}
+
+impl Redaction {
+  /// Return the redaction configured by `gdpr`, or `None` if the personal data are never redacted.
+  pub fn new(gdpr: &Gdpr) -> Option<Redaction> {
+    if !gdpr.enabled {
+      return None;
+    }
+
+    Mode::parse(&gdpr.mode).map(|mode| Redaction {
+      mode:   mode,
+      fields: gdpr.fields.to_owned(),
+      salt:   gdpr.salt.to_owned()
+    })
+  }
+
+  /// Return the hex-encoded hash of `value` salted by `salt`.
+  pub fn hash(&self, value: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.input_str(&self.salt);
+    hasher.input_str(value);
+    hasher.result_str()
+  }
+
+  /// Return `value` redacted, or `None` if it is stripped.
+  pub fn value(&self, value: &str) -> Option<String> {
+    match self.mode {
+      Mode::Strip => None,
+      Mode::Hash  => Some(self.hash(value))
+    }
+  }
+
+  /// Redact the personal fields of `document`, stripping them or hashing their strings.
+  pub fn redact(&self, document: &mut JsonValue) {
+    let object = match *document {
+      JsonValue::Object(ref mut object) => object,
+      _                                 => return
+    };
+
+    for field in &self.fields {
+      if self.mode == Mode::Strip {
+        object.remove(field);
+        continue;
+      }
+
+      if let Some(value) = object.get_mut(field) {
+        match *value {
+          JsonValue::String(ref mut string) => *string = self.hash(string),
+          JsonValue::Array(ref mut values)  => for value in values.iter_mut() {
+                                                 if let JsonValue::String(ref mut string) = *value {
+                                                   *string = self.hash(string);
+                                                 }
+                                               },
+          _                                 => ()
+        }
+      }
+    }
+  }
+
+  /// Redact every document nested in `value` (i.e. the results of a search), and strip
+  /// the personal fields from their `highlight`.
+  pub fn redact_nested(&self, value: &mut JsonValue) {
+    match *value {
+      JsonValue::Object(ref mut object) => {
+        if let Some(&mut JsonValue::Object(ref mut highlight)) = object.get_mut("highlight") {
+          for field in &self.fields {
+            highlight.remove(field);
+          }
+        }
+
+        for (_, nested) in object.iter_mut() {
+          self.redact_nested(nested);
+        }
+      },
+      JsonValue::Array(ref mut values) => {
+        for nested in values.iter_mut() {
+          self.redact_nested(nested);
+        }
+        return;
+      },
+      _ => return
+    }
+
+    self.redact(value);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use redaction::{Mode, Redaction};
+  use config::Gdpr;
+
+  use serde_json;
+
+  fn redaction(mode: Mode) -> Redaction {
+    Redaction { mode: mode, fields: vec!["summary".to_owned(), "emails".to_owned()], salt: "pepper".to_owned() }
+  }
+
+  #[test]
+  fn test_new() {
+    assert_eq!(Redaction::new(&Gdpr::default()), None);
+
+    let gdpr = Gdpr { enabled: true, ..Default::default() };
+    assert_eq!(Redaction::new(&gdpr).map(|redaction| redaction.mode), Some(Mode::Strip));
+  }
+
+  #[test]
+  fn test_strip() {
+    let mut document = serde_json::from_str("{\"id\": 42, \"summary\": \"Call me at 555-0100\"}").unwrap();
+    redaction(Mode::Strip).redact(&mut document);
+
+    assert_eq!(serde_json::to_string(&document).unwrap(), "{\"id\":42}");
+    assert_eq!(redaction(Mode::Strip).value("42"), None);
+  }
+
+  #[test]
+  fn test_hash() {
+    let redaction = redaction(Mode::Hash);
+
+    let mut document = serde_json::from_str("{\"id\": 42, \"summary\": \"Rustacean\", \"emails\": [\"a@b.c\"]}").unwrap();
+    redaction.redact(&mut document);
+
+    assert_eq!(document.find("summary").and_then(|summary| summary.as_str()), Some(&*redaction.hash("Rustacean")));
+    assert_eq!(document.pointer("/emails/0").and_then(|email| email.as_str()), Some(&*redaction.hash("a@b.c")));
+    assert_eq!(document.find("id").and_then(|id| id.as_u64()), Some(42));
+
+    assert_eq!(redaction.hash("Rustacean").len(), 64);
+    assert!(redaction.hash("Rustacean") != Redaction { salt: "salt".to_owned(), ..redaction.to_owned() }.hash("Rustacean"));
+  }
+
+  #[test]
+  fn test_redact_nested() {
+    let mut results = serde_json::from_str("{\"total\": 1, \"talents\": [{\"talent\": {\"id\": 42, \"summary\": \"Rustacean\"}, \
+                                            \"highlight\": {\"summary\": [\"Rustacean\"], \"headline\": [\"Rust\"]}}]}").unwrap();
+    redaction(Mode::Hash).redact_nested(&mut results);
+
+    assert_eq!(results.pointer("/talents/0/talent/summary").and_then(|summary| summary.as_str()),
+               Some(&*redaction(Mode::Hash).hash("Rustacean")));
+    assert_eq!(results.pointer("/talents/0/highlight/summary"), None);
+    assert!(results.pointer("/talents/0/highlight/headline").is_some());
+    assert_eq!(results.find("total").and_then(|total| total.as_u64()), Some(1));
+  }
+}
@@ -0,0 +1,116 @@
+//! The Redis server shared by the instances (`cache.redis_url`), which holds
+//! the cached searches and the search volume of the companies.
+//!
+//! Every thread keeps its own connection, opened again after any failure, so
+//! that a slow command only holds the thread sending it. Connecting, reading
+//! and writing give up after `cache.redis_timeout_ms`, so that a Redis server
+//! that stopped answering slows down the searches instead of blocking them.
+
+use hyper::Url;
+
+use redis::{Client, Connection, ErrorKind, RedisError, RedisResult};
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+/// The port of the Redis servers given without one.
+const DEFAULT_PORT: u16 = 6379;
+
+thread_local! {
+  /// The connections of the current thread, by URL.
+  static CONNECTIONS: RefCell<HashMap<String, Connection>> = RefCell::new(HashMap::new());
+}
+
+/// The Redis server at `url`, whose commands time out after `timeout`.
+#[derive(Clone)]
+pub struct RedisStore {
+  url:     String,
+  client:  Client,
+  timeout: Duration
+}
+
+impl RedisStore {
+  pub fn open(url: &str, timeout: Duration) -> RedisResult<RedisStore> {
+    Ok(RedisStore {
+      url:     url.to_owned(),
+      client:  try!(Client::open(url)),
+      timeout: timeout
+    })
+  }
+
+  pub fn client(&self) -> &Client {
+    &self.client
+  }
+
+  /// Run `command` on the connection of the current thread, opening it if needed.
+  /// The connection is dropped if the command fails, since it may be broken.
+  pub fn query<T, F>(&self, command: F) -> RedisResult<T>
+    where F: FnOnce(&Connection) -> RedisResult<T> {
+    CONNECTIONS.with(|connections| {
+      let mut connections = connections.borrow_mut();
+
+      if !connections.contains_key(&self.url) {
+        let connection = try!(self.connect());
+        connections.insert(self.url.to_owned(), connection);
+      }
+
+      let result = command(&connections[&self.url]);
+      if result.is_err() {
+        connections.remove(&self.url);
+      }
+
+      result
+    })
+  }
+
+  /// Open a new connection, whose reads and writes time out after `timeout`.
+  pub fn connect(&self) -> RedisResult<Connection> {
+    try!(self.probe());
+
+    let connection = try!(self.client.get_connection());
+    try!(connection.set_read_timeout(Some(self.timeout)));
+    try!(connection.set_write_timeout(Some(self.timeout)));
+    Ok(connection)
+  }
+
+  /// Check that the server can be reached within `timeout`, since `redis`
+  /// waits for the system to give up when it cannot.
+  fn probe(&self) -> RedisResult<()> {
+    let url = match Url::parse(&self.url) {
+      Ok(ref url) if url.scheme() == "redis" => url.to_owned(),
+      _                                      => return Ok(()) // i.e. a Unix socket
+    };
+
+    let host = match url.host_str() {
+      Some(host) => host.to_owned(),
+      None       => return Ok(())
+    };
+
+    for addr in try!((&*host, url.port().unwrap_or(DEFAULT_PORT)).to_socket_addrs()) {
+      if TcpStream::connect_timeout(&addr, self.timeout).is_ok() {
+        return Ok(());
+      }
+    }
+
+    Err(RedisError::from((ErrorKind::IoError, "The Redis server cannot be reached")))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use redis_store::RedisStore;
+
+  use std::time::{Duration, Instant};
+
+  #[test]
+  fn test_unreachable() {
+    // nothing listens on the port 1 of the loopback
+    let store = RedisStore::open("redis://127.0.0.1:1/0", Duration::from_millis(200)).unwrap();
+
+    let started = Instant::now();
+    assert!(store.query(|connection| ::redis::cmd("PING").query::<String>(connection)).is_err());
+    assert!(started.elapsed() < Duration::from_secs(5));
+  }
+}
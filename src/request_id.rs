@@ -0,0 +1,67 @@
+//! The identifier of the request being handled by the current thread,
+//! used to correlate the log lines and the ElasticSearch operations.
+
+use iron::typemap::Key;
+
+use uuid::Uuid;
+
+use std::cell::RefCell;
+
+/// The header carrying the request ID, both in requests and responses.
+pub const REQUEST_ID_HEADER: &'static str = "X-Request-Id";
+
+/// The longest request ID accepted from the clients.
+const MAX_LENGTH: usize = 200;
+
+thread_local! {
+  static CURRENT: RefCell<Option<String>> = RefCell::new(None);
+}
+
+/// The request ID, stored inside the request extensions.
+pub struct RequestId;
+
+impl Key for RequestId { type Value = String; }
+
+/// Return a new random request ID.
+pub fn generate() -> String {
+  Uuid::new_v4().hyphenated().to_string()
+}
+
+/// Return `true` if the ID given by a client can be used as it is,
+/// which avoids forging log lines through it.
+pub fn is_valid(id: &str) -> bool {
+  !id.is_empty() && id.len() <= MAX_LENGTH && id.chars().all(|c| c > ' ' && c <= '~')
+}
+
+/// Set the ID of the request being handled by the current thread.
+pub fn set(id: Option<String>) {
+  CURRENT.with(|current| *current.borrow_mut() = id);
+}
+
+/// Return the ID of the request being handled by the current thread, if any.
+pub fn current() -> Option<String> {
+  CURRENT.with(|current| current.borrow().to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+  use request_id;
+
+  #[test]
+  fn test_is_valid() {
+    assert!(request_id::is_valid("2f4b1c1e-7c4b-4d2e-9d1a-3f6f3b7c9a10"));
+    assert!(request_id::is_valid(&request_id::generate()));
+    assert!(!request_id::is_valid(""));
+    assert!(!request_id::is_valid("forged\nINFO - line"));
+    assert!(!request_id::is_valid(&"a".repeat(201)));
+  }
+
+  #[test]
+  fn test_current() {
+    request_id::set(Some("abc".to_owned()));
+    assert_eq!(request_id::current(), Some("abc".to_owned()));
+
+    request_id::set(None);
+    assert_eq!(request_id::current(), None);
+  }
+}
@@ -4,26 +4,317 @@ use serde::ser::Serialize;
 use rs_es::Client;
 use rs_es::operations::bulk::BulkResult;
 use rs_es::operations::delete::DeleteResult;
-use rs_es::operations::mapping::MappingResult;
-use rs_es::error::EsError;
+use rs_es::operations::index::IndexResult;
+use rs_es::operations::mapping::{Mapping, MappingResult};
 
 use params::*;
 
+use serde_json::Value as JsonValue;
+
+use schema::{Param, FieldError};
+use clock::{Clock, SystemClock};
+use error::Error;
+use backend::EsBackend;
+use ltr;
+use ltr::Rescoring;
+use config::{Decay, Features, ResourceSettings, ScoreThreshold, SearchSettings};
+
 use std::any::Any;
+use std::collections::BTreeMap;
 use std::fmt::Debug;
+use std::sync::Arc;
+
+/// The position of a page of results inside the whole set of found resources.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Pagination {
+  pub offset:   u64,
+  pub per_page: u64,
+  pub total:    u64
+}
+
+impl Pagination {
+  pub fn new(offset: u64, per_page: u64, total: u64) -> Pagination {
+    Pagination {
+      offset:   offset,
+      per_page: per_page,
+      total:    total
+    }
+  }
+
+  /// Return the offset of the next page, if any.
+  pub fn next(&self) -> Option<u64> {
+    if self.per_page > 0 && self.offset + self.per_page < self.total {
+      Some(self.offset + self.per_page)
+    }
+    else {
+      None
+    }
+  }
+
+  /// Return the offset of the previous page, if any.
+  pub fn prev(&self) -> Option<u64> {
+    if self.offset > 0 {
+      Some(self.offset.saturating_sub(self.per_page))
+    }
+    else {
+      None
+    }
+  }
+
+  /// Return the offset of the last page.
+  pub fn last(&self) -> u64 {
+    if self.per_page == 0 || self.total == 0 {
+      return 0;
+    }
+
+    (self.total - 1) / self.per_page * self.per_page
+  }
+}
+
+/// Implemented by `Resource::Results` to expose their `Pagination`, if any.
+pub trait Paginated {
+  fn pagination(&self) -> Option<Pagination> {
+    None
+  }
+}
+
+/// Implemented by `Resource::Results` to tell whether ElasticSearch
+/// reached the timeout before visiting every shard, and how long it took.
+pub trait TimedOut {
+  fn timed_out(&self) -> bool {
+    false
+  }
+
+  /// Return the milliseconds spent by ElasticSearch, if known.
+  fn took(&self) -> Option<u64> {
+    None
+  }
+}
+
+/// Return whether the `ignore_unavailable` param asks to skip the searched indexes that
+/// do not exist, rather than failing the search. `rs-es` cannot send it, hence the searches
+/// asking for it are sent as returned by `Resource::preview`.
+pub fn ignores_unavailable(params: &Map) -> bool {
+  match params.get("ignore_unavailable") {
+    Some(&Value::Boolean(ignore))    => ignore,
+    Some(&Value::String(ref ignore)) => ignore == "true",
+    _                                => false
+  }
+}
+
+/// The factors multiplying the score of the matches inside each field.
+pub type Boosts = BTreeMap<String, f64>;
+
+/// The limits to the complexity of the queries built from the params,
+/// beyond which the searches are refused.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct QueryLimits {
+  /// The maximum number of keywords
+  pub max_keywords: usize,
+  /// The maximum number of values of a single param
+  pub max_terms:    usize,
+  /// The maximum number of clauses of the whole query
+  pub max_clauses:  usize
+}
+
+impl QueryLimits {
+  pub fn new(search: &SearchSettings) -> QueryLimits {
+    QueryLimits {
+      max_keywords: search.max_keywords,
+      max_terms:    search.max_terms,
+      max_clauses:  search.max_clauses
+    }
+  }
+}
+
+impl Default for QueryLimits {
+  fn default() -> QueryLimits {
+    QueryLimits::new(&SearchSettings::default())
+  }
+}
 
-pub trait Resource: Send + Sync + Any + Serialize + Deserialize + Debug {
-  type Results: Serialize + Deserialize;
+/// The options given to `Resource::search` by the server.
+#[derive(Debug, Clone)]
+pub struct SearchOptions {
+  /// How long (in milliseconds) ElasticSearch can spend on the search
+  pub timeout:      Option<u64>,
+  /// The minimum score of the results, replacing the one of the resource
+  pub min_score:    Option<f64>,
+  /// The searches the minimum score applies to
+  pub score_threshold: ScoreThreshold,
+  /// The maximum number of results per page
+  pub max_per_page: Option<u64>,
+  /// The boosts of the fields matched by the full text search
+  pub boosts:       Boosts,
+  /// How much the fields other than the best matching one count
+  pub tie_breaker:  f64,
+  /// How many keywords must be matched (i.e. "75%")
+  pub minimum_should_match: Option<String>,
+  /// The decay of the score as a date field gets older
+  pub decay:        Option<Decay>,
+  /// The experimental behaviors that are enabled
+  pub features:     Features,
+  /// How much the `computed_score` boosts the full text searches, once enabled
+  pub scoring_boost: f64,
+  /// How the top full text matches are rescored by the `ltr_rescoring` feature
+  pub rescoring:    Option<Rescoring>,
+  /// The clock giving the current time, the one of the system by default
+  pub clock:        Arc<Clock>,
+  /// The limits to the complexity of the query
+  pub limits:       QueryLimits
+}
 
-  /// Respond to GET requests returning an array with found ids
-  fn search(es: &mut Client, default_index: &str, params: &Map) -> Self::Results;
+impl Default for SearchOptions {
+  fn default() -> SearchOptions {
+    SearchOptions {
+      timeout:         None,
+      min_score:       None,
+      score_threshold: ScoreThreshold::default(),
+      max_per_page:    None,
+      boosts:          Boosts::new(),
+      tie_breaker:     0.0,
+      minimum_should_match: None,
+      decay:           None,
+      features:        Features::default(),
+      scoring_boost:   1.0,
+      rescoring:       None,
+      clock:           Arc::new(SystemClock),
+      limits:          QueryLimits::default()
+    }
+  }
+}
+
+/// How many resources have a given value of a facet.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct FacetCount {
+  pub value: String,
+  pub count: u64
+}
+
+/// The counts of every facet, sorted by descending count.
+pub type Facets = BTreeMap<String, Vec<FacetCount>>;
+
+pub trait Resource: Send + Sync + Any + Serialize + Deserialize + Debug + Clone {
+  type Results: Serialize + Deserialize + Paginated + TimedOut;
+
+  /// Return the name of the resource, used by its `[resources.<name>]` configuration
+  fn name() -> &'static str;
+
+  /// Return the request that `search_with` sends to ElasticSearch
+  fn preview(default_index: &str, params: &Map, options: &SearchOptions) -> Result<JsonValue, Error>;
+
+  /// Respond to GET requests: send the request returned by `preview`
+  /// through `backend`, returning the results of its response
+  fn search_with<B: EsBackend>(backend: &B, default_index: &str, params: &Map, options: &SearchOptions) -> Result<Self::Results, Error> {
+    let search   = try!(Self::preview(default_index, params, options));
+    let response = try!(backend.search(&search));
+
+    if let Some(ref rescoring) = options.rescoring {
+      if rescoring.log_features && options.features.ltr_rescoring {
+        ltr::log(&rescoring.model, &response);
+      }
+    }
+
+    Self::results(params, options, response)
+  }
+
+  /// Return the results of a search from the response of ElasticSearch
+  /// to the request returned by `preview`
+  fn results(params: &Map, options: &SearchOptions, response: JsonValue) -> Result<Self::Results, Error>;
+
+  /// Return the counts of the values of each facet among the resources
+  /// matching `params` at the time of `options.clock`, ignoring the filter
+  /// on the facet itself
+  fn facets(es: &mut Client, default_index: &str, params: &Map, options: &SearchOptions) -> Result<Facets, Error>;
+
+  /// Return the schema of the params accepted by `search`
+  fn search_params() -> Vec<Param>;
 
   /// Respond to POST requests indexing given entity
-  fn index(es: &mut Client, index: &str, resources: Vec<Self>) -> Result<BulkResult, EsError>;
+  fn index(es: &mut Client, index: &str, resources: Vec<Self>) -> Result<BulkResult, Error>;
+
+  /// Index `resources` with given external versions (i.e.: the time of their last update),
+  /// leaving alone the ones already stored with a later version
+  fn index_versioned(es: &mut Client, index: &str, resources: Vec<(Self, u64)>) -> Result<BulkResult, Error>;
+
+  /// Return the resource associated to given id, if any
+  fn get(es: &mut Client, id: &str, index: &str) -> Result<Option<Self>, Error>;
+
+  /// Respond to PATCH requests replacing the resource associated to given id
+  fn update(es: &mut Client, id: &str, index: &str, resource: Self) -> Result<IndexResult, Error>;
 
   /// Respond to DELETE requests on given id deleting it from given index
-  fn delete(es: &mut Client, id: &str, index: &str) -> Result<DeleteResult, EsError>;
+  fn delete(es: &mut Client, id: &str, index: &str) -> Result<DeleteResult, Error>;
+
+  /// Delete the resource associated to given id with given external version (i.e.: the time
+  /// of its deletion), leaving it alone if it has been stored with a later version
+  fn delete_versioned(es: &mut Client, id: &str, index: &str, version: u64) -> Result<BulkResult, Error>;
+
+  /// Return the id used to store the resource on ElasticSearch
+  fn id(&self) -> String;
+
+  /// Return whether the resource can be shown by GET requests on its id
+  fn is_visible(&self) -> bool;
+
+  /// Return the problems of the values that cannot be told by the schema alone,
+  /// used to refuse the resource before indexing it
+  fn validate(&self) -> Vec<FieldError>;
+
+  /// Return the fields whose value differs from the `stored` resource with the same id
+  /// among the ones not expected to change once indexed, used to find the duplicated ids
+  fn conflicts(&self, stored: &Self) -> Vec<String>;
+
+  /// Return whether the resource matches the filters inside `params`,
+  /// used to notify the streams about the newly indexed resources
+  fn percolate(&self, params: &Map) -> bool;
+
+  /// Return the mapping expected to be found on ElasticSearch
+  fn mapping() -> Mapping<'static>;
+
+  /// Return the settings of the index (i.e.: its analyzers) given by `settings`,
+  /// as sent by `reset_index`
+  fn index_settings(settings: &ResourceSettings) -> JsonValue;
+
+  /// Respond to DELETE requests rebuilding and reindexing given index,
+  /// analyzed as given by `settings`
+  fn reset_index(es: &mut Client, index: &str, settings: &ResourceSettings) -> Result<MappingResult, Error>;
+}
+
+#[cfg(test)]
+mod tests {
+  use resource::Pagination;
+
+  #[test]
+  fn test_pagination() {
+    // first page
+    {
+      let pagination = Pagination::new(0, 10, 25);
+      assert_eq!(pagination.next(), Some(10));
+      assert_eq!(pagination.prev(), None);
+      assert_eq!(pagination.last(), 20);
+    }
+
+    // middle page with a misaligned offset
+    {
+      let pagination = Pagination::new(5, 10, 25);
+      assert_eq!(pagination.next(), Some(15));
+      assert_eq!(pagination.prev(), Some(0));
+    }
+
+    // last page
+    {
+      let pagination = Pagination::new(20, 10, 25);
+      assert_eq!(pagination.next(), None);
+      assert_eq!(pagination.prev(), Some(10));
+      assert_eq!(pagination.last(), 20);
+    }
 
-  /// Respond to DELETE requests rebuilding and reindexing given index
-  fn reset_index(es: &mut Client, index: &str) -> Result<MappingResult, EsError>;
+    // no results
+    {
+      let pagination = Pagination::new(0, 10, 0);
+      assert_eq!(pagination.next(), None);
+      assert_eq!(pagination.prev(), None);
+      assert_eq!(pagination.last(), 0);
+    }
+  }
 }
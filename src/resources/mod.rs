@@ -4,4 +4,4 @@ extern crate rs_es;
 extern crate params;
 
 mod talent;
-pub use self::talent::Talent;
+pub use self::talent::{Talent, TalentStream, SalaryExpectations};
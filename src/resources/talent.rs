@@ -1,44 +1,167 @@
-use super::chrono::UTC;
+use super::chrono::{UTC, DateTime};
 
 use super::params::*;
+use super::serde_json;
 use super::serde_json::Value as JsonValue;
 
 use super::rs_es::Client;
 use super::rs_es::query::Query;
+use super::rs_es::query::compound::BoostMode;
 use super::rs_es::operations::search::{Sort, SortField, Order, SearchHitsHitsResult};
+use super::rs_es::operations::search::SearchResult as EsSearchResult;
 use super::rs_es::operations::bulk::{BulkResult, Action};
+use super::rs_es::operations::common::VersionType;
 use super::rs_es::operations::delete::DeleteResult;
+use super::rs_es::operations::index::IndexResult;
 use super::rs_es::operations::mapping::*;
+use super::rs_es::query::MinimumShouldMatch;
 use super::rs_es::query::full_text::MatchQueryType;
+use super::rs_es::query::functions::{Function, Modifier};
+use super::rs_es::units::{Duration, JsonVal};
 use super::rs_es::error::EsError;
 use super::rs_es::operations::search::highlight::*;
+use super::rs_es::operations::search::aggregations::Aggregations;
+use super::rs_es::operations::search::aggregations::bucket::{Filter, Terms};
 
+use terms;
+use keywords;
 use terms::VectorOfTerms;
+use schema::{Param, Kind, FieldError};
+use error::Error;
 use resource::*;
+use config::ResourceSettings;
+use scroll::Scroll;
+use clock::Clock;
+use scoring;
+use ltr;
+use routing;
+use es;
+
+use std::collections::{HashMap, HashSet};
+
+/// The talents of an index, fetched lazily (i.e. to export them).
+pub type TalentStream = Scroll<Talent>;
 
 /// The type that we use in ElasticSearch for defining a `Talent`.
 const ES_TYPE: &'static str = "talent";
 
+/// The facets counted by `Talent::facets`, given as the param
+/// that filters them and the field they are aggregated on.
+const FACETS: [(&'static str, &'static str); 5] = [
+  ("desired_work_roles",      "desired_work_roles_vanilla"),
+  ("professional_experience", "professional_experience"),
+  ("work_authorization",      "work_authorization"),
+  ("work_locations",          "work_locations"),
+  ("languages",               "languages")
+];
+
+/// The fields searched by `keywords`.
+const FULL_TEXT_FIELDS: [&'static str; 5] = ["skills", "summary", "headline", "desired_work_roles", "work_experiences"];
+
+/// The fields of the `_source` fetched by the searches, the ones of `SearchedTalent`.
+const SEARCHED_FIELDS: [&'static str; 10] = ["id", "headline", "avatar_url", "work_locations", "current_location",
+                                             "salary_expectations", "desired_work_roles", "desired_work_roles_experience",
+                                             "latest_position", "batch_starts_at"];
+
+lazy_static! {
+  /// The sorting of the searches without keywords and the highlighting of
+  /// the ones with keywords, which are the same for every search.
+  static ref SORTING:   Sort      = Talent::sorting_criteria(false, false);
+  static ref HIGHLIGHT: Highlight = Talent::highlight();
+
+  /// The sorting once the `computed_score` feature is enabled.
+  static ref SCORED_SORTING: Sort = Talent::sorting_criteria(true, false);
+
+  /// The sorting of the searches with keywords that are not rescored.
+  static ref RELEVANCE_SORTING: Sort = Talent::relevance_sorting();
+
+  /// The same, as they are sent by `preview`.
+  static ref SORTING_JSON:           JsonValue = serde_json::to_value(&*SORTING);
+  static ref SCORED_SORTING_JSON:    JsonValue = serde_json::to_value(&*SCORED_SORTING);
+  static ref RELEVANCE_SORTING_JSON: JsonValue = serde_json::to_value(&*RELEVANCE_SORTING);
+  static ref HIGHLIGHT_JSON:         JsonValue = serde_json::to_value(&*HIGHLIGHT);
+}
+
+/// The minimum score of the talents found through `keywords`,
+/// unless given by `SearchOptions`.
+const MIN_SCORE: f64 = 0.56;
+
+/// How many values are returned for each facet.
+const FACET_SIZE: u64 = 50;
+
+/// The score added to the talents of `pinned_ids`, so that they rank above every other match.
+const PINNED_BOOST: f64 = 10000.0;
+
 /// A collection of `SearchResult`s.
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct SearchResults {
-  pub total:   u64,
-  pub talents: Vec<SearchResult>,
+  pub total:      u64,
+  pub talents:    Vec<SearchResult>,
+  pub pagination: Pagination,
+  #[serde(skip_serializing, default)]
+  pub timed_out:  bool,
+  #[serde(skip_serializing, default)]
+  pub took:       u64
+}
+
+impl SearchResults {
+  fn new(result: EsSearchResult<SearchedTalent>, offset: u64, per_page: u64, pinned_ids: &[u64]) -> SearchResults {
+    SearchResults {
+      total:      result.hits.total,
+      talents:    dedup(result.hits.hits.into_iter().filter_map(|hit| SearchResult::from_hit(hit, pinned_ids)).collect()),
+      pagination: Pagination::new(offset, per_page, result.hits.total),
+      timed_out:  result.timed_out,
+      took:       result.took
+    }
+  }
+}
+
+/// Return `results` without the talents already found by a previous hit (i.e. indexed
+/// in several of the searched indexes, whose hits are interleaved), keeping their ranking.
+fn dedup(results: Vec<SearchResult>) -> Vec<SearchResult> {
+  let mut seen = HashSet::new();
+  results.into_iter().filter(|result| seen.insert(result.talent.id)).collect()
+}
+
+impl Paginated for SearchResults {
+  fn pagination(&self) -> Option<Pagination> {
+    Some(self.pagination.to_owned())
+  }
+}
+
+impl TimedOut for SearchResults {
+  fn timed_out(&self) -> bool {
+    self.timed_out
+  }
+
+  fn took(&self) -> Option<u64> {
+    Some(self.took)
+  }
 }
 
 /// A single search result returned by ElasticSearch.
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct SearchResult {
   pub talent:    FoundTalent,
-  pub highlight: Option<HighlightResult>
+  pub highlight: Option<HighlightResult>,
+  #[serde(default)]
+  pub pinned:    bool // given in `pinned_ids`, hence ranked first
 }
 
-/// Convert the ElasticSearch results into a `SearchResult`.
-impl From<SearchHitsHitsResult<Talent>> for SearchResult {
-  fn from(hit: SearchHitsHitsResult<Talent>) -> SearchResult {
-    SearchResult {
-      talent:    hit.source.unwrap().into(),
-      highlight: hit.highlight
+impl SearchResult {
+  /// Convert a hit returned by ElasticSearch into a `SearchResult`, unless it has no
+  /// `_source` (i.e.: only stored fields, or a partial failure), which is logged and skipped.
+  fn from_hit(hit: SearchHitsHitsResult<SearchedTalent>, pinned_ids: &[u64]) -> Option<SearchResult> {
+    match hit.source {
+      Some(source) => Some(SearchResult {
+        pinned:    pinned_ids.contains(&source.id),
+        talent:    source.into(),
+        highlight: hit.highlight
+      }),
+      None => {
+        warn!("Skipping the hit {} of {}, returned without its source.", hit.id, hit.index);
+        None
+      }
     }
   }
 }
@@ -54,7 +177,7 @@ pub struct SalaryExpectations {
 /// A representation of `Talent` with limited fields.
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct FoundTalent {
-  pub id:                  u32,
+  pub id:                  u64,
   pub headline:            String,
   pub avatar_url:          String,
   pub work_locations:      Vec<String>,
@@ -81,9 +204,25 @@ impl RolesExperience {
   }
 }
 
-/// Convert a `Box<Talent>` returned by ElasticSearch into a `FoundTalent`.
-impl From<Box<Talent>> for FoundTalent {
-  fn from(talent: Box<Talent>) -> FoundTalent {
+/// The fields of a `Talent` returned by the searches, so that ElasticSearch
+/// does not send the whole documents just to build the `FoundTalent`s.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SearchedTalent {
+  pub id:                            u64,
+  pub headline:                      String,
+  pub avatar_url:                    String,
+  pub work_locations:                Vec<String>,
+  pub current_location:              String,
+  pub salary_expectations:           Vec<SalaryExpectations>,
+  pub desired_work_roles:            Vec<String>,
+  pub desired_work_roles_experience: Vec<String>,
+  pub latest_position:               String,
+  pub batch_starts_at:               String
+}
+
+/// Convert a `Box<SearchedTalent>` returned by ElasticSearch into a `FoundTalent`.
+impl From<Box<SearchedTalent>> for FoundTalent {
+  fn from(talent: Box<SearchedTalent>) -> FoundTalent {
     let mut roles_experiences = vec![];
 
     for (i, role) in talent.desired_work_roles.iter().enumerate() {
@@ -108,7 +247,7 @@ impl From<Box<Talent>> for FoundTalent {
 /// The talent that will be indexed into ElasticSearch.
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Talent {
-  pub id:                            u32,
+  pub id:                            u64,
   pub accepted:                      bool,
   pub desired_work_roles:            Vec<String>,
   pub desired_work_roles_vanilla:    Option<Vec<String>>, // not processed by ES
@@ -120,17 +259,19 @@ pub struct Talent {
   pub skills:                        Vec<String>,
   pub summary:                       String,
   pub headline:                      String,
-  pub contacted_company_ids:         Vec<u32>, // contacted companies
+  pub contacted_company_ids:         Vec<u64>, // contacted companies
   pub batch_starts_at:               String,
   pub batch_ends_at:                 String,
   pub added_to_batch_at:             String,
   pub weight:                        i32,
-  pub blocked_companies:             Vec<u32>,
+  pub blocked_companies:             Vec<u64>,
   pub work_experiences:              Vec<String>, // past work experiences (i.e. ["Frontend developer", "SysAdmin"])
   pub avatar_url:                    String,
   pub salary_expectations:           Vec<SalaryExpectations>,
   pub latest_position:               String, // the very last experience_entries#position
-  pub languages:                     Vec<String>
+  pub languages:                     Vec<String>,
+  #[serde(default)]
+  pub computed_score:                Option<f64> // set by the installed `scoring::Scorer` when indexed
 }
 
 impl Talent {
@@ -142,7 +283,7 @@ impl Talent {
   ///
   /// Basically, the talents must be accepted into the platform and must be
   /// inside a living batch to match the visibility criteria.
-  pub fn visibility_filters(epoch: &str, presented_talents: Vec<i32>) -> Vec<Query> {
+  pub fn visibility_filters(epoch: &str, presented_talents: Vec<u64>) -> Vec<Query> {
     let visibility_rules = Query::build_bool()
                                  .with_must(
                                     vec![
@@ -163,7 +304,7 @@ impl Talent {
       let presented_talents_filters = Query::build_bool()
                                             .with_must(
                                               vec![
-                                                <Query as VectorOfTerms<i32>>::build_terms(
+                                                <Query as VectorOfTerms<u64>>::build_terms(
                                                   "ids", &presented_talents)
                                               ].into_iter()
                                                .flat_map(|x| x)
@@ -189,128 +330,288 @@ impl Talent {
   /// I.e.: given ["Fullstack", "DevOps"] as `desired_work_roles`, found talents
   /// will present at least one of these roles), but both `desired_work_roles`
   /// and `work_location`, if provided, must be matched successfully.
-  pub fn search_filters(params: &Map, epoch: &str) -> Query {
-    let company_id = i32_vec_from_params!(params, "company_id");
+  ///
+  /// Only the keywords are scored: the terms and the ranges are given in the
+  /// filter context, so that ElasticSearch can cache them.
+  pub fn search_filters(params: &Map, epoch: &str, options: &SearchOptions) -> Query {
+    let company_id = u64_vec_from_params!(params, "company_id");
+
+    // filled in place, borrowing the strings from `params`
+    let mut filters = Vec::with_capacity(8);
+
+    filters.extend(<Query as VectorOfTerms<&str>>::build_terms(
+      "desired_work_roles_vanilla", &terms::strings(params, "desired_work_roles")));
+
+    filters.extend(<Query as VectorOfTerms<&str>>::build_terms(
+      "professional_experience", &terms::strings(params, "professional_experience")));
+
+    filters.extend(<Query as VectorOfTerms<&str>>::build_terms(
+      "work_authorization", &terms::strings(params, "work_authorization")));
+
+    filters.extend(<Query as VectorOfTerms<&str>>::build_terms(
+      "work_locations", &terms::strings(params, "work_locations")));
+
+    filters.extend(<Query as VectorOfTerms<u64>>::build_terms(
+      "id", &vec_from_params!(params, "ids")));
+
+    filters.extend(<Query as VectorOfTerms<&str>>::build_terms(
+      "languages", &terms::strings(params, "languages")));
+
+    filters.extend(Talent::visibility_filters(epoch, u64_vec_from_params!(params, "presented_talents")));
 
     Query::build_bool()
           .with_must(
+             match Talent::full_text_search(params, options) {
+               Some(keywords) => vec![keywords],
+               None           => vec![]
+             })
+          .with_filter(
+             Query::build_bool()
+                   .with_must(filters)
+                   .build())
+          .with_must_not(
              vec![
-                match Talent::full_text_search(params) {
-                  Some(keywords) => vec![keywords],
-                  None           => vec![]
-                },
-
-               <Query as VectorOfTerms<String>>::build_terms(
-                 "desired_work_roles_vanilla", &vec_from_params!(params, "desired_work_roles")),
-
-               <Query as VectorOfTerms<String>>::build_terms(
-                 "professional_experience", &vec_from_params!(params, "professional_experience")),
-
-               <Query as VectorOfTerms<String>>::build_terms(
-                 "work_authorization", &vec_from_params!(params, "work_authorization")),
-
-               <Query as VectorOfTerms<String>>::build_terms(
-                 "work_locations", &vec_from_params!(params, "work_locations")),
-
-               <Query as VectorOfTerms<i32>>::build_terms(
-                 "id", &vec_from_params!(params, "ids")),
-                 
-               <Query as VectorOfTerms<String>>::build_terms(
-                 "languages", &vec_from_params!(params, "languages")),
-
-               Talent::visibility_filters(epoch,
-                 i32_vec_from_params!(params, "presented_talents"))
-               ].into_iter()
-                .flat_map(|x| x)
-                .collect::<Vec<Query>>())
-                .with_must_not(
-                   vec![
-                     <Query as VectorOfTerms<i32>>::build_terms(
-                       "contacted_company_ids", &company_id),
-
-                     <Query as VectorOfTerms<i32>>::build_terms(
-                       "blocked_companies", &company_id),
-
-                     <Query as VectorOfTerms<i32>>::build_terms(
-                       "id", &vec_from_params!(params, "contacted_talents"))
-                   ].into_iter()
-                    .flat_map(|x| x)
-                    .collect::<Vec<Query>>())
+               <Query as VectorOfTerms<u64>>::build_terms(
+                 "contacted_company_ids", &company_id),
+
+               <Query as VectorOfTerms<u64>>::build_terms(
+                 "blocked_companies", &company_id),
+
+               <Query as VectorOfTerms<u64>>::build_terms(
+                 "id", &vec_from_params!(params, "contacted_talents"))
+             ].into_iter()
+              .flat_map(|x| x)
+              .collect::<Vec<Query>>())
           .build()
   }
 
-  pub fn full_text_search(params: &Map) -> Option<Query> {
+  /// Return a `Query` matching `keywords` against the text fields, whose
+  /// score is multiplied by the ones given inside `options.boosts`.
+  /// The keywords are sanitized first (see `keywords::sanitize`).
+  pub fn full_text_search(params: &Map, options: &SearchOptions) -> Option<Query> {
     match params.get("keywords") {
       Some(&Value::String(ref keywords)) => {
+        let keywords = keywords::sanitize(keywords);
         if keywords.is_empty() {
           return None;
         }
 
-        Some(Query::build_multi_match(
-                vec![
-                  "skills".to_owned(),
-                  "summary".to_owned(),
-                  "headline".to_owned(),
-                  "desired_work_roles".to_owned(),
-                  "work_experiences".to_owned()
-                ], keywords.to_owned())
-            .with_type(MatchQueryType::CrossFields)
-            .with_tie_breaker(0.0)
-            .build())
+        let fields = FULL_TEXT_FIELDS.iter()
+                                     .map(|field| match options.boosts.get(*field) {
+                                       Some(boost) => format!("{}^{}", field, boost),
+                                       None        => field.to_string()
+                                     })
+                                     .collect::<Vec<String>>();
+
+        let mut query = Query::build_multi_match(fields, keywords);
+        query.with_type(MatchQueryType::CrossFields)
+             .with_tie_breaker(options.tie_breaker);
+
+        if let Some(ref minimum_should_match) = options.minimum_should_match {
+          query.with_minimum_should_match(MinimumShouldMatch::from(&minimum_should_match[..]));
+        }
+
+        Some(query.build())
       },
       _ => None
     }
   }
 
-  /// Return a `Sort` that makes values be sorted for given fields, descendently.
-  pub fn sorting_criteria() -> Sort {
-    Sort::new(
-      vec![
-        SortField::new("batch_starts_at",   Some(Order::Desc)).with_unmapped_type("date").build(),
-        SortField::new("weight",            Some(Order::Desc)).with_unmapped_type("integer").build(),
-        SortField::new("added_to_batch_at", Some(Order::Desc)).with_unmapped_type("date").build()
-      ])
+  /// Return `true` if the talent is accepted and its batch is alive
+  /// at `epoch` (RFC 3339), as required by `visibility_filters`.
+  pub fn is_visible_at(&self, epoch: &str) -> bool {
+    let epoch = match DateTime::parse_from_rfc3339(epoch) {
+      Ok(epoch) => epoch,
+      Err(_)    => return false
+    };
+
+    let starts_at = DateTime::parse_from_rfc3339(&self.batch_starts_at);
+    let ends_at   = DateTime::parse_from_rfc3339(&self.batch_ends_at);
+
+    match (starts_at, ends_at) {
+      (Ok(starts_at), Ok(ends_at)) => self.accepted && starts_at <= epoch && ends_at >= epoch,
+      _                            => false
+    }
   }
-}
 
-impl Resource for Talent {
-  type Results = SearchResults;
+  /// Return the `pinned_ids` param, the talents curated to rank first.
+  fn pinned_ids(params: &Map) -> Vec<u64> {
+    u64_vec_from_params!(params, "pinned_ids")
+  }
 
-  /// Populate the ElasticSearch index with `Vec<Talent>`
-  fn index(mut es: &mut Client, index: &str, resources: Vec<Self>) -> Result<BulkResult, EsError> {
-    es.bulk(&resources.into_iter()
-                      .map(|mut r| {
-                          let id = r.id.to_string();
-                          r.desired_work_roles_vanilla = Some(r.desired_work_roles.to_owned());
-                          Action::index(r).with_id(id)
-                      })
-                      .collect::<Vec<Action<Talent>>>())
-      .with_index(index)
-      .with_doc_type(ES_TYPE)
-      .send()
+  /// Return `query` ranking the talents of `pinned_ids` above the other matches, as long as
+  /// they match it as well. The searches without keywords are scored only by the pinning,
+  /// so that sorting by `_score` first keeps the usual sorting of the other talents.
+  fn pinned(query: Query, pinned_ids: &[u64], keywords_present: bool) -> Query {
+    let pinning = match <Query as VectorOfTerms<u64>>::build_terms("id", &pinned_ids.to_vec()).into_iter().next() {
+      Some(pinning) => Query::build_constant_score(pinning).with_boost(PINNED_BOOST).build(),
+      None          => return query
+    };
+
+    let query = if keywords_present { query } else { Query::build_constant_score(query).build() };
+
+    Query::build_bool()
+          .with_must(vec![query])
+          .with_should(vec![pinning])
+          .build()
+  }
+
+  /// Return the minimum score of the matches of a search with or without keywords,
+  /// unless `options.score_threshold` does not apply it to such searches. The searches
+  /// without keywords are scored only by the decay, hence never filtered when it is not
+  /// applied, nor when they pin talents (being scored only by the pinning).
+  fn min_score(keywords_present: bool, pinning: bool, options: &SearchOptions) -> Option<f64> {
+    if !keywords_present && (pinning || !Talent::decaying(options)) {
+      None
+    }
+    else if options.features.score_filtering && options.score_threshold.applies(keywords_present) {
+      Some(options.min_score.unwrap_or(MIN_SCORE))
+    }
+    else {
+      None
+    }
+  }
+
+  /// Return the `epoch` param, defaulting to the time of `clock` when missing or given as `now`.
+  /// The values that are not RFC 3339 dates are refused, rather than replaced by now.
+  fn epoch(params: &Map, clock: &Clock) -> Result<String, Error> {
+    match params.get("epoch") {
+      None                                              => Ok(clock.now().to_rfc3339()),
+      Some(&Value::String(ref epoch)) if epoch == "now" => Ok(clock.now().to_rfc3339()),
+      Some(&Value::String(ref epoch)) => {
+        match DateTime::parse_from_rfc3339(epoch) {
+          Ok(_)  => Ok(epoch.to_owned()),
+          Err(_) => Err(Error::Validation(format!("`epoch` expects a RFC 3339 date or `now`, got {:?}.", epoch)))
+        }
+      },
+      Some(_) => Err(Error::Validation("`epoch` expects a single RFC 3339 date or `now`.".to_owned()))
+    }
   }
 
-  /// Query ElasticSearch on given `indexes` and `params` and return the IDs of
-  /// the found talents.
-  fn search(mut es: &mut Client, default_index: &str, params: &Map) -> Self::Results {
-    let epoch = match params.get("epoch") {
-      Some(&Value::String(ref epoch)) => epoch.to_owned(),
-      _                               => UTC::now().to_rfc3339()
+  /// Return the indexes to search in, given by the `index` param as comma-separated
+  /// names or `default_index`. They are sent within a single search, which ElasticSearch
+  /// runs on every index concurrently, merging the results by score (or sorting criteria).
+  fn indexes<'a>(params: &'a Map, default_index: &'a str) -> Vec<&'a str> {
+    let indexes = match params.get("index") {
+      Some(&Value::String(ref index)) => index.split(',')
+                                              .map(|index| index.trim())
+                                              .filter(|index| !index.is_empty())
+                                              .collect(),
+      _                               => vec![]
     };
 
-    let index: Vec<&str> = match params.get("index") {
-      Some(&Value::String(ref index)) => vec![&index[..]],
-      _                               => vec![default_index]
+    if indexes.is_empty() { vec![default_index] } else { indexes }
+  }
+
+  /// Refuse the params that would build a query beyond `limits`: too many keywords,
+  /// too many values of a single param or too many clauses overall, counting
+  /// every keyword once for each of the `FULL_TEXT_FIELDS` it is matched against.
+  fn check_complexity(params: &Map, limits: &QueryLimits) -> Result<(), Error> {
+    let keywords = match params.get("keywords") {
+      Some(&Value::String(ref keywords)) => keywords::sanitize(keywords).split(' ').filter(|word| !word.is_empty()).count(),
+      _                                  => 0
     };
 
-    let keywords_present = match params.get("keywords") {
-      Some(keywords) => match keywords {
-        &Value::String(ref keywords) => !keywords.is_empty(),
-        _                            => false
+    if keywords > limits.max_keywords {
+      return Err(Error::Validation(format!("`keywords` cannot contain more than {} words, got {}.", limits.max_keywords, keywords)));
+    }
+
+    let mut clauses = keywords * FULL_TEXT_FIELDS.len();
+
+    for (name, value) in params.iter() {
+      if let Value::Array(ref values) = *value {
+        if values.len() > limits.max_terms {
+          return Err(Error::Validation(format!("`{}` cannot be given more than {} values, got {}.", name, limits.max_terms, values.len())));
+        }
+
+        clauses += values.len();
+      }
+    }
+
+    if clauses > limits.max_clauses {
+      return Err(Error::Validation(format!("The search would contain {} clauses, more than {}.", clauses, limits.max_clauses)));
+    }
+
+    Ok(())
+  }
+
+  /// Return whether `keywords` have been given, not empty once sanitized.
+  fn keywords_present(params: &Map) -> bool {
+    Talent::keywords(params).is_some()
+  }
+
+  /// Return the `keywords`, sanitized, unless none have been given.
+  fn keywords(params: &Map) -> Option<String> {
+    match params.get("keywords") {
+      Some(&Value::String(ref keywords)) => {
+        let keywords = keywords::sanitize(keywords);
+        if keywords.is_empty() { None } else { Some(keywords) }
       },
-      None => false
-    };
+      _ => None
+    }
+  }
+
+  /// Return whether the score is decayed as `options.decay.field` gets older.
+  fn decaying(options: &SearchOptions) -> bool {
+    options.decay.is_some() && options.features.decay_ranking
+  }
+
+  /// Wrap `query` in a `function_score` decaying the score as `options.decay.field`
+  /// gets older, if configured and the `decay_ranking` feature is enabled.
+  /// The filters of the searches without keywords score `0`, so their score is replaced
+  /// by the decay rather than multiplied, for `min_score` to apply to it.
+  fn decayed(query: Query, keywords_present: bool, options: &SearchOptions) -> Query {
+    match options.decay {
+      Some(ref decay) if options.features.decay_ranking => {
+        let function = Function::build_decay(&decay.field[..], options.clock.now(), Duration::days(decay.scale as i64))
+                                .with_offset(Duration::days(decay.offset as i64))
+                                .with_decay(decay.decay)
+                                .build_gauss();
+
+        let decayed = Query::build_function_score()
+                            .with_query(query)
+                            .with_function(function);
+
+        if keywords_present { decayed.build() } else { decayed.with_boost_mode(BoostMode::Replace).build() }
+      },
+      _ => query
+    }
+  }
+
+  /// Wrap the `query` of a full text search in a `function_score` multiplying the score
+  /// by the `computed_score` of the talents, if the `computed_score` feature is enabled.
+  fn scored(query: Query, keywords_present: bool, options: &SearchOptions) -> Query {
+    if !keywords_present || !options.features.computed_score {
+      return query;
+    }
+
+    // log10(2 + boost * computed_score), so that the talents indexed without it are not dropped
+    let function = Function::build_field_value_factor("computed_score")
+                            .with_factor(options.scoring_boost)
+                            .with_modifier(Modifier::Log2p)
+                            .with_missing(JsonVal::from(0.0))
+                            .build();
+
+    Query::build_function_score()
+          .with_query(query)
+          .with_function(function)
+          .build()
+  }
+
+  /// Return the sorting of the searches without keywords, by `computed_score`
+  /// before `weight` if the `computed_score` feature is enabled.
+  fn sorting(options: &SearchOptions) -> &'static JsonValue {
+    if options.features.computed_score {
+      &SCORED_SORTING_JSON
+    }
+    else {
+      &SORTING_JSON
+    }
+  }
 
+  /// Return the `offset` and `per_page` params, defaulting to the first 10 talents.
+  /// `per_page` cannot exceed `options.max_per_page`.
+  fn page(params: &Map, options: &SearchOptions) -> (u64, u64) {
     let offset: u64 = match params.get("offset") {
       Some(offset) => u64::from_value(&offset).unwrap_or(0),
       _            => 0 as u64
@@ -321,73 +622,610 @@ impl Resource for Talent {
       _              => 10 as u64
     };
 
-    let result = if keywords_present {
-      let mut highlight = Highlight::new().with_encoder(Encoders::HTML)
-                                          .with_pre_tags(vec!["".to_owned()])
-                                          .with_post_tags(vec!["".to_owned()])
-                                          .to_owned();
-      let settings = Setting::new().with_type(SettingTypes::Plain)
-                                   .with_term_vector(TermVector::WithPositionsOffsets)
-                                   .with_fragment_size(1)
-                                   .to_owned();
-      highlight.add_setting("skills".to_owned(),  settings.clone());
-      highlight.add_setting("summary".to_owned(), settings.clone());
-      highlight.add_setting("headline".to_owned(), settings.clone());
-      highlight.add_setting("desired_work_roles".to_owned(), settings.clone());
-      highlight.add_setting("work_experiences".to_owned(), settings);
-
-      es.search_query()
-        .with_indexes(&*index)
-        .with_query(&Talent::search_filters(params, &*epoch))
-        .with_highlight(&highlight)
-        .with_from(offset)
-        .with_size(per_page)
-        .with_min_score(0.56)
-        .with_track_scores(true)
-        .send::<Talent>()
+    match options.max_per_page {
+      Some(max_per_page) if per_page > max_per_page => (offset, max_per_page),
+      _                                             => (offset, per_page)
     }
-    else {
-      es.search_query()
-        .with_indexes(&*index)
-        .with_query(&Talent::search_filters(params, &*epoch))
-        .with_sort(&Talent::sorting_criteria())
-        .with_from(offset)
-        .with_size(per_page)
-        .send::<Talent>()
+  }
+
+  /// Return the highlighting of the fields matched by `full_text_search`.
+  fn highlight() -> Highlight {
+    let mut highlight = Highlight::new().with_encoder(Encoders::HTML)
+                                        .with_pre_tags(vec!["".to_owned()])
+                                        .with_post_tags(vec!["".to_owned()])
+                                        .to_owned();
+    let settings = Setting::new().with_type(SettingTypes::Plain)
+                                 .with_term_vector(TermVector::WithPositionsOffsets)
+                                 .with_fragment_size(1)
+                                 .to_owned();
+    highlight.add_setting("skills".to_owned(),  settings.clone());
+    highlight.add_setting("summary".to_owned(), settings.clone());
+    highlight.add_setting("headline".to_owned(), settings.clone());
+    highlight.add_setting("desired_work_roles".to_owned(), settings.clone());
+    highlight.add_setting("work_experiences".to_owned(), settings);
+    highlight
+  }
+
+  /// Return a `Sort` that makes values be sorted for given fields, descendently,
+  /// by `computed_score` right after the batch if `computed_score`, and by
+  /// `_score` before anything else if `pinned` (see `Talent::pinned`).
+  /// The talents tied on every field are sorted by `id`, ascendently, so that
+  /// they keep their order between the pages and the requests.
+  pub fn sorting_criteria(computed_score: bool, pinned: bool) -> Sort {
+    let mut fields = vec![];
+
+    if pinned {
+      fields.push(SortField::new("_score", Some(Order::Desc)).build());
+    }
+
+    fields.push(SortField::new("batch_starts_at", Some(Order::Desc)).with_unmapped_type("date").build());
+
+    if computed_score {
+      fields.push(SortField::new("computed_score", Some(Order::Desc)).with_unmapped_type("float").build());
+    }
+
+    fields.push(SortField::new("weight",            Some(Order::Desc)).with_unmapped_type("integer").build());
+    fields.push(SortField::new("added_to_batch_at", Some(Order::Desc)).with_unmapped_type("date").build());
+    fields.push(SortField::new("id",                Some(Order::Asc)).with_unmapped_type("long").build());
+    Sort::new(fields)
+  }
+
+  /// Return a `Sort` by `_score`, the talents tied on it being sorted by `id`,
+  /// ascendently, as `sorting_criteria` does.
+  fn relevance_sorting() -> Sort {
+    Sort::new(vec![
+      SortField::new("_score", Some(Order::Desc)).build(),
+      SortField::new("id",     Some(Order::Asc)).with_unmapped_type("long").build()
+    ])
+  }
+
+  /// Return the bulk action indexing the talent, routed by its routing key if any.
+  fn action(mut self) -> Action<Talent> {
+    let id = self.id.to_string();
+    self.desired_work_roles_vanilla = Some(self.desired_work_roles.to_owned());
+    self.computed_score             = Some(scoring::score(&self));
+
+    match Talent::routing_key(&self) {
+      Some(key) => Action::index(self).with_id(id).with_routing(key),
+      None      => Action::index(self).with_id(id)
+    }
+  }
+
+  /// Return the routing key of `talent`, or `None` if the talents are routed by id.
+  fn routing_key(talent: &Talent) -> Option<String> {
+    routing::field(ES_TYPE).and_then(|field| routing::key(&field, &serde_json::to_value(talent)))
+  }
+
+  /// Return the routing keys given by the `routing` param, ignored unless the talents are routed.
+  fn routing_keys(params: &Map) -> Option<String> {
+    routing::field(ES_TYPE).and_then(|_| routing::keys(params))
+  }
+
+  /// Return every copy of the talents with given `ids` stored into `index`, along with
+  /// its routing key. Since the keys of the stored copies are unknown, every shard
+  /// is searched: only the refreshed copies are found.
+  fn stored_copies(es: &mut Client, index: &str, ids: &[u64]) -> Result<Vec<(Talent, Option<String>)>, Error> {
+    let query = match <Query as VectorOfTerms<u64>>::build_terms("id", &ids.to_vec()).into_iter().next() {
+      Some(query) => query,
+      None        => return Ok(vec![])
     };
 
-    match result {
-      Ok(result) => {
-        let results: Vec<SearchResult> = result.hits.hits.into_iter()
-                                                         .map(SearchResult::from)
-                                                         .collect();
+    // a talent may be stored under both its previous and its current key
+    let search = serde_json::to_value(&btreemap! {
+      "index" => serde_json::to_value(&vec![index]),
+      "body"  => serde_json::to_value(&btreemap! {
+        "query" => serde_json::to_value(&query),
+        "size"  => serde_json::to_value(&(2 * ids.len() as u64))
+      })
+    });
+
+    let response = try!(es::search(&es::url_of(es), &search, false));
+
+    es::hits(&response).into_iter()
+                       .filter_map(|hit| hit.find("_source").cloned())
+                       .map(|source| {
+                         let talent = try!(serde_json::from_value::<Talent>(source).map_err(|err| Error::from(EsError::from(err))));
+                         let key    = Talent::routing_key(&talent);
+                         Ok((talent, key))
+                       })
+                       .collect()
+  }
+
+  /// Return the routing keys, other than their current one, under which the talents given
+  /// as `(id, current key)` are stored into `index`, where they would be found twice. The
+  /// ones stored under their current key are fetched in real time through `_mget`, so that
+  /// only the new or moved talents are searched for on every shard, once the index has
+  /// been refreshed so that their latest copies are found.
+  fn stale_keys(es: &mut Client, index: &str, talents: &[(u64, Option<String>)]) -> Result<Vec<(u64, Option<String>)>, Error> {
+    if routing::field(ES_TYPE).is_none() || talents.is_empty() {
+      return Ok(vec![]);
+    }
+
+    let url  = es::url_of(es);
+    let docs = talents.iter().map(|&(id, ref key)| (id.to_string(), key.to_owned())).collect::<Vec<(String, Option<String>)>>();
+
+    let moved = try!(es::mget_keyed(&url, index, &docs)).into_iter()
+                                                        .zip(talents.iter())
+                                                        .filter(|&(ref current, _)| current.is_none())
+                                                        .map(|(_, &(id, _))| id)
+                                                        .collect::<Vec<u64>>();
+    if moved.is_empty() {
+      return Ok(vec![]);
+    }
+
+    try!(es::refresh(&url, index));
+
+    let keys = talents.iter().cloned().collect::<HashMap<u64, Option<String>>>();
+    Ok(try!(Talent::stored_copies(es, index, &moved)).into_iter()
+                                                     .filter(|&(ref talent, ref key)| keys.get(&talent.id) != Some(key))
+                                                     .map(|(talent, key)| (talent.id, key))
+                                                     .collect())
+  }
+
+  /// Return the bulk actions deleting the copies of `talents` stored into `index` under
+  /// another routing key than their current one (see `stale_keys`).
+  fn stale_copies(es: &mut Client, index: &str, talents: &[Talent]) -> Result<Vec<Action<Talent>>, Error> {
+    let keys = talents.iter().map(|talent| (talent.id, Talent::routing_key(talent))).collect::<Vec<(u64, Option<String>)>>();
+
+    Ok(try!(Talent::stale_keys(es, index, &keys)).into_iter()
+                                                 .map(|(id, key)| match key {
+                                                   Some(key) => Action::delete(id.to_string()).with_routing(key),
+                                                   None      => Action::delete(id.to_string())
+                                                 })
+                                                 .collect())
+  }
+
+  /// Send `actions` to `index` in a single bulk request, deleting the `stale` copies first.
+  /// Only the results of `actions` are returned, in order.
+  fn bulk(es: &mut Client, index: &str, stale: Vec<Action<Talent>>, actions: Vec<Action<Talent>>) -> Result<BulkResult, Error> {
+    let deletions = stale.len();
+
+    let mut all = stale;
+    all.extend(actions);
+
+    let mut result = try!(es.bulk(&all)
+                            .with_index(index)
+                            .with_doc_type(ES_TYPE)
+                            .send());
+
+    result.items = result.items.split_off(deletions);
+    Ok(result)
+  }
+
+  /// Delete the copy of the talent `id` stored into `index` under the routing `key`.
+  fn delete_copy(es: &mut Client, id: &str, index: &str, key: Option<String>) -> Result<DeleteResult, Error> {
+    let mut operation = es.delete(index, ES_TYPE, id);
+
+    if let Some(ref key) = key {
+      operation.with_routing(key);
+    }
+
+    operation.send().map_err(Error::from)
+  }
+
+  /// Return the shards and the analyzers of the index, as given by `settings`.
+  fn settings(settings: &ResourceSettings) -> Settings {
+    let analysis = &settings.analysis;
+    let shards   = settings.shards.unwrap_or(1);
+
+    Settings {
+      number_of_shards: shards,
+
+      analysis: Analysis {
+        filter: btreemap! {
+          "trigrams_filter".to_owned() => JsonValue::Object(btreemap! {
+            "type".to_owned()     => JsonValue::String("ngram".into()),
+            "min_gram".to_owned() => JsonValue::U64(analysis.min_gram),
+            "max_gram".to_owned() => JsonValue::U64(analysis.max_gram)
+          }),
+
+          "words_splitter".to_owned() => JsonValue::Object(btreemap! {
+            "type".to_owned()              => JsonValue::String("word_delimiter".into()),
+            "preserve_original".to_owned() => JsonValue::Bool(true),
+            "catenate_all".to_owned()      => JsonValue::Bool(true)
+          }),
+
+          "english_words_filter".to_owned() => JsonValue::Object(btreemap! {
+            "type".to_owned()      => JsonValue::String("stop".into()),
+            "stopwords".to_owned() => JsonValue::String("_english_".into())
+          }),
+
+          "tech_words_filter".to_owned() => JsonValue::Object(btreemap! {
+            "type".to_owned()      => JsonValue::String("stop".into()),
+            "stopwords".to_owned() => JsonValue::Array(
+                                        vec![
+                                          JsonValue::String("js".into())
+                                        ])
+          })
+        },
+        analyzer: btreemap! {
+          "trigrams".to_owned() => JsonValue::Object(btreemap! { // index time
+            "type".to_owned()      => JsonValue::String("custom".into()),
+            "tokenizer".to_owned() => JsonValue::String(analysis.tokenizer.to_owned()),
+            "filter".to_owned()    => JsonValue::Array(
+                                        vec![
+                                          JsonValue::String("lowercase".into()),
+                                          JsonValue::String("words_splitter".into()),
+                                          JsonValue::String("trigrams_filter".into()),
+                                          JsonValue::String("english_words_filter".into()),
+                                          JsonValue::String("tech_words_filter".into())
+                                        ])
+          }),
 
-        SearchResults {
-            total:   result.hits.total,
-            talents: results
+          "words".to_owned() => JsonValue::Object(btreemap! { // query time
+            "type".to_owned()      => JsonValue::String("custom".into()),
+            "tokenizer".to_owned() => JsonValue::String("keyword".into()),
+            "filter".to_owned()    => JsonValue::Array(
+                                        vec![
+                                          JsonValue::String("lowercase".into()),
+                                          JsonValue::String("words_splitter".into()),
+                                          JsonValue::String("english_words_filter".into()),
+                                          JsonValue::String("tech_words_filter".into())
+                                        ])
+          })
         }
-      },
-      Err(err) => {
-        error!("{:?}", err);
-        SearchResults { total: 0, talents: vec![] }
       }
     }
   }
+}
 
-  /// Delete the talent associated to given id.
-  fn delete(mut es: &mut Client, id: &str, index: &str) -> Result<DeleteResult, EsError> {
-    es.delete(index, ES_TYPE, id)
-      .send()
+impl Resource for Talent {
+  type Results = SearchResults;
+
+  fn name() -> &'static str {
+    ES_TYPE
   }
 
-  /// Reset the given index. All the data will be destroyed and then the index
-  /// will be created again. The map that will be used is hardcoded.
-  #[allow(unused_must_use)]
-  fn reset_index(mut es: &mut Client, index: &str) -> Result<MappingResult, EsError> {
-    let mapping = hashmap! {
+  /// Populate the ElasticSearch index with `Vec<Talent>`, deleting the copies
+  /// left under their previous routing key, if any.
+  fn index(es: &mut Client, index: &str, resources: Vec<Self>) -> Result<BulkResult, Error> {
+    let stale = try!(Talent::stale_copies(es, index, &resources));
+
+    Talent::bulk(es, index, stale, resources.into_iter()
+                                            .map(|r| r.action())
+                                            .collect::<Vec<Action<Talent>>>())
+  }
+
+  fn index_versioned(es: &mut Client, index: &str, resources: Vec<(Self, u64)>) -> Result<BulkResult, Error> {
+    let talents = resources.iter().map(|&(ref r, _)| r.to_owned()).collect::<Vec<Talent>>();
+    let stale   = try!(Talent::stale_copies(es, index, &talents));
+
+    Talent::bulk(es, index, stale, resources.into_iter()
+                                            .map(|(r, version)| r.action().with_version(version).with_version_type(VersionType::ExternalGte))
+                                            .collect::<Vec<Action<Talent>>>())
+  }
+
+  fn search_params() -> Vec<Param> {
+    vec![
+      Param::single("keywords",                  Kind::String),
+      Param::multiple("desired_work_roles",      Kind::String),
+      Param::multiple("professional_experience", Kind::String),
+      Param::multiple("work_authorization",      Kind::String),
+      Param::multiple("work_locations",          Kind::String),
+      Param::multiple("languages",               Kind::String),
+      Param::multiple("ids",                     Kind::Integer),
+      Param::single("company_id",                Kind::Integer),
+      Param::multiple("contacted_talents",       Kind::Integer),
+      Param::multiple("presented_talents",       Kind::Integer),
+      Param::multiple("pinned_ids",              Kind::Integer),
+      Param::single("epoch",                     Kind::Date),
+      Param::single("index",                     Kind::String),
+      Param::single("ignore_unavailable",        Kind::Boolean),
+      Param::multiple("routing",                 Kind::String),
+      Param::single("spellcheck",                Kind::Boolean),
+      Param::single("offset",                    Kind::Integer),
+      Param::single("per_page",                  Kind::Integer)
+    ]
+  }
+
+  /// Return the indexes and the body of the search request, without sending it
+  /// (see `Resource::search_with`).
+  fn preview(default_index: &str, params: &Map, options: &SearchOptions) -> Result<JsonValue, Error> {
+    try!(Talent::check_complexity(params, &options.limits));
+    let epoch = try!(Talent::epoch(params, &*options.clock));
+    let index = Talent::indexes(params, default_index);
+    let (offset, per_page) = Talent::page(params, options);
+
+    let keywords_present = Talent::keywords_present(params);
+    let pinned_ids       = Talent::pinned_ids(params);
+    let query = Talent::decayed(Talent::search_filters(params, &*epoch, options), keywords_present, options);
+    let query = Talent::scored(query, keywords_present, options);
+    let query = Talent::pinned(query, &pinned_ids, keywords_present);
+
+    let mut body = btreemap! {
+      "query"   => serde_json::to_value(&query),
+      "_source" => serde_json::to_value(&SEARCHED_FIELDS),
+      "from"    => serde_json::to_value(&offset),
+      "size"    => serde_json::to_value(&per_page)
+    };
+
+    if let Some(timeout) = options.timeout {
+      body.insert("timeout", serde_json::to_value(&format!("{}ms", timeout)));
+    }
+
+    // the sorted searches cannot be rescored
+    let rescoring = match (Talent::keywords(params), options.rescoring.as_ref()) {
+      (Some(keywords), Some(rescoring)) if options.features.ltr_rescoring => Some((keywords, rescoring)),
+      _                                                                   => None
+    };
+
+    if keywords_present {
+      body.insert("highlight",    HIGHLIGHT_JSON.to_owned());
+      body.insert("track_scores", serde_json::to_value(&true));
+
+      if rescoring.is_none() {
+        body.insert("sort", RELEVANCE_SORTING_JSON.to_owned());
+      }
+    }
+    else if !pinned_ids.is_empty() {
+      body.insert("sort", serde_json::to_value(&Talent::sorting_criteria(options.features.computed_score, true)));
+    }
+    else {
+      body.insert("sort", Talent::sorting(options).to_owned());
+    }
+
+    if let Some(min_score) = Talent::min_score(keywords_present, !pinned_ids.is_empty(), options) {
+      body.insert("min_score",    serde_json::to_value(&min_score));
+      body.insert("track_scores", serde_json::to_value(&true));
+    }
+
+    if let Some((keywords, rescoring)) = rescoring {
+      body.insert("rescore", ltr::rescore(rescoring, &keywords));
+
+      if rescoring.log_features {
+        body.insert("ext", ltr::log_specs());
+      }
+    }
+
+    let mut preview = btreemap! {
+      "index" => serde_json::to_value(&index),
+      "body"  => serde_json::to_value(&body)
+    };
+
+    if ignores_unavailable(params) {
+      preview.insert("ignore_unavailable", JsonValue::Bool(true));
+    }
+
+    if let Some(routing) = Talent::routing_keys(params) {
+      preview.insert("routing", serde_json::to_value(&routing));
+    }
+
+    Ok(serde_json::to_value(&preview))
+  }
+
+  fn results(params: &Map, options: &SearchOptions, response: JsonValue) -> Result<Self::Results, Error> {
+    let (offset, per_page) = Talent::page(params, options);
+
+    serde_json::from_value::<EsSearchResult<SearchedTalent>>(response)
+               .map(|result| SearchResults::new(result, offset, per_page, &Talent::pinned_ids(params)))
+               .map_err(|e| Error::EsQuery(EsError::EsError(e.to_string())))
+  }
+
+  /// Count the values of `FACETS` among the talents matching `params`.
+  /// Each facet is filtered by every param but its own, so that the
+  /// values that would widen the search are counted as well.
+  fn facets(es: &mut Client, default_index: &str, params: &Map, options: &SearchOptions) -> Result<Facets, Error> {
+    let epoch = try!(Talent::epoch(params, &*options.clock));
+    let index = Talent::indexes(params, default_index);
+
+    let filters = FACETS.iter()
+                        .map(|&(param, _)| {
+                          let mut params = params.to_owned();
+                          params.remove(param);
+                          Talent::search_filters(&params, &*epoch, options)
+                        })
+                        .collect::<Vec<Query>>();
+
+    let mut aggs = Aggregations::new();
+    for (&(param, field), filter) in FACETS.iter().zip(filters.iter()) {
+      aggs.add(param, (Filter::new(filter),
+                       Aggregations::from(("values", Terms::field(field).with_size(FACET_SIZE)))));
+    }
+
+    let search = serde_json::to_value(&btreemap! {
+      "index" => serde_json::to_value(&index),
+      "body"  => serde_json::to_value(&btreemap! {
+        "size" => serde_json::to_value(&0),
+        "aggs" => serde_json::to_value(&aggs)
+      })
+    });
+
+    let response = try!(es::search(&es::url_of(es), &search, false));
+
+    let mut facets = Facets::new();
+
+    if let Some(aggs) = response.find("aggregations") {
+      for &(param, _) in FACETS.iter() {
+        let buckets = aggs.pointer(&format!("/{}/values/buckets", param))
+                          .and_then(|buckets| buckets.as_array())
+                          .cloned()
+                          .unwrap_or(vec![]);
+
+        let counts = buckets.iter()
+                            .map(|bucket| FacetCount {
+                              value: match bucket.find("key") {
+                                Some(&JsonValue::String(ref key)) => key.to_owned(),
+                                Some(key)                         => key.to_string(),
+                                None                              => String::new()
+                              },
+                              count: bucket.find("doc_count").and_then(|count| count.as_u64()).unwrap_or(0)
+                            })
+                            .collect();
+
+        facets.insert(param.to_owned(), counts);
+      }
+    }
+
+    Ok(facets)
+  }
+
+  /// Return the talent associated to given id, if indexed.
+  fn get(mut es: &mut Client, id: &str, index: &str) -> Result<Option<Self>, Error> {
+    // the shard of a routed talent is unknown until it has been found
+    if routing::field(ES_TYPE).is_some() {
+      let id = match id.parse::<u64>() {
+        Ok(id) => id,
+        Err(_) => return Ok(None)
+      };
+
+      return Talent::stored_copies(es, index, &[id]).map(|copies| copies.into_iter().next().map(|(talent, _)| talent));
+    }
+
+    es.get(index, id)
+      .with_doc_type(ES_TYPE)
+      .send::<Talent>()
+      .map(|result| result.source)
+      .map_err(Error::from)
+  }
+
+  /// Replace the talent associated to given id with the given one,
+  /// deleting its copies left under another routing key, if any.
+  fn update(mut es: &mut Client, id: &str, index: &str, mut resource: Self) -> Result<IndexResult, Error> {
+    resource.desired_work_roles_vanilla = Some(resource.desired_work_roles.to_owned());
+    resource.computed_score             = Some(scoring::score(&resource));
+    let key = Talent::routing_key(&resource);
+
+    if let (Some(_), Ok(numeric)) = (key.as_ref(), id.parse::<u64>()) {
+      for (_, previous) in try!(Talent::stale_keys(es, index, &[(numeric, key.to_owned())])) {
+        try!(Talent::delete_copy(es, id, index, previous));
+      }
+    }
+
+    let mut operation = es.index(index, ES_TYPE);
+    operation.with_id(id)
+             .with_doc(&resource);
+
+    if let Some(ref key) = key {
+      operation.with_routing(key);
+    }
+
+    operation.send().map_err(Error::from)
+  }
+
+  /// Delete the talent associated to given id, along with its copies
+  /// stored under any routing key.
+  fn delete(mut es: &mut Client, id: &str, index: &str) -> Result<DeleteResult, Error> {
+    let copies = match id.parse::<u64>() {
+      Ok(numeric) if routing::field(ES_TYPE).is_some() => try!(Talent::stored_copies(es, index, &[numeric])),
+      _                                                => vec![]
+    };
+
+    if copies.is_empty() {
+      return Talent::delete_copy(es, id, index, None);
+    }
+
+    let mut deleted: Option<DeleteResult> = None;
+    for (_, key) in copies {
+      let result = try!(Talent::delete_copy(es, id, index, key));
+      if deleted.as_ref().map_or(true, |deleted| !deleted.found) {
+        deleted = Some(result);
+      }
+    }
+
+    Ok(deleted.unwrap())
+  }
+
+  fn delete_versioned(mut es: &mut Client, id: &str, index: &str, version: u64) -> Result<BulkResult, Error> {
+    let keys = match id.parse::<u64>() {
+      Ok(numeric) if routing::field(ES_TYPE).is_some() => try!(Talent::stored_copies(es, index, &[numeric])).into_iter()
+                                                                                                          .map(|(_, key)| key)
+                                                                                                          .collect(),
+      _                                                => vec![]
+    };
+
+    // a talent missing from every shard still leaves a tombstone, refusing the earlier versions
+    let keys = if keys.is_empty() { vec![None] } else { keys };
+    let actions = keys.into_iter()
+                      .map(|key| {
+                        let action = Action::delete(id.to_owned()).with_version(version).with_version_type(VersionType::ExternalGte);
+                        match key {
+                          Some(key) => action.with_routing(key),
+                          None      => action
+                        }
+                      })
+                      .collect::<Vec<Action<Talent>>>();
+
+    Talent::bulk(es, index, vec![], actions)
+  }
+
+  fn id(&self) -> String {
+    self.id.to_string()
+  }
+
+  fn is_visible(&self) -> bool {
+    self.is_visible_at(&UTC::now().to_rfc3339())
+  }
+
+  /// The batch must be given as RFC 3339 dates, not ending before it starts,
+  /// otherwise the talent would never be visible.
+  fn validate(&self) -> Vec<FieldError> {
+    let mut errors = vec![];
+
+    let starts_at = DateTime::parse_from_rfc3339(&self.batch_starts_at);
+    let ends_at   = DateTime::parse_from_rfc3339(&self.batch_ends_at);
+
+    if starts_at.is_err() {
+      errors.push(FieldError::new("batch_starts_at", format!("`batch_starts_at` expects a RFC 3339 date, got {:?}.", self.batch_starts_at)));
+    }
+
+    if ends_at.is_err() {
+      errors.push(FieldError::new("batch_ends_at", format!("`batch_ends_at` expects a RFC 3339 date, got {:?}.", self.batch_ends_at)));
+    }
+
+    if let (Ok(starts_at), Ok(ends_at)) = (starts_at, ends_at) {
+      if starts_at > ends_at {
+        errors.push(FieldError::new("batch_starts_at", "`batch_starts_at` cannot be after `batch_ends_at`.".to_owned()));
+      }
+    }
+
+    errors
+  }
+
+  /// A talent is expected to stay in the batch it has been indexed with.
+  fn conflicts(&self, stored: &Talent) -> Vec<String> {
+    let mut fields = vec![];
+
+    if self.batch_starts_at != stored.batch_starts_at {
+      fields.push("batch_starts_at".to_owned());
+    }
+
+    if self.batch_ends_at != stored.batch_ends_at {
+      fields.push("batch_ends_at".to_owned());
+    }
+
+    fields
+  }
+
+  /// Check the filters of `search_filters` that do not need ElasticSearch,
+  /// hence `keywords` and the batches are not taken into account.
+  fn percolate(&self, params: &Map) -> bool {
+    let matches_any = |param: &str, values: &[String]| {
+      let wanted: Vec<String> = vec_from_params!(params, param);
+      wanted.is_empty() || wanted.iter().any(|value| values.contains(value))
+    };
+
+    let ids:               Vec<u64> = vec_from_params!(params, "ids");
+    let contacted_talents: Vec<u64> = vec_from_params!(params, "contacted_talents");
+    let company_id = u64_vec_from_params!(params, "company_id");
+
+    self.accepted &&
+    matches_any("desired_work_roles",      &self.desired_work_roles) &&
+    matches_any("professional_experience", &[self.professional_experience.to_owned()]) &&
+    matches_any("work_authorization",      &[self.work_authorization.to_owned()]) &&
+    matches_any("work_locations",          &self.work_locations) &&
+    matches_any("languages",               &self.languages) &&
+    (ids.is_empty() || ids.contains(&self.id)) &&
+    !contacted_talents.contains(&self.id) &&
+    !company_id.iter().any(|id| self.contacted_company_ids.contains(id) ||
+                                self.blocked_companies.contains(id))
+  }
+
+  /// Return the mapping used by ElasticSearch to index the talents.
+  fn mapping() -> Mapping<'static> {
+    hashmap! {
       ES_TYPE => hashmap! {
         "id" => hashmap! {
-          "type"  => "integer",
+          "type"  => "long",
           "index" => "not_analyzed"
         },
 
@@ -435,7 +1273,14 @@ impl Resource for Talent {
         "skills" => hashmap! {
           "type"            => "string",
           "analyzer"        => "trigrams",
-          "search_analyzer" => "words"
+          "search_analyzer" => "words",
+          "copy_to"         => "skills_vocabulary"
+        },
+
+        // the words the keywords are spell checked against (see `spelling`)
+        "skills_vocabulary" => hashmap! {
+          "type"     => "string",
+          "analyzer" => "words"
         },
 
         "summary" => hashmap! {
@@ -459,7 +1304,7 @@ impl Resource for Talent {
         },
 
         "contacted_company_ids" => hashmap! {
-          "type"  => "integer",
+          "type"  => "long",
           "index" => "not_analyzed"
         },
 
@@ -491,8 +1336,13 @@ impl Resource for Talent {
           "index" => "not_analyzed"
         },
 
+        "computed_score" => hashmap! {
+          "type"  => "float",
+          "index" => "not_analyzed"
+        },
+
         "blocked_companies" => hashmap! {
-          "type"  => "integer",
+          "type"  => "long",
           "index" => "not_analyzed"
         },
 
@@ -506,69 +1356,23 @@ impl Resource for Talent {
 
         "latest_position" => hashmap! {
           "type"  => "string",
-          "index" => "not_analyzed"
-        }
-      }
-    };
-
-    let settings = Settings {
-      number_of_shards: 1,
-
-      analysis: Analysis {
-        filter: btreemap! {
-          "trigrams_filter".to_owned() => JsonValue::Object(btreemap! {
-            "type".to_owned()     => JsonValue::String("ngram".into()),
-            "min_gram".to_owned() => JsonValue::U64(2),
-            "max_gram".to_owned() => JsonValue::U64(20)
-          }),
-
-          "words_splitter".to_owned() => JsonValue::Object(btreemap! {
-            "type".to_owned()              => JsonValue::String("word_delimiter".into()),
-            "preserve_original".to_owned() => JsonValue::Bool(true),
-            "catenate_all".to_owned()      => JsonValue::Bool(true)
-          }),
-
-          "english_words_filter".to_owned() => JsonValue::Object(btreemap! {
-            "type".to_owned()      => JsonValue::String("stop".into()),
-            "stopwords".to_owned() => JsonValue::String("_english_".into())
-          }),
-
-          "tech_words_filter".to_owned() => JsonValue::Object(btreemap! {
-            "type".to_owned()      => JsonValue::String("stop".into()),
-            "stopwords".to_owned() => JsonValue::Array(
-                                        vec![
-                                          JsonValue::String("js".into())
-                                        ])
-          })
-        },
-        analyzer: btreemap! {
-          "trigrams".to_owned() => JsonValue::Object(btreemap! { // index time
-            "type".to_owned()      => JsonValue::String("custom".into()),
-            "tokenizer".to_owned() => JsonValue::String("whitespace".into()),
-            "filter".to_owned()    => JsonValue::Array(
-                                        vec![
-                                          JsonValue::String("lowercase".into()),
-                                          JsonValue::String("words_splitter".into()),
-                                          JsonValue::String("trigrams_filter".into()),
-                                          JsonValue::String("english_words_filter".into()),
-                                          JsonValue::String("tech_words_filter".into())
-                                        ])
-          }),
-
-          "words".to_owned() => JsonValue::Object(btreemap! { // query time
-            "type".to_owned()      => JsonValue::String("custom".into()),
-            "tokenizer".to_owned() => JsonValue::String("keyword".into()),
-            "filter".to_owned()    => JsonValue::Array(
-                                        vec![
-                                          JsonValue::String("lowercase".into()),
-                                          JsonValue::String("words_splitter".into()),
-                                          JsonValue::String("english_words_filter".into()),
-                                          JsonValue::String("tech_words_filter".into())
-                                        ])
-          })
+          "index" => "not_analyzed"
         }
       }
-    };
+    }
+  }
+
+  fn index_settings(settings: &ResourceSettings) -> JsonValue {
+    serde_json::to_value(&Talent::settings(settings))
+  }
+
+  /// Reset the given index. All the data will be destroyed and then the index
+  /// will be created again using the mapping returned by `Talent::mapping`
+  /// and the shards, ngrams and tokenizer given by `settings`.
+  #[allow(unused_must_use)]
+  fn reset_index(mut es: &mut Client, index: &str, settings: &ResourceSettings) -> Result<MappingResult, Error> {
+    let mapping  = Talent::mapping();
+    let settings = Talent::settings(settings);
 
     es.delete_index(index);
 
@@ -576,6 +1380,7 @@ impl Resource for Talent {
       .with_mapping(&mapping)
       .with_settings(&settings)
       .send()
+      .map_err(Error::from)
   }
 }
 
@@ -597,17 +1402,75 @@ mod tests {
   use config::*;
   use resource::*;
 
-  use resources::Talent;
-  use resources::talent::{SalaryExpectations, SearchResults};
+  use resources::{Talent, TalentStream, SalaryExpectations};
+  use resources::talent::SearchResults;
+  use clock::FixedClock;
+  use schema::FieldError;
+  use health::check_mapping;
+  use es;
+  use backend::{HttpBackend, RecordingBackend};
+  use error::Error;
+  use ltr::Rescoring;
+  use curl;
+  use test_support;
+  use test_support::{TestIndex, refresh_index};
+  #[cfg(feature = "embedded-es")]
+  use embedded::EmbeddedEs;
+
+  use proptest::collection;
+
+  use std::env;
+  use std::fs;
+  use std::fs::File;
+  use std::io::{Read, Write};
+  use std::sync::Arc;
 
   const CONFIG_FILE: &'static str = "examples/tests.toml";
+  const AVATAR_URL:  &'static str = "https://secure.gravatar.com/avatar/a0b9ad63fb35d210a218c317e0a6284e.jpg?s=250";
+
+  /// Where the expected queries of `GOLDEN_CASES` are kept, as `<name>.json`.
+  const GOLDEN_DIR: &'static str = "fixtures/queries";
+
+  /// The representative searches whose queries are compared with the golden files.
+  const GOLDEN_CASES: [(&'static str, &'static str); 6] = [
+    ("empty",      ""),
+    ("keywords",   "keywords=senior rust developer"),
+    ("filters",    "desired_work_roles[]=Fullstack&professional_experience[]=2..6&work_authorization[]=yes\
+                    &work_locations[]=Berlin&work_locations[]=Rome&languages[]=English"),
+    ("company",    "company_id=42&contacted_talents[]=1&contacted_talents[]=2"),
+    ("presented",  "presented_talents[]=4&ids[]=4&ids[]=5"),
+    ("everything", "keywords=C%2B%2B&work_locations[]=Berlin&company_id=6&presented_talents[]=3")
+  ];
 
   lazy_static! {
     static ref config: Config = Config::from_file(CONFIG_FILE.to_owned());
   }
 
+  #[cfg(feature = "embedded-es")]
+  lazy_static! {
+    static ref embedded_es: EmbeddedEs = EmbeddedEs::start(&config.es.urls()[0]).unwrap();
+  }
+
+  /// Return the URL of the cluster, running it first with `--features embedded-es`.
+  #[cfg(feature = "embedded-es")]
+  fn es_url() -> String {
+    embedded_es.url.to_owned()
+  }
+
+  #[cfg(not(feature = "embedded-es"))]
+  fn es_url() -> String {
+    config.es.urls()[0].to_owned()
+  }
+
   pub fn make_client() -> Client {
-    Client::new(&*config.es.url).unwrap()
+    Client::new(&es_url()).unwrap()
+  }
+
+  /// Return an index of its own for a test, named after `es.index`
+  /// and deleted once dropped (see `test_support::TestIndex`).
+  pub fn test_index(mut client: &mut Client) -> TestIndex {
+    es::configure_compatibility(&config.es.compatibility, &es_url());
+    TestIndex::create::<Talent>(&mut client, &es_url(), &config.es.index, &config.resource("talent")).unwrap()
   }
 
   macro_rules! epoch_from_year {
@@ -618,7 +1481,7 @@ mod tests {
   }
 
   impl SearchResults {
-    pub fn ids(&self) -> Vec<u32> {
+    pub fn ids(&self) -> Vec<u64> {
       self.talents.iter().map(|r| r.talent.id).collect()
     }
 
@@ -631,188 +1494,118 @@ mod tests {
     }
   }
 
-  impl SalaryExpectations {
-    fn new(minimum: u64, maximum: u64, currency: &str, city: &str) -> SalaryExpectations {
-      SalaryExpectations {
-        minimum:  Some(minimum),
-        maximum:  Some(maximum),
-        currency: currency.to_owned(),
-        city:     city.to_owned()
-      }
-    }
+  /// Search `index` the way the server does (see `server::preview_search`).
+  fn search(index: &str, params: &Map, options: &SearchOptions) -> Result<SearchResults, Error> {
+    Talent::search_with(&HttpBackend::new(&es_url()), index, params, options)
   }
 
-  pub fn populate_index(mut client: &mut Client) -> bool {
-    let talents = vec![
+  /// Return the talents searched by the tests, built from `test_support::talent`.
+  fn talents() -> Vec<Talent> {
+    vec![
       Talent {
-        id:                            1,
-        accepted:                      true,
-        desired_work_roles:            vec![],
-        desired_work_roles_vanilla:    None,
-        desired_work_roles_experience: vec![],
-        professional_experience:       "1..2".to_owned(),
-        work_locations:                vec!["Berlin".to_owned()],
-        current_location:              "Berlin".to_owned(),
-        work_authorization:            "yes".to_owned(),
-        skills:                        vec!["Rust".to_owned(), "HTML5".to_owned(), "HTML".to_owned()],
-        summary:                       "I'm a senior Rust developer and sometimes I do also HTML.".to_owned(),
-        headline:                      "Backend developer with Rust experience".to_owned(),
-        work_experiences:              vec!["Database Administrator".to_owned()],
-        contacted_company_ids:         vec![],
-        batch_starts_at:               epoch_from_year!("2006"),
-        batch_ends_at:                 epoch_from_year!("2020"),
-        added_to_batch_at:             epoch_from_year!("2006"),
-        weight:                        -5,
-        blocked_companies:             vec![],
-        avatar_url:                    "https://secure.gravatar.com/avatar/a0b9ad63fb35d210a218c317e0a6284e.jpg?s=250".to_owned(),
-        salary_expectations:           vec![SalaryExpectations::new(40_000, 50_000, "EUR", "Berlin")],
-        latest_position:               "Developer".to_owned(),
-        languages:                     vec!["English".to_owned()]
+        professional_experience: "1..2".to_owned(),
+        skills:                  vec!["Rust".to_owned(), "HTML5".to_owned(), "HTML".to_owned()],
+        summary:                 "I'm a senior Rust developer and sometimes I do also HTML.".to_owned(),
+        headline:                "Backend developer with Rust experience".to_owned(),
+        work_experiences:        vec!["Database Administrator".to_owned()],
+        batch_ends_at:           "2020-01-01T12:00:00+00:00".to_owned(),
+        weight:                  -5,
+        avatar_url:              AVATAR_URL.to_owned(),
+        salary_expectations:     vec![SalaryExpectations {
+                                   minimum:  Some(40000),
+                                   maximum:  Some(50000),
+                                   currency: "EUR".to_owned(),
+                                   city:     "Berlin".to_owned()
+                                 }],
+        latest_position:         "Developer".to_owned(),
+        ..test_support::talent(1)
       },
-
       Talent {
-        id:                            2,
-        accepted:                      true,
-        desired_work_roles:            vec![],
-        desired_work_roles_vanilla:    None,
-        desired_work_roles_experience: vec![],
-        professional_experience:       "8+".to_owned(),
-        work_locations:                vec!["Rome".to_owned(),"Berlin".to_owned()],
-        current_location:              "Berlin".to_owned(),
-        work_authorization:            "yes".to_owned(),
-        skills:                        vec!["Rust".to_owned(), "HTML5".to_owned(), "Java".to_owned()],
-        summary:                       "I'm a java dev with some tricks up my sleeves".to_owned(),
-        headline:                      "Senior Java engineer".to_owned(),
-        work_experiences:              vec![],
-        contacted_company_ids:         vec![],
-        batch_starts_at:               epoch_from_year!("2006"),
-        batch_ends_at:                 epoch_from_year!("2020"),
-        added_to_batch_at:             epoch_from_year!("2006"),
-        weight:                        6,
-        blocked_companies:             vec![22],
-        avatar_url:                    "https://secure.gravatar.com/avatar/a0b9ad63fb35d210a218c317e0a6284e.jpg?s=250".to_owned(),
-        salary_expectations:           vec![],
-        latest_position:               "".to_owned(),
-        languages:                     vec!["German".to_owned(), "English".to_owned()]
+        professional_experience: "8+".to_owned(),
+        work_locations:          vec!["Rome".to_owned(), "Berlin".to_owned()],
+        skills:                  vec!["Rust".to_owned(), "HTML5".to_owned(), "Java".to_owned()],
+        summary:                 "I'm a java dev with some tricks up my sleeves".to_owned(),
+        headline:                "Senior Java engineer".to_owned(),
+        batch_ends_at:           "2020-01-01T12:00:00+00:00".to_owned(),
+        weight:                  6,
+        blocked_companies:       vec![22],
+        avatar_url:              AVATAR_URL.to_owned(),
+        languages:               vec!["German".to_owned(), "English".to_owned()],
+        ..test_support::talent(2)
       },
-
       Talent {
-        id:                            3,
-        accepted:                      false,
-        desired_work_roles:            vec![],
-        desired_work_roles_vanilla:    None,
-        desired_work_roles_experience: vec![],
-        professional_experience:       "1..2".to_owned(),
-        work_locations:                vec!["Berlin".to_owned()],
-        current_location:              "Berlin".to_owned(),
-        work_authorization:            "yes".to_owned(),
-        skills:                        vec![],
-        summary:                       "".to_owned(),
-        headline:                      "".to_owned(),
-        work_experiences:              vec![],
-        contacted_company_ids:         vec![],
-        batch_starts_at:               epoch_from_year!("2007"),
-        batch_ends_at:                 epoch_from_year!("2020"),
-        added_to_batch_at:             epoch_from_year!("2011"),
-        weight:                        6,
-        blocked_companies:             vec![],
-        avatar_url:                    "https://secure.gravatar.com/avatar/a0b9ad63fb35d210a218c317e0a6284e.jpg?s=250".to_owned(),
-        salary_expectations:           vec![],
-        latest_position:               "".to_owned(),
-        languages:                     vec!["English".to_owned()]
+        accepted:                false,
+        professional_experience: "1..2".to_owned(),
+        batch_starts_at:         "2007-01-01T12:00:00+00:00".to_owned(),
+        batch_ends_at:           "2020-01-01T12:00:00+00:00".to_owned(),
+        added_to_batch_at:       "2011-01-01T12:00:00+00:00".to_owned(),
+        weight:                  6,
+        avatar_url:              AVATAR_URL.to_owned(),
+        ..test_support::talent(3)
       },
-
       Talent {
-        id:                            4,
-        accepted:                      true,
         desired_work_roles:            vec!["Fullstack".to_owned(), "DevOps".to_owned()],
-        desired_work_roles_vanilla:    None,
         desired_work_roles_experience: vec!["2..3".to_owned(), "5".to_owned()],
         professional_experience:       "1..2".to_owned(),
-        work_locations:                vec!["Berlin".to_owned()],
-        current_location:              "Berlin".to_owned(),
         work_authorization:            "no".to_owned(),
         skills:                        vec!["ClojureScript".to_owned(), "C++".to_owned(), "React.js".to_owned()],
         summary:                       "ClojureScript right now, previously C++".to_owned(),
         headline:                      "Senior fullstack developer with sysadmin skills".to_owned(),
         work_experiences:              vec!["Backend Engineer".to_owned(), "Database Administrator".to_owned()],
         contacted_company_ids:         vec![6],
-        batch_starts_at:               epoch_from_year!("2008"),
-        batch_ends_at:                 epoch_from_year!("2020"),
-        added_to_batch_at:             epoch_from_year!("2011"),
-        weight:                        0,
-        blocked_companies:             vec![],
-        avatar_url:                    "https://secure.gravatar.com/avatar/a0b9ad63fb35d210a218c317e0a6284e.jpg?s=250".to_owned(),
-        salary_expectations:           vec![],
-        latest_position:               "".to_owned(),
-        languages:                     vec!["English".to_owned()]
+        batch_starts_at:               "2008-01-01T12:00:00+00:00".to_owned(),
+        batch_ends_at:                 "2020-01-01T12:00:00+00:00".to_owned(),
+        added_to_batch_at:             "2011-01-01T12:00:00+00:00".to_owned(),
+        avatar_url:                    AVATAR_URL.to_owned(),
+        ..test_support::talent(4)
       },
-
       Talent {
-        id:                            5,
-        accepted:                      true,
         desired_work_roles:            vec!["Fullstack".to_owned(), "DevOps".to_owned()],
-        desired_work_roles_vanilla:    None,
         desired_work_roles_experience: vec!["2..3".to_owned(), "5".to_owned()],
         professional_experience:       "1..2".to_owned(),
-        work_locations:                vec!["Berlin".to_owned()],
-        current_location:              "Berlin".to_owned(),
-        work_authorization:            "yes".to_owned(),
         skills:                        vec!["JavaScript".to_owned(), "C++".to_owned(), "Ember.js".to_owned()],
         summary:                       "C++ and frontend dev. HTML, C++, JavaScript and C#. Did I say C++?".to_owned(),
         headline:                      "Amazing C developer".to_owned(),
-        work_experiences:              vec![],
         contacted_company_ids:         vec![6],
-        batch_starts_at:               epoch_from_year!("2008"),
-        batch_ends_at:                 epoch_from_year!("2020"),
-        added_to_batch_at:             epoch_from_year!("2011"),
-        weight:                        0,
-        blocked_companies:             vec![],
-        avatar_url:                    "https://secure.gravatar.com/avatar/a0b9ad63fb35d210a218c317e0a6284e.jpg?s=250".to_owned(),
-        salary_expectations:           vec![],
-        latest_position:               "".to_owned(),
-        languages:                     vec!["English".to_owned()]
+        batch_starts_at:               "2008-01-01T12:00:00+00:00".to_owned(),
+        batch_ends_at:                 "2020-01-01T12:00:00+00:00".to_owned(),
+        added_to_batch_at:             "2011-01-01T12:00:00+00:00".to_owned(),
+        avatar_url:                    AVATAR_URL.to_owned(),
+        ..test_support::talent(5)
       }
-    ];
-
-    Talent::index(&mut client, &config.es.index, talents).is_ok()
+    ]
   }
 
-  fn refresh_index(mut client: &mut Client) {
-    client.refresh()
-          .with_indexes(&[&config.es.index])
-          .send()
-          .unwrap();
+  pub fn populate_index(mut client: &mut Client, index: &str) -> bool {
+    test_support::populate_index(&mut client, index, talents()).is_ok()
   }
 
   #[test]
   fn test_search() {
     let mut client = make_client();
+    let index      = test_index(&mut client);
 
-    assert!(Talent::reset_index(&mut client, &*config.es.index).is_ok());
-    refresh_index(&mut client);
-
-    assert!(populate_index(&mut client));
-    refresh_index(&mut client);
+    assert!(populate_index(&mut client, &index.name));
+    refresh_index(&mut client, &index.name);
 
     // no parameters are given
     {
-      let results = Talent::search(&mut client, &*config.es.index, &Map::new());
+      let results = search(&index.name, &Map::new(), &SearchOptions::default()).unwrap();
       assert_eq!(vec![4, 5, 2, 1], results.ids());
       assert_eq!(4, results.total);
       assert!(results.highlights().iter().all(|r| r.is_none()));
     }
 
     {
-      assert!(Talent::delete(&mut client, "1", &*config.es.index).is_ok());
-      assert!(Talent::delete(&mut client, "4", &*config.es.index).is_ok());
-      refresh_index(&mut client);
+      assert!(Talent::delete(&mut client, "1", &*index.name).is_ok());
+      assert!(Talent::delete(&mut client, "4", &*index.name).is_ok());
+      refresh_index(&mut client, &index.name);
 
-      let results = Talent::search(&mut client, &*config.es.index, &Map::new());
+      let results = search(&index.name, &Map::new(), &SearchOptions::default()).unwrap();
       assert_eq!(vec![5, 2], results.ids());
 
-      assert!(populate_index(&mut client));
-      refresh_index(&mut client);
+      assert!(populate_index(&mut client, &index.name));
+      refresh_index(&mut client, &index.name);
     }
 
     // a non existing index is given
@@ -820,7 +1613,7 @@ mod tests {
       let mut map = Map::new();
       map.assign("index", Value::String("lololol".into())).unwrap();
 
-      let results = Talent::search(&mut client, &*config.es.index, &map);
+      let results = search(&index.name, &map, &SearchOptions::default()).unwrap();
       assert!(results.is_empty());
     }
 
@@ -829,7 +1622,7 @@ mod tests {
       let mut map = Map::new();
       map.assign("epoch", Value::String(epoch_from_year!("2040"))).unwrap();
 
-      let results = Talent::search(&mut client, &*config.es.index, &map);
+      let results = search(&index.name, &map, &SearchOptions::default()).unwrap();
       assert!(results.is_empty());
     }
 
@@ -838,7 +1631,7 @@ mod tests {
       let mut map = Map::new();
       map.assign("epoch", Value::String(epoch_from_year!("2006"))).unwrap();
 
-      let results = Talent::search(&mut client, &*config.es.index, &map);
+      let results = search(&index.name, &map, &SearchOptions::default()).unwrap();
       assert_eq!(vec![2, 1], results.ids());
     }
 
@@ -847,7 +1640,7 @@ mod tests {
       let mut map = Map::new();
       map.assign("desired_work_roles[]", Value::String("Fullstack".into())).unwrap();
 
-      let results = Talent::search(&mut client, &*config.es.index, &map);
+      let results = search(&index.name, &map, &SearchOptions::default()).unwrap();
       assert_eq!(vec![4, 5], results.ids());
     }
 
@@ -856,7 +1649,7 @@ mod tests {
       let mut map = Map::new();
       map.assign("professional_experience[]", Value::String("8+".into())).unwrap();
 
-      let results = Talent::search(&mut client, &*config.es.index, &map);
+      let results = search(&index.name, &map, &SearchOptions::default()).unwrap();
       assert_eq!(vec![2], results.ids());
     }
 
@@ -865,7 +1658,7 @@ mod tests {
       let mut map = Map::new();
       map.assign("work_locations[]", Value::String("Rome".into())).unwrap();
 
-      let results = Talent::search(&mut client, &*config.es.index, &map);
+      let results = search(&index.name, &map, &SearchOptions::default()).unwrap();
       assert_eq!(vec![2], results.ids());
     }
 
@@ -874,7 +1667,7 @@ mod tests {
       let mut map = Map::new();
       map.assign("languages[]", Value::String("German".into())).unwrap();
 
-      let results = Talent::search(&mut client, &*config.es.index, &map);
+      let results = search(&index.name, &map, &SearchOptions::default()).unwrap();
       assert_eq!(vec![2], results.ids());
     }
 
@@ -883,7 +1676,7 @@ mod tests {
       let mut map = Map::new();
       map.assign("keywords", Value::String("HTML5".into())).unwrap();
 
-      let results = Talent::search(&mut client, &*config.es.index, &map);
+      let results = search(&index.name, &map, &SearchOptions::default()).unwrap();
       assert_eq!(vec![1, 2, 5], results.ids());
     }
 
@@ -892,7 +1685,7 @@ mod tests {
       let mut map = Map::new();
       map.assign("keywords", Value::String("html".into())).unwrap();
 
-      let results = Talent::search(&mut client, &*config.es.index, &map);
+      let results = search(&index.name, &map, &SearchOptions::default()).unwrap();
       assert_eq!(vec![1, 2, 5], results.ids());
     }
 
@@ -902,7 +1695,7 @@ mod tests {
       map.assign("keywords", Value::String("Rust, HTML5 and HTML".into())).unwrap();
       map.assign("work_locations[]", Value::String("Rome".into())).unwrap();
 
-      let results = Talent::search(&mut client, &*config.es.index, &map);
+      let results = search(&index.name, &map, &SearchOptions::default()).unwrap();
       assert_eq!(vec![2], results.ids());
     }
 
@@ -911,7 +1704,7 @@ mod tests {
       let mut map = Map::new();
       map.assign("keywords", Value::String("reactjs".into())).unwrap();
 
-      let results = Talent::search(&mut client, &*config.es.index, &map);
+      let results = search(&index.name, &map, &SearchOptions::default()).unwrap();
       assert_eq!(vec![4], results.ids());
     }
 
@@ -922,7 +1715,7 @@ mod tests {
       map.assign("work_locations[]", Value::String("Berlin".into())).unwrap();
       map.assign("desired_work_roles[]", Value::String("Fullstack".into())).unwrap();
 
-      let results = Talent::search(&mut client, &*config.es.index, &map);
+      let results = search(&index.name, &map, &SearchOptions::default()).unwrap();
       assert_eq!(vec![4], results.ids());
     }
 
@@ -931,7 +1724,7 @@ mod tests {
       let mut map = Map::new();
       map.assign("keywords", Value::String("Criogenesi".into())).unwrap();
 
-      let results = Talent::search(&mut client, &*config.es.index, &map);
+      let results = search(&index.name, &map, &SearchOptions::default()).unwrap();
       assert!(results.is_empty());
     }
 
@@ -940,7 +1733,7 @@ mod tests {
       let mut map = Map::new();
       map.assign("keywords", Value::String("".into())).unwrap();
 
-      let results = Talent::search(&mut client, &*config.es.index, &map);
+      let results = search(&index.name, &map, &SearchOptions::default()).unwrap();
       assert_eq!(vec![4, 5, 2, 1], results.ids());
     }
 
@@ -952,7 +1745,7 @@ mod tests {
         let mut map = Map::new();
         map.assign("keywords", Value::String("Java".into())).unwrap();
 
-        let results = Talent::search(&mut client, &*config.es.index, &map);
+        let results = search(&index.name, &map, &SearchOptions::default()).unwrap();
         assert_eq!(vec![2, 5], results.ids());
       }
 
@@ -961,7 +1754,7 @@ mod tests {
         let mut map = Map::new();
         map.assign("keywords", Value::String("javascript".into())).unwrap();
 
-        let results = Talent::search(&mut client, &*config.es.index, &map);
+        let results = search(&index.name, &map, &SearchOptions::default()).unwrap();
         assert_eq!(vec![5], results.ids());
       }
 
@@ -970,7 +1763,7 @@ mod tests {
         let mut map = Map::new();
         map.assign("keywords", Value::String("script".into())).unwrap();
 
-        let results = Talent::search(&mut client, &*config.es.index, &map);
+        let results = search(&index.name, &map, &SearchOptions::default()).unwrap();
         assert_eq!(vec![4, 5], results.ids());
       }
     }
@@ -981,7 +1774,7 @@ mod tests {
         let mut map = Map::new();
         map.assign("keywords", Value::String("right now".into())).unwrap();
 
-        let results = Talent::search(&mut client, &*config.es.index, &map);
+        let results = search(&index.name, &map, &SearchOptions::default()).unwrap();
         assert_eq!(vec![4], results.ids());
       }
 
@@ -989,7 +1782,7 @@ mod tests {
         let mut map = Map::new();
         map.assign("keywords", Value::String("C++".into())).unwrap();
 
-        let results = Talent::search(&mut client, &*config.es.index, &map);
+        let results = search(&index.name, &map, &SearchOptions::default()).unwrap();
         assert_eq!(vec![4, 5], results.ids());
       }
 
@@ -997,7 +1790,7 @@ mod tests {
         let mut map = Map::new();
         map.assign("keywords", Value::String("C#".into())).unwrap();
 
-        let results = Talent::search(&mut client, &*config.es.index, &map);
+        let results = search(&index.name, &map, &SearchOptions::default()).unwrap();
         assert_eq!(vec![5], results.ids());
       }
 
@@ -1005,7 +1798,7 @@ mod tests {
         let mut map = Map::new();
         map.assign("keywords", Value::String("rust and".into())).unwrap();
 
-        let results = Talent::search(&mut client, &*config.es.index, &map);
+        let results = search(&index.name, &map, &SearchOptions::default()).unwrap();
         assert_eq!(vec![1, 2], results.ids());
       }
     }
@@ -1015,7 +1808,7 @@ mod tests {
       let mut map = Map::new();
       map.assign("keywords", Value::String("senior".to_owned())).unwrap();
 
-      let results = Talent::search(&mut client, &*config.es.index, &map);
+      let results = search(&index.name, &map, &SearchOptions::default()).unwrap();
       assert_eq!(vec![2, 4, 1], results.ids());
     }
 
@@ -1024,7 +1817,7 @@ mod tests {
       let mut map = Map::new();
       map.assign("keywords", Value::String("Devops".to_owned())).unwrap();
 
-      let results = Talent::search(&mut client, &*config.es.index, &map);
+      let results = search(&index.name, &map, &SearchOptions::default()).unwrap();
       assert_eq!(vec![4, 5], results.ids());
     }
 
@@ -1033,7 +1826,7 @@ mod tests {
       let mut map = Map::new();
       map.assign("keywords", Value::String("database admin".to_owned())).unwrap();
 
-      let results = Talent::search(&mut client, &*config.es.index, &map);
+      let results = search(&index.name, &map, &SearchOptions::default()).unwrap();
       assert_eq!(vec![1, 4], results.ids());
     }
 
@@ -1042,7 +1835,7 @@ mod tests {
       let mut map = Map::new();
       map.assign("keywords", Value::String("C#".into())).unwrap();
 
-      let results    = Talent::search(&mut client, &*config.es.index, &map).talents;
+      let results    = search(&index.name, &map, &SearchOptions::default()).unwrap().talents;
       let highlights = results.into_iter().map(|r| r.highlight.unwrap()).collect::<Vec<HighlightResult>>();
       assert_eq!(Some(&vec![" C#.".to_owned()]), highlights[0].get("summary"));
     }
@@ -1052,7 +1845,7 @@ mod tests {
       let mut map = Map::new();
       map.assign("company_id", Value::String("6".into())).unwrap();
 
-      let results = Talent::search(&mut client, &*config.es.index, &map);
+      let results = search(&index.name, &map, &SearchOptions::default()).unwrap();
       assert_eq!(vec![2, 1], results.ids());
     }
 
@@ -1068,7 +1861,7 @@ mod tests {
       map.assign("ids[]", Value::U64(7)).unwrap();
       map.assign("ids[]", Value::U64(8)).unwrap();
 
-      let results = Talent::search(&mut client, &*config.es.index, &map);
+      let results = search(&index.name, &map, &SearchOptions::default()).unwrap();
       assert_eq!(vec![4, 5, 2, 1], results.ids());
       assert_eq!(4, results.total);
     }
@@ -1078,7 +1871,7 @@ mod tests {
       let mut map = Map::new();
       map.assign("work_authorization[]", Value::String("no".into())).unwrap();
 
-      let results = Talent::search(&mut client, &*config.es.index, &map);
+      let results = search(&index.name, &map, &SearchOptions::default()).unwrap();
       assert_eq!(vec![4], results.ids());
     }
 
@@ -1087,7 +1880,7 @@ mod tests {
       let mut map = Map::new();
       map.assign("contacted_talents[]", Value::String("2".into())).unwrap();
 
-      let results = Talent::search(&mut client, &*config.es.index, &map);
+      let results = search(&index.name, &map, &SearchOptions::default()).unwrap();
       assert_eq!(vec![4, 5, 1], results.ids());
     }
 
@@ -1096,11 +1889,504 @@ mod tests {
       let mut map = Map::new();
       map.assign("company_id", Value::U64(22)).unwrap();
 
-      let results = Talent::search(&mut client, &*config.es.index, &map);
+      let results = search(&index.name, &map, &SearchOptions::default()).unwrap();
       assert_eq!(vec![4, 5, 1], results.ids());
     }
   }
 
+  #[test]
+  fn test_preview() {
+    let options = SearchOptions { timeout: Some(500), min_score: Some(0.2), ..Default::default() };
+
+    // with keywords, the talents are sorted by score
+    {
+      let mut map = Map::new();
+      map.assign("keywords", Value::String("Rust".into())).unwrap();
+      map.assign("per_page", Value::String("5".into())).unwrap();
+
+      let preview = Talent::preview(&*config.es.index, &map, &options).unwrap();
+      let body    = preview.find("body").unwrap();
+
+      assert_eq!(preview.find("index"), Some(&serde_json::to_value(&vec![&*config.es.index])));
+      assert_eq!(body.find("size"),    Some(&serde_json::to_value(&5)));
+      assert_eq!(body.find("timeout"), Some(&serde_json::to_value(&"500ms")));
+      assert_eq!(body.find("min_score"), Some(&serde_json::to_value(&0.2)));
+      assert!(body.find("highlight").is_some());
+      assert!(body.find("sort").is_none());
+
+      // only the fields of the found talents are fetched
+      assert_eq!(body.find("_source").and_then(|source| source.as_array()).map(|fields| fields.len()), Some(10));
+
+      // only the keywords are scored
+      let query = body.find("query").and_then(|query| query.find("bool")).unwrap();
+      assert!(query.find("must").is_some());
+      assert!(query.find("filter").is_some());
+    }
+
+    // otherwise, they are sorted by batch
+    {
+      let preview = Talent::preview(&*config.es.index, &Map::new(), &SearchOptions::default()).unwrap();
+      let body    = preview.find("body").unwrap();
+
+      assert!(body.find("sort").is_some());
+      assert!(body.find("highlight").is_none());
+      assert!(body.find("timeout").is_none());
+    }
+
+    // as they are when the keywords are only whitespaces and control characters
+    {
+      let mut map = Map::new();
+      map.assign("keywords", Value::String(" \t\u{0} ".into())).unwrap();
+
+      let preview = Talent::preview(&*config.es.index, &map, &SearchOptions::default()).unwrap();
+      assert!(preview.find("body").and_then(|body| body.find("sort")).is_some());
+    }
+
+    // the syntax inside the keywords is matched as plain text
+    {
+      let mut map = Map::new();
+      map.assign("keywords", Value::String("\"rust\"  OR\ntitle:/.*/".into())).unwrap();
+
+      let preview = Talent::preview(&*config.es.index, &map, &SearchOptions::default()).unwrap();
+      let query   = preview.find("body").and_then(|body| body.find("query")).unwrap();
+      assert!(serde_json::to_string(query).unwrap().contains("\"query\":\"\\\"rust\\\" OR title:/.*/\""));
+    }
+
+    // several indexes are searched at once
+    {
+      let mut map = Map::new();
+      map.assign("index", Value::String("talents, archived_talents,".into())).unwrap();
+
+      let preview = Talent::preview(&*config.es.index, &map, &SearchOptions::default()).unwrap();
+      assert_eq!(preview.find("index"), Some(&serde_json::to_value(&vec!["talents", "archived_talents"])));
+      assert!(preview.find("ignore_unavailable").is_none());
+
+      // skipping the ones that do not exist
+      map.assign("ignore_unavailable", Value::String("true".into())).unwrap();
+      let preview = Talent::preview(&*config.es.index, &map, &SearchOptions::default()).unwrap();
+      assert_eq!(preview.find("ignore_unavailable"), Some(&serde_json::to_value(&true)));
+    }
+
+    // the minimum score is not applied when `score_filtering` is disabled
+    {
+      let mut map = Map::new();
+      map.assign("keywords", Value::String("Rust".into())).unwrap();
+
+      let mut options = SearchOptions::default();
+      options.features.score_filtering = false;
+
+      let preview = Talent::preview(&*config.es.index, &map, &options).unwrap();
+      assert!(preview.find("body").unwrap().find("min_score").is_none());
+    }
+
+    // the computed score ranks the talents when `computed_score` is enabled
+    {
+      let mut options = SearchOptions::default();
+      options.features.computed_score = true;
+
+      let preview = Talent::preview(&*config.es.index, &Map::new(), &options).unwrap();
+      let sort    = preview.pointer("/body/sort").unwrap();
+      assert!(sort.pointer("/1/computed_score").is_some());
+
+      let mut map = Map::new();
+      map.assign("keywords", Value::String("Rust".into())).unwrap();
+
+      let preview = Talent::preview(&*config.es.index, &map, &options).unwrap();
+      let factor  = preview.pointer("/body/query/function_score/functions/0/field_value_factor").unwrap();
+      assert_eq!(factor.find("field"), Some(&serde_json::to_value(&"computed_score")));
+    }
+
+    // the full text matches are rescored by the LTR model when `ltr_rescoring` is enabled
+    {
+      let ltr = Ltr { log_features: true, ..Default::default() };
+      let mut options = SearchOptions { rescoring: Some(Rescoring::new("talents_v1", &ltr)), ..Default::default() };
+
+      let mut map = Map::new();
+      map.assign("keywords", Value::String("Rust".into())).unwrap();
+
+      let preview = Talent::preview(&*config.es.index, &map, &options).unwrap();
+      assert!(preview.pointer("/body/rescore").is_none());
+      assert!(preview.pointer("/body/sort").is_some());
+
+      options.features.ltr_rescoring = true;
+      let preview = Talent::preview(&*config.es.index, &map, &options).unwrap();
+      assert_eq!(preview.pointer("/body/rescore/query/rescore_query/sltr/model"), Some(&serde_json::to_value(&"talents_v1")));
+      assert!(preview.pointer("/body/ext/ltr_log").is_some());
+      assert!(preview.pointer("/body/sort").is_none());
+
+      // but not the sorted ones
+      let preview = Talent::preview(&*config.es.index, &Map::new(), &options).unwrap();
+      assert!(preview.pointer("/body/rescore").is_none());
+    }
+  }
+
+  #[test]
+  fn test_complexity() {
+    let limits  = QueryLimits { max_keywords: 3, max_terms: 2, max_clauses: 20 };
+    let options = SearchOptions { limits: limits, ..Default::default() };
+
+    // within the limits
+    {
+      let mut map = Map::new();
+      map.assign("keywords", Value::String("senior  rust developer".into())).unwrap();
+      map.assign("ids[]", Value::String("1".into())).unwrap();
+      map.assign("ids[]", Value::String("2".into())).unwrap();
+      assert!(Talent::preview(&*config.es.index, &map, &options).is_ok());
+    }
+
+    // too many keywords
+    {
+      let mut map = Map::new();
+      map.assign("keywords", Value::String("senior rust go developer".into())).unwrap();
+      assert_eq!(Talent::preview(&*config.es.index, &map, &options).unwrap_err().to_string(),
+                 "`keywords` cannot contain more than 3 words, got 4.");
+    }
+
+    // too many values of a single param
+    {
+      let mut map = Map::new();
+      for id in 1..4 {
+        map.assign("ids[]", Value::String(id.to_string())).unwrap();
+      }
+      assert_eq!(Talent::preview(&*config.es.index, &map, &options).unwrap_err().to_string(),
+                 "`ids` cannot be given more than 2 values, got 3.");
+    }
+
+    // too many clauses overall
+    {
+      let mut map = Map::new();
+      map.assign("keywords", Value::String("senior rust developer".into())).unwrap();
+      for param in &["ids[]", "contacted_talents[]", "presented_talents[]"] {
+        map.assign(param, Value::String("1".into())).unwrap();
+        map.assign(param, Value::String("2".into())).unwrap();
+      }
+      assert_eq!(Talent::preview(&*config.es.index, &map, &options).unwrap_err().to_string(),
+                 "The search would contain 21 clauses, more than 20.");
+    }
+  }
+
+  #[test]
+  fn test_score_threshold() {
+    let mut keywords = Map::new();
+    keywords.assign("keywords", Value::String("Rust".into())).unwrap();
+
+    let decay = Decay { field: "batch_starts_at".to_owned(), scale: 30, offset: 0, decay: 0.5 };
+    let min_score = |params: &Map, score_threshold: ScoreThreshold| {
+      let options = SearchOptions { min_score: Some(0.2), score_threshold: score_threshold, decay: Some(decay.clone()), ..Default::default() };
+      let preview = Talent::preview(&*config.es.index, params, &options).unwrap();
+      preview.find("body").unwrap().find("min_score").cloned()
+    };
+
+    // by default, only the full text searches are filtered
+    assert_eq!(min_score(&keywords, ScoreThreshold::Keywords), Some(serde_json::to_value(&0.2)));
+    assert_eq!(min_score(&Map::new(), ScoreThreshold::Keywords), None);
+
+    assert_eq!(min_score(&keywords, ScoreThreshold::Always), Some(serde_json::to_value(&0.2)));
+    assert_eq!(min_score(&Map::new(), ScoreThreshold::Always), Some(serde_json::to_value(&0.2)));
+
+    assert_eq!(min_score(&keywords, ScoreThreshold::Never), None);
+    assert_eq!(min_score(&Map::new(), ScoreThreshold::Never), None);
+
+    // the searches without keywords are scored by the decay alone, hence not filtered without it
+    let options = SearchOptions { min_score: Some(0.2), score_threshold: ScoreThreshold::Always, decay: Some(decay), ..Default::default() };
+    let preview = Talent::preview(&*config.es.index, &Map::new(), &options).unwrap();
+    assert_eq!(preview.pointer("/body/query/function_score/boost_mode"), Some(&serde_json::to_value(&"replace")));
+
+    let preview = Talent::preview(&*config.es.index, &keywords, &options).unwrap();
+    assert!(!serde_json::to_string(preview.pointer("/body/query").unwrap()).unwrap().contains("replace"));
+
+    let options = SearchOptions { min_score: Some(0.2), score_threshold: ScoreThreshold::Always, ..Default::default() };
+    let preview = Talent::preview(&*config.es.index, &Map::new(), &options).unwrap();
+    assert_eq!(preview.pointer("/body/min_score"), None);
+  }
+
+  #[test]
+  fn test_pinned_ids() {
+    let mut params = Map::new();
+    params.assign("pinned_ids[]", Value::String("4".into())).unwrap();
+
+    let options = SearchOptions { score_threshold: ScoreThreshold::Always, ..Default::default() };
+    let preview = Talent::preview(&*config.es.index, &params, &options).unwrap();
+
+    // the pinned talents are scored first, the other ones keep their sorting
+    assert_eq!(preview.pointer("/body/sort/0/_score/order"), Some(&serde_json::to_value(&"desc")));
+    assert_eq!(preview.pointer("/body/query/bool/should/0/constant_score/boost"), Some(&serde_json::to_value(&10000.0)));
+    assert_eq!(preview.pointer("/body/min_score"), None);
+
+    params.assign("keywords", Value::String("Rust".into())).unwrap();
+    let preview = Talent::preview(&*config.es.index, &params, &options).unwrap();
+    assert_eq!(preview.pointer("/body/sort/0/_score/order"), Some(&serde_json::to_value(&"desc")));
+    assert_eq!(preview.pointer("/body/sort/1/id/order"), Some(&serde_json::to_value(&"asc")));
+    assert!(preview.pointer("/body/min_score").is_some());
+  }
+
+  #[test]
+  fn test_sorting_tiebreaker() {
+    // the ties are broken by id, whatever the sorting
+    for &(computed_score, pinned) in &[(false, false), (true, false), (false, true), (true, true)] {
+      let sort   = serde_json::to_value(&Talent::sorting_criteria(computed_score, pinned));
+      let fields = sort.as_array().unwrap();
+
+      assert_eq!(fields.last().and_then(|field| field.pointer("/id/order")), Some(&serde_json::to_value(&"asc")));
+      assert_eq!(fields.iter().filter(|field| field.find("id").is_some()).count(), 1);
+    }
+  }
+
+  #[test]
+  fn test_epoch() {
+    let clock = FixedClock(UTC.ymd(2017, 3, 1).and_hms(10, 30, 12));
+
+    let mut map = Map::new();
+    map.assign("epoch", Value::String("2016-03-01T10:30:12+00:00".into())).unwrap();
+    assert_eq!(Talent::epoch(&map, &clock).unwrap(), "2016-03-01T10:30:12+00:00");
+
+    // missing or `now`, it is the current time
+    assert_eq!(Talent::epoch(&Map::new(), &clock).unwrap(), "2017-03-01T10:30:12+00:00");
+
+    let mut map = Map::new();
+    map.assign("epoch", Value::String("now".into())).unwrap();
+    assert_eq!(Talent::epoch(&map, &clock).unwrap(), "2017-03-01T10:30:12+00:00");
+
+    // a typo is refused rather than read as now
+    let mut map = Map::new();
+    map.assign("epoch", Value::String("2017-03-01 10:30".into())).unwrap();
+    assert!(Talent::epoch(&map, &clock).is_err());
+    assert!(Talent::preview(&*config.es.index, &map, &SearchOptions::default()).is_err());
+
+    // the searches are run at the time of their clock
+    let options = SearchOptions { clock: Arc::new(clock), ..Default::default() };
+    let preview = Talent::preview(&*config.es.index, &Map::new(), &options).unwrap();
+    assert!(preview.to_string().contains("2017-03-01T10:30:12+00:00"));
+  }
+
+  /// A hit of the talent `id` found in `index`, without `_source` if `source` is `false`.
+  fn hit(index: &str, id: u64, source: bool) -> String {
+    let source = format!(r#",
+      "_source": {{
+        "id": {}, "headline": "", "avatar_url": "", "work_locations": [], "current_location": "",
+        "salary_expectations": [], "desired_work_roles": [], "desired_work_roles_experience": [],
+        "latest_position": "", "batch_starts_at": "2017-03-01T10:30:12+00:00"
+      }}"#, id);
+
+    format!(r#"{{ "_index": "{}", "_type": "talent", "_id": "{}", "_score": 1.0{} }}"#,
+            index, id, if source { &*source } else { "" })
+  }
+
+  /// A response of ElasticSearch containing `hits`.
+  fn response(hits: Vec<String>) -> serde_json::Value {
+    serde_json::from_str(&format!(r#"{{
+      "took": 1, "timed_out": false,
+      "_shards": {{"total": 2, "successful": 2, "failed": 0}},
+      "hits": {{"total": {}, "max_score": 1.0, "hits": [{}]}}
+    }}"#, hits.len(), hits.join(","))).unwrap()
+  }
+
+  #[test]
+  fn test_dedup() {
+    // the same talents are found in both indexes, with interleaved hits
+    let hits = vec![hit("talents", 1, true), hit("archived", 2, true), hit("archived", 1, true),
+                    hit("talents", 3, true), hit("talents", 2, true)];
+
+    let results = Talent::results(&Map::new(), &SearchOptions::default(), response(hits)).unwrap();
+    assert_eq!(results.ids(), vec![1, 2, 3]);
+  }
+
+  #[test]
+  fn test_missing_source() {
+    let hits = vec![hit("talents", 1, true), hit("talents", 2, false), hit("talents", 3, true)];
+
+    let results = Talent::results(&Map::new(), &SearchOptions::default(), response(hits)).unwrap();
+    assert_eq!(results.ids(), vec![1, 3]);
+  }
+
+  /// The params of the searches, as sent by anyone.
+  fn arbitrary_params(lists: Vec<Vec<String>>, keywords: String, company_id: u64, ids: Vec<String>) -> Map {
+    let mut map = Map::new();
+
+    for (param, values) in ["desired_work_roles[]", "professional_experience[]", "work_authorization[]",
+                            "work_locations[]", "languages[]"].iter().zip(lists.into_iter()) {
+      for value in values {
+        map.assign(param, Value::String(value)).unwrap();
+      }
+    }
+
+    for id in ids {
+      map.assign("ids[]", Value::String(id.to_owned())).unwrap();
+      map.assign("presented_talents[]", Value::String(id.to_owned())).unwrap();
+      map.assign("contacted_talents[]", Value::String(id)).unwrap();
+    }
+
+    map.assign("keywords", Value::String(keywords)).unwrap();
+    map.assign("company_id", Value::String(company_id.to_string())).unwrap();
+    map
+  }
+
+  /// Return whether `query`, or any query nested inside it, has a `key`.
+  fn mentions(query: &serde_json::Value, key: &str) -> bool {
+    match *query {
+      serde_json::Value::Object(ref object) => object.iter().any(|(k, value)| k == key || mentions(value, key)),
+      serde_json::Value::Array(ref values)  => values.iter().any(|value| mentions(value, key)),
+      _                                     => false
+    }
+  }
+
+  proptest! {
+    #[test]
+    fn test_search_filters_properties(ref lists in collection::vec(collection::vec("\\PC{0,12}", 0..4), 5..6),
+                                      ref keywords in "\\PC{0,40}",
+                                      company_id in 0u64..1_000_000,
+                                      ref ids in collection::vec("[0-9a-z-]{0,6}", 0..3)) {
+      let map   = arbitrary_params(lists.to_owned(), keywords.to_owned(), company_id, ids.to_owned());
+      let query = serde_json::to_value(&Talent::search_filters(&map, "2016-03-04T12:24:00+01:00", &SearchOptions::default()));
+
+      // the company never finds the talents it has contacted or that have blocked it
+      let must_not = query.pointer("/bool/must_not").and_then(|must_not| must_not.as_array()).cloned().unwrap_or(vec![]);
+      for field in &["contacted_company_ids", "blocked_companies"] {
+        prop_assert!(must_not.iter().any(|filter| {
+          filter.pointer(&format!("/terms/{}", field))
+                .and_then(|values| values.as_array())
+                .map(|values| values.contains(&serde_json::Value::U64(company_id)))
+                .unwrap_or(false)
+        }), "{} is not excluded: {}", field, query);
+      }
+
+      // only the accepted talents inside a living batch are visible
+      let filter = query.pointer("/bool/filter").cloned().unwrap_or(serde_json::Value::Null);
+      prop_assert!(mentions(&filter, "accepted"), "the visibility filters are missing: {}", query);
+      prop_assert!(mentions(&filter, "batch_starts_at") && mentions(&filter, "batch_ends_at"),
+                   "the batch filters are missing: {}", query);
+    }
+  }
+
+  /// Compare the queries built for `GOLDEN_CASES` with the ones of the golden files,
+  /// so that any change to them shows up in the review. A missing golden file fails
+  /// the test as well: `UPDATE_GOLDEN=1 cargo test golden` (re)writes all of them.
+  #[test]
+  fn test_golden_queries() {
+    let update  = env::var("UPDATE_GOLDEN").ok().map_or(false, |update| update == "1");
+    let options = SearchOptions::default();
+    let mut changed = vec![];
+
+    if update {
+      fs::create_dir_all(GOLDEN_DIR).unwrap();
+    }
+
+    for &(name, query) in GOLDEN_CASES.iter() {
+      let params    = curl::params(query).unwrap();
+      let generated = serde_json::to_value(&btreemap! {
+        "params"           => serde_json::to_value(&query),
+        "search_filters"   => serde_json::to_value(&Talent::search_filters(&params, "2016-03-04T12:24:00+01:00", &options)),
+        "full_text_search" => serde_json::to_value(&Talent::full_text_search(&params, &options))
+      });
+
+      let path = format!("{}/{}.json", GOLDEN_DIR, name);
+      let mut golden = String::new();
+
+      if update {
+        let mut file = File::create(&path).unwrap();
+        writeln!(file, "{}", serde_json::to_string_pretty(&generated).unwrap()).unwrap();
+        continue;
+      }
+
+      match File::open(&path).and_then(|mut file| file.read_to_string(&mut golden)) {
+        Ok(_) => {
+          if serde_json::from_str::<serde_json::Value>(&golden).unwrap() != generated {
+            changed.push(format!("{}:\n{}", path, serde_json::to_string_pretty(&generated).unwrap()));
+          }
+        },
+        Err(err) => changed.push(format!("{} is missing ({}):\n{}", path, err, serde_json::to_string_pretty(&generated).unwrap()))
+      }
+    }
+
+    assert!(changed.is_empty(), "The queries differ from the golden files (rerun with UPDATE_GOLDEN=1 if expected):\n{}",
+            changed.join("\n"));
+  }
+
+  #[test]
+  fn test_search_with() {
+    let backend = RecordingBackend::new();
+
+    let mut map = Map::new();
+    map.assign("keywords", Value::String("rust".into())).unwrap();
+    map.assign("per_page", Value::String("2".into())).unwrap();
+
+    let results = Talent::search_with(&backend, "talents", &map, &SearchOptions::default()).unwrap();
+    assert!(results.is_empty());
+
+    let requests = backend.requests();
+    assert_eq!(requests.len(), 1);
+    assert_eq!(requests[0].find("index"), Some(&serde_json::to_value(&vec!["talents"])));
+    assert_eq!(requests[0].pointer("/body/size"), Some(&serde_json::to_value(&2u64)));
+    assert!(requests[0].pointer("/body/query").is_some());
+  }
+
+  #[test]
+  fn test_compatibility() {
+    let mut client = make_client();
+    let url = &es_url();
+    es::configure_compatibility(&config.es.compatibility, url);
+
+    let index = test_index(&mut client);
+
+    assert!(populate_index(&mut client, &index.name));
+    refresh_index(&mut client, &index.name);
+
+    assert_eq!(check_mapping(url, &*index.name, &Talent::mapping()).unwrap(), Vec::<String>::new());
+
+    // `hits.total` is a number on both the distributions
+    let search = serde_json::from_str::<serde_json::Value>(
+      &format!("{{\"index\": [\"{}\"], \"body\": {{\"query\": {{\"match_all\": {{}}}}}}}}", index.name)).unwrap();
+    assert!(es::search(url, &search, false).unwrap().pointer("/hits/total").unwrap().is_u64());
+
+    let responses = es::msearch(url, &[search]).unwrap();
+    assert!(responses[0].as_ref().unwrap().pointer("/hits/total").unwrap().is_u64());
+  }
+
+  #[test]
+  fn test_facets() {
+    let mut client = make_client();
+    let index      = test_index(&mut client);
+
+    assert!(populate_index(&mut client, &index.name));
+    refresh_index(&mut client, &index.name);
+
+    let mut map = Map::new();
+    map.assign("work_locations[]", Value::String("Rome".into())).unwrap();
+
+    let options = SearchOptions { clock: Arc::new(FixedClock(UTC.ymd(2015, 1, 1).and_hms(12, 0, 0))), ..Default::default() };
+    let facets  = Talent::facets(&mut client, &*index.name, &map, &options).unwrap();
+
+    // the other facets are filtered by the work locations
+    assert_eq!(facets["languages"], vec![
+      FacetCount { value: "English".to_owned(), count: 1 },
+      FacetCount { value: "German".to_owned(),  count: 1 }
+    ]);
+
+    // while the work locations are not filtered by themselves
+    assert!(facets["work_locations"].contains(&FacetCount { value: "Rome".to_owned(), count: 1 }));
+    assert!(facets["work_locations"].iter().any(|facet| facet.value == "Berlin"));
+
+    // once their batches are over, the talents are not counted anymore
+    let options = SearchOptions { clock: Arc::new(FixedClock(UTC.ymd(2021, 1, 1).and_hms(12, 0, 0))), ..Default::default() };
+    let facets  = Talent::facets(&mut client, &*index.name, &map, &options).unwrap();
+    assert!(facets.values().all(|counts| counts.is_empty()));
+  }
+
+  #[test]
+  fn test_stream() {
+    let mut client = make_client();
+    let index      = test_index(&mut client);
+
+    assert!(populate_index(&mut client, &index.name));
+    refresh_index(&mut client, &index.name);
+
+    let mut ids = TalentStream::new(&es_url(), &*index.name).unwrap()
+                               .map(|talent| talent.unwrap().id)
+                               .collect::<Vec<u64>>();
+    ids.sort();
+    assert_eq!(ids, vec![1, 2, 3, 4, 5]);
+  }
+
   #[test]
   fn test_json_decode() {
     let payload = "{
@@ -1133,4 +2419,155 @@ mod tests {
     assert!(resource.is_ok());
     assert_eq!(resource.unwrap().desired_work_roles, vec!["C/C++ Engineer"]);
   }
+
+  #[test]
+  fn test_percolate() {
+    let talent: Talent = serde_json::from_str("{
+      \"id\":13,
+      \"desired_work_roles\":[\"C/C++ Engineer\"],
+      \"desired_work_roles_experience\":[\"2..4\"],
+      \"professional_experience\":\"8+\",
+      \"work_locations\":[\"Berlin\"],
+      \"current_location\":\"Berlin\",
+      \"work_authorization\":\"yes\",
+      \"skills\":[\"Rust\"],
+      \"summary\":\"Blabla\",
+      \"headline\":\"I see things, I do stuff\",
+      \"contacted_company_ids\":[1],
+      \"accepted\":true,
+      \"batch_starts_at\":\"2016-03-04T12:24:00+01:00\",
+      \"batch_ends_at\":\"2016-04-11T12:24:00+02:00\",
+      \"added_to_batch_at\":\"2016-03-11T12:24:37+01:00\",
+      \"weight\":0,
+      \"blocked_companies\":[99],
+      \"work_experiences\":[],
+      \"avatar_url\":\"\",
+      \"salary_expectations\":[],
+      \"latest_position\":\"Developer\",
+      \"languages\":[\"English\"]
+    }").unwrap();
+
+    // no filters
+    {
+      assert!(talent.percolate(&Map::new()));
+    }
+
+    // at least one of the desired work roles
+    {
+      let mut map = Map::new();
+      map.assign("desired_work_roles[]", Value::String("C/C++ Engineer".into())).unwrap();
+      map.assign("desired_work_roles[]", Value::String("DevOps".into())).unwrap();
+      assert!(talent.percolate(&map));
+    }
+
+    // none of the desired work roles
+    {
+      let mut map = Map::new();
+      map.assign("desired_work_roles[]", Value::String("DevOps".into())).unwrap();
+      assert!(!talent.percolate(&map));
+    }
+
+    // blocked company
+    {
+      let mut map = Map::new();
+      map.assign("company_id", Value::String("99".into())).unwrap();
+      assert!(!talent.percolate(&map));
+    }
+  }
+
+  #[test]
+  fn test_is_visible_at() {
+    let mut talent: Talent = serde_json::from_str("{
+      \"id\":13,
+      \"desired_work_roles\":[],
+      \"desired_work_roles_experience\":[],
+      \"professional_experience\":\"8+\",
+      \"work_locations\":[],
+      \"current_location\":\"Berlin\",
+      \"work_authorization\":\"yes\",
+      \"skills\":[],
+      \"summary\":\"\",
+      \"headline\":\"\",
+      \"contacted_company_ids\":[],
+      \"accepted\":true,
+      \"batch_starts_at\":\"2016-03-04T12:24:00+01:00\",
+      \"batch_ends_at\":\"2016-04-11T12:24:00+02:00\",
+      \"added_to_batch_at\":\"2016-03-11T12:24:37+01:00\",
+      \"weight\":0,
+      \"blocked_companies\":[],
+      \"work_experiences\":[],
+      \"avatar_url\":\"\",
+      \"salary_expectations\":[],
+      \"latest_position\":\"\",
+      \"languages\":[]
+    }").unwrap();
+
+    // inside the batch
+    assert!(talent.is_visible_at("2016-03-20T00:00:00+00:00"));
+
+    // outside the batch
+    assert!(!talent.is_visible_at("2016-05-01T00:00:00+00:00"));
+
+    // not accepted
+    talent.accepted = false;
+    assert!(!talent.is_visible_at("2016-03-20T00:00:00+00:00"));
+  }
+
+  /// A talent in the batch from 2016-03-04 to 2016-04-11.
+  fn batched_talent() -> Talent {
+    serde_json::from_str("{
+      \"id\":13,
+      \"desired_work_roles\":[],
+      \"desired_work_roles_experience\":[],
+      \"professional_experience\":\"8+\",
+      \"work_locations\":[],
+      \"current_location\":\"Berlin\",
+      \"work_authorization\":\"yes\",
+      \"skills\":[],
+      \"summary\":\"\",
+      \"headline\":\"\",
+      \"contacted_company_ids\":[],
+      \"accepted\":true,
+      \"batch_starts_at\":\"2016-03-04T12:24:00+01:00\",
+      \"batch_ends_at\":\"2016-04-11T12:24:00+02:00\",
+      \"added_to_batch_at\":\"2016-03-11T12:24:37+01:00\",
+      \"weight\":0,
+      \"blocked_companies\":[],
+      \"work_experiences\":[],
+      \"avatar_url\":\"\",
+      \"salary_expectations\":[],
+      \"latest_position\":\"\",
+      \"languages\":[]
+    }").unwrap()
+  }
+
+  #[test]
+  fn test_validate() {
+    let mut talent = batched_talent();
+    assert!(talent.validate().is_empty());
+
+    talent.batch_ends_at = "2016-03-01T12:24:00+01:00".to_owned();
+    assert_eq!(talent.validate(), vec![
+      FieldError::new("batch_starts_at", "`batch_starts_at` cannot be after `batch_ends_at`.".to_owned())
+    ]);
+
+    talent.batch_starts_at = "next monday".to_owned();
+    talent.batch_ends_at   = "".to_owned();
+    let fields = talent.validate().into_iter().map(|error| error.field).collect::<Vec<String>>();
+    assert_eq!(fields, vec!["batch_starts_at", "batch_ends_at"]);
+  }
+
+  #[test]
+  fn test_conflicts() {
+    let stored = batched_talent();
+
+    // the other fields can change
+    let mut talent = stored.clone();
+    talent.headline = "Rustacean".to_owned();
+    assert!(talent.conflicts(&stored).is_empty());
+
+    // moved to another batch
+    talent.batch_ends_at = "2016-05-11T12:24:00+02:00".to_owned();
+    assert_eq!(talent.conflicts(&stored), vec!["batch_ends_at".to_owned()]);
+  }
 }
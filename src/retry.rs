@@ -0,0 +1,147 @@
+//! The retries of the requests to ElasticSearch that failed for transient
+//! reasons (i.e.: the node is restarting or overloaded), shared by the
+//! reads and the writes.
+
+use rs_es::error::EsError;
+
+use config::Retry as RetryPolicy;
+use error::Error;
+
+use std::cmp;
+use std::fmt::Display;
+use std::sync::RwLock;
+use std::thread;
+use std::time::Duration;
+
+lazy_static! {
+  static ref POLICY: RwLock<RetryPolicy> = RwLock::new(RetryPolicy::default());
+}
+
+/// Use `policy` for the following retries.
+pub fn configure(policy: RetryPolicy) {
+  *POLICY.write().unwrap() = policy;
+}
+
+/// Run `operation`, running it again as given by the configured policy
+/// while it fails with a retryable error. Return its last result.
+pub fn retry<T, E: Retryable, F: FnMut() -> Result<T, E>>(operation: F) -> Result<T, E> {
+  let policy = POLICY.read().unwrap().to_owned();
+  retry_with(&policy, operation)
+}
+
+/// Same as `retry`, using the given `policy`.
+pub fn retry_with<T, E: Retryable, F: FnMut() -> Result<T, E>>(policy: &RetryPolicy, mut operation: F) -> Result<T, E> {
+  let mut attempt = 0;
+
+  loop {
+    match operation() {
+      Err(ref err) if attempt < policy.attempts && err.is_retryable(policy) => {
+        let delay = delay(policy, attempt);
+        warn!("Retrying in {}ms after: {}", delay, err);
+        thread::sleep(Duration::from_millis(delay));
+        attempt += 1;
+      },
+      result => return result
+    }
+  }
+}
+
+/// Return how long to wait (in milliseconds) before the retry following `attempt`,
+/// doubling the delay at every attempt up to `max_delay_ms`.
+fn delay(policy: &RetryPolicy, attempt: u32) -> u64 {
+  let factor = 1u64.checked_shl(attempt).unwrap_or(u64::max_value());
+  cmp::min(policy.backoff_ms.saturating_mul(factor), policy.max_delay_ms)
+}
+
+/// The errors that can be caused by transient failures.
+pub trait Retryable: Display {
+  /// Return whether the operation failing with the error can be retried under `policy`.
+  fn is_retryable(&self, policy: &RetryPolicy) -> bool;
+}
+
+/// The errors caused by the connection or by one of the statuses of
+/// the policy, which are found at the beginning of the messages of the
+/// server errors (i.e.: `503 Service Unavailable - ...`), are retried.
+impl Retryable for EsError {
+  fn is_retryable(&self, policy: &RetryPolicy) -> bool {
+    match *self {
+      EsError::HttpError(_) | EsError::IoError(_) => true,
+      EsError::EsServerError(ref message) => {
+        message.split_whitespace()
+               .next()
+               .and_then(|status| status.parse::<u16>().ok())
+               .map(|status| policy.statuses.contains(&status))
+               .unwrap_or(false)
+      },
+      _ => false
+    }
+  }
+}
+
+impl Retryable for Error {
+  fn is_retryable(&self, policy: &RetryPolicy) -> bool {
+    match *self {
+      Error::EsTransport(ref err) | Error::EsQuery(ref err) => err.is_retryable(policy),
+      _                                                     => false
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use retry::{retry_with, delay};
+  use config::Retry as RetryPolicy;
+  use error::Error;
+
+  use rs_es::error::EsError;
+
+  fn policy() -> RetryPolicy {
+    RetryPolicy { attempts: 2, backoff_ms: 1, max_delay_ms: 3, statuses: vec![503] }
+  }
+
+  #[test]
+  fn test_retry() {
+    let mut calls = 0;
+    let result = retry_with(&policy(), || {
+      calls += 1;
+      if calls < 3 { Err(EsError::EsServerError("503 Service Unavailable - {}".to_owned())) } else { Ok(calls) }
+    });
+    assert_eq!(result.unwrap(), 3);
+
+    // the other statuses are not retried
+    let mut calls = 0;
+    let result: Result<(), EsError> = retry_with(&policy(), || {
+      calls += 1;
+      Err(EsError::EsServerError("400 Bad Request - {}".to_owned()))
+    });
+    assert!(result.is_err());
+    assert_eq!(calls, 1);
+
+    // up to `attempts` times
+    let mut calls = 0;
+    let result: Result<(), EsError> = retry_with(&policy(), || {
+      calls += 1;
+      Err(EsError::EsServerError("503 Service Unavailable - {}".to_owned()))
+    });
+    assert!(result.is_err());
+    assert_eq!(calls, 3);
+
+    // the errors wrapped by `Error` as well, but not the other ones
+    let mut calls = 0;
+    let result: Result<(), Error> = retry_with(&policy(), || {
+      calls += 1;
+      if calls < 2 { Err(Error::from(EsError::EsServerError("503 Service Unavailable - {}".to_owned()))) }
+      else { Err(Error::Validation("Invalid epoch.".to_owned())) }
+    });
+    assert!(result.is_err());
+    assert_eq!(calls, 2);
+  }
+
+  #[test]
+  fn test_delay() {
+    assert_eq!(delay(&policy(), 0), 1);
+    assert_eq!(delay(&policy(), 1), 2);
+    assert_eq!(delay(&policy(), 2), 3);
+    assert_eq!(delay(&policy(), 80), 3);
+  }
+}
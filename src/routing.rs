@@ -0,0 +1,99 @@
+//! The custom routing of the documents to the shards: once a resource is given
+//! `[resources.<name>] routing = "<field>"`, its documents are indexed on the
+//! shard picked by the value of that field (i.e. `batch_starts_at`, so that the
+//! talents of a batch share a shard) rather than by their id. The searches given
+//! the `routing` param only hit the shards of its values instead of all of them.
+//!
+//! The routing only picks the shards that are searched, it does not filter the
+//! documents: the other ones stored on the same shards are still matched.
+//! The values are turned into keys safe to be sent in a query string, the
+//! characters other than the letters, digits, `-` and `.` being replaced by `_`.
+
+use params::{Map, Value};
+use serde_json::Value as JsonValue;
+
+use config::Config;
+
+use std::collections::BTreeMap;
+use std::sync::RwLock;
+
+lazy_static! {
+  static ref FIELDS: RwLock<BTreeMap<String, String>> = RwLock::new(BTreeMap::new());
+}
+
+/// Route the documents of the resources of `config` by their `routing` field, if any.
+pub fn configure(config: &Config) {
+  *FIELDS.write().unwrap() = config.resources
+                                   .iter()
+                                   .filter_map(|(name, resource)| resource.routing.to_owned().map(|field| (name.to_owned(), field)))
+                                   .collect();
+}
+
+/// Return the field routing the documents of `resource`, or `None` if they are routed by id.
+pub fn field(resource: &str) -> Option<String> {
+  FIELDS.read().unwrap().get(resource).cloned()
+}
+
+/// Return the routing key of `document`, the value of its `field` if it is a string or a number.
+pub fn key(field: &str, document: &JsonValue) -> Option<String> {
+  match document.find(field) {
+    Some(&JsonValue::String(ref value)) if !value.is_empty() => Some(token(value)),
+    Some(&JsonValue::I64(value))                             => Some(value.to_string()),
+    Some(&JsonValue::U64(value))                             => Some(value.to_string()),
+    _                                                        => None
+  }
+}
+
+/// Return the routing keys given by the `routing` param, comma separated, if any.
+pub fn keys(params: &Map) -> Option<String> {
+  let keys = match params.get("routing") {
+    Some(&Value::Array(ref values)) => values.iter()
+                                             .filter_map(|value| match *value {
+                                               Value::String(ref key) if !key.is_empty() => Some(token(key)),
+                                               _                                         => None
+                                             })
+                                             .collect::<Vec<String>>(),
+    Some(&Value::String(ref key)) if !key.is_empty() => vec![token(key)],
+    _                                                => vec![]
+  };
+
+  if keys.is_empty() { None } else { Some(keys.join(",")) }
+}
+
+/// Return the routing key of `value`, which has to be the same when indexing and searching.
+fn token(value: &str) -> String {
+  value.chars()
+       .map(|c| match c {
+         'a'...'z' | 'A'...'Z' | '0'...'9' | '-' | '.' => c,
+         _                                             => '_'
+       })
+       .collect()
+}
+
+#[cfg(test)]
+mod tests {
+  use routing::{key, keys};
+
+  use params::{Map, Value};
+  use serde_json;
+
+  #[test]
+  fn test_key() {
+    let document = serde_json::from_str("{\"id\": 42, \"batch_starts_at\": \"2017-03-06T00:00:00+00:00\", \"headline\": \"\"}").unwrap();
+
+    assert_eq!(key("batch_starts_at", &document), Some("2017-03-06T00_00_00_00_00".to_owned()));
+    assert_eq!(key("id", &document), Some("42".to_owned()));
+    assert_eq!(key("headline", &document), None);
+    assert_eq!(key("batch_ends_at", &document), None);
+  }
+
+  #[test]
+  fn test_keys() {
+    let mut params = Map::new();
+    assert_eq!(keys(&params), None);
+
+    params.assign("routing[]", Value::String("2017-03-06T00:00:00+00:00".to_owned())).unwrap();
+    params.assign("routing[]", Value::String("2017-03-20T00:00:00+00:00".to_owned())).unwrap();
+    assert_eq!(keys(&params), Some("2017-03-06T00_00_00_00_00,2017-03-20T00_00_00_00_00".to_owned()));
+  }
+}
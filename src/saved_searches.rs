@@ -0,0 +1,291 @@
+//! The searches saved by the recruiters, stored with their params into a
+//! dedicated ElasticSearch index (`[saved_searches]`), so that they can be
+//! listed, deleted and run again by ID (see `/saved-searches`) without the
+//! main application keeping their query strings.
+//!
+//! The ones saved with `"alert": true` are run again periodically (see `alerts`),
+//! remembering the talents they matched the last time.
+
+use serde_json;
+use serde_json::Value as JsonValue;
+
+use params::Map;
+
+use chrono::UTC;
+
+use hyper::method::Method;
+
+use rs_es::error::EsError;
+
+use error::Error;
+use request_id;
+use schema;
+use es;
+
+/// The ElasticSearch type of the saved searches.
+const SAVED_SEARCH_TYPE: &'static str = "saved_search";
+
+/// How many saved searches of a recruiter are listed, the latest first.
+const MAX_LISTED: u64 = 100;
+
+/// How many saved searches are alerted at every run.
+const MAX_ALERTED: u64 = 10000;
+
+/// The params of the saved search that can be replaced when running it.
+const PAGINATION: [&'static str; 2] = ["offset", "per_page"];
+
+/// A saved search, i.e.: `{"id": "4f0c...", "recruiter_id": "42", "name": "Rust in Berlin",
+/// "params": {"keywords": "rust", "work_locations": ["Berlin"]}, "created_at": "2017-03-04T12:24:00+00:00"}`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SavedSearch {
+  pub id:           String,
+  pub recruiter_id: String,
+  pub name:         String,
+  pub params:       JsonValue, // as the objects given to `POST /talents/msearch`
+  pub created_at:   String,
+  #[serde(default)]
+  pub alert:        bool,
+  #[serde(default)]
+  pub matched_ids:  Vec<String>, // the top matches of the last alerting run
+  #[serde(default)]
+  pub alerted_at:   Option<String>
+}
+
+/// The body expected by `POST /saved-searches`.
+#[derive(Deserialize, Debug)]
+pub struct NewSavedSearch {
+  pub recruiter_id: String,
+  pub name:         String,
+  pub params:       JsonValue,
+  #[serde(default)]
+  pub alert:        bool
+}
+
+impl SavedSearch {
+  /// Return the search to save, with a new ID, unless `new` is not valid.
+  pub fn new(new: NewSavedSearch) -> Result<SavedSearch, String> {
+    if new.recruiter_id.trim().is_empty() {
+      return Err("`recruiter_id` cannot be empty.".to_owned());
+    }
+
+    if new.name.trim().is_empty() {
+      return Err("`name` cannot be empty.".to_owned());
+    }
+
+    try!(schema::from_json(&new.params));
+
+    Ok(SavedSearch {
+      id:           request_id::generate(),
+      recruiter_id: new.recruiter_id,
+      name:         new.name.trim().to_owned(),
+      params:       new.params,
+      created_at:   UTC::now().to_rfc3339(),
+      alert:        new.alert,
+      matched_ids:  vec![],
+      alerted_at:   None
+    })
+  }
+
+  /// Return the params of the search, whose pagination is replaced by the one of `overrides`.
+  pub fn params(&self, overrides: &Map) -> Result<Map, String> {
+    let mut params = try!(schema::from_json(&self.params));
+
+    for name in PAGINATION.iter() {
+      if let Some(value) = overrides.get(*name) {
+        params.remove(*name);
+        try!(params.assign(name, value.to_owned()).map_err(|e| format!("{:?}", e)));
+      }
+    }
+
+    Ok(params)
+  }
+}
+
+/// Create `index` with the mapping of the saved searches, whose params are
+/// stored without being indexed. Nothing is done if it exists already.
+pub fn create_index(url: &str, index: &str) -> Result<(), EsError> {
+  let mapping = btreemap! {
+    "mappings" => btreemap! {
+      SAVED_SEARCH_TYPE => btreemap! {
+        "properties" => btreemap! {
+          "id"           => serde_json::to_value(&btreemap! { "type" => "string", "index" => "not_analyzed" }),
+          "recruiter_id" => serde_json::to_value(&btreemap! { "type" => "string", "index" => "not_analyzed" }),
+          "name"         => serde_json::to_value(&btreemap! { "type" => "string" }),
+          "params"       => serde_json::to_value(&btreemap! {
+                              "type"    => serde_json::to_value(&"object"),
+                              "enabled" => serde_json::to_value(&false)
+                            }),
+          "created_at"   => serde_json::to_value(&btreemap! { "type" => "date" }),
+          "alert"        => serde_json::to_value(&btreemap! { "type" => "boolean" }),
+          "matched_ids"  => serde_json::to_value(&btreemap! { "type" => "string", "index" => "no" }),
+          "alerted_at"   => serde_json::to_value(&btreemap! { "type" => "date" })
+        }
+      }
+    }
+  };
+
+  match es::put(url, index, &serde_json::to_string(&mapping).unwrap()) {
+    Ok(_)                                                                => Ok(()),
+    Err(EsError::EsServerError(ref err)) if err.contains("already_exists") => Ok(()),
+    Err(err)                                                             => Err(err)
+  }
+}
+
+fn document_path(index: &str, id: &str) -> String {
+  format!("{}/{}/{}", index, SAVED_SEARCH_TYPE, id)
+}
+
+/// Return whether `err` is the `404` returned for a missing document.
+fn is_missing(err: &EsError) -> bool {
+  match *err {
+    EsError::EsServerError(ref message) => message.starts_with("404"),
+    _                                   => false
+  }
+}
+
+/// Store `search` into `index`, searchable right away.
+pub fn save(url: &str, index: &str, search: &SavedSearch) -> Result<(), Error> {
+  let path = format!("{}?refresh=true", document_path(index, &search.id));
+  try!(es::put(url, &path, &serde_json::to_string(search).unwrap()));
+  Ok(())
+}
+
+/// Return the saved search with given `id`, if it exists.
+pub fn get(url: &str, index: &str, id: &str) -> Result<Option<SavedSearch>, Error> {
+  match es::get(url, &document_path(index, id)) {
+    Ok(response)                    => Ok(response.find("_source").and_then(|source| serde_json::from_value(source.to_owned()).ok())),
+    Err(ref err) if is_missing(err) => Ok(None),
+    Err(err)                        => Err(Error::from(err))
+  }
+}
+
+/// Delete the saved search with given `id`, returning whether it existed.
+pub fn delete(url: &str, index: &str, id: &str) -> Result<bool, Error> {
+  let path = format!("{}?refresh=true", document_path(index, id));
+
+  match es::request(Method::Delete, url, &path, None) {
+    Ok(_)                           => Ok(true),
+    Err(ref err) if is_missing(err) => Ok(false),
+    Err(err)                        => Err(Error::from(err))
+  }
+}
+
+/// Return the body of the search of the saved searches of `recruiter_id`, the latest first.
+pub fn query(recruiter_id: &str) -> JsonValue {
+  serde_json::to_value(&btreemap! {
+    "size"  => serde_json::to_value(&MAX_LISTED),
+    "query" => serde_json::to_value(&btreemap! {
+      "bool" => btreemap! {
+        "filter" => vec![btreemap! { "term" => btreemap! { "recruiter_id" => recruiter_id } }]
+      }
+    }),
+    "sort" => serde_json::to_value(&vec![btreemap! { "created_at" => btreemap! { "order" => "desc" } }])
+  })
+}
+
+/// Return the saved searches of `recruiter_id`, the latest first.
+pub fn list(url: &str, index: &str, recruiter_id: &str) -> Result<Vec<SavedSearch>, Error> {
+  let body     = query(recruiter_id);
+  let response = try!(es::post(url, &format!("{}/_search", index), &serde_json::to_string(&body).unwrap()));
+  Ok(hits(&response))
+}
+
+/// Return the body of the search of the saved searches to alert about, the least recently alerted first.
+pub fn alerting_query() -> JsonValue {
+  serde_json::to_value(&btreemap! {
+    "size"  => serde_json::to_value(&MAX_ALERTED),
+    "query" => serde_json::to_value(&btreemap! {
+      "bool" => btreemap! {
+        "filter" => vec![btreemap! { "term" => btreemap! { "alert" => true } }]
+      }
+    }),
+    "sort" => serde_json::to_value(&vec![btreemap! { "alerted_at" => btreemap! { "order" => "asc", "missing" => "_first" } }])
+  })
+}
+
+/// Return the saved searches to alert about, the least recently alerted first.
+pub fn alerting(url: &str, index: &str) -> Result<Vec<SavedSearch>, Error> {
+  let body     = alerting_query();
+  let response = try!(es::post(url, &format!("{}/_search", index), &serde_json::to_string(&body).unwrap()));
+  Ok(hits(&response))
+}
+
+/// Return the saved searches found by `response`.
+fn hits(response: &JsonValue) -> Vec<SavedSearch> {
+  response.pointer("/hits/hits")
+          .and_then(|hits| hits.as_array())
+          .map(|hits| hits.iter()
+                          .filter_map(|hit| hit.find("_source"))
+                          .filter_map(|source| serde_json::from_value(source.to_owned()).ok())
+                          .collect())
+          .unwrap_or(vec![])
+}
+
+#[cfg(test)]
+mod tests {
+  use saved_searches::{SavedSearch, NewSavedSearch, query, alerting_query, hits};
+
+  use params::{Map, Value};
+
+  use serde_json;
+
+  fn new_search(recruiter_id: &str, name: &str, params: &str) -> NewSavedSearch {
+    NewSavedSearch {
+      recruiter_id: recruiter_id.to_owned(),
+      name:         name.to_owned(),
+      params:       serde_json::from_str(params).unwrap(),
+      alert:        false
+    }
+  }
+
+  #[test]
+  fn test_new() {
+    let search = SavedSearch::new(new_search("42", " Rust in Berlin ", "{\"keywords\": \"rust\", \"work_locations\": [\"Berlin\"]}")).unwrap();
+    assert_eq!(search.recruiter_id, "42");
+    assert_eq!(search.name, "Rust in Berlin");
+    assert!(!search.id.is_empty());
+
+    assert!(SavedSearch::new(new_search("", "Rust", "{}")).is_err());
+    assert!(SavedSearch::new(new_search("42", " ", "{}")).is_err());
+    assert!(SavedSearch::new(new_search("42", "Rust", "[\"rust\"]")).is_err());
+    assert!(SavedSearch::new(new_search("42", "Rust", "{\"keywords\": {\"rust\": true}}")).is_err());
+  }
+
+  #[test]
+  fn test_params() {
+    let search = SavedSearch::new(new_search("42", "Rust", "{\"keywords\": \"rust\", \"per_page\": 10}")).unwrap();
+
+    let mut overrides = Map::new();
+    overrides.assign("per_page", Value::String("5".into())).unwrap();
+    overrides.assign("keywords", Value::String("haskell".into())).unwrap();
+
+    let params = search.params(&overrides).unwrap();
+    assert_eq!(params.get("keywords"), Some(&Value::String("rust".into())));
+    assert_eq!(params.get("per_page"), Some(&Value::String("5".into())));
+  }
+
+  #[test]
+  fn test_query() {
+    let body = query("42");
+
+    assert_eq!(body.pointer("/query/bool/filter/0/term/recruiter_id"), Some(&serde_json::to_value(&"42")));
+    assert_eq!(body.pointer("/sort/0/created_at/order"), Some(&serde_json::to_value(&"desc")));
+
+    let alerting = alerting_query();
+    assert_eq!(alerting.pointer("/query/bool/filter/0/term/alert"), Some(&serde_json::to_value(&true)));
+    assert_eq!(alerting.pointer("/sort/0/alerted_at/missing"), Some(&serde_json::to_value(&"_first")));
+  }
+
+  #[test]
+  fn test_hits() {
+    let response = serde_json::from_str("{\"hits\": {\"total\": 1, \"hits\": [{\"_id\": \"1\", \"_source\": {\"id\": \"1\", \
+                                         \"recruiter_id\": \"42\", \"name\": \"Rust\", \"params\": {\"keywords\": \"rust\"}, \
+                                         \"created_at\": \"2017-03-04T12:24:00+00:00\"}}]}}").unwrap();
+
+    let searches = hits(&response);
+    assert_eq!(searches.len(), 1);
+    assert_eq!(searches[0].name, "Rust");
+    assert!(!searches[0].alert && searches[0].matched_ids.is_empty());
+    assert!(hits(&serde_json::from_str("{}").unwrap()).is_empty());
+  }
+}
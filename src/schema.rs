@@ -0,0 +1,303 @@
+//! The schemas of the query strings accepted by the endpoints,
+//! used to reject unknown or malformed params.
+
+use params::{Map, Value};
+
+use serde_json::Value as JsonValue;
+
+use chrono::DateTime;
+
+/// The type expected for the values of a param.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Kind {
+  String,
+  Integer, // not negative, since they are ids or pagination offsets
+  Boolean,
+  Date
+}
+
+impl Kind {
+  fn as_str(&self) -> &'static str {
+    match *self {
+      Kind::String  => "a string",
+      Kind::Integer => "an integer",
+      Kind::Boolean => "either `true` or `false`",
+      Kind::Date    => "a RFC 3339 date or `now`"
+    }
+  }
+
+  /// Return `true` if `value` can be read as this kind.
+  fn accepts(&self, value: &Value) -> bool {
+    match (*self, value) {
+      (Kind::String,  &Value::String(_))        => true,
+      (Kind::Integer, &Value::String(ref s))    => s.parse::<u64>().is_ok(),
+      (Kind::Integer, &Value::I64(i))           => i >= 0,
+      (Kind::Integer, &Value::U64(_))           => true,
+      (Kind::Boolean, &Value::String(ref s))    => s == "true" || s == "false",
+      (Kind::Boolean, &Value::Boolean(_))       => true,
+      (Kind::Date,    &Value::String(ref s))    => s == "now" || DateTime::parse_from_rfc3339(s).is_ok(),
+      _                                         => false
+    }
+  }
+}
+
+/// A param accepted by an endpoint. When `multiple` is `true`,
+/// it must be given as array (i.e.: `ids[]=1&ids[]=2`).
+#[derive(Debug, Clone, PartialEq)]
+pub struct Param {
+  pub name:     &'static str,
+  pub kind:     Kind,
+  pub multiple: bool
+}
+
+impl Param {
+  pub fn single(name: &'static str, kind: Kind) -> Param {
+    Param { name: name, kind: kind, multiple: false }
+  }
+
+  pub fn multiple(name: &'static str, kind: Kind) -> Param {
+    Param { name: name, kind: kind, multiple: true }
+  }
+}
+
+/// A problem found in a given param, or in a field of an indexed document.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct FieldError {
+  pub field:   String,
+  pub message: String
+}
+
+impl FieldError {
+  pub fn new(field: &str, message: String) -> FieldError {
+    FieldError {
+      field:   field.to_owned(),
+      message: message
+    }
+  }
+}
+
+/// Return the problems found in `params` according to `schema`,
+/// sorted by param name. An empty `Vec` means that they are valid.
+pub fn validate(params: &Map, schema: &[Param]) -> Vec<FieldError> {
+  let mut errors = vec![];
+
+  for (name, value) in params.iter() {
+    let param = match schema.iter().find(|param| param.name == name) {
+      Some(param) => param,
+      None => {
+        errors.push(FieldError::new(name, format!("`{}` is not a known param.", name)));
+        continue;
+      }
+    };
+
+    match (param.multiple, value) {
+      (true, &Value::Array(ref values)) => {
+        if !values.iter().all(|value| param.kind.accepts(value)) {
+          errors.push(FieldError::new(name, format!("`{}[]` expects {} for each value.", name, param.kind.as_str())));
+        }
+      },
+      (true, _) => {
+        errors.push(FieldError::new(name, format!("`{}` must be given as `{}[]`.", name, name)));
+      },
+      (false, &Value::Array(_)) => {
+        errors.push(FieldError::new(name, format!("`{}` expects a single value.", name)));
+      },
+      (false, value) => {
+        if !param.kind.accepts(value) {
+          errors.push(FieldError::new(name, format!("`{}` expects {}.", name, param.kind.as_str())));
+        }
+      }
+    }
+  }
+
+  errors
+}
+
+/// Return the problems found in the values of the params of `schema` given in `params`,
+/// which the conversion macros (i.e.: `u64_vec_from_params!`) would otherwise drop.
+/// Unlike `validate`, the unknown params, the shape of the values and the empty
+/// values (read as missing) are not taken into account.
+pub fn malformed(params: &Map, schema: &[Param]) -> Vec<FieldError> {
+  let mut errors = vec![];
+
+  for param in schema {
+    let values = match params.get(param.name) {
+      Some(&Value::Array(ref values)) => values.iter().collect::<Vec<&Value>>(),
+      Some(value)                     => vec![value],
+      None                            => continue
+    };
+
+    let accepted = values.into_iter()
+                         .filter(|value| **value != Value::String("".into()))
+                         .all(|value| param.kind.accepts(value));
+
+    if !accepted {
+      errors.push(FieldError::new(param.name, format!("`{}` expects {}.", param.name, param.kind.as_str())));
+    }
+  }
+
+  errors
+}
+
+/// Return the params that a query string would carry for the given
+/// JSON object (i.e.: `{"ids": [1, 2]}` is read as `ids[]=1&ids[]=2`).
+pub fn from_json(object: &JsonValue) -> Result<Map, String> {
+  let object = match object.as_object() {
+    Some(object) => object,
+    None         => return Err(format!("Expected an object of params, found `{}`.", object))
+  };
+
+  let scalar = |name: &str, value: &JsonValue| match *value {
+    JsonValue::String(ref value) => Ok(value.to_owned()),
+    JsonValue::Bool(_)     |
+    JsonValue::I64(_)      |
+    JsonValue::U64(_)      |
+    JsonValue::F64(_)            => Ok(value.to_string()),
+    _                            => Err(format!("`{}` must contain only strings, numbers or booleans.", name))
+  };
+
+  let mut map = Map::new();
+
+  for (name, value) in object.iter() {
+    match *value {
+      JsonValue::Null             => continue,
+      JsonValue::Array(ref items) => for item in items {
+        try!(map.assign(&format!("{}[]", name), Value::String(try!(scalar(name, item))))
+                .map_err(|e| format!("{:?}", e)));
+      },
+      _ => try!(map.assign(name, Value::String(try!(scalar(name, value))))
+                   .map_err(|e| format!("{:?}", e)))
+    }
+  }
+
+  Ok(map)
+}
+
+/// Return `params` as a JSON object sorted by name (i.e.: `ids[]=1&ids[]=2`
+/// is returned as `{"ids": ["1", "2"]}`), i.e. to log them.
+pub fn to_json(params: &Map) -> JsonValue {
+  fn convert(value: &Value) -> JsonValue {
+    match *value {
+      Value::Boolean(value)    => JsonValue::Bool(value),
+      Value::I64(value)        => JsonValue::I64(value),
+      Value::U64(value)        => JsonValue::U64(value),
+      Value::F64(value)        => JsonValue::F64(value),
+      Value::String(ref value) => JsonValue::String(value.to_owned()),
+      Value::Array(ref values) => JsonValue::Array(values.iter().map(convert).collect()),
+      Value::Map(ref map)      => to_json(map),
+      _                        => JsonValue::Null
+    }
+  }
+
+  JsonValue::Object(params.iter().map(|(name, value)| (name.to_owned(), convert(value))).collect())
+}
+
+#[cfg(test)]
+mod tests {
+  use schema::*;
+
+  use params::{Map, Value};
+
+  use serde_json;
+  use serde_json::Value as JsonValue;
+
+  fn schema() -> Vec<Param> {
+    vec![
+      Param::single("keywords",   Kind::String),
+      Param::single("company_id", Kind::Integer),
+      Param::single("epoch",      Kind::Date),
+      Param::multiple("ids",      Kind::Integer)
+    ]
+  }
+
+  #[test]
+  fn test_validate() {
+    // valid params
+    {
+      let mut map = Map::new();
+      map.assign("keywords", Value::String("rust".into())).unwrap();
+      map.assign("company_id", Value::String("4".into())).unwrap();
+      map.assign("epoch", Value::String("2016-03-04T12:24:00+01:00".into())).unwrap();
+      map.assign("ids[]", Value::String("1".into())).unwrap();
+      map.assign("ids[]", Value::String("2".into())).unwrap();
+      assert!(validate(&map, &schema()).is_empty());
+    }
+
+    // unknown and malformed params
+    {
+      let mut map = Map::new();
+      map.assign("keyword", Value::String("rust".into())).unwrap();
+      map.assign("company_id", Value::String("madukapls".into())).unwrap();
+      map.assign("epoch", Value::String("yesterday".into())).unwrap();
+      map.assign("ids", Value::String("1".into())).unwrap();
+
+      let fields = validate(&map, &schema()).into_iter()
+                                            .map(|error| error.field)
+                                            .collect::<Vec<String>>();
+      assert_eq!(fields, vec!["company_id", "epoch", "ids", "keyword"]);
+    }
+
+    // an array given for a single param
+    {
+      let mut map = Map::new();
+      map.assign("company_id[]", Value::String("4".into())).unwrap();
+      assert_eq!(validate(&map, &schema()),
+                 vec![FieldError::new("company_id", "`company_id` expects a single value.".to_owned())]);
+    }
+  }
+
+  #[test]
+  fn test_malformed() {
+    // unknown params, single values and empty values are not problems
+    {
+      let mut map = Map::new();
+      map.assign("keyword", Value::String("rust".into())).unwrap();
+      map.assign("company_id", Value::String("".into())).unwrap();
+      map.assign("ids", Value::String("1".into())).unwrap();
+      assert!(malformed(&map, &schema()).is_empty());
+    }
+
+    // values that would be dropped
+    {
+      let mut map = Map::new();
+      map.assign("epoch", Value::String("yesterday".into())).unwrap();
+      map.assign("ids[]", Value::String("1".into())).unwrap();
+      map.assign("ids[]", Value::String("two".into())).unwrap();
+      assert_eq!(malformed(&map, &schema()),
+                 vec![FieldError::new("epoch", "`epoch` expects a RFC 3339 date or `now`.".to_owned()),
+                      FieldError::new("ids", "`ids` expects an integer.".to_owned())]);
+    }
+
+    // the ids are never negative, but can exceed 2^31
+    {
+      let mut map = Map::new();
+      map.assign("company_id", Value::String("-4".into())).unwrap();
+      map.assign("ids[]", Value::String("4294967296".into())).unwrap();
+      assert_eq!(malformed(&map, &schema()),
+                 vec![FieldError::new("company_id", "`company_id` expects an integer.".to_owned())]);
+    }
+  }
+
+  #[test]
+  fn test_from_json() {
+    let object: JsonValue = serde_json::from_str("{\"keywords\": \"rust\", \"company_id\": 4, \"ids\": [1, 2], \"epoch\": null}").unwrap();
+    let map = from_json(&object).unwrap();
+
+    assert_eq!(map.find(&["keywords"]),   Some(&Value::String("rust".into())));
+    assert_eq!(map.find(&["company_id"]), Some(&Value::String("4".into())));
+    assert_eq!(map.find(&["ids"]),        Some(&Value::Array(vec![Value::String("1".into()), Value::String("2".into())])));
+    assert_eq!(map.find(&["epoch"]),      None);
+    assert!(validate(&map, &schema()).is_empty());
+
+    assert!(from_json(&serde_json::from_str("[1]").unwrap()).is_err());
+    assert!(from_json(&serde_json::from_str("{\"ids\": [{}]}").unwrap()).is_err());
+  }
+
+  #[test]
+  fn test_to_json() {
+    let object: JsonValue = serde_json::from_str("{\"keywords\": \"rust\", \"ids\": [1, 2]}").unwrap();
+
+    assert_eq!(serde_json::to_string(&to_json(&from_json(&object).unwrap())).unwrap(),
+               "{\"ids\":[\"1\",\"2\"],\"keywords\":\"rust\"}");
+  }
+}
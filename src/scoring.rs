@@ -0,0 +1,119 @@
+//! The `computed_score` of the talents, derived by a `Scorer` when they are
+//! indexed, so that the ranking can evolve (by changing the scorer and
+//! reindexing) without changing the query of every search. The searches
+//! rank by it once the `computed_score` feature is enabled.
+//!
+//! `WeightedScorer`, configured by `[scoring]`, is installed by
+//! `settings::apply`; the embedders can replace it with `install`.
+
+use chrono::{DateTime, UTC};
+
+use config::Scoring;
+use resources::Talent;
+use settings::Live;
+
+lazy_static! {
+  /// The scorer of the talents being indexed.
+  static ref SCORER: Live<Box<Scorer>> = Live::new(Box::new(WeightedScorer::new(&Scoring::default())));
+}
+
+pub trait Scorer: Send + Sync {
+  /// Return the score of `talent`, indexed at `now`.
+  fn score(&self, talent: &Talent, now: &DateTime<UTC>) -> f64;
+}
+
+/// Score the talents being indexed with `scorer` from now on.
+pub fn install(scorer: Box<Scorer>) {
+  SCORER.store(scorer);
+}
+
+/// Return the score of `talent` by the installed scorer, never negative
+/// so that the searches can boost by its logarithm.
+pub fn score(talent: &Talent) -> f64 {
+  SCORER.load().score(talent, &UTC::now()).max(0.0)
+}
+
+/// The sum of the weight, of the completeness and of the recency
+/// of the talent, each multiplied by its factor in `[scoring]`.
+#[derive(Debug, Clone)]
+pub struct WeightedScorer {
+  scoring: Scoring
+}
+
+impl WeightedScorer {
+  pub fn new(scoring: &Scoring) -> WeightedScorer {
+    WeightedScorer { scoring: scoring.to_owned() }
+  }
+}
+
+impl Scorer for WeightedScorer {
+  fn score(&self, talent: &Talent, now: &DateTime<UTC>) -> f64 {
+    self.scoring.weight       * talent.weight as f64 +
+    self.scoring.completeness * completeness(talent) +
+    self.scoring.recency      * recency(talent, now, self.scoring.recency_days)
+  }
+}
+
+/// Return the share of the optional fields of the profile filled in, from 0 to 1.
+pub fn completeness(talent: &Talent) -> f64 {
+  let filled = [!talent.summary.is_empty(),
+                !talent.headline.is_empty(),
+                !talent.avatar_url.is_empty(),
+                !talent.latest_position.is_empty(),
+                !talent.skills.is_empty(),
+                !talent.work_experiences.is_empty(),
+                !talent.salary_expectations.is_empty(),
+                !talent.languages.is_empty(),
+                !talent.desired_work_roles_experience.is_empty()];
+
+  filled.iter().filter(|&&filled| filled).count() as f64 / filled.len() as f64
+}
+
+/// Return 1 for the talents added to a batch at `now`, decreasing linearly
+/// to 0 for the ones added `days` days before (or with an invalid date).
+pub fn recency(talent: &Talent, now: &DateTime<UTC>, days: u64) -> f64 {
+  let added_at = match DateTime::parse_from_rfc3339(&talent.added_to_batch_at) {
+    Ok(added_at) => added_at.with_timezone(&UTC),
+    Err(_)       => return 0.0
+  };
+
+  let age = (*now - added_at).num_seconds().max(0) as f64 / 86400.0;
+  (1.0 - age / days as f64).max(0.0)
+}
+
+#[cfg(test)]
+mod tests {
+  use scoring::{Scorer, WeightedScorer, completeness, recency};
+  use config::Scoring;
+  use resources::Talent;
+  use test_support::talent;
+
+  use chrono::{DateTime, UTC};
+
+  fn now() -> DateTime<UTC> {
+    DateTime::parse_from_rfc3339("2006-01-31T12:00:00+00:00").unwrap().with_timezone(&UTC)
+  }
+
+  #[test]
+  fn test_completeness() {
+    assert_eq!(completeness(&Talent { languages: vec![], ..talent(1) }), 0.0);
+    assert_eq!(completeness(&talent(1)), 1.0 / 9.0);
+    assert_eq!(completeness(&Talent { summary: "Rustacean".to_owned(), skills: vec!["Rust".to_owned()], ..talent(1) }), 3.0 / 9.0);
+  }
+
+  #[test]
+  fn test_recency() {
+    assert_eq!(recency(&Talent { added_to_batch_at: "2006-01-31T12:00:00+00:00".to_owned(), ..talent(1) }, &now(), 60), 1.0);
+    assert_eq!(recency(&talent(1), &now(), 60), 0.5); // added on 2006-01-01
+    assert_eq!(recency(&talent(1), &now(), 15), 0.0);
+    assert_eq!(recency(&Talent { added_to_batch_at: "".to_owned(), ..talent(1) }, &now(), 60), 0.0);
+  }
+
+  #[test]
+  fn test_weighted_scorer() {
+    let scoring = Scoring { weight: 2.0, completeness: 9.0, recency: 4.0, recency_days: 60, boost: 1.0 };
+    let scorer  = WeightedScorer::new(&scoring);
+
+    assert_eq!(scorer.score(&Talent { weight: 3, ..talent(1) }, &now()), 2.0 * 3.0 + 9.0 / 9.0 + 4.0 * 0.5);
+  }
+}
@@ -0,0 +1,104 @@
+//! The documents of an index, fetched lazily one page of the scroll
+//! at a time so that they are never buffered all together in memory.
+//!
+//! The scroll is sent through `es` rather than `rs-es`, whose `scan` search
+//! type and numeric `hits.total` are refused by OpenSearch.
+
+use serde_json;
+use serde_json::Value as JsonValue;
+
+use rs_es::error::EsError;
+
+use resource::Resource;
+use error::Error;
+use es;
+
+use std::marker::PhantomData;
+use std::vec;
+
+/// How many documents are fetched for each page of the scroll.
+pub const PAGE_SIZE: u64 = 500;
+
+/// How long the scroll is kept open between its pages.
+const KEEP_ALIVE: &'static str = "1m";
+
+/// Iterate over every document of an index, closing the scroll
+/// once they have been consumed or the iterator is dropped.
+pub struct Scroll<R: Resource> {
+  url:      String,
+  last:     Option<JsonValue>,
+  page:     vec::IntoIter<JsonValue>,
+  resource: PhantomData<R>
+}
+
+impl<R: Resource> Scroll<R> {
+  /// Open a scroll over the documents of `index` on the cluster listening on `url`.
+  pub fn new(url: &str, index: &str) -> Result<Scroll<R>, Error> {
+    let body = serde_json::to_value(&btreemap! {
+      "size" => serde_json::to_value(&PAGE_SIZE),
+      "sort" => serde_json::to_value(&vec!["_doc"])
+    });
+
+    let first = try!(es::open_scroll(url, index, &body, KEEP_ALIVE));
+
+    Ok(Scroll {
+      url:      url.to_owned(),
+      page:     es::hits(&first).into_iter(),
+      last:     Some(first),
+      resource: PhantomData
+    })
+  }
+
+  /// Close the scroll, if still open, returning the error it failed with.
+  fn close(&mut self) -> Option<Error> {
+    match self.last.take() {
+      Some(last) => es::clear_scroll(&self.url, &last).err().map(Error::from),
+      None       => None
+    }
+  }
+}
+
+impl<R: Resource> Iterator for Scroll<R> {
+  type Item = Result<R, Error>;
+
+  fn next(&mut self) -> Option<Result<R, Error>> {
+    loop {
+      if let Some(hit) = self.page.next() {
+        match hit.find("_source") {
+          Some(source) => return Some(serde_json::from_value(source.to_owned()).map_err(|err| Error::from(EsError::from(err)))),
+          None         => continue
+        }
+      }
+
+      let page = match self.last {
+        Some(ref last) => es::scroll(&self.url, last, KEEP_ALIVE),
+        None           => return None
+      };
+
+      match page {
+        Ok(page) => {
+          let hits = es::hits(&page);
+          self.last = Some(page);
+
+          if hits.is_empty() {
+            return self.close().map(Err);
+          }
+
+          self.page = hits.into_iter();
+        },
+        Err(err) => {
+          self.close();
+          return Some(Err(Error::from(err)));
+        }
+      }
+    }
+  }
+}
+
+impl<R: Resource> Drop for Scroll<R> {
+  fn drop(&mut self) {
+    if let Some(err) = self.close() {
+      error!("Cannot close the scroll: {}", err);
+    }
+  }
+}
@@ -0,0 +1,142 @@
+//! The credentials given as references to where they are stored, instead
+//! of their values, and resolved on startup:
+//!
+//! - `file:/run/secrets/es_password` is replaced by the content of the file
+//!   (without the trailing newline)
+//! - `vault:secret/searchspot#es_password` is replaced by the `es_password` key
+//!   of the `secret/searchspot` secret, read from the Vault server at `VAULT_ADDR`
+//!   using `VAULT_TOKEN`
+//!
+//! Any other value is kept as it is.
+
+use serde_json;
+use serde_json::Value as JsonValue;
+
+use hyper::Client as HttpClient;
+use hyper::Url;
+use hyper::net::HttpsConnector;
+use hyper::header::Headers;
+use hyper_openssl::OpensslClient;
+
+use std::env;
+use std::fs::File;
+use std::io::prelude::*;
+
+const FILE_PREFIX:  &'static str = "file:";
+const VAULT_PREFIX: &'static str = "vault:";
+
+/// The header carrying the token of the requests sent to Vault.
+const VAULT_TOKEN_HEADER: &'static str = "X-Vault-Token";
+
+/// Return the secret referenced by `value`, or `value` itself if it is not a reference.
+pub fn resolve(value: &str) -> Result<String, String> {
+  if value.starts_with(FILE_PREFIX) {
+    read_file(&value[FILE_PREFIX.len()..])
+  }
+  else if value.starts_with(VAULT_PREFIX) {
+    let (path, key) = try!(vault_reference(&value[VAULT_PREFIX.len()..]));
+    read_vault(path, key)
+  }
+  else {
+    Ok(value.to_owned())
+  }
+}
+
+/// Same as `resolve`, for the optional values.
+pub fn resolve_option(value: &Option<String>) -> Result<Option<String>, String> {
+  match *value {
+    Some(ref value) => resolve(value).map(Some),
+    None            => Ok(None)
+  }
+}
+
+fn read_file(path: &str) -> Result<String, String> {
+  let mut file = try!(File::open(path).map_err(|err| format!("cannot read {}: {}", path, err)));
+
+  let mut secret = String::new();
+  try!(file.read_to_string(&mut secret).map_err(|err| format!("cannot read {}: {}", path, err)));
+
+  Ok(secret.trim_right_matches(|c| c == '\n' || c == '\r').to_owned())
+}
+
+/// Split `secret/searchspot#es_password` into its path and key.
+fn vault_reference(reference: &str) -> Result<(&str, &str), String> {
+  let mut parts = reference.splitn(2, '#');
+
+  match (parts.next(), parts.next()) {
+    (Some(path), Some(key)) if !path.is_empty() && !key.is_empty() => Ok((path, key)),
+    _ => Err(format!("expected vault:<path>#<key>, got vault:{}", reference))
+  }
+}
+
+fn read_vault(path: &str, key: &str) -> Result<String, String> {
+  let addr  = try!(env::var("VAULT_ADDR").map_err(|_| "VAULT_ADDR is not set".to_owned()));
+  let token = try!(env::var("VAULT_TOKEN").map_err(|_| "VAULT_TOKEN is not set".to_owned()));
+
+  let base = try!(Url::parse(&addr).map_err(|e| e.to_string()));
+  let url  = try!(base.join(&format!("/v1/{}", path)).map_err(|e| e.to_string()));
+
+  let mut headers = Headers::new();
+  headers.set_raw(VAULT_TOKEN_HEADER, vec![token.into_bytes()]);
+
+  let ssl    = try!(OpensslClient::new().map_err(|e| e.to_string()));
+  let client = HttpClient::with_connector(HttpsConnector::new(ssl));
+
+  let mut response = try!(client.get(url).headers(headers).send().map_err(|e| e.to_string()));
+  if !response.status.is_success() {
+    return Err(format!("Vault answered {} for {}", response.status, path));
+  }
+
+  let mut body = String::new();
+  try!(response.read_to_string(&mut body).map_err(|e| e.to_string()));
+
+  let json: JsonValue = try!(serde_json::from_str(&body).map_err(|e| e.to_string()));
+  vault_key(&json, key).ok_or(format!("{} has no key {}", path, key))
+}
+
+/// Return `key` from the response of Vault, supporting both the versions
+/// of the key/value engine (the second one nests the keys inside `data.data`).
+fn vault_key(json: &JsonValue, key: &str) -> Option<String> {
+  let data = json.find("data");
+
+  data.and_then(|data| data.find(key))
+      .or(data.and_then(|data| data.find("data")).and_then(|data| data.find(key)))
+      .and_then(|value| value.as_str())
+      .map(|value| value.to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+  use secrets::*;
+  use secrets::{vault_reference, vault_key};
+
+  use serde_json;
+
+  use std::env;
+  use std::fs::File;
+  use std::io::prelude::*;
+
+  #[test]
+  fn test_resolve() {
+    assert_eq!(resolve("yxxz7oap7rsf67zl"), Ok("yxxz7oap7rsf67zl".to_owned()));
+
+    let path = env::temp_dir().join("searchspot_test_secret");
+    File::create(&path).unwrap().write_all(b"e4jlb5tmiqv2l7px\n").unwrap();
+    assert_eq!(resolve(&format!("file:{}", path.display())), Ok("e4jlb5tmiqv2l7px".to_owned()));
+
+    assert!(resolve("file:/nonexistent/searchspot").is_err());
+    assert!(resolve("vault:secret/searchspot").is_err());
+  }
+
+  #[test]
+  fn test_vault() {
+    assert_eq!(vault_reference("secret/searchspot#es_password"), Ok(("secret/searchspot", "es_password")));
+    assert!(vault_reference("secret/searchspot#").is_err());
+
+    let v1: serde_json::Value = serde_json::from_str(r#"{"data": {"es_password": "a"}}"#).unwrap();
+    let v2: serde_json::Value = serde_json::from_str(r#"{"data": {"data": {"es_password": "b"}}}"#).unwrap();
+    assert_eq!(vault_key(&v1, "es_password"), Some("a".to_owned()));
+    assert_eq!(vault_key(&v2, "es_password"), Some("b".to_owned()));
+    assert_eq!(vault_key(&v2, "password"), None);
+  }
+}
@@ -1,17 +1,21 @@
 #![allow(non_upper_case_globals)]
 use serde_json;
+use serde_json::Value as JsonValue;
+use serde::ser::Serialize;
 
-use rs_es::Client;
+use chrono::UTC;
 
 use iron::prelude::*;
-use iron::{status, Handler, Headers};
+use iron::{status, Handler, Headers, Protocol};
+use iron::method::Method;
 use iron::mime::Mime;
 use iron::typemap::Key;
 use iron::headers;
-use iron::middleware::AfterMiddleware;
+use iron::middleware::{BeforeMiddleware, AfterMiddleware};
+use iron::response::{WriteBody, ResponseBody};
 use unicase::UniCase;
 
-use persistent::Write;
+use persistent::Read;
 
 use http_logger::Logger as HTTPLogger;
 
@@ -21,20 +25,118 @@ use params::*;
 
 use oath::*;
 
-use config::Auth as AuthConfig;
+use config::HMAC as HMACConfig;
+use config::CORS as CORSConfig;
+use config::Compression as CompressionConfig;
+use config::ResourceSettings;
 use config::Config;
+use config::Duplicates;
+use config::Variant;
 
-use resource::Resource;
+use resource::{Resource, Paginated, Pagination, TimedOut, SearchOptions, QueryLimits};
+use logger;
 use logger::start_logging;
-
-use std::collections::HashMap;
-use std::io::Read;
+use health::{Health, Readiness, check_mapping};
+use metrics::METRICS;
+use statsd::StatsD;
+use error::{Error, ErrorCode, ErrorResponse};
+use signature::{Verifier, UpstreamVerifier, SIGNATURE_HEADER, TIMESTAMP_HEADER, UPSTREAM_SIGNATURE_HEADER};
+use jwt;
+use version::ApiVersion;
+use stream::{EventStream, SUBSCRIPTIONS};
+use export::CsvExport;
+use compression::Encoding;
+use format::Format;
+use shutdown::SHUTDOWN;
+use settings;
+use webhooks::{WEBHOOKS, Event};
+use request_id;
+use admin;
+use schema;
+use schema::{Param, Kind, FieldError};
+use es;
+use request_id::{RequestId, REQUEST_ID_HEADER};
+use tracing;
+use audit;
+use audit::{AUDIT, SearchEntry, OperationEntry};
+use analytics;
+use analytics::{ANALYTICS, ZeroResult, Feedback};
+use saved_searches;
+use alerts;
+use experiments;
+use ltr::Rescoring;
+use federated;
+use spelling;
+use federated::{Registry, ResourceSearch, Section, FederatedResults};
+use redaction::Redaction;
+use erasure;
+use erasure::{Step, ErasureReport};
+use saved_searches::{SavedSearch, NewSavedSearch};
+use batches;
+use batches::{Batch, NewBatch, Assignment, BatchReport};
+use usage::{USAGE, RETENTION_DAYS};
+use quotas;
+use backup;
+use systemd;
+use loopback;
+use monitor;
+use client_cert;
+use tracing::{Span, TRACER, TRACEPARENT_HEADER};
+use jwt::{Claims, Scope};
+
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error as StdError;
+use std::fmt;
+use std::io::Read as IoRead;
 use std::marker::PhantomData;
+use std::mem;
+use std::process;
+use std::str;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+
+use rs_es::operations::bulk::ActionResult;
+use rs_es::error::EsError;
+
+use unix_socket::UnixSocketListener;
+use cluster;
+use cluster::Cluster;
+use backend::HttpBackend;
+use routing;
+use retry::retry;
+use cache;
+use cache::CACHE;
+use redis_store::RedisStore;
+use bulk;
+use clock::SystemClock;
+use coalesce::Coalescer;
+use queue::{QUEUE, Reservation};
+use consumer;
+use consumer::{Worker, Command};
+
+use chan_signal;
+use chan_signal::Signal;
+use chan;
+
+/// How often (in seconds) the reachability of the ElasticSearch nodes is checked.
+const CLUSTER_CHECK_INTERVAL: u64 = 5;
+
+/// How often (in seconds) the main loop wakes up when the watchdog of systemd is disabled.
+const WATCHDOG_IDLE_INTERVAL: u64 = 3600;
 
 #[derive(Copy, Clone)]
-pub struct SharedClient;
+pub struct SharedCluster;
+
+impl Key for SharedCluster { type Value = Cluster; }
 
-impl Key for SharedClient { type Value = Client; }
+macro_rules! error_response {
+  ($code:expr, $message:expr) => (error_response!($code, $message, None));
+  ($code:expr, $message:expr, $details:expr) => ({
+    return Ok(ErrorResponse::new($code, $message, $details).into_response())
+  })
+}
 
 macro_rules! try_or_422 {
   ($expr:expr) => (match $expr {
@@ -42,37 +144,99 @@ macro_rules! try_or_422 {
     Err(err) => {
       let error_message = err.to_string();
       error!("{}", error_message);
+      error_response!(ErrorCode::ValidationError, error_message);
+    }
+  })
+}
+
+/// Return the response matching the `Error` (or the `EsError`, hence `502`)
+/// returned by `$expr`, if any.
+macro_rules! try_or_respond {
+  ($expr:expr) => (match $expr {
+    Ok(val)  => val,
+    Err(err) => {
+      let err = Error::from(err);
 
-      let mut error = HashMap::new();
-      error.insert("error", error_message);
+      // only the unexpected errors are reported to the monitor
+      if err.is_unexpected() {
+        error!("{}", err);
+      }
+      else {
+        warn!("{}", err);
+      }
 
-      let content_type = "application/json".parse::<Mime>().unwrap();
-      return Ok(Response::with(
-        (content_type, status::UnprocessableEntity, serde_json::to_string(&error).unwrap())
-      ))
+      if err.is_es() {
+        METRICS.increment("searchspot_es_errors_total", &[]);
+      }
+      return Ok(err.into_response());
+    }
+  })
+}
+
+/// Refuse the searches asking for more than `search.max_page_size` results per page.
+macro_rules! try_page_size {
+  ($params:expr) => ({
+    if let Some((per_page, max_page_size)) = oversized_page($params) {
+      error_response!(ErrorCode::ValidationError,
+                      format!("`per_page` cannot exceed {}, got {}.", max_page_size, per_page),
+                      Some(serde_json::to_value(&btreemap! { "max_page_size" => max_page_size })));
+    }
+  })
+}
+
+/// Refuse the malformed values of the known params, and the unknown params in strict mode.
+macro_rules! try_params {
+  ($params:expr, $schema:expr) => ({
+    let errors = param_errors($params, $schema);
+
+    if !errors.is_empty() {
+      error_response!(ErrorCode::InvalidParams, "The query string contains unknown or malformed params.",
+                      Some(serde_json::to_value(&errors)));
     }
   })
 }
 
 macro_rules! unauthorized {
-  () => ({
-    return Ok(Response::with(
-      (status::Unauthorized)
-    ))
+  () => (error_response!(ErrorCode::Unauthorized, "A valid authorization token is required."));
+  ($endpoint:expr, $config:expr, $headers:expr) => ({
+    if $endpoint.lacks_scope($config, $headers) {
+      error_response!(ErrorCode::Forbidden, "The authorization token lacks the scope of the endpoint.");
+    }
+    unauthorized!()
   })
 }
 
+macro_rules! not_found {
+  ($message:expr) => (error_response!(ErrorCode::NotFound, $message))
+}
+
 macro_rules! authorization {
-  ($trait_name:ident, $mode:ident) => {
+  ($trait_name:ident, $mode:ident, $scope:expr, $client_cert:expr) => {
     trait $trait_name {
-      fn is_authorized(&self, auth_config: &AuthConfig, headers: &Headers, token_lifetime: u64) -> bool {
-        if auth_config.enabled == false {
+      fn is_authorized(&self, config: &Config, headers: &Headers, token_lifetime: u64) -> bool {
+        let ref auth_config = config.auth;
+        let ref jwt_config  = config.jwt;
+
+        // the certificate of the client is required along with the token
+        if $client_cert && !client_cert::is_allowed(&config.tls, client_cert::peer().as_ref().map(|peer| &peer[..])) {
+          return false;
+        }
+
+        if auth_config.enabled == false && jwt_config.enabled == false {
           return true;
         }
 
         match headers.get_raw("Authorization") {
           Some(header) => match String::from_utf8(header[0].to_owned()) {
-            Ok(header) => {
+            Ok(ref header) if jwt_config.enabled && header.starts_with("Bearer ") => {
+              let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as i64;
+
+              match jwt::decode(&header["Bearer ".len()..], &*jwt_config.secret, now) {
+                Ok(claims) => claims.has_scope($scope),
+                Err(_)     => false
+              }
+            },
+            Ok(ref header) if auth_config.enabled => {
               match header.split("token ").collect::<Vec<&str>>().last() {
                 Some(token) => {
                   match token.parse::<u64>() {
@@ -83,22 +247,239 @@ macro_rules! authorization {
                 None => false
               }
             },
-            Err(_) => false
+            _ => false
           },
           None => false
         }
       }
+
+      /// Return whether the request is authorized by a valid JWT lacking the scope of
+      /// the endpoint, hence refused with `403` rather than `401`.
+      fn lacks_scope(&self, config: &Config, headers: &Headers) -> bool {
+        claims(config, headers).map(|claims| !claims.has_scope($scope)).unwrap_or(false)
+      }
+    }
+  }
+}
+
+/// Return the body of the request, which may have already been
+/// read by `SignatureMiddleware`.
+fn read_body(req: &mut Request) -> Vec<u8> {
+  if let Some(body) = req.extensions.remove::<RequestBody>() {
+    return body;
+  }
+
+  let mut payload = vec![];
+  req.body.read_to_end(&mut payload).unwrap();
+  payload
+}
+
+/// Return the options of the searches on `resource`, according to the current settings.
+/// The boosts of the resource replace the ones of `[search]` field by field.
+pub fn search_options(resource: &ResourceSettings) -> SearchOptions {
+  let search = settings::SEARCH.load();
+
+  let mut boosts = search.boosts.to_owned();
+  boosts.extend(resource.boosts.to_owned());
+
+  SearchOptions {
+    timeout:      search.timeout(),
+    min_score:    search.min_score,
+    score_threshold: search.score_threshold(),
+    max_per_page: resource.max_per_page,
+    boosts:       boosts,
+    tie_breaker:  search.tie_breaker,
+    minimum_should_match: search.minimum_should_match.to_owned(),
+    decay:        search.decay.to_owned(),
+    features:     (*settings::FEATURES.load()).to_owned(),
+    scoring_boost: settings::SCORING.load().boost,
+    rescoring:    resource.ltr_model.as_ref().map(|model| Rescoring::new(model, &settings::LTR.load())),
+    clock:        Arc::new(SystemClock),
+    limits:       QueryLimits::new(&search)
+  }
+}
+
+/// Attach the `operation` and the `index` to the lines logged while handling the request.
+fn log_fields(operation: &str, index: &str) {
+  logger::set_field("operation", operation);
+  logger::set_field("index", index);
+}
+
+/// Return the `per_page` param, if given as a number.
+fn page_size(params: &Map) -> Option<u64> {
+  params.get("per_page").and_then(|per_page| u64::from_value(per_page))
+}
+
+/// Return the `per_page` param along with `search.max_page_size`, if it exceeds it.
+pub fn oversized_page(params: &Map) -> Option<(u64, u64)> {
+  let max_page_size = settings::SEARCH.load().max_page_size;
+  page_size(params).and_then(|per_page| if per_page > max_page_size { Some((per_page, max_page_size)) } else { None })
+}
+
+/// Return the errors of the malformed values of the known params, and of the unknown params in strict mode.
+pub fn param_errors(params: &Map, schema: &[Param]) -> Vec<FieldError> {
+  if settings::FEATURES.load().strict_params {
+    schema::validate(params, schema)
+  }
+  else {
+    schema::malformed(params, schema)
+  }
+}
+
+/// A search of `R` once its checked params have gone through `plan_search`.
+pub struct SearchPlan<'a> {
+  pub options:   SearchOptions,
+  pub variant:   Option<(&'a str, &'a Variant)>, // the variant of the experiment applied to `options`
+  pub corrected: Option<String>,                 // the keywords searched instead of the given ones
+  pub params:    Option<Map>                     // the params with the `corrected` keywords
+}
+
+impl<'a> SearchPlan<'a> {
+  /// Return the params to search, the corrected ones if any.
+  pub fn params<'b>(&'b self, params: &'b Map) -> &'b Map {
+    self.params.as_ref().unwrap_or(params)
+  }
+}
+
+/// Return the options of the search of `R` for `params` in `index`, with the variant of the
+/// experiment that `bucket_id` falls in, along with its keywords spell checked, as `GET /talents`
+/// searches them. The keywords that cannot be spell checked are searched as given.
+pub fn plan_search<'a, R: Resource>(config: &'a Config, url: &str, index: &str, params: &Map,
+                                    bucket_id: Option<&str>) -> SearchPlan<'a> {
+  let mut options = search_options(&config.resource(R::name()));
+
+  // the variant is part of the options, hence of the key of the cache
+  let ref experiment = config.experiment;
+  let variant = bucket_id.and_then(|id| experiments::variant(experiment, id));
+  if let Some((name, variant)) = variant {
+    logger::set_field("variant", name);
+    experiments::apply(variant, &mut options);
+  }
+
+  // the corrected keywords are searched instead, hence cached and audited as such
+  let corrected = if options.features.spell_checking && spelling::requested(params) {
+    let confidence = settings::SEARCH.load().spelling_confidence;
+
+    match spelling::check(url, index, params, confidence) {
+      Ok(corrected) => corrected,
+      Err(err)      => {
+        warn!("The keywords cannot be spell checked: {}", err);
+        None
+      }
+    }
+  }
+  else {
+    None
+  };
+
+  let corrected_params = corrected.as_ref().map(|keywords| {
+    logger::set_field("corrected_keywords", keywords);
+    METRICS.increment("searchspot_spelling_corrections_total", &[]);
+    spelling::corrected(params, keywords)
+  });
+
+  SearchPlan {
+    options:   options,
+    variant:   variant,
+    corrected: corrected,
+    params:    corrected_params
+  }
+}
+
+/// Return the value of given header, if present and valid UTF-8.
+fn raw_header(req: &Request, name: &str) -> Option<String> {
+  req.headers.get_raw(name)
+             .and_then(|h| String::from_utf8(h[0].to_owned()).ok())
+}
+
+/// Return the format in which the body of the request is encoded.
+fn request_format(req: &Request) -> Format {
+  Format::from_content_type(raw_header(req, "Content-Type").as_ref().map(|h| &h[..]))
+}
+
+/// Return the format in which the response must be encoded.
+fn response_format(req: &Request) -> Format {
+  Format::from_accept(raw_header(req, "Accept").as_ref().map(|h| &h[..]))
+}
+
+struct RequestBody;
+
+impl Key for RequestBody { type Value = Vec<u8>; }
+
+#[derive(Debug)]
+struct SignatureError(String);
+
+impl fmt::Display for SignatureError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{}", self.0)
+  }
+}
+
+impl StdError for SignatureError {
+  fn description(&self) -> &str {
+    &*self.0
+  }
+}
+
+/// Reject the requests that are not signed according to `signature::Verifier`.
+struct SignatureMiddleware {
+  verifier: Verifier
+}
+
+impl BeforeMiddleware for SignatureMiddleware {
+  fn before(&self, req: &mut Request) -> IronResult<()> {
+    let signature = req.headers.get_raw(SIGNATURE_HEADER)
+                               .and_then(|h| String::from_utf8(h[0].to_owned()).ok());
+    let timestamp = req.headers.get_raw(TIMESTAMP_HEADER)
+                               .and_then(|h| String::from_utf8(h[0].to_owned()).ok())
+                               .and_then(|t| t.parse::<i64>().ok());
+
+    let mut body = vec![];
+    req.body.read_to_end(&mut body).unwrap();
+
+    let now    = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as i64;
+    let method = req.method.to_string();
+    let path   = format!("/{}", req.url.path().join("/"));
+
+    let result = match (signature, timestamp) {
+      (Some(signature), Some(timestamp)) => self.verifier.verify(&signature, timestamp, now, &method, &path, &body),
+      _ => Err(format!("{} and {} headers are required.", SIGNATURE_HEADER, TIMESTAMP_HEADER))
+    };
+
+    match result {
+      Ok(_) => {
+        req.extensions.insert::<RequestBody>(body);
+        Ok(())
+      },
+      Err(error_message) => {
+        let response = ErrorResponse::new(ErrorCode::Unauthorized, &*error_message, None);
+        Err(IronError::new(SignatureError(error_message), response.into_response()))
+      }
     }
   }
 }
 
-authorization!(ReadableEndpoint, read);
-authorization!(WritableEndpoint, write);
+/// Wrap `handler` so that its requests must be signed, if enabled.
+fn signed<H: Handler>(handler: H, hmac: &HMACConfig, verifier: &Verifier) -> Chain {
+  let mut chain = Chain::new(handler);
+
+  if hmac.enabled {
+    chain.link_before(SignatureMiddleware { verifier: verifier.clone() });
+  }
+
+  chain
+}
+
+authorization!(ReadableEndpoint, read,  Scope::Search, false);
+authorization!(WritableEndpoint, write, Scope::Write,  true);
+authorization!(AdminEndpoint,    write, Scope::Admin,  true);
 
 pub struct Server<R: Resource> {
-  config:   Config,
-  endpoint: String,
-  resource: PhantomData<R>
+  config:      Config,
+  config_path: Option<String>,
+  endpoint:    String,
+  coalescer:   Arc<Coalescer<R>>,
+  registry:    Registry // searched by `GET /search`
 }
 
 pub struct SearchableHandler<R> {
@@ -119,202 +500,3155 @@ impl<R: Resource> ReadableEndpoint for SearchableHandler<R> {}
 
 impl<R: Resource> Handler for SearchableHandler<R> {
   fn handle(&self, req: &mut Request) -> IronResult<Response> {
+    log_fields("search", &*self.config.es.index);
+
     let ref lifetimes = self.config.tokens.lifetime;
-    if !self.is_authorized(&self.config.auth, &req.headers, lifetimes.read) {
-      unauthorized!();
+    if !self.is_authorized(&self.config, &req.headers, lifetimes.read) {
+      unauthorized!(self, &self.config, &req.headers);
     }
 
-    let client = req.get::<Write<SharedClient>>().unwrap();
-    let params = try_or_422!(req.get_ref::<Params>());
+    // taken before `params`, which keeps `req` borrowed
+    let path   = format!("/{}", req.url.path().join("/"));
+    let query  = req.url.query().map(|query| query.to_owned());
+    let format = response_format(req);
+    let caller = caller(&self.config, &req.headers);
+    let claims      = claims(&self.config, &req.headers);
+    let redaction   = redaction(&self.config, &req.headers);
+    let bucket_id   = raw_header(req, experiments::BUCKET_HEADER);
+    let received_at = Instant::now();
+
+    let cluster = req.get::<Read<SharedCluster>>().unwrap();
+    let params = {
+      let _span = Span::start("search.parse_params");
+      let params = try_or_422!(req.get_ref::<Params>());
+      try_params!(params, &R::search_params());
+      try_page_size!(params);
+      params
+    };
+
+    let company_id = company_id(claims.as_ref(), params);
+    if let Err(res) = check_quota(&self.config, &company_id) {
+      return Ok(res);
+    }
+    record_usage(&company_id);
+
+    let plan    = plan_search::<R>(&self.config, &cluster.url(), &*self.config.es.index, params,
+                                   bucket_id.as_ref().map(|id| &id[..]));
+    let params  = plan.params(params);
+    let options = &plan.options;
+    let variant = plan.variant;
+    let ref experiment = self.config.experiment;
+
+    let ref index = self.config.es.index;
+    let key = cache::key(R::name(), index, CACHE.generation(index), params, &format!("{:?}", options));
+
+    let (response, cached) = match CACHE.get::<R::Results>(&key) {
+      Some(response) => {
+        METRICS.increment("searchspot_cache_hits_total", &[]);
+        (response, true)
+      },
+      None => {
+        let started_at = Instant::now();
+        let response   = try_or_respond!(METRICS.instrument(R::name(), index, "search", || {
+          if options.features.profiling {
+            profiled_search::<R>(&cluster.url(), index, params, options)
+          }
+          else {
+            // unlike `rs-es`, `es` sends the request ID along (i.e.: to find the search in the slow logs)
+            preview_search::<R>(&cluster.url(), index, params, options)
+          }
+        }));
+        let elapsed    = started_at.elapsed();
+        METRICS.observe_duration("searchspot_search_duration_seconds", &[], elapsed);
+
+        if let Some((name, _)) = variant {
+          METRICS.observe_duration("searchspot_experiment_search_duration_seconds",
+                                   &[("experiment", &experiment.name[..]), ("variant", name)], elapsed);
+        }
 
-    let response = R::search(&mut client.lock().unwrap(), &*self.config.es.index, params);
+        if let Some(slow_ms) = settings::SEARCH.load().slow_ms() {
+          if millis(elapsed) > slow_ms {
+            log_slow_search(params, millis(elapsed), response.took());
+          }
+        }
 
-    let content_type = "application/json".parse::<Mime>().unwrap();
-    Ok(Response::with(
-      (content_type, status::Ok, try_or_422!(serde_json::to_string(&response)))
-    ))
+        // partial results are never returned
+        if response.timed_out() || options.timeout.map(|timeout| millis(elapsed) > timeout).unwrap_or(false) {
+          error_response!(ErrorCode::Timeout, "The search has not been completed in time.",
+                          Some(serde_json::to_value(&btreemap! { "partial" => false })));
+        }
+
+        CACHE.put(&key, &response);
+        (response, false)
+      }
+    };
+
+    AUDIT.record(SearchEntry::new(&caller, R::name(), index, schema::to_json(params),
+                                  response.pagination().map(|pagination| pagination.total),
+                                  millis(received_at.elapsed()), cached));
+
+    let no_results = response.pagination().map(|pagination| pagination.total == 0).unwrap_or(false);
+    if no_results {
+      ANALYTICS.record(ZeroResult::new(R::name(), index, &schema::to_json(params)));
+    }
+
+    if let Some((name, _)) = variant {
+      let labels = [("experiment", &experiment.name[..]), ("variant", name)];
+      METRICS.increment("searchspot_experiment_searches_total", &labels);
+
+      if no_results {
+        METRICS.increment("searchspot_experiment_zero_results_total", &labels);
+      }
+    }
+
+    let body = {
+      let _span = Span::start("search.serialize");
+      try_or_422!(format.serialize(&redacted(&response, &redaction)))
+    };
+    let mut res = Response::with((format.mime(), status::Ok, body));
+
+    if let Some(pagination) = response.pagination() {
+      res.headers.set_raw("X-Total-Count", vec![pagination.total.to_string().into_bytes()]);
+      res.headers.set_raw("Link", vec![pagination_links(&path, query.as_ref().map(|q| &q[..]), &pagination).into_bytes()]);
+    }
+
+    if let Some((name, _)) = variant {
+      res.headers.set_raw(experiments::VARIANT_HEADER, vec![name.as_bytes().to_vec()]);
+    }
+
+    if let Some(ref keywords) = plan.corrected {
+      res.headers.set_raw(spelling::CORRECTED_HEADER, vec![keywords.as_bytes().to_vec()]);
+    }
+
+    Ok(res)
   }
 }
 
-pub struct IndexableHandler<R> {
-  config:   Config,
-  resource: PhantomData<R>
+/// Run the search sent by `R::search` asking ElasticSearch to profile it,
+/// and log how long building the query, running it on ElasticSearch
+/// (as `took`, and as round trip) and deserializing the results took.
+fn profiled_search<R: Resource>(url: &str, index: &str, params: &Map, options: &SearchOptions) -> Result<R::Results, Error> {
+  let started_at = Instant::now();
+  let search     = try!(R::preview(index, params, options));
+  let built_in   = started_at.elapsed();
+
+  let started_at = Instant::now();
+  let response   = try!(es::search(url, &search, true));
+  let sent_in    = started_at.elapsed();
+
+  let took    = response.find("took").and_then(|took| took.as_u64()).unwrap_or(0);
+  let profile = response.find("profile").cloned().unwrap_or(JsonValue::Null);
+
+  let started_at = Instant::now();
+  let results    = try!(R::results(params, options, response));
+  let parsed_in  = started_at.elapsed();
+
+  info!("Search profiled: built in {}ms, took {}ms on ElasticSearch ({}ms round trip), deserialized in {}ms.",
+        millis(built_in), took, millis(sent_in), millis(parsed_in));
+  debug!("Search profile: {}", profile);
+
+  Ok(results)
 }
 
-impl<R: Resource> IndexableHandler<R> {
-  fn new(config: Config) -> Self {
-    IndexableHandler::<R> {
-      resource: PhantomData,
-      config:   config
-    }
+/// Send the search returned by `R::preview` through `es::search`, which
+/// supports the options that `rs-es` cannot send (i.e.: `ignore_unavailable`)
+/// and the `X-Opaque-Id` header.
+fn preview_search<R: Resource>(url: &str, index: &str, params: &Map, options: &SearchOptions) -> Result<R::Results, Error> {
+  R::search_with(&HttpBackend::new(url), index, params, options)
+}
+
+/// Log the search with given `params` that has taken `elapsed` milliseconds,
+/// `took` of which on ElasticSearch, as a JSON object, i.e.:
+/// `Slow search: {"elapsed_ms":1200,"params":{"keywords":"rust"},"took_ms":1100}`.
+fn log_slow_search(params: &Map, elapsed: u64, took: Option<u64>) {
+  let entry = btreemap! {
+    "elapsed_ms" => serde_json::to_value(&elapsed),
+    "took_ms"    => serde_json::to_value(&took),
+    "params"     => schema::to_json(params)
+  };
+
+  warn!("Slow search: {}", serde_json::to_string(&entry).unwrap());
+}
+
+/// Record the documents of `items` that have been indexed into the audit log,
+/// along with the versions they had `before` (see `audit::versions_before`).
+pub fn audit_indexed<R: Resource>(actor: &str, index: &str, items: &[ActionResult], before: &BTreeMap<String, u64>) {
+  for item in items.iter().filter(|item| item.inner.status < 300) {
+    AUDIT.record_operation(OperationEntry::new(actor, "index", R::name(), index, Some(item.inner.id.to_owned()),
+                                               before.get(&item.inner.id).cloned(), Some(item.inner.version)));
   }
 }
 
-impl<R: Resource> WritableEndpoint for IndexableHandler<R> {}
+/// Return who sent the request, as recorded by the audit log (see `audit::caller`).
+fn caller(config: &Config, headers: &Headers) -> String {
+  let authorization = headers.get_raw("Authorization")
+                             .and_then(|h| String::from_utf8(h[0].to_owned()).ok());
+  let jwt_secret    = if config.jwt.enabled { Some(&*config.jwt.secret) } else { None };
 
-impl<R: Resource> Handler for IndexableHandler<R> {
-  fn handle(&self, req: &mut Request) -> IronResult<Response> {
-    let ref lifetimes = self.config.tokens.lifetime;
-    if !self.is_authorized(&self.config.auth, &req.headers, lifetimes.write) {
-      unauthorized!();
-    }
+  audit::caller(authorization.as_ref().map(|a| &a[..]), jwt_secret)
+}
+
+/// Return the redaction of the personal data sent to the caller, unless it is granted the `pii` scope.
+fn redaction(config: &Config, headers: &Headers) -> Option<Redaction> {
+  let redaction = match Redaction::new(&config.gdpr) {
+    Some(redaction) => redaction,
+    None            => return None
+  };
 
-    let mut payload = String::new();
-    req.body.read_to_string(&mut payload).unwrap();
+  match claims(config, headers) {
+    Some(ref claims) if claims.has_scope(Scope::Pii) => None,
+    _                                                => Some(redaction)
+  }
+}
 
-    let resources: Vec<R> = try_or_422!(serde_json::from_str(&payload));
-    let client = req.get::<Write<SharedClient>>().unwrap();
-    try_or_422!(R::index(&mut client.lock().unwrap(), &*self.config.es.index, resources));
+/// Return `results` as JSON, the personal fields of their documents and of their highlights
+/// redacted by `redaction`, if given. They are cached unredacted, since the callers differ.
+fn redacted<T: Serialize>(results: &T, redaction: &Option<Redaction>) -> JsonValue {
+  let mut results = serde_json::to_value(results);
 
-    Ok(Response::with(status::Created))
+  if let Some(ref redaction) = *redaction {
+    redaction.redact_nested(&mut results);
   }
+
+  results
 }
 
-pub struct DeletableHandler<R> {
-  config:   Config,
-  resource: PhantomData<R>
+/// Return the claims of the valid JWT given as `Authorization: Bearer`, if any.
+fn claims(config: &Config, headers: &Headers) -> Option<Claims> {
+  if !config.jwt.enabled {
+    return None;
+  }
+
+  let authorization = headers.get_raw("Authorization")
+                             .and_then(|h| String::from_utf8(h[0].to_owned()).ok());
+
+  match authorization {
+    Some(ref header) if header.starts_with("Bearer ") => {
+      let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as i64;
+      jwt::decode(&header["Bearer ".len()..], &*config.jwt.secret, now).ok()
+    },
+    _ => None
+  }
 }
 
-impl<R: Resource> DeletableHandler<R> {
-  fn new(config: Config) -> Self {
-    DeletableHandler::<R> {
-      resource: PhantomData,
-      config:   config
+/// Return the company a search is sent for (see `quotas`): the `company_id` claim of the JWT
+/// authorizing it, which its caller cannot change, or else the `company_id` param of the search
+/// when no JWT is given (i.e. by the main app, which searches for any company through TOTP).
+/// The searches that are not sent for a company have none, hence are neither limited nor counted.
+fn company_id(claims: Option<&Claims>, params: &Map) -> Option<String> {
+  match claims {
+    Some(claims) => claims.company_id.to_owned(),
+    None         => match params.get("company_id") {
+      Some(&Value::String(ref company_id)) => Some(company_id.to_owned()),
+      _                                    => None
     }
   }
 }
 
-impl<R: Resource> WritableEndpoint for DeletableHandler<R> {}
+/// Count a search towards the usage of `company_id`, whether it is answered from the cache or not.
+fn record_usage(company_id: &Option<String>) {
+  if let Some(ref company_id) = *company_id {
+    USAGE.record(company_id, UTC::now());
+  }
+}
 
-impl<R: Resource> Handler for DeletableHandler<R> {
-  fn handle(&self, req: &mut Request) -> IronResult<Response> {
-    let ref lifetimes = self.config.tokens.lifetime;
-    if !self.is_authorized(&self.config.auth, &req.headers, lifetimes.write) {
-      unauthorized!();
-    }
+/// Return the `429` response telling which quota `company_id` has run out of, if any (see `quotas`).
+fn check_quota(config: &Config, company_id: &Option<String>) -> Result<(), Response> {
+  let company_id = match *company_id {
+    Some(ref company_id) => company_id,
+    None                 => return Ok(())
+  };
 
-    let     client = req.get::<Write<SharedClient>>().unwrap();
-    let mut client = client.lock().unwrap();
+  quotas::check(&config.quotas, &USAGE, company_id, UTC::now()).map_err(|exceeded| {
+    METRICS.increment("searchspot_quota_rejections_total", &[("period", exceeded.period)]);
 
-    let ref id = try_or_422!(req.extensions.get::<Router>().unwrap()
-                                                           .find("id")
-                                                           .ok_or("DELETE#:id not found"));
+    let mut res = ErrorResponse::new(ErrorCode::TooManyRequests, exceeded.message(), Some(serde_json::to_value(&exceeded))).into_response();
+    res.headers.set_raw("Retry-After", vec![exceeded.retry_after.to_string().into_bytes()]);
+    res
+  })
+}
 
-    match R::delete(&mut client, id, &*self.config.es.index) {
-      Ok(_)  => Ok(Response::with(status::NoContent)),
-      Err(e) => {
-        let error_message = e.to_string();
-        error!("{}", error_message);
+/// Return `duration` in milliseconds.
+fn millis(duration: Duration) -> u64 {
+  duration.as_secs() * 1000 + (duration.subsec_nanos() / 1_000_000) as u64
+}
+
+/// Index the resources queued into `coalescer` on `index`, queueing them again
+/// if ElasticSearch cannot be reached or could not index them for the time being
+/// (`429` or `5xx`). The ones it has refused are dropped.
+fn flush<R: Resource>(coalescer: &Coalescer<R>, cluster: &Cluster, index: &str) {
+  let pending = coalescer.take();
+  if pending.is_empty() {
+    return;
+  }
+
+  let resources = pending.iter().map(|&(_, ref resource)| resource.to_owned()).collect::<Vec<R>>();
+  let matches   = SUBSCRIPTIONS.percolate(&resources);
+
+  METRICS.increment("searchspot_index_operations_total", &[("operation", "flush")]);
+  match METRICS.instrument(R::name(), index, "flush", || retry(|| R::index(&mut cluster.get(), index, resources.to_owned()))) {
+    Ok(result) => {
+      let statuses = result.items.iter().map(|item| (item.inner.id.to_owned(), item.inner.status)).collect::<HashMap<String, u64>>();
+
+      let mut indexed = vec![];
+      let mut failed  = vec![];
+      for (id, resource) in pending {
+        match statuses.get(&id).cloned().unwrap_or(200) {
+          status if status < 300                   => indexed.push(id),
+          status if status == 429 || status >= 500 => failed.push((id, resource)),
+          status                                   => error!("The coalesced document {} has been refused ({}), it is dropped.", id, status)
+        }
+      }
 
-        let content_type = "application/json".parse::<Mime>().unwrap();
-        Ok(Response::with(
-          (content_type, status::UnprocessableEntity, error_message)
-        ))
+      if !failed.is_empty() {
+        error!("{} coalesced documents cannot be indexed, they are queued again.", failed.len());
+        METRICS.increment("searchspot_es_errors_total", &[]);
+        coalescer.restore(failed);
       }
+
+      SUBSCRIPTIONS.notify(matches);
+      CACHE.invalidate(index);
+      WEBHOOKS.fire(Event::Indexed, index, indexed);
+    },
+    Err(err) => {
+      error!("Cannot index the coalesced documents: {}", err);
+      METRICS.increment("searchspot_es_errors_total", &[]);
+      coalescer.restore(pending);
     }
   }
 }
 
-pub struct ResettableHandler<R> {
+/// Reserve room in the queue for `documents`, counting the ones pending inside
+/// `coalescer` as well, or return the `429` response telling how full it is.
+fn reserve<R: Resource>(documents: usize, coalescer: Option<&Coalescer<R>>) -> Result<Reservation<'static>, Response> {
+  let pending = coalescer.map(|coalescer| coalescer.len()).unwrap_or(0);
+
+  QUEUE.reserve(documents, pending).map_err(|depth| {
+    METRICS.increment("searchspot_write_rejections_total", &[]);
+
+    let mut res = ErrorResponse::new(ErrorCode::TooManyRequests, "Too many documents are being indexed.", None).into_response();
+    res.headers.set_raw("X-Queue-Depth",    vec![depth.to_string().into_bytes()]);
+    res.headers.set_raw("X-Queue-Capacity", vec![QUEUE.capacity().to_string().into_bytes()]);
+    res.headers.set_raw("Retry-After",      vec![b"1".to_vec()]);
+    res
+  })
+}
+
+/// A document whose values have been refused by `Resource::validate`.
+#[derive(Serialize, Debug)]
+pub struct InvalidDocument {
+  pub id:     String,
+  pub errors: Vec<FieldError>
+}
+
+/// Return the `resources` whose values are not valid, in order.
+fn invalid_documents<R: Resource>(resources: &[R]) -> Vec<InvalidDocument> {
+  resources.iter()
+           .map(|resource| InvalidDocument { id: resource.id(), errors: resource.validate() })
+           .filter(|invalid| !invalid.errors.is_empty())
+           .collect()
+}
+
+/// A document whose id has already been indexed with different values.
+#[derive(Serialize, Debug)]
+pub struct Duplicate {
+  pub id:     String,
+  pub fields: Vec<String>
+}
+
+/// Look for the `resources` already stored into `index` with different values
+/// (see `Resource::conflicts`), returning their positions among `resources`.
+/// Every duplicate is logged and counted by `searchspot_duplicate_ids_total`.
+fn find_duplicates<R: Resource>(url: &str, index: &str, resources: &[R]) -> Result<Vec<(usize, Duplicate)>, Error> {
+  let ids    = resources.iter().map(|resource| resource.id()).collect::<Vec<String>>();
+  let stored = try!(mget::<R>(url, index, &ids));
+
+  let mut duplicates = vec![];
+  for (position, (resource, stored)) in resources.iter().zip(stored.into_iter()).enumerate() {
+    let stored: R = match stored.and_then(|source| serde_json::from_value(source).ok()) {
+      Some(stored) => stored,
+      None         => continue
+    };
+
+    let fields = resource.conflicts(&stored);
+    if !fields.is_empty() {
+      warn!("`{}` has already been indexed with a different {}.", resource.id(), fields.join(", "));
+      METRICS.increment("searchspot_duplicate_ids_total", &[]);
+      duplicates.push((position, Duplicate { id: resource.id(), fields: fields }));
+    }
+  }
+
+  Ok(duplicates)
+}
+
+/// Fetch the `_source` of the documents of `R` with given `ids` from `index`,
+/// searching every shard for them if they are routed by another key than their id.
+fn mget<R: Resource>(url: &str, index: &str, ids: &[String]) -> Result<Vec<Option<JsonValue>>, EsError> {
+  if routing::field(R::name()).is_some() {
+    es::mget_routed(url, index, ids)
+  }
+  else {
+    es::mget(url, index, ids)
+  }
+}
+
+/// Return the value of the `Link` header (RFC 5988) pointing to
+/// the next, previous and last pages of the given `pagination`.
+fn pagination_links(path: &str, query: Option<&str>, pagination: &Pagination) -> String {
+  let params = query.unwrap_or("")
+                    .split('&')
+                    .filter(|param| !param.is_empty() && !param.starts_with("offset="))
+                    .collect::<Vec<&str>>();
+
+  let link = |offset: u64, rel: &str| {
+    let     offset = format!("offset={}", offset);
+    let mut params = params.to_owned();
+    params.push(&*offset);
+    format!("<{}?{}>; rel=\"{}\"", path, params.join("&"), rel)
+  };
+
+  let mut links = vec![];
+
+  if let Some(next) = pagination.next() {
+    links.push(link(next, "next"));
+  }
+
+  if let Some(prev) = pagination.prev() {
+    links.push(link(prev, "prev"));
+  }
+
+  links.push(link(pagination.last(), "last"));
+  links.join(", ")
+}
+
+pub struct MultiSearchableHandler<R> {
   config:   Config,
   resource: PhantomData<R>
 }
 
-impl<R: Resource> ResettableHandler<R> {
+impl<R: Resource> MultiSearchableHandler<R> {
   fn new(config: Config) -> Self {
-    ResettableHandler::<R> {
+    MultiSearchableHandler::<R> {
       resource: PhantomData,
       config:   config
     }
   }
 }
 
-impl<R: Resource> WritableEndpoint for ResettableHandler<R> {}
+impl<R: Resource> ReadableEndpoint for MultiSearchableHandler<R> {}
 
-impl<R: Resource> Handler for ResettableHandler<R> {
+/// Run the searches given as array of params in a single round trip
+/// using `_msearch`, returning their results in the same order.
+impl<R: Resource> Handler for MultiSearchableHandler<R> {
   fn handle(&self, req: &mut Request) -> IronResult<Response> {
+    log_fields("msearch", &*self.config.es.index);
+
     let ref lifetimes = self.config.tokens.lifetime;
-    if !self.is_authorized(&self.config.auth, &req.headers, lifetimes.write) {
-      unauthorized!();
+    if !self.is_authorized(&self.config, &req.headers, lifetimes.read) {
+      unauthorized!(self, &self.config, &req.headers);
     }
 
-    let     client = req.get::<Write<SharedClient>>().unwrap();
-    let mut client = client.lock().unwrap();
-    match R::reset_index(&mut client, &*self.config.es.index) {
-      Ok(_)  => Ok(Response::with(status::NoContent)),
-      Err(e) => {
-        let error_message = e.to_string();
-        error!("{}", error_message);
+    let request_format  = request_format(req);
+    let response_format = response_format(req);
+    let caller     = caller(&self.config, &req.headers);
+    let claims     = claims(&self.config, &req.headers);
+    let redaction  = redaction(&self.config, &req.headers);
+    let payload    = read_body(req);
+
+    let objects: Vec<JsonValue> = try_or_422!(request_format.deserialize(&payload));
+
+    let mut params = vec![];
+    for object in objects.iter() {
+      let map = try_or_422!(schema::from_json(object));
+      try_params!(&map, &R::search_params());
+      try_page_size!(&map);
+      params.push(map);
+    }
 
-        let content_type = "application/json".parse::<Mime>().unwrap();
-        Ok(Response::with(
-          (content_type, status::UnprocessableEntity, error_message)
-        ))
+    // every search counts towards the quota of the company it is sent for
+    let company_ids = params.iter().map(|params| company_id(claims.as_ref(), params)).collect::<Vec<_>>();
+    for company_id in company_ids.iter() {
+      if let Err(res) = check_quota(&self.config, company_id) {
+        return Ok(res);
       }
     }
-  }
-}
+    for company_id in company_ids.iter() {
+      record_usage(company_id);
+    }
 
-struct CorsMiddleware;
+    let options = search_options(&self.config.resource(R::name()));
 
-impl AfterMiddleware for CorsMiddleware {
-  fn after(&self, _: &mut Request, mut res: Response) -> IronResult<Response> {
-    res.headers.set(headers::AccessControlAllowOrigin::Any);
-    res.headers.set(headers::AccessControlAllowHeaders(vec![
-      UniCase("x-requested-withcontent-type".to_owned()),
-      UniCase("content-type".to_owned()),
-      UniCase("accept".to_owned()),
-      UniCase("authorization".to_owned())
-    ]));
-    Ok(res)
+    let searches = try_or_respond!(params.iter()
+                                         .map(|params| R::preview(&*self.config.es.index, params, &options))
+                                         .collect::<Result<Vec<JsonValue>, Error>>());
+
+    let cluster    = req.get::<Read<SharedCluster>>().unwrap();
+    let started_at = Instant::now();
+    let responses  = try_or_respond!(es::msearch(&cluster.url(), &searches));
+    METRICS.observe_duration("searchspot_msearch_duration_seconds", &[], started_at.elapsed());
+
+    let mut results = vec![];
+    for (params, response) in params.iter().zip(responses.into_iter()) {
+      let result = try_or_respond!(response.map_err(Error::from).and_then(|response| R::results(params, &options, response)));
+
+      // partial results are never returned
+      if result.timed_out() {
+        error_response!(ErrorCode::Timeout, "A search has not been completed in time.",
+                        Some(serde_json::to_value(&btreemap! { "partial" => false })));
+      }
+
+      results.push(result);
+    }
+
+    let latency_ms = millis(started_at.elapsed());
+    for (params, result) in params.iter().zip(results.iter()) {
+      AUDIT.record(SearchEntry::new(&caller, R::name(), &*self.config.es.index, schema::to_json(params),
+                                    result.pagination().map(|pagination| pagination.total), latency_ms, false));
+
+      if result.pagination().map(|pagination| pagination.total == 0).unwrap_or(false) {
+        ANALYTICS.record(ZeroResult::new(R::name(), &*self.config.es.index, &schema::to_json(params)));
+      }
+    }
+
+    Ok(Response::with(
+      (response_format.mime(), status::Ok, try_or_422!(response_format.serialize(&redacted(&results, &redaction))))
+    ))
   }
 }
 
-impl<R: Resource> Server<R> {
-  pub fn new(config: Config, endpoint: &str) -> Self {
-    Server {
-      config:   config,
-      endpoint: endpoint.to_owned(),
-      resource: PhantomData
+pub struct PreviewHandler<R> {
+  config:   Config,
+  resource: PhantomData<R>
+}
+
+impl<R: Resource> PreviewHandler<R> {
+  fn new(config: Config) -> Self {
+    PreviewHandler::<R> {
+      resource: PhantomData,
+      config:   config
     }
   }
+}
 
-  pub fn start(&self) {
-    start_logging(&self.config).unwrap();
+impl<R: Resource> ReadableEndpoint for PreviewHandler<R> {}
 
-    let host = format!("{}:{}", self.config.http.host, self.config.http.port);
+/// Return the request that the search would send to ElasticSearch,
+/// without sending it.
+impl<R: Resource> Handler for PreviewHandler<R> {
+  fn handle(&self, req: &mut Request) -> IronResult<Response> {
+    log_fields("preview", &*self.config.es.index);
 
-    println!("Searchspot v{}\n{}\n", env!("CARGO_PKG_VERSION"), self.config);
+    let ref lifetimes = self.config.tokens.lifetime;
+    if !self.is_authorized(&self.config, &req.headers, lifetimes.read) {
+      unauthorized!(self, &self.config, &req.headers);
+    }
 
-    let mut router = Router::new();
-    router.get(&self.endpoint,    SearchableHandler::<R>::new(self.config.to_owned()), "search");
-    router.post(&self.endpoint,   IndexableHandler::<R>::new(self.config.to_owned()),  "index");
-    router.delete(&self.endpoint, ResettableHandler::<R>::new(self.config.to_owned()), "reset");
+    let params = try_or_422!(req.get_ref::<Params>());
+    try_params!(params, &R::search_params());
 
-    let deletable_endpoint = format!("{}/:id", self.endpoint);
-    router.delete(deletable_endpoint, DeletableHandler::<R>::new(self.config.to_owned()), "delete");
+    let options = search_options(&self.config.resource(R::name()));
 
-    let client = Client::new(&*self.config.to_owned().es.url).unwrap();
+    let preview = try_or_respond!(R::preview(&*self.config.es.index, params, &options));
 
-    let mut chain = Chain::new(router);
-    chain.link(Write::<SharedClient>::both(client));
-    chain.link(HTTPLogger::new(None));
-    chain.link_after(CorsMiddleware);
-    Iron::new(chain).http(&*host).unwrap();
+    let content_type = "application/json".parse::<Mime>().unwrap();
+    Ok(Response::with((content_type, status::Ok, serde_json::to_string(&preview).unwrap())))
+  }
+}
+
+pub struct FacetsHandler<R> {
+  config:   Config,
+  resource: PhantomData<R>
+}
+
+impl<R: Resource> FacetsHandler<R> {
+  fn new(config: Config) -> Self {
+    FacetsHandler::<R> {
+      resource: PhantomData,
+      config:   config
+    }
+  }
+}
+
+impl<R: Resource> ReadableEndpoint for FacetsHandler<R> {}
+
+/// Return the counts of the facets given the filters of the search,
+/// without fetching the resources.
+impl<R: Resource> Handler for FacetsHandler<R> {
+  fn handle(&self, req: &mut Request) -> IronResult<Response> {
+    log_fields("facets", &*self.config.es.index);
+
+    let ref lifetimes = self.config.tokens.lifetime;
+    if !self.is_authorized(&self.config, &req.headers, lifetimes.read) {
+      unauthorized!(self, &self.config, &req.headers);
+    }
+
+    let format = response_format(req);
+    let claims = claims(&self.config, &req.headers);
+
+    let cluster = req.get::<Read<SharedCluster>>().unwrap();
+    let params = try_or_422!(req.get_ref::<Params>());
+    try_params!(params, &R::search_params());
+
+    let company_id = company_id(claims.as_ref(), params);
+    if let Err(res) = check_quota(&self.config, &company_id) {
+      return Ok(res);
+    }
+    record_usage(&company_id);
+
+    let options = search_options(&self.config.resource(R::name()));
+    let facets  = try_or_respond!(retry(|| R::facets(&mut cluster.get(), &*self.config.es.index, params, &options)));
+
+    Ok(Response::with(
+      (format.mime(), status::Ok, try_or_422!(format.serialize(&facets)))
+    ))
+  }
+}
+
+pub struct StreamHandler<R> {
+  config:   Config,
+  resource: PhantomData<R>
+}
+
+impl<R: Resource> StreamHandler<R> {
+  fn new(config: Config) -> Self {
+    StreamHandler::<R> {
+      resource: PhantomData,
+      config:   config
+    }
+  }
+}
+
+impl<R: Resource> ReadableEndpoint for StreamHandler<R> {}
+
+/// Keep the connection open, pushing the ids of the newly indexed
+/// resources that match the given filters as server-sent events.
+/// Every open stream holds one of the server threads.
+impl<R: Resource> Handler for StreamHandler<R> {
+  fn handle(&self, req: &mut Request) -> IronResult<Response> {
+    log_fields("stream", &*self.config.es.index);
+
+    let ref lifetimes = self.config.tokens.lifetime;
+    if !self.is_authorized(&self.config, &req.headers, lifetimes.read) {
+      unauthorized!(self, &self.config, &req.headers);
+    }
+
+    let params = try_or_422!(req.get::<Params>());
+    try_params!(&params, &R::search_params());
+
+    let stream: Box<WriteBody> = Box::new(EventStream::new(params));
+
+    let content_type = "text/event-stream".parse::<Mime>().unwrap();
+    let mut res = Response::with((content_type, status::Ok, stream));
+    res.headers.set(headers::CacheControl(vec![headers::CacheDirective::NoCache]));
+
+    Ok(res)
+  }
+}
+
+pub struct ExportHandler<R> {
+  config:   Config,
+  resource: PhantomData<R>
+}
+
+impl<R: Resource> ExportHandler<R> {
+  fn new(config: Config) -> Self {
+    ExportHandler::<R> {
+      resource: PhantomData,
+      config:   config
+    }
+  }
+}
+
+impl<R: Resource> ReadableEndpoint for ExportHandler<R> {}
+
+/// Stream the resources matching the search as CSV, with the columns given by
+/// `columns[]` or by `export.columns`, their personal fields redacted (see `redaction`).
+/// The pagination params are ignored. The export is audited as a search.
+impl<R: Resource> Handler for ExportHandler<R> {
+  fn handle(&self, req: &mut Request) -> IronResult<Response> {
+    log_fields("export", &*self.config.es.index);
+
+    let ref lifetimes = self.config.tokens.lifetime;
+    if !self.is_authorized(&self.config, &req.headers, lifetimes.read) {
+      unauthorized!(self, &self.config, &req.headers);
+    }
+
+    let mut params = try_or_422!(req.get::<Params>());
+    let mut schema = R::search_params();
+    schema.push(Param::multiple("columns", Kind::String));
+    try_params!(&params, &schema);
+
+    let columns = match params.remove("columns") {
+      Some(Value::Array(columns)) => columns.into_iter().filter_map(|column| String::from_value(&column)).collect(),
+      _                           => self.config.export.columns.to_owned()
+    };
+
+    let company_id = company_id(claims(&self.config, &req.headers).as_ref(), &params);
+    if let Err(res) = check_quota(&self.config, &company_id) {
+      return Ok(res);
+    }
+    record_usage(&company_id);
+
+    let caller      = caller(&self.config, &req.headers);
+    let received_at = Instant::now();
+
+    let ref index = self.config.es.index;
+    let options   = search_options(&self.config.resource(R::name()));
+    let preview   = try_or_respond!(R::preview(index, &params, &options));
+
+    let redaction = redaction(&self.config, &req.headers);
+
+    let cluster = req.get::<Read<SharedCluster>>().unwrap();
+    let export  = try_or_respond!(CsvExport::open(&cluster.url(), &preview, columns, self.config.export.max_rows, redaction));
+
+    AUDIT.record(SearchEntry::new(&caller, R::name(), index, schema::to_json(&params), export.total(),
+                                  millis(received_at.elapsed()), false));
+    let export: Box<WriteBody> = Box::new(export);
+
+    let content_type = "text/csv; charset=utf-8".parse::<Mime>().unwrap();
+    let mut res = Response::with((content_type, status::Ok, export));
+    res.headers.set_raw("Content-Disposition", vec![format!("attachment; filename=\"{}.csv\"", R::name()).into_bytes()]);
+
+    Ok(res)
+  }
+}
+
+pub struct IndexableHandler<R> {
+  config:    Config,
+  coalescer: Option<Arc<Coalescer<R>>>
+}
+
+impl<R: Resource> IndexableHandler<R> {
+  /// The resources are queued into `coalescer`, if given, instead of being indexed.
+  fn new(config: Config, coalescer: Option<Arc<Coalescer<R>>>) -> Self {
+    IndexableHandler::<R> {
+      config:    config,
+      coalescer: coalescer
+    }
+  }
+}
+
+impl<R: Resource> WritableEndpoint for IndexableHandler<R> {}
+
+impl<R: Resource> Handler for IndexableHandler<R> {
+  fn handle(&self, req: &mut Request) -> IronResult<Response> {
+    log_fields("index", &*self.config.es.index);
+
+    let ref lifetimes = self.config.tokens.lifetime;
+    if !self.is_authorized(&self.config, &req.headers, lifetimes.write) {
+      unauthorized!(self, &self.config, &req.headers);
+    }
+
+    let format  = request_format(req);
+    let actor   = caller(&self.config, &req.headers);
+    let payload = read_body(req);
+
+    let resources: Vec<R> = try_or_422!(format.deserialize(&payload));
+
+    let invalid = invalid_documents(&resources);
+    if !invalid.is_empty() {
+      error_response!(ErrorCode::ValidationError, "Some documents contain invalid values.",
+                      Some(serde_json::to_value(&invalid)));
+    }
+
+    let policy = self.config.ingestion.duplicates();
+    if policy != Duplicates::Ignore {
+      let cluster    = req.get::<Read<SharedCluster>>().unwrap();
+      let duplicates = try_or_respond!(find_duplicates(&cluster.url(), &*self.config.es.index, &resources));
+
+      if policy == Duplicates::Reject && !duplicates.is_empty() {
+        let duplicates = duplicates.into_iter().map(|(_, duplicate)| duplicate).collect::<Vec<Duplicate>>();
+        error_response!(ErrorCode::Conflict, "Some documents have already been indexed with different values.",
+                        Some(serde_json::to_value(&duplicates)));
+      }
+    }
+
+    let _reservation = match reserve(resources.len(), self.coalescer.as_ref().map(|coalescer| &**coalescer)) {
+      Ok(reservation) => reservation,
+      Err(response)   => return Ok(response)
+    };
+
+    if let Some(ref coalescer) = self.coalescer {
+      for resource in resources {
+        // the versions are not known until the queue is flushed
+        AUDIT.record_operation(OperationEntry::new(&actor, "index", R::name(), &*self.config.es.index,
+                                                   Some(resource.id()), None, None));
+        coalescer.push(resource.id(), resource);
+      }
+
+      return Ok(Response::with(status::Accepted));
+    }
+
+    let matches = SUBSCRIPTIONS.percolate(&resources);
+    let ids     = resources.iter().map(|resource| resource.id()).collect();
+
+    let cluster = req.get::<Read<SharedCluster>>().unwrap();
+    let before  = audit::versions_before(&cluster.url(), &*self.config.es.index, &resources);
+    METRICS.increment("searchspot_index_operations_total", &[("operation", "index")]);
+    let result = try_or_respond!(METRICS.instrument(R::name(), &*self.config.es.index, "index",
+                                                    || R::index(&mut cluster.get(), &*self.config.es.index, resources)));
+    audit_indexed::<R>(&actor, &*self.config.es.index, &result.items, &before);
+    SUBSCRIPTIONS.notify(matches);
+    CACHE.invalidate(&*self.config.es.index);
+    WEBHOOKS.fire(Event::Indexed, &*self.config.es.index, ids);
+
+    Ok(Response::with(status::Created))
+  }
+}
+
+/// The outcome of a single document sent to the bulk endpoint.
+#[derive(Serialize, Debug)]
+pub struct BulkItemStatus {
+  pub position: usize,
+  pub id:       Option<String>,
+  pub status:   u64,
+  pub error:    Option<String>
+}
+
+/// The report returned by the bulk endpoint.
+#[derive(Serialize, Debug)]
+pub struct BulkReport {
+  pub errors: bool,
+  pub items:  Vec<BulkItemStatus>
+}
+
+pub struct BulkIndexableHandler<R> {
+  config:   Config,
+  resource: PhantomData<R>
+}
+
+impl<R: Resource> BulkIndexableHandler<R> {
+  fn new(config: Config) -> Self {
+    BulkIndexableHandler::<R> {
+      resource: PhantomData,
+      config:   config
+    }
+  }
+
+  /// Decode either an array (JSON or MessagePack) or a NDJSON stream into
+  /// a list of documents, keeping track of the ones that could not be decoded.
+  fn decode(payload: &[u8], format: Format) -> Result<Vec<Result<R, String>>, String> {
+    let is_array = format == Format::MessagePack ||
+                   str::from_utf8(payload).map(|p| p.trim_left().starts_with("[")).unwrap_or(false);
+
+    if is_array {
+      let documents: Vec<JsonValue> = try!(format.deserialize(payload));
+
+      Ok(documents.into_iter()
+                  .map(|document| serde_json::from_value(document).map_err(|e| e.to_string()))
+                  .collect())
+    }
+    else {
+      let payload = try!(str::from_utf8(payload).map_err(|e| e.to_string()));
+
+      Ok(payload.lines()
+                .filter(|line| !line.trim().is_empty())
+                .map(|line| serde_json::from_str(line).map_err(|e| e.to_string()))
+                .collect())
+    }
+  }
+}
+
+impl<R: Resource> WritableEndpoint for BulkIndexableHandler<R> {}
+
+impl<R: Resource> Handler for BulkIndexableHandler<R> {
+  fn handle(&self, req: &mut Request) -> IronResult<Response> {
+    log_fields("bulk", &*self.config.es.index);
+
+    let ref lifetimes = self.config.tokens.lifetime;
+    if !self.is_authorized(&self.config, &req.headers, lifetimes.write) {
+      unauthorized!(self, &self.config, &req.headers);
+    }
+
+    let request_format  = request_format(req);
+    let response_format = response_format(req);
+    let actor   = caller(&self.config, &req.headers);
+    let payload = read_body(req);
+
+    let documents = try_or_422!(Self::decode(&payload, request_format));
+
+    let mut items:     Vec<Option<BulkItemStatus>> = Vec::with_capacity(documents.len());
+    let mut positions: Vec<usize> = vec![];
+    let mut resources: Vec<R>     = vec![];
+
+    for (position, document) in documents.into_iter().enumerate() {
+      let document = document.and_then(|resource: R| {
+        let errors = resource.validate();
+        if errors.is_empty() {
+          Ok(resource)
+        }
+        else {
+          Err(errors.into_iter().map(|error| error.message).collect::<Vec<String>>().join(" "))
+        }
+      });
+
+      match document {
+        Ok(resource) => {
+          positions.push(position);
+          resources.push(resource);
+          items.push(None);
+        },
+        Err(error) => {
+          items.push(Some(BulkItemStatus {
+            position: position,
+            id:       None,
+            status:   422,
+            error:    Some(error)
+          }));
+        }
+      }
+    }
+
+    let policy = self.config.ingestion.duplicates();
+    if policy != Duplicates::Ignore && !resources.is_empty() {
+      let cluster    = req.get::<Read<SharedCluster>>().unwrap();
+      let duplicates = try_or_respond!(find_duplicates(&cluster.url(), &*self.config.es.index, &resources));
+
+      if policy == Duplicates::Reject && !duplicates.is_empty() {
+        let mut duplicates = duplicates.into_iter().collect::<HashMap<usize, Duplicate>>();
+        let pending = mem::replace(&mut positions, vec![]).into_iter()
+                                                          .zip(mem::replace(&mut resources, vec![]).into_iter());
+
+        for (i, (position, resource)) in pending.enumerate() {
+          match duplicates.remove(&i) {
+            Some(duplicate) => items[position] = Some(BulkItemStatus {
+              position: position,
+              id:       Some(duplicate.id),
+              status:   409,
+              error:    Some(format!("Already indexed with a different {}.", duplicate.fields.join(", ")))
+            }),
+            None => {
+              positions.push(position);
+              resources.push(resource);
+            }
+          }
+        }
+      }
+    }
+
+    if !resources.is_empty() {
+      let _reservation = match reserve::<R>(resources.len(), None) {
+        Ok(reservation) => reservation,
+        Err(response)   => return Ok(response)
+      };
+
+      let matches = SUBSCRIPTIONS.percolate(&resources);
+
+      let cluster = req.get::<Read<SharedCluster>>().unwrap();
+      let before  = audit::versions_before(&cluster.url(), &*self.config.es.index, &resources);
+      let ids     = resources.iter().map(|resource| resource.id()).collect::<Vec<String>>();
+      METRICS.increment("searchspot_index_operations_total", &[("operation", "bulk")]);
+      let chunks = METRICS.instrument(R::name(), &*self.config.es.index, "bulk", || {
+        let chunks = bulk::index_chunks(cluster, &*self.config.es.index, resources, self.config.es.max_concurrent_bulks);
+        if chunks.iter().all(|chunk| chunk.is_ok()) { Ok(chunks) } else { Err(chunks) }
+      }).unwrap_or_else(|chunks| chunks);
+      SUBSCRIPTIONS.notify(matches);
+
+      // a failed chunk is reported on each of its documents, the other chunks have been indexed anyway
+      let mut indexed = vec![];
+      let mut results = vec![];
+      let pending = bulk::chunks(positions.into_iter().zip(ids.into_iter()).collect(), bulk::CHUNK_SIZE);
+      for (pending, chunk) in pending.into_iter().zip(chunks.into_iter()) {
+        match chunk {
+          Ok(result) => {
+            indexed.extend(pending.into_iter().map(|(position, _)| position));
+            results.extend(result.items.into_iter());
+          },
+          Err(err) => {
+            error!("{}", err);
+            if err.is_es() {
+              METRICS.increment("searchspot_es_errors_total", &[]);
+            }
+
+            for (position, id) in pending {
+              items[position] = Some(BulkItemStatus {
+                position: position,
+                id:       Some(id),
+                status:   err.code().status().to_u16() as u64,
+                error:    Some(err.to_string())
+              });
+            }
+          }
+        }
+      }
+
+      audit_indexed::<R>(&actor, &*self.config.es.index, &results, &before);
+
+      let ids = results.iter()
+                       .filter(|item| item.inner.status < 300)
+                       .map(|item| item.inner.id.to_owned())
+                       .collect();
+      CACHE.invalidate(&*self.config.es.index);
+      WEBHOOKS.fire(Event::Indexed, &*self.config.es.index, ids);
+
+      // ElasticSearch returns the items in the same order they have been sent
+      for (position, item) in indexed.into_iter().zip(results.into_iter()) {
+        items[position] = Some(BulkItemStatus {
+          position: position,
+          id:       Some(item.inner.id),
+          status:   item.inner.status,
+          error:    None
+        });
+      }
+    }
+
+    let items: Vec<BulkItemStatus> = items.into_iter().filter_map(|item| item).collect();
+    let report = BulkReport {
+      errors: items.iter().any(|item| item.status >= 300),
+      items:  items
+    };
+
+    Ok(Response::with(
+      (response_format.mime(), status::Ok, response_format.serialize(&report).unwrap())
+    ))
+  }
+}
+
+pub struct FetchableHandler<R> {
+  config:   Config,
+  resource: PhantomData<R>
+}
+
+impl<R: Resource> FetchableHandler<R> {
+  fn new(config: Config) -> Self {
+    FetchableHandler::<R> {
+      resource: PhantomData,
+      config:   config
+    }
+  }
+}
+
+impl<R: Resource> ReadableEndpoint for FetchableHandler<R> {}
+
+/// Return the stored document, its personal fields redacted (see `redaction`),
+/// unless it is not visible and `include_hidden=true` has not been given.
+impl<R: Resource> Handler for FetchableHandler<R> {
+  fn handle(&self, req: &mut Request) -> IronResult<Response> {
+    log_fields("get", &*self.config.es.index);
+
+    let ref lifetimes = self.config.tokens.lifetime;
+    if !self.is_authorized(&self.config, &req.headers, lifetimes.read) {
+      unauthorized!(self, &self.config, &req.headers);
+    }
+
+    let id = try_or_422!(req.extensions.get::<Router>().unwrap()
+                                                       .find("id")
+                                                       .ok_or("GET#:id not found"))
+                                                       .to_owned();
+
+    let include_hidden = {
+      let params = try_or_422!(req.get_ref::<Params>());
+      try_params!(params, &[Param::single("include_hidden", Kind::Boolean)]);
+
+      match params.get("include_hidden") {
+        Some(&Value::String(ref include_hidden)) => include_hidden == "true",
+        _                                        => false
+      }
+    };
+
+    let     cluster = req.get::<Read<SharedCluster>>().unwrap();
+    let mut client = cluster.get();
+
+    let mut resource = match try_or_respond!(retry(|| R::get(&mut client, &id, &*self.config.es.index))) {
+      Some(ref resource) if include_hidden || resource.is_visible() => serde_json::to_value(resource),
+      _ => not_found!(format!("`{}` has not been found.", id))
+    };
+
+    if let Some(redaction) = redaction(&self.config, &req.headers) {
+      redaction.redact(&mut resource);
+    }
+
+    let content_type = "application/json".parse::<Mime>().unwrap();
+    Ok(Response::with(
+      (content_type, status::Ok, serde_json::to_string(&resource).unwrap())
+    ))
+  }
+}
+
+/// The body expected by the multi-get endpoint.
+#[derive(Deserialize, Debug)]
+pub struct MultiGetRequest {
+  pub ids:            Vec<String>,
+  #[serde(default)]
+  pub include_hidden: bool
+}
+
+/// The documents found by the multi-get endpoint, in the requested order.
+#[derive(Serialize, Debug)]
+pub struct MultiGetResponse {
+  pub documents: Vec<JsonValue>, // redacted (see `redaction`)
+  pub missing:   Vec<String>
+}
+
+pub struct MultiGettableHandler<R> {
+  config:   Config,
+  resource: PhantomData<R>
+}
+
+impl<R: Resource> MultiGettableHandler<R> {
+  fn new(config: Config) -> Self {
+    MultiGettableHandler::<R> {
+      resource: PhantomData,
+      config:   config
+    }
+  }
+}
+
+impl<R: Resource> ReadableEndpoint for MultiGettableHandler<R> {}
+
+/// Return the stored documents for the given ids using `_mget`, reporting
+/// as missing the ones that have not been found or are not visible.
+impl<R: Resource> Handler for MultiGettableHandler<R> {
+  fn handle(&self, req: &mut Request) -> IronResult<Response> {
+    log_fields("mget", &*self.config.es.index);
+
+    let ref lifetimes = self.config.tokens.lifetime;
+    if !self.is_authorized(&self.config, &req.headers, lifetimes.read) {
+      unauthorized!(self, &self.config, &req.headers);
+    }
+
+    let payload = read_body(req);
+    let request: MultiGetRequest = try_or_422!(serde_json::from_slice(&payload));
+
+    let cluster = req.get::<Read<SharedCluster>>().unwrap();
+    let sources = try_or_respond!(mget::<R>(&cluster.url(), &*self.config.es.index, &request.ids));
+
+    let redaction    = redaction(&self.config, &req.headers);
+    let mut response = MultiGetResponse {
+      documents: vec![],
+      missing:   vec![]
+    };
+
+    for (id, source) in request.ids.into_iter().zip(sources.into_iter()) {
+      let resource = source.and_then(|source| serde_json::from_value::<R>(source).ok());
+
+      match resource {
+        Some(resource) if request.include_hidden || resource.is_visible() => {
+          let mut document = serde_json::to_value(&resource);
+          if let Some(ref redaction) = redaction {
+            redaction.redact(&mut document);
+          }
+
+          response.documents.push(document);
+        },
+        _ => response.missing.push(id)
+      }
+    }
+
+    let content_type = "application/json".parse::<Mime>().unwrap();
+    Ok(Response::with(
+      (content_type, status::Ok, serde_json::to_string(&response).unwrap())
+    ))
+  }
+}
+
+pub struct DeletableHandler<R> {
+  config:    Config,
+  coalescer: Option<Arc<Coalescer<R>>>
+}
+
+impl<R: Resource> DeletableHandler<R> {
+  /// The pending version of the resource inside `coalescer`, if given, is dropped as well.
+  fn new(config: Config, coalescer: Option<Arc<Coalescer<R>>>) -> Self {
+    DeletableHandler::<R> {
+      config:    config,
+      coalescer: coalescer
+    }
+  }
+}
+
+impl<R: Resource> WritableEndpoint for DeletableHandler<R> {}
+
+impl<R: Resource> Handler for DeletableHandler<R> {
+  fn handle(&self, req: &mut Request) -> IronResult<Response> {
+    log_fields("delete", &*self.config.es.index);
+
+    let ref lifetimes = self.config.tokens.lifetime;
+    if !self.is_authorized(&self.config, &req.headers, lifetimes.write) {
+      unauthorized!(self, &self.config, &req.headers);
+    }
+
+    let     actor   = caller(&self.config, &req.headers);
+    let     cluster = req.get::<Read<SharedCluster>>().unwrap();
+    let mut client = cluster.get();
+
+    let ref id = try_or_422!(req.extensions.get::<Router>().unwrap()
+                                                           .find("id")
+                                                           .ok_or("DELETE#:id not found"));
+
+    // dropped first, so that it is not indexed again once flushed
+    let pending = self.coalescer.as_ref().map(|coalescer| coalescer.remove(id)).unwrap_or(false);
+
+    let before = audit::version_before::<R>(&cluster.url(), &*self.config.es.index, id);
+    METRICS.increment("searchspot_index_operations_total", &[("operation", "delete")]);
+    let result = try_or_respond!(METRICS.instrument(R::name(), &*self.config.es.index, "delete",
+                                                    || retry(|| R::delete(&mut client, id, &*self.config.es.index))));
+
+    if result.found || pending {
+      AUDIT.record_operation(OperationEntry::new(&actor, "delete", R::name(), &*self.config.es.index,
+                                                 Some(id.to_string()), before, None));
+      CACHE.invalidate(&*self.config.es.index);
+      WEBHOOKS.fire(Event::Deleted, &*self.config.es.index, vec![id.to_string()]);
+      Ok(Response::with(status::NoContent))
+    }
+    else {
+      not_found!(format!("`{}` has not been found.", id))
+    }
+  }
+}
+
+pub struct UpdatableHandler<R> {
+  config:   Config,
+  resource: PhantomData<R>
+}
+
+impl<R: Resource> UpdatableHandler<R> {
+  fn new(config: Config) -> Self {
+    UpdatableHandler::<R> {
+      resource: PhantomData,
+      config:   config
+    }
+  }
+}
+
+impl<R: Resource> WritableEndpoint for UpdatableHandler<R> {}
+
+impl<R: Resource> Handler for UpdatableHandler<R> {
+  fn handle(&self, req: &mut Request) -> IronResult<Response> {
+    log_fields("update", &*self.config.es.index);
+
+    let ref lifetimes = self.config.tokens.lifetime;
+    if !self.is_authorized(&self.config, &req.headers, lifetimes.write) {
+      unauthorized!(self, &self.config, &req.headers);
+    }
+
+    let actor   = caller(&self.config, &req.headers);
+    let payload = read_body(req);
+
+    let changes = match try_or_422!(serde_json::from_slice(&payload)) {
+      JsonValue::Object(changes) => changes,
+      _ => error_response!(ErrorCode::ValidationError, "PATCH expects a JSON object.")
+    };
+
+    let id = try_or_422!(req.extensions.get::<Router>().unwrap()
+                                                       .find("id")
+                                                       .ok_or("PATCH#:id not found"))
+                                                       .to_owned();
+
+    let     cluster = req.get::<Read<SharedCluster>>().unwrap();
+    let mut client = cluster.get();
+
+    let resource = match try_or_respond!(retry(|| R::get(&mut client, &id, &*self.config.es.index))) {
+      Some(resource) => resource,
+      None           => not_found!(format!("`{}` has not been found.", id))
+    };
+    let before = audit::versions_before(&cluster.url(), &*self.config.es.index, &[resource.to_owned()]);
+
+    // the changes are merged into the stored document and then
+    // decoded back into `R` to make sure they respect its schema
+    let mut document = match serde_json::to_value(&resource) {
+      JsonValue::Object(document) => document,
+      _ => error_response!(ErrorCode::ValidationError, "PATCH is not supported by this resource.")
+    };
+
+    for (field, value) in changes {
+      if !document.contains_key(&field) {
+        error_response!(ErrorCode::ValidationError, format!("Unknown field `{}`.", field),
+                        Some(JsonValue::String(field)));
+      }
+
+      document.insert(field, value);
+    }
+
+    let resource: R = try_or_422!(serde_json::from_value(JsonValue::Object(document)));
+
+    let errors = resource.validate();
+    if !errors.is_empty() {
+      error_response!(ErrorCode::ValidationError, "The changes contain invalid values.",
+                      Some(serde_json::to_value(&errors)));
+    }
+
+    METRICS.increment("searchspot_index_operations_total", &[("operation", "update")]);
+    let result = try_or_respond!(METRICS.instrument(R::name(), &*self.config.es.index, "update",
+                                                    || retry(|| R::update(&mut client, &id, &*self.config.es.index, resource.to_owned()))));
+    AUDIT.record_operation(OperationEntry::new(&actor, "update", R::name(), &*self.config.es.index, Some(id.to_owned()),
+                                               before.get(&id).cloned(), Some(result.version)));
+    CACHE.invalidate(&*self.config.es.index);
+    WEBHOOKS.fire(Event::Indexed, &*self.config.es.index, vec![id]);
+
+    Ok(Response::with(status::NoContent))
+  }
+}
+
+pub struct ResettableHandler<R> {
+  config:   Config,
+  resource: PhantomData<R>
+}
+
+impl<R: Resource> ResettableHandler<R> {
+  fn new(config: Config) -> Self {
+    ResettableHandler::<R> {
+      resource: PhantomData,
+      config:   config
+    }
+  }
+}
+
+impl<R: Resource> AdminEndpoint for ResettableHandler<R> {}
+
+impl<R: Resource> Handler for ResettableHandler<R> {
+  fn handle(&self, req: &mut Request) -> IronResult<Response> {
+    log_fields("reset", &*self.config.es.index);
+
+    let ref lifetimes = self.config.tokens.lifetime;
+    if !self.is_authorized(&self.config, &req.headers, lifetimes.write) {
+      unauthorized!(self, &self.config, &req.headers);
+    }
+
+    let     actor   = caller(&self.config, &req.headers);
+    let     cluster = req.get::<Read<SharedCluster>>().unwrap();
+    let mut client = cluster.get();
+    METRICS.increment("searchspot_index_operations_total", &[("operation", "reset")]);
+    try_or_respond!(METRICS.instrument(R::name(), &*self.config.es.index, "reset", || {
+      admin::reset::<R>(&mut client, &cluster.url(), &*self.config.es.index, &self.config.resource(R::name()))
+    }));
+    AUDIT.record_operation(OperationEntry::new(&actor, "reset", R::name(), &*self.config.es.index, None, None, None));
+    CACHE.invalidate(&*self.config.es.index);
+    WEBHOOKS.fire(Event::Reset, &*self.config.es.index, vec![]);
+
+    Ok(Response::with(status::NoContent))
+  }
+}
+
+/// The outcome of a reindex started through the admin endpoint.
+#[derive(Serialize, Debug)]
+pub struct ReindexReport {
+  pub index:     String,
+  pub documents: u64
+}
+
+pub struct AdminResetHandler<R> {
+  config:   Config,
+  resource: PhantomData<R>
+}
+
+impl<R: Resource> AdminResetHandler<R> {
+  fn new(config: Config) -> Self {
+    AdminResetHandler::<R> {
+      resource: PhantomData,
+      config:   config
+    }
+  }
+}
+
+impl<R: Resource> AdminEndpoint for AdminResetHandler<R> {}
+
+impl<R: Resource> Handler for AdminResetHandler<R> {
+  fn handle(&self, req: &mut Request) -> IronResult<Response> {
+    let ref lifetimes = self.config.tokens.lifetime;
+    if !self.is_authorized(&self.config, &req.headers, lifetimes.write) {
+      unauthorized!(self, &self.config, &req.headers);
+    }
+
+    let name = try_or_422!(req.extensions.get::<Router>().unwrap()
+                                                         .find("name")
+                                                         .ok_or("POST#:name not found"))
+                                                         .to_owned();
+    log_fields("admin_reset", &name);
+
+    let     actor   = caller(&self.config, &req.headers);
+    let     cluster = req.get::<Read<SharedCluster>>().unwrap();
+    let mut client = cluster.get();
+    METRICS.increment("searchspot_index_operations_total", &[("operation", "reset")]);
+    try_or_respond!(METRICS.instrument(R::name(), &name, "reset", || {
+      admin::reset::<R>(&mut client, &cluster.url(), &name, &self.config.resource(R::name()))
+    }));
+    AUDIT.record_operation(OperationEntry::new(&actor, "reset", R::name(), &name, None, None, None));
+    CACHE.invalidate(&name);
+    WEBHOOKS.fire(Event::Reset, &name, vec![]);
+
+    Ok(Response::with(status::NoContent))
+  }
+}
+
+pub struct AdminReindexHandler<R> {
+  config:   Config,
+  resource: PhantomData<R>
+}
+
+impl<R: Resource> AdminReindexHandler<R> {
+  fn new(config: Config) -> Self {
+    AdminReindexHandler::<R> {
+      resource: PhantomData,
+      config:   config
+    }
+  }
+}
+
+impl<R: Resource> AdminEndpoint for AdminReindexHandler<R> {}
+
+impl<R: Resource> Handler for AdminReindexHandler<R> {
+  fn handle(&self, req: &mut Request) -> IronResult<Response> {
+    let ref lifetimes = self.config.tokens.lifetime;
+    if !self.is_authorized(&self.config, &req.headers, lifetimes.write) {
+      unauthorized!(self, &self.config, &req.headers);
+    }
+
+    let name = try_or_422!(req.extensions.get::<Router>().unwrap()
+                                                         .find("name")
+                                                         .ok_or("POST#:name not found"))
+                                                         .to_owned();
+    log_fields("admin_reindex", &name);
+
+    let     actor   = caller(&self.config, &req.headers);
+    let     cluster = req.get::<Read<SharedCluster>>().unwrap();
+    let mut client = cluster.get();
+    METRICS.increment("searchspot_index_operations_total", &[("operation", "reindex")]);
+    let reindexed = try_or_respond!(METRICS.instrument(R::name(), &name, "reindex", || {
+      admin::reindex::<R>(&mut client, &cluster.url(), &name, &self.config.resource(R::name()))
+    }));
+    AUDIT.record_operation(OperationEntry::new(&actor, "reindex", R::name(), &name, None, None, None).with_target(&reindexed.target));
+    CACHE.invalidate(&name);
+
+    let report = ReindexReport {
+      index:     name,
+      documents: reindexed.documents
+    };
+
+    let content_type = "application/json".parse::<Mime>().unwrap();
+    Ok(Response::with(
+      (content_type, status::Ok, serde_json::to_string(&report).unwrap())
+    ))
+  }
+}
+
+/// Return the most wanted keywords and filters of the searches without
+/// results of the last `days` (7 by default), the `size` (20 by default)
+/// most frequent ones each.
+pub struct AdminZeroResultsHandler<R> {
+  config:   Config,
+  resource: PhantomData<R>
+}
+
+impl<R: Resource> AdminZeroResultsHandler<R> {
+  fn new(config: Config) -> Self {
+    AdminZeroResultsHandler::<R> {
+      resource: PhantomData,
+      config:   config
+    }
+  }
+}
+
+impl<R: Resource> AdminEndpoint for AdminZeroResultsHandler<R> {}
+
+impl<R: Resource> Handler for AdminZeroResultsHandler<R> {
+  fn handle(&self, req: &mut Request) -> IronResult<Response> {
+    log_fields("admin_zero_results", &*self.config.analytics.index);
+
+    let ref lifetimes = self.config.tokens.lifetime;
+    if !self.is_authorized(&self.config, &req.headers, lifetimes.write) {
+      unauthorized!(self, &self.config, &req.headers);
+    }
+
+    if !self.config.analytics.enabled {
+      not_found!("The searches without results are not recorded (see analytics.enabled).".to_owned());
+    }
+
+    let (days, size) = {
+      let params = try_or_422!(req.get_ref::<Params>());
+      try_params!(params, &[Param::single("days", Kind::Integer), Param::single("size", Kind::Integer)]);
+
+      let integer = |name: &str, default: u64| match params.get(name) {
+        Some(&Value::String(ref value)) => value.parse().unwrap_or(default),
+        _                               => default
+      };
+
+      (integer("days", 7), integer("size", 20))
+    };
+
+    let names   = R::search_params().iter().map(|param| param.name).collect::<Vec<&str>>();
+    let cluster = req.get::<Read<SharedCluster>>().unwrap();
+    let results = try_or_respond!(analytics::zero_results(&cluster.url(), &*self.config.analytics.index, R::name(),
+                                                          &analytics::filters(&names), days, size));
+
+    let content_type = "application/json".parse::<Mime>().unwrap();
+    Ok(Response::with(
+      (content_type, status::Ok, serde_json::to_string(&results).unwrap())
+    ))
+  }
+}
+
+/// Return how many searches every company has sent each day over the
+/// last `days` (30 by default), the heaviest users first.
+pub struct AdminCompanyStatsHandler {
+  config: Config
+}
+
+impl AdminCompanyStatsHandler {
+  fn new(config: Config) -> Self {
+    AdminCompanyStatsHandler {
+      config: config
+    }
+  }
+}
+
+impl AdminEndpoint for AdminCompanyStatsHandler {}
+
+impl Handler for AdminCompanyStatsHandler {
+  fn handle(&self, req: &mut Request) -> IronResult<Response> {
+    log_fields("admin_company_stats", &*self.config.es.index);
+
+    let ref lifetimes = self.config.tokens.lifetime;
+    if !self.is_authorized(&self.config, &req.headers, lifetimes.write) {
+      unauthorized!(self, &self.config, &req.headers);
+    }
+
+    let days = {
+      let params = try_or_422!(req.get_ref::<Params>());
+      try_params!(params, &[Param::single("days", Kind::Integer)]);
+
+      match params.get("days") {
+        Some(&Value::String(ref days)) => days.parse().unwrap_or(RETENTION_DAYS),
+        _                              => RETENTION_DAYS
+      }
+    };
+
+    let stats = USAGE.companies(days, UTC::now());
+
+    let content_type = "application/json".parse::<Mime>().unwrap();
+    Ok(Response::with(
+      (content_type, status::Ok, serde_json::to_string(&stats).unwrap())
+    ))
+  }
+}
+
+/// Return the `recruiter_id` param, required to list and delete the saved searches.
+fn recruiter_id(params: &Map) -> Option<String> {
+  match params.get("recruiter_id") {
+    Some(&Value::String(ref recruiter_id)) if !recruiter_id.trim().is_empty() => Some(recruiter_id.to_owned()),
+    _                                                                         => None
+  }
+}
+
+/// Save the search given as `{"recruiter_id": "42", "name": "Rust in Berlin", "params": {...}}`,
+/// whose params are validated as the ones of `POST /talents/msearch`.
+pub struct SaveSearchHandler<R> {
+  config:   Config,
+  resource: PhantomData<R>
+}
+
+impl<R: Resource> SaveSearchHandler<R> {
+  fn new(config: Config) -> Self {
+    SaveSearchHandler::<R> {
+      resource: PhantomData,
+      config:   config
+    }
+  }
+}
+
+impl<R: Resource> WritableEndpoint for SaveSearchHandler<R> {}
+
+impl<R: Resource> Handler for SaveSearchHandler<R> {
+  fn handle(&self, req: &mut Request) -> IronResult<Response> {
+    log_fields("save_search", &*self.config.saved_searches.index);
+
+    let ref lifetimes = self.config.tokens.lifetime;
+    if !self.is_authorized(&self.config, &req.headers, lifetimes.write) {
+      unauthorized!(self, &self.config, &req.headers);
+    }
+
+    if !self.config.saved_searches.enabled {
+      not_found!("The searches cannot be saved (see saved_searches.enabled).".to_owned());
+    }
+
+    let payload = read_body(req);
+    let request: NewSavedSearch = try_or_422!(serde_json::from_slice(&payload));
+
+    let search = match SavedSearch::new(request) {
+      Ok(search) => search,
+      Err(err)   => error_response!(ErrorCode::ValidationError, &*err)
+    };
+
+    let params = try_or_422!(search.params(&Map::new()));
+    try_params!(&params, &R::search_params());
+    try_page_size!(&params);
+
+    let cluster = req.get::<Read<SharedCluster>>().unwrap();
+    try_or_respond!(saved_searches::save(&cluster.url(), &*self.config.saved_searches.index, &search));
+
+    let content_type = "application/json".parse::<Mime>().unwrap();
+    Ok(Response::with(
+      (content_type, status::Created, serde_json::to_string(&search).unwrap())
+    ))
+  }
+}
+
+/// Return the searches saved by the recruiter given as `recruiter_id`, the latest first.
+pub struct ListSavedSearchesHandler {
+  config: Config
+}
+
+impl ListSavedSearchesHandler {
+  fn new(config: Config) -> Self {
+    ListSavedSearchesHandler {
+      config: config
+    }
+  }
+}
+
+impl ReadableEndpoint for ListSavedSearchesHandler {}
+
+impl Handler for ListSavedSearchesHandler {
+  fn handle(&self, req: &mut Request) -> IronResult<Response> {
+    log_fields("saved_searches", &*self.config.saved_searches.index);
+
+    let ref lifetimes = self.config.tokens.lifetime;
+    if !self.is_authorized(&self.config, &req.headers, lifetimes.read) {
+      unauthorized!(self, &self.config, &req.headers);
+    }
+
+    if !self.config.saved_searches.enabled {
+      not_found!("The searches cannot be saved (see saved_searches.enabled).".to_owned());
+    }
+
+    let recruiter_id = {
+      let params = try_or_422!(req.get_ref::<Params>());
+      try_params!(params, &[Param::single("recruiter_id", Kind::String)]);
+
+      match recruiter_id(params) {
+        Some(recruiter_id) => recruiter_id,
+        None               => error_response!(ErrorCode::ValidationError, "`recruiter_id` is required.")
+      }
+    };
+
+    let cluster  = req.get::<Read<SharedCluster>>().unwrap();
+    let searches = try_or_respond!(saved_searches::list(&cluster.url(), &*self.config.saved_searches.index, &recruiter_id));
+
+    let content_type = "application/json".parse::<Mime>().unwrap();
+    Ok(Response::with(
+      (content_type, status::Ok, serde_json::to_string(&searches).unwrap())
+    ))
+  }
+}
+
+/// Delete the saved search, if it has been saved by the recruiter given as `recruiter_id`.
+pub struct DeleteSavedSearchHandler {
+  config: Config
+}
+
+impl DeleteSavedSearchHandler {
+  fn new(config: Config) -> Self {
+    DeleteSavedSearchHandler {
+      config: config
+    }
+  }
+}
+
+impl WritableEndpoint for DeleteSavedSearchHandler {}
+
+impl Handler for DeleteSavedSearchHandler {
+  fn handle(&self, req: &mut Request) -> IronResult<Response> {
+    log_fields("delete_saved_search", &*self.config.saved_searches.index);
+
+    let ref lifetimes = self.config.tokens.lifetime;
+    if !self.is_authorized(&self.config, &req.headers, lifetimes.write) {
+      unauthorized!(self, &self.config, &req.headers);
+    }
+
+    if !self.config.saved_searches.enabled {
+      not_found!("The searches cannot be saved (see saved_searches.enabled).".to_owned());
+    }
+
+    let id = try_or_422!(req.extensions.get::<Router>().unwrap()
+                                                       .find("id")
+                                                       .ok_or("DELETE#:id not found"))
+                                                       .to_owned();
+
+    let recruiter_id = {
+      let params = try_or_422!(req.get_ref::<Params>());
+      try_params!(params, &[Param::single("recruiter_id", Kind::String)]);
+
+      match recruiter_id(params) {
+        Some(recruiter_id) => recruiter_id,
+        None               => error_response!(ErrorCode::ValidationError, "`recruiter_id` is required.")
+      }
+    };
+
+    let cluster   = req.get::<Read<SharedCluster>>().unwrap();
+    let ref index = self.config.saved_searches.index;
+
+    // the searches of the other recruiters are reported as missing
+    match try_or_respond!(saved_searches::get(&cluster.url(), index, &id)) {
+      Some(ref search) if search.recruiter_id == recruiter_id => (),
+      _ => not_found!(format!("`{}` has not been found.", id))
+    }
+
+    if try_or_respond!(saved_searches::delete(&cluster.url(), index, &id)) {
+      Ok(Response::with(status::NoContent))
+    }
+    else {
+      not_found!(format!("`{}` has not been found.", id))
+    }
+  }
+}
+
+/// Run the saved search, returning its results as the search endpoint does.
+/// Its pagination is replaced by the `offset` and `per_page` params, if given.
+pub struct RunSavedSearchHandler<R> {
+  config:   Config,
+  resource: PhantomData<R>
+}
+
+impl<R: Resource> RunSavedSearchHandler<R> {
+  fn new(config: Config) -> Self {
+    RunSavedSearchHandler::<R> {
+      resource: PhantomData,
+      config:   config
+    }
+  }
+}
+
+impl<R: Resource> ReadableEndpoint for RunSavedSearchHandler<R> {}
+
+impl<R: Resource> Handler for RunSavedSearchHandler<R> {
+  fn handle(&self, req: &mut Request) -> IronResult<Response> {
+    log_fields("run_saved_search", &*self.config.es.index);
+
+    let ref lifetimes = self.config.tokens.lifetime;
+    if !self.is_authorized(&self.config, &req.headers, lifetimes.read) {
+      unauthorized!(self, &self.config, &req.headers);
+    }
+
+    if !self.config.saved_searches.enabled {
+      not_found!("The searches cannot be saved (see saved_searches.enabled).".to_owned());
+    }
+
+    let id = try_or_422!(req.extensions.get::<Router>().unwrap()
+                                                       .find("id")
+                                                       .ok_or("GET#:id not found"))
+                                                       .to_owned();
+
+    let format     = response_format(req);
+    let caller     = caller(&self.config, &req.headers);
+    let claims     = claims(&self.config, &req.headers);
+    let redaction  = redaction(&self.config, &req.headers);
+    let cluster    = req.get::<Read<SharedCluster>>().unwrap();
+
+    let search = match try_or_respond!(saved_searches::get(&cluster.url(), &*self.config.saved_searches.index, &id)) {
+      Some(search) => search,
+      None         => not_found!(format!("`{}` has not been found.", id))
+    };
+
+    let params = {
+      let overrides = try_or_422!(req.get_ref::<Params>());
+      try_params!(overrides, &[Param::single("offset", Kind::Integer), Param::single("per_page", Kind::Integer)]);
+      try_or_422!(search.params(overrides))
+    };
+    try_params!(&params, &R::search_params());
+    try_page_size!(&params);
+
+    let company_id = company_id(claims.as_ref(), &params);
+    if let Err(res) = check_quota(&self.config, &company_id) {
+      return Ok(res);
+    }
+    record_usage(&company_id);
+
+    let options    = search_options(&self.config.resource(R::name()));
+    let ref index  = self.config.es.index;
+    let started_at = Instant::now();
+    let response   = try_or_respond!(METRICS.instrument(R::name(), index, "search", || {
+      preview_search::<R>(&cluster.url(), index, &params, &options)
+    }));
+
+    // partial results are never returned
+    if response.timed_out() {
+      error_response!(ErrorCode::Timeout, "The search has not been completed in time.",
+                      Some(serde_json::to_value(&btreemap! { "partial" => false })));
+    }
+
+    AUDIT.record(SearchEntry::new(&caller, R::name(), index, schema::to_json(&params),
+                                  response.pagination().map(|pagination| pagination.total),
+                                  millis(started_at.elapsed()), false));
+
+    let mut res = Response::with((format.mime(), status::Ok, try_or_422!(format.serialize(&redacted(&response, &redaction)))));
+
+    if let Some(pagination) = response.pagination() {
+      res.headers.set_raw("X-Total-Count", vec![pagination.total.to_string().into_bytes()]);
+    }
+
+    Ok(res)
+  }
+}
+
+/// Create the batch given as JSON object, returning it with its ID.
+pub struct CreateBatchHandler {
+  config: Config
+}
+
+impl CreateBatchHandler {
+  fn new(config: Config) -> Self {
+    CreateBatchHandler {
+      config: config
+    }
+  }
+}
+
+impl WritableEndpoint for CreateBatchHandler {}
+
+impl Handler for CreateBatchHandler {
+  fn handle(&self, req: &mut Request) -> IronResult<Response> {
+    log_fields("create_batch", &*self.config.batches.index);
+
+    let ref lifetimes = self.config.tokens.lifetime;
+    if !self.is_authorized(&self.config, &req.headers, lifetimes.write) {
+      unauthorized!(self, &self.config, &req.headers);
+    }
+
+    if !self.config.batches.enabled {
+      not_found!("The batches are not managed (see batches.enabled).".to_owned());
+    }
+
+    let payload = read_body(req);
+    let request: NewBatch = try_or_422!(serde_json::from_slice(&payload));
+
+    let batch = match Batch::new(request) {
+      Ok(batch) => batch,
+      Err(err)  => error_response!(ErrorCode::ValidationError, &*err)
+    };
+
+    let cluster   = req.get::<Read<SharedCluster>>().unwrap();
+    let ref index = self.config.batches.index;
+
+    if try_or_respond!(batches::get(&cluster.url(), index, &batch.id)).is_some() {
+      error_response!(ErrorCode::Conflict, format!("`{}` exists already.", batch.id));
+    }
+    try_or_respond!(batches::save(&cluster.url(), index, &batch));
+
+    let content_type = "application/json".parse::<Mime>().unwrap();
+    Ok(Response::with(
+      (content_type, status::Created, serde_json::to_string(&batch).unwrap())
+    ))
+  }
+}
+
+/// Return the batch, with the talents assigned to it.
+pub struct BatchHandler {
+  config: Config
+}
+
+impl BatchHandler {
+  fn new(config: Config) -> Self {
+    BatchHandler {
+      config: config
+    }
+  }
+}
+
+impl ReadableEndpoint for BatchHandler {}
+
+impl Handler for BatchHandler {
+  fn handle(&self, req: &mut Request) -> IronResult<Response> {
+    log_fields("batch", &*self.config.batches.index);
+
+    let ref lifetimes = self.config.tokens.lifetime;
+    if !self.is_authorized(&self.config, &req.headers, lifetimes.read) {
+      unauthorized!(self, &self.config, &req.headers);
+    }
+
+    if !self.config.batches.enabled {
+      not_found!("The batches are not managed (see batches.enabled).".to_owned());
+    }
+
+    let id = try_or_422!(req.extensions.get::<Router>().unwrap()
+                                                       .find("id")
+                                                       .ok_or("GET#:id not found"))
+                                                       .to_owned();
+
+    let cluster = req.get::<Read<SharedCluster>>().unwrap();
+    let batch   = match try_or_respond!(batches::get(&cluster.url(), &*self.config.batches.index, &id)) {
+      Some(batch) => batch,
+      None        => not_found!(format!("`{}` has not been found.", id))
+    };
+
+    let content_type = "application/json".parse::<Mime>().unwrap();
+    Ok(Response::with(
+      (content_type, status::Ok, serde_json::to_string(&batch).unwrap())
+    ))
+  }
+}
+
+/// Assign the talents given as `{"talent_ids": [...]}` to the batch, setting their
+/// batch dates and moving them out of their previous batch.
+pub struct AssignBatchHandler<R> {
+  config:   Config,
+  resource: PhantomData<R>
+}
+
+impl<R: Resource> AssignBatchHandler<R> {
+  fn new(config: Config) -> Self {
+    AssignBatchHandler::<R> {
+      resource: PhantomData,
+      config:   config
+    }
+  }
+}
+
+impl<R: Resource> WritableEndpoint for AssignBatchHandler<R> {}
+
+impl<R: Resource> Handler for AssignBatchHandler<R> {
+  fn handle(&self, req: &mut Request) -> IronResult<Response> {
+    log_fields("assign_batch", &*self.config.es.index);
+
+    let ref lifetimes = self.config.tokens.lifetime;
+    if !self.is_authorized(&self.config, &req.headers, lifetimes.write) {
+      unauthorized!(self, &self.config, &req.headers);
+    }
+
+    if !self.config.batches.enabled {
+      not_found!("The batches are not managed (see batches.enabled).".to_owned());
+    }
+
+    let id = try_or_422!(req.extensions.get::<Router>().unwrap()
+                                                       .find("id")
+                                                       .ok_or("POST#:id not found"))
+                                                       .to_owned();
+
+    let actor   = caller(&self.config, &req.headers);
+    let payload = read_body(req);
+    let request: Assignment = try_or_422!(serde_json::from_slice(&payload));
+
+    let cluster   = req.get::<Read<SharedCluster>>().unwrap();
+    let url       = cluster.url();
+    let ref index = self.config.batches.index;
+
+    let batch = match try_or_respond!(batches::get(&url, index, &id)) {
+      Some(ref batch) if batch.is_closed() => error_response!(ErrorCode::Conflict, format!("`{}` has been closed.", id)),
+      Some(batch)                          => batch,
+      None                                 => not_found!(format!("`{}` has not been found.", id))
+    };
+
+    // a talent belongs to a single batch
+    for previous in try_or_respond!(batches::previous(&url, index, &id, &request.talent_ids)) {
+      try_or_respond!(batches::save(&url, index, &previous.unassign(&request.talent_ids)));
+    }
+
+    let batch = batch.assign(&request.talent_ids);
+    try_or_respond!(batches::save(&url, index, &batch));
+
+    let ref talents = self.config.es.index;
+    let missing = try_or_respond!(batches::update_talents(&url, talents, R::name(), &batch, &request.talent_ids,
+                                                          Some(&UTC::now().to_rfc3339())));
+    let updated = request.talent_ids.iter().cloned().filter(|id| !missing.contains(id)).collect::<Vec<u64>>();
+    CACHE.invalidate(talents);
+
+    for id in &updated {
+      AUDIT.record_operation(OperationEntry::new(&actor, "assign_batch", R::name(), talents, Some(id.to_string()), None, None));
+    }
+
+    let report = BatchReport { batch: batch, updated: updated, missing: missing };
+
+    let content_type = "application/json".parse::<Mime>().unwrap();
+    Ok(Response::with(
+      (content_type, status::Ok, serde_json::to_string(&report).unwrap())
+    ))
+  }
+}
+
+/// Close the batch, ending it now (unless it has ended already) for all of its talents.
+pub struct CloseBatchHandler<R> {
+  config:   Config,
+  resource: PhantomData<R>
+}
+
+impl<R: Resource> CloseBatchHandler<R> {
+  fn new(config: Config) -> Self {
+    CloseBatchHandler::<R> {
+      resource: PhantomData,
+      config:   config
+    }
+  }
+}
+
+impl<R: Resource> WritableEndpoint for CloseBatchHandler<R> {}
+
+impl<R: Resource> Handler for CloseBatchHandler<R> {
+  fn handle(&self, req: &mut Request) -> IronResult<Response> {
+    log_fields("close_batch", &*self.config.es.index);
+
+    let ref lifetimes = self.config.tokens.lifetime;
+    if !self.is_authorized(&self.config, &req.headers, lifetimes.write) {
+      unauthorized!(self, &self.config, &req.headers);
+    }
+
+    if !self.config.batches.enabled {
+      not_found!("The batches are not managed (see batches.enabled).".to_owned());
+    }
+
+    let id = try_or_422!(req.extensions.get::<Router>().unwrap()
+                                                       .find("id")
+                                                       .ok_or("POST#:id not found"))
+                                                       .to_owned();
+
+    let actor     = caller(&self.config, &req.headers);
+    let cluster   = req.get::<Read<SharedCluster>>().unwrap();
+    let url       = cluster.url();
+    let ref index = self.config.batches.index;
+
+    let batch = match try_or_respond!(batches::get(&url, index, &id)) {
+      Some(ref batch) if batch.is_closed() => error_response!(ErrorCode::Conflict, format!("`{}` has been closed already.", id)),
+      Some(batch)                          => batch.close(&UTC::now().to_rfc3339()),
+      None                                 => not_found!(format!("`{}` has not been found.", id))
+    };
+    try_or_respond!(batches::save(&url, index, &batch));
+
+    let ref talents = self.config.es.index;
+    let missing = try_or_respond!(batches::update_talents(&url, talents, R::name(), &batch, &batch.talent_ids, None));
+    let updated = batch.talent_ids.iter().cloned().filter(|id| !missing.contains(id)).collect::<Vec<u64>>();
+    CACHE.invalidate(talents);
+
+    for id in &updated {
+      AUDIT.record_operation(OperationEntry::new(&actor, "close_batch", R::name(), talents, Some(id.to_string()), None, None));
+    }
+
+    let report = BatchReport { batch: batch, updated: updated, missing: missing };
+
+    let content_type = "application/json".parse::<Mime>().unwrap();
+    Ok(Response::with(
+      (content_type, status::Ok, serde_json::to_string(&report).unwrap())
+    ))
+  }
+}
+
+/// Search every resource given as `resources[]` (all of them by default) in a single
+/// round trip, returning their results as a section each.
+pub struct FederatedSearchHandler {
+  config:   Config,
+  registry: Arc<Registry>
+}
+
+impl FederatedSearchHandler {
+  fn new(config: Config, registry: Arc<Registry>) -> Self {
+    FederatedSearchHandler {
+      config:   config,
+      registry: registry
+    }
+  }
+}
+
+impl ReadableEndpoint for FederatedSearchHandler {}
+
+impl Handler for FederatedSearchHandler {
+  fn handle(&self, req: &mut Request) -> IronResult<Response> {
+    log_fields("federated_search", &*self.config.es.index);
+
+    let ref lifetimes = self.config.tokens.lifetime;
+    if !self.is_authorized(&self.config, &req.headers, lifetimes.read) {
+      unauthorized!(self, &self.config, &req.headers);
+    }
+
+    let format     = response_format(req);
+    let caller     = caller(&self.config, &req.headers);
+    let claims     = claims(&self.config, &req.headers);
+    let redaction  = redaction(&self.config, &req.headers);
+    let cluster    = req.get::<Read<SharedCluster>>().unwrap();
+    let params     = try_or_422!(req.get_ref::<Params>());
+
+    let names = match federated::resources(params, &self.registry) {
+      Ok(names) => names,
+      Err(err)  => error_response!(ErrorCode::ValidationError, err)
+    };
+
+    // every param must be accepted by one of the resources at least
+    let mut search_params = vec![Param::multiple("resources", Kind::String)];
+    for name in &names {
+      search_params.extend(self.registry[name].search_params());
+    }
+    try_params!(params, &search_params);
+    try_page_size!(params);
+
+    let company_id = company_id(claims.as_ref(), params);
+    if let Err(res) = check_quota(&self.config, &company_id) {
+      return Ok(res);
+    }
+    record_usage(&company_id);
+
+    // the searches that cannot even be built are reported in their section
+    let mut sections = vec![];
+    let mut pending  = vec![];
+    let mut searches = vec![];
+    for name in &names {
+      let ref searchable = self.registry[name];
+      let index   = self.config.index_of(searchable.name());
+      let options = search_options(&self.config.resource(searchable.name()));
+      let params  = federated::accepted(params, &searchable.search_params());
+
+      match searchable.preview(&index, &params, &options) {
+        Ok(search) => {
+          pending.push((sections.len(), name, index, options, params));
+          searches.push(search);
+          sections.push(None);
+        },
+        Err(err) => sections.push(Some(Section::failed(name, &err)))
+      }
+    }
+
+    let started_at = Instant::now();
+    let responses  = if searches.is_empty() { vec![] } else { try_or_respond!(es::msearch(&cluster.url(), &searches)) };
+    METRICS.observe_duration("searchspot_federated_search_duration_seconds", &[], started_at.elapsed());
+
+    let latency_ms = millis(started_at.elapsed());
+    for ((position, name, index, options, params), response) in pending.into_iter().zip(responses.into_iter()) {
+      let ref searchable = self.registry[name];
+
+      // a resource failing does not fail the others
+      let section = response.map_err(Error::from)
+                            .and_then(|response| searchable.section(name, &params, &options, response))
+                            .unwrap_or_else(|err| Section::failed(name, &err));
+
+      AUDIT.record(SearchEntry::new(&caller, searchable.name(), &index, schema::to_json(&params), section.total, latency_ms, false));
+      sections[position] = Some(section);
+    }
+
+    let results = FederatedResults { sections: sections.into_iter().filter_map(|section| section).collect() };
+    Ok(Response::with((format.mime(), status::Ok, try_or_422!(format.serialize(&redacted(&results, &redaction))))))
+  }
+}
+
+/// Record which of the talents returned by a search have been clicked or contacted
+/// into the analytics index, in background.
+pub struct FeedbackHandler<R> {
+  config:   Config,
+  resource: PhantomData<R>
+}
+
+impl<R: Resource> FeedbackHandler<R> {
+  fn new(config: Config) -> Self {
+    FeedbackHandler::<R> {
+      resource: PhantomData,
+      config:   config
+    }
+  }
+}
+
+impl<R: Resource> WritableEndpoint for FeedbackHandler<R> {}
+
+impl<R: Resource> Handler for FeedbackHandler<R> {
+  fn handle(&self, req: &mut Request) -> IronResult<Response> {
+    log_fields("feedback", &*self.config.analytics.index);
+
+    let ref lifetimes = self.config.tokens.lifetime;
+    if !self.is_authorized(&self.config, &req.headers, lifetimes.write) {
+      unauthorized!(self, &self.config, &req.headers);
+    }
+
+    if !self.config.analytics.enabled {
+      not_found!("The feedback is not recorded (see analytics.enabled).".to_owned());
+    }
+
+    let payload = read_body(req);
+    let request: Feedback = try_or_422!(serde_json::from_slice(&payload));
+
+    match Feedback::new(request, R::name(), &*self.config.es.index) {
+      Ok(mut feedback) => {
+        // the analytics never record who the recruiters are
+        if let Some(redaction) = Redaction::new(&self.config.gdpr) {
+          feedback.recruiter_id = feedback.recruiter_id.and_then(|id| redaction.value(&id));
+        }
+
+        ANALYTICS.record(feedback)
+      },
+      Err(err)         => error_response!(ErrorCode::ValidationError, &*err)
+    }
+
+    Ok(Response::with(status::Accepted))
+  }
+}
+
+/// Accept the events pushed by the main application, signed with `ingestion.webhook_secret`,
+/// queueing them to be applied in background (see `consumer::Worker`).
+pub struct UpstreamWebhookHandler<R> {
+  config:   Config,
+  verifier: UpstreamVerifier,
+  worker:   Arc<Worker<R>>
+}
+
+impl<R: Resource> UpstreamWebhookHandler<R> {
+  fn new(config: Config, worker: Arc<Worker<R>>) -> Self {
+    UpstreamWebhookHandler::<R> {
+      verifier: UpstreamVerifier::new(&config.ingestion.webhook_secret, config.ingestion.webhook_tolerance),
+      config:   config,
+      worker:   worker
+    }
+  }
+}
+
+impl<R: Resource> Handler for UpstreamWebhookHandler<R> {
+  fn handle(&self, req: &mut Request) -> IronResult<Response> {
+    log_fields("upstream_webhook", &*self.config.es.index);
+
+    let ref ingestion = self.config.ingestion;
+    if ingestion.webhook_secret.is_empty() {
+      not_found!("The events of the main application are not accepted.");
+    }
+
+    let header = req.headers.get_raw(UPSTREAM_SIGNATURE_HEADER)
+                            .and_then(|h| String::from_utf8(h[0].to_owned()).ok())
+                            .unwrap_or("".to_owned());
+    let body   = read_body(req);
+    let now    = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as i64;
+
+    if let Err(err) = self.verifier.verify(&header, now, &body) {
+      error_response!(ErrorCode::Unauthorized, &*err);
+    }
+
+    let command = match consumer::parse_webhook::<R>(&body) {
+      Ok(command) => command,
+      Err(err)    => error_response!(ErrorCode::ValidationError, &*err)
+    };
+
+    // held until the event has been applied, so that the events cannot pile up in the worker
+    let reservation = match reserve::<R>(1, None) {
+      Ok(reservation) => reservation,
+      Err(response)   => return Ok(response)
+    };
+
+    let cluster = req.get::<Read<SharedCluster>>().unwrap();
+    self.worker.push(cluster, command, Some(reservation));
+
+    Ok(Response::with(status::Accepted))
+  }
+}
+
+/// Erase the talent from every store that Searchspot keeps it in (see `erasure`),
+/// returning the report of the erasure, as the `details` of a `503` if any of them
+/// could not be purged.
+pub struct EraseHandler<R> {
+  config:    Config,
+  coalescer: Arc<Coalescer<R>>,
+  worker:    Arc<Worker<R>>
+}
+
+impl<R: Resource> EraseHandler<R> {
+  fn new(config: Config, coalescer: Arc<Coalescer<R>>, worker: Arc<Worker<R>>) -> Self {
+    EraseHandler::<R> {
+      config:    config,
+      coalescer: coalescer,
+      worker:    worker
+    }
+  }
+}
+
+impl<R: Resource> AdminEndpoint for EraseHandler<R> {}
+
+impl<R: Resource> Handler for EraseHandler<R> {
+  fn handle(&self, req: &mut Request) -> IronResult<Response> {
+    log_fields("erase", &*self.config.es.index);
+
+    let ref lifetimes = self.config.tokens.lifetime;
+    if !self.is_authorized(&self.config, &req.headers, lifetimes.write) {
+      unauthorized!(self, &self.config, &req.headers);
+    }
+
+    let id = try_or_422!(req.extensions.get::<Router>().unwrap()
+                                                       .find("id")
+                                                       .ok_or("DELETE#:id not found"))
+                                                       .to_owned();
+
+    let     actor   = caller(&self.config, &req.headers);
+    let     cluster = req.get::<Read<SharedCluster>>().unwrap();
+    let     url     = cluster.url();
+    let ref index   = self.config.es.index;
+    let mut steps   = vec![];
+
+    // dropped first, so that it is not flushed back into the index
+    steps.push(Step::new("queue", "coalescer", Ok(self.coalescer.remove(&id) as u64)));
+
+    // the fresh index of a running reindex (i.e. `talents_20170304122400`) holds a copy as well
+    let indexes = format!("{},{}_*", index, index);
+    steps.push(Step::new("index", &indexes, erasure::delete_by_query(&url, &indexes, &erasure::document_query(&id))));
+
+    // applied after the events of the main application queued meanwhile, which would index it again
+    self.worker.push(cluster.clone(), Command::Delete(id.to_owned(), None), None);
+
+    let cached = CACHE.len();
+    CACHE.invalidate(index);
+    steps.push(Step::new("cache", index, Ok(cached.saturating_sub(CACHE.len()) as u64)));
+
+    let ref audit = self.config.audit;
+    if audit.sink == "index" || audit.operations {
+      let query = erasure::audit_query(R::name(), &id);
+      steps.push(Step::new("audit", &audit.index, erasure::delete_by_query(&url, &audit.index, &query)));
+    }
+
+    if self.config.analytics.enabled {
+      let ref analytics_index = self.config.analytics.index;
+      let query = erasure::analytics_query(&id);
+      steps.push(Step::new("analytics", analytics_index, erasure::delete_by_query(&url, analytics_index, &query)));
+    }
+
+    // the ids of the talents are numbers, no batch can hold any other
+    if let (true, Ok(talent_id)) = (self.config.batches.enabled, id.parse::<u64>()) {
+      let ref batches_index = self.config.batches.index;
+      let withdrawn = batches::withdraw(&url, batches_index, talent_id).map_err(|err| err.to_string());
+      steps.push(Step::new("batches", batches_index, withdrawn));
+    }
+
+    let ref backups = self.config.backup;
+    if backups.enabled {
+      backup::purge_in_background::<R>(backups, index, &id);
+      steps.push(Step::queued("backups", &format!("s3://{}/{}{}/", backups.bucket, backups.prefix, index)));
+    }
+
+    let report  = ErasureReport::new(&id, steps);
+    let outcome = if report.complete { "complete" } else { "incomplete" };
+
+    // recorded without the id, which must not be kept anywhere
+    AUDIT.record_operation(OperationEntry::new(&actor, "erase", R::name(), index, None, None, None));
+    METRICS.increment("searchspot_erasures_total", &[("outcome", outcome)]);
+
+    if !report.complete {
+      error_response!(ErrorCode::Unavailable, "The talent has not been erased from every store, the erasure must be sent again.",
+                      Some(serde_json::to_value(&report)));
+    }
+
+    let content_type = "application/json".parse::<Mime>().unwrap();
+    Ok(Response::with(
+      (content_type, status::Ok, serde_json::to_string(&report).unwrap())
+    ))
+  }
+}
+
+pub struct HealthHandler {
+  config: Config
+}
+
+impl HealthHandler {
+  fn new(config: Config) -> Self {
+    HealthHandler {
+      config: config
+    }
+  }
+}
+
+impl Handler for HealthHandler {
+  fn handle(&self, req: &mut Request) -> IronResult<Response> {
+    let cluster = req.get::<Read<SharedCluster>>().unwrap();
+    let health  = Health::check(&cluster.url(), &*self.config.es.index).with_unreachable_nodes(cluster.unreachable());
+
+    let status = if health.is_available() {
+      status::Ok
+    }
+    else {
+      status::ServiceUnavailable
+    };
+
+    let content_type = "application/json".parse::<Mime>().unwrap();
+    Ok(Response::with(
+      (content_type, status, serde_json::to_string(&health).unwrap())
+    ))
+  }
+}
+
+pub struct LivenessHandler;
+
+impl Handler for LivenessHandler {
+  fn handle(&self, _: &mut Request) -> IronResult<Response> {
+    Ok(Response::with(status::Ok))
+  }
+}
+
+pub struct ReadinessHandler<R> {
+  config:   Config,
+  resource: PhantomData<R>
+}
+
+impl<R: Resource> ReadinessHandler<R> {
+  fn new(config: Config) -> Self {
+    ReadinessHandler::<R> {
+      resource: PhantomData,
+      config:   config
+    }
+  }
+}
+
+impl<R: Resource> Handler for ReadinessHandler<R> {
+  fn handle(&self, req: &mut Request) -> IronResult<Response> {
+    let cluster   = req.get::<Read<SharedCluster>>().unwrap();
+    let readiness = Readiness::check(&cluster.url(), &*self.config.es.index, &R::mapping());
+
+    let status = if readiness.ready {
+      status::Ok
+    }
+    else {
+      status::ServiceUnavailable
+    };
+
+    let content_type = "application/json".parse::<Mime>().unwrap();
+    Ok(Response::with(
+      (content_type, status, serde_json::to_string(&readiness).unwrap())
+    ))
+  }
+}
+
+/// The OpenAPI specification describing the endpoints.
+const OPENAPI: &'static str = include_str!("openapi.json");
+
+pub struct OpenApiHandler;
+
+impl Handler for OpenApiHandler {
+  fn handle(&self, _: &mut Request) -> IronResult<Response> {
+    let content_type = "application/json".parse::<Mime>().unwrap();
+    Ok(Response::with(
+      (content_type, status::Ok, OPENAPI)
+    ))
+  }
+}
+
+pub struct MetricsHandler;
+
+impl Handler for MetricsHandler {
+  fn handle(&self, req: &mut Request) -> IronResult<Response> {
+    let openmetrics = req.headers.get_raw("Accept")
+                                 .and_then(|h| str::from_utf8(&h[0]).ok())
+                                 .map(|accept| accept.contains("application/openmetrics-text"))
+                                 .unwrap_or(false);
+
+    // the exemplars are only understood by the scrapers asking for OpenMetrics
+    if openmetrics {
+      let content_type = "application/openmetrics-text; version=1.0.0; charset=utf-8".parse::<Mime>().unwrap();
+      Ok(Response::with(
+        (content_type, status::Ok, METRICS.render_openmetrics())
+      ))
+    }
+    else {
+      let content_type = "text/plain; version=0.0.4".parse::<Mime>().unwrap();
+      Ok(Response::with(
+        (content_type, status::Ok, METRICS.render())
+      ))
+    }
+  }
+}
+
+/// Take the request ID from `X-Request-Id` (generating it if missing or invalid),
+/// make it available to the logger and echo it in the response.
+struct RequestIdMiddleware;
+
+impl RequestIdMiddleware {
+  fn set_header(req: &Request, res: &mut Response) {
+    if let Some(id) = req.extensions.get::<RequestId>() {
+      res.headers.set_raw(REQUEST_ID_HEADER, vec![id.as_bytes().to_vec()]);
+    }
+  }
+}
+
+impl BeforeMiddleware for RequestIdMiddleware {
+  fn before(&self, req: &mut Request) -> IronResult<()> {
+    let id = req.headers.get_raw(REQUEST_ID_HEADER)
+                        .and_then(|h| String::from_utf8(h[0].to_owned()).ok())
+                        .and_then(|id| if request_id::is_valid(&id) { Some(id) } else { None })
+                        .unwrap_or_else(request_id::generate);
+
+    request_id::set(Some(id.to_owned()));
+    logger::clear_fields();
+    monitor::set_request(Some((req.method.to_string(), format!("/{}", req.url.path().join("/")))));
+    req.extensions.insert::<RequestId>(id);
+    Ok(())
+  }
+}
+
+impl AfterMiddleware for RequestIdMiddleware {
+  fn after(&self, req: &mut Request, mut res: Response) -> IronResult<Response> {
+    RequestIdMiddleware::set_header(req, &mut res);
+    Ok(res)
+  }
+
+  fn catch(&self, req: &mut Request, mut err: IronError) -> IronResult<Response> {
+    RequestIdMiddleware::set_header(req, &mut err.response);
+    Err(err)
+  }
+}
+
+struct TraceSpan;
+
+impl Key for TraceSpan { type Value = Span; }
+
+/// Trace every request as child of the span given as `traceparent`, if any,
+/// returning the context of its span through the same header.
+struct TracingMiddleware;
+
+impl TracingMiddleware {
+  fn end_span(req: &mut Request, res: &mut Response) {
+    if let Some(mut span) = req.extensions.remove::<TraceSpan>() {
+      let status = res.status.map(|status| status.to_u16()).unwrap_or(200);
+      span.attribute("http.status_code", &status.to_string());
+
+      if status >= 500 {
+        span.fail(&format!("The request has failed with {}.", status));
+      }
+
+      if let Some(context) = span.context() {
+        res.headers.set_raw(TRACEPARENT_HEADER, vec![context.header().into_bytes()]);
+      }
+    }
+  }
+}
+
+impl BeforeMiddleware for TracingMiddleware {
+  fn before(&self, req: &mut Request) -> IronResult<()> {
+    let traceparent = req.headers.get_raw(TRACEPARENT_HEADER)
+                                 .and_then(|h| String::from_utf8(h[0].to_owned()).ok());
+
+    // nothing is left over by the previous requests handled by this thread
+    tracing::set(None);
+
+    let path     = format!("/{}", req.url.path().join("/"));
+    let mut span = TRACER.trace(&format!("{} {}", req.method, path), traceparent.as_ref().map(|t| &t[..]));
+    span.attribute("http.method", &req.method.to_string());
+    span.attribute("http.target", &path);
+
+    if let Some(id) = req.extensions.get::<RequestId>() {
+      span.attribute("request_id", id);
+    }
+
+    req.extensions.insert::<TraceSpan>(span);
+    Ok(())
+  }
+}
+
+impl AfterMiddleware for TracingMiddleware {
+  fn after(&self, req: &mut Request, mut res: Response) -> IronResult<Response> {
+    TracingMiddleware::end_span(req, &mut res);
+    Ok(res)
+  }
+
+  fn catch(&self, req: &mut Request, mut err: IronError) -> IronResult<Response> {
+    TracingMiddleware::end_span(req, &mut err.response);
+    Err(err)
+  }
+}
+
+struct InFlight;
+
+impl Key for InFlight { type Value = (); }
+
+/// Keep track of the in-flight requests, refusing the new ones with `503`
+/// once the server has started to shut down.
+struct DrainMiddleware;
+
+impl DrainMiddleware {
+  fn end_request(req: &mut Request) {
+    if req.extensions.remove::<InFlight>().is_some() {
+      SHUTDOWN.end_request();
+    }
+  }
+}
+
+impl BeforeMiddleware for DrainMiddleware {
+  fn before(&self, req: &mut Request) -> IronResult<()> {
+    if !SHUTDOWN.begin_request() {
+      let error_message = "The server is shutting down.";
+      let response = ErrorResponse::new(ErrorCode::Unavailable, error_message, None);
+      return Err(IronError::new(ShutdownError(error_message.to_owned()), response.into_response()));
+    }
+
+    req.extensions.insert::<InFlight>(());
+    Ok(())
+  }
+}
+
+impl AfterMiddleware for DrainMiddleware {
+  fn after(&self, req: &mut Request, res: Response) -> IronResult<Response> {
+    DrainMiddleware::end_request(req);
+    Ok(res)
+  }
+
+  fn catch(&self, req: &mut Request, err: IronError) -> IronResult<Response> {
+    DrainMiddleware::end_request(req);
+    Err(err)
+  }
+}
+
+#[derive(Debug)]
+struct ShutdownError(String);
+
+impl fmt::Display for ShutdownError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{}", self.0)
+  }
+}
+
+impl StdError for ShutdownError {
+  fn description(&self) -> &str {
+    &*self.0
+  }
+}
+
+struct RequestStartedAt;
+
+impl Key for RequestStartedAt { type Value = Instant; }
+
+/// Count the requests and measure how long they take.
+struct MetricsMiddleware;
+
+impl MetricsMiddleware {
+  fn record(req: &mut Request, status: Option<status::Status>) {
+    let method = req.method.to_string();
+    let status = status.map(|s| s.to_u16().to_string()).unwrap_or("".to_owned());
+    METRICS.increment("searchspot_http_requests_total", &[("method", &*method), ("status", &*status)]);
+
+    if let Some(started_at) = req.extensions.get::<RequestStartedAt>() {
+      METRICS.observe_duration("searchspot_http_request_duration_seconds",
+                               &[("method", &*method)], started_at.elapsed());
+    }
+  }
+}
+
+impl BeforeMiddleware for MetricsMiddleware {
+  fn before(&self, req: &mut Request) -> IronResult<()> {
+    req.extensions.insert::<RequestStartedAt>(Instant::now());
+    Ok(())
+  }
+}
+
+impl AfterMiddleware for MetricsMiddleware {
+  fn after(&self, req: &mut Request, res: Response) -> IronResult<Response> {
+    MetricsMiddleware::record(req, res.status);
+    Ok(res)
+  }
+
+  fn catch(&self, req: &mut Request, err: IronError) -> IronResult<Response> {
+    MetricsMiddleware::record(req, err.response.status);
+    Err(err)
+  }
+}
+
+/// Detect the requested `ApiVersion`, storing it inside the request
+/// extensions and returning it in the `X-Api-Version` header.
+struct VersionMiddleware;
+
+impl BeforeMiddleware for VersionMiddleware {
+  fn before(&self, req: &mut Request) -> IronResult<()> {
+    let from_path = ApiVersion::from_path(&req.url.path());
+
+    let version = match from_path {
+      Some(version) => version,
+      None => {
+        let accept = req.headers.get_raw("Accept")
+                                .and_then(|h| String::from_utf8(h[0].to_owned()).ok())
+                                .unwrap_or("".to_owned());
+
+        match ApiVersion::from_accept(&accept) {
+          Ok(version) => version.unwrap_or(ApiVersion::default()),
+          Err(error_message) => {
+            let response = ErrorResponse::new(ErrorCode::ValidationError, &*error_message, None);
+            let mut response = response.into_response();
+            response.status = Some(status::NotAcceptable);
+            return Err(IronError::new(VersionError(error_message), response));
+          }
+        }
+      }
+    };
+
+    req.extensions.insert::<ApiVersion>(version);
+    Ok(())
+  }
+}
+
+impl AfterMiddleware for VersionMiddleware {
+  fn after(&self, req: &mut Request, mut res: Response) -> IronResult<Response> {
+    if let Some(version) = req.extensions.get::<ApiVersion>() {
+      res.headers.set_raw("X-Api-Version", vec![version.as_str().as_bytes().to_vec()]);
+    }
+
+    Ok(res)
+  }
+}
+
+#[derive(Debug)]
+struct VersionError(String);
+
+impl fmt::Display for VersionError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{}", self.0)
+  }
+}
+
+impl StdError for VersionError {
+  fn description(&self) -> &str {
+    &*self.0
+  }
+}
+
+/// Add the CORS headers to the responses and answer to the preflight requests.
+struct CorsMiddleware {
+  config: CORSConfig
+}
+
+impl CorsMiddleware {
+  fn set_headers(&self, req: &Request, res: &mut Response) {
+    let origin = req.headers.get_raw("Origin")
+                            .and_then(|h| String::from_utf8(h[0].to_owned()).ok());
+
+    match origin {
+      Some(ref origin) if self.config.allows_origin(origin) => {
+        if self.config.allowed_origins.iter().any(|o| o == "*") {
+          res.headers.set(headers::AccessControlAllowOrigin::Any);
+        }
+        else {
+          res.headers.set(headers::AccessControlAllowOrigin::Value(origin.to_owned()));
+          res.headers.set(headers::Vary::Items(vec![UniCase("origin".to_owned())]));
+        }
+      },
+      Some(_) => return,
+      None    => res.headers.set(headers::AccessControlAllowOrigin::Any)
+    };
+
+    res.headers.set(headers::AccessControlAllowMethods(
+      self.config.allowed_methods.iter()
+                                 .filter_map(|m| m.parse::<Method>().ok())
+                                 .collect()
+    ));
+
+    res.headers.set(headers::AccessControlAllowHeaders(
+      self.config.allowed_headers.iter()
+                                 .map(|h| UniCase(h.to_owned()))
+                                 .collect()
+    ));
+  }
+}
+
+impl AfterMiddleware for CorsMiddleware {
+  fn after(&self, req: &mut Request, mut res: Response) -> IronResult<Response> {
+    self.set_headers(req, &mut res);
+    Ok(res)
+  }
+
+  fn catch(&self, req: &mut Request, err: IronError) -> IronResult<Response> {
+    // preflight requests do not match any route
+    if req.method == Method::Options {
+      let mut res = Response::with(status::Ok);
+      self.set_headers(req, &mut res);
+      return Ok(res);
+    }
+
+    let mut err = err;
+    self.set_headers(req, &mut err.response);
+    Err(err)
+  }
+}
+
+/// Compress the responses larger than the configured threshold
+/// using the encoding negotiated through `Accept-Encoding`.
+struct CompressionMiddleware {
+  config: CompressionConfig
+}
+
+impl AfterMiddleware for CompressionMiddleware {
+  fn after(&self, req: &mut Request, mut res: Response) -> IronResult<Response> {
+    let encoding = req.headers.get_raw("Accept-Encoding")
+                              .and_then(|h| String::from_utf8(h[0].to_owned()).ok())
+                              .and_then(|h| Encoding::negotiate(&h));
+
+    let encoding = match encoding {
+      Some(encoding) => encoding,
+      None           => return Ok(res)
+    };
+
+    // the streams never end, hence they cannot be buffered
+    let is_stream = res.headers.get::<headers::ContentType>()
+                               .map(|content_type| content_type.to_string().starts_with("text/event-stream"))
+                               .unwrap_or(false);
+
+    if is_stream || res.headers.has::<headers::ContentEncoding>() {
+      return Ok(res);
+    }
+
+    let mut body = match res.body.take() {
+      Some(body) => body,
+      None       => return Ok(res)
+    };
+
+    let mut buffer = vec![];
+    body.write_body(&mut ResponseBody::new(&mut buffer)).unwrap();
+
+    if buffer.len() < self.config.threshold {
+      res.body = Some(Box::new(buffer));
+      return Ok(res);
+    }
+
+    let compressed = encoding.compress(&buffer).unwrap();
+
+    let mut vary = match res.headers.get::<headers::Vary>() {
+      Some(&headers::Vary::Items(ref items)) => items.to_owned(),
+      _                                      => vec![]
+    };
+    vary.push(UniCase("accept-encoding".to_owned()));
+
+    res.headers.set(headers::Vary::Items(vary));
+    res.headers.set_raw("Content-Encoding", vec![encoding.as_str().as_bytes().to_vec()]);
+    res.headers.set(headers::ContentLength(compressed.len() as u64));
+    res.body = Some(Box::new(compressed));
+
+    Ok(res)
+  }
+}
+
+impl<R: Resource> Server<R> {
+  /// The index configured for the resource (see `Config::index_of`)
+  /// replaces `es.index` for every handler.
+  pub fn new(mut config: Config, endpoint: &str) -> Self {
+    config.es.index = config.index_of(R::name());
+
+    let mut registry = Registry::new();
+    registry.insert(endpoint.trim_left_matches('/').to_owned(), Arc::new(ResourceSearch::<R>::new()));
+
+    Server {
+      config:      config,
+      config_path: None,
+      endpoint:    endpoint.to_owned(),
+      coalescer:   Arc::new(Coalescer::new()),
+      registry:    registry
+    }
+  }
+
+  /// Search the resource `S` as well through `GET /search?resources[]=<name>`,
+  /// on its `[resources.<name>]` index.
+  pub fn register<S: Resource>(mut self, name: &str) -> Self {
+    self.registry.insert(name.to_owned(), Arc::new(ResourceSearch::<S>::new()));
+    self
+  }
+
+  /// Reload the tunable settings from the configuration file at `path`
+  /// when SIGHUP is received (see `settings`).
+  pub fn with_config_path(mut self, path: &str) -> Self {
+    self.config_path = Some(path.to_owned());
+    self
+  }
+
+  /// Add the routes for the resource under given `prefix`.
+  /// Each route name starts with `name_prefix`, since they must be unique.
+  fn route_resource(&self, router: &mut Router, prefix: &str, name_prefix: &str, verifier: &Verifier) {
+    let ref hmac     = self.config.hmac;
+    let     endpoint = format!("{}{}", prefix, self.endpoint);
+    let     name     = |route: &str| format!("{}{}", name_prefix, route);
+
+    let coalescer = if self.config.coalescing.enabled { Some(self.coalescer.clone()) } else { None };
+
+    router.get(&*endpoint,    SearchableHandler::<R>::new(self.config.to_owned()), name("search"));
+    router.post(&*endpoint,   signed(IndexableHandler::<R>::new(self.config.to_owned(), coalescer.to_owned()), hmac, verifier), name("index"));
+    router.delete(&*endpoint, signed(ResettableHandler::<R>::new(self.config.to_owned()), hmac, verifier), name("reset"));
+
+    let msearch_endpoint = format!("{}/msearch", endpoint);
+    router.post(&*msearch_endpoint, MultiSearchableHandler::<R>::new(self.config.to_owned()), name("msearch"));
+
+    let preview_endpoint = format!("{}/search/preview", endpoint);
+    router.get(&*preview_endpoint, PreviewHandler::<R>::new(self.config.to_owned()), name("preview"));
+
+    let facets_endpoint = format!("{}/facets", endpoint);
+    router.get(&*facets_endpoint, FacetsHandler::<R>::new(self.config.to_owned()), name("facets"));
+
+    let export_endpoint = format!("{}/export.csv", endpoint);
+    router.get(&*export_endpoint, ExportHandler::<R>::new(self.config.to_owned()), name("export"));
+
+    let stream_endpoint = format!("{}/stream", endpoint);
+    router.get(&*stream_endpoint, StreamHandler::<R>::new(self.config.to_owned()), name("stream"));
+
+    let bulk_endpoint = format!("{}/bulk", endpoint);
+    router.post(&*bulk_endpoint, signed(BulkIndexableHandler::<R>::new(self.config.to_owned()), hmac, verifier), name("bulk"));
+
+    let mget_endpoint = format!("{}/mget", endpoint);
+    router.post(&*mget_endpoint, MultiGettableHandler::<R>::new(self.config.to_owned()), name("mget"));
+
+    let deletable_endpoint = format!("{}/:id", endpoint);
+    router.get(&*deletable_endpoint,    FetchableHandler::<R>::new(self.config.to_owned()), name("get"));
+    router.delete(&*deletable_endpoint, signed(DeletableHandler::<R>::new(self.config.to_owned(), coalescer.to_owned()), hmac, verifier), name("delete"));
+    router.patch(&*deletable_endpoint,  signed(UpdatableHandler::<R>::new(self.config.to_owned()), hmac, verifier), name("update"));
+  }
+
+  /// Compare the live mapping of the index with the one expected by the resource,
+  /// exiting with the list of problems if they are not compatible, since the searches
+  /// would return wrong results otherwise. An unreachable cluster is only reported.
+  fn check_mapping(&self, url: &str) {
+    let ref index = self.config.es.index;
+
+    match check_mapping(url, index, &R::mapping()) {
+      Ok(ref problems) if problems.is_empty() => info!("The mapping of `{}` is compatible.", index),
+      Ok(problems) => {
+        error!("The mapping of `{}` is not compatible with the one expected by `{}`:", index, R::name());
+        for problem in problems {
+          error!("  {}", problem);
+        }
+        error!("Set es.check_mapping to false to start anyway (i.e.: to fix it through POST /admin/indexes/{}/reindex).", index);
+        process::exit(1);
+      },
+      Err(err) => warn!("Cannot fetch the mapping of `{}`, it has not been checked: {}", index, err)
+    }
+  }
+
+  /// Serve the requests until SIGINT or SIGTERM is received,
+  /// then wait for the in-flight ones before returning.
+  /// SIGHUP reloads the tunable settings instead.
+  pub fn start(&self) {
+    // must be called before any thread is spawned
+    let signals = chan_signal::notify(&[Signal::INT, Signal::TERM, Signal::HUP]);
+
+    start_logging(&self.config).unwrap();
+    settings::apply(&self.config);
+    WEBHOOKS.start(&self.config.webhooks);
+    TRACER.start(&self.config.tracing);
+
+    if self.config.statsd.enabled {
+      match StatsD::new(&self.config.statsd) {
+        Ok(statsd) => METRICS.emit_to(statsd),
+        Err(err)   => error!("The metrics cannot be sent to StatsD at {}:{}: {}", self.config.statsd.host, self.config.statsd.port, err)
+      }
+    }
+
+    let host = format!("{}:{}", self.config.http.host, self.config.http.port);
+
+    info!("Searchspot v{}", env!("CARGO_PKG_VERSION"));
+    for line in self.config.to_string().lines() {
+      info!("{}", line);
+    }
+
+    let ref hmac = self.config.hmac;
+    let verifier = Verifier::new(&*hmac.secret, hmac.tolerance);
+
+    let mut router = Router::new();
+
+    // the unprefixed routes are kept for the existing consumers
+    self.route_resource(&mut router, "", "", &verifier);
+    for version in ApiVersion::all() {
+      self.route_resource(&mut router, &*version.prefix(), &format!("{}_", version.as_str()), &verifier);
+    }
+
+    router.post("/admin/indexes/:name/reset",   signed(AdminResetHandler::<R>::new(self.config.to_owned()),   hmac, &verifier), "admin_reset");
+    router.post("/admin/indexes/:name/reindex", signed(AdminReindexHandler::<R>::new(self.config.to_owned()), hmac, &verifier), "admin_reindex");
+    router.get("/admin/analytics/zero-results", signed(AdminZeroResultsHandler::<R>::new(self.config.to_owned()), hmac, &verifier), "admin_zero_results");
+    router.get("/admin/stats/companies",        signed(AdminCompanyStatsHandler::new(self.config.to_owned()),        hmac, &verifier), "admin_company_stats");
+
+    router.post("/saved-searches",            signed(SaveSearchHandler::<R>::new(self.config.to_owned()),      hmac, &verifier), "save_search");
+    router.get("/saved-searches",             ListSavedSearchesHandler::new(self.config.to_owned()),                          "saved_searches");
+    router.delete("/saved-searches/:id",      signed(DeleteSavedSearchHandler::new(self.config.to_owned()),    hmac, &verifier), "delete_saved_search");
+    router.get("/saved-searches/:id/results", RunSavedSearchHandler::<R>::new(self.config.to_owned()),                        "run_saved_search");
+
+    router.post("/batches",             signed(CreateBatchHandler::new(self.config.to_owned()),       hmac, &verifier), "create_batch");
+    router.get("/batches/:id",          BatchHandler::new(self.config.to_owned()),                                     "batch");
+    router.post("/batches/:id/talents", signed(AssignBatchHandler::<R>::new(self.config.to_owned()), hmac, &verifier), "assign_batch");
+    router.post("/batches/:id/close",   signed(CloseBatchHandler::<R>::new(self.config.to_owned()),  hmac, &verifier), "close_batch");
+
+    router.post("/feedback", signed(FeedbackHandler::<R>::new(self.config.to_owned()), hmac, &verifier), "feedback");
+    router.get("/search",    FederatedSearchHandler::new(self.config.to_owned(), Arc::new(self.registry.to_owned())), "federated_search");
+
+    // signed by the main application rather than with `hmac.secret`
+    let worker = Arc::new(Worker::<R>::start("webhook", &*self.config.es.index));
+    router.post(&*format!("/webhooks{}", self.endpoint), UpstreamWebhookHandler::<R>::new(self.config.to_owned(), worker.clone()), "upstream_webhook");
+
+    let erase = EraseHandler::<R>::new(self.config.to_owned(), self.coalescer.clone(), worker);
+    router.delete(&*format!("/gdpr{}/:id", self.endpoint), signed(erase, hmac, &verifier), "erase");
+
+    router.get("/healthz", HealthHandler::new(self.config.to_owned()),          "healthz");
+    router.get("/live",    LivenessHandler,                                      "live");
+    router.get("/ready",   ReadinessHandler::<R>::new(self.config.to_owned()),   "ready");
+    router.get("/metrics", MetricsHandler,                                       "metrics");
+    router.get("/openapi.json", OpenApiHandler,                                  "openapi");
+
+    cluster::bootstrap(&self.config);
+    CACHE.configure(&self.config.cache);
+    if let Some(ref redis_url) = self.config.cache.redis_url {
+      match RedisStore::open(redis_url, self.config.cache.redis_timeout()) {
+        Ok(store) => {
+          USAGE.connect(store.to_owned());
+          CACHE.connect(store);
+        },
+        Err(err)  => error!("The cache and the usage cannot be shared through Redis: {}", err)
+      }
+    }
+    QUEUE.configure(self.config.http.max_queued_documents);
+
+    let cluster = Arc::new(Cluster::new(&self.config.es.urls(), self.config.es.connections));
+    es::configure_compatibility(&self.config.es.compatibility, &cluster.url());
+    Cluster::watch(cluster.clone(), Duration::from_secs(CLUSTER_CHECK_INTERVAL), self.config.es.sniff_interval());
+
+    if self.config.es.check_mapping {
+      self.check_mapping(&cluster.url());
+    }
+
+    AUDIT.start(&self.config.audit, cluster.clone());
+    ANALYTICS.start(&self.config.analytics, cluster.clone());
+
+    if self.config.saved_searches.enabled {
+      if let Err(err) = saved_searches::create_index(&cluster.url(), &self.config.saved_searches.index) {
+        error!("The index of the saved searches {} cannot be created: {}", self.config.saved_searches.index, err);
+      }
+    }
+    if self.config.batches.enabled {
+      if let Err(err) = batches::create_index(&cluster.url(), &self.config.batches.index) {
+        error!("The index of the batches {} cannot be created: {}", self.config.batches.index, err);
+      }
+    }
+    alerts::start::<R>(&self.config, cluster.clone());
+    backup::start::<R>(&self.config.backup, cluster.clone(), &self.config.index_of(R::name()));
+
+    if self.config.coalescing.enabled {
+      let window    = Duration::from_millis(self.config.coalescing.window_ms);
+      let coalescer = self.coalescer.clone();
+      let flushed   = cluster.clone();
+      let index     = self.config.es.index.to_owned();
+
+      thread::spawn(move || loop {
+        thread::sleep(window);
+        flush(&coalescer, &flushed, &index);
+      });
+
+      // the documents still pending are indexed before exiting
+      let coalescer = self.coalescer.clone();
+      let flushed   = cluster.clone();
+      let index     = self.config.es.index.to_owned();
+      SHUTDOWN.on_drain(Box::new(move || flush(&coalescer, &flushed, &index)));
+    }
+
+    // systemd is told that the searches can be served once ElasticSearch is available and mapped as expected
+    if systemd::enabled() {
+      let checked = cluster.clone();
+      let index   = self.config.es.index.to_owned();
+
+      thread::spawn(move || loop {
+        let readiness = Readiness::check(&checked.url(), &index, &R::mapping());
+        if readiness.ready {
+          systemd::ready();
+          break;
+        }
+
+        systemd::status(&format!("Waiting for ElasticSearch: {}", readiness.problems.join(" ")));
+        thread::sleep(Duration::from_secs(CLUSTER_CHECK_INTERVAL));
+      });
+    }
+
+    let mut chain = Chain::new(router);
+    chain.link_before(DrainMiddleware);
+    chain.link_before(RequestIdMiddleware);
+    chain.link_before(TracingMiddleware);
+    chain.link(Read::<SharedCluster>::both(cluster));
+    chain.link(HTTPLogger::new(None));
+    chain.link_before(MetricsMiddleware);
+    chain.link_before(VersionMiddleware);
+    chain.link_after(VersionMiddleware);
+    chain.link_after(MetricsMiddleware);
+    chain.link_after(TracingMiddleware);
+    chain.link_after(RequestIdMiddleware);
+    chain.link_after(DrainMiddleware);
+    chain.link_after(CorsMiddleware { config: self.config.cors.to_owned() });
+
+    if self.config.compression.enabled {
+      chain.link_after(CompressionMiddleware { config: self.config.compression.to_owned() });
+    }
+
+    let mut iron = Iron::new(chain);
+    iron.threads = self.config.http.workers;
+
+    let ref tls = self.config.tls;
+    let mut listening = if let Some(ref socket) = self.config.http.socket {
+      let listener = UnixSocketListener::bind(socket)
+                                        .unwrap_or_else(|err| panic!("Error while binding {}: {}", socket, err));
+      iron.listen(listener, Protocol::http()).unwrap()
+    }
+    else if tls.enabled {
+      let ssl = client_cert::server(tls).unwrap_or_else(|err| panic!("Error while loading the TLS certificate: {}", err));
+      iron.https(&*host, ssl).unwrap()
+    }
+    else {
+      iron.http(&*host).unwrap()
+    };
+
+    // the watchdog of systemd restarts the service once `GET /live` is no longer answered
+    let interval = systemd::watchdog_interval().unwrap_or(Duration::from_secs(WATCHDOG_IDLE_INTERVAL));
+    let watchdog = chan::tick(interval);
+    systemd::pet();
+
+    loop {
+      chan_select! {
+        watchdog.recv() => {
+          match loopback::check(&self.config, interval / 2) {
+            Ok(_)    => { systemd::pet(); },
+            Err(err) => {
+              error!("The watchdog has not been petted, the liveness check has failed: {}", err);
+              METRICS.increment("searchspot_liveness_failures_total", &[]);
+            }
+          }
+        },
+        signals.recv() -> signal => {
+          let signal = match signal {
+            Some(signal) => signal,
+            None         => break
+          };
+
+          if signal != Signal::HUP {
+            info!("Received {:?}, draining the in-flight requests...", signal);
+            systemd::stopping();
+            break;
+          }
+
+          match self.config_path {
+            Some(ref path) => match settings::reload(path) {
+              Ok(_)    => info!("Received HUP, the settings have been reloaded from {}.", path),
+              Err(err) => error!("Received HUP, but the settings have not been reloaded: {}", err)
+            },
+            None => warn!("Received HUP, but there is no configuration file to reload.")
+          }
+        }
+      }
+    }
+
+    if !SHUTDOWN.drain(Duration::from_secs(self.config.http.drain_timeout)) {
+      error!("{} requests were still in flight after {}s.", SHUTDOWN.in_flight(), self.config.http.drain_timeout);
+    }
+
+    let _ = listening.close();
+
+    // dropping `Listening` would wait for the acceptor thread, which never ends
+    mem::forget(listening);
   }
 }
 
 #[cfg(test)]
 mod tests {
-  use resource::Resource;
+  use resource::{Resource, Paginated, Pagination, TimedOut, SearchOptions, Facets};
+  use resources::Talent;
+  use server::{pagination_links, page_size, SharedCluster, SearchableHandler, IndexableHandler,
+               AdminCompanyStatsHandler, ResettableHandler};
+  use schema::{Param, FieldError};
+  use config::{Config, Cache, Quotas, ResourceSettings};
+  use cluster::Cluster;
+  use cache::CACHE;
+  use usage::{USAGE, CompanyStats};
+  use test_support::{TestIndex, talent};
+  use jwt;
+  use jwt::Claims;
+  use error::Error;
+
+  use iron::{status, Chain, Handler, Headers, Response};
+  use iron_test::{request, response};
+  use persistent::Read;
+
+  use chrono::{Duration, UTC};
+
+  use serde_json;
+  use serde_json::Value as JsonValue;
 
   use params::*;
 
   use rs_es::Client;
   use rs_es::operations::bulk::{BulkResult, Action};
+  use rs_es::operations::common::VersionType;
   use rs_es::operations::delete::DeleteResult;
-  use rs_es::operations::mapping::{MappingOperation, MappingResult};
-  use rs_es::error::EsError;
+  use rs_es::operations::index::IndexResult;
+  use rs_es::operations::mapping::{Mapping, MappingOperation, MappingResult};
+
+  const CONFIG_FILE: &'static str = "examples/tests.toml";
+
+  impl Paginated for Vec<u32> {}
+  impl TimedOut for Vec<u32> {}
 
   #[derive(Serialize, Deserialize, Clone, Debug)]
   pub struct TestResource {
@@ -326,11 +3660,23 @@ mod tests {
   impl Resource for TestResource {
     type Results = Vec<u32>;
 
-    fn search(_: &mut Client, _: &str, _: &Map) -> Self::Results {
-      vec![]
+    fn name() -> &'static str {
+      ES_TYPE
+    }
+
+    fn preview(_: &str, _: &Map, _: &SearchOptions) -> Result<JsonValue, Error> {
+      Ok(JsonValue::Null)
+    }
+
+    fn results(_: &Map, _: &SearchOptions, _: JsonValue) -> Result<Self::Results, Error> {
+      Ok(vec![])
+    }
+
+    fn facets(_: &mut Client, _: &str, _: &Map, _: &SearchOptions) -> Result<Facets, Error> {
+      Ok(Facets::new())
     }
 
-    fn index(mut es: &mut Client, index: &str, resources: Vec<Self>) -> Result<BulkResult, EsError> {
+    fn index(mut es: &mut Client, index: &str, resources: Vec<Self>) -> Result<BulkResult, Error> {
       es.bulk(&resources.into_iter()
                         .map(|r| {
                             let id = r.id.to_string();
@@ -340,15 +3686,203 @@ mod tests {
         .with_index(index)
         .with_doc_type(ES_TYPE)
         .send()
+        .map_err(Error::from)
+    }
+
+    fn index_versioned(es: &mut Client, index: &str, resources: Vec<(Self, u64)>) -> Result<BulkResult, Error> {
+      TestResource::index(es, index, resources.into_iter().map(|(r, _)| r).collect())
+    }
+
+    fn get(mut es: &mut Client, id: &str, index: &str) -> Result<Option<Self>, Error> {
+      es.get(index, id)
+        .with_doc_type(ES_TYPE)
+        .send::<TestResource>()
+        .map(|result| result.source)
+        .map_err(Error::from)
+    }
+
+    fn update(mut es: &mut Client, id: &str, index: &str, resource: Self) -> Result<IndexResult, Error> {
+      es.index(index, ES_TYPE)
+        .with_id(id)
+        .with_doc(&resource)
+        .send()
+        .map_err(Error::from)
     }
 
-    fn delete(mut es: &mut Client, id: &str, index: &str) -> Result<DeleteResult, EsError> {
+    fn delete(mut es: &mut Client, id: &str, index: &str) -> Result<DeleteResult, Error> {
       es.delete(index, ES_TYPE, id)
         .send()
+        .map_err(Error::from)
+    }
+
+    fn delete_versioned(mut es: &mut Client, id: &str, index: &str, version: u64) -> Result<BulkResult, Error> {
+      es.bulk(&[Action::<TestResource>::delete(id.to_owned()).with_version(version).with_version_type(VersionType::ExternalGte)])
+        .with_index(index)
+        .with_doc_type(ES_TYPE)
+        .send()
+        .map_err(Error::from)
+    }
+
+    fn id(&self) -> String {
+      self.id.to_string()
+    }
+
+    fn is_visible(&self) -> bool {
+      true
+    }
+
+    fn validate(&self) -> Vec<FieldError> {
+      vec![]
+    }
+
+    fn conflicts(&self, _: &Self) -> Vec<String> {
+      vec![]
+    }
+
+    fn percolate(&self, _: &Map) -> bool {
+      true
+    }
+
+    fn search_params() -> Vec<Param> {
+      vec![]
+    }
+
+    fn mapping() -> Mapping<'static> {
+      hashmap! {
+        ES_TYPE => hashmap! {
+          "id" => hashmap! {
+            "type" => "integer"
+          }
+        }
+      }
+    }
+
+    fn index_settings(_: &ResourceSettings) -> JsonValue {
+      JsonValue::Object(Default::default())
+    }
+
+    fn reset_index(mut es: &mut Client, index: &str, _: &ResourceSettings) -> Result<MappingResult, Error> {
+      MappingOperation::new(&mut es, index).send().map_err(Error::from)
+    }
+  }
+
+  #[test]
+  fn test_page_size() {
+    let mut params = Map::new();
+    assert_eq!(page_size(&params), None);
+
+    params.assign("per_page", Value::String("100000".into())).unwrap();
+    assert_eq!(page_size(&params), Some(100000));
+  }
+
+  #[test]
+  fn test_pagination_links() {
+    let pagination = Pagination::new(10, 10, 35);
+    assert_eq!(pagination_links("/talents", Some("keywords=rust&offset=10&per_page=10"), &pagination),
+      "</talents?keywords=rust&per_page=10&offset=20>; rel=\"next\", \
+       </talents?keywords=rust&per_page=10&offset=0>; rel=\"prev\", \
+       </talents?keywords=rust&per_page=10&offset=30>; rel=\"last\"");
+
+    let pagination = Pagination::new(0, 10, 5);
+    assert_eq!(pagination_links("/talents", None, &pagination),
+      "</talents?offset=0>; rel=\"last\"");
+  }
+
+  /// Send `GET url` to `handler`, reaching the cluster of `config` as the server does.
+  fn get<H: Handler>(config: &Config, handler: H, url: &str) -> Response {
+    let mut chain = Chain::new(handler);
+    chain.link(Read::<SharedCluster>::both(Cluster::new(&config.es.urls(), 1)));
+    request::get(&format!("http://localhost:3000{}", url), Headers::new(), &chain).unwrap()
+  }
+
+  #[test]
+  fn test_search_quotas() {
+    let mut config = Config::from_file(CONFIG_FILE.to_owned());
+    let mut client = Client::new(&config.es.urls()[0]).unwrap();
+    let index      = TestIndex::create::<Talent>(&mut client, &config.es.urls()[0], "talents", &ResourceSettings::default()).unwrap();
+
+    config.auth.enabled = false;
+    config.es.index     = index.name.to_owned();
+    config.quotas       = Quotas { enabled: true, daily: 2, ..Default::default() };
+    CACHE.configure(&Cache { size: 10, ..Default::default() });
+
+    let search = |url: &str| get(&config, SearchableHandler::<Talent>::new(config.to_owned()), url).status;
+
+    // the search answered from the cache counts towards the quota as well
+    assert_eq!(search("/talents?company_id=1248"), Some(status::Ok));
+    assert_eq!(search("/talents?company_id=1248"), Some(status::Ok));
+    assert_eq!(search("/talents?company_id=1248"), Some(status::TooManyRequests));
+    assert_eq!(USAGE.since("1248", UTC::now() - Duration::days(1), UTC::now()), 2);
+
+    // the searches that are not sent for a company share no quota
+    for _ in 0..3 {
+      assert_eq!(search("/talents"), Some(status::Ok));
     }
+    assert_eq!(search("/talents?company_id=1249"), Some(status::Ok));
+  }
 
-    fn reset_index(mut es: &mut Client, index: &str) -> Result<MappingResult, EsError> {
-      MappingOperation::new(&mut es, index).send()
+  #[test]
+  fn test_company_stats() {
+    let mut config = Config::from_file(CONFIG_FILE.to_owned());
+    let mut client = Client::new(&config.es.urls()[0]).unwrap();
+    let index      = TestIndex::create::<Talent>(&mut client, &config.es.urls()[0], "talents", &ResourceSettings::default()).unwrap();
+
+    config.auth.enabled = false;
+    config.es.index     = index.name.to_owned();
+    CACHE.configure(&Cache { size: 10, ..Default::default() });
+
+    // the second search is answered from the cache
+    for _ in 0..2 {
+      get(&config, SearchableHandler::<Talent>::new(config.to_owned()), "/talents?company_id=1204");
     }
+    get(&config, SearchableHandler::<Talent>::new(config.to_owned()), "/talents");
+
+    let res   = get(&config, AdminCompanyStatsHandler::new(config.to_owned()), "/admin/stats/companies");
+    let stats = serde_json::from_str::<CompanyStats>(&response::extract_body_to_string(res)).unwrap();
+
+    let company = stats.companies.iter().find(|company| company.company_id == "1204").unwrap();
+    assert_eq!(company.total, 2);
+    assert!(stats.companies.iter().all(|company| company.company_id != "anonymous"));
+  }
+
+  #[test]
+  fn test_index_unavailable() {
+    let mut config = Config::from_file(CONFIG_FILE.to_owned());
+    config.auth.enabled = false;
+    config.es.url       = "http://localhost:1".to_owned();
+
+    // ElasticSearch being unreachable is not the fault of the documents
+    let mut chain = Chain::new(IndexableHandler::<Talent>::new(config.to_owned(), None));
+    chain.link(Read::<SharedCluster>::both(Cluster::new(&config.es.urls(), 1)));
+    let body = serde_json::to_string(&vec![talent(1)]).unwrap();
+    let res  = request::post("http://localhost:3000/talents", Headers::new(), &body, &chain).unwrap();
+    assert_eq!(res.status, Some(status::BadGateway));
+  }
+
+  #[test]
+  fn test_reset_scope() {
+    let mut config = Config::from_file(CONFIG_FILE.to_owned());
+    config.jwt.enabled = true;
+    config.jwt.secret  = "secret".to_owned();
+
+    let reset = |scope: Option<&str>| {
+      let mut headers = Headers::new();
+      if let Some(scope) = scope {
+        let claims = Claims { sub: None, exp: None, scope: scope.to_owned(), company_id: None };
+        headers.set_raw("Authorization", vec![format!("Bearer {}", jwt::encode(&claims, "secret")).into_bytes()]);
+      }
+      request::delete("http://localhost:3000/talents", headers, &ResettableHandler::<Talent>::new(config.to_owned())).unwrap().status
+    };
+
+    // wiping the index takes the scope of the admin endpoints
+    assert_eq!(reset(Some("search write")), Some(status::Forbidden));
+    assert_eq!(reset(None), Some(status::Unauthorized));
+  }
+
+  #[test]
+  fn test_openapi() {
+    let spec: JsonValue = serde_json::from_str(super::OPENAPI).unwrap();
+    assert_eq!(spec.find("openapi").and_then(|v| v.as_str()), Some("3.0.0"));
+    assert!(spec.find("paths").and_then(|p| p.find("/talents")).is_some());
   }
 }
@@ -0,0 +1,80 @@
+//! The settings that can be tuned while the server is running, by
+//! editing the configuration file and sending `SIGHUP` to the process.
+//! Only `[search]`, `[features]`, `[scoring]`, `[ltr]` and `[log]` are reloaded:
+//! the other sections still require a restart.
+
+use config::{Config, Features, SearchSettings, Scoring, Ltr};
+use error::Error;
+use scoring::{self, WeightedScorer};
+use logger;
+
+use std::sync::{Arc, RwLock};
+
+/// A value that is replaced atomically as a whole: the readers keep
+/// using the copy they have loaded until they load it again.
+pub struct Live<T> {
+  current: RwLock<Arc<T>>
+}
+
+impl<T> Live<T> {
+  pub fn new(value: T) -> Live<T> {
+    Live {
+      current: RwLock::new(Arc::new(value))
+    }
+  }
+
+  pub fn load(&self) -> Arc<T> {
+    self.current.read().unwrap().clone()
+  }
+
+  pub fn store(&self, value: T) {
+    *self.current.write().unwrap() = Arc::new(value);
+  }
+}
+
+lazy_static! {
+  /// The search settings currently in use.
+  pub static ref SEARCH: Live<SearchSettings> = Live::new(SearchSettings::default());
+
+  /// The feature flags currently in use.
+  pub static ref FEATURES: Live<Features> = Live::new(Features::default());
+
+  /// The scoring of the talents currently in use.
+  pub static ref SCORING: Live<Scoring> = Live::new(Scoring::default());
+
+  /// The rescoring by the learning to rank models currently in use.
+  pub static ref LTR: Live<Ltr> = Live::new(Ltr::default());
+}
+
+/// Start using the tunable settings of `config`.
+pub fn apply(config: &Config) {
+  SEARCH.store(config.search.to_owned());
+  FEATURES.store(config.features.to_owned());
+  SCORING.store(config.scoring.to_owned());
+  LTR.store(config.ltr.to_owned());
+  scoring::install(Box::new(WeightedScorer::new(&config.scoring)));
+  logger::set_level(config.log.level());
+}
+
+/// Read the configuration file at `path` again and apply its tunable
+/// settings. The current ones are kept if the file is not valid.
+pub fn reload(path: &str) -> Result<(), Error> {
+  let config = try!(Config::load(path));
+  apply(&config);
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use settings::Live;
+
+  #[test]
+  fn test_live() {
+    let live   = Live::new(1);
+    let loaded = live.load();
+
+    live.store(2);
+    assert_eq!(*loaded, 1);
+    assert_eq!(*live.load(), 2);
+  }
+}
@@ -0,0 +1,107 @@
+//! The state used to shut the server down gracefully: once draining,
+//! new requests are refused while the in-flight ones are completed.
+
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+lazy_static! {
+  /// The shutdown state shared across the handlers.
+  pub static ref SHUTDOWN: Shutdown = Shutdown::new();
+}
+
+pub struct Shutdown {
+  in_flight: AtomicUsize,
+  draining:  AtomicBool,
+  hooks:     Mutex<Vec<Box<Fn() + Send>>>
+}
+
+impl Shutdown {
+  pub fn new() -> Shutdown {
+    Shutdown {
+      in_flight: AtomicUsize::new(0),
+      draining:  AtomicBool::new(false),
+      hooks:     Mutex::new(vec![])
+    }
+  }
+
+  /// Track a new request, returning `false` if it must be refused.
+  pub fn begin_request(&self) -> bool {
+    if self.is_draining() {
+      return false;
+    }
+
+    self.in_flight.fetch_add(1, Ordering::SeqCst);
+    true
+  }
+
+  /// Stop tracking a request accepted by `begin_request`.
+  pub fn end_request(&self) {
+    self.in_flight.fetch_sub(1, Ordering::SeqCst);
+  }
+
+  pub fn in_flight(&self) -> usize {
+    self.in_flight.load(Ordering::SeqCst)
+  }
+
+  pub fn is_draining(&self) -> bool {
+    self.draining.load(Ordering::SeqCst)
+  }
+
+  /// Register a function to be run once the in-flight requests
+  /// have been completed (i.e.: to flush the buffered writes).
+  pub fn on_drain(&self, hook: Box<Fn() + Send>) {
+    self.hooks.lock().unwrap().push(hook);
+  }
+
+  /// Refuse the new requests and wait up to `timeout` for the in-flight ones,
+  /// then run the hooks. Return `false` if some requests were still in flight.
+  pub fn drain(&self, timeout: Duration) -> bool {
+    self.draining.store(true, Ordering::SeqCst);
+
+    let started_at = Instant::now();
+    while self.in_flight() > 0 && started_at.elapsed() < timeout {
+      thread::sleep(Duration::from_millis(50));
+    }
+
+    let drained = self.in_flight() == 0;
+
+    for hook in self.hooks.lock().unwrap().iter() {
+      hook();
+    }
+
+    drained
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use shutdown::Shutdown;
+
+  use std::sync::Arc;
+  use std::sync::atomic::{AtomicBool, Ordering};
+  use std::time::Duration;
+
+  #[test]
+  fn test_drain() {
+    let shutdown = Shutdown::new();
+    let flushed  = Arc::new(AtomicBool::new(false));
+
+    let flag = flushed.clone();
+    shutdown.on_drain(Box::new(move || flag.store(true, Ordering::SeqCst)));
+
+    assert!(shutdown.begin_request());
+    assert_eq!(shutdown.in_flight(), 1);
+
+    // a request is still in flight
+    assert!(!shutdown.drain(Duration::from_millis(10)));
+    assert!(flushed.load(Ordering::SeqCst));
+
+    // new requests are refused
+    assert!(!shutdown.begin_request());
+
+    shutdown.end_request();
+    assert!(shutdown.drain(Duration::from_millis(10)));
+  }
+}
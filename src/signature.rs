@@ -0,0 +1,247 @@
+//! Sign and verify the write requests using HMAC-SHA256.
+//!
+//! The signature is computed on the string `"{timestamp}\n{method}\n{path}\n{body}"`,
+//! where `timestamp` is the UNIX time in seconds at which the request has been
+//! sent, and it is expected to be found hex-encoded inside the
+//! `X-Searchspot-Signature` header along with `X-Searchspot-Timestamp`.
+//!
+//! The events pushed by the main application carry their own signature instead,
+//! inside `X-Honeypot-Signature` as `t={timestamp},v1={signature}`, computed on
+//! the string `"{timestamp}.{body}"` (see `verify_upstream`), and are accepted
+//! only once as well (see `UpstreamVerifier`).
+
+use crypto::hmac::Hmac;
+use crypto::sha2::Sha256;
+use crypto::mac::Mac;
+use crypto::util::fixed_time_eq;
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+pub const SIGNATURE_HEADER: &'static str = "X-Searchspot-Signature";
+pub const TIMESTAMP_HEADER: &'static str = "X-Searchspot-Timestamp";
+pub const UPSTREAM_SIGNATURE_HEADER: &'static str = "X-Honeypot-Signature";
+
+/// Return the hex-encoded signature for given request.
+pub fn sign<B: AsRef<[u8]>>(secret: &str, timestamp: i64, method: &str, path: &str, body: B) -> String {
+  let mut hmac = Hmac::new(Sha256::new(), secret.as_bytes());
+  hmac.input(format!("{}\n{}\n{}\n", timestamp, method, path).as_bytes());
+  hmac.input(body.as_ref());
+
+  hex(hmac.result().code())
+}
+
+/// Return the hex-encoded signature of an event pushed by the main application at `timestamp`.
+pub fn sign_upstream<B: AsRef<[u8]>>(secret: &str, timestamp: i64, body: B) -> String {
+  let mut hmac = Hmac::new(Sha256::new(), secret.as_bytes());
+  hmac.input(format!("{}.", timestamp).as_bytes());
+  hmac.input(body.as_ref());
+
+  hex(hmac.result().code())
+}
+
+/// Verify the `X-Honeypot-Signature` header of an event pushed by the main application,
+/// rejecting it if its timestamp is more than `tolerance` seconds away from `now`.
+pub fn verify_upstream<B: AsRef<[u8]>>(secret: &str, header: &str, now: i64, tolerance: i64, body: B) -> Result<(), String> {
+  let (timestamp, signature) = try!(parse_upstream(header));
+
+  if (now - timestamp).abs() > tolerance {
+    return Err("The event timestamp is outside the allowed window.".to_owned());
+  }
+
+  if !fixed_time_eq(sign_upstream(secret, timestamp, body).as_bytes(), signature.as_bytes()) {
+    return Err("The event signature is not valid.".to_owned());
+  }
+
+  Ok(())
+}
+
+/// Return the timestamp and the lower-cased signature given by the `X-Honeypot-Signature` header.
+fn parse_upstream(header: &str) -> Result<(i64, String), String> {
+  let mut timestamp = None;
+  let mut signature = None;
+
+  for part in header.split(',') {
+    let mut pair = part.trim().splitn(2, '=');
+    match (pair.next(), pair.next()) {
+      (Some("t"),  Some(value)) => timestamp = value.parse::<i64>().ok(),
+      (Some("v1"), Some(value)) => signature = Some(value.to_lowercase()),
+      _                         => ()
+    }
+  }
+
+  match (timestamp, signature) {
+    (Some(timestamp), Some(signature)) => Ok((timestamp, signature)),
+    _ => Err(format!("{} must be given as t=<timestamp>,v1=<signature>.", UPSTREAM_SIGNATURE_HEADER))
+  }
+}
+
+fn hex(bytes: &[u8]) -> String {
+  bytes.iter()
+       .map(|byte| format!("{:02x}", byte))
+       .collect::<Vec<String>>()
+       .join("")
+}
+
+/// Verify the signatures, rejecting the ones whose timestamp is too far from
+/// `now` and the ones that have already been seen (replay attacks).
+#[derive(Clone)]
+pub struct Verifier {
+  secret:    String,
+  tolerance: i64,
+  seen:      Arc<Mutex<HashMap<String, i64>>>
+}
+
+impl Verifier {
+  pub fn new(secret: &str, tolerance: i64) -> Verifier {
+    Verifier {
+      secret:    secret.to_owned(),
+      tolerance: tolerance,
+      seen:      Arc::new(Mutex::new(HashMap::new()))
+    }
+  }
+
+  pub fn verify<B: AsRef<[u8]>>(&self, signature: &str, timestamp: i64, now: i64,
+                                method: &str, path: &str, body: B) -> Result<(), String> {
+    if (now - timestamp).abs() > self.tolerance {
+      return Err("The request timestamp is outside the allowed window.".to_owned());
+    }
+
+    let expected  = sign(&self.secret, timestamp, method, path, body);
+    let signature = signature.to_lowercase();
+
+    if !fixed_time_eq(expected.as_bytes(), signature.as_bytes()) {
+      return Err("The request signature is not valid.".to_owned());
+    }
+
+    if !remember(&self.seen, signature, timestamp, now, self.tolerance) {
+      return Err("The request has already been received.".to_owned());
+    }
+
+    Ok(())
+  }
+}
+
+/// Verify the signatures of the events pushed by the main application (see `verify_upstream`),
+/// rejecting as well the ones that have already been seen, so that a captured event (i.e. a
+/// deletion) cannot be sent again while its timestamp is still within the window.
+#[derive(Clone)]
+pub struct UpstreamVerifier {
+  secret:    String,
+  tolerance: i64,
+  seen:      Arc<Mutex<HashMap<String, i64>>>
+}
+
+impl UpstreamVerifier {
+  pub fn new(secret: &str, tolerance: i64) -> UpstreamVerifier {
+    UpstreamVerifier {
+      secret:    secret.to_owned(),
+      tolerance: tolerance,
+      seen:      Arc::new(Mutex::new(HashMap::new()))
+    }
+  }
+
+  pub fn verify<B: AsRef<[u8]>>(&self, header: &str, now: i64, body: B) -> Result<(), String> {
+    try!(verify_upstream(&self.secret, header, now, self.tolerance, body));
+
+    let (timestamp, signature) = try!(parse_upstream(header));
+    if !remember(&self.seen, signature, timestamp, now, self.tolerance) {
+      return Err("The event has already been received.".to_owned());
+    }
+
+    Ok(())
+  }
+}
+
+/// Record `signature`, sent at `timestamp`, among the `seen` ones, returning
+/// whether it was not seen before. The ones outside the window are forgotten.
+fn remember(seen: &Mutex<HashMap<String, i64>>, signature: String, timestamp: i64, now: i64, tolerance: i64) -> bool {
+  let mut seen = seen.lock().unwrap();
+
+  // signatures older than the window would be rejected anyway
+  seen.retain(|_, seen_at| (now - *seen_at).abs() <= tolerance);
+
+  if seen.contains_key(&signature) {
+    return false;
+  }
+
+  seen.insert(signature, timestamp);
+  true
+}
+
+#[cfg(test)]
+mod tests {
+  use signature::*;
+
+  #[test]
+  fn test_sign() {
+    let signature = sign("secret", 1483228800, "POST", "/talents", "[]");
+    assert_eq!(signature.len(), 64);
+    assert_eq!(signature, sign("secret", 1483228800, "POST", "/talents", "[]"));
+    assert!(signature != sign("secret", 1483228800, "POST", "/talents", "[{}]"));
+    assert!(signature != sign("terces", 1483228800, "POST", "/talents", "[]"));
+  }
+
+  #[test]
+  fn test_verify() {
+    let verifier = Verifier::new("secret", 300);
+    let now      = 1483228800;
+
+    // a valid signature is accepted only once
+    {
+      let signature = sign("secret", now, "DELETE", "/talents/1", "");
+      assert!(verifier.verify(&signature, now, now, "DELETE", "/talents/1", "").is_ok());
+      assert!(verifier.verify(&signature, now, now, "DELETE", "/talents/1", "").is_err());
+    }
+
+    // an upper-cased signature is accepted
+    {
+      let signature = sign("secret", now, "DELETE", "/talents/2", "").to_uppercase();
+      assert!(verifier.verify(&signature, now, now + 10, "DELETE", "/talents/2", "").is_ok());
+    }
+
+    // a signature for a different request is rejected
+    {
+      let signature = sign("secret", now, "DELETE", "/talents/3", "");
+      assert!(verifier.verify(&signature, now, now, "DELETE", "/talents/4", "").is_err());
+    }
+
+    // an expired timestamp is rejected
+    {
+      let signature = sign("secret", now - 301, "DELETE", "/talents/5", "");
+      assert!(verifier.verify(&signature, now - 301, now, "DELETE", "/talents/5", "").is_err());
+    }
+  }
+
+  #[test]
+  fn test_verify_upstream() {
+    let now    = 1483228800;
+    let body   = "{\"event\": \"talent.deleted\", \"data\": {\"id\": 1}}";
+    let header = format!("t={},v1={}", now, sign_upstream("secret", now, body));
+
+    assert!(verify_upstream("secret", &header, now + 10, 300, body).is_ok());
+    assert!(verify_upstream("secret", &format!("t={}, v1={}", now, sign_upstream("secret", now, body).to_uppercase()), now, 300, body).is_ok());
+    assert!(verify_upstream("terces", &header, now, 300, body).is_err());
+    assert!(verify_upstream("secret", &header, now, 300, "{}").is_err());
+    assert!(verify_upstream("secret", &header, now + 301, 300, body).is_err());
+    assert!(verify_upstream("secret", &sign_upstream("secret", now, body), now, 300, body).is_err());
+  }
+
+  #[test]
+  fn test_upstream_verifier() {
+    let verifier = UpstreamVerifier::new("secret", 300);
+    let now      = 1483228800;
+    let body     = "{\"event\": \"talent.deleted\", \"data\": {\"id\": 1}}";
+    let header   = format!("t={},v1={}", now, sign_upstream("secret", now, body));
+
+    // a valid event is accepted only once
+    assert!(verifier.verify(&header, now, body).is_ok());
+    assert_eq!(verifier.verify(&header, now + 10, body), Err("The event has already been received.".to_owned()));
+
+    // the same event signed again later is a new one
+    let later = format!("t={},v1={}", now + 1, sign_upstream("secret", now + 1, body));
+    assert!(verifier.verify(&later, now + 10, body).is_ok());
+
+    assert!(verifier.verify(&header, now, "{}").is_err());
+  }
+}
@@ -0,0 +1,223 @@
+//! The tailing of the slow searches logged by the server (see `search.slow_ms`),
+//! run by `searchspot-admin slowlog --threshold 500ms`, which reads the logs
+//! (either as text or as JSON lines) from a file it follows or from the standard
+//! input (i.e.: `journalctl -fu searchspot | searchspot-admin slowlog`) and
+//! prints one line per slow search, i.e.:
+//!
+//! `2017-03-04T12:24:00+00:00  1200ms (es 1100ms)  [2f4b1c1e]  keywords=rust work_locations=Berlin,Rome`
+
+use serde_json;
+use serde_json::Value as JsonValue;
+
+use std::fs::File;
+use std::io;
+use std::io::{BufRead, BufReader, Seek, SeekFrom, Write};
+use std::thread;
+use std::time::Duration;
+
+/// The message the slow searches are logged with.
+const MARKER: &'static str = "Slow search: ";
+
+/// How long to wait for the followed file to grow.
+const POLL_INTERVAL_MS: u64 = 250;
+
+/// A search logged as slow.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SlowSearch {
+  pub timestamp:  Option<String>,
+  pub request_id: Option<String>,
+  pub elapsed_ms: u64,
+  pub took_ms:    Option<u64>,
+  pub params:     JsonValue
+}
+
+/// Return the milliseconds of `threshold`, given as `500ms`, `2s` or `500`.
+pub fn parse_threshold(threshold: &str) -> Result<u64, String> {
+  let threshold = threshold.trim();
+  let parsed = if threshold.ends_with("ms") {
+    threshold[..threshold.len() - 2].parse::<u64>().ok()
+  }
+  else if threshold.ends_with('s') {
+    threshold[..threshold.len() - 1].parse::<u64>().ok().map(|seconds| seconds * 1000)
+  }
+  else {
+    threshold.parse::<u64>().ok()
+  };
+
+  parsed.ok_or(format!("expected a duration as 500ms, 2s or 500, got {:?}", threshold))
+}
+
+/// Return the slow search logged by `line`, either as text
+/// (`WARN - [2f4b1c1e] Slow search: {...} index=talents`) or as JSON.
+pub fn parse(line: &str) -> Option<SlowSearch> {
+  let line = line.trim();
+
+  if line.starts_with('{') {
+    let object  = match serde_json::from_str::<JsonValue>(line) {
+      Ok(object) => object,
+      Err(_)     => return None
+    };
+    let message = match object.find("message").and_then(|message| message.as_str()) {
+      Some(message) if message.starts_with(MARKER) => message,
+      _                                            => return None
+    };
+
+    let field = |name: &str| object.find(name).and_then(|value| value.as_str()).map(|value| value.to_owned());
+    return entry(&message[MARKER.len()..], field("timestamp"), field("request_id"));
+  }
+
+  let start = match line.find(MARKER) {
+    Some(start) => start,
+    None        => return None
+  };
+
+  // `<LEVEL> - [<request id>] Slow search: ...`
+  let request_id = line[..start].find('[').and_then(|open| {
+    line[open + 1..start].find(']').map(|close| line[open + 1..open + 1 + close].to_owned())
+  });
+
+  entry(&line[start + MARKER.len()..], None, request_id)
+}
+
+/// Return the search whose entry is the JSON object at the beginning of `text`
+/// (the fields of the line may follow it).
+fn entry(text: &str, timestamp: Option<String>, request_id: Option<String>) -> Option<SlowSearch> {
+  let object = match json_object(text).and_then(|object| serde_json::from_str::<JsonValue>(object).ok()) {
+    Some(object) => object,
+    None         => return None
+  };
+
+  object.find("elapsed_ms").and_then(|elapsed| elapsed.as_u64()).map(|elapsed| SlowSearch {
+    timestamp:  timestamp,
+    request_id: request_id,
+    elapsed_ms: elapsed,
+    took_ms:    object.find("took_ms").and_then(|took| took.as_u64()),
+    params:     object.find("params").cloned().unwrap_or(JsonValue::Null)
+  })
+}
+
+/// Return the JSON object at the beginning of `text`.
+fn json_object(text: &str) -> Option<&str> {
+  let mut depth   = 0;
+  let mut quoted  = false;
+  let mut escaped = false;
+
+  for (position, c) in text.char_indices() {
+    match c {
+      _ if escaped      => escaped = false,
+      '\\' if quoted    => escaped = true,
+      '"'               => quoted = !quoted,
+      '{' if !quoted    => depth += 1,
+      '}' if !quoted    => {
+        depth -= 1;
+        if depth == 0 {
+          return Some(&text[..position + 1]);
+        }
+      },
+      _ => ()
+    }
+  }
+
+  None
+}
+
+/// Return `search` as a line, its params as `key=value`.
+pub fn format(search: &SlowSearch) -> String {
+  let params = search.params.as_object().map(|params| {
+    params.iter().map(|(key, value)| format!("{}={}", key, param(value))).collect::<Vec<String>>().join(" ")
+  }).unwrap_or("".to_owned());
+
+  format!("{}  {}ms{}  [{}]  {}",
+          search.timestamp.to_owned().unwrap_or("-".to_owned()),
+          search.elapsed_ms,
+          search.took_ms.map(|took| format!(" (es {}ms)", took)).unwrap_or("".to_owned()),
+          search.request_id.to_owned().unwrap_or("-".to_owned()),
+          params)
+}
+
+fn param(value: &JsonValue) -> String {
+  match *value {
+    JsonValue::String(ref value) => value.to_owned(),
+    JsonValue::Array(ref values) => values.iter().map(param).collect::<Vec<String>>().join(","),
+    ref value                    => value.to_string()
+  }
+}
+
+/// Print the searches of `input` slower than `threshold` milliseconds to `out`,
+/// until `input` ends.
+pub fn tail<B: BufRead, W: Write>(input: B, threshold: u64, out: &mut W) -> io::Result<()> {
+  for line in input.lines() {
+    if let Some(search) = parse(&try!(line)).into_iter().find(|search| search.elapsed_ms >= threshold) {
+      try!(writeln!(out, "{}", format(&search)));
+    }
+  }
+
+  Ok(())
+}
+
+/// Print the searches slower than `threshold` milliseconds logged to the file at `path`
+/// from now on, following it as it grows, to `out`.
+pub fn follow<W: Write>(path: &str, threshold: u64, out: &mut W) -> io::Result<()> {
+  let mut file = try!(File::open(path));
+  try!(file.seek(SeekFrom::End(0)));
+
+  let mut reader = BufReader::new(file);
+  let mut line   = String::new();
+
+  loop {
+    if try!(reader.read_line(&mut line)) == 0 || !line.ends_with('\n') {
+      thread::sleep(Duration::from_millis(POLL_INTERVAL_MS));
+      continue;
+    }
+
+    try!(tail(line.as_bytes(), threshold, out));
+    try!(out.flush());
+    line.clear();
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use slowlog::{parse_threshold, parse, format, tail};
+
+  use serde_json;
+
+  #[test]
+  fn test_parse_threshold() {
+    assert_eq!(parse_threshold("500ms"), Ok(500));
+    assert_eq!(parse_threshold("2s"), Ok(2000));
+    assert_eq!(parse_threshold("750"), Ok(750));
+    assert!(parse_threshold("fast").is_err());
+  }
+
+  #[test]
+  fn test_parse() {
+    let text = parse("WARN - [2f4b1c1e] Slow search: {\"elapsed_ms\":1200,\"params\":{\"keywords\":\"C{\\\"}\",\
+                      \"work_locations\":[\"Berlin\",\"Rome\"]},\"took_ms\":1100} index=talents operation=search").unwrap();
+    assert_eq!(text.request_id, Some("2f4b1c1e".to_owned()));
+    assert_eq!(text.elapsed_ms, 1200);
+    assert_eq!(text.took_ms, Some(1100));
+    assert_eq!(format(&text), "-  1200ms (es 1100ms)  [2f4b1c1e]  keywords=C{\"} work_locations=Berlin,Rome");
+
+    let json = parse(&serde_json::to_string(&btreemap! {
+      "level"      => "WARN",
+      "message"    => "Slow search: {\"elapsed_ms\":800,\"params\":{},\"took_ms\":null}",
+      "request_id" => "abc",
+      "timestamp"  => "2017-03-04T12:24:00+00:00"
+    }).unwrap()).unwrap();
+    assert_eq!(format(&json), "2017-03-04T12:24:00+00:00  800ms  [abc]  ");
+
+    assert_eq!(parse("INFO - Searchspot v0.12.2"), None);
+  }
+
+  #[test]
+  fn test_tail() {
+    let input = "WARN - Slow search: {\"elapsed_ms\":400,\"params\":{},\"took_ms\":300}\n\
+                 INFO - Listening\n\
+                 WARN - Slow search: {\"elapsed_ms\":900,\"params\":{\"keywords\":\"rust\"},\"took_ms\":850}\n";
+
+    let mut out = vec![];
+    tail(input.as_bytes(), 500, &mut out).unwrap();
+    assert_eq!(String::from_utf8(out).unwrap(), "-  900ms (es 850ms)  [-]  keywords=rust\n");
+  }
+}
@@ -0,0 +1,207 @@
+//! The full reindex from the source of truth of the resources (i.e.: the
+//! database of the main application), run by `searchspot --reindex-from-source`.
+//!
+//! The resources are streamed into a fresh index, named after the index of
+//! the resource and the time of the reindex (i.e.: `talents_20170304122400`),
+//! which then replaces the previous one behind an alias, so that the searches
+//! never see a partial index. The previous indexes are deleted afterwards.
+
+use postgres::{Connection, TlsMode};
+
+use serde_json;
+
+use config::{Config, Source as SourceConfig};
+use resource::Resource;
+use cluster;
+use cluster::Cluster;
+use error::Error;
+use admin;
+use cache::CACHE;
+use bulk;
+use es;
+use settings;
+use logger::start_logging;
+
+use std::sync::Arc;
+
+/// Implemented by the sources of truth the resources can be read from.
+pub trait DataSource<R: Resource> {
+  /// Call `batch` with every resource read, at most `size` at a time,
+  /// returning how many have been read.
+  fn read(&mut self, size: u64, batch: &mut FnMut(Vec<R>) -> Result<(), Error>) -> Result<u64, Error>;
+}
+
+/// The resources read from a PostgreSQL view, whose rows are converted into
+/// resources through their JSON representation (hence, the columns must be
+/// named after the fields of the resource). The rows that cannot be converted,
+/// or that are not valid, are skipped.
+pub struct PostgresSource {
+  connection: Connection,
+  view:       String,
+  skipped:    u64
+}
+
+impl PostgresSource {
+  pub fn connect(config: &SourceConfig) -> Result<PostgresSource, Error> {
+    let connection = try!(Connection::connect(&*config.url, TlsMode::None).map_err(|err| Error::Source(err.to_string())));
+
+    Ok(PostgresSource {
+      connection: connection,
+      view:       config.view.to_owned(),
+      skipped:    0
+    })
+  }
+
+  /// Return how many rows have been skipped.
+  pub fn skipped(&self) -> u64 {
+    self.skipped
+  }
+}
+
+impl<R: Resource> DataSource<R> for PostgresSource {
+  fn read(&mut self, size: u64, batch: &mut FnMut(Vec<R>) -> Result<(), Error>) -> Result<u64, Error> {
+    // the rows are fetched through a cursor, they may not fit in memory at once
+    let transaction = try!(self.connection.transaction().map_err(|err| Error::Source(err.to_string())));
+    try!(transaction.batch_execute(&query(&self.view)).map_err(|err| Error::Source(err.to_string())));
+
+    let mut read = 0;
+
+    loop {
+      let rows = try!(transaction.query(&format!("FETCH {} FROM searchspot_source", size), &[])
+                                 .map_err(|err| Error::Source(err.to_string())));
+      if rows.is_empty() {
+        break;
+      }
+
+      let mut resources = vec![];
+      for row in rows.iter() {
+        let json: String = row.get(0);
+
+        match parse::<R>(&json) {
+          Ok(resource) => resources.push(resource),
+          Err(err)     => {
+            warn!("The row {} has been skipped: {}", json, err);
+            self.skipped += 1;
+          }
+        }
+      }
+
+      read += resources.len() as u64;
+      try!(batch(resources));
+    }
+
+    try!(transaction.finish().map_err(|err| Error::Source(err.to_string())));
+    Ok(read)
+  }
+}
+
+/// Return the statement declaring the cursor over the rows of `view`, as JSON.
+fn query(view: &str) -> String {
+  format!("DECLARE searchspot_source NO SCROLL CURSOR FOR SELECT row_to_json(source)::text FROM {} source", view)
+}
+
+/// Return the resource of the row given as JSON, or why it cannot be indexed.
+pub fn parse<R: Resource>(json: &str) -> Result<R, String> {
+  let resource = try!(serde_json::from_str::<R>(json).map_err(|err| err.to_string()));
+
+  let errors = resource.validate();
+  if errors.is_empty() {
+    Ok(resource)
+  }
+  else {
+    Err(errors.into_iter().map(|error| error.message).collect::<Vec<String>>().join(" "))
+  }
+}
+
+
+/// Index every resource of `source` into a fresh index, then point `alias` to it
+/// and delete the indexes it pointed to before, returning how many have been indexed.
+/// Nothing is replaced if any of them cannot be indexed.
+pub fn reindex<R: Resource, S: DataSource<R>>(config: &Config, cluster: Arc<Cluster>, alias: &str, source: &mut S) -> Result<u64, Error> {
+  let index = admin::fresh_index(alias);
+  info!("Reindexing {} into {}.", alias, index);
+
+  try!(admin::reset::<R>(&mut cluster.get(), &cluster.url(), &index, &config.resource(R::name())));
+
+  let indexed = source.read(config.source.batch_size, &mut |resources| {
+    let results = try!(bulk::index_many(cluster.clone(), &index, resources, config.es.max_concurrent_bulks));
+
+    match results.iter().flat_map(|result| result.items.iter()).find(|item| item.inner.status >= 300) {
+      Some(item) => Err(Error::Validation(format!("ElasticSearch has refused `{}` ({}).", item.inner.id, item.inner.status))),
+      None       => Ok(())
+    }
+  });
+
+  let indexed = match indexed {
+    Ok(indexed) => indexed,
+    Err(err)    => {
+      // the partial index is useless, the alias still points to the previous one
+      let _ = cluster.get().delete_index(&index);
+      return Err(err);
+    }
+  };
+
+  try!(cluster.get().refresh().with_indexes(&[&index]).send());
+
+  for previous in try!(es::swap_alias(&cluster.url(), alias, &index)) {
+    if let Err(err) = cluster.get().delete_index(&previous) {
+      warn!("The previous index {} has not been deleted: {}", previous, err);
+    }
+  }
+
+  Ok(indexed)
+}
+
+/// Rebuild the index of `R` from the PostgreSQL view of `config`,
+/// returning whether it has been replaced.
+pub fn reindex_from_source<R: Resource>(config: Config) -> bool {
+  start_logging(&config).unwrap();
+  settings::apply(&config);
+
+  info!("Searchspot v{}", env!("CARGO_PKG_VERSION"));
+  info!("{}", config.source);
+
+  cluster::bootstrap(&config);
+
+  let cluster = Arc::new(Cluster::new(&config.es.urls(), config.es.connections));
+  es::configure_compatibility(&config.es.compatibility, &cluster.url());
+  let alias   = config.index_of(R::name());
+
+  let mut source = match PostgresSource::connect(&config.source) {
+    Ok(source) => source,
+    Err(err)   => {
+      error!("{}", err);
+      return false;
+    }
+  };
+
+  match reindex::<R, PostgresSource>(&config, cluster.clone(), &alias, &mut source) {
+    Ok(indexed) => {
+      CACHE.publish_invalidation(&config.cache, &alias);
+      info!("{} documents have been reindexed into {}, {} rows have been skipped.", indexed, alias, source.skipped());
+      true
+    },
+    Err(err) => {
+      error!("{} has not been reindexed: {}", alias, err);
+      false
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use source::{parse, query};
+  use resources::Talent;
+
+  #[test]
+  fn test_query() {
+    assert_eq!(query("public.searchspot_talents"),
+               "DECLARE searchspot_source NO SCROLL CURSOR FOR SELECT row_to_json(source)::text FROM public.searchspot_talents source");
+  }
+
+  #[test]
+  fn test_parse() {
+    assert!(parse::<Talent>("not json").is_err());
+    assert!(parse::<Talent>("{\"id\": \"forty-two\"}").is_err());
+  }
+}
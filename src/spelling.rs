@@ -0,0 +1,157 @@
+//! The spell checking of the keywords: once the `spell_checking` feature is
+//! enabled, the keywords of a search are first sent to a term suggester over
+//! the vocabulary of the skills of the talents, and the words missing from it
+//! are replaced by their best suggestion when it scores at least
+//! `search.spelling_confidence`. The corrected keywords are then searched
+//! instead, and returned as `X-Searchspot-Corrected-Keywords` so that the
+//! search bar can show "showing results for ...". `spellcheck=false` searches
+//! the keywords as given.
+
+use serde_json;
+use serde_json::Value as JsonValue;
+
+use params::{Map, Value};
+
+use error::Error;
+use keywords;
+use es;
+
+/// The header giving the keywords that have been searched instead of the given ones.
+pub const CORRECTED_HEADER: &'static str = "X-Searchspot-Corrected-Keywords";
+
+/// The field holding the vocabulary, a copy of `skills` split into words.
+pub const VOCABULARY_FIELD: &'static str = "skills_vocabulary";
+
+/// The name of the suggestion in the request and in the response.
+const SUGGESTION: &'static str = "keywords";
+
+/// Return whether the keywords of the search given by `params` should be spell checked,
+/// unless the caller opted out with `spellcheck=false`.
+pub fn requested(params: &Map) -> bool {
+  match params.get("spellcheck") {
+    Some(&Value::Boolean(spellcheck))    => spellcheck,
+    Some(&Value::String(ref spellcheck)) => spellcheck != "false",
+    _                                    => true
+  }
+}
+
+/// Return the request suggesting the corrections of `keywords` from the vocabulary of `index`.
+pub fn suggestion(index: &str, keywords: &str) -> JsonValue {
+  let term = btreemap! {
+    "field"        => serde_json::to_value(&VOCABULARY_FIELD),
+    "suggest_mode" => serde_json::to_value(&"missing"), // the known words are never corrected
+    "size"         => serde_json::to_value(&1)
+  };
+
+  let suggest = btreemap! {
+    "text"     => serde_json::to_value(&keywords),
+    SUGGESTION => serde_json::to_value(&btreemap! { "term" => term })
+  };
+
+  serde_json::to_value(&btreemap! {
+    "index" => serde_json::to_value(&[index]),
+    "body"  => serde_json::to_value(&btreemap! {
+      "size"    => serde_json::to_value(&0),
+      "suggest" => serde_json::to_value(&suggest)
+    })
+  })
+}
+
+/// Return `keywords` with their words replaced by the suggestions of `response` scoring
+/// at least `confidence`, or `None` if none of them does.
+pub fn correction(keywords: &str, response: &JsonValue, confidence: f64) -> Option<String> {
+  let entries = match response.pointer(&format!("/suggest/{}", SUGGESTION)).and_then(|entries| entries.as_array()) {
+    Some(entries) => entries,
+    None          => return None
+  };
+
+  // the offsets count the characters of the text
+  let mut corrected = keywords.chars().collect::<Vec<char>>();
+  let mut replaced  = false;
+
+  // replaced from the end, so that the offsets of the previous words still hold
+  for entry in entries.iter().rev() {
+    let best = entry.pointer("/options/0");
+
+    let (offset, length, text, score) = match (entry.find("offset").and_then(|offset| offset.as_u64()),
+                                               entry.find("length").and_then(|length| length.as_u64()),
+                                               best.and_then(|best| best.find("text")).and_then(|text| text.as_str()),
+                                               best.and_then(|best| best.find("score")).and_then(|score| score.as_f64())) {
+      (Some(offset), Some(length), Some(text), Some(score)) => (offset as usize, length as usize, text, score),
+      _                                                     => continue
+    };
+
+    if score < confidence || offset + length > corrected.len() {
+      continue;
+    }
+
+    let rest = corrected.split_off(offset + length);
+    corrected.truncate(offset);
+    corrected.extend(text.chars().chain(rest));
+    replaced = true;
+  }
+
+  if replaced { Some(corrected.into_iter().collect()) } else { None }
+}
+
+/// Return the keywords of `params` corrected from the vocabulary of `index`, if they need to.
+pub fn check(url: &str, index: &str, params: &Map, confidence: f64) -> Result<Option<String>, Error> {
+  let keywords = match params.get("keywords") {
+    Some(&Value::String(ref keywords)) => keywords::sanitize(keywords),
+    _                                  => return Ok(None)
+  };
+
+  if keywords.is_empty() {
+    return Ok(None);
+  }
+
+  let response = try!(es::search(url, &suggestion(index, &keywords), false));
+  Ok(correction(&keywords, &response, confidence))
+}
+
+/// Return `params` searching `keywords` instead of the given ones.
+pub fn corrected(params: &Map, keywords: &str) -> Map {
+  let mut corrected = params.to_owned();
+  corrected.insert("keywords".to_owned(), Value::String(keywords.to_owned()));
+  corrected
+}
+
+#[cfg(test)]
+mod tests {
+  use spelling::{requested, suggestion, correction};
+
+  use params::{Map, Value};
+
+  use serde_json;
+
+  #[test]
+  fn test_requested() {
+    let mut params = Map::new();
+    assert!(requested(&params));
+
+    params.assign("spellcheck", Value::String("false".into())).unwrap();
+    assert!(!requested(&params));
+  }
+
+  #[test]
+  fn test_suggestion() {
+    let request = suggestion("talents", "rsut");
+
+    assert_eq!(request.pointer("/index/0"), Some(&serde_json::to_value(&"talents")));
+    assert_eq!(request.pointer("/body/suggest/text"), Some(&serde_json::to_value(&"rsut")));
+    assert_eq!(request.pointer("/body/suggest/keywords/term/field"), Some(&serde_json::to_value(&"skills_vocabulary")));
+  }
+
+  #[test]
+  fn test_correction() {
+    let response = serde_json::from_str("{\"suggest\": {\"keywords\": [\
+                                           {\"text\": \"rsut\", \"offset\": 0, \"length\": 4, \"options\": [{\"text\": \"rust\", \"score\": 0.75}]}, \
+                                           {\"text\": \"postgre\", \"offset\": 5, \"length\": 7, \"options\": [{\"text\": \"postgres\", \"score\": 0.9}]}, \
+                                           {\"text\": \"go\", \"offset\": 13, \"length\": 2, \"options\": []}]}}").unwrap();
+
+    assert_eq!(correction("rsut postgre go", &response, 0.8), Some("rsut postgres go".to_owned()));
+    assert_eq!(correction("rsut postgre go", &response, 0.7), Some("rust postgres go".to_owned()));
+    assert_eq!(correction("rsut postgre go", &response, 0.95), None);
+    assert_eq!(correction("rust", &serde_json::from_str("{}").unwrap(), 0.8), None);
+  }
+}
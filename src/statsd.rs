@@ -0,0 +1,128 @@
+//! A StatsD client sending the counters, gauges and timings recorded by
+//! `metrics::Registry` over UDP as well, for the teams running a StatsD
+//! server or a Datadog agent rather than scraping `/metrics`.
+//!
+//! The names drop the `searchspot_` namespace in favour of `statsd.prefix`
+//! (i.e.: `searchspot_operations_total` is sent as `searchspot.operations_total`)
+//! and the labels are sent as Datadog tags, or appended to the names.
+
+use config::StatsD as StatsDConfig;
+
+use std::io;
+use std::net::{UdpSocket, SocketAddr, ToSocketAddrs};
+
+/// The namespace of the metrics of the registry, replaced by the prefix.
+const NAMESPACE: &'static str = "searchspot_";
+
+/// The kinds of metrics, as StatsD calls them.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Kind {
+  Counter,
+  Gauge,
+  Timing,    // in milliseconds
+  Histogram
+}
+
+impl Kind {
+  pub fn as_str(&self) -> &'static str {
+    match *self {
+      Kind::Counter   => "c",
+      Kind::Gauge     => "g",
+      Kind::Timing    => "ms",
+      Kind::Histogram => "h"
+    }
+  }
+}
+
+#[derive(Debug)]
+pub struct StatsD {
+  socket:  UdpSocket,
+  address: SocketAddr,
+  prefix:  String,
+  tags:    bool
+}
+
+impl StatsD {
+  /// Return a client sending the metrics to the server of `config`.
+  pub fn new(config: &StatsDConfig) -> io::Result<StatsD> {
+    let address = match try!((&*config.host, config.port).to_socket_addrs()).next() {
+      Some(address) => address,
+      None          => return Err(io::Error::new(io::ErrorKind::NotFound, format!("{} cannot be resolved", config.host)))
+    };
+
+    Ok(StatsD {
+      socket:  try!(UdpSocket::bind("0.0.0.0:0")),
+      address: address,
+      prefix:  config.prefix.to_owned(),
+      tags:    config.tags
+    })
+  }
+
+  /// Send `value` as the metric `name` with given `labels`. The packets
+  /// that cannot be sent are lost, as UDP would lose them anyway.
+  pub fn send(&self, name: &str, labels: &[(&str, &str)], value: &str, kind: Kind) {
+    let line = line(&self.prefix, self.tags, name, labels, value, kind);
+    let _    = self.socket.send_to(line.as_bytes(), self.address);
+  }
+}
+
+/// Return the StatsD line of the metric, i.e.: `searchspot.operations_total:1|c|#index:talents`
+/// with the tags, `searchspot.operations_total.talents:1|c` without.
+pub fn line(prefix: &str, tags: bool, name: &str, labels: &[(&str, &str)], value: &str, kind: Kind) -> String {
+  let mut name = format!("{}{}", prefix, name.trim_left_matches(NAMESPACE));
+
+  // the timings are sent in milliseconds, the unit is not part of their name anymore
+  if kind == Kind::Timing && name.ends_with("_seconds") {
+    let length = name.len() - "_seconds".len();
+    name.truncate(length);
+  }
+
+  if tags {
+    let mut line = format!("{}:{}|{}", name, value, kind.as_str());
+
+    if !labels.is_empty() {
+      let tags = labels.iter()
+                       .map(|&(key, value)| format!("{}:{}", key, sanitize(value)))
+                       .collect::<Vec<String>>();
+      line.push_str(&format!("|#{}", tags.join(",")));
+    }
+
+    line
+  }
+  else {
+    for &(_, value) in labels {
+      name.push_str(&format!(".{}", sanitize(value).replace('.', "_")));
+    }
+
+    format!("{}:{}|{}", name, value, kind.as_str())
+  }
+}
+
+/// Return `value` without the characters delimiting the fields of the lines.
+fn sanitize(value: &str) -> String {
+  value.chars()
+       .map(|c| if c == ':' || c == '|' || c == ',' || c == '#' || c == '@' || c.is_whitespace() { '_' } else { c })
+       .collect()
+}
+
+#[cfg(test)]
+mod tests {
+  use statsd::{line, Kind};
+
+  #[test]
+  fn test_line() {
+    let labels = [("index", "talents"), ("outcome", "ok")];
+
+    assert_eq!(line("searchspot.", true, "searchspot_operations_total", &labels, "1", Kind::Counter),
+               "searchspot.operations_total:1|c|#index:talents,outcome:ok");
+    assert_eq!(line("searchspot.", true, "searchspot_write_queue_depth", &[], "12", Kind::Gauge),
+               "searchspot.write_queue_depth:12|g");
+    assert_eq!(line("searchspot.", true, "searchspot_search_duration_seconds", &[], "34", Kind::Timing),
+               "searchspot.search_duration:34|ms");
+
+    assert_eq!(line("app.searchspot.", false, "searchspot_operations_total", &labels, "1", Kind::Counter),
+               "app.searchspot.operations_total.talents.ok:1|c");
+    assert_eq!(line("", false, "searchspot_http_requests_total", &[("path", "/v1/talents"), ("host", "a.b:80")], "1", Kind::Counter),
+               "http_requests_total./v1/talents.a_b_80:1|c");
+  }
+}
@@ -0,0 +1,156 @@
+//! The subscriptions to the newly indexed resources, pushed
+//! to the clients as server-sent events.
+
+use iron::response::{WriteBody, ResponseBody};
+
+use params::Map;
+
+use resource::Resource;
+
+use std::io;
+use std::io::Write;
+use std::sync::Mutex;
+use std::sync::mpsc::{channel, Sender, Receiver, RecvTimeoutError};
+use std::time::Duration;
+
+/// How often (in seconds) an idle stream sends a comment, so that
+/// the closed connections are detected.
+const KEEP_ALIVE: u64 = 15;
+
+lazy_static! {
+  /// The subscriptions shared across the handlers.
+  pub static ref SUBSCRIPTIONS: Subscriptions = Subscriptions::new();
+}
+
+struct Subscriber {
+  id:     usize,
+  params: Map,
+  sender: Sender<String>
+}
+
+pub struct Subscriptions {
+  subscribers: Mutex<(usize, Vec<Subscriber>)>
+}
+
+impl Subscriptions {
+  pub fn new() -> Subscriptions {
+    Subscriptions {
+      subscribers: Mutex::new((0, vec![]))
+    }
+  }
+
+  /// Subscribe to the resources matching the filters inside `params`,
+  /// returning the id of the subscription and the receiving end of
+  /// the ids of the matching resources.
+  pub fn subscribe(&self, params: Map) -> (usize, Receiver<String>) {
+    let (sender, receiver) = channel();
+    let mut subscribers = self.subscribers.lock().unwrap();
+
+    subscribers.0 += 1;
+    let id = subscribers.0;
+    subscribers.1.push(Subscriber { id: id, params: params, sender: sender });
+
+    (id, receiver)
+  }
+
+  pub fn unsubscribe(&self, id: usize) {
+    self.subscribers.lock().unwrap().1.retain(|subscriber| subscriber.id != id);
+  }
+
+  pub fn len(&self) -> usize {
+    self.subscribers.lock().unwrap().1.len()
+  }
+
+  /// Return the pairs of subscription and resource id for every
+  /// subscription matched by `resources`. They are meant to be
+  /// given to `notify` once the resources have been indexed.
+  pub fn percolate<R: Resource>(&self, resources: &[R]) -> Vec<(usize, String)> {
+    let subscribers = self.subscribers.lock().unwrap();
+
+    subscribers.1.iter()
+                 .flat_map(|subscriber| {
+                   resources.iter()
+                            .filter(|resource| resource.percolate(&subscriber.params))
+                            .map(|resource| (subscriber.id, resource.id()))
+                            .collect::<Vec<(usize, String)>>()
+                 })
+                 .collect()
+  }
+
+  /// Send the ids returned by `percolate` to their subscribers.
+  pub fn notify(&self, matches: Vec<(usize, String)>) {
+    let subscribers = self.subscribers.lock().unwrap();
+
+    for (id, resource_id) in matches {
+      if let Some(subscriber) = subscribers.1.iter().find(|subscriber| subscriber.id == id) {
+        // the receiver may have been dropped in the meanwhile
+        let _ = subscriber.sender.send(resource_id);
+      }
+    }
+  }
+}
+
+/// The body of a `text/event-stream` response, that keeps writing the
+/// ids received by a subscription until the client goes away.
+pub struct EventStream {
+  id:       usize,
+  receiver: Receiver<String>
+}
+
+impl EventStream {
+  pub fn new(params: Map) -> EventStream {
+    let (id, receiver) = SUBSCRIPTIONS.subscribe(params);
+
+    EventStream {
+      id:       id,
+      receiver: receiver
+    }
+  }
+}
+
+impl WriteBody for EventStream {
+  fn write_body(&mut self, res: &mut ResponseBody) -> io::Result<()> {
+    loop {
+      match self.receiver.recv_timeout(Duration::from_secs(KEEP_ALIVE)) {
+        Ok(id) => try!(write!(res, "data: {}\n\n", id)),
+        Err(RecvTimeoutError::Timeout)      => try!(write!(res, ": keep-alive\n\n")),
+        Err(RecvTimeoutError::Disconnected) => return Ok(())
+      }
+
+      try!(res.flush());
+    }
+  }
+}
+
+impl Drop for EventStream {
+  fn drop(&mut self) {
+    SUBSCRIPTIONS.unsubscribe(self.id);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use stream::Subscriptions;
+
+  use params::Map;
+
+  #[test]
+  fn test_notify() {
+    let subscriptions = Subscriptions::new();
+
+    let (first,  first_receiver)  = subscriptions.subscribe(Map::new());
+    let (second, second_receiver) = subscriptions.subscribe(Map::new());
+    assert_eq!(subscriptions.len(), 2);
+
+    subscriptions.notify(vec![(first, "1".to_owned()), (second, "2".to_owned()), (first, "3".to_owned())]);
+    assert_eq!(first_receiver.try_iter().collect::<Vec<String>>(), vec!["1", "3"]);
+    assert_eq!(second_receiver.try_iter().collect::<Vec<String>>(), vec!["2"]);
+
+    subscriptions.unsubscribe(first);
+    assert_eq!(subscriptions.len(), 1);
+
+    // the ids of removed subscriptions are ignored
+    subscriptions.notify(vec![(first, "4".to_owned())]);
+    assert!(first_receiver.try_recv().is_err());
+  }
+}
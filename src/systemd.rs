@@ -0,0 +1,117 @@
+//! The notifications sent to systemd when run as a `Type=notify` service
+//! (see `sd_notify(3)`): the readiness, once ElasticSearch can be searched,
+//! the shutdown and the keep-alives of the watchdog, sent by the main loop so
+//! that a wedged instance is restarted. Nothing is sent outside of systemd.
+
+use std::env;
+use std::io;
+use std::os::unix::net::UnixDatagram;
+use std::process;
+use std::time::Duration;
+
+/// Return whether the service is run by systemd, expecting the notifications.
+pub fn enabled() -> bool {
+  env::var_os("NOTIFY_SOCKET").is_some()
+}
+
+/// Send `state` (i.e.: `READY=1`) to the socket of `NOTIFY_SOCKET`, returning
+/// whether it has been sent. Nothing is sent when not run by systemd.
+pub fn notify(state: &str) -> bool {
+  let socket = match env::var("NOTIFY_SOCKET") {
+    Ok(socket) => socket,
+    Err(_)     => return false
+  };
+
+  match send(&socket, state) {
+    Ok(_)    => true,
+    Err(err) => {
+      warn!("{:?} cannot be sent to systemd through {}: {}", state, socket, err);
+      false
+    }
+  }
+}
+
+/// Send `state` to the datagram socket at `path`.
+fn send(path: &str, state: &str) -> io::Result<()> {
+  // the abstract sockets (i.e.: `@/org/freedesktop/systemd1/notify`) are not supported by `std`
+  if path.starts_with('@') {
+    return Err(io::Error::new(io::ErrorKind::Other, "abstract sockets are not supported"));
+  }
+
+  let socket = try!(UnixDatagram::unbound());
+  socket.send_to(state.as_bytes(), path).map(|_| ())
+}
+
+/// Tell systemd that the requests can be served.
+pub fn ready() -> bool {
+  notify(&format!("READY=1\nMAINPID={}", process::id()))
+}
+
+/// Tell systemd why the requests cannot be served yet.
+pub fn status(status: &str) -> bool {
+  notify(&format!("STATUS={}", status.replace('\n', " ")))
+}
+
+/// Tell systemd that the in-flight requests are being drained.
+pub fn stopping() -> bool {
+  notify("STOPPING=1")
+}
+
+/// Keep the watchdog from restarting the service.
+pub fn pet() -> bool {
+  notify("WATCHDOG=1")
+}
+
+/// Return how often the watchdog should be petted, if enabled for this process:
+/// half of its timeout, so that a late keep-alive does not restart the service.
+pub fn watchdog_interval() -> Option<Duration> {
+  interval(env::var("WATCHDOG_USEC").ok(), env::var("WATCHDOG_PID").ok(), process::id())
+}
+
+fn interval(usec: Option<String>, pid: Option<String>, own_pid: u32) -> Option<Duration> {
+  if let Some(pid) = pid {
+    if pid.parse::<u32>().ok() != Some(own_pid) {
+      return None;
+    }
+  }
+
+  match usec.and_then(|usec| usec.parse::<u64>().ok()) {
+    Some(usec) if usec > 0 => Some(Duration::from_millis(usec / 2000)),
+    _                      => None
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use systemd::{send, interval};
+
+  use std::env;
+  use std::fs;
+  use std::os::unix::net::UnixDatagram;
+  use std::time::Duration;
+
+  #[test]
+  fn test_send() {
+    let path = env::temp_dir().join("searchspot_test_notify.sock");
+    let _    = fs::remove_file(&path);
+    let socket = UnixDatagram::bind(&path).unwrap();
+
+    send(path.to_str().unwrap(), "READY=1").unwrap();
+
+    let mut buffer = [0; 64];
+    let received = socket.recv(&mut buffer).unwrap();
+    assert_eq!(&buffer[..received], b"READY=1");
+
+    assert!(send("@/org/freedesktop/systemd1/notify", "READY=1").is_err());
+    let _ = fs::remove_file(&path);
+  }
+
+  #[test]
+  fn test_interval() {
+    assert_eq!(interval(Some("30000000".to_owned()), None, 42), Some(Duration::from_secs(15)));
+    assert_eq!(interval(Some("30000000".to_owned()), Some("42".to_owned()), 42), Some(Duration::from_secs(15)));
+    assert_eq!(interval(Some("30000000".to_owned()), Some("7".to_owned()), 42), None);
+    assert_eq!(interval(Some("0".to_owned()), None, 42), None);
+    assert_eq!(interval(None, None, 42), None);
+  }
+}
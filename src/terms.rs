@@ -1,5 +1,7 @@
 use rs_es::query::Query;
 
+use params::{Map, Value};
+
 pub trait VectorOfTerms<T> {
   /// Extract the elements inside `Vec<T>` into `Vec<Filter>`, if present.
   /// Every element will be mapped into a `JsonVal`.
@@ -20,6 +22,35 @@ impl VectorOfTerms<String> for Query {
   }
 }
 
+/// Same as `VectorOfTerms<String>`, for the strings borrowed from the params.
+impl<'a> VectorOfTerms<&'a str> for Query {
+  fn build_terms(key: &str, values: &Vec<&'a str>) -> Vec<Query> {
+    if values.is_empty() {
+      return vec![];
+    }
+
+    vec![
+      Query::build_terms(key).with_values(values.to_owned()).build()
+    ]
+  }
+}
+
+/// Return the strings given as `param` (i.e.: `work_roles[]`) borrowed from
+/// `params`, instead of copying them like `vec_from_params!`. As it does,
+/// nothing is returned if any of the values is not a string.
+pub fn strings<'a>(params: &'a Map, param: &str) -> Vec<&'a str> {
+  match params.get(param) {
+    Some(&Value::Array(ref values)) => values.iter()
+                                             .map(|value| match *value {
+                                               Value::String(ref value) => Some(&value[..]),
+                                               _                        => None
+                                             })
+                                             .collect::<Option<Vec<&str>>>()
+                                             .unwrap_or(vec![]),
+    _ => vec![]
+  }
+}
+
 macro_rules! build_vector_of_terms_impl {
   ($t:ty) => {
     impl<'a> VectorOfTerms<$t> for Query {
@@ -37,12 +68,14 @@ macro_rules! build_vector_of_terms_impl {
   }
 }
 
-build_vector_of_terms_impl!(i32);
+// the ids of the talents and of the companies
+build_vector_of_terms_impl!(u64);
 
 #[cfg(test)]
 mod tests {
   use terms::*;
   use rs_es::query::Query;
+  use params::{Map, Value};
   use serde_json;
 
   #[test]
@@ -58,10 +91,33 @@ mod tests {
     }
 
     {
-      let filters = <Query as VectorOfTerms<i32>>::build_terms(
+      let filters = <Query as VectorOfTerms<u64>>::build_terms(
                   "work_roles", &vec![1]);
       assert_eq!(serde_json::to_string(&filters[0]).unwrap(),
                   "{\"terms\":{\"work_roles\":[1]}}".to_owned());
     }
+
+    {
+      let filters = <Query as VectorOfTerms<u64>>::build_terms(
+                  "id", &vec![4294967296]);
+      assert_eq!(serde_json::to_string(&filters[0]).unwrap(),
+                  "{\"terms\":{\"id\":[4294967296]}}".to_owned());
+    }
+  }
+  #[test]
+  fn test_strings() {
+    let mut params = Map::new();
+    params.assign("work_roles[]", Value::String("Fullstack".into())).unwrap();
+    params.assign("work_roles[]", Value::String("DevOps".into())).unwrap();
+    params.assign("ids[]", Value::String("1".into())).unwrap();
+    params.assign("ids[]", Value::U64(2)).unwrap();
+
+    assert_eq!(strings(&params, "work_roles"), vec!["Fullstack", "DevOps"]);
+    assert!(strings(&params, "ids").is_empty());
+    assert!(strings(&params, "languages").is_empty());
+
+    let filters = <Query as VectorOfTerms<&str>>::build_terms("work_roles", &strings(&params, "work_roles"));
+    assert_eq!(serde_json::to_string(&filters[0]).unwrap(),
+               "{\"terms\":{\"work_roles\":[\"Fullstack\",\"DevOps\"]}}".to_owned());
   }
 }
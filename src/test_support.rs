@@ -0,0 +1,124 @@
+//! The helpers of the tests reaching ElasticSearch, shipped with the
+//! `test-support` feature so that the services embedding the resources of
+//! Searchspot can reuse them in their own integration tests.
+//!
+//! ```rust,ignore
+//! let mut client = Client::new("http://localhost:9200").unwrap();
+//! let index      = TestIndex::create::<Talent>(&mut client, "http://localhost:9200", "talents", &ResourceSettings::default()).unwrap();
+//!
+//! populate_index(&mut client, &index.name, vec![talent(1), Talent { weight: 6, ..talent(2) }]).unwrap();
+//! refresh_index(&mut client, &index.name);
+//! ```
+
+use rs_es::Client;
+use rs_es::operations::bulk::BulkResult;
+
+use uuid::Uuid;
+
+use config::ResourceSettings;
+use resource::Resource;
+use resources::Talent;
+use error::Error;
+use admin;
+
+/// Return a name no other test is using, i.e.: `talents_test_0b3c...`.
+pub fn index_name(prefix: &str) -> String {
+  format!("{}_test_{}", prefix, Uuid::new_v4().simple())
+}
+
+/// An index of its own for a test, created with the mapping of a resource,
+/// which is deleted once dropped. The tests can then run concurrently and
+/// never touch the indexes of the configuration.
+pub struct TestIndex {
+  pub name: String,
+  url:      String
+}
+
+impl TestIndex {
+  /// Create an index named after `prefix` (see `index_name`) with the mapping of `R`
+  /// and `settings` on the cluster listening on `url`.
+  pub fn create<R: Resource>(mut client: &mut Client, url: &str, prefix: &str, settings: &ResourceSettings) -> Result<TestIndex, Error> {
+    let name = index_name(prefix);
+    try!(admin::reset::<R>(&mut client, url, &name, settings));
+    refresh_index(&mut client, &name);
+
+    Ok(TestIndex { name: name, url: url.to_owned() })
+  }
+}
+
+impl Drop for TestIndex {
+  fn drop(&mut self) {
+    if let Ok(mut client) = Client::new(&self.url) {
+      let _ = client.delete_index(&self.name);
+    }
+  }
+}
+
+/// Index `resources` into `index`.
+pub fn populate_index<R: Resource>(mut client: &mut Client, index: &str, resources: Vec<R>) -> Result<BulkResult, Error> {
+  R::index(&mut client, index, resources)
+}
+
+/// Make the documents indexed so far searchable.
+pub fn refresh_index(client: &mut Client, index: &str) {
+  client.refresh()
+        .with_indexes(&[index])
+        .send()
+        .unwrap();
+}
+
+/// Return a talent visible to every company, without skills nor preferences,
+/// to be completed through the struct update syntax (i.e.: `Talent { skills: ..., ..talent(1) }`).
+pub fn talent(id: u64) -> Talent {
+  Talent {
+    id:                            id,
+    accepted:                      true,
+    desired_work_roles:            vec![],
+    desired_work_roles_vanilla:    None,
+    desired_work_roles_experience: vec![],
+    professional_experience:       "2..6".to_owned(),
+    work_locations:                vec!["Berlin".to_owned()],
+    current_location:              "Berlin".to_owned(),
+    work_authorization:            "yes".to_owned(),
+    skills:                        vec![],
+    summary:                       "".to_owned(),
+    headline:                      "".to_owned(),
+    contacted_company_ids:         vec![],
+    batch_starts_at:               "2006-01-01T12:00:00+00:00".to_owned(),
+    batch_ends_at:                 "2100-01-01T12:00:00+00:00".to_owned(),
+    added_to_batch_at:             "2006-01-01T12:00:00+00:00".to_owned(),
+    weight:                        0,
+    blocked_companies:             vec![],
+    work_experiences:              vec![],
+    avatar_url:                    "".to_owned(),
+    salary_expectations:           vec![],
+    latest_position:               "".to_owned(),
+    languages:                     vec!["English".to_owned()],
+    computed_score:                None
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use test_support::{index_name, talent};
+  use resource::Resource;
+  use resources::Talent;
+
+  #[test]
+  fn test_index_name() {
+    let name = index_name("talents");
+
+    assert!(name.starts_with("talents_test_"));
+    assert!(name.chars().all(|c| !c.is_uppercase()));
+    assert!(index_name("talents") != name);
+  }
+
+  #[test]
+  fn test_talent() {
+    let talent = Talent { skills: vec!["Rust".to_owned()], ..talent(42) };
+
+    assert_eq!(talent.id, 42);
+    assert!(talent.accepted);
+    assert!(talent.validate().is_empty());
+  }
+}
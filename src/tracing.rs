@@ -0,0 +1,521 @@
+//! The spans of the requests, exported in background to an OpenTelemetry
+//! collector (OTLP over HTTP, as JSON), so that searchspot shows up in the
+//! distributed traces of its callers.
+//!
+//! The trace context is read from, and sent along with, the W3C `traceparent`
+//! header (i.e.: `00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01`):
+//! the requests carrying it are traced as children of the caller's span,
+//! the others start a new trace.
+
+use serde_json;
+use uuid::Uuid;
+
+use hyper::Client as HttpClient;
+use hyper::Url;
+use hyper::net::HttpsConnector;
+use hyper::header::{Headers, ContentType};
+use hyper_openssl::OpensslClient;
+
+use config::Tracing as TracingConfig;
+
+use std::cell::RefCell;
+use std::mem;
+use std::sync::Mutex;
+use std::sync::mpsc::{channel, Sender};
+use std::thread;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The header carrying the trace context, both in requests and responses.
+pub const TRACEPARENT_HEADER: &'static str = "traceparent";
+
+/// The most spans sent to the collector at once.
+const MAX_BATCH: usize = 512;
+
+lazy_static! {
+  /// The tracer shared across the handlers.
+  pub static ref TRACER: Tracer = Tracer::new();
+}
+
+thread_local! {
+  /// The context of the span being run by the current thread.
+  static CURRENT: RefCell<Option<Context>> = RefCell::new(None);
+}
+
+/// The identifiers of a span, and whether its trace is recorded.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Context {
+  pub trace_id: String, // 32 lowercase hex digits
+  pub span_id:  String, // 16 lowercase hex digits
+  pub sampled:  bool
+}
+
+impl Context {
+  /// Return the context of the first span of a new trace.
+  pub fn new(sampled: bool) -> Context {
+    Context {
+      trace_id: Uuid::new_v4().simple().to_string(),
+      span_id:  span_id(),
+      sampled:  sampled
+    }
+  }
+
+  /// Return the context given as `traceparent` header, if valid.
+  pub fn parse(header: &str) -> Option<Context> {
+    let parts = header.trim().split('-').collect::<Vec<&str>>();
+
+    // the later versions may add fields, but keep these ones
+    if parts.len() < 4 || parts[0] == "ff" || !is_hex(parts[0], 2) || (parts[0] == "00" && parts.len() > 4) {
+      return None;
+    }
+
+    let (trace_id, span_id, flags) = (parts[1], parts[2], parts[3]);
+    if !is_hex(trace_id, 32) || !is_hex(span_id, 16) || !is_hex(flags, 2) ||
+       trace_id.chars().all(|c| c == '0') || span_id.chars().all(|c| c == '0') {
+      return None;
+    }
+
+    Some(Context {
+      trace_id: trace_id.to_owned(),
+      span_id:  span_id.to_owned(),
+      sampled:  u8::from_str_radix(flags, 16).unwrap() & 1 == 1
+    })
+  }
+
+  /// Return the context of a new span, child of this one.
+  pub fn child(&self) -> Context {
+    Context {
+      trace_id: self.trace_id.to_owned(),
+      span_id:  span_id(),
+      sampled:  self.sampled
+    }
+  }
+
+  /// Return the context as `traceparent` header.
+  pub fn header(&self) -> String {
+    format!("00-{}-{}-{}", self.trace_id, self.span_id, if self.sampled { "01" } else { "00" })
+  }
+}
+
+fn span_id() -> String {
+  Uuid::new_v4().simple().to_string()[..16].to_owned()
+}
+
+fn is_hex(value: &str, length: usize) -> bool {
+  value.len() == length && value.chars().all(|c| c.is_digit(16) && !c.is_uppercase())
+}
+
+/// Return the context of the span being run by the current thread, if any.
+pub fn current() -> Option<Context> {
+  CURRENT.with(|current| current.borrow().to_owned())
+}
+
+/// Set the context of the span being run by the current thread.
+pub fn set(context: Option<Context>) {
+  CURRENT.with(|current| *current.borrow_mut() = context);
+}
+
+/// What a span represents, as OpenTelemetry numbers it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Kind {
+  Internal = 1,
+  Server   = 2, // a request received by searchspot
+  Client   = 3  // a request sent to ElasticSearch
+}
+
+/// A span of the current trace, current until it is dropped, when it is
+/// exported. The spans started outside of a trace do nothing.
+pub struct Span {
+  name:       String,
+  kind:       Kind,
+  context:    Option<Context>,
+  parent_id:  Option<String>,
+  previous:   Option<Context>,
+  started_at: SystemTime,
+  attributes: Vec<(String, String)>,
+  failed:     bool
+}
+
+impl Span {
+  /// Start the span called `name`, child of the current one.
+  pub fn start(name: &str) -> Span {
+    Span::child(name, Kind::Internal)
+  }
+
+  /// Start the span called `name`, child of the current one,
+  /// for a request sent by searchspot.
+  pub fn client(name: &str) -> Span {
+    Span::child(name, Kind::Client)
+  }
+
+  fn child(name: &str, kind: Kind) -> Span {
+    let previous  = current();
+    let context   = previous.as_ref().map(Context::child);
+    let parent_id = previous.as_ref().map(|parent| parent.span_id.to_owned());
+    Span::new(name, kind, context, parent_id, previous)
+  }
+
+  fn new(name: &str, kind: Kind, context: Option<Context>, parent_id: Option<String>, previous: Option<Context>) -> Span {
+    if context.is_some() {
+      set(context.to_owned());
+    }
+
+    Span {
+      name:       name.to_owned(),
+      kind:       kind,
+      context:    context,
+      parent_id:  parent_id,
+      previous:   previous,
+      started_at: SystemTime::now(),
+      attributes: vec![],
+      failed:     false
+    }
+  }
+
+  /// Return the context of the span, unless it is outside of a trace.
+  pub fn context(&self) -> Option<&Context> {
+    self.context.as_ref()
+  }
+
+  /// Attach `value` to the span as `key`.
+  pub fn attribute(&mut self, key: &str, value: &str) {
+    if self.context.is_some() {
+      self.attributes.push((key.to_owned(), value.to_owned()));
+    }
+  }
+
+  /// Mark the span as failed, attaching `message` to it.
+  pub fn fail(&mut self, message: &str) {
+    self.failed = true;
+    self.attribute("error.message", message);
+  }
+}
+
+impl Drop for Span {
+  fn drop(&mut self) {
+    if let Some(context) = self.context.take() {
+      set(self.previous.take());
+
+      if context.sampled {
+        TRACER.export(Record {
+          trace_id:   context.trace_id,
+          span_id:    context.span_id,
+          parent_id:  self.parent_id.take(),
+          name:       mem::replace(&mut self.name, String::new()),
+          kind:       self.kind,
+          started_at: nanos(self.started_at),
+          ended_at:   nanos(SystemTime::now()),
+          attributes: mem::replace(&mut self.attributes, vec![]),
+          failed:     self.failed
+        });
+      }
+    }
+  }
+}
+
+fn nanos(time: SystemTime) -> u64 {
+  let elapsed = time.duration_since(UNIX_EPOCH).unwrap();
+  elapsed.as_secs() * 1_000_000_000 + elapsed.subsec_nanos() as u64
+}
+
+/// A span that has ended, waiting to be exported.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Record {
+  pub trace_id:   String,
+  pub span_id:    String,
+  pub parent_id:  Option<String>,
+  pub name:       String,
+  pub kind:       Kind,
+  pub started_at: u64, // nanoseconds since the epoch
+  pub ended_at:   u64,
+  pub attributes: Vec<(String, String)>,
+  pub failed:     bool
+}
+
+pub struct Tracer {
+  sender:       Mutex<Option<Sender<Record>>>,
+  sample_ratio: Mutex<f64>
+}
+
+impl Tracer {
+  pub fn new() -> Tracer {
+    Tracer {
+      sender:       Mutex::new(None),
+      sample_ratio: Mutex::new(1.0)
+    }
+  }
+
+  /// Start exporting the spans to the configured collector from a background
+  /// thread. Nothing is traced before, or when tracing is disabled.
+  pub fn start(&self, config: &TracingConfig) {
+    if !config.enabled {
+      return;
+    }
+
+    let (sender, receiver) = channel::<Record>();
+    let config = config.to_owned();
+
+    thread::spawn(move || {
+      while let Ok(record) = receiver.recv() {
+        let mut records = vec![record];
+        records.extend(receiver.try_iter().take(MAX_BATCH - 1));
+
+        let body = serde_json::to_string(&payload(&config.service_name, records)).unwrap();
+        if let Err(err) = post(&config.endpoint, &body) {
+          warn!("The spans have not been exported to {}: {}", config.endpoint, err);
+        }
+      }
+    });
+
+    *self.sample_ratio.lock().unwrap() = config.sample_ratio;
+    *self.sender.lock().unwrap() = Some(sender);
+  }
+
+  /// Return whether the spans are exported.
+  pub fn is_enabled(&self) -> bool {
+    self.sender.lock().unwrap().is_some()
+  }
+
+  /// Start the span called `name` for a request received by searchspot,
+  /// child of the one given as `traceparent` or the first of a new trace.
+  pub fn trace(&self, name: &str, traceparent: Option<&str>) -> Span {
+    let previous = current();
+
+    if !self.is_enabled() {
+      return Span::new(name, Kind::Server, None, None, previous);
+    }
+
+    match traceparent.and_then(Context::parse) {
+      Some(parent) => Span::new(name, Kind::Server, Some(parent.child()), Some(parent.span_id), previous),
+      None         => Span::new(name, Kind::Server, Some(Context::new(self.sample())), None, previous)
+    }
+  }
+
+  /// Return whether a new trace is recorded, according to `sample_ratio`.
+  fn sample(&self) -> bool {
+    let ratio = *self.sample_ratio.lock().unwrap();
+    let bytes = Uuid::new_v4();
+    let value = bytes.as_bytes()[..8].iter().fold(0u64, |value, byte| (value << 8) | *byte as u64);
+
+    ratio >= 1.0 || (value as f64 / u64::max_value() as f64) < ratio
+  }
+
+  fn export(&self, record: Record) {
+    if let Some(ref sender) = *self.sender.lock().unwrap() {
+      let _ = sender.send(record);
+    }
+  }
+}
+
+/// The body of the requests sent to the collector, as OTLP expects it.
+#[derive(Serialize, Debug)]
+pub struct Payload {
+  #[serde(rename="resourceSpans")]
+  resource_spans: Vec<ResourceSpans>
+}
+
+#[derive(Serialize, Debug)]
+struct ResourceSpans {
+  resource: Resource,
+  #[serde(rename="scopeSpans")]
+  scope_spans: Vec<ScopeSpans>
+}
+
+#[derive(Serialize, Debug)]
+struct Resource {
+  attributes: Vec<Attribute>
+}
+
+#[derive(Serialize, Debug)]
+struct ScopeSpans {
+  scope: Scope,
+  spans: Vec<SpanData>
+}
+
+#[derive(Serialize, Debug)]
+struct Scope {
+  name:    &'static str,
+  version: &'static str
+}
+
+#[derive(Serialize, Debug)]
+struct SpanData {
+  #[serde(rename="traceId")]
+  trace_id: String,
+  #[serde(rename="spanId")]
+  span_id: String,
+  #[serde(rename="parentSpanId", skip_serializing_if="Option::is_none")]
+  parent_span_id: Option<String>,
+  name: String,
+  kind: u8,
+  #[serde(rename="startTimeUnixNano")]
+  start_time_unix_nano: String, // 64-bit integers are given as strings
+  #[serde(rename="endTimeUnixNano")]
+  end_time_unix_nano: String,
+  attributes: Vec<Attribute>,
+  status: Status
+}
+
+#[derive(Serialize, Debug)]
+struct Attribute {
+  key:   String,
+  value: AttributeValue
+}
+
+impl Attribute {
+  fn new(key: &str, value: &str) -> Attribute {
+    Attribute {
+      key:   key.to_owned(),
+      value: AttributeValue { string_value: value.to_owned() }
+    }
+  }
+}
+
+#[derive(Serialize, Debug)]
+struct AttributeValue {
+  #[serde(rename="stringValue")]
+  string_value: String
+}
+
+#[derive(Serialize, Debug)]
+struct Status {
+  code: u8 // 0 is unset, 2 is error
+}
+
+/// Return the body exporting `records` as spans of `service_name`.
+pub fn payload(service_name: &str, records: Vec<Record>) -> Payload {
+  let spans = records.into_iter().map(|record| SpanData {
+    trace_id:             record.trace_id,
+    span_id:              record.span_id,
+    parent_span_id:       record.parent_id,
+    name:                 record.name,
+    kind:                 record.kind as u8,
+    start_time_unix_nano: record.started_at.to_string(),
+    end_time_unix_nano:   record.ended_at.to_string(),
+    attributes:           record.attributes.iter().map(|&(ref key, ref value)| Attribute::new(key, value)).collect(),
+    status:               Status { code: if record.failed { 2 } else { 0 } }
+  }).collect();
+
+  Payload {
+    resource_spans: vec![ResourceSpans {
+      resource:    Resource { attributes: vec![Attribute::new("service.name", service_name)] },
+      scope_spans: vec![ScopeSpans {
+        scope: Scope { name: "searchspot", version: env!("CARGO_PKG_VERSION") },
+        spans: spans
+      }]
+    }]
+  }
+}
+
+/// Send a `POST` request with given `body` to `url`,
+/// considering any non-2xx response as failed.
+fn post(url: &str, body: &str) -> Result<(), String> {
+  let url = try!(Url::parse(url).map_err(|e| e.to_string()));
+
+  let mut headers = Headers::new();
+  headers.set(ContentType::json());
+
+  let ssl    = try!(OpensslClient::new().map_err(|e| e.to_string()));
+  let client = HttpClient::with_connector(HttpsConnector::new(ssl));
+
+  let response = try!(client.post(url).headers(headers).body(body).send().map_err(|e| e.to_string()));
+
+  if response.status.is_success() {
+    Ok(())
+  }
+  else {
+    Err(response.status.to_string())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use tracing::*;
+
+  use serde_json;
+
+  #[test]
+  fn test_parse() {
+    let context = Context::parse("00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01").unwrap();
+    assert_eq!(context.trace_id, "0af7651916cd43dd8448eb211c80319c");
+    assert_eq!(context.span_id,  "b7ad6b7169203331");
+    assert!(context.sampled);
+    assert_eq!(context.header(), "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01");
+
+    assert!(!Context::parse("00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-00").unwrap().sampled);
+    assert!(Context::parse("01-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01-extra").is_some());
+
+    assert!(Context::parse("").is_none());
+    assert!(Context::parse("00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01-extra").is_none());
+    assert!(Context::parse("ff-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01").is_none());
+    assert!(Context::parse("00-0AF7651916CD43DD8448EB211C80319C-b7ad6b7169203331-01").is_none());
+    assert!(Context::parse("00-00000000000000000000000000000000-b7ad6b7169203331-01").is_none());
+    assert!(Context::parse("00-0af7651916cd43dd8448eb211c80319c-0000000000000000-01").is_none());
+    assert!(Context::parse("00-0af7651916cd43dd-b7ad6b7169203331-01").is_none());
+  }
+
+  #[test]
+  fn test_child() {
+    let context = Context::new(true);
+    assert!(Context::parse(&context.header()).is_some());
+
+    let child = context.child();
+    assert_eq!(child.trace_id, context.trace_id);
+    assert!(child.span_id != context.span_id);
+    assert_eq!(child.span_id.len(), 16);
+  }
+
+  #[test]
+  fn test_spans() {
+    set(None);
+    {
+      // outside of a trace nothing happens
+      let span = Span::start("search.build_query");
+      assert!(span.context().is_none());
+      assert_eq!(current(), None);
+    }
+
+    let root = Context::new(false);
+    set(Some(root.to_owned()));
+    {
+      let span = Span::start("search.build_query");
+      assert_eq!(span.context().unwrap().trace_id, root.trace_id);
+      assert_eq!(current().as_ref(), span.context());
+
+      {
+        let nested = Span::client("search.es");
+        assert_eq!(current().as_ref(), nested.context());
+      }
+
+      assert_eq!(current().as_ref(), span.context());
+    }
+
+    assert_eq!(current(), Some(root));
+    set(None);
+  }
+
+  #[test]
+  fn test_payload() {
+    let record = Record {
+      trace_id:   "0af7651916cd43dd8448eb211c80319c".to_owned(),
+      span_id:    "00f067aa0ba902b7".to_owned(),
+      parent_id:  Some("b7ad6b7169203331".to_owned()),
+      name:       "GET /talents".to_owned(),
+      kind:       Kind::Server,
+      started_at: 1490000000000000000,
+      ended_at:   1490000000250000000,
+      attributes: vec![("http.status_code".to_owned(), "200".to_owned())],
+      failed:     false
+    };
+
+    let payload = serde_json::to_value(&payload("searchspot", vec![record]));
+    let span    = payload.pointer("/resourceSpans/0/scopeSpans/0/spans/0").unwrap();
+
+    assert_eq!(payload.pointer("/resourceSpans/0/resource/attributes/0/value/stringValue").unwrap().as_str(), Some("searchspot"));
+    assert_eq!(span.find("traceId").unwrap().as_str(),      Some("0af7651916cd43dd8448eb211c80319c"));
+    assert_eq!(span.find("parentSpanId").unwrap().as_str(), Some("b7ad6b7169203331"));
+    assert_eq!(span.find("kind").unwrap().as_u64(),         Some(2));
+    assert_eq!(span.find("endTimeUnixNano").unwrap().as_str(), Some("1490000000250000000"));
+    assert_eq!(span.pointer("/attributes/0/value/stringValue").unwrap().as_str(), Some("200"));
+    assert_eq!(span.pointer("/status/code").unwrap().as_u64(), Some(0));
+  }
+}
@@ -0,0 +1,120 @@
+//! A listener accepting the HTTP connections on a Unix domain socket.
+
+use hyper;
+use hyper::net::{NetworkListener, NetworkStream};
+
+use std::fs;
+use std::io;
+use std::io::{Read, Write};
+use std::net::{SocketAddr, Shutdown};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+use std::time::Duration;
+
+/// The address reported for the socket and its peers, which have none.
+fn unspecified_addr() -> SocketAddr {
+  "127.0.0.1:0".parse().unwrap()
+}
+
+pub struct UnixSocketListener(UnixListener);
+
+impl UnixSocketListener {
+  /// Bind the socket to `path`, replacing the file left there
+  /// by a previous instance, if any.
+  pub fn bind<P: AsRef<Path>>(path: P) -> io::Result<UnixSocketListener> {
+    if path.as_ref().exists() {
+      try!(fs::remove_file(&path));
+    }
+
+    UnixListener::bind(path).map(UnixSocketListener)
+  }
+}
+
+impl Clone for UnixSocketListener {
+  fn clone(&self) -> UnixSocketListener {
+    UnixSocketListener(self.0.try_clone().unwrap())
+  }
+}
+
+impl NetworkListener for UnixSocketListener {
+  type Stream = UnixSocketStream;
+
+  fn accept(&mut self) -> hyper::Result<UnixSocketStream> {
+    let (stream, _) = try!(self.0.accept());
+    Ok(UnixSocketStream(stream))
+  }
+
+  fn local_addr(&mut self) -> io::Result<SocketAddr> {
+    Ok(unspecified_addr())
+  }
+}
+
+pub struct UnixSocketStream(UnixStream);
+
+impl Read for UnixSocketStream {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    self.0.read(buf)
+  }
+}
+
+impl Write for UnixSocketStream {
+  fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    self.0.write(buf)
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    self.0.flush()
+  }
+}
+
+impl NetworkStream for UnixSocketStream {
+  fn peer_addr(&mut self) -> io::Result<SocketAddr> {
+    Ok(unspecified_addr())
+  }
+
+  fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+    self.0.set_read_timeout(timeout)
+  }
+
+  fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+    self.0.set_write_timeout(timeout)
+  }
+
+  fn close(&mut self, how: Shutdown) -> io::Result<()> {
+    self.0.shutdown(how)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use unix_socket::UnixSocketListener;
+
+  use hyper::net::NetworkListener;
+
+  use std::env;
+  use std::io::{Read, Write};
+  use std::os::unix::net::UnixStream;
+  use std::thread;
+
+  #[test]
+  fn test_accept() {
+    let path = env::temp_dir().join("searchspot_test.sock");
+    let mut listener = UnixSocketListener::bind(&path).unwrap();
+
+    let client_path = path.clone();
+    let client = thread::spawn(move || {
+      let mut stream = UnixStream::connect(client_path).unwrap();
+      stream.write_all(b"ping").unwrap();
+    });
+
+    let mut stream  = listener.accept().unwrap();
+    let mut payload = String::new();
+    stream.read_to_string(&mut payload).unwrap();
+    client.join().unwrap();
+
+    assert_eq!(payload, "ping");
+
+    // a stale socket file is replaced
+    assert!(UnixSocketListener::bind(&path).is_ok());
+  }
+}
@@ -0,0 +1,223 @@
+//! The daily search volume of every company (the one the searches are sent
+//! for, see `quotas`), answered from the cache or not, kept for the last
+//! `RETENTION_DAYS` days to enforce the limits of the plans and to tell apart
+//! the heaviest users.
+//!
+//! The counts are stored into the Redis server shared by the instances
+//! (`cache.redis_url`), as a hash by day, so that they are summed across the
+//! instances and survive their restarts. They are kept in memory by every
+//! instance otherwise, or while Redis cannot be reached.
+
+use chrono::{DateTime, Duration, UTC};
+
+use redis;
+use redis::RedisResult;
+
+use redis_store::RedisStore;
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Mutex, RwLock};
+
+/// How many days are kept, the older ones being dropped: a whole calendar month.
+pub const RETENTION_DAYS: u64 = 31;
+
+/// The prefix of the hashes counting the searches of a day by company in Redis.
+const DAY_PREFIX: &'static str = "searchspot:usage:";
+
+lazy_static! {
+  /// The search volume shared across the handlers.
+  pub static ref USAGE: Usage = Usage::new();
+}
+
+/// The searches of a company, i.e.: `{"company_id": "42", "total": 15,
+/// "daily": {"2017-03-03": 3, "2017-03-04": 12}}`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct CompanyUsage {
+  pub company_id: String,
+  pub total:      u64,
+  pub daily:      BTreeMap<String, u64> // by day, as `YYYY-MM-DD` (UTC)
+}
+
+/// The searches of every company over the last `days`, the heaviest first.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct CompanyStats {
+  pub days:      u64,
+  pub companies: Vec<CompanyUsage>
+}
+
+pub struct Usage {
+  days:  Mutex<BTreeMap<String, BTreeMap<String, u64>>>, // the counts by company, by day
+  redis: RwLock<Option<RedisStore>>
+}
+
+impl Usage {
+  pub fn new() -> Usage {
+    Usage {
+      days:  Mutex::new(BTreeMap::new()),
+      redis: RwLock::new(None)
+    }
+  }
+
+  /// Store the counts into `store` from now on.
+  pub fn connect(&self, store: RedisStore) {
+    *self.redis.write().unwrap() = Some(store);
+  }
+
+  /// Return the Redis server the counts are stored into, if any.
+  fn store(&self) -> Option<RedisStore> {
+    self.redis.read().unwrap().clone()
+  }
+
+  /// Count a search of `company_id` at `now`.
+  pub fn record(&self, company_id: &str, now: DateTime<UTC>) {
+    if let Some(redis) = self.store() {
+      let key = format!("{}{}", DAY_PREFIX, day(now));
+      let ttl = RETENTION_DAYS as usize * 24 * 3600;
+
+      let recorded = redis.query(|connection| redis::pipe().cmd("HINCRBY").arg(&*key).arg(company_id).arg(1).ignore()
+                                                           .cmd("EXPIRE").arg(&*key).arg(ttl).ignore()
+                                                           .query::<()>(connection));
+      match recorded {
+        Ok(())   => return,
+        Err(err) => warn!("The search of {} cannot be counted in Redis: {}", company_id, err)
+      }
+    }
+
+    let mut days = self.days.lock().unwrap();
+
+    *days.entry(day(now)).or_insert(BTreeMap::new())
+         .entry(company_id.to_owned()).or_insert(0) += 1;
+
+    let oldest = day(now - Duration::days(RETENTION_DAYS as i64 - 1));
+    let expired = days.keys().take_while(|day| **day < oldest).cloned().collect::<Vec<String>>();
+    for day in expired {
+      days.remove(&day);
+    }
+  }
+
+  /// Return how many searches `company_id` has sent from the day of `since` until the one of `now`, included.
+  pub fn since(&self, company_id: &str, since: DateTime<UTC>, now: DateTime<UTC>) -> u64 {
+    let (oldest, latest) = (day(since), day(now));
+
+    self.counts(since, now)
+        .iter()
+        .filter(|&(day, _)| *day >= oldest && *day <= latest)
+        .filter_map(|(_, counts)| counts.get(company_id).cloned())
+        .sum()
+  }
+
+  /// Return the searches of every company over the last `days` until `now`, included.
+  pub fn companies(&self, days: u64, now: DateTime<UTC>) -> CompanyStats {
+    let days   = days.max(1).min(RETENTION_DAYS);
+    let since  = now - Duration::days(days as i64 - 1);
+    let oldest = day(since);
+
+    let mut companies = BTreeMap::<String, CompanyUsage>::new();
+    for (day, counts) in self.counts(since, now).iter().filter(|&(day, _)| *day >= oldest) {
+      for (company_id, count) in counts {
+        let usage = companies.entry(company_id.to_owned()).or_insert(CompanyUsage {
+          company_id: company_id.to_owned(),
+          total:      0,
+          daily:      BTreeMap::new()
+        });
+
+        usage.total += *count;
+        usage.daily.insert(day.to_owned(), *count);
+      }
+    }
+
+    let mut companies = companies.into_iter().map(|(_, usage)| usage).collect::<Vec<CompanyUsage>>();
+    companies.sort_by(|a, b| (b.total, &a.company_id).cmp(&(a.total, &b.company_id)));
+
+    CompanyStats {
+      days:      days,
+      companies: companies
+    }
+  }
+
+  /// Return the counts by company of the days from the one of `since` until the one of `now`,
+  /// read from Redis if it is shared, from the memory of the instance otherwise.
+  fn counts(&self, since: DateTime<UTC>, now: DateTime<UTC>) -> BTreeMap<String, BTreeMap<String, u64>> {
+    if let Some(redis) = self.store() {
+      match read_counts(&redis, since, now) {
+        Ok(counts) => return counts,
+        Err(err)   => warn!("The usage cannot be read from Redis: {}", err)
+      }
+    }
+
+    self.days.lock().unwrap().to_owned()
+  }
+}
+
+/// Read the counts by company of the days from the one of `since` until the one of `now` from `redis`.
+fn read_counts(redis: &RedisStore, since: DateTime<UTC>, now: DateTime<UTC>) -> RedisResult<BTreeMap<String, BTreeMap<String, u64>>> {
+  let mut days = vec![];
+  let mut time = since;
+  while day(time) <= day(now) {
+    days.push(day(time));
+    time = time + Duration::days(1);
+  }
+
+  let counts = try!(redis.query(|connection| {
+    let mut pipe = redis::pipe();
+    for day in &days {
+      pipe.cmd("HGETALL").arg(format!("{}{}", DAY_PREFIX, day));
+    }
+    pipe.query::<Vec<HashMap<String, u64>>>(connection)
+  }));
+
+  Ok(days.into_iter()
+         .zip(counts.into_iter())
+         .filter(|&(_, ref counts)| !counts.is_empty())
+         .map(|(day, counts)| (day, counts.into_iter().collect()))
+         .collect())
+}
+
+/// Return the day of `time`, as `YYYY-MM-DD`.
+fn day(time: DateTime<UTC>) -> String {
+  time.format("%Y-%m-%d").to_string()
+}
+
+#[cfg(test)]
+mod tests {
+  use usage::{Usage, RETENTION_DAYS};
+
+  use chrono::{Duration, TimeZone, UTC};
+
+  #[test]
+  fn test_companies() {
+    let usage = Usage::new();
+    let now   = UTC.ymd(2017, 3, 4).and_hms(12, 0, 0);
+
+    usage.record("1", now - Duration::days(1));
+    usage.record("1", now);
+    usage.record("2", now);
+    usage.record("2", now);
+    usage.record("3", now - Duration::days(3));
+
+    let stats = usage.companies(2, now);
+    assert_eq!(stats.days, 2);
+    assert_eq!(stats.companies.iter().map(|c| (&*c.company_id, c.total)).collect::<Vec<(&str, u64)>>(),
+               vec![("1", 2), ("2", 2)]);
+    assert_eq!(stats.companies[0].daily, btreemap! { "2017-03-03".to_owned() => 1, "2017-03-04".to_owned() => 1 });
+
+    assert_eq!(usage.companies(7, now).companies.len(), 3);
+    assert_eq!(usage.since("1", now - Duration::days(1), now), 2);
+    assert_eq!(usage.since("1", now, now), 1);
+    assert_eq!(usage.since("1", now - Duration::days(1), now - Duration::days(1)), 1);
+    assert_eq!(usage.since("4", now, now), 0);
+    assert_eq!(usage.companies(365, now).days, RETENTION_DAYS);
+  }
+
+  #[test]
+  fn test_retention() {
+    let usage = Usage::new();
+    let now   = UTC.ymd(2017, 3, 4).and_hms(12, 0, 0);
+
+    usage.record("1", now - Duration::days(RETENTION_DAYS as i64));
+    usage.record("2", now);
+
+    assert_eq!(usage.days.lock().unwrap().len(), 1);
+    assert_eq!(usage.companies(RETENTION_DAYS, now).companies.len(), 1);
+  }
+}
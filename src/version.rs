@@ -0,0 +1,107 @@
+//! Negotiation of the API version requested by the clients.
+//!
+//! The version can be given either as path prefix (i.e.: `/v1/talents`)
+//! or, for unprefixed paths, through the `Accept` header
+//! (i.e.: `application/vnd.searchspot.v1+json`). When none is given,
+//! `ApiVersion::default()` is assumed.
+
+use iron::typemap::Key;
+
+/// The versions of the API served by Searchspot.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ApiVersion {
+  V1
+}
+
+impl ApiVersion {
+  /// Return all the supported versions.
+  pub fn all() -> Vec<ApiVersion> {
+    vec![ApiVersion::V1]
+  }
+
+  pub fn as_str(&self) -> &'static str {
+    match *self {
+      ApiVersion::V1 => "v1"
+    }
+  }
+
+  /// Return the prefix of the paths served by this version.
+  pub fn prefix(&self) -> String {
+    format!("/{}", self.as_str())
+  }
+
+  /// Parse either `v1` or `1`.
+  pub fn parse(version: &str) -> Option<ApiVersion> {
+    ApiVersion::all().into_iter().find(|v| {
+      version == v.as_str() || version == &v.as_str()[1..]
+    })
+  }
+
+  /// Return the version requested by the first segment of `path`, if any.
+  pub fn from_path(path: &[&str]) -> Option<ApiVersion> {
+    path.first().and_then(|segment| {
+      if segment.starts_with("v") {
+        ApiVersion::parse(segment)
+      }
+      else {
+        None
+      }
+    })
+  }
+
+  /// Return the version requested through a vendor media
+  /// type (`application/vnd.searchspot.v1+json`), if any.
+  /// `Err` is returned if the requested version is not supported.
+  pub fn from_accept(accept: &str) -> Result<Option<ApiVersion>, String> {
+    const PREFIX: &'static str = "application/vnd.searchspot.";
+
+    for media_type in accept.split(',').map(|m| m.split(';').next().unwrap_or("").trim()) {
+      if media_type.starts_with(PREFIX) {
+        let version = media_type[PREFIX.len()..].split('+').next().unwrap_or("");
+
+        return match ApiVersion::parse(version) {
+          Some(version) => Ok(Some(version)),
+          None          => Err(format!("API version `{}` is not supported.", version))
+        };
+      }
+    }
+
+    Ok(None)
+  }
+}
+
+impl Default for ApiVersion {
+  fn default() -> ApiVersion {
+    ApiVersion::V1
+  }
+}
+
+impl Key for ApiVersion { type Value = ApiVersion; }
+
+#[cfg(test)]
+mod tests {
+  use version::ApiVersion;
+
+  #[test]
+  fn test_parse() {
+    assert_eq!(ApiVersion::parse("v1"), Some(ApiVersion::V1));
+    assert_eq!(ApiVersion::parse("1"),  Some(ApiVersion::V1));
+    assert_eq!(ApiVersion::parse("v2"), None);
+    assert_eq!(ApiVersion::parse(""),   None);
+  }
+
+  #[test]
+  fn test_from_path() {
+    assert_eq!(ApiVersion::from_path(&["v1", "talents"]), Some(ApiVersion::V1));
+    assert_eq!(ApiVersion::from_path(&["talents"]),       None);
+    assert_eq!(ApiVersion::from_path(&[]),                None);
+  }
+
+  #[test]
+  fn test_from_accept() {
+    assert_eq!(ApiVersion::from_accept("application/vnd.searchspot.v1+json"), Ok(Some(ApiVersion::V1)));
+    assert_eq!(ApiVersion::from_accept("text/html, application/vnd.searchspot.v1+json; q=0.9"), Ok(Some(ApiVersion::V1)));
+    assert_eq!(ApiVersion::from_accept("application/json"), Ok(None));
+    assert!(ApiVersion::from_accept("application/vnd.searchspot.v9+json").is_err());
+  }
+}
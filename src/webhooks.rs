@@ -0,0 +1,221 @@
+//! The webhooks notified, in background, about the documents that
+//! have been indexed or deleted, about the indexes that have been reset
+//! and about the new matches of the saved searches (see `alerts`).
+//!
+//! Every request is signed like the write requests received by searchspot
+//! (see `signature`) when a secret is configured.
+
+use serde_json;
+
+use hyper::Client as HttpClient;
+use hyper::Url;
+use hyper::net::HttpsConnector;
+use hyper::header::{Headers, ContentType};
+use hyper_openssl::OpensslClient;
+
+use config::Webhooks as WebhooksConfig;
+use signature::{sign, SIGNATURE_HEADER, TIMESTAMP_HEADER};
+
+use std::sync::Mutex;
+use std::sync::mpsc::{channel, Sender};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+lazy_static! {
+  /// The webhooks shared across the handlers.
+  pub static ref WEBHOOKS: Webhooks = Webhooks::new();
+}
+
+/// The events sent to the webhooks.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Event {
+  Indexed,
+  Deleted,
+  Reset,
+  NewMatches
+}
+
+impl Event {
+  pub fn as_str(&self) -> &'static str {
+    match *self {
+      Event::Indexed    => "document.indexed",
+      Event::Deleted    => "document.deleted",
+      Event::Reset      => "index.reset",
+      Event::NewMatches => "saved_search.new_matches"
+    }
+  }
+}
+
+/// The body of the requests sent to the webhooks, i.e.:
+/// `{"event": "document.indexed", "index": "talents", "ids": ["1"], "timestamp": 1490000000}`.
+/// The new matches of a saved search carry its ID and the one of its recruiter as well.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Payload {
+  pub event:     &'static str,
+  pub index:     String,
+  pub ids:       Vec<String>,
+  pub timestamp: i64,
+  #[serde(skip_serializing_if="Option::is_none")]
+  pub saved_search_id: Option<String>,
+  #[serde(skip_serializing_if="Option::is_none")]
+  pub recruiter_id:    Option<String>
+}
+
+impl Payload {
+  pub fn new(event: Event, index: &str, ids: Vec<String>, timestamp: i64) -> Payload {
+    Payload {
+      event:     event.as_str(),
+      index:     index.to_owned(),
+      ids:       ids,
+      timestamp: timestamp,
+      saved_search_id: None,
+      recruiter_id:    None
+    }
+  }
+
+  /// Return the payload of the new matches `ids` of the saved search `saved_search_id` of `recruiter_id`.
+  pub fn new_matches(index: &str, saved_search_id: &str, recruiter_id: &str, ids: Vec<String>, timestamp: i64) -> Payload {
+    Payload {
+      saved_search_id: Some(saved_search_id.to_owned()),
+      recruiter_id:    Some(recruiter_id.to_owned()),
+      ..Payload::new(Event::NewMatches, index, ids, timestamp)
+    }
+  }
+}
+
+pub struct Webhooks {
+  sender: Mutex<Option<Sender<Payload>>>
+}
+
+impl Webhooks {
+  pub fn new() -> Webhooks {
+    Webhooks {
+      sender: Mutex::new(None)
+    }
+  }
+
+  /// Start delivering the events to the configured URLs from a background
+  /// thread. The events fired before, or without any URL, are dropped.
+  pub fn start(&self, config: &WebhooksConfig) {
+    if config.urls.is_empty() {
+      return;
+    }
+
+    let (sender, receiver) = channel::<Payload>();
+    let config = config.to_owned();
+
+    thread::spawn(move || {
+      for payload in receiver {
+        let body = serde_json::to_string(&payload).unwrap();
+
+        for url in &config.urls {
+          deliver(&config, url, &body);
+        }
+      }
+    });
+
+    *self.sender.lock().unwrap() = Some(sender);
+  }
+
+  /// Queue `event` for the documents with given `ids` of `index`.
+  pub fn fire(&self, event: Event, index: &str, ids: Vec<String>) {
+    self.send(Payload::new(event, index, ids, now()));
+  }
+
+  /// Queue the new matches `ids` of `index` for the saved search `saved_search_id` of `recruiter_id`.
+  pub fn fire_new_matches(&self, index: &str, saved_search_id: &str, recruiter_id: &str, ids: Vec<String>) {
+    self.send(Payload::new_matches(index, saved_search_id, recruiter_id, ids, now()));
+  }
+
+  fn send(&self, payload: Payload) {
+    if let Some(ref sender) = *self.sender.lock().unwrap() {
+      let _ = sender.send(payload);
+    }
+  }
+}
+
+fn now() -> i64 {
+  SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as i64
+}
+
+/// Send `body` to `url`, retrying up to `config.retries` times
+/// with an exponential backoff. Return whether it has been accepted.
+fn deliver(config: &WebhooksConfig, url: &str, body: &str) -> bool {
+  for attempt in 0..(config.retries + 1) {
+    if attempt > 0 {
+      thread::sleep(Duration::from_secs(1 << (attempt - 1)));
+    }
+
+    match post(&config.secret, url, body) {
+      Ok(_)    => return true,
+      Err(err) => warn!("Webhook {} failed (attempt {}): {}", url, attempt + 1, err)
+    }
+  }
+
+  error!("Webhook {} has not been delivered after {} attempts.", url, config.retries + 1);
+  false
+}
+
+/// Send a signed `POST` request with given `body` to `url`,
+/// considering any non-2xx response as failed.
+fn post(secret: &str, url: &str, body: &str) -> Result<(), String> {
+  let url = try!(Url::parse(url).map_err(|e| e.to_string()));
+
+  let mut headers = Headers::new();
+  headers.set(ContentType::json());
+
+  if !secret.is_empty() {
+    // signed at every attempt, since the receiver checks the timestamp
+    let timestamp = now();
+    let signature = sign(secret, timestamp, "POST", url.path(), body);
+    headers.set_raw(SIGNATURE_HEADER, vec![signature.into_bytes()]);
+    headers.set_raw(TIMESTAMP_HEADER, vec![timestamp.to_string().into_bytes()]);
+  }
+
+  let ssl    = try!(OpensslClient::new().map_err(|e| e.to_string()));
+  let client = HttpClient::with_connector(HttpsConnector::new(ssl));
+
+  let response = try!(client.post(url).headers(headers).body(body).send().map_err(|e| e.to_string()));
+
+  if response.status.is_success() {
+    Ok(())
+  }
+  else {
+    Err(response.status.to_string())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use webhooks::*;
+
+  use serde_json;
+
+  #[test]
+  fn test_payload() {
+    let payload = Payload::new(Event::Indexed, "talents", vec!["1".to_owned(), "2".to_owned()], 1490000000);
+    assert_eq!(serde_json::to_string(&payload).unwrap(),
+               "{\"event\":\"document.indexed\",\"index\":\"talents\",\"ids\":[\"1\",\"2\"],\"timestamp\":1490000000}");
+
+    assert_eq!(Event::Deleted.as_str(), "document.deleted");
+    assert_eq!(Event::Reset.as_str(),   "index.reset");
+  }
+
+  #[test]
+  fn test_new_matches_payload() {
+    let payload = Payload::new_matches("talents", "4f0c", "42", vec!["3".to_owned()], 1490000000);
+    assert_eq!(serde_json::to_string(&payload).unwrap(),
+               "{\"event\":\"saved_search.new_matches\",\"index\":\"talents\",\"ids\":[\"3\"],\"timestamp\":1490000000,\
+                \"saved_search_id\":\"4f0c\",\"recruiter_id\":\"42\"}");
+  }
+
+  #[test]
+  fn test_fire_without_urls() {
+    let webhooks = Webhooks::new();
+    webhooks.start(&Default::default());
+
+    // nothing is queued
+    webhooks.fire(Event::Reset, "talents", vec![]);
+    assert!(webhooks.sender.lock().unwrap().is_none());
+  }
+}